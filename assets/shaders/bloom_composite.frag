@@ -0,0 +1,20 @@
+#version 450
+
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(set = 0, binding = 0) uniform UniformBuffer {
+    float elapsed_seconds;
+    float viewport_w;
+    float viewport_h;
+    float bloom_mip;
+} ubo;
+// The scene, with its downsample chain in the mips below it
+layout (binding = 1) uniform sampler2D tex_sampler;
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    vec2 uv = gl_FragCoord.xy / vec2(ubo.viewport_w, ubo.viewport_h);
+    vec3 scene = textureLod(tex_sampler, uv, 0.0).rgb;
+    vec3 bloom = textureLod(tex_sampler, uv, ubo.bloom_mip).rgb;
+    out_color = vec4(scene + 0.5 * bloom, 1.0);
+}
@@ -8,15 +8,57 @@ layout(set = 0, binding = 0) uniform UniformBuffer {
     float elapsed_seconds;
     float viewport_w;
     float viewport_h;
+    float dither_lsb;
+    float is_output_srgb;
+    float tonemap_operator;
+    float exposure_scale;
 } ubo;
-layout (binding = 1) uniform sampler2D tex_sampler;
+// The scene, and the ordered dither thresholds
+layout (binding = 1) uniform sampler2D tex_sampler[2];
 layout(location = 0) in vec3 frag_norm_world;
 layout(location = 0) out vec4 out_color;
 
+const int DITHER_SIZE = 8; // Must match default_resources.rs
+// Must match TonemapOperator in tonemap.rs
+const int TONEMAP_CLAMP = 0;
+const int TONEMAP_REINHARD = 1;
+const int TONEMAP_ACES = 2;
+
 float noise1d(float n){
     return fract(cos(n*89.42)*343.42);
 }
 
+vec3 linear_to_srgb(vec3 c) {
+    return mix(c * 12.92, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, c));
+}
+
+vec3 srgb_to_linear(vec3 c) {
+    return mix(c / 12.92, pow((c + 0.055) / 1.055, vec3(2.4)), step(0.04045, c));
+}
+
+// The scene is rendered in linear HDR, and this maps it to the display range
+vec3 tonemap(vec3 color) {
+    vec3 x = color * ubo.exposure_scale;
+    int op = int(ubo.tonemap_operator);
+    if (op == TONEMAP_REINHARD) {
+        x = x / (1.0 + x);
+    } else if (op == TONEMAP_ACES) {
+        x = (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+    }
+    return clamp(x, 0.0, 1.0);
+}
+
+// Add -0.5..0.5 output steps of a fixed pattern, in the space that gets quantized
+vec3 dither(vec3 color) {
+    ivec2 texel = ivec2(gl_FragCoord.xy) % DITHER_SIZE;
+    float offset = (texelFetch(tex_sampler[1], texel, 0).r - 0.5) * ubo.dither_lsb;
+    if (ubo.is_output_srgb != 0.0) {
+        vec3 encoded = linear_to_srgb(clamp(color, 0.0, 1.0));
+        return srgb_to_linear(clamp(encoded + offset, 0.0, 1.0));
+    }
+    return color + offset;
+}
+
 void main() {
     vec2 viewport_size = vec2(ubo.viewport_w, ubo.viewport_h);
     vec2 uv = gl_FragCoord.xy / viewport_size;
@@ -27,8 +69,11 @@ void main() {
     vec2 uv_r = uv + vec2(1, 1) * 0.012;
     vec2 uv_g = uv + vec2(1, 0.2) * 0.008;
 
-    out_color.r = texture(tex_sampler, uv_r).r;
-    out_color.g = texture(tex_sampler, uv_g).g;
-    out_color.b = texture(tex_sampler, uv).b;
-
+    out_color.r = texture(tex_sampler[0], uv_r).r;
+    out_color.g = texture(tex_sampler[0], uv_g).g;
+    out_color.b = texture(tex_sampler[0], uv).b;
+    out_color.rgb = tonemap(out_color.rgb);
+    if (ubo.dither_lsb > 0.0) {
+        out_color.rgb = dither(out_color.rgb);
+    }
 }
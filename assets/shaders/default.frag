@@ -1,5 +1,8 @@
 #version 450
 
+#extension GL_GOOGLE_include_directive : require
+#include "shader_debug.glsl"
+
 layout(set = 0, binding = 0) uniform UniformBuffer {
     mat4 mtx_obj_to_clip;
     mat4 mtx_norm_obj_to_world;
@@ -18,9 +21,12 @@ vec3 f_schlick(vec3 f0, float f90, float u) {
 }
 
 void main() {
-    vec3 n = normalize(frag_norm_world);
+    // Back faces are only drawn by double-sided passes, and face away from their normals
+    vec3 n = normalize(gl_FrontFacing ? frag_norm_world : -frag_norm_world);
     vec3 lit = vec3(1, 1, 1) * dot(vec3(0, -1, 0), n.xyz);
     out_color = vec4(lit, 1.0);
+    // Catches degenerate normals from the mesh
+    assert_not_nan(out_color);
     return;
 
     // float roughness = cos(ubo.elapsed_seconds) * 0.5 + 0.5;
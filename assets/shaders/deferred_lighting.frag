@@ -0,0 +1,31 @@
+#version 450
+
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(set = 0, binding = 0) uniform UniformBuffer {
+    mat4 mtx_obj_to_clip;
+    mat4 mtx_norm_obj_to_world;
+    float elapsed_seconds;
+    float viewport_w;
+    float viewport_h;
+} ubo;
+// Albedo, normal, roughness/metalness. Written by gbuffer.frag.
+layout (binding = 1) uniform sampler2D gbuffer[3];
+layout(location = 0) out vec4 out_color;
+
+const vec3 BACKGROUND = vec3(0.0144, 0.0144, 0.0214); // Linear sRGB (32, 32, 40)
+
+void main() {
+    vec2 uv = gl_FragCoord.xy / vec2(ubo.viewport_w, ubo.viewport_h);
+    vec4 albedo = texture(gbuffer[0], uv);
+    if (albedo.a == 0.0) {
+        // No geometry here
+        out_color = vec4(BACKGROUND, 1.0);
+        return;
+    }
+    vec3 n = normalize(texture(gbuffer[1], uv).xyz);
+
+    // Same light as default.frag
+    vec3 lit = albedo.rgb * dot(vec3(0, -1, 0), n);
+    out_color = vec4(lit, 1.0);
+}
@@ -0,0 +1,17 @@
+#version 450
+
+#extension GL_ARB_separate_shader_objects : enable
+
+// The mip above the one that is rendered to, which is twice its size
+layout (binding = 1) uniform sampler2D tex_sampler;
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    ivec2 max_texel = textureSize(tex_sampler, 0) - 1;
+    ivec2 texel = ivec2(gl_FragCoord.xy) * 2;
+    vec4 sum = texelFetch(tex_sampler, min(texel, max_texel), 0);
+    sum += texelFetch(tex_sampler, min(texel + ivec2(1, 0), max_texel), 0);
+    sum += texelFetch(tex_sampler, min(texel + ivec2(0, 1), max_texel), 0);
+    sum += texelFetch(tex_sampler, min(texel + ivec2(1, 1), max_texel), 0);
+    out_color = 0.25 * sum;
+}
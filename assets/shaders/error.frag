@@ -0,0 +1,12 @@
+#version 450
+
+#extension GL_ARB_separate_shader_objects : enable
+
+// Substituted for fragment shaders that fail to compile. Deliberately loud.
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    ivec2 cell = ivec2(gl_FragCoord.xy) / 16;
+    bool is_magenta = ((cell.x + cell.y) & 1) == 0;
+    out_color = is_magenta ? vec4(1.0, 0.0, 1.0, 1.0) : vec4(0.0, 0.0, 0.0, 1.0);
+}
@@ -0,0 +1,24 @@
+#version 450
+
+// G-buffer output of the default material, for the deferred path. The
+// lighting that default.frag does is in deferred_lighting.frag instead.
+
+layout(set = 0, binding = 0) uniform UniformBuffer {
+    mat4 mtx_obj_to_clip;
+    mat4 mtx_norm_obj_to_world;
+    float elapsed_seconds;
+    float viewport_w;
+    float viewport_h;
+} ubo;
+layout(location = 0) in vec3 frag_norm_world;
+layout(location = 0) out vec4 out_albedo; // Alpha marks texels with geometry
+layout(location = 1) out vec4 out_normal;
+layout(location = 2) out vec2 out_material; // Roughness, metalness
+
+void main() {
+    out_albedo = vec4(1.0, 1.0, 1.0, 1.0);
+    // Back faces are only drawn by double-sided passes, and face away from their normals
+    vec3 n = gl_FrontFacing ? frag_norm_world : -frag_norm_world;
+    out_normal = vec4(normalize(n), 0.0);
+    out_material = vec2(1.0, 0.0);
+}
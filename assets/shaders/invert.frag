@@ -0,0 +1,12 @@
+#version 450
+
+#extension GL_ARB_separate_shader_objects : enable
+
+// The image to invert, which is the same size as the output
+layout (binding = 1) uniform sampler2D tex_sampler;
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    vec4 color = texelFetch(tex_sampler, ivec2(gl_FragCoord.xy), 0);
+    out_color = vec4(1.0 - color.rgb, color.a);
+}
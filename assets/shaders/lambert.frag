@@ -0,0 +1,12 @@
+#version 450
+
+layout(location = 0) in vec3 frag_norm_world;
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    // Back faces are only drawn by double-sided passes, and face away from their normals
+    vec3 n = normalize(gl_FrontFacing ? frag_norm_world : -frag_norm_world);
+    vec3 light_dir = normalize(vec3(0.4, 1.0, 0.6));
+    float lit = 0.2 + 0.8 * max(dot(n, light_dir), 0.0);
+    out_color = vec4(vec3(lit), 1.0);
+}
@@ -0,0 +1,24 @@
+#version 450
+
+#extension GL_ARB_separate_shader_objects : enable
+
+/* Resolves linear HDR samples with weights that fall off with brightness,
+which averages them roughly as they look once tonemapped. The render pass
+resolve averages the linear values, so a single very bright sample outweighs
+the rest of the texel and leaves bright fringes along the edges. */
+layout (binding = 1) uniform sampler2DMS tex_sampler;
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    int num_samples = textureSamples(tex_sampler);
+    vec4 sum = vec4(0.0);
+    float weight_sum = 0.0;
+    for (int i = 0; i < num_samples; i++) {
+        vec4 color = texelFetch(tex_sampler, texel, i);
+        float weight = 1.0 / (1.0 + max(color.r, max(color.g, color.b)));
+        sum += color * weight;
+        weight_sum += weight;
+    }
+    out_color = sum / weight_sum;
+}
@@ -0,0 +1,43 @@
+// Shader-side assertions, which are read back and logged by the engine. Use with:
+//
+//     #extension GL_GOOGLE_include_directive : require
+//     #include "shader_debug.glsl"
+//
+// The layout must match src/shader_debug.rs. Needs the fragmentStoresAndAtomics feature.
+
+#define SHADER_DEBUG_MAX_RECORDS 32
+
+// Failure codes
+#define SHADER_ASSERT_NAN 1u
+#define SHADER_ASSERT_OUT_OF_RANGE 2u
+
+struct ShaderDebugRecord {
+    uint code;
+    uint payload_0;
+    uint payload_1;
+    uint _padding;
+};
+
+layout(std430, set = 0, binding = 2) buffer ShaderDebugBuffer {
+    uint count;
+    uint _padding[3];
+    ShaderDebugRecord records[SHADER_DEBUG_MAX_RECORDS];
+} shader_debug;
+
+void shader_assert(bool condition, uint code, uint payload_0, uint payload_1) {
+    if (!condition) {
+        // The count keeps going past the end, so that dropped failures can be reported
+        uint idx = atomicAdd(shader_debug.count, 1u);
+        if (idx < SHADER_DEBUG_MAX_RECORDS) {
+            shader_debug.records[idx] = ShaderDebugRecord(code, payload_0, payload_1, 0u);
+        }
+    }
+}
+
+void assert_not_nan(vec4 value) {
+    shader_assert(!any(isnan(value)), SHADER_ASSERT_NAN, 0u, 0u);
+}
+
+void assert_in_range(uint idx, uint len) {
+    shader_assert(idx < len, SHADER_ASSERT_OUT_OF_RANGE, idx, len);
+}
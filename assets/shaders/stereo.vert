@@ -0,0 +1,23 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+#include "view_index.glsl"
+
+// Like default.vert, with a matrix per eye. The vertices are in world space.
+layout(set = 0, binding = 0) uniform UniformBuffer {
+    mat4 mtx_world_to_clip[2];
+    float elapsed_seconds;
+    float viewport_w;
+    float viewport_h;
+} ubo;
+layout(location = 0) in vec3 in_pos;
+layout(location = 1) in vec3 in_norm;
+layout(location = 0) out vec3 frag_norm_world;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    gl_Position = ubo.mtx_world_to_clip[VIEW_INDEX] * vec4(in_pos, 1.0);
+    frag_norm_world = in_norm;
+}
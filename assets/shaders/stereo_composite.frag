@@ -0,0 +1,18 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform UniformBuffer {
+    mat4 mtx_world_to_clip[2];
+    float elapsed_seconds;
+    float viewport_w;
+    float viewport_h;
+} ubo;
+// The eyes are its layers, and are placed side by side, the left one on the left
+layout(binding = 1) uniform sampler2DArray stereo_sampler;
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    vec2 uv = gl_FragCoord.xy / vec2(ubo.viewport_w, ubo.viewport_h);
+    float eye = uv.x < 0.5 ? 0.0 : 1.0;
+    uv.x = uv.x * 2.0 - eye;
+    out_color = texture(stereo_sampler, vec3(uv, eye));
+}
@@ -0,0 +1,24 @@
+#version 450
+
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(set = 0, binding = 0) uniform UniformBuffer {
+    mat4 mtx_obj_to_clip;       // Unused
+    mat4 mtx_norm_obj_to_world; // Unused
+    float elapsed_seconds;
+    float viewport_w;
+    float viewport_h;
+    float num_iterations;
+} ubo;
+layout(location = 0) out vec4 out_color;
+
+// Dependent ALU work per pixel, so that the cost scales with the iteration count
+void main() {
+    vec2 p = gl_FragCoord.xy / vec2(ubo.viewport_w, ubo.viewport_h) + ubo.elapsed_seconds;
+    int num_iterations = int(ubo.num_iterations);
+    for (int i = 0; i < num_iterations; i++) {
+        p = vec2(sin(p.x * 1.7 + p.y), cos(p.y * 1.3 - p.x)) * 0.99 + 0.01;
+    }
+    // Written out, so that the loop isn't optimized away
+    out_color = vec4(p, 0.0, 1.0);
+}
@@ -0,0 +1,20 @@
+#version 450
+
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(set = 0, binding = 0) uniform UniformBuffer {
+    float elapsed_seconds;
+    float viewport_w;
+    float viewport_h;
+    float num_colors;
+} ubo;
+// TEXEL_BUFFER_BINDING
+layout(set = 0, binding = 3) uniform samplerBuffer palette;
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    vec2 uv = gl_FragCoord.xy / vec2(ubo.viewport_w, ubo.viewport_h);
+    float t = fract(uv.x + 0.1 * sin(uv.y * 6.2831853 + ubo.elapsed_seconds));
+    int idx = int(t * (ubo.num_colors - 1.0));
+    out_color = vec4(texelFetch(palette, idx).rgb, 1.0);
+}
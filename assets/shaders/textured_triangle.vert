@@ -0,0 +1,23 @@
+#version 450
+
+layout(location = 0) out vec2 frag_uv;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+vec2 positions[3] = vec2[](
+    vec2(0.0, -0.7),
+    vec2(0.7, 0.7),
+    vec2(-0.7, 0.7)
+);
+vec2 uvs[3] = vec2[](
+    vec2(0.5, 0.0),
+    vec2(1.0, 1.0),
+    vec2(0.0, 1.0)
+);
+
+void main() {
+    gl_Position = vec4(positions[gl_VertexIndex], 0, 1);
+    frag_uv = uvs[gl_VertexIndex];
+}
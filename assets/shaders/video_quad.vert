@@ -0,0 +1,28 @@
+#version 450
+
+// Matches `Uniforms` in demo 08
+layout(set = 0, binding = 0) uniform UniformBuffer {
+    mat4 mtx_obj_to_clip;
+} ubo;
+layout(location = 0) out vec2 frag_uv;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+// Two triangles, from -1 to 1 in X and Y. The object transform gives the quad its aspect ratio.
+vec2 corners[6] = vec2[](
+    vec2(-1.0, -1.0),
+    vec2(1.0, -1.0),
+    vec2(1.0, 1.0),
+    vec2(-1.0, -1.0),
+    vec2(1.0, 1.0),
+    vec2(-1.0, 1.0)
+);
+
+void main() {
+    vec2 corner = corners[gl_VertexIndex];
+    gl_Position = ubo.mtx_obj_to_clip * vec4(corner, 0.0, 1.0);
+    // Video frames are flipped when decoded, so that V points up like Y
+    frag_uv = corner * 0.5 + 0.5;
+}
@@ -0,0 +1,20 @@
+// The view that a pass with several views is rendering, e.g. an eye. Use with:
+//
+//     #extension GL_GOOGLE_include_directive : require
+//     #include "view_index.glsl"
+//
+// right after `#version`, since it may enable an extension. The permutations
+// compiled with MULTIVIEW render every view at once, and read it from
+// gl_ViewIndex. Otherwise each view is rendered on its own, with its index
+// pushed as a constant. The size must match VIEW_INDEX_PUSH_CONSTANT_SIZE in
+// src/rdg/graph.rs.
+
+#ifdef MULTIVIEW
+#extension GL_EXT_multiview : require
+#define VIEW_INDEX gl_ViewIndex
+#else
+layout(push_constant) uniform ViewIndexConstants {
+    uint view_index;
+} view_index_constants;
+#define VIEW_INDEX int(view_index_constants.view_index)
+#endif
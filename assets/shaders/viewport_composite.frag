@@ -0,0 +1,44 @@
+#version 450
+
+#define MAX_INSETS 4
+
+// Matches `CompositeUniforms` in viewport_composite.rs
+layout(set = 0, binding = 0) uniform UniformBuffer {
+    vec4 rects[MAX_INSETS]; // x, y, width and height, as fractions of the output from its top left
+    vec4 border_colors[MAX_INSETS]; // Linear
+    vec4 borders[MAX_INSETS]; // x: Width in pixels. y: 1 if the inset is visible.
+    float viewport_w;
+    float viewport_h;
+} ubo;
+// The main view, and then the insets in order. The images are sRGB, so they are sampled as linear.
+layout(binding = 1) uniform sampler2D input_samplers[1 + MAX_INSETS];
+layout(location = 0) out vec4 out_color;
+
+// Later insets are drawn over earlier ones
+vec4 composite_inset(vec4 color, sampler2D inset_sampler, int inset_idx, vec2 pixel) {
+    if (ubo.borders[inset_idx].y == 0.0) {
+        return color;
+    }
+    vec2 viewport = vec2(ubo.viewport_w, ubo.viewport_h);
+    vec2 rect_min = ubo.rects[inset_idx].xy * viewport;
+    vec2 rect_max = rect_min + ubo.rects[inset_idx].zw * viewport;
+    float border_width = ubo.borders[inset_idx].x;
+    if (any(lessThan(pixel, rect_min - border_width)) || any(greaterThanEqual(pixel, rect_max + border_width))) {
+        return color;
+    }
+    if (any(lessThan(pixel, rect_min)) || any(greaterThanEqual(pixel, rect_max))) {
+        return ubo.border_colors[inset_idx];
+    }
+    return texture(inset_sampler, (pixel - rect_min) / (rect_max - rect_min));
+}
+
+void main() {
+    vec2 pixel = gl_FragCoord.xy;
+    vec4 color = texture(input_samplers[0], pixel / vec2(ubo.viewport_w, ubo.viewport_h));
+    // Indexed with constants, which doesn't need dynamic indexing of sampler arrays
+    color = composite_inset(color, input_samplers[1], 0, pixel);
+    color = composite_inset(color, input_samplers[2], 1, pixel);
+    color = composite_inset(color, input_samplers[3], 2, pixel);
+    color = composite_inset(color, input_samplers[4], 3, pixel);
+    out_color = vec4(color.rgb, 1.0);
+}
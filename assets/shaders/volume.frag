@@ -0,0 +1,62 @@
+#version 450
+
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(set = 0, binding = 0) uniform UniformBuffer {
+    mat4 mtx_clip_to_world;
+    vec4 camera_position;
+    float step_count;
+    float density_scale;
+    float viewport_w;
+    float viewport_h;
+} ubo;
+layout (binding = 1) uniform sampler3D volume_sampler;
+layout(location = 0) out vec4 out_color;
+
+const vec3 BACKGROUND = vec3(0.02, 0.02, 0.03);
+
+// Entry and exit distances of the ray through the [-1, 1] volume box
+vec2 intersect_box(vec3 origin, vec3 dir) {
+    vec3 inv_dir = 1.0 / dir;
+    vec3 t0 = (vec3(-1.0) - origin) * inv_dir;
+    vec3 t1 = (vec3(1.0) - origin) * inv_dir;
+    vec3 t_min = min(t0, t1);
+    vec3 t_max = max(t0, t1);
+    float t_enter = max(max(t_min.x, t_min.y), max(t_min.z, 0.0));
+    float t_exit = min(min(t_max.x, t_max.y), t_max.z);
+    return vec2(t_enter, t_exit);
+}
+
+void main() {
+    vec2 viewport_size = vec2(ubo.viewport_w, ubo.viewport_h);
+    vec2 ndc = gl_FragCoord.xy / viewport_size * 2.0 - 1.0;
+    vec4 far = ubo.mtx_clip_to_world * vec4(ndc, 1.0, 1.0);
+    vec3 origin = ubo.camera_position.xyz;
+    vec3 dir = normalize(far.xyz / far.w - origin);
+
+    vec2 t = intersect_box(origin, dir);
+    if (t.x >= t.y) {
+        out_color = vec4(BACKGROUND, 1.0);
+        return;
+    }
+
+    // The step count is the number of samples along the diagonal of the box
+    float step_size = 2.0 * sqrt(3.0) / ubo.step_count;
+    vec3 color = vec3(0.0);
+    float transmittance = 1.0;
+    // Composite front-to-back
+    for (float s = t.x + 0.5 * step_size; s < t.y; s += step_size) {
+        vec3 uvw = (origin + dir * s) * 0.5 + 0.5;
+        float density = texture(volume_sampler, uvw).r;
+        float alpha = 1.0 - exp(-density * ubo.density_scale * step_size);
+        vec3 sample_color = mix(vec3(0.1, 0.3, 1.0), vec3(1.0, 0.6, 0.2), density);
+        color += transmittance * alpha * sample_color;
+        transmittance *= 1.0 - alpha;
+        if (transmittance < 0.01) {
+            // Early termination. Nothing behind this is visible.
+            break;
+        }
+    }
+
+    out_color = vec4(color + transmittance * BACKGROUND, 1.0);
+}
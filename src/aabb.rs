@@ -0,0 +1,52 @@
+use glam::*;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// An inverted box that becomes valid as soon as a point is added to it.
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vec3::splat(f32::MAX),
+            max: Vec3::splat(-f32::MAX),
+        }
+    }
+
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Aabb {
+        let mut aabb = Aabb::empty();
+        for point in points {
+            aabb.add_point(point);
+        }
+        aabb
+    }
+
+    pub fn add_point(&mut self, point: Vec3) {
+        self.min = self.min.min(point);
+        self.max = self.max.max(point);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x() > self.max.x() || self.min.y() > self.max.y() || self.min.z() > self.max.z()
+    }
+
+    pub fn is_finite(&self) -> bool {
+        let is_vec_finite = |v: Vec3| v.x().is_finite() && v.y().is_finite() && v.z().is_finite();
+        is_vec_finite(self.min) && is_vec_finite(self.max)
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extents(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    /// Radius of the sphere that encloses the box, centered on `center()`.
+    pub fn bounding_radius(&self) -> f32 {
+        self.extents().length() * 0.5
+    }
+}
@@ -0,0 +1,483 @@
+use crate::*;
+use std::cell::RefCell;
+
+// Size of the memory blocks that allocations are carved out of. Heaps that are
+// small, like the device-local, host-visible heap of some GPUs, get smaller
+// blocks so that one block doesn't take up most of the heap.
+const MEMORY_BLOCK_SIZE: u64 = 64 * 1024 * 1024;
+const MIN_BLOCKS_PER_HEAP: u64 = 8;
+
+/* Buffers and optimally tiled images are kept in separate blocks, so that
+they are never closer than `bufferImageGranularity` to each other. */
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum ResourceKind {
+    Buffer,
+    Image,
+}
+
+/// A range of device memory that a buffer or image is bound to. Give it back
+/// with `Allocator::free()` once the resource has been destroyed.
+#[doc(hidden)]
+pub struct Allocation {
+    pub memory: vk::DeviceMemory,
+    pub offset: u64,
+    pub size: u64,
+    pub property_flags: vk::MemoryPropertyFlags, // Of the memory type
+    // Points to `offset`, if the memory is host-visible. Host-visible memory
+    // stays mapped for the lifetime of its block.
+    pub opt_mapped_ptr: Option<*mut u8>,
+    opt_block_idx: Option<usize>, // None if the allocation has its own memory
+    id: u64,                      // Of its entry in the live allocations
+}
+
+/// A live allocation, as listed in the memory report.
+#[derive(Clone, Debug)]
+pub struct AllocationInfo {
+    pub name: String, // Of the resource that it's bound to
+    pub size: u64,
+    pub memory_type_idx: usize,
+    pub heap_idx: usize,
+    pub is_host_visible: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HeapStats {
+    pub heap_idx: usize,
+    pub heap_size: u64,
+    pub is_device_local: bool,
+    pub num_blocks: usize, // Including the allocations that have their own memory
+    pub block_bytes: u64,  // Allocated from the driver
+    pub used_bytes: u64,   // Handed out to resources
+    pub num_allocations: usize,
+}
+
+struct MemoryBlock {
+    memory: vk::DeviceMemory,
+    size: u64,
+    memory_type_idx: usize,
+    kind: ResourceKind,
+    free_ranges: Vec<(u64, u64)>, // (offset, size), sorted by offset
+    opt_mapped_ptr: Option<*mut u8>,
+    num_allocations: usize,
+}
+
+#[derive(Default)]
+struct AllocatorState {
+    blocks: Vec<Option<MemoryBlock>>, // Freed blocks leave a hole, so that indices stay valid
+    // Allocations that got their own memory, by memory type and size
+    dedicated_allocations: Vec<(vk::DeviceMemory, usize, u64)>,
+    live_allocations: Vec<(u64, AllocationInfo)>, // (id, info)
+    next_allocation_id: u64,
+}
+
+/* Sub-allocates buffers and images from large blocks of device memory, one
+list of blocks per memory type, instead of calling `vkAllocateMemory` for
+every resource. Drivers limit the number of allocations, often to 4096.
+Ranges are first-fit from each block's free list, and a block is freed when
+its last allocation is, unless it's the only block of its memory type.
+Requests larger than half a block get memory of their own. Internal, but
+reachable through `Gpu`; the memory report has what's public of it. */
+#[doc(hidden)]
+pub struct Allocator {
+    state: RefCell<AllocatorState>,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    non_coherent_atom_size: u64,
+    device: ash::Device,
+}
+
+impl Allocator {
+    pub(crate) fn new(
+        device: ash::Device,
+        memory_properties: vk::PhysicalDeviceMemoryProperties,
+        properties: &vk::PhysicalDeviceProperties,
+    ) -> Allocator {
+        Allocator {
+            state: RefCell::new(AllocatorState::default()),
+            memory_properties,
+            non_coherent_atom_size: properties.limits.non_coherent_atom_size.max(1),
+            device,
+        }
+    }
+
+    /* Allocate and bind memory from the first memory type that has all of
+    `required_memory_properties` and `preferred_memory_properties`, or else
+    from the first that has the required ones. */
+    pub(crate) fn allocate_for_buffer(
+        &self,
+        name: &str,
+        vk_buffer: vk::Buffer,
+        required_memory_properties: vk::MemoryPropertyFlags,
+        preferred_memory_properties: vk::MemoryPropertyFlags,
+    ) -> Allocation {
+        let requirements = unsafe { self.device.get_buffer_memory_requirements(vk_buffer) };
+        let allocation = self.allocate(
+            name,
+            &requirements,
+            required_memory_properties,
+            preferred_memory_properties,
+            ResourceKind::Buffer,
+        );
+        unsafe {
+            self.device
+                .bind_buffer_memory(vk_buffer, allocation.memory, allocation.offset)
+                .expect("Failed to bind buffer.");
+        }
+        allocation
+    }
+
+    /// Allocate and bind memory for an optimally tiled image.
+    pub(crate) fn allocate_for_image(
+        &self,
+        name: &str,
+        vk_image: vk::Image,
+        required_memory_properties: vk::MemoryPropertyFlags,
+    ) -> Allocation {
+        let requirements = unsafe { self.device.get_image_memory_requirements(vk_image) };
+        let allocation = self.allocate(
+            name,
+            &requirements,
+            required_memory_properties,
+            vk::MemoryPropertyFlags::empty(),
+            ResourceKind::Image,
+        );
+        unsafe {
+            self.device
+                .bind_image_memory(vk_image, allocation.memory, allocation.offset)
+                .expect("Failed to bind image memory.");
+        }
+        allocation
+    }
+
+    fn allocate(
+        &self,
+        name: &str,
+        requirements: &vk::MemoryRequirements,
+        required_memory_properties: vk::MemoryPropertyFlags,
+        preferred_memory_properties: vk::MemoryPropertyFlags,
+        kind: ResourceKind,
+    ) -> Allocation {
+        let (memory_type_idx, mut allocation) = self.allocate_range(
+            requirements,
+            required_memory_properties,
+            preferred_memory_properties,
+            kind,
+        );
+        let state = &mut *self.state.borrow_mut();
+        allocation.id = state.next_allocation_id;
+        state.next_allocation_id += 1;
+        state.live_allocations.push((
+            allocation.id,
+            AllocationInfo {
+                name: String::from(name),
+                size: allocation.size,
+                memory_type_idx,
+                heap_idx: self.memory_properties.memory_types[memory_type_idx].heap_index as usize,
+                is_host_visible: allocation
+                    .property_flags
+                    .contains(vk::MemoryPropertyFlags::HOST_VISIBLE),
+            },
+        ));
+        allocation
+    }
+
+    // Returns the memory type, and the allocation without an id
+    fn allocate_range(
+        &self,
+        requirements: &vk::MemoryRequirements,
+        required_memory_properties: vk::MemoryPropertyFlags,
+        preferred_memory_properties: vk::MemoryPropertyFlags,
+        kind: ResourceKind,
+    ) -> (usize, Allocation) {
+        let memory_types = &self.memory_properties.memory_types
+            [..self.memory_properties.memory_type_count as usize];
+        let find_memory_type = |properties: vk::MemoryPropertyFlags| {
+            memory_types.iter().enumerate().position(|(i, &m)| {
+                (requirements.memory_type_bits & (1 << i)) > 0
+                    && m.property_flags.contains(properties)
+            })
+        };
+        let memory_type_idx =
+            find_memory_type(required_memory_properties | preferred_memory_properties)
+                .or_else(|| find_memory_type(required_memory_properties))
+                .expect("Failed to find suitable memory type.");
+        let property_flags = memory_types[memory_type_idx].property_flags;
+
+        /* Host-visible ranges are padded to whole atoms, so that flushing or
+        invalidating one never touches its neighbors. */
+        let is_host_visible = property_flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE);
+        let atom_size = if is_host_visible {
+            self.non_coherent_atom_size
+        } else {
+            1
+        };
+        let alignment = requirements.alignment.max(atom_size);
+        let size = requirements.size.max(1).div_ceil(atom_size) * atom_size;
+
+        let block_size = self.block_size(memory_type_idx);
+        if size > block_size / 2 {
+            let (memory, opt_mapped_ptr) = self.allocate_memory(memory_type_idx, size);
+            self.state
+                .borrow_mut()
+                .dedicated_allocations
+                .push((memory, memory_type_idx, size));
+            let allocation = Allocation {
+                memory,
+                offset: 0,
+                size,
+                property_flags,
+                opt_mapped_ptr,
+                opt_block_idx: None,
+                id: 0,
+            };
+            return (memory_type_idx, allocation);
+        }
+
+        let state = &mut *self.state.borrow_mut();
+        let opt_found = state
+            .blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(block_idx, opt_block)| opt_block.as_ref().map(|b| (block_idx, b)))
+            .filter(|(_, block)| block.memory_type_idx == memory_type_idx && block.kind == kind)
+            .find_map(|(block_idx, block)| {
+                block
+                    .free_ranges
+                    .iter()
+                    .position(|(offset, range_size)| {
+                        let start = offset.div_ceil(alignment) * alignment;
+                        start + size <= offset + range_size
+                    })
+                    .map(|range_idx| (block_idx, range_idx))
+            });
+        let (block_idx, range_idx) = opt_found.unwrap_or_else(|| {
+            let (memory, opt_mapped_ptr) = self.allocate_memory(memory_type_idx, block_size);
+            let block = MemoryBlock {
+                memory,
+                size: block_size,
+                memory_type_idx,
+                kind,
+                free_ranges: vec![(0, block_size)],
+                opt_mapped_ptr,
+                num_allocations: 0,
+            };
+            // Reuse a hole left by a freed block
+            match state.blocks.iter().position(|b| b.is_none()) {
+                Some(block_idx) => {
+                    state.blocks[block_idx] = Some(block);
+                    (block_idx, 0)
+                }
+                None => {
+                    state.blocks.push(Some(block));
+                    (state.blocks.len() - 1, 0)
+                }
+            }
+        });
+
+        let block = state.blocks[block_idx].as_mut().unwrap();
+        let (range_offset, range_size) = block.free_ranges.remove(range_idx);
+        let offset = range_offset.div_ceil(alignment) * alignment;
+        // Give back what is left on either side of the allocation
+        let range_end = range_offset + range_size;
+        let mut insert_idx = range_idx;
+        if offset > range_offset {
+            block
+                .free_ranges
+                .insert(insert_idx, (range_offset, offset - range_offset));
+            insert_idx += 1;
+        }
+        if offset + size < range_end {
+            block
+                .free_ranges
+                .insert(insert_idx, (offset + size, range_end - offset - size));
+        }
+        block.num_allocations += 1;
+
+        let allocation = Allocation {
+            memory: block.memory,
+            offset,
+            size,
+            property_flags,
+            opt_mapped_ptr: block
+                .opt_mapped_ptr
+                .map(|ptr| unsafe { ptr.add(offset as usize) }),
+            opt_block_idx: Some(block_idx),
+            id: 0,
+        };
+        (memory_type_idx, allocation)
+    }
+
+    fn block_size(&self, memory_type_idx: usize) -> u64 {
+        let heap_idx = self.memory_properties.memory_types[memory_type_idx].heap_index as usize;
+        let heap_size = self.memory_properties.memory_heaps[heap_idx].size;
+        let block_size = MEMORY_BLOCK_SIZE.min(heap_size / MIN_BLOCKS_PER_HEAP);
+        block_size.div_ceil(self.non_coherent_atom_size) * self.non_coherent_atom_size
+    }
+
+    fn allocate_memory(
+        &self,
+        memory_type_idx: usize,
+        size: u64,
+    ) -> (vk::DeviceMemory, Option<*mut u8>) {
+        let allocate_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(size)
+            .memory_type_index(memory_type_idx as u32);
+        let memory = unsafe {
+            self.device
+                .allocate_memory(&allocate_info, None)
+                .expect("Failed to allocate memory.")
+        };
+        audit_created(
+            VkObjectKind::Memory,
+            memory,
+            &[device_id(&self.device)],
+            &format!("memory_type_{}", memory_type_idx),
+        );
+        let property_flags = self.memory_properties.memory_types[memory_type_idx].property_flags;
+        let opt_mapped_ptr = if property_flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            let ptr = unsafe {
+                self.device
+                    .map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+                    .expect("Failed to map memory.")
+            };
+            Some(ptr as *mut u8)
+        } else {
+            None
+        };
+        (memory, opt_mapped_ptr)
+    }
+
+    fn free_memory(&self, memory: vk::DeviceMemory) {
+        unsafe { self.device.free_memory(memory, None) };
+        audit_destroyed(VkObjectKind::Memory, memory);
+    }
+
+    /// The resource that was bound to the allocation must be destroyed first.
+    pub(crate) fn free(&self, allocation: &Allocation) {
+        let state = &mut *self.state.borrow_mut();
+        state
+            .live_allocations
+            .retain(|(id, _)| *id != allocation.id);
+        let block_idx = match allocation.opt_block_idx {
+            Some(block_idx) => block_idx,
+            None => {
+                if let Some(idx) = state
+                    .dedicated_allocations
+                    .iter()
+                    .position(|(memory, _, _)| *memory == allocation.memory)
+                {
+                    state.dedicated_allocations.remove(idx);
+                    self.free_memory(allocation.memory);
+                }
+                return;
+            }
+        };
+        // Blocks are gone after destroy()
+        let block = match state.blocks.get_mut(block_idx).and_then(|b| b.as_mut()) {
+            Some(block) => block,
+            None => return,
+        };
+
+        let free_ranges = &mut block.free_ranges;
+        let idx = free_ranges.partition_point(|(offset, _)| *offset < allocation.offset);
+        free_ranges.insert(idx, (allocation.offset, allocation.size));
+        // Merge with the neighbors, so that the block doesn't fragment
+        if idx + 1 < free_ranges.len()
+            && allocation.offset + allocation.size == free_ranges[idx + 1].0
+        {
+            free_ranges[idx].1 += free_ranges.remove(idx + 1).1;
+        }
+        if idx > 0 && free_ranges[idx - 1].0 + free_ranges[idx - 1].1 == allocation.offset {
+            free_ranges[idx - 1].1 += free_ranges.remove(idx).1;
+        }
+        block.num_allocations -= 1;
+
+        // Keep one block per memory type around, so that a resource that is
+        // recreated every frame doesn't allocate a block every frame
+        let (memory_type_idx, kind) = (block.memory_type_idx, block.kind);
+        if block.num_allocations == 0 {
+            let num_similar_blocks = state
+                .blocks
+                .iter()
+                .flatten()
+                .filter(|b| b.memory_type_idx == memory_type_idx && b.kind == kind)
+                .count();
+            if num_similar_blocks > 1 {
+                let block = state.blocks[block_idx].take().unwrap();
+                self.free_memory(block.memory);
+            }
+        }
+    }
+
+    /* Rename the allocation in the memory report, and name its memory if it
+    has its own, for debuggers. Memory in a block is shared with other
+    resources, so it keeps its block's name. */
+    pub(crate) fn rename(&self, allocation: &Allocation, name: &str, debug_utils: &DebugUtils) {
+        let state = &mut *self.state.borrow_mut();
+        if let Some((_, info)) = state
+            .live_allocations
+            .iter_mut()
+            .find(|(id, _)| *id == allocation.id)
+        {
+            info.name = String::from(name);
+        }
+        if allocation.opt_block_idx.is_none() {
+            debug_utils.set_memory_name(allocation.memory, name);
+        }
+    }
+
+    /// Every allocation that hasn't been freed, in the order they were made.
+    pub fn live_allocations(&self) -> Vec<AllocationInfo> {
+        self.state
+            .borrow()
+            .live_allocations
+            .iter()
+            .map(|(_, info)| info.clone())
+            .collect()
+    }
+
+    pub fn stats(&self) -> Vec<HeapStats> {
+        let mut heap_stats: Vec<HeapStats> = self.memory_properties.memory_heaps
+            [..self.memory_properties.memory_heap_count as usize]
+            .iter()
+            .enumerate()
+            .map(|(heap_idx, heap)| HeapStats {
+                heap_idx,
+                heap_size: heap.size,
+                is_device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+                ..Default::default()
+            })
+            .collect();
+        let heap_of = |memory_type_idx: usize| {
+            self.memory_properties.memory_types[memory_type_idx].heap_index as usize
+        };
+        let state = self.state.borrow();
+        for block in state.blocks.iter().flatten() {
+            let stats = &mut heap_stats[heap_of(block.memory_type_idx)];
+            let free_bytes: u64 = block.free_ranges.iter().map(|(_, size)| size).sum();
+            stats.num_blocks += 1;
+            stats.block_bytes += block.size;
+            stats.used_bytes += block.size - free_bytes;
+            stats.num_allocations += block.num_allocations;
+        }
+        for (_, memory_type_idx, size) in &state.dedicated_allocations {
+            let stats = &mut heap_stats[heap_of(*memory_type_idx)];
+            stats.num_blocks += 1;
+            stats.block_bytes += size;
+            stats.used_bytes += size;
+            stats.num_allocations += 1;
+        }
+        heap_stats
+    }
+
+    /// Free all memory. The device must be idle, and the resources that were
+    /// bound to it destroyed.
+    pub(crate) fn destroy(&self) {
+        let state = &mut *self.state.borrow_mut();
+        for block in state.blocks.drain(..).flatten() {
+            self.free_memory(block.memory);
+        }
+        for (memory, _, _) in state.dedicated_allocations.drain(..) {
+            self.free_memory(memory);
+        }
+    }
+}
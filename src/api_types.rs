@@ -0,0 +1,412 @@
+use crate::*;
+use std::ops::BitOr;
+
+/* Formats, usages and sizes as the context's API takes them, so that apps
+don't need to know about Vulkan. The engine converts them with `to_vk()` at
+the boundary, and uses Vulkan types everywhere behind it. */
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Format {
+    R8Unorm,
+    R8Srgb,
+    R8G8Unorm,
+    R8G8Srgb,
+    R8G8B8A8Unorm,
+    R8G8B8A8Srgb,
+    B8G8R8A8Unorm,
+    B8G8R8A8Srgb,
+    R32Sfloat,
+    R16G16B16A16Sfloat,
+    R32G32B32A32Sfloat,
+    D32Sfloat,
+    D32SfloatS8Uint,
+    D24UnormS8Uint,
+    // Block-compressed, for sampled images uploaded with their mips
+    Bc1RgbaUnorm,
+    Bc1RgbaSrgb,
+    Bc2Unorm,
+    Bc2Srgb,
+    Bc3Unorm,
+    Bc3Srgb,
+    Bc4Unorm,
+    Bc4Snorm,
+    Bc5Unorm,
+    Bc5Snorm,
+    Bc6hUfloat,
+    Bc6hSfloat,
+    Bc7Unorm,
+    Bc7Srgb,
+}
+
+/// In order of preference. `Context::depth_format()` is the first one that
+/// the device can render depth to.
+pub const DEPTH_FORMATS: [Format; 3] = [
+    Format::D32Sfloat,
+    Format::D32SfloatS8Uint,
+    Format::D24UnormS8Uint,
+];
+
+impl Format {
+    pub fn to_vk(self) -> vk::Format {
+        match self {
+            Format::R8Unorm => vk::Format::R8_UNORM,
+            Format::R8Srgb => vk::Format::R8_SRGB,
+            Format::R8G8Unorm => vk::Format::R8G8_UNORM,
+            Format::R8G8Srgb => vk::Format::R8G8_SRGB,
+            Format::R8G8B8A8Unorm => vk::Format::R8G8B8A8_UNORM,
+            Format::R8G8B8A8Srgb => vk::Format::R8G8B8A8_SRGB,
+            Format::B8G8R8A8Unorm => vk::Format::B8G8R8A8_UNORM,
+            Format::B8G8R8A8Srgb => vk::Format::B8G8R8A8_SRGB,
+            Format::R32Sfloat => vk::Format::R32_SFLOAT,
+            Format::R16G16B16A16Sfloat => vk::Format::R16G16B16A16_SFLOAT,
+            Format::R32G32B32A32Sfloat => vk::Format::R32G32B32A32_SFLOAT,
+            Format::D32Sfloat => vk::Format::D32_SFLOAT,
+            Format::D32SfloatS8Uint => vk::Format::D32_SFLOAT_S8_UINT,
+            Format::D24UnormS8Uint => vk::Format::D24_UNORM_S8_UINT,
+            Format::Bc1RgbaUnorm => vk::Format::BC1_RGBA_UNORM_BLOCK,
+            Format::Bc1RgbaSrgb => vk::Format::BC1_RGBA_SRGB_BLOCK,
+            Format::Bc2Unorm => vk::Format::BC2_UNORM_BLOCK,
+            Format::Bc2Srgb => vk::Format::BC2_SRGB_BLOCK,
+            Format::Bc3Unorm => vk::Format::BC3_UNORM_BLOCK,
+            Format::Bc3Srgb => vk::Format::BC3_SRGB_BLOCK,
+            Format::Bc4Unorm => vk::Format::BC4_UNORM_BLOCK,
+            Format::Bc4Snorm => vk::Format::BC4_SNORM_BLOCK,
+            Format::Bc5Unorm => vk::Format::BC5_UNORM_BLOCK,
+            Format::Bc5Snorm => vk::Format::BC5_SNORM_BLOCK,
+            Format::Bc6hUfloat => vk::Format::BC6H_UFLOAT_BLOCK,
+            Format::Bc6hSfloat => vk::Format::BC6H_SFLOAT_BLOCK,
+            Format::Bc7Unorm => vk::Format::BC7_UNORM_BLOCK,
+            Format::Bc7Srgb => vk::Format::BC7_SRGB_BLOCK,
+        }
+    }
+
+    pub fn is_depth(self) -> bool {
+        DEPTH_FORMATS.contains(&self)
+    }
+
+    pub fn is_block_compressed(self) -> bool {
+        bc_block_bytes(self.to_vk()).is_some()
+    }
+
+    pub fn has_stencil(self) -> bool {
+        self == Format::D32SfloatS8Uint || self == Format::D24UnormS8Uint
+    }
+
+    pub fn aspect_flags(self) -> vk::ImageAspectFlags {
+        if self.has_stencil() {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        } else if self.is_depth() {
+            vk::ImageAspectFlags::DEPTH
+        } else {
+            vk::ImageAspectFlags::COLOR
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ImageUsage(u32);
+
+impl ImageUsage {
+    pub const SAMPLED: ImageUsage = ImageUsage(1);
+    pub const COLOR_ATTACHMENT: ImageUsage = ImageUsage(1 << 1);
+    pub const DEPTH_ATTACHMENT: ImageUsage = ImageUsage(1 << 2);
+    pub const STORAGE: ImageUsage = ImageUsage(1 << 3);
+    pub const TRANSFER_SRC: ImageUsage = ImageUsage(1 << 4);
+    pub const TRANSFER_DST: ImageUsage = ImageUsage(1 << 5);
+
+    pub fn contains(self, other: ImageUsage) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn to_vk(self) -> vk::ImageUsageFlags {
+        let pairs = [
+            (ImageUsage::SAMPLED, vk::ImageUsageFlags::SAMPLED),
+            (
+                ImageUsage::COLOR_ATTACHMENT,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            ),
+            (
+                ImageUsage::DEPTH_ATTACHMENT,
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            ),
+            (ImageUsage::STORAGE, vk::ImageUsageFlags::STORAGE),
+            (ImageUsage::TRANSFER_SRC, vk::ImageUsageFlags::TRANSFER_SRC),
+            (ImageUsage::TRANSFER_DST, vk::ImageUsageFlags::TRANSFER_DST),
+        ];
+        pairs
+            .iter()
+            .filter(|(usage, _)| self.contains(*usage))
+            .fold(vk::ImageUsageFlags::empty(), |flags, (_, vk_flags)| {
+                flags | *vk_flags
+            })
+    }
+}
+
+impl BitOr for ImageUsage {
+    type Output = ImageUsage;
+    fn bitor(self, other: ImageUsage) -> ImageUsage {
+        ImageUsage(self.0 | other.0)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BufferUsage(u32);
+
+impl BufferUsage {
+    pub const VERTEX: BufferUsage = BufferUsage(1);
+    pub const INDEX: BufferUsage = BufferUsage(1 << 1);
+    pub const UNIFORM: BufferUsage = BufferUsage(1 << 2);
+    pub const STORAGE: BufferUsage = BufferUsage(1 << 3);
+    pub const TRANSFER_SRC: BufferUsage = BufferUsage(1 << 4);
+    pub const TRANSFER_DST: BufferUsage = BufferUsage(1 << 5);
+    pub const UNIFORM_TEXEL: BufferUsage = BufferUsage(1 << 6);
+    pub const STORAGE_TEXEL: BufferUsage = BufferUsage(1 << 7);
+    pub const INDIRECT: BufferUsage = BufferUsage(1 << 8);
+
+    pub fn contains(self, other: BufferUsage) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn to_vk(self) -> vk::BufferUsageFlags {
+        let pairs = [
+            (BufferUsage::VERTEX, vk::BufferUsageFlags::VERTEX_BUFFER),
+            (BufferUsage::INDEX, vk::BufferUsageFlags::INDEX_BUFFER),
+            (BufferUsage::UNIFORM, vk::BufferUsageFlags::UNIFORM_BUFFER),
+            (BufferUsage::STORAGE, vk::BufferUsageFlags::STORAGE_BUFFER),
+            (
+                BufferUsage::TRANSFER_SRC,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+            ),
+            (
+                BufferUsage::TRANSFER_DST,
+                vk::BufferUsageFlags::TRANSFER_DST,
+            ),
+            (
+                BufferUsage::UNIFORM_TEXEL,
+                vk::BufferUsageFlags::UNIFORM_TEXEL_BUFFER,
+            ),
+            (
+                BufferUsage::STORAGE_TEXEL,
+                vk::BufferUsageFlags::STORAGE_TEXEL_BUFFER,
+            ),
+            (BufferUsage::INDIRECT, vk::BufferUsageFlags::INDIRECT_BUFFER),
+        ];
+        pairs
+            .iter()
+            .filter(|(usage, _)| self.contains(*usage))
+            .fold(vk::BufferUsageFlags::empty(), |flags, (_, vk_flags)| {
+                flags | *vk_flags
+            })
+    }
+}
+
+impl BitOr for BufferUsage {
+    type Output = BufferUsage;
+    fn bitor(self, other: BufferUsage) -> BufferUsage {
+        BufferUsage(self.0 | other.0)
+    }
+}
+
+/// How a pass's depth test compares a fragment's depth to the depth image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CompareOp {
+    Never,
+    Less,
+    Equal,
+    LessOrEqual,
+    Greater,
+    NotEqual,
+    GreaterOrEqual,
+    Always,
+}
+
+impl CompareOp {
+    pub fn to_vk(self) -> vk::CompareOp {
+        match self {
+            CompareOp::Never => vk::CompareOp::NEVER,
+            CompareOp::Less => vk::CompareOp::LESS,
+            CompareOp::Equal => vk::CompareOp::EQUAL,
+            CompareOp::LessOrEqual => vk::CompareOp::LESS_OR_EQUAL,
+            CompareOp::Greater => vk::CompareOp::GREATER,
+            CompareOp::NotEqual => vk::CompareOp::NOT_EQUAL,
+            CompareOp::GreaterOrEqual => vk::CompareOp::GREATER_OR_EQUAL,
+            CompareOp::Always => vk::CompareOp::ALWAYS,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+impl Filter {
+    pub fn to_vk(self) -> vk::Filter {
+        match self {
+            Filter::Nearest => vk::Filter::NEAREST,
+            Filter::Linear => vk::Filter::LINEAR,
+        }
+    }
+
+    pub fn to_vk_mipmap_mode(self) -> vk::SamplerMipmapMode {
+        match self {
+            Filter::Nearest => vk::SamplerMipmapMode::NEAREST,
+            Filter::Linear => vk::SamplerMipmapMode::LINEAR,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AddressMode {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+    ClampToBorder, // Opaque black outside the image
+}
+
+impl AddressMode {
+    pub fn to_vk(self) -> vk::SamplerAddressMode {
+        match self {
+            AddressMode::Repeat => vk::SamplerAddressMode::REPEAT,
+            AddressMode::MirroredRepeat => vk::SamplerAddressMode::MIRRORED_REPEAT,
+            AddressMode::ClampToEdge => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            AddressMode::ClampToBorder => vk::SamplerAddressMode::CLAMP_TO_BORDER,
+        }
+    }
+}
+
+/// Samples per texel of the images that a pass renders to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SampleCount {
+    X1,
+    X2,
+    X4,
+    X8,
+}
+
+impl SampleCount {
+    pub fn to_vk(self) -> vk::SampleCountFlags {
+        match self {
+            SampleCount::X1 => vk::SampleCountFlags::TYPE_1,
+            SampleCount::X2 => vk::SampleCountFlags::TYPE_2,
+            SampleCount::X4 => vk::SampleCountFlags::TYPE_4,
+            SampleCount::X8 => vk::SampleCountFlags::TYPE_8,
+        }
+    }
+
+    /// Of an image's `samples`, which holds a single count.
+    pub fn from_vk(samples: vk::SampleCountFlags) -> SampleCount {
+        match samples {
+            vk::SampleCountFlags::TYPE_2 => SampleCount::X2,
+            vk::SampleCountFlags::TYPE_4 => SampleCount::X4,
+            vk::SampleCountFlags::TYPE_8 => SampleCount::X8,
+            _ => SampleCount::X1,
+        }
+    }
+
+    /// The next lower count, or `X1` itself.
+    pub fn lower(self) -> SampleCount {
+        match self {
+            SampleCount::X1 | SampleCount::X2 => SampleCount::X1,
+            SampleCount::X4 => SampleCount::X2,
+            SampleCount::X8 => SampleCount::X4,
+        }
+    }
+
+    pub fn is_multisampled(self) -> bool {
+        self != SampleCount::X1
+    }
+}
+
+/* How the texels of an image file are encoded, so that it gets a matching
+format. Color textures, like albedo maps, are authored in sRGB and are
+decoded to linear when sampled. Data, like normal and roughness maps, is
+linear and is sampled as it is stored. */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    /// Image files are loaded as 8-bit RGBA.
+    pub fn rgba8_format(self) -> vk::Format {
+        match self {
+            ColorSpace::Srgb => vk::Format::R8G8B8A8_SRGB,
+            ColorSpace::Linear => vk::Format::R8G8B8A8_UNORM,
+        }
+    }
+}
+
+/* How an image is sized. Swapchain-relative images are recreated with the
+swapchain, keeping their names, formats and usages, so they always match the
+window. Absolute sizes are left alone by resizes. */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ImageSize {
+    Absolute(Extent),
+    SwapchainRelative(f32), // E.g. 0.5 for half the window's width and height
+}
+
+/// Size of an image in texels. `depth` is 1 for 2D images.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Extent {
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+}
+
+impl Extent {
+    pub fn new_2d(width: u32, height: u32) -> Extent {
+        Extent {
+            width,
+            height,
+            depth: 1,
+        }
+    }
+
+    pub fn new_3d(width: u32, height: u32, depth: u32) -> Extent {
+        Extent {
+            width,
+            height,
+            depth,
+        }
+    }
+
+    pub fn to_vk(self) -> vk::Extent3D {
+        vk::Extent3D {
+            width: self.width,
+            height: self.height,
+            depth: self.depth,
+        }
+    }
+}
+
+/// The arguments of draws in an indirect buffer, which the GPU can write.
+pub trait IndirectCommand: Copy {}
+
+/// Laid out like `VkDrawIndirectCommand`. `first_instance` must be 0, since
+/// the `drawIndirectFirstInstance` feature isn't enabled.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DrawIndirectCommand {
+    pub num_vertices: u32,
+    pub num_instances: u32,
+    pub first_vertex: u32,
+    pub first_instance: u32,
+}
+
+impl IndirectCommand for DrawIndirectCommand {}
+
+/// Laid out like `VkDrawIndexedIndirectCommand`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DrawIndexedIndirectCommand {
+    pub num_indices: u32,
+    pub num_instances: u32,
+    pub first_index: u32,
+    pub vertex_offset: i32,
+    pub first_instance: u32,
+}
+
+impl IndirectCommand for DrawIndexedIndirectCommand {}
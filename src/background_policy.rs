@@ -0,0 +1,27 @@
+use std::time::Duration;
+
+// How often events are checked while rendering is paused
+pub const PAUSED_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What `begin_frame()` does while the window doesn't have focus, so that a
+/// window in the background doesn't keep the GPU busy.
+#[derive(Copy, Clone, Debug)]
+pub struct BackgroundPolicy {
+    pub opt_throttle_fps: Option<f32>, // Frame rate cap while unfocused. None keeps the full rate.
+    pub is_rendering_paused: bool,     // Don't return from begin_frame() until focus is back
+}
+
+impl BackgroundPolicy {
+    pub fn new() -> BackgroundPolicy {
+        BackgroundPolicy {
+            opt_throttle_fps: Some(10.0),
+            is_rendering_paused: false,
+        }
+    }
+
+    /// Minimum time between the starts of two background frames.
+    pub fn min_frame_duration(&self) -> Option<Duration> {
+        self.opt_throttle_fps
+            .map(|fps| Duration::from_secs_f32(1.0 / fps.max(0.1)))
+    }
+}
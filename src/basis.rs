@@ -3,11 +3,15 @@ use ash::vk_make_version;
 use std::os::raw::c_char;
 use winit::window::Window;
 
+// Needed by device extensions like VK_KHR_multiview on Vulkan 1.0
+const PROPERTIES_2_EXT: &str = "VK_KHR_get_physical_device_properties2";
+
 pub struct Basis {
     pub entry: ash::Entry,
     pub instance: ash::Instance,
     pub surface: vk::SurfaceKHR,
     pub validation_layers: Vec<String>,
+    pub is_properties_2_enabled: bool, // VK_KHR_get_physical_device_properties2, if it's available
 
     // - Extensions
     pub ext_surface: ash::extensions::khr::Surface,
@@ -17,7 +21,9 @@ impl Drop for Basis {
     fn drop(&mut self) {
         unsafe {
             self.ext_surface.destroy_surface(self.surface, None);
+            audit_destroyed(VkObjectKind::Surface, self.surface);
             self.instance.destroy_instance(None);
+            audit_destroyed(VkObjectKind::Instance, self.instance.handle());
         }
     }
 }
@@ -29,6 +35,12 @@ impl Basis {
         // # Init Ash
         let entry = ash::Entry::new().unwrap();
 
+        let is_properties_2_enabled = entry
+            .enumerate_instance_extension_properties()
+            .expect("Failed to enumerate instance extensions.")
+            .iter()
+            .any(|ext| vk_to_string(&ext.extension_name) == PROPERTIES_2_EXT);
+
         // # Create Vulkan instance
         let instance = {
             let app_name = CString::new(app_name).unwrap();
@@ -71,7 +83,11 @@ impl Basis {
                 .map(|layer_name| layer_name.as_ptr())
                 .collect();
 
-            let extension_names = platforms::required_extension_names();
+            let properties_2_ext = CString::new(PROPERTIES_2_EXT).unwrap();
+            let mut extension_names = platforms::required_extension_names();
+            if is_properties_2_enabled {
+                extension_names.push(properties_2_ext.as_ptr());
+            }
 
             let create_info = vk::InstanceCreateInfo::builder()
                 .enabled_layer_names(&layer_names)
@@ -84,22 +100,46 @@ impl Basis {
                     .expect("Failed to create instance.")
             };
 
+            audit_created(VkObjectKind::Instance, instance.handle(), &[], "instance");
             instance
         };
 
         // # Create surface
         let ext_surface = ash::extensions::khr::Surface::new(&entry, &instance);
         let surface = unsafe {
-            platforms::create_surface(&entry, &instance, &window)
-                .expect("Failed to create surface.")
+            platforms::create_surface(&entry, &instance, window).expect("Failed to create surface.")
         };
+        audit_created(
+            VkObjectKind::Surface,
+            surface,
+            &[vk_object_id(VkObjectKind::Instance, instance.handle())],
+            "surface",
+        );
 
         Basis {
             instance,
             surface,
             validation_layers,
+            is_properties_2_enabled,
             entry,
             ext_surface,
         }
     }
+
+    /// Replace a lost surface with a new one for the same window. Anything
+    /// created from the old surface, like the swapchain, must be gone.
+    pub fn recreate_surface(&mut self, window: &Window) {
+        unsafe {
+            self.ext_surface.destroy_surface(self.surface, None);
+            audit_destroyed(VkObjectKind::Surface, self.surface);
+            self.surface = platforms::create_surface(&self.entry, &self.instance, window)
+                .expect("Failed to recreate surface.");
+            audit_created(
+                VkObjectKind::Surface,
+                self.surface,
+                &[vk_object_id(VkObjectKind::Instance, self.instance.handle())],
+                "surface",
+            );
+        }
+    }
 }
@@ -0,0 +1,743 @@
+use crate::*;
+
+/* Block-compressed formats store 4x4 texel blocks, including the blocks at
+the right and bottom edges of mips whose sizes aren't multiples of 4. Copies
+still use the mip's true size as their extent, which Vulkan allows for
+regions that end at the edge of the mip. */
+pub const BC_BLOCK_SIZE: u32 = 4;
+
+/// Bytes per 4x4 block, or None if `format` isn't block-compressed.
+pub(crate) fn bc_block_bytes(format: vk::Format) -> Option<usize> {
+    match format {
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC4_SNORM_BLOCK => Some(8),
+        vk::Format::BC2_UNORM_BLOCK
+        | vk::Format::BC2_SRGB_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::BC5_SNORM_BLOCK
+        | vk::Format::BC6H_UFLOAT_BLOCK
+        | vk::Format::BC6H_SFLOAT_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK => Some(16),
+        _ => None,
+    }
+}
+
+/// Size of one layer of a mip, for block-compressed formats and the
+/// uncompressed ones that `format_texel_size()` knows.
+pub(crate) fn mip_size_in_bytes(
+    format: vk::Format,
+    width: u32,
+    height: u32,
+    depth: u32,
+) -> Option<usize> {
+    if let Some(block_bytes) = bc_block_bytes(format) {
+        let num_blocks_x = width.div_ceil(BC_BLOCK_SIZE) as usize;
+        let num_blocks_y = height.div_ceil(BC_BLOCK_SIZE) as usize;
+        return Some(num_blocks_x * num_blocks_y * depth as usize * block_bytes);
+    }
+    format_texel_size(format)
+        .map(|texel_size| width as usize * height as usize * depth as usize * texel_size)
+}
+
+/// The format that `decode_bc_to_rgba8()` decodes to, if it can decode `format`.
+pub fn bc_decoded_format(format: vk::Format) -> Option<vk::Format> {
+    match format {
+        vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC2_SRGB_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK => Some(vk::Format::R8G8B8A8_SRGB),
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC2_UNORM_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK => Some(vk::Format::R8G8B8A8_UNORM),
+        _ => None, // Signed and HDR formats don't fit in RGBA8
+    }
+}
+
+/* Decode one layer of a mip, for devices that can't sample `format`.
+Channels that the format doesn't store are decoded like the GPU would
+sample them, e.g. BC4 to (r, 0, 0, 255). */
+pub fn decode_bc_to_rgba8(
+    format: vk::Format,
+    width: u32,
+    height: u32,
+    data: &[u8],
+) -> Result<Vec<u8>, String> {
+    if bc_decoded_format(format).is_none() {
+        return Err(format!("Decoding {:?} isn't supported.", format));
+    }
+    let block_bytes = bc_block_bytes(format).unwrap();
+    let required_size = mip_size_in_bytes(format, width, height, 1).unwrap();
+    if data.len() < required_size {
+        return Err(format!(
+            "{:?} data is {} bytes, but a {}x{} mip needs {} bytes.",
+            format,
+            data.len(),
+            width,
+            height,
+            required_size
+        ));
+    }
+
+    let mut rgba = vec![0; width as usize * height as usize * 4];
+    let num_blocks_x = width.div_ceil(BC_BLOCK_SIZE);
+    for (block_idx, block) in data[..required_size].chunks_exact(block_bytes).enumerate() {
+        let texels = match format {
+            vk::Format::BC1_RGB_UNORM_BLOCK | vk::Format::BC1_RGB_SRGB_BLOCK => {
+                let mut texels = decode_bc1(block, true);
+                for texel in &mut texels {
+                    texel[3] = 255;
+                }
+                texels
+            }
+            vk::Format::BC1_RGBA_UNORM_BLOCK | vk::Format::BC1_RGBA_SRGB_BLOCK => {
+                decode_bc1(block, true)
+            }
+            vk::Format::BC2_UNORM_BLOCK | vk::Format::BC2_SRGB_BLOCK => {
+                let mut texels = decode_bc1(&block[8..], false);
+                for (texel_idx, texel) in texels.iter_mut().enumerate() {
+                    let alpha = (block[texel_idx / 2] >> (4 * (texel_idx % 2))) & 0xf;
+                    texel[3] = alpha * 17;
+                }
+                texels
+            }
+            vk::Format::BC3_UNORM_BLOCK | vk::Format::BC3_SRGB_BLOCK => {
+                let mut texels = decode_bc1(&block[8..], false);
+                let alphas = decode_bc4_channel(&block[..8]);
+                for (texel, alpha) in texels.iter_mut().zip(alphas.iter()) {
+                    texel[3] = *alpha;
+                }
+                texels
+            }
+            vk::Format::BC4_UNORM_BLOCK => {
+                let mut texels = [[0, 0, 0, 255]; 16];
+                for (texel, red) in texels.iter_mut().zip(decode_bc4_channel(block).iter()) {
+                    texel[0] = *red;
+                }
+                texels
+            }
+            vk::Format::BC5_UNORM_BLOCK => {
+                let mut texels = [[0, 0, 0, 255]; 16];
+                let reds = decode_bc4_channel(&block[..8]);
+                let greens = decode_bc4_channel(&block[8..]);
+                for (texel_idx, texel) in texels.iter_mut().enumerate() {
+                    texel[0] = reds[texel_idx];
+                    texel[1] = greens[texel_idx];
+                }
+                texels
+            }
+            _ => decode_bc7(block),
+        };
+
+        // Edge blocks cover texels past the mip, which are dropped
+        let block_x = (block_idx as u32 % num_blocks_x) * BC_BLOCK_SIZE;
+        let block_y = (block_idx as u32 / num_blocks_x) * BC_BLOCK_SIZE;
+        for (texel_idx, texel) in texels.iter().enumerate() {
+            let x = block_x + texel_idx as u32 % BC_BLOCK_SIZE;
+            let y = block_y + texel_idx as u32 / BC_BLOCK_SIZE;
+            if x < width && y < height {
+                let offset = (y as usize * width as usize + x as usize) * 4;
+                rgba[offset..offset + 4].copy_from_slice(texel);
+            }
+        }
+    }
+    Ok(rgba)
+}
+
+fn rgb565_to_rgb8(color: u16) -> [u32; 3] {
+    let r = (color >> 11) as u32 & 0x1f;
+    let g = (color >> 5) as u32 & 0x3f;
+    let b = color as u32 & 0x1f;
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+    ]
+}
+
+// BC2 and BC3 always use four colors, whatever the order of the endpoints
+fn decode_bc1(block: &[u8], has_punch_through: bool) -> [[u8; 4]; 16] {
+    let color0 = u16::from_le_bytes([block[0], block[1]]);
+    let color1 = u16::from_le_bytes([block[2], block[3]]);
+    let (c0, c1) = (rgb565_to_rgb8(color0), rgb565_to_rgb8(color1));
+    let mut palette = [[0; 4]; 4];
+    palette[0] = [c0[0] as u8, c0[1] as u8, c0[2] as u8, 255];
+    palette[1] = [c1[0] as u8, c1[1] as u8, c1[2] as u8, 255];
+    if color0 > color1 || !has_punch_through {
+        for channel in 0..3 {
+            palette[2][channel] = ((2 * c0[channel] + c1[channel]) / 3) as u8;
+            palette[3][channel] = ((c0[channel] + 2 * c1[channel]) / 3) as u8;
+        }
+        palette[2][3] = 255;
+        palette[3][3] = 255;
+    } else {
+        for channel in 0..3 {
+            palette[2][channel] = ((c0[channel] + c1[channel]) / 2) as u8;
+        }
+        palette[2][3] = 255;
+        palette[3] = [0, 0, 0, 0]; // Transparent black
+    }
+
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    let mut texels = [[0; 4]; 16];
+    for (texel_idx, texel) in texels.iter_mut().enumerate() {
+        *texel = palette[((indices >> (2 * texel_idx)) & 0x3) as usize];
+    }
+    texels
+}
+
+// BC3's alpha, and each channel of BC4 and BC5
+fn decode_bc4_channel(block: &[u8]) -> [u8; 16] {
+    let (v0, v1) = (block[0] as u32, block[1] as u32);
+    let mut palette = [0; 8];
+    palette[0] = v0;
+    palette[1] = v1;
+    if v0 > v1 {
+        for i in 1..7 {
+            palette[i + 1] = ((7 - i as u32) * v0 + i as u32 * v1) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = ((5 - i as u32) * v0 + i as u32 * v1) / 5;
+        }
+        palette[6] = 0;
+        palette[7] = 255;
+    }
+
+    let mut indices = 0_u64;
+    for (byte_idx, byte) in block[2..8].iter().enumerate() {
+        indices |= (*byte as u64) << (8 * byte_idx);
+    }
+    let mut values = [0; 16];
+    for (texel_idx, value) in values.iter_mut().enumerate() {
+        *value = palette[((indices >> (3 * texel_idx)) & 0x7) as usize] as u8;
+    }
+    values
+}
+
+struct Bc7Mode {
+    num_subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_p_bits: bool, // One per endpoint
+    shared_p_bits: bool,   // One per subset
+    index_bits: u32,
+    secondary_index_bits: u32, // Of the separate alpha indices, in modes 4 and 5
+}
+
+#[rustfmt::skip]
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode { num_subsets: 3, partition_bits: 4, rotation_bits: 0, index_selection_bits: 0, color_bits: 4, alpha_bits: 0, endpoint_p_bits: true, shared_p_bits: false, index_bits: 3, secondary_index_bits: 0 },
+    Bc7Mode { num_subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 6, alpha_bits: 0, endpoint_p_bits: false, shared_p_bits: true, index_bits: 3, secondary_index_bits: 0 },
+    Bc7Mode { num_subsets: 3, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 0, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, secondary_index_bits: 0 },
+    Bc7Mode { num_subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 0, endpoint_p_bits: true, shared_p_bits: false, index_bits: 2, secondary_index_bits: 0 },
+    Bc7Mode { num_subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 1, color_bits: 5, alpha_bits: 6, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, secondary_index_bits: 3 },
+    Bc7Mode { num_subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 0, color_bits: 7, alpha_bits: 8, endpoint_p_bits: false, shared_p_bits: false, index_bits: 2, secondary_index_bits: 2 },
+    Bc7Mode { num_subsets: 1, partition_bits: 0, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 7, endpoint_p_bits: true, shared_p_bits: false, index_bits: 4, secondary_index_bits: 0 },
+    Bc7Mode { num_subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 5, endpoint_p_bits: true, shared_p_bits: false, index_bits: 2, secondary_index_bits: 0 },
+];
+
+// The subset of every texel, by partition
+#[rustfmt::skip]
+const BC7_PARTITIONS_2: [[u8; 16]; 64] = [
+    [0,0,1,1,0,0,1,1,0,0,1,1,0,0,1,1], [0,0,0,1,0,0,0,1,0,0,0,1,0,0,0,1],
+    [0,1,1,1,0,1,1,1,0,1,1,1,0,1,1,1], [0,0,0,1,0,0,1,1,0,0,1,1,0,1,1,1],
+    [0,0,0,0,0,0,0,1,0,0,0,1,0,0,1,1], [0,0,1,1,0,1,1,1,0,1,1,1,1,1,1,1],
+    [0,0,0,1,0,0,1,1,0,1,1,1,1,1,1,1], [0,0,0,0,0,0,0,1,0,0,1,1,0,1,1,1],
+    [0,0,0,0,0,0,0,0,0,0,0,1,0,0,1,1], [0,0,1,1,0,1,1,1,1,1,1,1,1,1,1,1],
+    [0,0,0,0,0,0,0,1,0,1,1,1,1,1,1,1], [0,0,0,0,0,0,0,0,0,0,0,1,0,1,1,1],
+    [0,0,0,1,0,1,1,1,1,1,1,1,1,1,1,1], [0,0,0,0,0,0,0,0,1,1,1,1,1,1,1,1],
+    [0,0,0,0,1,1,1,1,1,1,1,1,1,1,1,1], [0,0,0,0,0,0,0,0,0,0,0,0,1,1,1,1],
+    [0,0,0,0,1,0,0,0,1,1,1,0,1,1,1,1], [0,1,1,1,0,0,0,1,0,0,0,0,0,0,0,0],
+    [0,0,0,0,0,0,0,0,1,0,0,0,1,1,1,0], [0,1,1,1,0,0,1,1,0,0,0,1,0,0,0,0],
+    [0,0,1,1,0,0,0,1,0,0,0,0,0,0,0,0], [0,0,0,0,1,0,0,0,1,1,0,0,1,1,1,0],
+    [0,0,0,0,0,0,0,0,1,0,0,0,1,1,0,0], [0,1,1,1,0,0,1,1,0,0,1,1,0,0,0,1],
+    [0,0,1,1,0,0,0,1,0,0,0,1,0,0,0,0], [0,0,0,0,1,0,0,0,1,0,0,0,1,1,0,0],
+    [0,1,1,0,0,1,1,0,0,1,1,0,0,1,1,0], [0,0,1,1,0,1,1,0,0,1,1,0,1,1,0,0],
+    [0,0,0,1,0,1,1,1,1,1,1,0,1,0,0,0], [0,0,0,0,1,1,1,1,1,1,1,1,0,0,0,0],
+    [0,1,1,1,0,0,0,1,1,0,0,0,1,1,1,0], [0,0,1,1,1,0,0,1,1,0,0,1,1,1,0,0],
+    [0,1,0,1,0,1,0,1,0,1,0,1,0,1,0,1], [0,0,0,0,1,1,1,1,0,0,0,0,1,1,1,1],
+    [0,1,0,1,1,0,1,0,0,1,0,1,1,0,1,0], [0,0,1,1,0,0,1,1,1,1,0,0,1,1,0,0],
+    [0,0,1,1,1,1,0,0,0,0,1,1,1,1,0,0], [0,1,0,1,0,1,0,1,1,0,1,0,1,0,1,0],
+    [0,1,1,0,1,0,0,1,0,1,1,0,1,0,0,1], [0,1,0,1,1,0,1,0,1,0,1,0,0,1,0,1],
+    [0,1,1,1,0,0,1,1,1,1,0,0,1,1,1,0], [0,0,0,1,0,0,1,1,1,1,0,0,1,0,0,0],
+    [0,0,1,1,0,0,1,0,0,1,0,0,1,1,0,0], [0,0,1,1,1,0,1,1,1,1,0,1,1,1,0,0],
+    [0,1,1,0,1,0,0,1,1,0,0,1,0,1,1,0], [0,0,1,1,1,1,0,0,1,1,0,0,0,0,1,1],
+    [0,1,1,0,0,1,1,0,1,0,0,1,1,0,0,1], [0,0,0,0,0,1,1,0,0,1,1,0,0,0,0,0],
+    [0,1,0,0,1,1,1,0,0,1,0,0,0,0,0,0], [0,0,1,0,0,1,1,1,0,0,1,0,0,0,0,0],
+    [0,0,0,0,0,0,1,0,0,1,1,1,0,0,1,0], [0,0,0,0,0,1,0,0,1,1,1,0,0,1,0,0],
+    [0,1,1,0,1,1,0,0,1,0,0,1,0,0,1,1], [0,0,1,1,0,1,1,0,1,1,0,0,1,0,0,1],
+    [0,1,1,0,0,0,1,1,1,0,0,1,1,1,0,0], [0,0,1,1,1,0,0,1,1,1,0,0,0,1,1,0],
+    [0,1,1,0,1,1,0,0,1,1,0,0,1,0,0,1], [0,1,1,0,0,0,1,1,0,0,1,1,1,0,0,1],
+    [0,1,1,1,1,1,1,0,1,0,0,0,0,0,0,1], [0,0,0,1,1,0,0,0,1,1,1,0,0,1,1,1],
+    [0,0,0,0,1,1,1,1,0,0,1,1,0,0,1,1], [0,0,1,1,0,0,1,1,1,1,1,1,0,0,0,0],
+    [0,0,1,0,0,0,1,0,1,1,1,0,1,1,1,0], [0,1,0,0,0,1,0,0,0,1,1,1,0,1,1,1],
+];
+
+#[rustfmt::skip]
+const BC7_PARTITIONS_3: [[u8; 16]; 64] = [
+    [0,0,1,1,0,0,1,1,0,2,2,1,2,2,2,2], [0,0,0,1,0,0,1,1,2,2,1,1,2,2,2,1],
+    [0,0,0,0,2,0,0,1,2,2,1,1,2,2,1,1], [0,2,2,2,0,0,2,2,0,0,1,1,0,1,1,1],
+    [0,0,0,0,0,0,0,0,1,1,2,2,1,1,2,2], [0,0,1,1,0,0,1,1,0,0,2,2,0,0,2,2],
+    [0,0,2,2,0,0,2,2,1,1,1,1,1,1,1,1], [0,0,1,1,0,0,1,1,2,2,1,1,2,2,1,1],
+    [0,0,0,0,0,0,0,0,1,1,1,1,2,2,2,2], [0,0,0,0,1,1,1,1,1,1,1,1,2,2,2,2],
+    [0,0,0,0,1,1,1,1,2,2,2,2,2,2,2,2], [0,0,1,2,0,0,1,2,0,0,1,2,0,0,1,2],
+    [0,1,1,2,0,1,1,2,0,1,1,2,0,1,1,2], [0,1,2,2,0,1,2,2,0,1,2,2,0,1,2,2],
+    [0,0,1,1,0,1,1,2,1,1,2,2,1,2,2,2], [0,0,1,1,2,0,0,1,2,2,0,0,2,2,2,0],
+    [0,0,0,1,0,0,1,1,0,1,1,2,1,1,2,2], [0,1,1,1,0,0,1,1,2,0,0,1,2,2,0,0],
+    [0,0,0,0,1,1,2,2,1,1,2,2,1,1,2,2], [0,0,2,2,0,0,2,2,0,0,2,2,1,1,1,1],
+    [0,1,1,1,0,1,1,1,0,2,2,2,0,2,2,2], [0,0,0,1,0,0,0,1,2,2,2,1,2,2,2,1],
+    [0,0,0,0,0,0,1,1,0,1,2,2,0,1,2,2], [0,0,0,0,1,1,0,0,2,2,1,0,2,2,1,0],
+    [0,1,2,2,0,1,2,2,0,0,1,1,0,0,0,0], [0,0,1,2,0,0,1,2,1,1,2,2,2,2,2,2],
+    [0,1,1,0,1,2,2,1,1,2,2,1,0,1,1,0], [0,0,0,0,0,1,1,0,1,2,2,1,1,2,2,1],
+    [0,0,2,2,1,1,0,2,1,1,0,2,0,0,2,2], [0,1,1,0,0,1,1,0,2,0,0,2,2,2,2,2],
+    [0,0,1,1,0,1,2,2,0,1,2,2,0,0,1,1], [0,0,0,0,2,0,0,0,2,2,1,1,2,2,2,1],
+    [0,0,0,0,0,0,0,2,1,1,2,2,1,2,2,2], [0,2,2,2,0,0,2,2,0,0,1,2,0,0,1,1],
+    [0,0,1,1,0,0,1,2,0,0,2,2,0,2,2,2], [0,1,2,0,0,1,2,0,0,1,2,0,0,1,2,0],
+    [0,0,0,0,1,1,1,1,2,2,2,2,0,0,0,0], [0,1,2,0,1,2,0,1,2,0,1,2,0,1,2,0],
+    [0,1,2,0,2,0,1,2,1,2,0,1,0,1,2,0], [0,0,1,1,2,2,0,0,1,1,2,2,0,0,1,1],
+    [0,0,1,1,1,1,2,2,2,2,0,0,0,0,1,1], [0,1,0,1,0,1,0,1,2,2,2,2,2,2,2,2],
+    [0,0,0,0,0,0,0,0,2,1,2,1,2,1,2,1], [0,0,2,2,1,1,2,2,0,0,2,2,1,1,2,2],
+    [0,0,2,2,0,0,1,1,0,0,2,2,0,0,1,1], [0,2,2,0,1,2,2,1,0,2,2,0,1,2,2,1],
+    [0,1,0,1,2,2,2,2,2,2,2,2,0,1,0,1], [0,0,0,0,2,1,2,1,2,1,2,1,2,1,2,1],
+    [0,1,0,1,0,1,0,1,0,1,0,1,2,2,2,2], [0,2,2,2,0,1,1,1,0,2,2,2,0,1,1,1],
+    [0,0,0,2,1,1,1,2,0,0,0,2,1,1,1,2], [0,0,0,0,2,1,1,2,2,1,1,2,2,1,1,2],
+    [0,2,2,2,0,1,1,1,0,1,1,1,0,2,2,2], [0,0,0,2,1,1,1,2,1,1,1,2,0,0,0,2],
+    [0,1,1,0,0,1,1,0,0,1,1,0,2,2,2,2], [0,0,0,0,0,0,0,0,2,1,1,2,2,1,1,2],
+    [0,1,1,0,0,1,1,0,2,2,2,2,2,2,2,2], [0,0,2,2,0,0,1,1,0,0,1,1,0,0,2,2],
+    [0,0,2,2,1,1,2,2,1,1,2,2,0,0,2,2], [0,0,0,0,0,0,0,0,0,0,0,0,2,1,1,2],
+    [0,0,0,2,0,0,0,1,0,0,0,2,0,0,0,1], [0,2,2,2,1,2,2,2,0,2,2,2,1,2,2,2],
+    [0,1,0,1,2,2,2,2,2,2,2,2,2,2,2,2], [0,1,1,1,2,0,1,1,2,2,0,1,2,2,2,0],
+];
+
+// The texel of each subset after the first whose index has one fewer bit
+#[rustfmt::skip]
+const BC7_ANCHORS_2: [u8; 64] = [
+    15,15,15,15,15,15,15,15, 15,15,15,15,15,15,15,15,
+    15, 2, 8, 2, 2, 8, 8,15,  2, 8, 2, 2, 8, 8, 2, 2,
+    15,15, 6, 8, 2, 8,15,15,  2, 8, 2, 2, 2,15,15, 6,
+     6, 2, 6, 8,15,15, 2, 2, 15,15,15,15,15, 2, 2,15,
+];
+
+#[rustfmt::skip]
+const BC7_ANCHORS_3_SECOND: [u8; 64] = [
+     3, 3,15,15, 8, 3,15,15,  8, 8, 6, 6, 6, 5, 3, 3,
+     3, 3, 8,15, 3, 3, 6,10,  5, 8, 8, 6, 8, 5,15,15,
+     8,15, 3, 5, 6,10, 8,15, 15, 3,15, 5,15,15,15,15,
+     3,15, 5, 5, 5, 8, 5,10,  5,10, 8,13,15,12, 3, 3,
+];
+
+#[rustfmt::skip]
+const BC7_ANCHORS_3_THIRD: [u8; 64] = [
+    15, 8, 8, 3,15,15, 3, 8, 15,15,15,15,15,15,15, 8,
+    15, 8,15, 3,15, 8,15, 8,  3,15, 6,10,15,15,10, 8,
+    15, 3,15,10,10, 8, 9,10,  6,15, 8,15, 3, 6, 6, 8,
+    15, 3,15,15,15,15,15,15, 15,15,15,15, 3,15,15, 8,
+];
+
+const BC7_WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const BC7_WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+// Reads a block's fields from its least significant bit up
+struct BitReader {
+    bits: u128,
+    position: u32,
+}
+
+impl BitReader {
+    fn read(&mut self, num_bits: u32) -> u32 {
+        let value = (self.bits >> self.position) as u32 & ((1_u64 << num_bits) - 1) as u32;
+        self.position += num_bits;
+        value
+    }
+}
+
+fn bc7_weights(num_bits: u32) -> &'static [u32] {
+    match num_bits {
+        2 => &BC7_WEIGHTS_2,
+        3 => &BC7_WEIGHTS_3,
+        _ => &BC7_WEIGHTS_4,
+    }
+}
+
+fn decode_bc7(block: &[u8]) -> [[u8; 4]; 16] {
+    let mut bytes = [0; 16];
+    bytes.copy_from_slice(block);
+    let mut reader = BitReader {
+        bits: u128::from_le_bytes(bytes),
+        position: 0,
+    };
+    // The mode is the number of zeros before the first set bit
+    let mode_idx = match (0..8).find(|mode_idx| bytes[0] & (1 << mode_idx) != 0) {
+        Some(mode_idx) => mode_idx,
+        None => return [[0; 4]; 16], // Reserved, decoded as transparent black
+    };
+    reader.read(mode_idx as u32 + 1);
+    let mode = &BC7_MODES[mode_idx];
+
+    let partition = reader.read(mode.partition_bits) as usize;
+    let rotation = reader.read(mode.rotation_bits);
+    let index_selection = reader.read(mode.index_selection_bits);
+
+    // Endpoints, by subset and then by end, read channel by channel
+    let num_endpoints = mode.num_subsets * 2;
+    let mut endpoints = [[0_u32; 4]; 6];
+    for channel in 0..3 {
+        for endpoint in endpoints.iter_mut().take(num_endpoints) {
+            endpoint[channel] = reader.read(mode.color_bits);
+        }
+    }
+    for endpoint in endpoints.iter_mut().take(num_endpoints) {
+        endpoint[3] = if mode.alpha_bits > 0 {
+            reader.read(mode.alpha_bits)
+        } else {
+            255
+        };
+    }
+
+    // P-bits add a shared least significant bit to every channel of an endpoint
+    let (mut color_bits, mut alpha_bits) = (mode.color_bits, mode.alpha_bits);
+    if mode.endpoint_p_bits || mode.shared_p_bits {
+        let mut p_bits = [0; 6];
+        if mode.endpoint_p_bits {
+            for p_bit in p_bits.iter_mut().take(num_endpoints) {
+                *p_bit = reader.read(1);
+            }
+        } else {
+            for subset in 0..mode.num_subsets {
+                let p_bit = reader.read(1);
+                p_bits[2 * subset] = p_bit;
+                p_bits[2 * subset + 1] = p_bit;
+            }
+        }
+        for (endpoint, p_bit) in endpoints.iter_mut().zip(p_bits.iter()).take(num_endpoints) {
+            for (channel, value) in endpoint.iter_mut().enumerate() {
+                if channel < 3 || mode.alpha_bits > 0 {
+                    *value = (*value << 1) | p_bit;
+                }
+            }
+        }
+        color_bits += 1;
+        if alpha_bits > 0 {
+            alpha_bits += 1;
+        }
+    }
+    // Expand to 8 bits by repeating the high bits
+    for endpoint in endpoints.iter_mut().take(num_endpoints) {
+        for (channel, value) in endpoint.iter_mut().enumerate() {
+            let num_bits = if channel < 3 { color_bits } else { alpha_bits };
+            if num_bits > 0 {
+                *value <<= 8 - num_bits;
+                *value |= *value >> num_bits;
+            }
+        }
+    }
+
+    let subset_of = |texel_idx: usize| match mode.num_subsets {
+        1 => 0,
+        2 => BC7_PARTITIONS_2[partition][texel_idx] as usize,
+        _ => BC7_PARTITIONS_3[partition][texel_idx] as usize,
+    };
+    let is_anchor = |texel_idx: usize| {
+        texel_idx == 0
+            || match mode.num_subsets {
+                1 => false,
+                2 => texel_idx == BC7_ANCHORS_2[partition] as usize,
+                _ => {
+                    texel_idx == BC7_ANCHORS_3_SECOND[partition] as usize
+                        || texel_idx == BC7_ANCHORS_3_THIRD[partition] as usize
+                }
+            }
+    };
+    let mut primary_indices = [0; 16];
+    for (texel_idx, index) in primary_indices.iter_mut().enumerate() {
+        let num_bits = mode.index_bits - is_anchor(texel_idx) as u32;
+        *index = reader.read(num_bits) as usize;
+    }
+    // Only modes 4 and 5 have these, with one subset, so only texel 0 is an anchor
+    let mut secondary_indices = [0; 16];
+    if mode.secondary_index_bits > 0 {
+        for (texel_idx, index) in secondary_indices.iter_mut().enumerate() {
+            let num_bits = mode.secondary_index_bits - (texel_idx == 0) as u32;
+            *index = reader.read(num_bits) as usize;
+        }
+    }
+
+    let interpolate =
+        |e0: u32, e1: u32, weight: u32| (((64 - weight) * e0 + weight * e1 + 32) >> 6) as u8;
+    let mut texels = [[0; 4]; 16];
+    for (texel_idx, texel) in texels.iter_mut().enumerate() {
+        let subset = subset_of(texel_idx);
+        let (e0, e1) = (endpoints[2 * subset], endpoints[2 * subset + 1]);
+        let (color_weight, alpha_weight) = if mode.secondary_index_bits == 0 {
+            let weight = bc7_weights(mode.index_bits)[primary_indices[texel_idx]];
+            (weight, weight)
+        } else {
+            let primary_weight = bc7_weights(mode.index_bits)[primary_indices[texel_idx]];
+            let secondary_weight =
+                bc7_weights(mode.secondary_index_bits)[secondary_indices[texel_idx]];
+            // Mode 4's index selection bit swaps which indices are for color
+            if index_selection == 0 {
+                (primary_weight, secondary_weight)
+            } else {
+                (secondary_weight, primary_weight)
+            }
+        };
+        for channel in 0..3 {
+            texel[channel] = interpolate(e0[channel], e1[channel], color_weight);
+        }
+        texel[3] = interpolate(e0[3], e1[3], alpha_weight);
+        // The rotation swaps alpha with one of the color channels
+        match rotation {
+            1 => texel.swap(0, 3),
+            2 => texel.swap(1, 3),
+            3 => texel.swap(2, 3),
+            _ => (),
+        }
+    }
+    texels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Packs (value, num_bits) fields from the least significant bit up, like BC7 blocks
+    fn bc7_block(fields: &[(u32, u32)]) -> Vec<u8> {
+        let mut bits = 0_u128;
+        let mut position = 0;
+        for &(value, num_bits) in fields {
+            assert!(value < (1 << num_bits));
+            bits |= (value as u128) << position;
+            position += num_bits;
+        }
+        assert!(position <= 128);
+        bits.to_le_bytes().to_vec()
+    }
+
+    // R, G and B of every endpoint in turn, as BC7 stores them
+    fn bc7_colors(endpoints: &[[u32; 3]], num_bits: u32) -> Vec<(u32, u32)> {
+        (0..3)
+            .flat_map(|channel| endpoints.iter().map(move |e| (e[channel], num_bits)))
+            .collect()
+    }
+
+    fn decode_4x4(format: vk::Format, block: &[u8]) -> Vec<[u8; 4]> {
+        decode_bc_to_rgba8(format, 4, 4, block)
+            .unwrap()
+            .chunks_exact(4)
+            .map(|texel| [texel[0], texel[1], texel[2], texel[3]])
+            .collect()
+    }
+
+    // The texels of a 4x4 block that are in each subset of partition 0
+    const PARTITION_0_OF_2: [usize; 16] = [0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1, 0, 0, 1, 1];
+    const PARTITION_0_OF_3: [usize; 16] = [0, 0, 1, 1, 0, 0, 1, 1, 0, 2, 2, 1, 2, 2, 2, 2];
+
+    fn assert_subset_colors(texels: &[[u8; 4]], partition: &[usize; 16], colors: &[[u8; 4]]) {
+        for (texel_idx, texel) in texels.iter().enumerate() {
+            assert_eq!(*texel, colors[partition[texel_idx]], "texel {}", texel_idx);
+        }
+    }
+
+    #[test]
+    fn mip_sizes_round_partial_blocks_up() {
+        let sizes = |format| {
+            [(1, 1), (3, 5), (5, 3)].map(|(w, h)| mip_size_in_bytes(format, w, h, 1).unwrap())
+        };
+        assert_eq!(sizes(vk::Format::BC1_RGB_UNORM_BLOCK), [8, 16, 16]);
+        assert_eq!(sizes(vk::Format::BC4_UNORM_BLOCK), [8, 16, 16]);
+        assert_eq!(sizes(vk::Format::BC7_SRGB_BLOCK), [16, 32, 32]);
+        assert_eq!(sizes(vk::Format::R8G8B8A8_UNORM), [4, 60, 60]);
+        assert_eq!(
+            mip_size_in_bytes(vk::Format::BC3_UNORM_BLOCK, 5, 3, 2),
+            Some(64)
+        );
+        assert_eq!(mip_size_in_bytes(vk::Format::D32_SFLOAT, 1, 1, 1), None);
+    }
+
+    #[test]
+    fn bc1_four_color_block() {
+        // Red, then blue, which is smaller, so there are two interpolated colors
+        let block = [0x00, 0xf8, 0x1f, 0x00, 0xe4, 0xe4, 0xe4, 0xe4];
+        let palette = [
+            [255, 0, 0, 255],
+            [0, 0, 255, 255],
+            [170, 0, 85, 255],
+            [85, 0, 170, 255],
+        ];
+        let texels = decode_4x4(vk::Format::BC1_RGBA_UNORM_BLOCK, &block);
+        for (texel_idx, texel) in texels.iter().enumerate() {
+            assert_eq!(*texel, palette[texel_idx % 4]);
+        }
+    }
+
+    #[test]
+    fn bc1_three_color_block() {
+        // Blue, then red, which is larger, so there's one midpoint and transparent black
+        let block = [0x1f, 0x00, 0x00, 0xf8, 0xe4, 0xe4, 0xe4, 0xe4];
+        let palette = [
+            [0, 0, 255, 255],
+            [255, 0, 0, 255],
+            [127, 0, 127, 255],
+            [0, 0, 0, 0],
+        ];
+        let texels = decode_4x4(vk::Format::BC1_RGBA_SRGB_BLOCK, &block);
+        for (texel_idx, texel) in texels.iter().enumerate() {
+            assert_eq!(*texel, palette[texel_idx % 4]);
+        }
+        // Without alpha, the transparent texels are opaque black
+        let texels = decode_4x4(vk::Format::BC1_RGB_UNORM_BLOCK, &block);
+        assert_eq!(texels[3], [0, 0, 0, 255]);
+        assert_eq!(texels[2], [127, 0, 127, 255]);
+    }
+
+    #[test]
+    fn bc7_mode_0() {
+        let mut fields = vec![(1, 1), (0, 4)];
+        let endpoints = [
+            [1, 7, 13],
+            [2, 8, 14],
+            [3, 9, 15],
+            [4, 10, 0],
+            [5, 11, 1],
+            [6, 12, 2],
+        ];
+        fields.extend(bc7_colors(&endpoints, 4));
+        fields.extend([1, 0, 1, 0, 0, 1].map(|p_bit| (p_bit, 1)));
+        let texels = decode_4x4(vk::Format::BC7_UNORM_BLOCK, &bc7_block(&fields));
+        // 4 bits and a p-bit, expanded from 5 bits to 8
+        let colors = [[24, 123, 222, 255], [57, 156, 255, 255], [82, 181, 16, 255]];
+        assert_subset_colors(&texels, &PARTITION_0_OF_3, &colors);
+    }
+
+    #[test]
+    fn bc7_mode_1() {
+        let mut fields = vec![(2, 2), (0, 6)];
+        let endpoints = [[10, 63, 5], [20, 0, 6], [30, 1, 7], [40, 2, 8]];
+        fields.extend(bc7_colors(&endpoints, 6));
+        fields.extend([(1, 1), (0, 1)]); // Shared by the ends of each subset
+        let texels = decode_4x4(vk::Format::BC7_UNORM_BLOCK, &bc7_block(&fields));
+        let colors = [[42, 255, 22, 255], [120, 4, 28, 255]];
+        assert_subset_colors(&texels, &PARTITION_0_OF_2, &colors);
+    }
+
+    #[test]
+    fn bc7_mode_2() {
+        let mut fields = vec![(4, 3), (0, 6)];
+        let endpoints = [
+            [31, 0, 2],
+            [0, 0, 0],
+            [16, 8, 4],
+            [0, 0, 0],
+            [1, 30, 6],
+            [0, 0, 0],
+        ];
+        fields.extend(bc7_colors(&endpoints, 5));
+        let texels = decode_4x4(vk::Format::BC7_UNORM_BLOCK, &bc7_block(&fields));
+        let colors = [[255, 0, 16, 255], [132, 66, 33, 255], [8, 247, 49, 255]];
+        assert_subset_colors(&texels, &PARTITION_0_OF_3, &colors);
+    }
+
+    #[test]
+    fn bc7_mode_3() {
+        let mut fields = vec![(8, 4), (0, 6)];
+        let endpoints = [[100, 1, 64], [0, 0, 0], [50, 127, 3], [0, 0, 0]];
+        fields.extend(bc7_colors(&endpoints, 7));
+        fields.extend([1, 0, 0, 1].map(|p_bit| (p_bit, 1)));
+        let texels = decode_4x4(vk::Format::BC7_UNORM_BLOCK, &bc7_block(&fields));
+        let colors = [[201, 3, 129, 255], [100, 254, 6, 255]];
+        assert_subset_colors(&texels, &PARTITION_0_OF_2, &colors);
+    }
+
+    #[test]
+    fn bc7_mode_4_with_rotation() {
+        // Rotation 1 swaps red and alpha
+        let mut fields = vec![(16, 5), (1, 2), (0, 1)];
+        fields.extend(bc7_colors(&[[20, 10, 31], [0, 0, 0]], 5));
+        fields.extend([(33, 6), (0, 6)]);
+        let texels = decode_4x4(vk::Format::BC7_UNORM_BLOCK, &bc7_block(&fields));
+        assert!(texels.iter().all(|texel| *texel == [134, 82, 255, 165]));
+    }
+
+    #[test]
+    fn bc7_mode_5() {
+        let mut fields = vec![(32, 6), (0, 2)];
+        fields.extend(bc7_colors(&[[127, 64, 1], [0, 0, 0]], 7));
+        fields.extend([(200, 8), (0, 8)]);
+        let texels = decode_4x4(vk::Format::BC7_UNORM_BLOCK, &bc7_block(&fields));
+        assert!(texels.iter().all(|texel| *texel == [255, 129, 2, 200]));
+    }
+
+    #[test]
+    fn bc7_mode_6_interpolates_between_endpoints() {
+        let mut fields = vec![(64, 7)];
+        fields.extend(bc7_colors(&[[0x40, 0x20, 0x10], [0, 0, 0]], 7));
+        fields.extend([(0x7f, 7), (0, 7), (1, 1), (0, 1)]);
+        // The anchor's index has one bit less. Texel 1 is at weight 34, and texel 15 at e1.
+        fields.push((0, 3));
+        fields.push((8, 4));
+        fields.extend((2..15).map(|_| (0, 4)));
+        fields.push((15, 4));
+        let texels = decode_4x4(vk::Format::BC7_UNORM_BLOCK, &bc7_block(&fields));
+        assert_eq!(texels[0], [0x81, 0x41, 0x21, 0xff]);
+        assert_eq!(texels[1], [60, 30, 15, 120]);
+        assert_eq!(texels[14], [0x81, 0x41, 0x21, 0xff]);
+        assert_eq!(texels[15], [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn bc7_mode_7() {
+        let mut fields = vec![(128, 8), (0, 6)];
+        let endpoints = [[10, 31, 3], [0, 0, 0], [20, 0, 17], [0, 0, 0]];
+        fields.extend(bc7_colors(&endpoints, 5));
+        fields.extend([31, 0, 15, 0].map(|alpha| (alpha, 5)));
+        fields.extend([1, 0, 0, 0].map(|p_bit| (p_bit, 1)));
+        let texels = decode_4x4(vk::Format::BC7_UNORM_BLOCK, &bc7_block(&fields));
+        let colors = [[85, 255, 28, 255], [162, 0, 138, 121]];
+        assert_subset_colors(&texels, &PARTITION_0_OF_2, &colors);
+    }
+
+    #[test]
+    fn bc7_reserved_mode_is_transparent_black() {
+        let texels = decode_4x4(vk::Format::BC7_UNORM_BLOCK, &[0; 16]);
+        assert!(texels.iter().all(|texel| *texel == [0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn edge_blocks_are_cropped() {
+        // A 5x3 mip has two blocks. The first is all red, and the second all blue.
+        let mut data = vec![0x00, 0xf8, 0x1f, 0x00, 0x00, 0x00, 0x00, 0x00];
+        data.extend([0x00, 0xf8, 0x1f, 0x00, 0x55, 0x55, 0x55, 0x55]);
+        let rgba = decode_bc_to_rgba8(vk::Format::BC1_RGB_UNORM_BLOCK, 5, 3, &data).unwrap();
+        assert_eq!(rgba.len(), 5 * 3 * 4);
+        for y in 0..3 {
+            for x in 0..5 {
+                let offset = (y * 5 + x) * 4;
+                let expected = if x < 4 {
+                    [255, 0, 0, 255]
+                } else {
+                    [0, 0, 255, 255]
+                };
+                assert_eq!(rgba[offset..offset + 4], expected, "texel ({}, {})", x, y);
+            }
+        }
+        assert!(decode_bc_to_rgba8(vk::Format::BC1_RGB_UNORM_BLOCK, 5, 3, &data[..15]).is_err());
+        assert!(decode_bc_to_rgba8(vk::Format::BC6H_UFLOAT_BLOCK, 4, 4, &[0; 16]).is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use crate::*;
+
+/// How shaders read a buffer view. Uniform texel buffers are read-only, and
+/// storage texel buffers can also be written with `imageStore()`.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum TexelBufferKind {
+    Uniform, // `uniform samplerBuffer` in GLSL
+    Storage, // `uniform imageBuffer` in GLSL
+}
+
+impl TexelBufferKind {
+    pub fn descriptor_type(self) -> vk::DescriptorType {
+        match self {
+            TexelBufferKind::Uniform => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+            TexelBufferKind::Storage => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+        }
+    }
+
+    fn format_feature(self) -> vk::FormatFeatureFlags {
+        match self {
+            TexelBufferKind::Uniform => vk::FormatFeatureFlags::UNIFORM_TEXEL_BUFFER,
+            TexelBufferKind::Storage => vk::FormatFeatureFlags::STORAGE_TEXEL_BUFFER,
+        }
+    }
+}
+
+/* A range of a buffer, read by shaders as an array of formatted texels, e.g.
+a large table of floats that wouldn't fit in a uniform buffer. The buffer
+needs `UNIFORM_TEXEL` or `STORAGE_TEXEL` usage and must outlive the view. */
+pub struct BufferView {
+    pub name: String,
+    pub vk_buffer_view: vk::BufferView,
+    pub format: vk::Format,
+    pub offset: usize,
+    pub range: usize,
+    pub kind: TexelBufferKind,
+    device: ash::Device,
+}
+
+impl Drop for BufferView {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_buffer_view(self.vk_buffer_view, None);
+        }
+        audit_destroyed(VkObjectKind::BufferView, self.vk_buffer_view);
+    }
+}
+
+impl BufferView {
+    /// `buffer_size` is checked against, so that a view can't go past the end.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        name: &str,
+        buffer_name: &str,
+        vk_buffer: vk::Buffer,
+        buffer_size: usize,
+        format: vk::Format,
+        offset: usize,
+        range: usize,
+        kind: TexelBufferKind,
+        basis: &Basis,
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> Result<BufferView, BufferError> {
+        if offset
+            .checked_add(range)
+            .is_none_or(|end| end > buffer_size)
+        {
+            return Err(BufferError::OutOfBounds {
+                buffer_name: String::from(buffer_name),
+                offset,
+                data_size: range,
+                buffer_size,
+            });
+        }
+        let format_properties = unsafe {
+            basis
+                .instance
+                .get_physical_device_format_properties(gpu.physical_device, format)
+        };
+        let opt_texel_size = format_texel_size(format);
+        if !format_properties
+            .buffer_features
+            .contains(kind.format_feature())
+            || opt_texel_size.is_none()
+        {
+            return Err(BufferError::UnsupportedBufferViewFormat {
+                buffer_name: String::from(buffer_name),
+                format,
+                kind,
+            });
+        }
+        let texel_size = opt_texel_size.unwrap();
+        let limits = &gpu.properties.limits;
+        let offset_alignment = limits.min_texel_buffer_offset_alignment as usize;
+        let max_texels = limits.max_texel_buffer_elements as usize;
+        if !offset.is_multiple_of(offset_alignment.max(1))
+            || range == 0
+            || !range.is_multiple_of(texel_size)
+            || range / texel_size > max_texels
+        {
+            return Err(BufferError::InvalidBufferView {
+                buffer_name: String::from(buffer_name),
+                offset,
+                range,
+                offset_alignment,
+                texel_size,
+                max_texels,
+            });
+        }
+
+        let create_info = vk::BufferViewCreateInfo::builder()
+            .buffer(vk_buffer)
+            .format(format)
+            .offset(offset as vk::DeviceSize)
+            .range(range as vk::DeviceSize);
+        let vk_buffer_view = unsafe {
+            gpu.device
+                .create_buffer_view(&create_info, None)
+                .expect("Failed to create buffer view.")
+        };
+        audit_created(
+            VkObjectKind::BufferView,
+            vk_buffer_view,
+            &[
+                device_id(&gpu.device),
+                vk_object_id(VkObjectKind::Buffer, vk_buffer),
+            ],
+            name,
+        );
+        debug_utils.set_buffer_view_name(vk_buffer_view, name);
+
+        Ok(BufferView {
+            name: String::from(name),
+            vk_buffer_view,
+            format,
+            offset,
+            range,
+            kind,
+            device: gpu.device.clone(),
+        })
+    }
+}
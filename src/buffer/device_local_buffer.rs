@@ -1,22 +1,59 @@
 use crate::*;
+use std::rc::Rc;
 
 pub struct DeviceLocalBuffer {
+    pub name: String,
     pub vk_buffer: vk::Buffer,
-    pub memory: vk::DeviceMemory,
+    pub allocation: Allocation,
     pub num_elements: usize,
+    pub element_size: usize, // In bytes. Selects the index type of index buffers.
+    pub usage: vk::BufferUsageFlags,
+    pub opt_upload_ticket: Option<UploadTicket>, // Set if the data is uploaded asynchronously
+    pub is_written_directly: bool,               // In host-visible memory, without a staging copy
     device: ash::Device,
+    allocator: Rc<Allocator>,
+    debug_fill: Rc<DebugFill>,
 }
 
 impl Drop for DeviceLocalBuffer {
     fn drop(&mut self) {
+        track_destroyed(&LIVE_BUFFERS);
+        self.debug_fill.cancel(self.vk_buffer);
         unsafe {
             self.device.destroy_buffer(self.vk_buffer, None);
-            self.device.free_memory(self.memory, None);
         }
+        audit_destroyed(VkObjectKind::Buffer, self.vk_buffer);
+        self.allocator.free(&self.allocation);
     }
 }
 
 impl DeviceLocalBuffer {
+    /// Like `Image::set_debug_name()`.
+    pub fn set_debug_name(&mut self, name: &str, debug_utils: &DebugUtils) {
+        debug_utils.set_buffer_name(self.vk_buffer, name);
+        audit_renamed(VkObjectKind::Buffer, self.vk_buffer, name);
+        self.allocator.rename(&self.allocation, name, debug_utils);
+        self.name = String::from(name);
+    }
+
+    /// Only works for buffers that were created with `TRANSFER_SRC` usage.
+    pub fn read_back(
+        &self,
+        name: &str,
+        gpu: &Gpu,
+        command_pool: vk::CommandPool,
+        debug_utils: &DebugUtils,
+    ) -> Result<Vec<u8>, BufferError> {
+        super::read_back_buffer(
+            name,
+            self.vk_buffer,
+            self.num_elements * self.element_size,
+            gpu,
+            command_pool,
+            debug_utils,
+        )
+    }
+
     pub fn new<T>(
         name: &str,
         data: &[T],
@@ -26,56 +63,196 @@ impl DeviceLocalBuffer {
         debug_utils: &DebugUtils,
     ) -> DeviceLocalBuffer {
         let size = std::mem::size_of_val(data);
+        if gpu.writes_device_local_directly() {
+            return DeviceLocalBuffer::new_written_directly(name, data, usage, gpu, debug_utils);
+        }
 
-        // ## Create staging buffer in host-visible memory
-        let staging_buffer = HostVisibleBuffer::new(
-            "device_local_staging_buffer",
-            size,
-            vk::BufferUsageFlags::TRANSFER_SRC,
-            &gpu,
-            debug_utils,
-        );
-
-        // ## Copy data to staging buffer
-        staging_buffer.upload_data(data, 0);
+        // ## Copy data to a staging chunk in host-visible memory
+        let staging_chunk = gpu.staging_pool.allocate(data, gpu, debug_utils);
 
         // ## Create buffer in device-local memory
-        let (vk_buffer, memory) = super::new_raw_buffer(
-            size,
-            vk::BufferUsageFlags::TRANSFER_DST | usage,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            gpu,
-        );
+        let buffer = DeviceLocalBuffer::new_uninitialized(name, data, usage, gpu, debug_utils);
+        let vk_buffer = buffer.vk_buffer;
+        // Every byte is copied in below, before the buffer is returned
+        gpu.debug_fill.cancel(vk_buffer);
 
         // ## Copy staging buffer -> vertex buffer
-        {
-            let command_buffer = begin_single_use_command_buffer(&gpu.device, command_pool);
+        let copy_regions = [vk::BufferCopy {
+            src_offset: staging_chunk.offset as u64,
+            dst_offset: 0,
+            size: size as u64,
+        }];
+        if gpu.has_dedicated_transfer_queue() {
+            /* Copy on the transfer queue, and then move the buffer to the
+            graphics family. The release on the transfer queue and the acquire
+            on the graphics queue need matching barriers. */
+            let ownership_barrier = |src_access_mask, dst_access_mask| vk::BufferMemoryBarrier {
+                src_access_mask,
+                dst_access_mask,
+                src_queue_family_index: gpu.transfer_queue_idx,
+                dst_queue_family_index: gpu.graphics_queue_idx,
+                buffer: vk_buffer,
+                offset: 0,
+                size: vk::WHOLE_SIZE,
+                ..Default::default()
+            };
+            let transfer = gpu.submit_transfer(|command_buffer| unsafe {
+                gpu.device.cmd_copy_buffer(
+                    command_buffer,
+                    staging_chunk.vk_buffer,
+                    vk_buffer,
+                    &copy_regions,
+                );
+                gpu.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[ownership_barrier(
+                        vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::empty(),
+                    )],
+                    &[],
+                );
+            });
+            gpu.finish_transfer(transfer);
 
+            let command_buffer = begin_single_use_command_buffer(&gpu.device, command_pool);
+            debug_utils.begin_label(
+                command_buffer,
+                &format!("acquire {}", name),
+                LABEL_COLOR_UPLOAD,
+            );
             unsafe {
-                let copy_regions = [vk::BufferCopy {
-                    src_offset: 0,
-                    dst_offset: 0,
-                    size: size as u64,
-                }];
+                gpu.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    vk::PipelineStageFlags::ALL_COMMANDS,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[ownership_barrier(
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::MEMORY_READ,
+                    )],
+                    &[],
+                );
+            }
+            debug_utils.end_label(command_buffer);
+            end_single_use_command_buffer(command_buffer, command_pool, gpu);
+        } else {
+            let command_buffer = begin_single_use_command_buffer(&gpu.device, command_pool);
+            debug_utils.begin_label(
+                command_buffer,
+                &format!("upload {}", name),
+                LABEL_COLOR_UPLOAD,
+            );
 
+            unsafe {
                 gpu.device.cmd_copy_buffer(
                     command_buffer,
-                    staging_buffer.vk_buffer,
+                    staging_chunk.vk_buffer,
                     vk_buffer,
                     &copy_regions,
                 );
             }
 
-            end_single_use_command_buffer(command_buffer, command_pool, &gpu);
+            debug_utils.end_label(command_buffer);
+            end_single_use_command_buffer(command_buffer, command_pool, gpu);
         }
+        gpu.staging_pool.free(staging_chunk);
 
+        buffer
+    }
+
+    /// Sized for `data`, which is left to the caller to copy in.
+    pub(crate) fn new_uninitialized<T>(
+        name: &str,
+        data: &[T],
+        usage: vk::BufferUsageFlags,
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> DeviceLocalBuffer {
+        let (vk_buffer, allocation) = super::new_raw_buffer(
+            name,
+            std::mem::size_of_val(data),
+            vk::BufferUsageFlags::TRANSFER_DST | usage,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            vk::MemoryPropertyFlags::empty(),
+            gpu,
+        );
+        debug_utils.set_buffer_name(vk_buffer, name);
+
+        track_created(&LIVE_BUFFERS);
+        DeviceLocalBuffer {
+            name: String::from(name),
+            vk_buffer,
+            allocation,
+            num_elements: data.len(),
+            element_size: std::mem::size_of::<T>(),
+            usage: vk::BufferUsageFlags::TRANSFER_DST | usage,
+            opt_upload_ticket: None,
+            is_written_directly: false,
+            device: gpu.device.clone(),
+            allocator: gpu.allocator.clone(),
+            debug_fill: gpu.debug_fill.clone(),
+        }
+    }
+
+    /* With unified memory, the buffer goes in memory that is device-local and
+    host-visible, and `data` is copied in through the mapping. Nothing is
+    submitted, so the buffer can be used right away. It can still be written
+    with `cmd_update_buffer()`, like staged buffers. */
+    pub(crate) fn new_written_directly<T>(
+        name: &str,
+        data: &[T],
+        usage: vk::BufferUsageFlags,
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> DeviceLocalBuffer {
+        let size = std::mem::size_of_val(data);
+        let (vk_buffer, allocation) = super::new_raw_buffer(
+            name,
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST | usage,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL | vk::MemoryPropertyFlags::HOST_VISIBLE,
+            vk::MemoryPropertyFlags::HOST_COHERENT,
+            gpu,
+        );
+        let mapped_ptr = allocation
+            .opt_mapped_ptr
+            .expect("Host-visible memory isn't mapped.");
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr() as *const u8, mapped_ptr, size);
+        }
+        if !allocation
+            .property_flags
+            .contains(vk::MemoryPropertyFlags::HOST_COHERENT)
+        {
+            // Allocations in host-visible memory are padded to whole atoms
+            let ranges = [vk::MappedMemoryRange::builder()
+                .memory(allocation.memory)
+                .offset(allocation.offset)
+                .size(allocation.size)
+                .build()];
+            unsafe { gpu.device.flush_mapped_memory_ranges(&ranges) }
+                .expect("Failed to flush mapped memory.");
+        }
         debug_utils.set_buffer_name(vk_buffer, name);
 
+        track_created(&LIVE_BUFFERS);
         DeviceLocalBuffer {
+            name: String::from(name),
             vk_buffer,
-            memory,
+            allocation,
             num_elements: data.len(),
+            element_size: std::mem::size_of::<T>(),
+            usage: vk::BufferUsageFlags::TRANSFER_DST | usage,
+            opt_upload_ticket: None,
+            is_written_directly: true,
             device: gpu.device.clone(),
+            allocator: gpu.allocator.clone(),
+            debug_fill: gpu.debug_fill.clone(),
         }
     }
 }
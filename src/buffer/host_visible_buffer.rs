@@ -1,23 +1,97 @@
 use crate::*;
+use std::rc::Rc;
 
+/// Types that every bit pattern of their size is a valid value of, like
+/// integers and floats, and arrays of them. Not `bool`, enums, references or
+/// anything with padding, which mapped memory could hold invalid values of.
+///
+/// # Safety
+/// Implement it only for `Copy` types without padding, invalid bit patterns
+/// or pointers.
+pub unsafe trait AnyBitPattern: Copy {}
+
+macro_rules! impl_any_bit_pattern {
+    ($($t:ty),*) => {
+        $(unsafe impl AnyBitPattern for $t {})*
+    };
+}
+impl_any_bit_pattern!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+unsafe impl<T: AnyBitPattern, const N: usize> AnyBitPattern for [T; N] {}
+
+// Reallocated buffers are at least this many times their old size, so that a
+// buffer that grows a little every frame isn't reallocated every frame
+const CAPACITY_GROWTH_FACTOR: usize = 2;
+
+/// The size to reallocate a buffer of `size` bytes to, so that it holds at
+/// least `new_size`. None if it already does.
+fn grown_capacity(size: usize, new_size: usize) -> Option<usize> {
+    if new_size <= size {
+        return None;
+    }
+    Some(new_size.max(size.saturating_mul(CAPACITY_GROWTH_FACTOR)))
+}
+
+/* A buffer in memory that the host can access. The allocator keeps
+host-visible memory mapped, so uploads and downloads are plain copies, and
+data can also be written in place through `mapped_slice_mut()`. */
 pub struct HostVisibleBuffer {
     pub name: String,
     pub vk_buffer: vk::Buffer,
-    pub memory: vk::DeviceMemory,
+    pub allocation: Allocation,
     pub size: usize,
+    // Kept for reallocating
+    usage: vk::BufferUsageFlags,
+    preferred_memory_properties: vk::MemoryPropertyFlags,
+    /* Without `HOST_COHERENT`, writes must be flushed before the GPU reads
+    them, and ranges must be invalidated before reading what the GPU wrote,
+    in units of `non_coherent_atom_size`. */
+    is_coherent: bool,
+    non_coherent_atom_size: usize,
+    mapped_ptr: *mut u8,
     device: ash::Device,
+    allocator: Rc<Allocator>,
+}
+
+/* `size` bytes at `offset` into an allocation, widened to whole atoms and
+made relative to the start of the memory, as flushing and invalidating need.
+The allocator aligns host-visible allocations to atoms and pads them to whole
+atoms, so the range stays inside the allocation. It's clamped to the
+allocation's end anyway, since a range may also end at the end of its memory,
+which allocations with memory of their own do. */
+fn atom_range(
+    allocation_offset: u64,
+    allocation_size: u64,
+    atom_size: usize,
+    offset: usize,
+    size: usize,
+) -> (u64, u64) {
+    let start = (offset / atom_size * atom_size) as u64;
+    let end = ((offset + size).div_ceil(atom_size) * atom_size) as u64;
+    let end = end.min(allocation_size);
+    (allocation_offset + start, end - start)
 }
 
 impl Drop for HostVisibleBuffer {
     fn drop(&mut self) {
+        track_destroyed(&LIVE_BUFFERS);
         unsafe {
             self.device.destroy_buffer(self.vk_buffer, None);
-            self.device.free_memory(self.memory, None);
         }
+        audit_destroyed(VkObjectKind::Buffer, self.vk_buffer);
+        self.allocator.free(&self.allocation);
     }
 }
 
 impl HostVisibleBuffer {
+    /// Like `Image::set_debug_name()`.
+    pub fn set_debug_name(&mut self, name: &str, debug_utils: &DebugUtils) {
+        debug_utils.set_buffer_name(self.vk_buffer, name);
+        audit_renamed(VkObjectKind::Buffer, self.vk_buffer, name);
+        self.allocator.rename(&self.allocation, name, debug_utils);
+        self.name = String::from(name);
+    }
+
+    /// Prefers host-coherent memory, for buffers that the host writes.
     pub fn new(
         name: &str,
         size: usize,
@@ -25,41 +99,294 @@ impl HostVisibleBuffer {
         gpu: &Gpu,
         debug_utils: &DebugUtils,
     ) -> HostVisibleBuffer {
-        let (vk_buffer, memory) = super::new_raw_buffer(
+        HostVisibleBuffer::new_with_memory(
+            name,
             size,
             usage,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            vk::MemoryPropertyFlags::HOST_COHERENT,
+            gpu,
+            debug_utils,
+        )
+    }
+
+    /// Prefers host-cached memory, which is faster for the host to read but
+    /// is often not coherent. Meant for reading back what the GPU wrote.
+    pub fn new_host_cached(
+        name: &str,
+        size: usize,
+        usage: vk::BufferUsageFlags,
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> HostVisibleBuffer {
+        HostVisibleBuffer::new_with_memory(
+            name,
+            size,
+            usage,
+            vk::MemoryPropertyFlags::HOST_CACHED,
+            gpu,
+            debug_utils,
+        )
+    }
+
+    fn new_with_memory(
+        name: &str,
+        size: usize,
+        usage: vk::BufferUsageFlags,
+        preferred_memory_properties: vk::MemoryPropertyFlags,
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> HostVisibleBuffer {
+        let (vk_buffer, allocation) = super::new_raw_buffer(
+            name,
+            size,
+            usage,
+            vk::MemoryPropertyFlags::HOST_VISIBLE,
+            preferred_memory_properties,
             gpu,
         );
+        let mapped_ptr = allocation
+            .opt_mapped_ptr
+            .expect("Host-visible memory is always mapped.");
 
         debug_utils.set_buffer_name(vk_buffer, name);
 
+        track_created(&LIVE_BUFFERS);
         HostVisibleBuffer {
             name: String::from(name),
             vk_buffer,
-            memory,
+            is_coherent: allocation
+                .property_flags
+                .contains(vk::MemoryPropertyFlags::HOST_COHERENT),
+            allocation,
             size,
+            usage,
+            preferred_memory_properties,
+            non_coherent_atom_size: (gpu.properties.limits.non_coherent_atom_size as usize).max(1),
+            mapped_ptr,
             device: gpu.device.clone(),
+            allocator: gpu.allocator.clone(),
+        }
+    }
+
+    pub fn is_coherent(&self) -> bool {
+        self.is_coherent
+    }
+
+    /* The mapped memory as a slice of `T`, for writing structured data in
+    place. Trailing bytes that don't fit a whole `T` are left out. Writes need
+    a `flush_mapped_range()` if the memory isn't coherent. The memory holds
+    whatever the GPU or the debug fill wrote, so `T` must be valid for any
+    bits, which `AnyBitPattern` promises. */
+    pub fn mapped_slice_mut<T: AnyBitPattern>(&mut self) -> &mut [T] {
+        let data_ptr = self.mapped_ptr;
+        assert!(
+            (data_ptr as usize).is_multiple_of(std::mem::align_of::<T>()),
+            "The mapped memory of buffer `{}` isn't aligned for the requested type.",
+            self.name
+        );
+        let len = self.size / std::mem::size_of::<T>().max(1);
+        unsafe { std::slice::from_raw_parts_mut(data_ptr as *mut T, len) }
+    }
+
+    /* Reallocate the buffer if it's smaller than `new_size` bytes, to at
+    least twice its size, keeping its name, usage and memory type preference,
+    and optionally its contents.
+    Blocks until `opt_fence` signals, or until the device is idle if there is
+    no fence, and then destroys the old buffer before returning. Returns true
+    if the buffer was reallocated, in which case anything that points to the
+    old `vk_buffer`, like descriptor sets, must be updated. Never shrinks. */
+    pub fn ensure_capacity(
+        &mut self,
+        new_size: usize,
+        should_keep_contents: bool,
+        opt_fence: Option<vk::Fence>,
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> bool {
+        let new_size = match grown_capacity(self.size, new_size) {
+            Some(new_size) => new_size,
+            None => return false,
+        };
+        let new_buffer = HostVisibleBuffer::new_with_memory(
+            &self.name,
+            new_size,
+            self.usage,
+            self.preferred_memory_properties,
+            gpu,
+            debug_utils,
+        );
+
+        unsafe {
+            match opt_fence {
+                Some(fence) => gpu
+                    .device
+                    .wait_for_fences(&[fence], true, u64::MAX)
+                    .expect("Failed to wait for Fence."),
+                None => gpu
+                    .device
+                    .device_wait_idle()
+                    .expect("Failed to wait device idle!"),
+            }
+        }
+        if should_keep_contents {
+            let mut contents = vec![0u8; self.size];
+            self.download_data(&mut contents, 0)
+                .and_then(|()| new_buffer.upload_data(&contents, 0))
+                .unwrap_or_else(|e| panic!("{}", e));
+        }
+        // Dropping the old buffer destroys it
+        *self = new_buffer;
+        true
+    }
+
+    fn check_bounds(&self, offset: usize, data_size: usize) -> Result<(), BufferError> {
+        let is_in_bounds = offset
+            .checked_add(data_size)
+            .is_some_and(|end| end <= self.size);
+        if !is_in_bounds {
+            return Err(BufferError::OutOfBounds {
+                buffer_name: self.name.clone(),
+                offset,
+                data_size,
+                buffer_size: self.size,
+            });
         }
+        Ok(())
     }
 
-    pub fn upload_data<T>(&self, data: &[T], offset: usize) {
+    fn atom_range(&self, offset: usize, size: usize) -> (u64, u64) {
+        atom_range(
+            self.allocation.offset,
+            self.allocation.size,
+            self.non_coherent_atom_size,
+            offset,
+            size,
+        )
+    }
+
+    /// Make host writes to a range of the buffer visible to the GPU. Does
+    /// nothing if the memory is coherent.
+    pub fn flush_mapped_range(&self, offset: usize, size: usize) -> Result<(), BufferError> {
+        if self.is_coherent || size == 0 {
+            return Ok(());
+        }
+        let (offset, size) = self.atom_range(offset, size);
+        let ranges = [vk::MappedMemoryRange::builder()
+            .memory(self.allocation.memory)
+            .offset(offset)
+            .size(size)
+            .build()];
+        unsafe { self.device.flush_mapped_memory_ranges(&ranges) }.map_err(|result| {
+            BufferError::FlushFailed {
+                buffer_name: self.name.clone(),
+                result,
+            }
+        })
+    }
+
+    /// Make GPU writes to a range of the buffer visible to the host. Does
+    /// nothing if the memory is coherent.
+    pub fn invalidate_mapped_range(&self, offset: usize, size: usize) -> Result<(), BufferError> {
+        if self.is_coherent || size == 0 {
+            return Ok(());
+        }
+        let (offset, size) = self.atom_range(offset, size);
+        let ranges = [vk::MappedMemoryRange::builder()
+            .memory(self.allocation.memory)
+            .offset(offset)
+            .size(size)
+            .build()];
+        unsafe { self.device.invalidate_mapped_memory_ranges(&ranges) }.map_err(|result| {
+            BufferError::FlushFailed {
+                buffer_name: self.name.clone(),
+                result,
+            }
+        })
+    }
+
+    pub fn upload_data<T>(&self, data: &[T], offset: usize) -> Result<(), BufferError> {
         let data_size = std::mem::size_of_val(data);
-        debug_assert!(self.size >= offset + data_size);
+        self.check_bounds(offset, data_size)?;
 
+        // Byte-wise, since the offset doesn't have to be aligned for `T`
         unsafe {
-            let data_ptr = self
-                .device
-                .map_memory(
-                    self.memory,
-                    offset as u64,
-                    data_size as u64,
-                    vk::MemoryMapFlags::empty(),
-                )
-                .expect("Failed to map memory.") as *mut T;
-
-            data_ptr.copy_from_nonoverlapping(data.as_ptr(), data.len());
-            self.device.unmap_memory(self.memory);
+            self.mapped_ptr
+                .add(offset)
+                .copy_from_nonoverlapping(data.as_ptr() as *const u8, data_size);
         }
+        self.flush_mapped_range(offset, data_size)
+    }
+
+    /// Copy `out.len()` elements starting at `offset` bytes into `out`. The
+    /// GPU must be done writing to the buffer. `T` is `Copy`, since its bytes
+    /// are overwritten without dropping the old values.
+    pub fn download_data<T: Copy>(&self, out: &mut [T], offset: usize) -> Result<(), BufferError> {
+        let data_size = std::mem::size_of_val(out);
+        self.check_bounds(offset, data_size)?;
+
+        self.invalidate_mapped_range(offset, data_size)?;
+        unsafe {
+            (out.as_mut_ptr() as *mut u8)
+                .copy_from_nonoverlapping(self.mapped_ptr.add(offset), data_size);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligned_ranges_are_kept() {
+        assert_eq!(atom_range(256, 1024, 64, 0, 64), (256, 64));
+        assert_eq!(atom_range(256, 1024, 64, 128, 256), (384, 256));
+    }
+
+    #[test]
+    fn unaligned_ranges_are_widened_to_whole_atoms() {
+        assert_eq!(atom_range(256, 1024, 64, 10, 4), (256, 64));
+        assert_eq!(atom_range(256, 1024, 64, 60, 8), (256, 128));
+        assert_eq!(atom_range(256, 1024, 64, 65, 63), (320, 64));
+        assert_eq!(atom_range(0, 1024, 256, 300, 1), (256, 256));
+    }
+
+    #[test]
+    fn ranges_at_the_end_stay_inside_the_allocation() {
+        // A 100-byte buffer in an allocation padded to two atoms
+        assert_eq!(atom_range(256, 128, 64, 96, 4), (320, 64));
+        assert_eq!(atom_range(256, 128, 64, 0, 100), (256, 128));
+        // Memory of its own, which ends with the buffer rather than on an atom
+        assert_eq!(atom_range(0, 100, 64, 96, 4), (64, 36));
+        assert_eq!(atom_range(0, 100, 64, 0, 100), (0, 100));
+    }
+
+    #[test]
+    fn atoms_of_one_byte_keep_every_range() {
+        assert_eq!(atom_range(256, 100, 1, 3, 5), (259, 5));
+        assert_eq!(atom_range(256, 100, 1, 99, 1), (355, 1));
+        assert_eq!(atom_range(256, 100, 1, 0, 100), (256, 100));
+    }
+
+    #[test]
+    fn shrinking_is_a_no_op() {
+        assert_eq!(grown_capacity(1024, 512), None);
+        assert_eq!(grown_capacity(1024, 0), None);
+    }
+
+    #[test]
+    fn the_exact_size_is_not_grown() {
+        assert_eq!(grown_capacity(1024, 1024), None);
+        assert_eq!(grown_capacity(0, 0), None);
+    }
+
+    #[test]
+    fn growth_is_at_least_the_growth_factor() {
+        assert_eq!(grown_capacity(1024, 1025), Some(2048));
+        assert_eq!(grown_capacity(1024, 2048), Some(2048));
+        // Larger requests get exactly what they asked for
+        assert_eq!(grown_capacity(1024, 5000), Some(5000));
+        assert_eq!(grown_capacity(0, 16), Some(16));
+        assert_eq!(grown_capacity(usize::MAX - 1, usize::MAX), Some(usize::MAX));
     }
 }
@@ -1,17 +1,131 @@
 use crate::*;
+use std::cell::{Cell, RefCell};
 
+pub mod buffer_view;
+pub use buffer_view::*;
 pub mod device_local_buffer;
 pub use device_local_buffer::*;
 pub mod host_visible_buffer;
 pub use host_visible_buffer::*;
+pub mod ring_buffer;
+pub use ring_buffer::*;
+pub mod staging_pool;
+pub use staging_pool::*;
+pub mod typed_buffer;
+pub use typed_buffer::*;
+pub mod uniform_buffer;
+pub use uniform_buffer::*;
 
+// The limit of vkCmdUpdateBuffer
+pub const MAX_INLINE_UPDATE_SIZE: usize = 65536;
+pub const DEBUG_FILL_PATTERN: u32 = 0xDEAD_BEEF;
+
+#[derive(Debug)]
+pub enum BufferError {
+    // Accessing `data_size` bytes at `offset` would go past the end of the buffer
+    OutOfBounds {
+        buffer_name: String,
+        offset: usize,
+        data_size: usize,
+        buffer_size: usize,
+    },
+    // Inline updates are limited to 65536 bytes, at offsets and sizes that are multiples of 4
+    InvalidInlineUpdate {
+        buffer_name: String,
+        offset: usize,
+        data_size: usize,
+    },
+    // Flushing or invalidating non-coherent memory failed
+    FlushFailed {
+        buffer_name: String,
+        result: vk::Result,
+    },
+    /* A texel buffer view must start at a multiple of the device's
+    `minTexelBufferOffsetAlignment`, span a whole number of texels, and have
+    at most `maxTexelBufferElements` of them */
+    InvalidBufferView {
+        buffer_name: String,
+        offset: usize,
+        range: usize,
+        offset_alignment: usize,
+        texel_size: usize,
+        max_texels: usize,
+    },
+    // The format can't be read as a texel buffer of that kind on this device
+    UnsupportedBufferViewFormat {
+        buffer_name: String,
+        format: vk::Format,
+        kind: TexelBufferKind,
+    },
+}
+
+impl std::fmt::Display for BufferError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BufferError::OutOfBounds {
+                buffer_name,
+                offset,
+                data_size,
+                buffer_size,
+            } => write!(
+                f,
+                "Writing {} bytes at offset {} is out of bounds of buffer `{}`, which is {} bytes.",
+                data_size, offset, buffer_name, buffer_size
+            ),
+            BufferError::InvalidInlineUpdate {
+                buffer_name,
+                offset,
+                data_size,
+            } => write!(
+                f,
+                "Can't update {} bytes at offset {} of buffer `{}` inline. Inline updates must be at most {} bytes, with an offset and size that are multiples of 4.",
+                data_size, offset, buffer_name, MAX_INLINE_UPDATE_SIZE
+            ),
+            BufferError::FlushFailed {
+                buffer_name,
+                result,
+            } => write!(
+                f,
+                "Failed to flush or invalidate the memory of buffer `{}`: {}",
+                buffer_name, result
+            ),
+            BufferError::InvalidBufferView {
+                buffer_name,
+                offset,
+                range,
+                offset_alignment,
+                texel_size,
+                max_texels,
+            } => write!(
+                f,
+                "Can't view {} bytes at offset {} of buffer `{}` as texels. The offset must be a multiple of {}, and the range a multiple of {} bytes and at most {} texels.",
+                range, offset, buffer_name, offset_alignment, texel_size, max_texels
+            ),
+            BufferError::UnsupportedBufferViewFormat {
+                buffer_name,
+                format,
+                kind,
+            } => write!(
+                f,
+                "Can't view buffer `{}` as a {:?} texel buffer of format {:?}, since the device doesn't support it.",
+                buffer_name, kind, format
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BufferError {}
+
+/// Create a buffer and bind it to memory from the allocator. See
+/// `Allocator::allocate_for_buffer()` for how the memory type is picked.
 fn new_raw_buffer(
+    name: &str,
     size: usize,
     usage: vk::BufferUsageFlags,
     required_memory_properties: vk::MemoryPropertyFlags,
+    preferred_memory_properties: vk::MemoryPropertyFlags,
     gpu: &Gpu,
-) -> (vk::Buffer, vk::DeviceMemory) {
-    // Create buffer
+) -> (vk::Buffer, Allocation) {
     let buffer_create_info = vk::BufferCreateInfo::builder()
         .size(size as vk::DeviceSize)
         .usage(usage)
@@ -22,35 +136,354 @@ fn new_raw_buffer(
             .create_buffer(&buffer_create_info, None)
             .expect("Failed to create buffer.")
     };
-    // Locate memory type
-    let mem_requirements = unsafe { gpu.device.get_buffer_memory_requirements(vk_buffer) };
-    let memory_type_index = gpu
-        .memory_properties
-        .memory_types
-        .iter()
-        .enumerate()
-        .position(|(i, &m)| {
-            (mem_requirements.memory_type_bits & (1 << i)) > 0
-                && m.property_flags.contains(required_memory_properties)
-        })
-        .expect("Failed to find suitable memory type.") as u32;
-    // Allocate memory
-    // TODO: Replace with allocator library?
-    let allocate_info = vk::MemoryAllocateInfo::builder()
-        .allocation_size(mem_requirements.size)
-        .memory_type_index(memory_type_index);
-
-    let device_memory = unsafe {
-        gpu.device
-            .allocate_memory(&allocate_info, None)
-            .expect("Failed to allocate buffer memory.")
+    audit_created(
+        VkObjectKind::Buffer,
+        vk_buffer,
+        &[device_id(&gpu.device)],
+        name,
+    );
+    let allocation = gpu.allocator.allocate_for_buffer(
+        name,
+        vk_buffer,
+        required_memory_properties,
+        preferred_memory_properties,
+    );
+    if gpu.debug_fill.is_enabled() {
+        match allocation.opt_mapped_ptr {
+            Some(_) => fill_mapped(&allocation, size, gpu),
+            None => gpu.debug_fill.queue(vk_buffer),
+        }
+    }
+    (vk_buffer, allocation)
+}
+
+/* New buffers are filled with `DEBUG_FILL_PATTERN`, so that reading a range
+that was never written shows up as 0xDEADBEEF, or as a float of about -6.3e18,
+instead of as plausible garbage. Host-visible buffers are filled through their
+mapping. Device-local ones, like those from `DeviceLocalBuffer::new_uninitialized()`
+that are left for the caller to write, can only be filled on the GPU, so their
+fills are queued and recorded into the upload context's batch, ahead of any
+copy in it, rather than submitted one by one. Buffers whose every byte is
+copied in before they are returned, like those from `DeviceLocalBuffer::new()`,
+are dropped from the queue. Images aren't filled.
+
+It's on in debug builds, and switched with `Context::set_debug_fill_enabled()`.
+Buffers that were created before switching it keep the old setting. */
+pub struct DebugFill {
+    is_enabled: Cell<bool>,
+    pending_buffers: RefCell<Vec<vk::Buffer>>, // Device-local, in creation order
+}
+
+impl DebugFill {
+    pub fn new() -> DebugFill {
+        DebugFill {
+            is_enabled: Cell::new(cfg!(debug_assertions)),
+            pending_buffers: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.is_enabled.get()
+    }
+
+    pub fn set_enabled(&self, is_enabled: bool) {
+        self.is_enabled.set(is_enabled);
+    }
+
+    pub fn num_pending(&self) -> usize {
+        self.pending_buffers.borrow().len()
+    }
+
+    fn queue(&self, vk_buffer: vk::Buffer) {
+        self.pending_buffers.borrow_mut().push(vk_buffer);
+    }
+
+    /// Drop the buffer's fill if it hasn't been recorded yet, e.g. because
+    /// the buffer is being destroyed or is about to be fully written.
+    pub fn cancel(&self, vk_buffer: vk::Buffer) {
+        self.pending_buffers
+            .borrow_mut()
+            .retain(|&pending| pending != vk_buffer);
+    }
+
+    /* Record the queued fills, followed by a barrier that orders them before
+    later transfers in the command buffer, which must be on a queue with
+    graphics or compute. Returns whether anything was recorded. */
+    pub fn record_pending(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) -> bool {
+        let pending_buffers = std::mem::take(&mut *self.pending_buffers.borrow_mut());
+        if pending_buffers.is_empty() {
+            return false;
+        }
+        unsafe {
+            for &vk_buffer in &pending_buffers {
+                device.cmd_fill_buffer(
+                    command_buffer,
+                    vk_buffer,
+                    0,
+                    vk::WHOLE_SIZE,
+                    DEBUG_FILL_PATTERN,
+                );
+            }
+            let memory_barriers = [vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .build()];
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &memory_barriers,
+                &[],
+                &[],
+            );
+        }
+        true
+    }
+}
+
+impl Default for DebugFill {
+    fn default() -> DebugFill {
+        DebugFill::new()
+    }
+}
+
+fn fill_mapped(allocation: &Allocation, size: usize, gpu: &Gpu) {
+    let mapped_ptr = allocation
+        .opt_mapped_ptr
+        .expect("Host-visible memory isn't mapped.");
+    let pattern = DEBUG_FILL_PATTERN.to_le_bytes();
+    unsafe {
+        let bytes = std::slice::from_raw_parts_mut(mapped_ptr, size);
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = pattern[i % 4];
+        }
+    }
+    if !allocation
+        .property_flags
+        .contains(vk::MemoryPropertyFlags::HOST_COHERENT)
+    {
+        // Allocations in host-visible memory are padded to whole atoms
+        let ranges = [vk::MappedMemoryRange::builder()
+            .memory(allocation.memory)
+            .offset(allocation.offset)
+            .size(allocation.size)
+            .build()];
+        unsafe { gpu.device.flush_mapped_memory_ranges(&ranges) }
+            .expect("Failed to flush mapped memory.");
+    }
+}
+
+/* Copy `size` bytes of a buffer that the host can't map, like a device-local
+one, into a transient host-visible buffer and return them. The buffer needs
+`TRANSFER_SRC` usage. Blocks until the copy is done. */
+pub fn read_back_buffer(
+    name: &str,
+    vk_buffer: vk::Buffer,
+    size: usize,
+    gpu: &Gpu,
+    command_pool: vk::CommandPool,
+    debug_utils: &DebugUtils,
+) -> Result<Vec<u8>, BufferError> {
+    let readback_buffer = HostVisibleBuffer::new_host_cached(
+        &format!("{}_readback", name),
+        size,
+        vk::BufferUsageFlags::TRANSFER_DST,
+        gpu,
+        debug_utils,
+    );
+
+    let command_buffer = begin_single_use_command_buffer(&gpu.device, command_pool);
+    debug_utils.begin_label(
+        command_buffer,
+        &format!("read back {}", name),
+        LABEL_COLOR_UPLOAD,
+    );
+    unsafe {
+        let copy_regions = [vk::BufferCopy {
+            src_offset: 0,
+            dst_offset: 0,
+            size: size as u64,
+        }];
+        gpu.device.cmd_copy_buffer(
+            command_buffer,
+            vk_buffer,
+            readback_buffer.vk_buffer,
+            &copy_regions,
+        );
+        // Make the copy visible to the host
+        let barriers = [vk::MemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::HOST_READ,
+            ..Default::default()
+        }];
+        gpu.device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &barriers,
+            &[],
+            &[],
+        );
+    }
+    debug_utils.end_label(command_buffer);
+    end_single_use_command_buffer(command_buffer, command_pool, gpu);
+
+    let mut bytes = vec![0u8; size];
+    readback_buffer.download_data(&mut bytes, 0)?;
+    Ok(bytes)
+}
+
+/* Whether `data_size` bytes at `offset` can be written with
+`vkCmdUpdateBuffer`, which takes at most `MAX_INLINE_UPDATE_SIZE` bytes, and
+a multiple of 4 at a multiple of 4. */
+fn check_inline_update(
+    buffer_name: &str,
+    buffer_size: usize,
+    offset: usize,
+    data_size: usize,
+) -> Result<(), BufferError> {
+    if offset
+        .checked_add(data_size)
+        .is_none_or(|end| end > buffer_size)
+    {
+        return Err(BufferError::OutOfBounds {
+            buffer_name: String::from(buffer_name),
+            offset,
+            data_size,
+            buffer_size,
+        });
+    }
+    if data_size == 0
+        || data_size > MAX_INLINE_UPDATE_SIZE
+        || !offset.is_multiple_of(4)
+        || !data_size.is_multiple_of(4)
+    {
+        return Err(BufferError::InvalidInlineUpdate {
+            buffer_name: String::from(buffer_name),
+            offset,
+            data_size,
+        });
+    }
+    Ok(())
+}
+
+/* Write a few bytes, like a matrix, into a device-local buffer by recording
+them into the command buffer, without a staging buffer. The write waits for
+earlier reads of the buffer, and for earlier writes by compute shaders. It's
+visible to indirect draws, and to vertex, index, uniform and shader reads in
+graphics and compute shaders after it. Updates can't be recorded inside a
+render pass, so call this between passes rather than from a pass's recording
+closure. */
+pub fn update_device_local<T>(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    buffer: &DeviceLocalBuffer,
+    offset: usize,
+    data: &[T],
+) -> Result<(), BufferError> {
+    let data_size = std::mem::size_of_val(data);
+    let buffer_size = buffer.num_elements * buffer.element_size;
+    check_inline_update(&buffer.name, buffer_size, offset, data_size)?;
+
+    let read_stages = vk::PipelineStageFlags::DRAW_INDIRECT
+        | vk::PipelineStageFlags::VERTEX_INPUT
+        | vk::PipelineStageFlags::VERTEX_SHADER
+        | vk::PipelineStageFlags::FRAGMENT_SHADER
+        | vk::PipelineStageFlags::COMPUTE_SHADER;
+    let buffer_barrier = |src_access_mask, dst_access_mask| {
+        [vk::BufferMemoryBarrier::builder()
+            .src_access_mask(src_access_mask)
+            .dst_access_mask(dst_access_mask)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(buffer.vk_buffer)
+            .offset(offset as u64)
+            .size(data_size as u64)
+            .build()]
     };
-    // Bind memory to buffer
+    let read_access_mask = vk::AccessFlags::INDIRECT_COMMAND_READ
+        | vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+        | vk::AccessFlags::INDEX_READ
+        | vk::AccessFlags::UNIFORM_READ
+        | vk::AccessFlags::SHADER_READ;
     unsafe {
-        gpu.device
-            .bind_buffer_memory(vk_buffer, device_memory, 0)
-            .expect("Failed to bind buffer.");
+        // Reads by earlier commands must finish before the write, and compute writes be visible
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            read_stages,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &buffer_barrier(
+                vk::AccessFlags::SHADER_WRITE,
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+            &[],
+        );
+        let bytes = std::slice::from_raw_parts(data.as_ptr() as *const u8, data_size);
+        device.cmd_update_buffer(command_buffer, buffer.vk_buffer, offset as u64, bytes);
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            read_stages,
+            vk::DependencyFlags::empty(),
+            &[],
+            &buffer_barrier(vk::AccessFlags::TRANSFER_WRITE, read_access_mask),
+            &[],
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inline_updates_that_fit_are_valid() {
+        assert!(check_inline_update("buffer", 64, 0, 64).is_ok());
+        assert!(check_inline_update("buffer", 64, 60, 4).is_ok());
+        assert!(check_inline_update("buffer", 1 << 20, 4, MAX_INLINE_UPDATE_SIZE).is_ok());
     }
 
-    (vk_buffer, device_memory)
+    #[test]
+    fn inline_updates_past_the_end_are_out_of_bounds() {
+        match check_inline_update("buffer_matrices", 64, 48, 32) {
+            Err(BufferError::OutOfBounds {
+                buffer_name,
+                offset,
+                data_size,
+                buffer_size,
+            }) => {
+                assert_eq!(buffer_name, "buffer_matrices");
+                assert_eq!((offset, data_size, buffer_size), (48, 32, 64));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(
+            check_inline_update("buffer", 64, usize::MAX, 4),
+            Err(BufferError::OutOfBounds { .. })
+        ));
+        // Out of bounds is reported first, even if the update is misaligned too
+        assert!(matches!(
+            check_inline_update("buffer", 64, 62, 3),
+            Err(BufferError::OutOfBounds { .. })
+        ));
+    }
+
+    #[test]
+    fn inline_updates_must_be_aligned_and_small() {
+        for (offset, data_size) in &[(0, 0), (2, 4), (0, 6), (0, MAX_INLINE_UPDATE_SIZE + 4)] {
+            match check_inline_update("buffer", 1 << 20, *offset, *data_size) {
+                Err(BufferError::InvalidInlineUpdate {
+                    offset: error_offset,
+                    data_size: error_size,
+                    ..
+                }) => assert_eq!((error_offset, error_size), (*offset, *data_size)),
+                other => panic!("{:?}", other),
+            }
+        }
+    }
 }
@@ -0,0 +1,161 @@
+use crate::*;
+
+/* Linear allocator for data that is written every frame, like camera matrices
+or debug lines. The buffer is split into one region per frame in flight, and a
+frame's region is only reused once the fence of the frame that last wrote to
+it has signaled, so writing never has to wait for the GPU. */
+pub struct RingBuffer {
+    pub buffer: HostVisibleBuffer,
+    region_size: usize,
+    num_regions: usize,
+    region_idx: usize,
+    cursor: usize, // Offset into the current region
+    min_uniform_alignment: usize,
+}
+
+/* The offset of an allocation from the start of the buffer, aligned there
+rather than within the region, since regions start at the uniform offset
+alignment and `alignment` may be larger. None if it would overflow. */
+fn aligned_offset(
+    region_start: usize,
+    cursor: usize,
+    num_bytes: usize,
+    alignment: usize,
+) -> Option<usize> {
+    let unaligned = region_start.checked_add(cursor)?;
+    let offset = unaligned.checked_add(alignment - 1)? & !(alignment - 1);
+    offset.checked_add(num_bytes)?;
+    Some(offset)
+}
+
+impl RingBuffer {
+    pub fn new(
+        name: &str,
+        size: usize,
+        num_frames: usize,
+        usage: vk::BufferUsageFlags,
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> RingBuffer {
+        let min_uniform_alignment =
+            (gpu.properties.limits.min_uniform_buffer_offset_alignment as usize).max(1);
+        // Keep every region start aligned for uniforms
+        let region_size = size / num_frames / min_uniform_alignment * min_uniform_alignment;
+        assert!(
+            region_size > 0,
+            "Ring buffer `{}` is too small to split into {} frames.",
+            name,
+            num_frames
+        );
+        RingBuffer {
+            buffer: HostVisibleBuffer::new(name, region_size * num_frames, usage, gpu, debug_utils),
+            region_size,
+            num_regions: num_frames,
+            region_idx: 0,
+            cursor: 0,
+            min_uniform_alignment,
+        }
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.num_regions
+    }
+
+    /// Bytes allocated in the current region, including alignment padding.
+    pub fn bytes_used(&self) -> usize {
+        self.cursor
+    }
+
+    /// Switch to the region of `sync_idx`, discarding what was allocated in it.
+    /// `complete_fence` is the fence of the frame that used it last, which is
+    /// waited for, so that the GPU is done reading the region.
+    pub fn begin_frame(&mut self, sync_idx: usize, complete_fence: vk::Fence, gpu: &Gpu) {
+        unsafe {
+            gpu.device
+                .wait_for_fences(&[complete_fence], true, u64::MAX)
+                .expect("Failed to wait for Fence.");
+        }
+        self.region_idx = sync_idx % self.num_regions;
+        self.cursor = 0;
+    }
+
+    /* Returns the offset of the allocation from the start of the buffer, and
+    the memory to write to. `alignment` must be a power of two. Panics if the
+    current frame's region is full. */
+    pub fn allocate(&mut self, num_bytes: usize, alignment: usize) -> (usize, &mut [u8]) {
+        assert!(
+            alignment.is_power_of_two(),
+            "Alignment must be a power of two."
+        );
+        let region_start = self.region_idx * self.region_size;
+        let offset = aligned_offset(region_start, self.cursor, num_bytes, alignment)
+            .filter(|offset| offset + num_bytes <= region_start + self.region_size)
+            .unwrap_or_else(|| {
+                panic!(
+                    "Ring buffer `{}` is out of space. Allocating {} bytes with {} byte alignment after {} bytes exceeds the {} bytes per frame.",
+                    self.buffer.name, num_bytes, alignment, self.cursor, self.region_size
+                )
+            });
+        self.cursor = offset + num_bytes - region_start;
+        let bytes = self.buffer.mapped_slice_mut::<u8>();
+        (offset, &mut bytes[offset..offset + num_bytes])
+    }
+
+    /// Allocate with the alignment that uniform buffer offsets need on this GPU.
+    pub fn allocate_uniform(&mut self, num_bytes: usize) -> (usize, &mut [u8]) {
+        let alignment = self.min_uniform_alignment;
+        self.allocate(num_bytes, alignment)
+    }
+
+    /// Make this frame's allocations visible to the GPU, if the memory isn't
+    /// coherent. Call before submitting the commands that read them.
+    pub fn flush(&self) {
+        self.buffer
+            .flush_mapped_range(self.region_idx * self.region_size, self.cursor)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// Copy `data` into a uniform allocation and return its offset.
+    pub fn write_uniform<T>(&mut self, data: &[T]) -> usize {
+        let num_bytes = std::mem::size_of_val(data);
+        let (offset, bytes) = self.allocate_uniform(num_bytes);
+        unsafe {
+            bytes
+                .as_mut_ptr()
+                .copy_from_nonoverlapping(data.as_ptr() as *const u8, num_bytes);
+        }
+        offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_are_aligned_from_the_buffer_start() {
+        // Regions start at multiples of 256, but not of 1024
+        assert_eq!(aligned_offset(256, 0, 16, 1024), Some(1024));
+        assert_eq!(aligned_offset(768, 4, 16, 1024), Some(1024));
+        assert_eq!(aligned_offset(1024, 1, 16, 1024), Some(2048));
+        // Aligning within the region would have been wrong
+        let offset = aligned_offset(256, 10, 8, 512).unwrap();
+        assert_eq!(offset % 512, 0);
+        assert_ne!(offset, 256 + 512);
+    }
+
+    #[test]
+    fn offsets_that_are_already_aligned_are_kept() {
+        assert_eq!(aligned_offset(0, 0, 16, 1), Some(0));
+        assert_eq!(aligned_offset(256, 32, 16, 16), Some(288));
+        assert_eq!(aligned_offset(256, 33, 16, 16), Some(304));
+    }
+
+    #[test]
+    fn overflowing_offsets_are_none() {
+        assert_eq!(aligned_offset(usize::MAX - 4, 8, 1, 1), None);
+        assert_eq!(aligned_offset(usize::MAX - 4, 0, 1, 256), None);
+        assert_eq!(aligned_offset(0, 0, usize::MAX, 1), Some(0));
+        assert_eq!(aligned_offset(0, 1, usize::MAX, 1), None);
+    }
+}
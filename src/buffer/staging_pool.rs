@@ -0,0 +1,174 @@
+use crate::*;
+use std::cell::RefCell;
+
+// Size of the allocations that chunks are carved out of. Larger requests get a
+// block of their own.
+const STAGING_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+// Buffer-to-image copies need offsets that are a multiple of the texel size and of 4
+const STAGING_ALIGNMENT: usize = 16;
+
+/// A piece of a staging block, already holding the data to upload. Copy from
+/// `vk_buffer` starting at `offset`, then give it back to the pool.
+pub struct StagingChunk {
+    pub vk_buffer: vk::Buffer,
+    pub offset: usize,
+    pub size: usize,
+    block_idx: usize,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StagingStats {
+    pub num_blocks: usize,
+    pub total_bytes: usize,
+    pub bytes_in_use: usize,
+    pub peak_bytes_in_use: usize,
+    pub num_allocations: usize,
+    pub bytes_uploaded: u64, // Total over every allocation, without alignment padding
+}
+
+struct StagingBlock {
+    buffer: HostVisibleBuffer,
+    free_ranges: Vec<(usize, usize)>, // (offset, size), sorted by offset
+}
+
+#[derive(Default)]
+struct StagingPoolState {
+    blocks: Vec<StagingBlock>,
+    // Freed, but the copy out of them may still be executing
+    in_flight_chunks: Vec<(StagingChunk, vk::Fence)>,
+    stats: StagingStats,
+}
+
+/* Hands out host-visible chunks for uploads from a few large, persistently
+mapped allocations, instead of creating a buffer and allocating memory for
+every upload. Chunks are first-fit from each block's free ranges, and the pool
+grows by a block when none of them has room.
+
+A chunk must only be freed once the GPU is done copying out of it. */
+pub struct StagingPool {
+    state: RefCell<StagingPoolState>,
+}
+
+impl StagingPool {
+    pub fn new() -> StagingPool {
+        StagingPool {
+            state: RefCell::new(StagingPoolState::default()),
+        }
+    }
+
+    pub fn allocate<T>(&self, data: &[T], gpu: &Gpu, debug_utils: &DebugUtils) -> StagingChunk {
+        self.reclaim(gpu);
+
+        let size = std::mem::size_of_val(data).max(1);
+        let aligned_size = size.div_ceil(STAGING_ALIGNMENT) * STAGING_ALIGNMENT;
+        let state = &mut *self.state.borrow_mut();
+
+        let opt_found = state
+            .blocks
+            .iter()
+            .enumerate()
+            .find_map(|(block_idx, block)| {
+                block
+                    .free_ranges
+                    .iter()
+                    .position(|(_, range_size)| *range_size >= aligned_size)
+                    .map(|range_idx| (block_idx, range_idx))
+            });
+        let (block_idx, range_idx) = opt_found.unwrap_or_else(|| {
+            let block_size = aligned_size.max(STAGING_BLOCK_SIZE);
+            let buffer = HostVisibleBuffer::new(
+                &format!("staging_block_{}", state.blocks.len()),
+                block_size,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+                gpu,
+                debug_utils,
+            );
+            state.blocks.push(StagingBlock {
+                buffer,
+                free_ranges: vec![(0, block_size)],
+            });
+            state.stats.num_blocks += 1;
+            state.stats.total_bytes += block_size;
+            (state.blocks.len() - 1, 0)
+        });
+
+        let block = &mut state.blocks[block_idx];
+        let (offset, range_size) = block.free_ranges[range_idx];
+        if range_size == aligned_size {
+            block.free_ranges.remove(range_idx);
+        } else {
+            block.free_ranges[range_idx] = (offset + aligned_size, range_size - aligned_size);
+        }
+        block
+            .buffer
+            .upload_data(data, offset)
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        state.stats.num_allocations += 1;
+        state.stats.bytes_uploaded += std::mem::size_of_val(data) as u64;
+        state.stats.bytes_in_use += aligned_size;
+        state.stats.peak_bytes_in_use = state.stats.peak_bytes_in_use.max(state.stats.bytes_in_use);
+
+        StagingChunk {
+            vk_buffer: block.buffer.vk_buffer,
+            offset,
+            size: aligned_size,
+            block_idx,
+        }
+    }
+
+    /// Give back a chunk that the GPU is done with, e.g. after waiting on the
+    /// copy's fence.
+    pub fn free(&self, chunk: StagingChunk) {
+        let state = &mut *self.state.borrow_mut();
+        let free_ranges = &mut state.blocks[chunk.block_idx].free_ranges;
+        let idx = free_ranges.partition_point(|(offset, _)| *offset < chunk.offset);
+        free_ranges.insert(idx, (chunk.offset, chunk.size));
+        // Merge with the neighbors, so that the block doesn't fragment
+        if idx + 1 < free_ranges.len() && chunk.offset + chunk.size == free_ranges[idx + 1].0 {
+            free_ranges[idx].1 += free_ranges.remove(idx + 1).1;
+        }
+        if idx > 0 && free_ranges[idx - 1].0 + free_ranges[idx - 1].1 == chunk.offset {
+            free_ranges[idx - 1].1 += free_ranges.remove(idx).1;
+        }
+        state.stats.bytes_in_use -= chunk.size;
+    }
+
+    /// Give back a chunk whose copy is still executing. It is recycled once
+    /// `fence` signals, and the fence is then released to the sync pool.
+    pub fn free_after(&self, chunk: StagingChunk, fence: vk::Fence) {
+        self.state
+            .borrow_mut()
+            .in_flight_chunks
+            .push((chunk, fence));
+    }
+
+    fn reclaim(&self, gpu: &Gpu) {
+        let in_flight_chunks = std::mem::take(&mut self.state.borrow_mut().in_flight_chunks);
+        for (chunk, fence) in in_flight_chunks {
+            let is_signaled = unsafe { gpu.device.get_fence_status(fence) }.is_ok();
+            if is_signaled {
+                self.free(chunk);
+                gpu.sync_pool.release_fence(fence);
+            } else {
+                self.state
+                    .borrow_mut()
+                    .in_flight_chunks
+                    .push((chunk, fence));
+            }
+        }
+    }
+
+    pub fn stats(&self) -> StagingStats {
+        self.state.borrow().stats
+    }
+
+    /// Free every block. The GPU must be idle.
+    pub fn destroy(&self, sync_pool: &SyncPool) {
+        let state = &mut *self.state.borrow_mut();
+        for (_, fence) in state.in_flight_chunks.drain(..) {
+            sync_pool.release_fence(fence);
+        }
+        state.blocks.clear();
+    }
+}
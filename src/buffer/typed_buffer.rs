@@ -0,0 +1,173 @@
+use crate::*;
+use std::marker::PhantomData;
+
+/* Host-visible buffer of `len` elements of `T`, so that sizes and offsets are
+counted in elements instead of bytes. `T: Copy` keeps types that own heap
+memory or have drop glue out of GPU memory. */
+pub struct TypedBuffer<T: Copy> {
+    pub buffer: HostVisibleBuffer, // For binding, and for byte-level access
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Copy> TypedBuffer<T> {
+    pub fn new(
+        name: &str,
+        len: usize,
+        usage: vk::BufferUsageFlags,
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> TypedBuffer<T> {
+        let size = typed_buffer_size::<T>(name, len);
+        TypedBuffer {
+            buffer: HostVisibleBuffer::new(name, size, usage, gpu, debug_utils),
+            len,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Like `HostVisibleBuffer::new_host_cached()`, for buffers that are read back.
+    pub fn new_host_cached(
+        name: &str,
+        len: usize,
+        usage: vk::BufferUsageFlags,
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> TypedBuffer<T> {
+        let size = typed_buffer_size::<T>(name, len);
+        TypedBuffer {
+            buffer: HostVisibleBuffer::new_host_cached(name, size, usage, gpu, debug_utils),
+            len,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Number of elements.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn vk_buffer(&self) -> vk::Buffer {
+        self.buffer.vk_buffer
+    }
+
+    /// Write `data` starting at element `offset`.
+    pub fn upload(&self, data: &[T], offset: usize) -> Result<(), BufferError> {
+        check_element_bounds::<T>(&self.buffer.name, self.len, offset, data.len())?;
+        self.buffer
+            .upload_data(data, offset * std::mem::size_of::<T>())
+    }
+
+    /// Read elements starting at element `offset` into `out`. The GPU must be
+    /// done writing to the buffer.
+    pub fn download(&self, out: &mut [T], offset: usize) -> Result<(), BufferError> {
+        check_element_bounds::<T>(&self.buffer.name, self.len, offset, out.len())?;
+        self.buffer
+            .download_data(out, offset * std::mem::size_of::<T>())
+    }
+}
+
+fn typed_buffer_size<T>(name: &str, len: usize) -> usize {
+    len.checked_mul(std::mem::size_of::<T>())
+        .unwrap_or_else(|| panic!("Buffer `{}` of {} elements is too large.", name, len))
+}
+
+/* Whether `count` elements at element `offset` fit in a buffer of `len`
+elements. Errors are reported in bytes, like every other buffer error. */
+fn check_element_bounds<T>(
+    buffer_name: &str,
+    len: usize,
+    offset: usize,
+    count: usize,
+) -> Result<(), BufferError> {
+    let is_in_bounds = offset.checked_add(count).is_some_and(|end| end <= len);
+    if !is_in_bounds {
+        let element_size = std::mem::size_of::<T>();
+        return Err(BufferError::OutOfBounds {
+            buffer_name: String::from(buffer_name),
+            offset: offset.saturating_mul(element_size),
+            data_size: count.saturating_mul(element_size),
+            buffer_size: len * element_size,
+        });
+    }
+    Ok(())
+}
+
+/// Device-local buffer that remembers the type of its elements.
+pub struct TypedDeviceLocalBuffer<T: Copy> {
+    pub buffer: DeviceLocalBuffer, // For binding
+    _marker: PhantomData<T>,
+}
+
+impl<T: Copy> TypedDeviceLocalBuffer<T> {
+    pub fn new(
+        name: &str,
+        data: &[T],
+        usage: vk::BufferUsageFlags,
+        gpu: &Gpu,
+        command_pool: vk::CommandPool,
+        debug_utils: &DebugUtils,
+    ) -> TypedDeviceLocalBuffer<T> {
+        TypedDeviceLocalBuffer {
+            buffer: DeviceLocalBuffer::new(name, data, usage, gpu, command_pool, debug_utils),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Number of elements.
+    pub fn len(&self) -> usize {
+        self.buffer.num_elements
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.num_elements == 0
+    }
+
+    pub fn vk_buffer(&self) -> vk::Buffer {
+        self.buffer.vk_buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elements_that_fit_are_in_bounds() {
+        assert!(check_element_bounds::<u32>("buffer", 8, 0, 8).is_ok());
+        assert!(check_element_bounds::<u32>("buffer", 8, 3, 5).is_ok());
+        assert!(check_element_bounds::<u32>("buffer", 8, 8, 0).is_ok());
+    }
+
+    #[test]
+    fn out_of_bounds_errors_are_in_bytes() {
+        match check_element_bounds::<[f32; 4]>("buffer_points", 10, 8, 3) {
+            Err(BufferError::OutOfBounds {
+                buffer_name,
+                offset,
+                data_size,
+                buffer_size,
+            }) => {
+                assert_eq!(buffer_name, "buffer_points");
+                assert_eq!((offset, data_size, buffer_size), (128, 48, 160));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn overflowing_ranges_are_out_of_bounds() {
+        assert!(check_element_bounds::<u64>("buffer", 8, usize::MAX, 2).is_err());
+        assert!(check_element_bounds::<u64>("buffer", 8, 2, usize::MAX).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "too large")]
+    fn overflowing_sizes_panic() {
+        typed_buffer_size::<u64>("buffer", usize::MAX / 4);
+    }
+}
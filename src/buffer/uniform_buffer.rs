@@ -0,0 +1,96 @@
+use crate::*;
+use std::marker::PhantomData;
+
+/* One copy of `T` per frame in flight, in a single persistently mapped
+buffer, so that writing this frame's copy never races the GPU reading an
+earlier frame's. Each copy starts at a multiple of the GPU's
+`min_uniform_buffer_offset_alignment`, so that it can be selected with the
+dynamic offset of a `UNIFORM_BUFFER_DYNAMIC` descriptor of range
+`size_of::<T>()`. `T` must follow std140 layout rules itself, e.g. by only
+holding matrices, `Vec4`s and scalars in the order the shader declares. */
+pub struct UniformBuffer<T: Copy> {
+    pub buffer: HostVisibleBuffer,
+    num_frames: usize,
+    stride: usize, // Bytes between copies
+    _marker: PhantomData<T>,
+}
+
+impl<T: Copy> UniformBuffer<T> {
+    pub fn new(
+        name: &str,
+        num_frames: usize,
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> UniformBuffer<T> {
+        let stride = uniform_stride(
+            std::mem::size_of::<T>(),
+            gpu.properties.limits.min_uniform_buffer_offset_alignment as usize,
+        );
+        UniformBuffer {
+            buffer: HostVisibleBuffer::new(
+                name,
+                stride * num_frames,
+                vk::BufferUsageFlags::UNIFORM_BUFFER,
+                gpu,
+                debug_utils,
+            ),
+            num_frames,
+            stride,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.num_frames
+    }
+
+    /// Overwrite the copy of `frame_idx`, which the GPU must be done reading.
+    pub fn write(&self, frame_idx: usize, value: &T) {
+        self.buffer
+            .upload_data(
+                std::slice::from_ref(value),
+                self.dynamic_offset(frame_idx) as usize,
+            )
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    pub fn dynamic_offset(&self, frame_idx: usize) -> u32 {
+        assert!(
+            frame_idx < self.num_frames,
+            "Uniform buffer `{}` has no copy for frame {}.",
+            self.buffer.name,
+            frame_idx
+        );
+        (frame_idx * self.stride) as u32
+    }
+
+    /// Range of the descriptor that the copies are bound through.
+    pub fn descriptor_range(&self) -> u64 {
+        std::mem::size_of::<T>() as u64
+    }
+}
+
+// Bytes between copies of `size`, so that each copy starts at a multiple of `alignment`
+fn uniform_stride(size: usize, alignment: usize) -> usize {
+    let alignment = alignment.max(1);
+    size.max(1).div_ceil(alignment) * alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strides_are_padded_to_the_alignment() {
+        assert_eq!(uniform_stride(64, 256), 256);
+        assert_eq!(uniform_stride(256, 256), 256);
+        assert_eq!(uniform_stride(257, 256), 512);
+        assert_eq!(uniform_stride(144, 16), 144);
+    }
+
+    #[test]
+    fn empty_uniforms_and_missing_alignments_still_get_a_stride() {
+        assert_eq!(uniform_stride(0, 64), 64);
+        assert_eq!(uniform_stride(12, 0), 12);
+    }
+}
@@ -5,12 +5,14 @@ use crate::*;
 // don't overlap.
 
 pub struct BufferList {
-    pub list: Vec<(BufferHandle, HostVisibleBuffer)>, // TODO: Support device local buffers too
+    pub list: Registry<HostVisibleBuffer>, // TODO: Support device local buffers too
 }
 
 impl BufferList {
     pub fn new() -> BufferList {
-        BufferList { list: Vec::new() }
+        BufferList {
+            list: Registry::new("buffer"),
+        }
     }
 
     pub fn new_buffer(
@@ -21,43 +23,51 @@ impl BufferList {
         gpu: &Gpu,
         debug_utils: &DebugUtils,
     ) -> Result<BufferHandle, String> {
-        // Hash
-        let handle = {
-            let mut hasher = DefaultHasher::new();
-            name.hash(&mut hasher);
-            BufferHandle(hasher.finish())
-        };
         // Error if name already exists
-        if self.get_buffer_from_handle(handle).is_some() {
+        if self.list.find(name).is_some() {
             return Err(format!(
                 "A buffer with the same name `{}` already exists in the context.",
                 name
             ));
         }
-        // Create and insert new buffer
+        // Create and insert new buffer. These are updated every frame, so they stay mapped.
         let buffer = HostVisibleBuffer::new(name, size, usage, gpu, debug_utils);
-        self.list.push((handle, buffer));
+        self.list.insert(name, buffer)
+    }
 
-        Ok(handle)
+    /// Fails with an error that names the buffer if it has been destroyed.
+    pub fn get_buffer(&self, buffer_handle: BufferHandle) -> Result<&HostVisibleBuffer, String> {
+        self.list.get(buffer_handle)
     }
 
     pub fn get_buffer_from_handle(
         &self,
         buffer_handle: BufferHandle,
     ) -> Option<&HostVisibleBuffer> {
-        for (handle, buffer) in &self.list {
-            if *handle == buffer_handle {
-                return Some(buffer);
-            }
-        }
-        None
+        self.list.get(buffer_handle).ok()
     }
 
-    pub fn upload_data<T>(&self, buffer_handle: BufferHandle, data: &[T]) {
-        let internal_buffer = self.get_buffer_from_handle(buffer_handle).unwrap_or_else(||panic!(
-            "A buffer with the hash `{}` not found in the context.",
-            buffer_handle.0
-        ));
-        internal_buffer.upload_data(data, 0);
+    pub fn get_buffer_from_handle_mut(
+        &mut self,
+        buffer_handle: BufferHandle,
+    ) -> Option<&mut HostVisibleBuffer> {
+        self.list.get_mut(buffer_handle).ok()
+    }
+
+    /// Destroy a buffer, which the GPU must be done with. Its handles are stale
+    /// from now on.
+    pub fn destroy_buffer(&mut self, buffer_handle: BufferHandle) -> Result<(), String> {
+        self.list.remove(buffer_handle).map(drop)
+    }
+
+    pub fn upload_data<T>(
+        &self,
+        buffer_handle: BufferHandle,
+        data: &[T],
+    ) -> Result<(), BufferError> {
+        let internal_buffer = self
+            .get_buffer(buffer_handle)
+            .unwrap_or_else(|e| panic!("{}", e));
+        internal_buffer.upload_data(data, 0)
     }
 }
@@ -0,0 +1,410 @@
+use crate::*;
+
+const MAX_OBJECTS_PER_LEAF: usize = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BvhHit {
+    pub object_idx: usize, // Index into the boxes that the BVH was built from
+    pub distance: f32,     // Along the ray, to where it enters the object's box
+}
+
+#[derive(Copy, Clone, Debug)]
+enum BvhNodeKind {
+    Leaf { first: usize, count: usize }, // Range in `object_order`
+    Inner { left: usize, right: usize },
+}
+
+#[derive(Copy, Clone, Debug)]
+struct BvhNode {
+    aabb: Aabb,
+    kind: BvhNodeKind,
+    opt_parent: Option<usize>,
+}
+
+/* Bounding volume hierarchy over object boxes, for ray queries that would
+otherwise test every object. Built top-down with median splits along the
+longest axis of the box centers.
+
+When objects move, `set_object_aabb()` followed by `refit()` only updates the
+leaves that changed and their ancestors, instead of rebuilding. Refitting
+keeps the tree's structure, so rebuild with `new()` when objects are added or
+removed, or when they have moved so far that queries get slow.
+
+Boxes with infinite or NaN coordinates, e.g. of objects whose transform has
+blown up, are never hit. Objects that have one when the tree is built are left
+out of it until the next rebuild. */
+pub struct Bvh {
+    nodes: Vec<BvhNode>, // The root is at 0, if there are any objects
+    object_aabbs: Vec<Aabb>,
+    object_order: Vec<usize>,           // Object indices, grouped by leaf
+    leaf_of_object: Vec<Option<usize>>, // Node index of each object's leaf, if it's in the tree
+    dirty_leaves: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn new(object_aabbs: &[Aabb]) -> Bvh {
+        let object_order: Vec<usize> = (0..object_aabbs.len())
+            .filter(|idx| object_aabbs[*idx].is_finite())
+            .collect();
+        let num_finite_objects = object_order.len();
+        let mut bvh = Bvh {
+            nodes: Vec::new(),
+            object_aabbs: object_aabbs.to_vec(),
+            object_order,
+            leaf_of_object: vec![None; object_aabbs.len()],
+            dirty_leaves: Vec::new(),
+        };
+        if num_finite_objects > 0 {
+            bvh.build_node(0, num_finite_objects, None);
+        }
+        bvh
+    }
+
+    pub fn num_objects(&self) -> usize {
+        self.object_aabbs.len()
+    }
+
+    // Builds the node for `object_order[first..first + count]` and returns its index
+    fn build_node(&mut self, first: usize, count: usize, opt_parent: Option<usize>) -> usize {
+        let object_aabbs = &self.object_aabbs;
+        let objects = &mut self.object_order[first..first + count];
+        let aabb = union_of(objects.iter().map(|idx| &object_aabbs[*idx]));
+        let node_idx = self.nodes.len();
+        self.nodes.push(BvhNode {
+            aabb,
+            kind: BvhNodeKind::Leaf { first, count },
+            opt_parent,
+        });
+        if count <= MAX_OBJECTS_PER_LEAF {
+            for object_idx in objects.iter() {
+                self.leaf_of_object[*object_idx] = Some(node_idx);
+            }
+            return node_idx;
+        }
+
+        /* Split at the median along the longest axis of the centers. When all
+        centers coincide, every axis ties, and splitting by index still keeps
+        the tree balanced. */
+        let centers = Aabb::from_points(objects.iter().map(|idx| object_aabbs[*idx].center()));
+        let extents: [f32; 3] = centers.extents().into();
+        let axis = (0..3)
+            .max_by(|a, b| {
+                extents[*a]
+                    .partial_cmp(&extents[*b])
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap();
+        let center_on_axis = |idx: &usize| -> f32 {
+            let center: [f32; 3] = object_aabbs[*idx].center().into();
+            center[axis]
+        };
+        let half = count / 2;
+        objects.select_nth_unstable_by(half, |a, b| {
+            center_on_axis(a)
+                .partial_cmp(&center_on_axis(b))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let left = self.build_node(first, half, Some(node_idx));
+        let right = self.build_node(first + half, count - half, Some(node_idx));
+        self.nodes[node_idx].kind = BvhNodeKind::Inner { left, right };
+        node_idx
+    }
+
+    /// Takes effect on the next `refit()`, or on the next rebuild for objects
+    /// that were left out of the tree.
+    pub fn set_object_aabb(&mut self, object_idx: usize, aabb: Aabb) {
+        self.object_aabbs[object_idx] = aabb;
+        if let Some(leaf_idx) = self.leaf_of_object[object_idx] {
+            if !self.dirty_leaves.contains(&leaf_idx) {
+                self.dirty_leaves.push(leaf_idx);
+            }
+        }
+    }
+
+    /// Update the boxes of the leaves that changed since the last refit, and
+    /// of their ancestors. Ancestors whose box doesn't change stop the walk.
+    pub fn refit(&mut self) {
+        let dirty_leaves = std::mem::take(&mut self.dirty_leaves);
+        for leaf_idx in dirty_leaves {
+            let mut node_idx = leaf_idx;
+            loop {
+                let aabb = match self.nodes[node_idx].kind {
+                    BvhNodeKind::Leaf { first, count } => union_of(
+                        self.object_order[first..first + count]
+                            .iter()
+                            .map(|idx| &self.object_aabbs[*idx]),
+                    ),
+                    BvhNodeKind::Inner { left, right } => {
+                        union_of([&self.nodes[left].aabb, &self.nodes[right].aabb])
+                    }
+                };
+                let node = &mut self.nodes[node_idx];
+                if node.aabb == aabb && node_idx != leaf_idx {
+                    break;
+                }
+                node.aabb = aabb;
+                match node.opt_parent {
+                    Some(parent_idx) => node_idx = parent_idx,
+                    None => break,
+                }
+            }
+        }
+    }
+
+    /// Nearest object box that the ray enters. Boxes that contain the ray's
+    /// origin are hit at distance 0.
+    pub fn raycast(&self, ray: &Ray) -> Option<BvhHit> {
+        let mut opt_best: Option<BvhHit> = None;
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut stack = vec![0];
+        while let Some(node_idx) = stack.pop() {
+            let node = &self.nodes[node_idx];
+            let distance = match ray.intersect_aabb(&node.aabb) {
+                Some(distance) => distance,
+                None => continue,
+            };
+            if opt_best.is_some_and(|best| best.distance <= distance) {
+                continue;
+            }
+            match node.kind {
+                BvhNodeKind::Leaf { first, count } => {
+                    for object_idx in &self.object_order[first..first + count] {
+                        if let Some(distance) =
+                            intersect_object(ray, &self.object_aabbs[*object_idx])
+                        {
+                            if opt_best.is_none_or(|best| distance < best.distance) {
+                                opt_best = Some(BvhHit {
+                                    object_idx: *object_idx,
+                                    distance,
+                                });
+                            }
+                        }
+                    }
+                }
+                BvhNodeKind::Inner { left, right } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+        opt_best
+    }
+}
+
+/// Nearest object box that the ray enters, testing every box. For checking
+/// `Bvh::raycast()` against, and for small numbers of objects.
+pub fn raycast_linear(object_aabbs: &[Aabb], ray: &Ray) -> Option<BvhHit> {
+    let mut opt_best: Option<BvhHit> = None;
+    for (object_idx, aabb) in object_aabbs.iter().enumerate() {
+        if let Some(distance) = intersect_object(ray, aabb) {
+            if opt_best.is_none_or(|best| distance < best.distance) {
+                opt_best = Some(BvhHit {
+                    object_idx,
+                    distance,
+                });
+            }
+        }
+    }
+    opt_best
+}
+
+fn intersect_object(ray: &Ray, aabb: &Aabb) -> Option<f32> {
+    if aabb.is_finite() {
+        ray.intersect_aabb(aabb)
+    } else {
+        None
+    }
+}
+
+// Boxes that aren't finite are skipped, like in queries
+fn union_of<'a>(aabbs: impl IntoIterator<Item = &'a Aabb>) -> Aabb {
+    let mut union = Aabb::empty();
+    for aabb in aabbs.into_iter().filter(|aabb| aabb.is_finite()) {
+        union.min = union.min.min(aabb.min);
+        union.max = union.max.max(aabb.max);
+    }
+    union
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::*;
+
+    const NUM_RAYS: usize = 500;
+
+    // Xorshift, so that the tests don't need a dependency and are reproducible
+    struct Random(u32);
+
+    impl Random {
+        // Uniform in [min, max)
+        fn range(&mut self, min: f32, max: f32) -> f32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            min + (max - min) * (self.0 >> 8) as f32 / (1 << 24) as f32
+        }
+
+        fn vec3(&mut self, min: f32, max: f32) -> Vec3 {
+            Vec3::new(
+                self.range(min, max),
+                self.range(min, max),
+                self.range(min, max),
+            )
+        }
+
+        fn aabb(&mut self) -> Aabb {
+            let center = self.vec3(-50.0, 50.0);
+            let half_extents = self.vec3(0.1, 3.0);
+            Aabb {
+                min: center - half_extents,
+                max: center + half_extents,
+            }
+        }
+
+        fn ray(&mut self) -> Ray {
+            Ray::new(self.vec3(-80.0, 80.0), self.vec3(-1.0, 1.0).normalize())
+        }
+
+        // Aimed at one of the boxes, so that most rays hit something
+        fn ray_towards(&mut self, aabbs: &[Aabb]) -> Ray {
+            let target =
+                aabbs[(self.range(0.0, aabbs.len() as f32) as usize).min(aabbs.len() - 1)].center();
+            let origin = self.vec3(-80.0, 80.0);
+            Ray::new(origin, (target - origin).normalize())
+        }
+    }
+
+    /* Ties between boxes at the same distance can be broken either way, so
+    the hits must agree on the distance, and the BVH's object must really be
+    at it. */
+    fn assert_same_hit(bvh: &Bvh, aabbs: &[Aabb], ray: &Ray) {
+        let opt_hit = bvh.raycast(ray);
+        let opt_expected = raycast_linear(aabbs, ray);
+        assert_eq!(
+            opt_hit.map(|hit| hit.distance),
+            opt_expected.map(|hit| hit.distance),
+            "{:?}",
+            ray
+        );
+        if let Some(hit) = opt_hit {
+            assert_eq!(
+                intersect_object(ray, &aabbs[hit.object_idx]),
+                Some(hit.distance)
+            );
+        }
+    }
+
+    #[test]
+    fn raycast_matches_the_linear_scan() {
+        let mut random = Random(0x1234_5678);
+        let aabbs: Vec<Aabb> = (0..1000).map(|_| random.aabb()).collect();
+        let bvh = Bvh::new(&aabbs);
+        let mut num_hits = 0;
+        for ray_idx in 0..NUM_RAYS {
+            let ray = if ray_idx % 2 == 0 {
+                random.ray()
+            } else {
+                random.ray_towards(&aabbs)
+            };
+            assert_same_hit(&bvh, &aabbs, &ray);
+            num_hits += bvh.raycast(&ray).is_some() as usize;
+        }
+        // Otherwise the comparison would only cover misses
+        assert!(num_hits > NUM_RAYS / 4, "{}", num_hits);
+    }
+
+    #[test]
+    fn raycast_matches_the_linear_scan_after_refitting() {
+        let mut random = Random(0x9e37_79b9);
+        let mut aabbs: Vec<Aabb> = (0..300).map(|_| random.aabb()).collect();
+        let mut bvh = Bvh::new(&aabbs);
+        for object_idx in (0..aabbs.len()).step_by(3) {
+            aabbs[object_idx] = random.aabb();
+            bvh.set_object_aabb(object_idx, aabbs[object_idx]);
+        }
+        bvh.refit();
+        for _ in 0..NUM_RAYS {
+            let ray = random.ray_towards(&aabbs);
+            assert_same_hit(&bvh, &aabbs, &ray);
+        }
+    }
+
+    #[test]
+    fn empty_bvh_is_never_hit() {
+        let bvh = Bvh::new(&[]);
+        assert_eq!(bvh.num_objects(), 0);
+        let mut random = Random(7);
+        for _ in 0..10 {
+            assert_eq!(bvh.raycast(&random.ray()), None);
+        }
+    }
+
+    #[test]
+    fn objects_at_one_point_are_all_in_the_tree() {
+        let point = Vec3::new(1.0, 2.0, 3.0);
+        let aabbs = vec![
+            Aabb {
+                min: point,
+                max: point,
+            };
+            100
+        ];
+        let bvh = Bvh::new(&aabbs);
+        assert!(bvh.leaf_of_object.iter().all(|opt_leaf| opt_leaf.is_some()));
+        // Along an axis, since a diagonal ray would need to pass exactly through the point
+        let ray = Ray::new(point - Vec3::unit_x() * 10.0, Vec3::unit_x());
+        let hit = bvh.raycast(&ray).unwrap();
+        assert_eq!(hit.distance, 10.0);
+        let mut random = Random(11);
+        for _ in 0..NUM_RAYS {
+            assert_same_hit(&bvh, &aabbs, &random.ray());
+        }
+    }
+
+    #[test]
+    fn non_finite_boxes_are_left_out() {
+        let mut random = Random(0xdead_beef);
+        let mut aabbs: Vec<Aabb> = (0..50).map(|_| random.aabb()).collect();
+        let nan_idx = 3;
+        let infinite_idx = 20;
+        aabbs[nan_idx].min = Vec3::splat(f32::NAN);
+        aabbs[infinite_idx].max = Vec3::splat(f32::INFINITY);
+        let mut bvh = Bvh::new(&aabbs);
+        assert_eq!(bvh.leaf_of_object[nan_idx], None);
+        assert_eq!(bvh.leaf_of_object[infinite_idx], None);
+        for _ in 0..NUM_RAYS {
+            let ray = random.ray_towards(&aabbs);
+            assert_same_hit(&bvh, &aabbs, &ray);
+            let opt_hit = bvh.raycast(&ray);
+            assert!(opt_hit
+                .is_none_or(|hit| hit.object_idx != nan_idx && hit.object_idx != infinite_idx));
+        }
+        // A box that blows up after the build stops being hit, without spoiling its ancestors
+        let moved_idx = 10;
+        aabbs[moved_idx].max = Vec3::splat(f32::INFINITY);
+        bvh.set_object_aabb(moved_idx, aabbs[moved_idx]);
+        bvh.refit();
+        assert!(bvh.nodes.iter().all(|node| node.aabb.is_finite()));
+        for _ in 0..NUM_RAYS {
+            assert_same_hit(&bvh, &aabbs, &random.ray_towards(&aabbs));
+        }
+    }
+
+    #[test]
+    fn all_non_finite_boxes_build_an_empty_tree() {
+        let aabbs = vec![
+            Aabb {
+                min: Vec3::splat(f32::NEG_INFINITY),
+                max: Vec3::splat(f32::INFINITY),
+            };
+            8
+        ];
+        let bvh = Bvh::new(&aabbs);
+        assert_eq!(bvh.num_objects(), 8);
+        assert_eq!(bvh.raycast(&Ray::new(Vec3::zero(), Vec3::unit_x())), None);
+    }
+}
@@ -0,0 +1,509 @@
+use crate::*;
+use glam::*;
+use winit::event::{MouseButton, VirtualKeyCode};
+
+// Keep the orbit and look pitch slightly away from the poles, so that the
+// forward vector never becomes parallel to the up axis.
+const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+const MIN_FOCUS_RADIUS: f32 = 0.01;
+
+/* Conventions: The world is right-handed with +Y up. A camera looks down its
+local -Z axis, with +Y up and +X right. The view space that is fed to the
+projection matrix is Vulkan-style: +X right, +Y down, +Z forward. */
+#[derive(Copy, Clone, Debug)]
+pub struct Camera {
+    pub position: Vec3,
+    pub rotation: Quat, // Camera-to-world rotation
+    pub fov_y: f32,     // In radians
+    pub z_near: f32,
+    pub z_far: f32,
+}
+
+impl Camera {
+    pub fn new(position: Vec3, rotation: Quat) -> Camera {
+        Camera {
+            position,
+            rotation,
+            fov_y: 60.0_f32.to_radians(),
+            z_near: 0.01,
+            z_far: 100.0,
+        }
+    }
+
+    pub fn forward(&self) -> Vec3 {
+        self.rotation * -Vec3::unit_z()
+    }
+
+    pub fn right(&self) -> Vec3 {
+        self.rotation * Vec3::unit_x()
+    }
+
+    pub fn up(&self) -> Vec3 {
+        self.rotation * Vec3::unit_y()
+    }
+
+    pub fn world_to_view(&self) -> Mat4 {
+        Mat4::from_scale(Vec3::new(1.0, -1.0, -1.0))
+            * Mat4::from_rotation_translation(self.rotation, self.position).inverse()
+    }
+
+    pub fn view_to_clip(&self, aspect_ratio: f32) -> Mat4 {
+        Mat4::perspective_lh(self.fov_y, aspect_ratio, self.z_near, self.z_far)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CameraMode {
+    Orbit,
+    Fly,
+    FirstPerson,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum CameraController {
+    // Rotates around `target` with left-drag, pans with middle-drag and dollies with the scroll wheel
+    Orbit {
+        target: Vec3,
+        distance: f32,
+        yaw: f32,
+        pitch: f32,
+    },
+    // Free 6DOF movement with WASD/QE, accelerating and damping velocity. Looks with right-drag.
+    Fly {
+        position: Vec3,
+        velocity: Vec3,
+        yaw: f32,
+        pitch: f32,
+    },
+    // Walks on the horizontal plane with WASD. Looks with right-drag.
+    FirstPerson {
+        position: Vec3,
+        yaw: f32,
+        pitch: f32,
+    },
+}
+
+pub struct CameraSettings {
+    pub look_sensitivity: f32, // Radians per pixel of cursor movement
+    pub pan_sensitivity: f32,  // World units per pixel, per unit of orbit distance
+    pub dolly_speed: f32,      // Fraction of the orbit distance per scroll line
+    pub min_distance: f32,
+    pub max_distance: f32,
+    pub fly_acceleration: f32,
+    pub fly_damping: f32, // Exponential velocity decay rate, per second
+    pub walk_speed: f32,
+    pub fast_multiplier: f32,     // Applied to movement while shift is held
+    pub smoothing_sharpness: f32, // Higher is snappier. Zero disables smoothing.
+}
+
+impl CameraSettings {
+    pub fn new() -> CameraSettings {
+        CameraSettings {
+            look_sensitivity: 0.005,
+            pan_sensitivity: 0.002,
+            dolly_speed: 0.1,
+            min_distance: 0.05,
+            max_distance: 1000.0,
+            fly_acceleration: 20.0,
+            fly_damping: 6.0,
+            walk_speed: 3.0,
+            fast_multiplier: 4.0,
+            smoothing_sharpness: 15.0,
+        }
+    }
+}
+
+fn rotation_from_yaw_pitch(yaw: f32, pitch: f32) -> Quat {
+    Quat::from_rotation_y(yaw) * Quat::from_rotation_x(pitch)
+}
+
+/// Returns the movement direction from WASD (and QE for vertical movement),
+/// in camera-local coordinates.
+fn local_movement_direction(input: &InputState, allow_vertical: bool) -> Vec3 {
+    let mut dir = Vec3::zero();
+    let mut add_if_down = |key, v: Vec3| {
+        if input.is_key_down(key) {
+            dir += v;
+        }
+    };
+    add_if_down(VirtualKeyCode::W, -Vec3::unit_z());
+    add_if_down(VirtualKeyCode::S, Vec3::unit_z());
+    add_if_down(VirtualKeyCode::D, Vec3::unit_x());
+    add_if_down(VirtualKeyCode::A, -Vec3::unit_x());
+    if allow_vertical {
+        add_if_down(VirtualKeyCode::E, Vec3::unit_y());
+        add_if_down(VirtualKeyCode::Q, -Vec3::unit_y());
+    }
+    if dir.length_squared() > 0.0 {
+        dir.normalize()
+    } else {
+        dir
+    }
+}
+
+fn speed_multiplier(input: &InputState, settings: &CameraSettings) -> f32 {
+    if input.is_key_down(VirtualKeyCode::LShift) || input.is_key_down(VirtualKeyCode::RShift) {
+        settings.fast_multiplier
+    } else {
+        1.0
+    }
+}
+
+impl CameraController {
+    pub fn mode(&self) -> CameraMode {
+        match self {
+            CameraController::Orbit { .. } => CameraMode::Orbit,
+            CameraController::Fly { .. } => CameraMode::Fly,
+            CameraController::FirstPerson { .. } => CameraMode::FirstPerson,
+        }
+    }
+
+    /// Returns the unsmoothed (position, rotation) that the controller wants
+    /// the camera to be at.
+    pub fn transform(&self) -> (Vec3, Quat) {
+        match *self {
+            CameraController::Orbit {
+                target,
+                distance,
+                yaw,
+                pitch,
+            } => {
+                let rotation = rotation_from_yaw_pitch(yaw, pitch);
+                (target - (rotation * -Vec3::unit_z()) * distance, rotation)
+            }
+            CameraController::Fly {
+                position,
+                yaw,
+                pitch,
+                ..
+            }
+            | CameraController::FirstPerson {
+                position,
+                yaw,
+                pitch,
+            } => (position, rotation_from_yaw_pitch(yaw, pitch)),
+        }
+    }
+
+    pub fn update(&mut self, input: &InputState, settings: &CameraSettings, dt: f32) {
+        let look_delta = input.cursor_delta * settings.look_sensitivity;
+        match self {
+            CameraController::Orbit {
+                target,
+                distance,
+                yaw,
+                pitch,
+            } => {
+                if input.is_mouse_button_down(MouseButton::Left) {
+                    *yaw -= look_delta.x();
+                    *pitch = (*pitch - look_delta.y()).clamp(-MAX_PITCH, MAX_PITCH);
+                }
+                if input.is_mouse_button_down(MouseButton::Middle) {
+                    let rotation = rotation_from_yaw_pitch(*yaw, *pitch);
+                    let pan = input.cursor_delta * settings.pan_sensitivity * *distance;
+                    *target += rotation * Vec3::new(-pan.x(), pan.y(), 0.0);
+                }
+                if input.scroll_delta != 0.0 {
+                    *distance *= (-input.scroll_delta * settings.dolly_speed).exp();
+                }
+                *distance = distance.clamp(settings.min_distance, settings.max_distance);
+            }
+            CameraController::Fly {
+                position,
+                velocity,
+                yaw,
+                pitch,
+            } => {
+                if input.is_mouse_button_down(MouseButton::Right) {
+                    *yaw -= look_delta.x();
+                    *pitch = (*pitch - look_delta.y()).clamp(-MAX_PITCH, MAX_PITCH);
+                }
+                let rotation = rotation_from_yaw_pitch(*yaw, *pitch);
+                let acceleration = rotation
+                    * local_movement_direction(input, true)
+                    * settings.fly_acceleration
+                    * speed_multiplier(input, settings);
+                *velocity += acceleration * dt;
+                *velocity *= (-settings.fly_damping * dt).exp();
+                *position += *velocity * dt;
+            }
+            CameraController::FirstPerson {
+                position,
+                yaw,
+                pitch,
+            } => {
+                if input.is_mouse_button_down(MouseButton::Right) {
+                    *yaw -= look_delta.x();
+                    *pitch = (*pitch - look_delta.y()).clamp(-MAX_PITCH, MAX_PITCH);
+                }
+                // Only yaw affects the walking direction, so looking up doesn't make you fly
+                let walk = Quat::from_rotation_y(*yaw)
+                    * local_movement_direction(input, false)
+                    * settings.walk_speed
+                    * speed_multiplier(input, settings);
+                *position += walk * dt;
+            }
+        }
+    }
+}
+
+/// Owns a camera controller and a smoothed camera that follows it. Switching
+/// modes and focusing on objects only change the controller, and the camera
+/// interpolates towards it, so there are no jarring snaps.
+pub struct CameraRig {
+    pub controller: CameraController,
+    pub settings: CameraSettings,
+    pub camera: Camera,
+    last_orbit_distance: f32, // Used when switching back into orbit mode
+}
+
+impl CameraRig {
+    pub fn new_orbit(target: Vec3, distance: f32, yaw: f32, pitch: f32) -> CameraRig {
+        let controller = CameraController::Orbit {
+            target,
+            distance,
+            yaw,
+            pitch,
+        };
+        let (position, rotation) = controller.transform();
+        CameraRig {
+            controller,
+            settings: CameraSettings::new(),
+            camera: Camera::new(position, rotation),
+            last_orbit_distance: distance,
+        }
+    }
+
+    pub fn new_fly(position: Vec3, yaw: f32, pitch: f32) -> CameraRig {
+        let mut rig = CameraRig::new_orbit(Vec3::zero(), 5.0, yaw, pitch);
+        rig.controller = CameraController::Fly {
+            position,
+            velocity: Vec3::zero(),
+            yaw,
+            pitch,
+        };
+        rig.camera.position = position;
+        rig
+    }
+
+    pub fn mode(&self) -> CameraMode {
+        self.controller.mode()
+    }
+
+    fn yaw_pitch(&self) -> (f32, f32) {
+        match self.controller {
+            CameraController::Orbit { yaw, pitch, .. }
+            | CameraController::Fly { yaw, pitch, .. }
+            | CameraController::FirstPerson { yaw, pitch, .. } => (yaw, pitch),
+        }
+    }
+
+    /// Switch modes, starting the new controller where the old one was looking from.
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        if mode == self.mode() {
+            return;
+        }
+        let (position, rotation) = self.controller.transform();
+        let (yaw, pitch) = self.yaw_pitch();
+        if let CameraController::Orbit { distance, .. } = self.controller {
+            self.last_orbit_distance = distance;
+        }
+        self.controller = match mode {
+            CameraMode::Orbit => CameraController::Orbit {
+                target: position + (rotation * -Vec3::unit_z()) * self.last_orbit_distance,
+                distance: self.last_orbit_distance,
+                yaw,
+                pitch,
+            },
+            CameraMode::Fly => CameraController::Fly {
+                position,
+                velocity: Vec3::zero(),
+                yaw,
+                pitch,
+            },
+            CameraMode::FirstPerson => CameraController::FirstPerson {
+                position,
+                yaw,
+                pitch,
+            },
+        };
+    }
+
+    /// Move the controller so that the bounding sphere of `aabb` fills the view.
+    /// Degenerate boxes (empty, or with non-finite coordinates) are ignored.
+    pub fn focus_on(&mut self, aabb: &Aabb) {
+        if aabb.is_empty() || !aabb.is_finite() {
+            return;
+        }
+        let center = aabb.center();
+        let radius = aabb.bounding_radius().max(MIN_FOCUS_RADIUS);
+        let fit_distance = (radius / (self.camera.fov_y * 0.5).sin())
+            .clamp(self.settings.min_distance, self.settings.max_distance);
+        let (_, rotation) = self.controller.transform();
+        let forward = rotation * -Vec3::unit_z();
+        match &mut self.controller {
+            CameraController::Orbit {
+                target, distance, ..
+            } => {
+                *target = center;
+                *distance = fit_distance;
+            }
+            CameraController::Fly {
+                position, velocity, ..
+            } => {
+                *position = center - forward * fit_distance;
+                *velocity = Vec3::zero();
+            }
+            CameraController::FirstPerson { position, .. } => {
+                *position = center - forward * fit_distance;
+            }
+        }
+    }
+
+    pub fn update(&mut self, input: &InputState, dt: f32) -> &Camera {
+        self.controller.update(input, &self.settings, dt);
+        let (position, mut rotation) = self.controller.transform();
+
+        let t = if self.settings.smoothing_sharpness > 0.0 && dt > 0.0 {
+            1.0 - (-self.settings.smoothing_sharpness * dt).exp()
+        } else {
+            1.0
+        };
+        // Slerp the short way around
+        if self.camera.rotation.dot(rotation) < 0.0 {
+            rotation = Quat::from_xyzw(-rotation.x(), -rotation.y(), -rotation.z(), -rotation.w());
+        }
+        self.camera.position = self.camera.position.lerp(position, t);
+        self.camera.rotation = self.camera.rotation.slerp(rotation, t).normalize();
+
+        &self.camera
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::event::{DeviceId, ElementState, WindowEvent};
+
+    #[allow(deprecated)] // `modifiers` has to be set, although winit deprecates it
+    fn input_with_button_down(button: MouseButton) -> InputState {
+        let mut input = InputState::new();
+        input.handle_window_event(&WindowEvent::MouseInput {
+            device_id: unsafe { DeviceId::dummy() },
+            state: ElementState::Pressed,
+            button,
+            modifiers: Default::default(),
+        });
+        input
+    }
+
+    fn pitch(controller: &CameraController) -> f32 {
+        match *controller {
+            CameraController::Orbit { pitch, .. }
+            | CameraController::Fly { pitch, .. }
+            | CameraController::FirstPerson { pitch, .. } => pitch,
+        }
+    }
+
+    fn orbit_distance(controller: &CameraController) -> f32 {
+        match *controller {
+            CameraController::Orbit { distance, .. } => distance,
+            _ => panic!("Not an orbit controller."),
+        }
+    }
+
+    fn is_vec_finite(v: Vec3) -> bool {
+        v.x().is_finite() && v.y().is_finite() && v.z().is_finite()
+    }
+
+    #[test]
+    fn pitch_is_clamped_at_the_poles() {
+        let settings = CameraSettings::new();
+        let mut controllers = [
+            (
+                CameraRig::new_orbit(Vec3::zero(), 5.0, 0.0, 0.0).controller,
+                MouseButton::Left,
+            ),
+            (
+                CameraRig::new_fly(Vec3::zero(), 0.0, 0.0).controller,
+                MouseButton::Right,
+            ),
+            (
+                CameraController::FirstPerson {
+                    position: Vec3::zero(),
+                    yaw: 0.0,
+                    pitch: 0.0,
+                },
+                MouseButton::Right,
+            ),
+        ];
+        for (controller, button) in controllers.iter_mut() {
+            let mut input = input_with_button_down(*button);
+            for &(cursor_dy, expected_pitch) in &[(-1.0e6, MAX_PITCH), (1.0e6, -MAX_PITCH)] {
+                input.cursor_delta = Vec2::new(0.0, cursor_dy);
+                controller.update(&input, &settings, 1.0 / 60.0);
+                assert_eq!(pitch(controller), expected_pitch, "{:?}", controller.mode());
+
+                // The forward vector must not line up with the up axis
+                let (position, rotation) = controller.transform();
+                let forward = rotation * -Vec3::unit_z();
+                assert!(is_vec_finite(position));
+                assert!(forward.cross(Vec3::unit_y()).length() > 1.0e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn dolly_is_clamped_near_zero_distance() {
+        let settings = CameraSettings::new();
+        let mut controller = CameraRig::new_orbit(Vec3::zero(), 5.0, 0.0, 0.0).controller;
+        let mut input = InputState::new();
+
+        // Scrolling in this far underflows the distance to zero before clamping
+        input.scroll_delta = 1.0e4;
+        controller.update(&input, &settings, 1.0 / 60.0);
+        assert_eq!(orbit_distance(&controller), settings.min_distance);
+        let (position, rotation) = controller.transform();
+        assert!(is_vec_finite(position));
+        assert!((rotation * -Vec3::unit_z()).is_normalized());
+
+        input.scroll_delta = -1.0e4;
+        controller.update(&input, &settings, 1.0 / 60.0);
+        assert_eq!(orbit_distance(&controller), settings.max_distance);
+    }
+
+    #[test]
+    fn focus_on_ignores_degenerate_boxes() {
+        let mut rig = CameraRig::new_orbit(Vec3::new(1.0, 2.0, 3.0), 5.0, 0.3, 0.2);
+        let before = rig.controller.transform();
+        let nan = Vec3::new(f32::NAN, 0.0, 0.0);
+        let degenerate_boxes = [
+            Aabb::empty(),
+            Aabb {
+                min: nan,
+                max: Vec3::one(),
+            },
+            Aabb {
+                min: -Vec3::one(),
+                max: Vec3::splat(f32::INFINITY),
+            },
+        ];
+        for aabb in &degenerate_boxes {
+            rig.focus_on(aabb);
+            assert_eq!(rig.controller.transform(), before, "{:?}", aabb);
+        }
+    }
+
+    #[test]
+    fn focus_on_a_point_keeps_a_finite_distance() {
+        let settings = CameraSettings::new();
+        let point = Vec3::new(4.0, -1.0, 2.0);
+        let mut rig = CameraRig::new_orbit(Vec3::zero(), 5.0, 0.0, 0.0);
+        rig.focus_on(&Aabb::from_points(vec![point]));
+        let distance = orbit_distance(&rig.controller);
+        assert!(distance >= settings.min_distance && distance.is_finite());
+        let (position, _) = rig.controller.transform();
+        assert!(is_vec_finite(position));
+        assert!((position - point).length() > 0.0);
+    }
+}
@@ -0,0 +1,151 @@
+use glam::*;
+
+#[derive(Copy, Clone, Debug)]
+pub struct CameraKeyframe {
+    pub seconds: f32,
+    pub position: Vec3,
+    pub rotation: Quat, // Camera-to-world, like `Camera::rotation`
+}
+
+/* A keyframed camera path, for flying through a scene the same way on every
+run. Positions are interpolated with a Catmull-Rom spline through the
+keyframes, and rotations with slerp.
+
+Paths are stored as text, one keyframe per line:
+`seconds position.x position.y position.z rotation.x rotation.y rotation.z rotation.w`.
+Empty lines and lines starting with `#` are ignored. */
+pub struct CameraPath {
+    pub keyframes: Vec<CameraKeyframe>, // Sorted by time
+}
+
+impl CameraPath {
+    pub fn new() -> CameraPath {
+        CameraPath {
+            keyframes: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<CameraPath, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read camera path `{}`: {}", path, e))?;
+        let mut keyframes = Vec::new();
+        for (line_idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values: Vec<f32> = line
+                .split_whitespace()
+                .map(|value| value.parse::<f32>())
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("{}:{}: {}", path, line_idx + 1, e))?;
+            if values.len() != 8 {
+                return Err(format!(
+                    "{}:{}: Expected 8 values per keyframe, found {}.",
+                    path,
+                    line_idx + 1,
+                    values.len()
+                ));
+            }
+            keyframes.push(CameraKeyframe {
+                seconds: values[0],
+                position: Vec3::new(values[1], values[2], values[3]),
+                rotation: Quat::from_xyzw(values[4], values[5], values[6], values[7]).normalize(),
+            });
+        }
+        if keyframes.is_empty() {
+            return Err(format!("Camera path `{}` has no keyframes.", path));
+        }
+        keyframes.sort_by(|a, b| a.seconds.partial_cmp(&b.seconds).unwrap());
+        Ok(CameraPath { keyframes })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let mut text = String::from("# seconds position.xyz rotation.xyzw\n");
+        for keyframe in &self.keyframes {
+            let p = keyframe.position;
+            let r = keyframe.rotation;
+            text.push_str(&format!(
+                "{} {} {} {} {} {} {} {}\n",
+                keyframe.seconds,
+                p.x(),
+                p.y(),
+                p.z(),
+                r.x(),
+                r.y(),
+                r.z(),
+                r.w()
+            ));
+        }
+        std::fs::write(path, text)
+            .map_err(|e| format!("Couldn't write camera path `{}`: {}", path, e))
+    }
+
+    pub fn add_keyframe(&mut self, seconds: f32, position: Vec3, rotation: Quat) {
+        self.keyframes.push(CameraKeyframe {
+            seconds,
+            position,
+            rotation,
+        });
+    }
+
+    pub fn duration(&self) -> f32 {
+        match (self.keyframes.first(), self.keyframes.last()) {
+            (Some(first), Some(last)) => last.seconds - first.seconds,
+            _ => 0.0,
+        }
+    }
+
+    /// Position and rotation at `seconds`, held at the ends of the path.
+    pub fn sample(&self, seconds: f32) -> (Vec3, Quat) {
+        let keyframes = &self.keyframes;
+        assert!(!keyframes.is_empty(), "Sampled an empty camera path.");
+        let last_idx = keyframes.len() - 1;
+        if keyframes.len() == 1 || seconds <= keyframes[0].seconds {
+            return (keyframes[0].position, keyframes[0].rotation);
+        }
+        if seconds >= keyframes[last_idx].seconds {
+            return (keyframes[last_idx].position, keyframes[last_idx].rotation);
+        }
+
+        let idx = keyframes
+            .windows(2)
+            .position(|pair| seconds < pair[1].seconds)
+            .unwrap();
+        let k1 = &keyframes[idx];
+        let k2 = &keyframes[idx + 1];
+        let segment_duration = k2.seconds - k1.seconds;
+        let t = if segment_duration > 0.0 {
+            (seconds - k1.seconds) / segment_duration
+        } else {
+            0.0
+        };
+        // The ends of the path reuse the end keyframes as their outer control points
+        let p0 = keyframes[idx.saturating_sub(1)].position;
+        let p3 = keyframes[(idx + 2).min(last_idx)].position;
+        let position = catmull_rom(p0, k1.position, k2.position, p3, t);
+
+        // Take the short way around
+        let end_rotation = if k1.rotation.dot(k2.rotation) < 0.0 {
+            Quat::from_xyzw(
+                -k2.rotation.x(),
+                -k2.rotation.y(),
+                -k2.rotation.z(),
+                -k2.rotation.w(),
+            )
+        } else {
+            k2.rotation
+        };
+        let rotation = k1.rotation.slerp(end_rotation, t).normalize();
+        (position, rotation)
+    }
+}
+
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * ((2.0 * p1)
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
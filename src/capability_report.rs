@@ -0,0 +1,284 @@
+use crate::*;
+
+const CAPABILITY_REPORT_PATH: &str = "_cache/capabilities.json";
+
+pub struct QueueFamilyReport {
+    pub flags: String,
+    pub queue_count: u32,
+    pub is_graphics_queue: bool, // The family that we submit graphics work to
+    pub is_present_queue: bool,
+    pub is_transfer_queue: bool, // The family that uploads are submitted to
+}
+
+pub struct MemoryHeapReport {
+    pub size_bytes: u64,
+    pub is_device_local: bool,
+}
+
+/* What the device and the surface support, and what we actually use out of
+it. Built once at startup, so that it can be attached to bug reports without
+touching Vulkan again. */
+pub struct CapabilityReport {
+    pub device_name: String,
+    pub device_type: String,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub driver_version: String,
+    pub api_version: String,
+    pub enabled_exts: Vec<String>,
+    pub enabled_features: Vec<String>,
+    pub queue_families: Vec<QueueFamilyReport>,
+    pub surface_formats: Vec<String>,
+    pub active_surface_format: String,
+    pub present_modes: Vec<String>,
+    pub active_present_mode: String,
+    pub memory_heaps: Vec<MemoryHeapReport>,
+    // Limits
+    pub max_image_dimension_2d: u32,
+    pub max_image_dimension_3d: u32,
+    pub min_uniform_buffer_offset_alignment: u64,
+    pub max_per_stage_descriptor_samplers: u32,
+    pub max_sampler_anisotropy: f32,
+}
+
+impl CapabilityReport {
+    pub fn new(gpu: &Gpu, facade: &Facade) -> CapabilityReport {
+        let properties = &gpu.properties;
+        let limits = &properties.limits;
+        let format_to_string =
+            |f: &vk::SurfaceFormatKHR| format!("{:?} {:?}", f.format, f.color_space);
+
+        let features = &gpu.enabled_features;
+        let enabled_features = [
+            ("sampler_anisotropy", features.sampler_anisotropy),
+            (
+                "fragment_stores_and_atomics",
+                features.fragment_stores_and_atomics,
+            ),
+            ("fill_mode_non_solid", features.fill_mode_non_solid),
+            ("geometry_shader", features.geometry_shader),
+            ("tessellation_shader", features.tessellation_shader),
+            ("multi_draw_indirect", features.multi_draw_indirect),
+            ("shader_int64", features.shader_int64),
+            ("texture_compression_bc", features.texture_compression_bc),
+        ]
+        .iter()
+        .filter(|(_, is_enabled)| *is_enabled == vk::TRUE)
+        .map(|(name, _)| String::from(*name))
+        .collect();
+
+        CapabilityReport {
+            device_name: vk_to_string(&properties.device_name),
+            device_type: format!("{:?}", properties.device_type),
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            driver_version: version_to_string(properties.vendor_id, properties.driver_version),
+            api_version: version_to_string(0, properties.api_version),
+            enabled_exts: gpu.enabled_exts.clone(),
+            enabled_features,
+            queue_families: gpu
+                .queue_family_properties
+                .iter()
+                .enumerate()
+                .map(|(i, family)| QueueFamilyReport {
+                    flags: format!("{:?}", family.queue_flags),
+                    queue_count: family.queue_count,
+                    is_graphics_queue: i as u32 == gpu.graphics_queue_idx,
+                    is_present_queue: i as u32 == gpu.present_queue_idx,
+                    is_transfer_queue: i as u32 == gpu.transfer_queue_idx,
+                })
+                .collect(),
+            surface_formats: facade
+                .surface_formats
+                .iter()
+                .map(format_to_string)
+                .collect(),
+            active_surface_format: format_to_string(&facade.surface_format),
+            present_modes: gpu
+                .present_modes
+                .iter()
+                .map(|mode| format!("{:?}", mode))
+                .collect(),
+            active_present_mode: format!("{:?}", facade.present_mode),
+            memory_heaps: gpu.memory_properties.memory_heaps
+                [..gpu.memory_properties.memory_heap_count as usize]
+                .iter()
+                .map(|heap| MemoryHeapReport {
+                    size_bytes: heap.size,
+                    is_device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+                })
+                .collect(),
+            max_image_dimension_2d: limits.max_image_dimension2_d,
+            max_image_dimension_3d: limits.max_image_dimension3_d,
+            min_uniform_buffer_offset_alignment: limits.min_uniform_buffer_offset_alignment,
+            max_per_stage_descriptor_samplers: limits.max_per_stage_descriptor_samplers,
+            max_sampler_anisotropy: limits.max_sampler_anisotropy,
+        }
+    }
+
+    /// Human-readable listing. Active entries are marked with `*`.
+    pub fn to_text(&self) -> String {
+        let mark = |is_active: bool| if is_active { "*" } else { " " };
+        let mut lines = vec![
+            format!("Device: {} ({})", self.device_name, self.device_type),
+            format!("Id: {:04x}:{:04x}", self.vendor_id, self.device_id),
+            format!(
+                "Driver: {}, Vulkan: {}",
+                self.driver_version, self.api_version
+            ),
+            format!("Enabled extensions: {}", self.enabled_exts.join(", ")),
+            format!("Enabled features: {}", self.enabled_features.join(", ")),
+            String::from("Queue families:"),
+        ];
+        for (i, family) in self.queue_families.iter().enumerate() {
+            lines.push(format!(
+                " {} {}: {} x{}",
+                mark(
+                    family.is_graphics_queue || family.is_present_queue || family.is_transfer_queue
+                ),
+                i,
+                family.flags,
+                family.queue_count
+            ));
+        }
+        lines.push(String::from("Surface formats:"));
+        for format in &self.surface_formats {
+            lines.push(format!(
+                " {} {}",
+                mark(*format == self.active_surface_format),
+                format
+            ));
+        }
+        lines.push(String::from("Present modes:"));
+        for mode in &self.present_modes {
+            lines.push(format!(
+                " {} {}",
+                mark(*mode == self.active_present_mode),
+                mode
+            ));
+        }
+        lines.push(String::from("Memory heaps:"));
+        for (i, heap) in self.memory_heaps.iter().enumerate() {
+            let mut line = format!("   {}: ", i);
+            write_bytes(&mut line, heap.size_bytes, 0);
+            if heap.is_device_local {
+                line.push_str(" (device local)");
+            }
+            lines.push(line);
+        }
+        lines.push(format!(
+            "Limits: max image 2D {}, max image 3D {}, UBO offset alignment {}, max samplers per stage {}, max anisotropy {}",
+            self.max_image_dimension_2d,
+            self.max_image_dimension_3d,
+            self.min_uniform_buffer_offset_alignment,
+            self.max_per_stage_descriptor_samplers,
+            self.max_sampler_anisotropy,
+        ));
+        lines.join("\n")
+    }
+
+    pub fn to_json(&self) -> String {
+        let string_list = |list: &[String]| {
+            let items: Vec<String> = list.iter().map(|s| json_string(s)).collect();
+            format!("[{}]", items.join(", "))
+        };
+        let queue_families: Vec<String> = self
+            .queue_families
+            .iter()
+            .map(|family| {
+                format!(
+                    "{{\"flags\": {}, \"queue_count\": {}, \"is_graphics_queue\": {}, \"is_present_queue\": {}, \"is_transfer_queue\": {}}}",
+                    json_string(&family.flags),
+                    family.queue_count,
+                    family.is_graphics_queue,
+                    family.is_present_queue,
+                    family.is_transfer_queue
+                )
+            })
+            .collect();
+        let memory_heaps: Vec<String> = self
+            .memory_heaps
+            .iter()
+            .map(|heap| {
+                format!(
+                    "{{\"size_bytes\": {}, \"is_device_local\": {}}}",
+                    heap.size_bytes, heap.is_device_local
+                )
+            })
+            .collect();
+        let fields = [
+            ("device_name", json_string(&self.device_name)),
+            ("device_type", json_string(&self.device_type)),
+            ("vendor_id", self.vendor_id.to_string()),
+            ("device_id", self.device_id.to_string()),
+            ("driver_version", json_string(&self.driver_version)),
+            ("api_version", json_string(&self.api_version)),
+            ("enabled_exts", string_list(&self.enabled_exts)),
+            ("enabled_features", string_list(&self.enabled_features)),
+            ("queue_families", format!("[{}]", queue_families.join(", "))),
+            ("surface_formats", string_list(&self.surface_formats)),
+            (
+                "active_surface_format",
+                json_string(&self.active_surface_format),
+            ),
+            ("present_modes", string_list(&self.present_modes)),
+            (
+                "active_present_mode",
+                json_string(&self.active_present_mode),
+            ),
+            ("memory_heaps", format!("[{}]", memory_heaps.join(", "))),
+            (
+                "max_image_dimension_2d",
+                self.max_image_dimension_2d.to_string(),
+            ),
+            (
+                "max_image_dimension_3d",
+                self.max_image_dimension_3d.to_string(),
+            ),
+            (
+                "min_uniform_buffer_offset_alignment",
+                self.min_uniform_buffer_offset_alignment.to_string(),
+            ),
+            (
+                "max_per_stage_descriptor_samplers",
+                self.max_per_stage_descriptor_samplers.to_string(),
+            ),
+            (
+                "max_sampler_anisotropy",
+                self.max_sampler_anisotropy.to_string(),
+            ),
+        ];
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|(key, value)| format!("  \"{}\": {}", key, value))
+            .collect();
+        format!("{{\n{}\n}}\n", fields.join(",\n"))
+    }
+
+    /// Write the JSON report to the _cache directory, and return its path.
+    pub fn save_json(&self) -> Result<&'static str, String> {
+        std::fs::create_dir_all("_cache")
+            .map_err(|e| format!("Couldn't create the cache directory: {}", e))?;
+        std::fs::write(CAPABILITY_REPORT_PATH, self.to_json()).map_err(|e| {
+            format!(
+                "Couldn't write capability report `{}`: {}",
+                CAPABILITY_REPORT_PATH, e
+            )
+        })?;
+        Ok(CAPABILITY_REPORT_PATH)
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
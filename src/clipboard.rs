@@ -0,0 +1,215 @@
+use crate::*;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const FALLBACK_FILE_NAME: &str = "graphene_report.txt";
+
+pub enum CopyDestination {
+    Clipboard,
+    File(std::path::PathBuf), // When there's no clipboard, like over SSH or in CI
+}
+
+/* Put text on the system clipboard, through the tool that ships with each
+platform: `clip` on Windows, `pbcopy` on macOS, and `wl-copy`, `xclip` or
+`xsel` on Linux, depending on the display server. The tool keeps serving the
+text after it's copied, so it stays pasteable once the app exits. If none of
+them work, the text is written to a file in the temp directory instead. */
+pub fn copy_text(text: &str) -> Result<CopyDestination, String> {
+    for (program, args) in clipboard_commands() {
+        if pipe_to(program, args, text) {
+            return Ok(CopyDestination::Clipboard);
+        }
+    }
+    let path = std::env::temp_dir().join(FALLBACK_FILE_NAME);
+    std::fs::write(&path, text)
+        .map_err(|e| format!("Couldn't write `{}`: {}", path.display(), e))?;
+    Ok(CopyDestination::File(path))
+}
+
+/* Lays out the diagnostics that F4 copies. Each section has a heading line,
+and sections are separated by a blank line. Sections with nothing to report
+say "None", so that a pasted report shows they weren't left out. */
+pub(crate) fn format_diagnostics(
+    capabilities: &str,
+    num_validation_errors: usize,
+    validation_messages: &[String],
+    opt_frame_times: Option<&FrameTimeSummary>,
+    memory: &str,
+) -> String {
+    let mut text = String::from("Capabilities\n");
+    text.push_str(capabilities);
+
+    text.push_str(&format!(
+        "\n\nValidation messages ({} errors in total)\n",
+        num_validation_errors
+    ));
+    if validation_messages.is_empty() {
+        text.push_str("None\n");
+    }
+    for message in validation_messages {
+        text.push_str(message);
+        text.push('\n');
+    }
+
+    text.push_str("\nFrame times\n");
+    match opt_frame_times {
+        Some(summary) => text.push_str(&summary.to_text()),
+        None => text.push_str("None"),
+    }
+
+    text.push_str("\n\nMemory\n");
+    text.push_str(memory);
+    text
+}
+
+#[cfg(target_os = "windows")]
+fn clipboard_commands() -> Vec<(&'static str, &'static [&'static str])> {
+    vec![("clip", &[])]
+}
+
+#[cfg(target_os = "macos")]
+fn clipboard_commands() -> Vec<(&'static str, &'static [&'static str])> {
+    vec![("pbcopy", &[])]
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn clipboard_commands() -> Vec<(&'static str, &'static [&'static str])> {
+    let mut commands: Vec<(&'static str, &'static [&'static str])> = Vec::new();
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        commands.push(("wl-copy", &[]));
+    }
+    // XWayland serves X11 clipboards under Wayland too
+    if std::env::var_os("DISPLAY").is_some() {
+        commands.push(("xclip", &["-selection", "clipboard"]));
+        commands.push(("xsel", &["--clipboard", "--input"]));
+    }
+    commands
+}
+
+// False if the program is missing or fails, e.g. without a display to talk to
+fn pipe_to(program: &str, args: &[&str], text: &str) -> bool {
+    let mut child = match Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(_) => return false,
+    };
+    let is_written = match child.stdin.take() {
+        Some(mut stdin) => stdin.write_all(text.as_bytes()).is_ok(),
+        None => false,
+    };
+    // Dropping stdin closes it, so the program sees the end of the text
+    is_written && child.wait().is_ok_and(|status| status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // Splits a report back into its headings and section texts
+    fn parse_sections(text: &str) -> Vec<(String, String)> {
+        let is_heading = |line: &str| {
+            ["Capabilities", "Frame times", "Memory"].contains(&line)
+                || (line.starts_with("Validation messages (")
+                    && line.ends_with(" errors in total)"))
+        };
+        let mut sections: Vec<(String, String)> = Vec::new();
+        let mut previous_line = "";
+        for line in text.split('\n') {
+            if previous_line.is_empty() && is_heading(line) {
+                if let Some((_, body)) = sections.last_mut() {
+                    // Drop the blank line that separates sections
+                    body.truncate(body.len() - 2);
+                }
+                sections.push((String::from(line), String::new()));
+            } else if let Some((_, body)) = sections.last_mut() {
+                body.push_str(line);
+                body.push('\n');
+            }
+            previous_line = line;
+        }
+        if let Some((_, body)) = sections.last_mut() {
+            body.pop();
+        }
+        sections
+    }
+
+    fn sample_memory_report() -> MemoryReport {
+        MemoryReport {
+            allocations: vec![AllocationInfo {
+                name: String::from("image_depth"),
+                size: 8 << 20,
+                memory_type_idx: 1,
+                heap_idx: 0,
+                is_host_visible: false,
+            }],
+            heaps: vec![HeapStats {
+                heap_idx: 0,
+                heap_size: 4 << 30,
+                is_device_local: true,
+                num_blocks: 1,
+                block_bytes: 256 << 20,
+                used_bytes: 8 << 20,
+                num_allocations: 1,
+            }],
+        }
+    }
+
+    #[test]
+    fn sections_round_trip() {
+        let capabilities = "Device: Test GPU (Discrete)\nQueue families:\n * 0: GRAPHICS x1";
+        let messages = vec![
+            String::from("[Error][Validation] The first"),
+            String::from("[Warning][Performance] The second"),
+        ];
+        let frame_times = FrameTimeSummary::new(&[
+            Duration::from_millis(16),
+            Duration::from_millis(17),
+            Duration::from_millis(33),
+        ])
+        .unwrap();
+        let memory = sample_memory_report().to_string();
+        let text = format_diagnostics(capabilities, 3, &messages, Some(&frame_times), &memory);
+
+        let sections = parse_sections(&text);
+        let headings: Vec<&str> = sections.iter().map(|(h, _)| h.as_str()).collect();
+        assert_eq!(
+            headings,
+            [
+                "Capabilities",
+                "Validation messages (3 errors in total)",
+                "Frame times",
+                "Memory"
+            ]
+        );
+        assert_eq!(sections[0].1, capabilities);
+        assert_eq!(sections[1].1, messages.join("\n"));
+        assert_eq!(sections[2].1, frame_times.to_text());
+        assert_eq!(sections[3].1, memory);
+    }
+
+    #[test]
+    fn empty_sections_say_none() {
+        let text = format_diagnostics("Device: Test GPU (Discrete)", 0, &[], None, "");
+        let sections = parse_sections(&text);
+        assert_eq!(sections.len(), 4);
+        assert_eq!(sections[1].0, "Validation messages (0 errors in total)");
+        assert_eq!(sections[1].1, "None");
+        assert_eq!(sections[2].1, "None");
+        assert_eq!(sections[3].1, "");
+    }
+
+    #[test]
+    fn the_memory_report_lists_heaps_and_allocations() {
+        let memory = sample_memory_report().to_string();
+        let text = format_diagnostics("", 0, &[], None, &memory);
+        assert!(text.ends_with(&memory));
+        assert!(memory.starts_with("Heap 0 (device local): 8.0 MiB used"));
+        assert!(memory.lines().any(|line| line.starts_with("image_depth")));
+    }
+}
@@ -0,0 +1,167 @@
+use crate::*;
+use glam::*;
+
+/* Colors are always stored in linear space, and all colors that reach the
+shaders are linear. Colors authored in sRGB (color pickers, hex codes, 8-bit
+image data) are converted once, at the API boundary, by `from_srgb8()`.
+
+This matters for clear values in particular. Unlike shader outputs, clear
+values are written raw, so with an sRGB attachment the hardware encodes the
+linear clear color to sRGB, landing on the intended authored value. */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+// Colors are used as keys when hashing builder passes
+impl Hash for Color {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.r.to_bits().hash(state);
+        self.g.to_bits().hash(state);
+        self.b.to_bits().hash(state);
+        self.a.to_bits().hash(state);
+    }
+}
+
+impl Color {
+    pub const BLACK: Color = Color::from_linear_f32(0.0, 0.0, 0.0, 1.0);
+    pub const WHITE: Color = Color::from_linear_f32(1.0, 1.0, 1.0, 1.0);
+    pub const TRANSPARENT: Color = Color::from_linear_f32(0.0, 0.0, 0.0, 0.0);
+
+    pub const fn from_linear_f32(r: f32, g: f32, b: f32, a: f32) -> Color {
+        Color { r, g, b, a }
+    }
+
+    /// Opaque color from 8-bit sRGB-encoded channels.
+    pub fn from_srgb8(r: u8, g: u8, b: u8) -> Color {
+        Color::from_srgba8(r, g, b, 255)
+    }
+
+    /// Alpha is linear even in sRGB formats, so it is not converted.
+    pub fn from_srgba8(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color {
+            r: srgb_to_linear(r as f32 / 255.0),
+            g: srgb_to_linear(g as f32 / 255.0),
+            b: srgb_to_linear(b as f32 / 255.0),
+            a: a as f32 / 255.0,
+        }
+    }
+
+    pub fn to_srgba8(&self) -> [u8; 4] {
+        let quantize = |x: f32| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+        [
+            quantize(linear_to_srgb(self.r)),
+            quantize(linear_to_srgb(self.g)),
+            quantize(linear_to_srgb(self.b)),
+            quantize(self.a),
+        ]
+    }
+
+    pub fn to_linear_f32(&self) -> [f32; 4] {
+        [self.r, self.g, self.b, self.a]
+    }
+
+    pub fn to_vec4(&self) -> Vec4 {
+        Vec4::new(self.r, self.g, self.b, self.a)
+    }
+
+    pub fn to_clear_value(&self) -> vk::ClearValue {
+        vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: self.to_linear_f32(),
+            },
+        }
+    }
+}
+
+/// The exact piecewise sRGB transfer function, not the 2.2 gamma approximation.
+pub fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(x: f32) -> f32 {
+    if x <= 0.003_130_8 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} isn't close to {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn srgb_to_linear_reference_values() {
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert_close(srgb_to_linear(0.04045), 0.003_130_805);
+        assert_close(srgb_to_linear(0.5), 0.214_041_14);
+        assert_close(srgb_to_linear(1.0), 1.0);
+    }
+
+    #[test]
+    fn linear_to_srgb_reference_values() {
+        assert_eq!(linear_to_srgb(0.0), 0.0);
+        assert_close(linear_to_srgb(0.003_130_8), 0.040_449_94);
+        assert_close(linear_to_srgb(0.214_041_14), 0.5);
+        assert_close(linear_to_srgb(0.5), 0.735_356_6);
+        assert_close(linear_to_srgb(1.0), 1.0);
+    }
+
+    #[test]
+    fn the_knee_is_continuous() {
+        let below = srgb_to_linear(0.04045);
+        let above = srgb_to_linear(0.040_451);
+        assert!(above > below && above - below < 1e-6);
+        let below = linear_to_srgb(0.003_130_8);
+        let above = linear_to_srgb(0.003_130_9);
+        assert!(above > below && above - below < 1e-5);
+    }
+
+    #[test]
+    fn from_srgb8_reference_values() {
+        let color = Color::from_srgb8(0, 128, 255);
+        assert_eq!(color.r, 0.0);
+        assert_close(color.g, 0.215_860_5);
+        assert_close(color.b, 1.0);
+        assert_eq!(color.a, 1.0);
+        // Alpha isn't converted
+        assert_close(Color::from_srgba8(0, 0, 0, 128).a, 128.0 / 255.0);
+    }
+
+    #[test]
+    fn to_srgba8_rounds_and_clamps() {
+        assert_eq!(
+            Color::from_linear_f32(0.0, 0.5, 1.0, 0.5).to_srgba8(),
+            [0, 188, 255, 128]
+        );
+        assert_eq!(
+            Color::from_linear_f32(-1.0, 2.0, f32::INFINITY, 1.5).to_srgba8(),
+            [0, 255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn every_8_bit_value_round_trips() {
+        for value in 0..=255_u8 {
+            let color = Color::from_srgba8(value, value, value, value);
+            assert_eq!(color.to_srgba8(), [value; 4]);
+        }
+    }
+}
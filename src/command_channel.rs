@@ -0,0 +1,82 @@
+use crate::*;
+use std::sync::mpsc;
+
+/// Structural changes that can be requested from anywhere, and are applied by
+/// the context at a single point in the frame.
+pub enum ContextCommand {
+    LoadImage {
+        name: String,
+        path: String,
+        receipt: mpsc::Sender<Result<ImageHandle, String>>,
+    },
+    SetWindowSize {
+        width: u32,
+        height: u32,
+    },
+    SetPassFlag {
+        flag: PassFlagHandle,
+        is_set: bool,
+    },
+}
+
+/* Cheap to clone and safe to send to other threads. Commands are applied in
+the order that they were sent, in `begin_frame()`, once the frame that last
+used the current synchronization primitives has finished executing and before
+anything is recorded. Results come back through the returned receivers, which
+can be polled or blocked on. */
+#[derive(Clone)]
+pub struct CommandSender {
+    tx: mpsc::Sender<ContextCommand>,
+}
+
+impl CommandSender {
+    pub fn load_image(
+        &self,
+        name: &str,
+        path: &str,
+    ) -> mpsc::Receiver<Result<ImageHandle, String>> {
+        let (receipt, rx) = mpsc::channel();
+        self.send(ContextCommand::LoadImage {
+            name: String::from(name),
+            path: String::from(path),
+            receipt,
+        });
+        rx
+    }
+
+    pub fn set_window_size(&self, width: u32, height: u32) {
+        self.send(ContextCommand::SetWindowSize { width, height });
+    }
+
+    pub fn set_pass_flag(&self, flag: PassFlagHandle, is_set: bool) {
+        self.send(ContextCommand::SetPassFlag { flag, is_set });
+    }
+
+    pub fn send(&self, command: ContextCommand) {
+        // The context outlives every frame, so this only fails during shutdown
+        let _ = self.tx.send(command);
+    }
+}
+
+pub struct CommandChannel {
+    tx: mpsc::Sender<ContextCommand>,
+    rx: mpsc::Receiver<ContextCommand>,
+}
+
+impl CommandChannel {
+    pub fn new() -> CommandChannel {
+        let (tx, rx) = mpsc::channel();
+        CommandChannel { tx, rx }
+    }
+
+    pub fn sender(&self) -> CommandSender {
+        CommandSender {
+            tx: self.tx.clone(),
+        }
+    }
+
+    /// Take every command sent so far, in order.
+    pub fn drain(&self) -> Vec<ContextCommand> {
+        self.rx.try_iter().collect()
+    }
+}
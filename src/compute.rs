@@ -0,0 +1,275 @@
+use crate::*;
+
+pub const COMPUTE_STORAGE_IMAGE_BINDING: u32 = 0;
+/// The push constant range that every device supports.
+pub const MAX_COMPUTE_PUSH_CONSTANTS_SIZE: usize = 128;
+
+/// A resource that a compute shader reads or writes, at `set = 0` and the
+/// binding of its index in the pipeline's list.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ComputeBinding {
+    StorageImage(vk::ImageView), // In GENERAL layout, as an `image2D`
+    SampledImage(vk::ImageView, vk::Sampler, vk::ImageLayout), // As a `sampler2D`, in that layout
+    StorageBuffer(vk::Buffer),   // All of it, as a `buffer` block
+}
+
+impl ComputeBinding {
+    fn descriptor_type(&self) -> vk::DescriptorType {
+        match self {
+            ComputeBinding::StorageImage(_) => vk::DescriptorType::STORAGE_IMAGE,
+            ComputeBinding::SampledImage(..) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            ComputeBinding::StorageBuffer(_) => vk::DescriptorType::STORAGE_BUFFER,
+        }
+    }
+}
+
+/* A compute shader and the resources it's bound to. Small parameters are
+pushed as constants with every dispatch. The context creates one the first
+time a shader dispatches with a set of bindings, and keeps it until the
+shader is reloaded or the images are recreated. */
+pub struct ComputePipeline {
+    device: ash::Device,
+    pub shader_handle: ShaderHandle,
+    pub bindings: Vec<ComputeBinding>, // Which the descriptor set points to
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl Drop for ComputePipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_pipeline(self.pipeline, None);
+            audit_destroyed(VkObjectKind::Pipeline, self.pipeline);
+            self.device
+                .destroy_pipeline_layout(self.pipeline_layout, None);
+            audit_destroyed(VkObjectKind::PipelineLayout, self.pipeline_layout);
+            self.device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            audit_destroyed(VkObjectKind::DescriptorPool, self.descriptor_pool);
+            self.device
+                .destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            audit_destroyed(
+                VkObjectKind::DescriptorSetLayout,
+                self.descriptor_set_layout,
+            );
+        }
+    }
+}
+
+impl ComputePipeline {
+    pub fn new(
+        name: &str,
+        shader_handle: ShaderHandle,
+        shader_module: vk::ShaderModule,
+        bindings: &[ComputeBinding],
+        pipeline_cache: &PipelineCache,
+        gpu: &Gpu,
+    ) -> ComputePipeline {
+        let device = gpu.device.clone();
+        let layout_bindings: Vec<vk::DescriptorSetLayoutBinding> = bindings
+            .iter()
+            .enumerate()
+            .map(|(idx, binding)| vk::DescriptorSetLayoutBinding {
+                binding: idx as u32,
+                descriptor_type: binding.descriptor_type(),
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                p_immutable_samplers: ptr::null(),
+            })
+            .collect();
+        let layout_create_info =
+            vk::DescriptorSetLayoutCreateInfo::builder().bindings(&layout_bindings);
+        let descriptor_set_layout = unsafe {
+            device
+                .create_descriptor_set_layout(&layout_create_info, None)
+                .expect("Failed to create Descriptor Set Layout!")
+        };
+
+        let pool_sizes: Vec<vk::DescriptorPoolSize> = bindings
+            .iter()
+            .map(|binding| vk::DescriptorPoolSize {
+                ty: binding.descriptor_type(),
+                descriptor_count: 1,
+            })
+            .collect();
+        let pool_create_info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(1)
+            .pool_sizes(&pool_sizes);
+        let descriptor_pool = unsafe {
+            device
+                .create_descriptor_pool(&pool_create_info, None)
+                .expect("Failed to create descriptor pool.")
+        };
+        let descriptor_set = {
+            let layouts = [descriptor_set_layout];
+            let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&layouts);
+            unsafe {
+                device
+                    .allocate_descriptor_sets(&allocate_info)
+                    .expect("Failed to allocate descriptor sets.")[0]
+            }
+        };
+        // Written once, since the set only ever points to these resources
+        let image_infos: Vec<[vk::DescriptorImageInfo; 1]> = bindings
+            .iter()
+            .map(|binding| match *binding {
+                ComputeBinding::StorageImage(image_view) => [vk::DescriptorImageInfo {
+                    sampler: vk::Sampler::null(),
+                    image_view,
+                    image_layout: vk::ImageLayout::GENERAL,
+                }],
+                ComputeBinding::SampledImage(image_view, sampler, image_layout) => {
+                    [vk::DescriptorImageInfo {
+                        sampler,
+                        image_view,
+                        image_layout,
+                    }]
+                }
+                ComputeBinding::StorageBuffer(_) => [vk::DescriptorImageInfo::default()],
+            })
+            .collect();
+        let buffer_infos: Vec<[vk::DescriptorBufferInfo; 1]> = bindings
+            .iter()
+            .map(|binding| match *binding {
+                ComputeBinding::StorageBuffer(buffer) => [vk::DescriptorBufferInfo {
+                    buffer,
+                    offset: 0,
+                    range: vk::WHOLE_SIZE,
+                }],
+                _ => [vk::DescriptorBufferInfo::default()],
+            })
+            .collect();
+        let descriptor_writes: Vec<vk::WriteDescriptorSet> = bindings
+            .iter()
+            .enumerate()
+            .map(|(idx, binding)| {
+                let write = vk::WriteDescriptorSet::builder()
+                    .dst_set(descriptor_set)
+                    .dst_binding(idx as u32)
+                    .descriptor_type(binding.descriptor_type());
+                match binding {
+                    ComputeBinding::StorageBuffer(_) => write.buffer_info(&buffer_infos[idx]),
+                    _ => write.image_info(&image_infos[idx]),
+                }
+                .build()
+            })
+            .collect();
+        unsafe {
+            device.update_descriptor_sets(&descriptor_writes, &[]);
+        }
+
+        let set_layouts = [descriptor_set_layout];
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: MAX_COMPUTE_PUSH_CONSTANTS_SIZE as u32,
+        }];
+        let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        let pipeline_layout = unsafe {
+            device
+                .create_pipeline_layout(&pipeline_layout_create_info, None)
+                .expect("Failed to create pipeline layout.")
+        };
+
+        let main_function_name = CString::new("main").unwrap();
+        let pipeline_create_infos = [vk::ComputePipelineCreateInfo {
+            stage: vk::PipelineShaderStageCreateInfo {
+                module: shader_module,
+                p_name: main_function_name.as_ptr(),
+                stage: vk::ShaderStageFlags::COMPUTE,
+                ..Default::default()
+            },
+            layout: pipeline_layout,
+            ..Default::default()
+        }];
+        let pipeline = unsafe {
+            device
+                .create_compute_pipelines(
+                    pipeline_cache.vk_pipeline_cache,
+                    &pipeline_create_infos,
+                    None,
+                )
+                .expect("Failed to create Compute Pipeline.")[0]
+        };
+
+        let parents = [device_id(&device)];
+        audit_created(
+            VkObjectKind::DescriptorSetLayout,
+            descriptor_set_layout,
+            &parents,
+            name,
+        );
+        audit_created(
+            VkObjectKind::DescriptorPool,
+            descriptor_pool,
+            &parents,
+            name,
+        );
+        audit_created(
+            VkObjectKind::PipelineLayout,
+            pipeline_layout,
+            &parents,
+            name,
+        );
+        audit_created(VkObjectKind::Pipeline, pipeline, &parents, name);
+
+        ComputePipeline {
+            device,
+            shader_handle,
+            bindings: bindings.to_vec(),
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_set,
+            pipeline_layout,
+            pipeline,
+        }
+    }
+
+    /// The images must already be in the layouts that their bindings name.
+    pub fn record_dispatch(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        push_constants: &[u8],
+        (num_groups_x, num_groups_y, num_groups_z): (u32, u32, u32),
+    ) {
+        assert!(
+            push_constants.len() <= MAX_COMPUTE_PUSH_CONSTANTS_SIZE,
+            "Compute push constants are {} bytes, but at most {} are supported.",
+            push_constants.len(),
+            MAX_COMPUTE_PUSH_CONSTANTS_SIZE
+        );
+        unsafe {
+            self.device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            self.device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            if !push_constants.is_empty() {
+                self.device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    push_constants,
+                );
+            }
+            self.device
+                .cmd_dispatch(command_buffer, num_groups_x, num_groups_y, num_groups_z);
+        }
+    }
+}
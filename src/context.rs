@@ -1,21 +1,48 @@
 use crate::*;
+use glam::*;
+use std::rc::Rc;
 
 use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::platform::desktop::EventLoopExtDesktop;
 
 const ENABLE_DEBUG_MESSENGER_CALLBACK: bool = true;
-
-#[derive(Copy, Clone, Debug, Hash, PartialEq)]
-pub struct BufferHandle(pub u64);
+const NUM_REPORTED_FRAME_TIMES: usize = 300;
+pub const GRAPH_REPORT_PATH: &str = "_cache/graph_report.html";
+const RING_BUFFER_SIZE_PER_FRAME: usize = 1024 * 1024;
+// Grows if a frame needs more
+const FRAME_ARENA_SIZE: usize = 256 * 1024;
+// Defined for the permutations of shaders that passes render several views at once with
+const MULTIVIEW_DEFINE: &str = "MULTIVIEW";
+// Every device with VK_KHR_multiview renders at least this many views at once
+const MIN_MAX_MULTIVIEW_VIEWS: u32 = 6;
+
+// Resources are kept in registries, whose handles are caught if they outlive them
+pub type BufferHandle = Handle<HostVisibleBuffer>;
+pub type ImageHandle = Handle<InternalImage>;
+pub type ShaderHandle = Handle<InternalShader>;
 #[derive(Copy, Clone)]
 pub struct GraphHandle(pub u64);
 #[derive(Copy, Clone, Debug, Hash, PartialEq)]
 pub struct PassHandle(pub u64);
 #[derive(Copy, Clone, Debug, Hash, PartialEq)]
-pub struct ImageHandle(pub u64);
-#[derive(Copy, Clone, Debug, Hash, PartialEq)]
-pub struct ShaderHandle(pub u64);
+pub struct PassFlagHandle(pub usize);
+
+/// The uniform buffer to add passes with, whose uniforms are written every
+/// frame with `write_pass_uniforms()`.
+pub const RING_UNIFORM_BUFFER: BufferHandle = Handle::from_raw_parts(u32::MAX, 0);
+
+#[derive(Default)]
+pub struct GraphStats {
+    pub graphs_built_during_warmup: usize,
+    pub graphs_built_after_warmup: usize,
+    pub graphs_evicted: usize, // Dropped from the cache on resize or shader reload
+    pub cache_hits: usize,
+    pub cache_misses: usize,
+    pub passes_skipped: usize, // Disabled by their flag in begin_pass()
+    pub last_build_duration: std::time::Duration, // Time spent in build_graph() in the last call
+    pub total_build_duration: std::time::Duration, // Time spent building graphs on cache misses
+}
 
 pub struct Context {
     window: winit::window::Window,
@@ -23,19 +50,61 @@ pub struct Context {
 
     // Graph being built in the current frame
     pub builder_passes: Vec<(PassHandle, BuilderPass)>,
+    /* Runtime booleans that passes can be conditional on. Since only the
+    handle of a pass's flag is part of the graph's hash, toggling a flag
+    doesn't rebuild the graph. */
+    pass_flags: Vec<(String, bool)>, // (name, is_set)
+    command_channel: CommandChannel,
 
     pub shader_list: ShaderList,
     // TODO: Move these to the graph builder instead?
     pub image_list: ImageList,
     pub buffer_list: BufferList,
+    pub default_resources: DefaultResources,
+    pub sampler_cache: SamplerCache,
 
     graph_cache: Vec<(Graph, GraphHandle)>, // (graph, hash) // TODO: Make this a proper LRU and move it to its own file
+    compute_pipelines: Vec<ComputePipeline>, // Created by dispatch_compute() on first use
+    is_warmup_finished: bool,
+    pipeline_cache: PipelineCache,
+    pub graph_stats: GraphStats,
     pub command_pool: vk::CommandPool,
+    pub upload_context: UploadContext, // Flushed at the start of every frame
 
     pub sync_idx: usize,      // Index of the synchronization primitives
     pub swapchain_idx: usize, // Index of the swapchain frame
 
+    pub input: InputState,
+    pub latency_tracker: LatencyTracker,
+    pub background_policy: BackgroundPolicy,
+    pub frame_pacer: FramePacer, // Change the mode with set_pacing_mode()
+    surface_format_preference: SurfaceFormatPreference, // Set with set_surface_format_preference()
+    pub monitor_tracker: MonitorTracker,
+    opt_frames_until_exit: Option<usize>, // Set with --verify-teardown
+    pub is_frame_throttled: bool, // True while the window is unfocused. Apps can skip expensive passes.
+    pub num_throttled_frames: usize,
+    last_frame_start_instant: std::time::Instant,
+    recent_frame_times: std::collections::VecDeque<std::time::Duration>, // For the graph report
+    pub capability_report: CapabilityReport,
+    pub shader_debug: ShaderDebug,
+    pub gpu_timer: GpuTimer,
+    pub ring_buffer: RingBuffer, // Reset for every frame
+    pub frame_arena: FrameArena, // For CPU data that lives for one frame. Reset for every frame.
+    dynamic_uniform_offsets: Vec<(PassHandle, u32)>, // Set in the current frame
+    // Per sync index, of the uploads recorded into the frame. Freed once the frame completes.
+    frame_staging_chunks: Vec<Vec<StagingChunk>>,
+    pub budget_monitor: BudgetMonitor, // Set the budgets with set_frame_budgets()
+    frame_stats: FrameStats,           // Of the current frame, until end_frame()
+    pub last_frame_stats: FrameStats,
+    record_start_instant: std::time::Instant,
+    staging_bytes_at_frame_start: u64,
+    inline_update_bytes: u64, // Written with update_device_local_buffer() in the current frame
+    opt_screenshot_path: Option<String>, // Set with capture_screenshot(), saved in end_frame()
+    is_multiview_enabled: bool, // Set with set_multiview_enabled(), if the device supports it
+
+    #[cfg(feature = "hot-reload")]
     _watcher: notify::RecommendedWatcher, // Need to keep this alive to keep the receiver alive
+    #[cfg(feature = "hot-reload")]
     watch_rx: std::sync::mpsc::Receiver<notify::DebouncedEvent>,
 
     pub command_buffers: Vec<vk::CommandBuffer>,
@@ -52,6 +121,7 @@ impl Drop for Context {
                 .device
                 .device_wait_idle()
                 .expect("Failed to wait device idle!");
+            self.pipeline_cache.save();
             self.gpu
                 .device
                 .free_command_buffers(self.command_pool, &self.command_buffers);
@@ -59,8 +129,10 @@ impl Drop for Context {
             self.gpu
                 .device
                 .destroy_command_pool(self.command_pool, None);
+            audit_destroyed(VkObjectKind::CommandPool, self.command_pool);
+            self.upload_context.destroy(&self.gpu);
 
-            self.facade.destroy(&mut self.image_list);
+            self.facade.destroy(&self.gpu, &mut self.image_list);
         }
     }
 }
@@ -73,25 +145,66 @@ impl Context {
                 .device_wait_idle()
                 .expect("Failed to wait device idle.")
         };
+        /* Cached graphs hold framebuffers and descriptor sets that point to the
+        images which are about to be recreated, so they can't be reused. */
+        self.graph_stats.graphs_evicted += self.graph_cache.len();
+        self.graph_cache.clear();
+        self.compute_pipelines.clear();
         // Recreate swapchain
-        self.facade.destroy(&mut self.image_list);
+        self.facade.destroy(&self.gpu, &mut self.image_list);
         self.facade = Facade::new(
-            &self.basis,
+            &mut self.basis,
             &self.gpu,
             &self.window,
+            self.frame_pacer.present_mode(&self.gpu.present_modes),
+            self.surface_format_preference,
             &mut self.image_list,
             &self.debug_utils,
         );
+        // The graphs that point to the shader debug buffer are gone, so it can be resized
+        if self.shader_debug.num_frames() != self.facade.num_frames {
+            self.shader_debug =
+                ShaderDebug::new(self.facade.num_frames, &self.gpu, &self.debug_utils);
+        }
+        if self.gpu_timer.num_frames() != self.facade.num_frames {
+            self.gpu_timer = GpuTimer::new(self.facade.num_frames, &self.gpu);
+        }
+        // The device is idle, so the uploads of every frame have completed
+        for staging_chunk in self.frame_staging_chunks.drain(..).flatten() {
+            self.gpu.staging_pool.free(staging_chunk);
+        }
+        self.frame_staging_chunks
+            .resize_with(self.facade.num_frames, Vec::new);
+        if self.ring_buffer.num_frames() != self.facade.num_frames {
+            self.ring_buffer =
+                new_ring_buffer(self.facade.num_frames, &self.gpu, &self.debug_utils);
+        }
+        add_breadcrumb(&format!(
+            "Recreated the swapchain at {}x{}, with {:?}.",
+            self.facade.swapchain_width, self.facade.swapchain_height, self.facade.present_mode
+        ));
+        self.capability_report.active_present_mode = format!("{:?}", self.facade.present_mode);
+        self.capability_report.active_surface_format = format!(
+            "{:?} {:?}",
+            self.facade.surface_format.format, self.facade.surface_format.color_space
+        );
         // Recreate the images which depend on the resolution of the swapchain
-        for i in 0..self.image_list.list.len() {
-            let (_, internal_image) = &mut self.image_list.list[i];
+        for (_, internal_image) in self.image_list.list.iter_mut() {
             if let ImageKind::RelativeSized { scale } = internal_image.kind {
-                let w = (self.facade.swapchain_width as f32 * scale) as u32;
-                let h = (self.facade.swapchain_height as f32 * scale) as u32;
-                internal_image.image = Image::new(
+                let (w, h) = relative_image_size_checked(
+                    &internal_image.image.name,
+                    scale,
+                    &self.facade,
+                    &self.gpu,
+                );
+                internal_image.image = Image::new_with_mips(
                     &internal_image.image.name,
                     w,
                     h,
+                    1,
+                    internal_image.image.num_mips.min(num_mips_for_size(w, h)),
+                    1,
+                    internal_image.image.samples,
                     internal_image.image.format,
                     internal_image.image.usage,
                     internal_image.image.aspect_flags,
@@ -102,8 +215,17 @@ impl Context {
         }
     }
 
+    /* Request a new inner size for the window, in physical pixels. The window
+    system may not honor it exactly. The resolution-dependent state is
+    recreated once the resize comes through. */
+    pub fn set_window_size(&self, width: u32, height: u32) {
+        self.window
+            .set_inner_size(winit::dpi::PhysicalSize::new(width, height));
+    }
+
     pub fn new() -> Context {
         const APP_NAME: &str = "";
+        install_crash_handler();
 
         // # Init window
         let event_loop = EventLoop::new();
@@ -116,7 +238,8 @@ impl Context {
                 .expect("Failed to create window.")
         };
 
-        let basis = Basis::new(APP_NAME, &window);
+        let monitor_tracker = MonitorTracker::new(&window);
+        let mut basis = Basis::new(APP_NAME, &window);
         let gpu = Gpu::new(&basis);
         let debug_utils = DebugUtils::new(&basis, &gpu, ENABLE_DEBUG_MESSENGER_CALLBACK);
 
@@ -126,19 +249,49 @@ impl Context {
                 .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER)
                 .queue_family_index(gpu.graphics_queue_idx);
 
-            unsafe {
+            let command_pool = unsafe {
                 gpu.device
                     .create_command_pool(&info, None)
                     .expect("Failed to create command pool")
-            }
+            };
+            audit_created(
+                VkObjectKind::CommandPool,
+                command_pool,
+                &[device_id(&gpu.device)],
+                "command_pool",
+            );
+            command_pool
         };
 
         let shader_list = ShaderList::new(gpu.device.clone());
+        let pipeline_cache = PipelineCache::new(&gpu);
 
         // TODO: Move this up?
         let mut image_list = ImageList::new();
-        let facade = Facade::new(&basis, &gpu, &window, &mut image_list, &debug_utils);
+        let frame_pacer = FramePacer::new(monitor_tracker.current.opt_refresh_rate);
+        let facade = Facade::new(
+            &mut basis,
+            &gpu,
+            &window,
+            frame_pacer.present_mode(&gpu.present_modes),
+            SurfaceFormatPreference::Srgb,
+            &mut image_list,
+            &debug_utils,
+        );
+        log_surface_format(SurfaceFormatPreference::Srgb, &facade);
         let buffer_list = BufferList::new();
+        let capability_report = CapabilityReport::new(&gpu, &facade);
+        set_crash_capabilities(capability_report.to_text());
+        add_breadcrumb(&format!(
+            "Created the context, with a {}x{} swapchain.",
+            facade.swapchain_width, facade.swapchain_height
+        ));
+        let shader_debug = ShaderDebug::new(facade.num_frames, &gpu, &debug_utils);
+        let gpu_timer = GpuTimer::new(facade.num_frames, &gpu);
+        let ring_buffer = new_ring_buffer(facade.num_frames, &gpu, &debug_utils);
+        let default_resources =
+            DefaultResources::new(&mut image_list, &gpu, command_pool, &debug_utils);
+        let upload_context = UploadContext::new(&gpu);
 
         // # Allocate command buffers
         let command_buffers = {
@@ -155,6 +308,7 @@ impl Context {
         };
 
         // Add expect messages to all these unwraps
+        #[cfg(feature = "hot-reload")]
         let (watcher, watch_rx) = {
             use notify::{RecommendedWatcher, RecursiveMode, Watcher};
             use std::sync::mpsc::channel;
@@ -171,17 +325,59 @@ impl Context {
             event_loop,
 
             builder_passes: Vec::new(),
+            pass_flags: Vec::new(),
+            command_channel: CommandChannel::new(),
             shader_list,
             image_list,
             buffer_list,
+            default_resources,
+            sampler_cache: SamplerCache::new(),
 
             graph_cache: Vec::new(),
+            compute_pipelines: Vec::new(),
+            is_warmup_finished: false,
+            pipeline_cache,
+            graph_stats: GraphStats::default(),
             command_pool,
+            upload_context,
 
             sync_idx: 0,
             swapchain_idx: 0,
 
+            input: InputState::new(),
+            latency_tracker: LatencyTracker::new(),
+            background_policy: BackgroundPolicy::new(),
+            frame_pacer,
+            surface_format_preference: SurfaceFormatPreference::Srgb,
+            monitor_tracker,
+            opt_frames_until_exit: if is_verifying_teardown() {
+                Some(NUM_VERIFY_TEARDOWN_FRAMES)
+            } else {
+                None
+            },
+            is_frame_throttled: false,
+            num_throttled_frames: 0,
+            last_frame_start_instant: std::time::Instant::now(),
+            recent_frame_times: std::collections::VecDeque::new(),
+            capability_report,
+            shader_debug,
+            gpu_timer,
+            ring_buffer,
+            frame_arena: FrameArena::new(FRAME_ARENA_SIZE),
+            dynamic_uniform_offsets: Vec::new(),
+            frame_staging_chunks: (0..facade.num_frames).map(|_| Vec::new()).collect(),
+            budget_monitor: BudgetMonitor::new(FrameBudgets::default()),
+            frame_stats: FrameStats::default(),
+            last_frame_stats: FrameStats::default(),
+            record_start_instant: std::time::Instant::now(),
+            staging_bytes_at_frame_start: 0,
+            inline_update_bytes: 0,
+            opt_screenshot_path: None,
+            is_multiview_enabled: gpu.is_multiview_supported,
+
+            #[cfg(feature = "hot-reload")]
             _watcher: watcher,
+            #[cfg(feature = "hot-reload")]
             watch_rx,
 
             command_buffers,
@@ -193,6 +389,7 @@ impl Context {
     }
 
     pub fn build_graph(&mut self) -> GraphHandle {
+        let start_instant = std::time::Instant::now();
         // Get the hash of the graph builder
         let req_hash: u64 = {
             let mut hasher = DefaultHasher::new();
@@ -205,69 +402,245 @@ impl Context {
             .iter()
             .position(|(_, cached_hash)| cached_hash.0 == req_hash);
 
-        if opt_idx.is_none() {
+        if opt_idx.is_some() {
+            self.graph_stats.cache_hits += 1;
+        } else {
+            self.graph_stats.cache_misses += 1;
             // The requested graph doesn't exist. Build it and add it to the cache.
-            println!("Adding graph to cache");
+            if self.is_warmup_finished {
+                /* Building pipelines mid-frame causes hitches. If this gets
+                logged, the warmup is missing a combination of passes. */
+                let pass_names: Vec<&str> = self
+                    .builder_passes
+                    .iter()
+                    .map(|(_, pass)| pass.name.as_str())
+                    .collect();
+                println!(
+                    "Adding graph to cache after warmup. Passes: {:?}",
+                    pass_names
+                );
+                self.graph_stats.graphs_built_after_warmup += 1;
+            } else {
+                println!("Adding graph to cache");
+                self.graph_stats.graphs_built_during_warmup += 1;
+            }
+            add_breadcrumb(&format!(
+                "Built a graph with {} passes.",
+                self.builder_passes.len()
+            ));
             self.graph_cache.push((
                 Graph::new(
                     &self.gpu,
+                    &self.pipeline_cache,
                     &self.builder_passes,
                     &self.shader_list,
                     &self.buffer_list,
                     &self.image_list,
+                    &self.shader_debug,
+                    self.facade.num_frames,
+                    &self.debug_utils,
                 ),
                 GraphHandle(req_hash),
             ));
         }
 
+        self.graph_stats.last_build_duration = start_instant.elapsed();
+        if opt_idx.is_none() {
+            self.graph_stats.total_build_duration += self.graph_stats.last_build_duration;
+        }
         GraphHandle(req_hash)
     }
 
+    /// Drop every cached graph, for when something that graphs point to, like
+    /// a sampler, is about to be destroyed. Waits for the GPU to go idle.
+    pub fn clear_graph_cache(&mut self) {
+        unsafe {
+            self.gpu
+                .device
+                .device_wait_idle()
+                .expect("Failed to wait device idle.")
+        };
+        self.graph_stats.graphs_evicted += self.graph_cache.len();
+        self.graph_cache.clear();
+    }
+
+    /// The best depth format that the device can render to, for depth images.
+    pub fn depth_format(&self) -> Format {
+        *DEPTH_FORMATS
+            .iter()
+            .find(|format| format.to_vk() == self.gpu.depth_format)
+            .unwrap()
+    }
+
+    /// The shared sampler for `desc`, created the first time it's requested.
+    /// Cached samplers outlive the graphs, which can keep pointing to them.
+    pub fn sampler(&mut self, desc: &SamplerDesc) -> Rc<Sampler> {
+        self.sampler_cache.get(desc, &self.gpu, &self.debug_utils)
+    }
+
+    /// Build the graph out of the passes added so far, without executing it.
+    /// Call this before the frame loop for every graph that the app can
+    /// request, so that the pipelines are created ahead of time.
+    pub fn warmup_graph(&mut self) -> GraphHandle {
+        let graph_handle = self.build_graph();
+        self.builder_passes.clear();
+        graph_handle
+    }
+
+    /// Mark the end of the warmup. Any graph that gets built after this is
+    /// logged and counted in `graph_stats`.
+    pub fn finish_warmup(&mut self) {
+        self.is_warmup_finished = true;
+        println!(
+            "Warmup finished. {} graph(s) built in {:.2} ms.",
+            self.graph_stats.graphs_built_during_warmup,
+            self.graph_stats.total_build_duration.as_secs_f64() * 1000.0
+        );
+    }
+
+    /// Switch present modes, which recreates the swapchain if the mode changes.
+    pub fn set_pacing_mode(&mut self, mode: PacingMode) {
+        self.frame_pacer.mode = mode;
+        self.frame_pacer.reset_stats();
+        if self.frame_pacer.present_mode(&self.gpu.present_modes) != self.facade.present_mode {
+            self.recreate_resolution_dependent_state();
+        }
+    }
+
+    /// Recreates the swapchain, and logs the format that was chosen, if the
+    /// preference changes.
+    pub fn set_surface_format_preference(&mut self, preference: SurfaceFormatPreference) {
+        if preference != self.surface_format_preference {
+            self.surface_format_preference = preference;
+            self.recreate_resolution_dependent_state();
+            log_surface_format(preference, &self.facade);
+        }
+    }
+
     pub fn begin_frame(&mut self) -> bool {
         // Clear the passes of the current graph
         self.builder_passes.clear();
+        // Submit the uploads that were recorded since the last frame
+        self.upload_context.flush(&self.gpu);
 
         // Execute the event loop
         let mut is_running = true;
         let mut resize_needed = false;
+        let mut was_moved = false;
         let swapchain_width = self.facade.swapchain_width;
         let swapchain_height = self.facade.swapchain_height;
+        self.input.begin_frame();
 
-        self.event_loop.run_return(|event, _, control_flow| {
-            *control_flow = ControlFlow::Wait;
+        // While paused in the background, keep pumping events until focus comes back
+        loop {
+            let input = &mut self.input;
+            self.event_loop.run_return(|event, _, control_flow| {
+                *control_flow = ControlFlow::Wait;
 
-            match event {
-                Event::WindowEvent { event, .. } => match event {
-                    WindowEvent::CloseRequested => is_running = false,
-                    #[allow(clippy::match_single_binding)] // TODO: Simplify  this
-                    WindowEvent::KeyboardInput { input, .. } => match input {
-                        KeyboardInput {
-                            virtual_keycode,
-                            state,
-                            ..
-                        } => match (virtual_keycode, state) {
-                            (Some(VirtualKeyCode::Escape), ElementState::Pressed)
-                            | (Some(VirtualKeyCode::Return), ElementState::Pressed) => {
-                                is_running = false;
-                            }
-                            _ => {}
+                if let Event::WindowEvent { event, .. } = &event {
+                    input.handle_window_event(event);
+                }
+
+                match event {
+                    Event::WindowEvent { event, .. } => match event {
+                        WindowEvent::CloseRequested => is_running = false,
+                        #[allow(clippy::match_single_binding)] // TODO: Simplify  this
+                        WindowEvent::KeyboardInput { input, .. } => match input {
+                            KeyboardInput {
+                                virtual_keycode,
+                                state,
+                                ..
+                            } => match (virtual_keycode, state) {
+                                (Some(VirtualKeyCode::Escape), ElementState::Pressed)
+                                | (Some(VirtualKeyCode::Return), ElementState::Pressed) => {
+                                    is_running = false;
+                                }
+                                _ => {}
+                            },
                         },
-                    },
-                    WindowEvent::Resized(physical_size) => {
-                        if swapchain_width != physical_size.width
-                            || swapchain_height != physical_size.height
+                        WindowEvent::Moved(_) => was_moved = true,
+                        WindowEvent::Resized(physical_size)
+                            if swapchain_width != physical_size.width
+                                || swapchain_height != physical_size.height =>
                         {
                             resize_needed = true;
                         }
+                        _ => {}
+                    },
+                    Event::MainEventsCleared => {
+                        *control_flow = ControlFlow::Exit;
                     }
-                    _ => {}
-                },
-                Event::MainEventsCleared => {
-                    *control_flow = ControlFlow::Exit;
+                    _ => (),
                 }
-                _ => (),
+            });
+            // A minimized window has no area to create a swapchain with
+            let window_size = self.window.inner_size();
+            let is_minimized = window_size.width == 0 || window_size.height == 0;
+            let is_paused = is_minimized
+                || (!self.input.is_window_focused && self.background_policy.is_rendering_paused);
+            if !is_running || !is_paused {
+                break;
             }
-        });
+            std::thread::sleep(PAUSED_POLL_INTERVAL);
+        }
+
+        if let Some(num_frames_left) = &mut self.opt_frames_until_exit {
+            if *num_frames_left == 0 {
+                is_running = false;
+            }
+            *num_frames_left = num_frames_left.saturating_sub(1);
+        }
+
+        if was_moved {
+            self.monitor_tracker.on_moved();
+        }
+        self.monitor_tracker.update(&self.window);
+
+        // Throttle the frame rate in the background. Focus restores the full rate right away.
+        self.is_frame_throttled = !self.input.is_window_focused;
+        if self.is_frame_throttled {
+            self.num_throttled_frames += 1;
+            if let Some(min_frame_duration) = self.background_policy.min_frame_duration() {
+                let elapsed = self.last_frame_start_instant.elapsed();
+                if elapsed < min_frame_duration {
+                    std::thread::sleep(min_frame_duration - elapsed);
+                }
+            }
+        }
+        let frame_start_instant = std::time::Instant::now();
+        if self.recent_frame_times.len() == NUM_REPORTED_FRAME_TIMES {
+            self.recent_frame_times.pop_front();
+        }
+        self.recent_frame_times
+            .push_back(frame_start_instant - self.last_frame_start_instant);
+        self.last_frame_start_instant = frame_start_instant;
+
+        if let Some(click_instant) = self.input.opt_click_instant {
+            self.latency_tracker.on_input(click_instant);
+        }
+        self.latency_tracker.on_update();
+
+        // F1 prints what the device supports, and F2 saves it for attaching to bug reports
+        if self.input.was_key_pressed(VirtualKeyCode::F1) {
+            println!("{}", self.capability_report.to_text());
+        }
+        if self.input.was_key_pressed(VirtualKeyCode::F2) {
+            match self.capability_report.save_json() {
+                Ok(path) => println!("Saved capability report to `{}`.", path),
+                Err(e) => println!("{}", e),
+            }
+        }
+        // F4 copies all diagnostics at once, for pasting into an issue
+        if self.input.was_key_pressed(VirtualKeyCode::F4) {
+            match copy_text(&self.diagnostics_text()) {
+                Ok(CopyDestination::Clipboard) => println!("Copied diagnostics to the clipboard."),
+                Ok(CopyDestination::File(path)) => println!(
+                    "No clipboard is available, so diagnostics were saved to `{}`.",
+                    path.display()
+                ),
+                Err(e) => println!("{}", e),
+            }
+        }
 
         // This mechanism is need on Windows:
         if resize_needed {
@@ -284,12 +657,12 @@ impl Context {
             unsafe {
                 self.gpu
                     .device
-                    .wait_for_fences(&wait_fences, true, std::u64::MAX)
+                    .wait_for_fences(&wait_fences, true, u64::MAX)
                     .expect("Failed to wait for Fence.");
 
                 let result = self.facade.ext_swapchain.acquire_next_image(
                     self.facade.swapchain,
-                    std::u64::MAX,
+                    u64::MAX,
                     self.facade.image_available_semaphores[self.sync_idx],
                     vk::Fence::null(),
                 );
@@ -316,6 +689,20 @@ impl Context {
         }
 
         self.swapchain_idx = opt_frame_idx.unwrap();
+        // The last frame that used these sync primitives has finished executing
+        self.shader_debug.read_back(self.sync_idx);
+        self.ring_buffer.begin_frame(
+            self.sync_idx,
+            self.facade.command_buffer_complete_fences[self.sync_idx],
+            &self.gpu,
+        );
+        for staging_chunk in self.frame_staging_chunks[self.sync_idx].drain(..) {
+            self.gpu.staging_pool.free(staging_chunk);
+        }
+        self.dynamic_uniform_offsets.clear();
+        self.frame_arena.reset();
+        self.apply_commands();
+        self.latency_tracker.on_record();
 
         let cmd_buf = self.command_buffers[self.swapchain_idx];
         // Reset command buffer
@@ -337,13 +724,276 @@ impl Context {
         }
         /* Naming the command buffer doesn't seem to work on creating it, so we
         name it on every begin frame instead.*/
+        let name = self
+            .frame_arena
+            .alloc_fmt(format_args!("command_buffer_{}", self.swapchain_idx));
         self.debug_utils
-            .set_command_buffer_name(cmd_buf, &format!("command_buffer_{}", self.swapchain_idx));
+            .set_command_buffer_name_cstr(cmd_buf, self.frame_arena.get_cstr(name));
 
+        self.frame_stats = FrameStats::default();
+        // Of the last frame with this sync index, which has finished executing
+        if let Some(times) = self.gpu_timer.read_back(self.sync_idx) {
+            self.frame_stats.opt_gpu_ms = Some(times.total_ms);
+            self.frame_stats.pass_gpu_ms = times.pass_ms;
+        }
+        self.gpu_timer.begin_frame(cmd_buf, self.sync_idx);
+        // The barriers recorded while loading count towards no frame
+        for (_, internal_image) in self.image_list.list.iter() {
+            internal_image.image.take_num_barriers();
+        }
+        self.record_start_instant = std::time::Instant::now();
+        self.staging_bytes_at_frame_start = self.gpu.staging_pool.stats().bytes_uploaded;
+        self.inline_update_bytes = 0;
         is_running
     }
 
+    /// For requesting structural changes from other threads, or from
+    /// anywhere that can't touch the context directly.
+    pub fn command_sender(&self) -> CommandSender {
+        self.command_channel.sender()
+    }
+
+    fn apply_commands(&mut self) {
+        for command in self.command_channel.drain() {
+            match command {
+                ContextCommand::LoadImage {
+                    name,
+                    path,
+                    receipt,
+                } => {
+                    let result = self.new_image_from_file(&name, &path);
+                    // The requester may not care about the result
+                    let _ = receipt.send(result);
+                }
+                ContextCommand::SetWindowSize { width, height } => {
+                    self.set_window_size(width, height)
+                }
+                ContextCommand::SetPassFlag { flag, is_set } => self.set_pass_flag(flag, is_set),
+            }
+        }
+    }
+
+    /* Write the passes of the current frame, recent frame times, graph cache
+    statistics and the capability report to one HTML file. Call this after
+    adding the frame's passes, e.g. right before exiting. */
+    pub fn export_graph_report(&self, path: &str) -> Result<(), String> {
+        let image_name = |image_view: vk::ImageView| {
+            self.image_list
+                .list
+                .iter()
+                .find(|(_, internal_image)| internal_image.image.image_view == image_view)
+                .map_or_else(
+                    || String::from("(external image)"),
+                    |(_, internal_image)| internal_image.image.name.clone(),
+                )
+        };
+        let handle_name = |image_handle: ImageHandle| {
+            self.image_list
+                .get_image_from_handle(image_handle)
+                .map_or_else(
+                    || format!("{:?}", image_handle),
+                    |internal_image| internal_image.image.name.clone(),
+                )
+        };
+        let passes: Vec<ReportPass> = self
+            .builder_passes
+            .iter()
+            .map(|(_, pass)| ReportPass {
+                name: pass.name.clone(),
+                input_images: pass
+                    .input_images
+                    .iter()
+                    .map(|(image_view, _)| image_name(*image_view))
+                    .collect(),
+                output_images: pass
+                    .output_images
+                    .iter()
+                    .chain(&pass.opt_depth_image)
+                    .map(|image_handle| handle_name(*image_handle))
+                    .collect(),
+                is_conditional: pass.opt_enabled_if.is_some(),
+            })
+            .collect();
+        let frame_times: Vec<std::time::Duration> =
+            self.recent_frame_times.iter().copied().collect();
+        let html = graph_report_html(
+            &passes,
+            &self.graph_stats,
+            &frame_times,
+            &self.capability_report,
+        );
+        if let Some(dir) = std::path::Path::new(path).parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Couldn't create directory for `{}`: {}", path, e))?;
+        }
+        std::fs::write(path, html)
+            .map_err(|e| format!("Couldn't write graph report `{}`: {}", path, e))
+    }
+
+    /* Plain text for pasting into bug reports: the capability report, the
+    latest validation messages, recent frame times and the memory report.
+    Each section comes from the same code that prints or saves it. */
+    pub fn diagnostics_text(&self) -> String {
+        let frame_times: Vec<std::time::Duration> =
+            self.recent_frame_times.iter().copied().collect();
+        format_diagnostics(
+            &self.capability_report.to_text(),
+            num_validation_errors(),
+            &recent_validation_messages(),
+            FrameTimeSummary::new(&frame_times).as_ref(),
+            &self.gpu.memory_report().to_string(),
+        )
+    }
+
+    /* Alerts are printed when a budget starts or stops being exceeded, not on
+    every frame. The alert names the pass or upload category that contributed
+    the most, and F5 prints the frame's full breakdown. */
+    pub fn set_frame_budgets(&mut self, budgets: FrameBudgets) {
+        self.budget_monitor.budgets = budgets;
+    }
+
+    fn evaluate_frame_budgets(&mut self) {
+        let mut stats = std::mem::take(&mut self.frame_stats);
+        stats.record_ms = self.record_start_instant.elapsed().as_secs_f32() * 1000.0;
+        let staging_bytes =
+            self.gpu.staging_pool.stats().bytes_uploaded - self.staging_bytes_at_frame_start;
+        stats.upload_bytes = vec![
+            ("staging", staging_bytes),
+            ("ring buffer", self.ring_buffer.bytes_used() as u64),
+            ("inline updates", self.inline_update_bytes),
+        ];
+        stats.image_barriers = self
+            .image_list
+            .list
+            .iter()
+            .map(|(_, internal_image)| {
+                let image = &internal_image.image;
+                (image.name.clone(), image.take_num_barriers())
+            })
+            .filter(|(_, num_barriers)| *num_barriers > 0)
+            .collect();
+        for event in self.budget_monitor.evaluate(&stats) {
+            match event {
+                BudgetEvent::Raised(alert) => println!("Over budget: {}", alert.to_text()),
+                BudgetEvent::Cleared(kind) => println!("Back within the {:?} budget.", kind),
+            }
+        }
+        if self.input.was_key_pressed(VirtualKeyCode::F5) {
+            println!("{}", stats.to_text());
+            for alert in self.budget_monitor.active_alerts() {
+                println!("Over budget: {}", alert.to_text());
+            }
+        }
+        self.last_frame_stats = stats;
+    }
+
+    /// Save the frame being recorded as a PNG once it has been presented.
+    /// Call between `begin_frame()` and `end_frame()`. Surfaces without
+    /// `facade.is_screenshot_supported()` print why nothing was saved.
+    pub fn capture_screenshot(&mut self, path: &str) {
+        self.opt_screenshot_path = Some(String::from(path));
+    }
+
+    /* The texels of an image that passes render to, as tightly packed RGBA8,
+    with the layers of arrays one after another, e.g. to compare against a
+    reference in a test. Call it after
+    `end_frame()`, and the frames submitted so far complete before the copy.
+    The image needs `ImageUsage::TRANSFER_SRC`. */
+    pub fn read_back_image(&self, image: ImageHandle) -> Result<Vec<u8>, String> {
+        let internal_image = self.image_list.get_image(image)?;
+        internal_image
+            .image
+            .read_back(&self.gpu, self.command_pool, &self.debug_utils)
+    }
+
+    // Of the swapchain image, after the frame's passes. Returns the buffer it's copied into.
+    fn record_screenshot_copy(&self) -> Result<HostVisibleBuffer, String> {
+        if !self.facade.is_screenshot_supported() {
+            return Err(String::from(
+                "The surface doesn't support copying from swapchain images.",
+            ));
+        }
+        let image = &self
+            .image_list
+            .get_image_from_handle(self.facade.swapchain_images[self.swapchain_idx])
+            .expect("Swapchain image not found in the context.")
+            .image;
+        let texel_size = screenshot_texel_size(image.format)?;
+        let readback_buffer = HostVisibleBuffer::new_host_cached(
+            "screenshot_readback",
+            texel_size * (image.width * image.height) as usize,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            &self.gpu,
+            &self.debug_utils,
+        );
+        record_screenshot_copy(
+            image,
+            &readback_buffer,
+            &self.gpu.device,
+            self.command_buffers[self.swapchain_idx],
+        )?;
+        Ok(readback_buffer)
+    }
+
+    // Blocks until the frame that copied the swapchain image completes
+    fn save_screenshot(
+        &self,
+        path: &str,
+        readback_buffer: &HostVisibleBuffer,
+        fence: vk::Fence,
+    ) -> Result<(), String> {
+        unsafe {
+            self.gpu
+                .device
+                .wait_for_fences(&[fence], true, u64::MAX)
+                .expect("Failed to wait for Fence.");
+        }
+        let width = self.facade.swapchain_width;
+        let height = self.facade.swapchain_height;
+        let format = self.facade.surface_format.format;
+        let texel_size = screenshot_texel_size(format)?;
+        let mut texels = vec![0u8; texel_size * (width * height) as usize];
+        readback_buffer
+            .download_data(&mut texels, 0)
+            .map_err(|e| e.to_string())?;
+        let rgba =
+            screenshot_to_rgba8(&texels, width, height, texel_size * width as usize, format)?;
+        save_screenshot_png(path, &rgba, width, height)
+    }
+
     pub fn end_frame(&mut self) {
+        // F3 saves a report of the frame's graph, now that its passes have been added
+        if self.input.was_key_pressed(VirtualKeyCode::F3) {
+            match self.export_graph_report(GRAPH_REPORT_PATH) {
+                Ok(()) => println!("Saved graph report to `{}`.", GRAPH_REPORT_PATH),
+                Err(e) => println!("{}", e),
+            }
+        }
+        self.evaluate_frame_budgets();
+        record_crash_frame(
+            &self.last_frame_stats,
+            self.builder_passes
+                .iter()
+                .map(|(_, pass)| pass.name.clone())
+                .collect(),
+        );
+        // F12 saves a screenshot of this frame
+        if self.input.was_key_pressed(VirtualKeyCode::F12) {
+            self.capture_screenshot(&default_screenshot_path());
+        }
+        let opt_screenshot =
+            self.opt_screenshot_path
+                .take()
+                .and_then(|path| match self.record_screenshot_copy() {
+                    Ok(readback_buffer) => Some((path, readback_buffer)),
+                    Err(e) => {
+                        println!("Failed to save screenshot `{}`: {}", path, e);
+                        None
+                    }
+                });
+        self.ring_buffer.flush();
+        self.gpu_timer
+            .end_frame(self.command_buffers[self.swapchain_idx], self.sync_idx);
         // End command buffer. TODO: Is this in the right place?
         unsafe {
             self.gpu
@@ -355,7 +1005,7 @@ impl Context {
         let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
         let wait_semaphores = [self.facade.image_available_semaphores[self.sync_idx]];
         let signal_semaphores = [self.facade.render_finished_semaphores[self.sync_idx]];
-        let command_buffers = [self.command_buffers[self.swapchain_idx as usize]];
+        let command_buffers = [self.command_buffers[self.swapchain_idx]];
 
         let submit_infos = [vk::SubmitInfo {
             wait_semaphore_count: wait_semaphores.len() as u32,
@@ -384,6 +1034,8 @@ impl Context {
                 )
                 .expect("Failed to execute queue submit.");
         }
+        self.latency_tracker.on_submit();
+        let submitted_fence = self.facade.command_buffer_complete_fences[self.sync_idx];
         self.sync_idx = (self.sync_idx + 1) % self.facade.num_frames;
 
         let swapchains = [self.facade.swapchain];
@@ -400,12 +1052,25 @@ impl Context {
         // if it does happen. This works fine, when tested on Windows and on Linux on an
         // integrated GPU. If this fails on some other platform, consider calling
         // recreate_resolution_dependent_state() on error.
+        self.frame_pacer.wait_before_present();
         let _ = unsafe {
             self.facade
                 .ext_swapchain
                 .queue_present(self.gpu.present_queue, &present_info)
         };
+        self.frame_pacer.on_present();
+        self.latency_tracker.on_present();
+        if let Some((path, readback_buffer)) = opt_screenshot {
+            match self.save_screenshot(&path, &readback_buffer, submitted_fence) {
+                Ok(()) => println!("Saved screenshot to `{}`.", path),
+                Err(e) => println!("{}", e),
+            }
+        }
+        if is_debug_panic_requested() {
+            panic!("Panicking after a submitted frame, as `--debug-panic` asked.");
+        }
 
+        #[cfg(feature = "hot-reload")]
         for event in self.watch_rx.try_iter() {
             use notify::DebouncedEvent::*;
             match event {
@@ -416,20 +1081,243 @@ impl Context {
                             .device_wait_idle()
                             .expect("Failed to wait device idle!");
                     }
-                    self.shader_list.hot_reload(&mut self.graph_cache);
+                    let num_cached_graphs = self.graph_cache.len();
+                    self.shader_list
+                        .hot_reload(&mut self.graph_cache, &mut self.compute_pipelines);
+                    /* Graphs don't record which images they sample, so
+                    replacing any image drops all of them. Passes pick up the
+                    new images the next time they are added. */
+                    if self.image_list.hot_reload(
+                        &self.basis,
+                        &self.gpu,
+                        self.command_pool,
+                        &self.debug_utils,
+                    ) {
+                        self.graph_cache.clear();
+                        self.compute_pipelines.clear();
+                    }
+                    let num_evicted = num_cached_graphs - self.graph_cache.len();
+                    self.graph_stats.graphs_evicted += num_evicted;
+                    add_breadcrumb(&format!(
+                        "Hot reloaded assets, which evicted {} graphs.",
+                        num_evicted
+                    ));
                 }
                 _ => (),
             }
         }
     }
 
-    pub fn begin_pass(&self, graph_handle: GraphHandle, pass_handle: PassHandle) {
+    /* Returns false without recording anything if the pass is disabled by its
+    flag. In that case, skip the pass's draws and don't call `end_pass()`. A
+    skipped pass doesn't write its outputs, so passes that read them must
+    either be disabled too or not depend on their contents. Passes whose views
+    are rendered one at a time must be recorded with `record_pass()`. */
+    pub fn begin_pass(&mut self, graph_handle: GraphHandle, pass_handle: PassHandle) -> bool {
+        {
+            let (graph, _) = self
+                .graph_cache
+                .iter()
+                .find(|(_, cached_hash)| cached_hash.0 == graph_handle.0)
+                .expect("Graph not found in cache. Have you called build_graph()?");
+            let built_pass = graph.get_built_pass(pass_handle);
+            assert!(
+                built_pass.framebuffers.len() == 1,
+                "Pass `{}` renders its views one at a time, so it needs `record_pass()`.",
+                built_pass.label_name.to_string_lossy()
+            );
+        }
+        self.begin_first_view(graph_handle, pass_handle).is_some()
+    }
+
+    /* Begins the pass with its first view, and returns the offsets that the
+    other views are begun with, or None if the pass is disabled. */
+    fn begin_first_view(
+        &mut self,
+        graph_handle: GraphHandle,
+        pass_handle: PassHandle,
+    ) -> Option<(u32, Option<u32>)> {
+        let (graph, _) = self
+            .graph_cache
+            .iter()
+            .find(|(_, cached_hash)| cached_hash.0 == graph_handle.0)
+            .expect("Graph not found in cache. Have you called build_graph()?");
+        let command_buffer = self.command_buffers[self.swapchain_idx];
+        let built_pass = graph.get_built_pass(pass_handle);
+        if let Some(flag) = built_pass.opt_enabled_if {
+            if !self.pass_flags[flag.0].1 {
+                self.graph_stats.passes_skipped += 1;
+                return None;
+            }
+        }
+        self.debug_utils.begin_label_cstr(
+            command_buffer,
+            &built_pass.label_name,
+            built_pass.label_color,
+        );
+        let shader_debug_offset = self
+            .shader_debug
+            .begin_pass(self.sync_idx, &built_pass.label_name.to_string_lossy());
+        let opt_dynamic_uniform_offset = if built_pass.is_dynamic_uniform {
+            let (_, offset) = self
+                .dynamic_uniform_offsets
+                .iter()
+                .find(|(handle, _)| *handle == pass_handle)
+                .unwrap_or_else(|| {
+                    panic!(
+                        "Pass `{}` reads its uniforms with a dynamic offset, but they weren't written this frame.",
+                        built_pass.label_name.to_string_lossy()
+                    )
+                });
+            Some(*offset)
+        } else {
+            None
+        };
+        self.gpu_timer.begin_pass(
+            command_buffer,
+            self.sync_idx,
+            &built_pass.label_name.to_string_lossy(),
+        );
+        graph.begin_pass(
+            pass_handle,
+            self.sync_idx,
+            command_buffer,
+            shader_debug_offset,
+            opt_dynamic_uniform_offset,
+            0,
+        );
+        // The final layouts of the pass's render pass
+        for final_layout in &built_pass.final_layouts {
+            if let Some(internal_image) = self.image_list.get_image_from_handle(final_layout.image)
+            {
+                internal_image.image.set_layout(
+                    final_layout.mip,
+                    1,
+                    final_layout.base_layer,
+                    final_layout.num_layers,
+                    final_layout.layout,
+                );
+            }
+        }
+        Some((shader_debug_offset, opt_dynamic_uniform_offset))
+    }
+
+    /* Begin the pass, record its commands through `record`, and end it.
+    Returns false without calling `record` if the pass is disabled by its
+    flag. Passes whose views are rendered one at a time are begun and ended
+    once per view, and `record` is called for each of them. */
+    pub fn record_pass(
+        &mut self,
+        graph_handle: GraphHandle,
+        pass_handle: PassHandle,
+        mut record: impl FnMut(&PassRecorder),
+    ) -> bool {
+        let (shader_debug_offset, opt_dynamic_uniform_offset) =
+            match self.begin_first_view(graph_handle, pass_handle) {
+                Some(offsets) => offsets,
+                None => return false,
+            };
+        {
+            let (graph, _) = self
+                .graph_cache
+                .iter()
+                .find(|(_, cached_hash)| cached_hash.0 == graph_handle.0)
+                .expect("Graph not found in cache. Have you called build_graph()?");
+            let command_buffer = self.command_buffers[self.swapchain_idx];
+            let start_instant = std::time::Instant::now();
+            let recorder = PassRecorder::new(self, graph, pass_handle, command_buffer);
+            let num_recorded_views = graph.get_built_pass(pass_handle).framebuffers.len() as u32;
+            for view in 0..num_recorded_views {
+                if view > 0 {
+                    graph.end_pass(command_buffer);
+                    graph.begin_pass(
+                        pass_handle,
+                        self.sync_idx,
+                        command_buffer,
+                        shader_debug_offset,
+                        opt_dynamic_uniform_offset,
+                        view,
+                    );
+                }
+                record(&recorder);
+            }
+            let num_draws = recorder.num_draws();
+            let name = graph
+                .get_built_pass(pass_handle)
+                .label_name
+                .to_string_lossy()
+                .into_owned();
+            let record_ms = start_instant.elapsed().as_secs_f32() * 1000.0;
+            self.frame_stats
+                .pass_record_ms
+                .push((name.clone(), record_ms));
+            self.frame_stats.pass_draws.push((name, num_draws));
+        }
+        self.end_pass(graph_handle);
+        true
+    }
+
+    /* Like `record_pass()`, for draws from several systems. The draws are
+    sorted by their keys first, so `record` gets them in layer order, batched
+    by state, with opaque draws front to back and transparent ones back to
+    front. Build the list in the frame arena, so that it isn't allocated on
+    the heap. The state changes that sorting saved are in the frame's stats. */
+    pub fn record_sorted_pass<T: Copy>(
+        &mut self,
+        graph_handle: GraphHandle,
+        pass_handle: PassHandle,
+        draws: ArenaSlice<(SortKey, T)>,
+        mut record: impl FnMut(&PassRecorder, &[(SortKey, T)]),
+    ) -> bool {
+        let sorted_draws = self.frame_arena.get_mut(draws);
+        let (num_pipelines, num_materials) = count_state_changes(sorted_draws);
+        sort_draws(sorted_draws);
+        let (num_sorted_pipelines, num_sorted_materials) = count_state_changes(sorted_draws);
+        let is_recorded = self.record_pass(graph_handle, pass_handle, |recorder| {
+            record(recorder, recorder.context().frame_arena.get(draws))
+        });
+        if is_recorded {
+            // The name that `record_pass()` has just added the pass's stats under
+            let name = self.frame_stats.pass_draws.last().unwrap().0.clone();
+            self.frame_stats.pass_state_changes.push((
+                name,
+                num_pipelines + num_materials,
+                num_sorted_pipelines + num_sorted_materials,
+            ));
+        }
+        is_recorded
+    }
+
+    /// Bind a per-draw image in the current pass. This is cheap enough to call
+    /// for every draw. Shaders read it from `set = 1, binding = 0`.
+    pub fn bind_draw_image(
+        &self,
+        graph_handle: GraphHandle,
+        pass_handle: PassHandle,
+        image_handle: ImageHandle,
+        sampler: &Sampler,
+    ) {
         let (graph, _) = self
             .graph_cache
             .iter()
             .find(|(_, cached_hash)| cached_hash.0 == graph_handle.0)
             .expect("Graph not found in cache. Have you called build_graph()?");
-        graph.begin_pass(pass_handle, self.command_buffers[self.swapchain_idx])
+        let internal_image = self
+            .image_list
+            .get_image(image_handle)
+            .unwrap_or_else(|e| panic!("{}", e));
+        debug_assert_upload_complete(
+            &internal_image.image.opt_upload_ticket,
+            &internal_image.image.name,
+            &self.gpu,
+        );
+        graph.bind_draw_image(
+            pass_handle,
+            self.sync_idx,
+            self.command_buffers[self.swapchain_idx],
+            internal_image.image.image_view,
+            sampler.vk_sampler,
+        );
     }
 
     pub fn end_pass(&self, graph_handle: GraphHandle) {
@@ -438,7 +1326,10 @@ impl Context {
             .iter()
             .find(|(_, cached_hash)| cached_hash.0 == graph_handle.0)
             .expect("Graph not found in cache. Have you called build_graph()?");
-        graph.end_pass(self.command_buffers[self.swapchain_idx]);
+        let command_buffer = self.command_buffers[self.swapchain_idx];
+        graph.end_pass(command_buffer);
+        self.gpu_timer.end_pass(command_buffer, self.sync_idx);
+        self.debug_utils.end_label(command_buffer);
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -448,32 +1339,100 @@ impl Context {
         vertex_shader: ShaderHandle,
         fragment_shader: ShaderHandle,
         output_images: &[ImageHandle],
+        clear_color: Color,
         opt_depth_image: Option<ImageHandle>,
         uniform_buffer: BufferHandle,
         image_handle: ImageHandle,
         environment_sampler: &Sampler,
     ) -> Result<PassHandle, String> {
-        // TODO: Assert that color and depth images have the same resolution
+        if let Some(depth_handle) = opt_depth_image {
+            let depth_image = &self
+                .image_list
+                .get_image_from_handle(depth_handle)
+                .ok_or_else(|| format!("Depth image of pass `{}` not found.", name))?
+                .image;
+            for output_handle in output_images {
+                let output_image = &self
+                    .image_list
+                    .get_image_from_handle(*output_handle)
+                    .ok_or_else(|| format!("Output image of pass `{}` not found.", name))?
+                    .image;
+                if (output_image.width, output_image.height)
+                    != (depth_image.width, depth_image.height)
+                {
+                    return Err(format!(
+                        "Pass `{}` writes to `{}` ({}x{}), which doesn't match the resolution of depth image `{}` ({}x{}).",
+                        name,
+                        output_image.name,
+                        output_image.width,
+                        output_image.height,
+                        depth_image.name,
+                        depth_image.width,
+                        depth_image.height
+                    ));
+                }
+            }
+        }
         let img = self
             .image_list
-            .get_image_from_handle(image_handle)
-            .unwrap_or_else(|| {
-                panic!(
-                    "Image with handle `{:?}` not found in the context.",
-                    image_handle
-                )
-            });
+            .get_image(image_handle)
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        // Sized like the images, e.g. the layers of a shadow map, or else the swapchain
+        let (viewport_width, viewport_height) = output_images
+            .iter()
+            .chain(&opt_depth_image)
+            .find_map(|image_handle| self.image_list.get_image_from_handle(*image_handle))
+            .map(|internal_image| (internal_image.image.width, internal_image.image.height))
+            .unwrap_or((self.facade.swapchain_width, self.facade.swapchain_height));
+
+        /* Passes render into multisampled output images at their sample
+        count, and a resolve pass samples them later. */
+        let output_sample_counts: Vec<SampleCount> = output_images
+            .iter()
+            .filter_map(|image_handle| self.image_list.get_image_from_handle(*image_handle))
+            .map(|internal_image| SampleCount::from_vk(internal_image.image.samples))
+            .collect();
+        let sample_count = output_sample_counts
+            .first()
+            .copied()
+            .unwrap_or(SampleCount::X1);
+        if output_sample_counts
+            .iter()
+            .any(|count| *count != sample_count)
+        {
+            return Err(format!(
+                "Pass `{}` writes to output images with different sample counts.",
+                name
+            ));
+        }
 
         let pass = BuilderPass {
             name: String::from(name),
             vertex_shader,
             fragment_shader,
             output_images: output_images.to_owned(),
-            input_image: (img.image.image_view, environment_sampler.vk_sampler),
+            blend_modes: vec![BlendMode::Opaque; output_images.len()],
+            clear_color,
+            label_color: LABEL_COLOR_SCENE,
+            input_images: vec![(img.image.image_view, environment_sampler.vk_sampler)],
             opt_depth_image,
-            viewport_width: self.facade.swapchain_width,
-            viewport_height: self.facade.swapchain_height,
+            viewport_width,
+            viewport_height,
             uniform_buffer,
+            opt_enabled_if: None,
+            is_double_sided: false,
+            is_depth_biased: false,
+            depth_compare_op: CompareOp::Less,
+            opt_dynamic_uniforms: None,
+            opt_texel_buffer: None,
+            sample_count,
+            is_sample_shaded: false,
+            opt_resolve_source: None,
+            output_layer: 0,
+            output_mip: 0,
+            num_views: 1,
+            is_multiview: false,
         };
 
         let pass_handle = {
@@ -487,58 +1446,1092 @@ impl Context {
         Ok(pass_handle)
     }
 
-    /* Shaders */
-    pub fn new_shader(
-        &mut self,
-        name: &str,
-        shader_stage: ShaderStage,
-        path: &str,
-    ) -> Result<ShaderHandle, String> {
-        self.shader_list.new_shader(name, shader_stage, path)
+    /// Draw both sides of triangles. Shaders should flip the normals of back
+    /// faces, using `gl_FrontFacing`.
+    pub fn set_pass_double_sided(&mut self, pass_handle: PassHandle, is_double_sided: bool) {
+        self.get_builder_pass_mut(pass_handle).is_double_sided = is_double_sided;
     }
 
-    /* Buffers */
-    pub fn new_buffer(
-        &mut self,
-        name: &str,
-        size: usize,
-        usage: vk::BufferUsageFlags,
-    ) -> Result<BufferHandle, String> {
-        self.buffer_list
-            .new_buffer(name, size, usage, &self.gpu, &self.debug_utils)
+    /* Render into one layer of the pass's output and depth images that are
+    arrays. Images that aren't arrays are rendered to as usual. Call this right
+    after `add_pass()`, every time the pass is added. */
+    pub fn set_pass_output_layer(&mut self, pass_handle: PassHandle, layer: u32) {
+        let pass = self.get_builder_pass_mut(pass_handle);
+        let image_handles: Vec<ImageHandle> = pass
+            .output_images
+            .iter()
+            .chain(&pass.opt_depth_image)
+            .copied()
+            .collect();
+        for image_handle in image_handles {
+            if let Some(internal_image) = self.image_list.get_image_from_handle(image_handle) {
+                let image = &internal_image.image;
+                assert!(
+                    image.num_layers == 1 || layer < image.num_layers,
+                    "Pass renders into layer {} of `{}`, which has {} layers.",
+                    layer,
+                    image.name,
+                    image.num_layers
+                );
+            }
+        }
+        self.get_builder_pass_mut(pass_handle).output_layer = layer;
     }
 
-    pub fn upload_data<T>(&self, buffer_handle: BufferHandle, data: &[T]) {
-        self.buffer_list.upload_data(buffer_handle, data);
+    /* Render into one mip of the pass's output and depth images, e.g. for a
+    downsample chain, with the viewport sized to it. Call this right after
+    `add_pass()`, every time the pass is added. */
+    pub fn set_pass_output_mip(&mut self, pass_handle: PassHandle, mip: u32) {
+        let pass = self.get_builder_pass_mut(pass_handle);
+        let image_handles: Vec<ImageHandle> = pass
+            .output_images
+            .iter()
+            .chain(&pass.opt_depth_image)
+            .copied()
+            .collect();
+        let mut opt_viewport_size = None;
+        for image_handle in image_handles {
+            if let Some(internal_image) = self.image_list.get_image_from_handle(image_handle) {
+                let image = &internal_image.image;
+                assert!(
+                    mip < image.num_mips,
+                    "Pass renders into mip {} of `{}`, which has {} mips.",
+                    mip,
+                    image.name,
+                    image.num_mips
+                );
+                opt_viewport_size
+                    .get_or_insert(((image.width >> mip).max(1), (image.height >> mip).max(1)));
+            }
+        }
+        let pass = self.get_builder_pass_mut(pass_handle);
+        pass.output_mip = mip;
+        if let Some((viewport_width, viewport_height)) = opt_viewport_size {
+            pass.viewport_width = viewport_width;
+            pass.viewport_height = viewport_height;
+        }
     }
 
-    /* Images */
-    pub fn new_image_relative_size(
+    /* Render the pass with multisampling, resolved into its output images.
+    Counts that the device doesn't support are clamped down. Call this right
+    after `add_pass()`, every time the pass is added. */
+    pub fn set_pass_sample_count(&mut self, pass_handle: PassHandle, requested: SampleCount) {
+        let pass = self.get_builder_pass_mut(pass_handle);
+        let pass_name = pass.name.clone();
+        let output_handles = pass.output_images.clone();
+        for image_handle in output_handles {
+            if let Some(internal_image) = self.image_list.get_image_from_handle(image_handle) {
+                assert!(
+                    internal_image.image.samples == vk::SampleCountFlags::TYPE_1,
+                    "Pass `{}` renders into multisampled image `{}`, so it has its sample count.",
+                    pass_name,
+                    internal_image.image.name
+                );
+            }
+        }
+        let sample_count = self.gpu.supported_sample_count(requested);
+        self.get_builder_pass_mut(pass_handle).sample_count = sample_count;
+    }
+
+    /* Shade every sample of a multisampled pass instead of every texel, e.g.
+    to tonemap each sample of an image that a later pass resolves. Ignored if
+    the device can't shade per sample. Call this right after `add_pass()`,
+    every time the pass is added. */
+    pub fn set_pass_sample_shading(&mut self, pass_handle: PassHandle, is_sample_shaded: bool) {
+        self.get_builder_pass_mut(pass_handle).is_sample_shaded = is_sample_shaded;
+    }
+
+    /* A fullscreen pass that resolves `msaa_image` into `resolved_image` with
+    its own fragment shader, instead of the averaging that render passes do,
+    e.g. to weight HDR samples by their brightness. The shader reads the
+    samples with `texelFetch()` from a `sampler2DMS` at binding 1. Passes
+    that render into `msaa_image` leave it ready to be sampled. */
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_resolve_pass(
         &mut self,
         name: &str,
-        scale: f32,
-        format: vk::Format,
-        usage: vk::ImageUsageFlags,
-        aspect_flags: vk::ImageAspectFlags,
-    ) -> Result<ImageHandle, String> {
-        self.image_list.new_image_relative_size(
+        vertex_shader: ShaderHandle,
+        fragment_shader: ShaderHandle,
+        msaa_image: ImageHandle,
+        resolved_image: ImageHandle,
+        uniform_buffer: BufferHandle,
+        sampler: &Sampler,
+    ) -> Result<PassHandle, String> {
+        let find_image = |image_handle: ImageHandle| {
+            self.image_list
+                .get_image_from_handle(image_handle)
+                .map(|internal_image| &internal_image.image)
+                .ok_or_else(|| format!("Image of resolve pass `{}` not found.", name))
+        };
+        let source = find_image(msaa_image)?;
+        let target = find_image(resolved_image)?;
+        if source.samples == vk::SampleCountFlags::TYPE_1 {
+            return Err(format!(
+                "Resolve pass `{}` reads `{}`, which isn't multisampled.",
+                name, source.name
+            ));
+        }
+        if !source.usage.contains(vk::ImageUsageFlags::SAMPLED) {
+            return Err(format!(
+                "Resolve pass `{}` reads `{}`, which wasn't created with `ImageUsage::SAMPLED`.",
+                name, source.name
+            ));
+        }
+        if target.samples != vk::SampleCountFlags::TYPE_1
+            || (target.width, target.height) != (source.width, source.height)
+        {
+            return Err(format!(
+                "Resolve pass `{}` writes to `{}`, which must be single-sampled and {}x{} like `{}`.",
+                name, target.name, source.width, source.height, source.name
+            ));
+        }
+        let pass_handle = self.add_pass(
             name,
-            scale,
-            format,
-            usage,
-            aspect_flags,
-            &self.facade,
-            &self.gpu,
-            &self.debug_utils,
-        )
+            vertex_shader,
+            fragment_shader,
+            &[resolved_image],
+            Color::BLACK,
+            None,
+            uniform_buffer,
+            msaa_image,
+            sampler,
+        )?;
+        self.get_builder_pass_mut(pass_handle).opt_resolve_source = Some(msaa_image);
+        Ok(pass_handle)
     }
-    pub fn new_image_from_file(&mut self, name: &str, path: &str) -> Result<ImageHandle, String> {
-        self.image_list.new_image_from_file(
-            name,
-            path,
-            &self.gpu,
-            self.command_pool,
+
+    /// The highest sample count up to `requested` that the device supports,
+    /// logged if it's lower. For choosing the count once, e.g. at startup.
+    pub fn supported_sample_count(&self, requested: SampleCount) -> SampleCount {
+        let sample_count = self.gpu.supported_sample_count(requested);
+        if sample_count != requested {
+            println!(
+                "{:?} multisampling isn't supported. Using {:?} instead.",
+                requested, sample_count
+            );
+        }
+        sample_count
+    }
+
+    /// Passes keep fragments that are closer than the depth image (`Less`)
+    /// unless set otherwise. Call this right after `add_pass()`, every time
+    /// the pass is added.
+    pub fn set_pass_depth_compare_op(&mut self, pass_handle: PassHandle, compare_op: CompareOp) {
+        self.get_builder_pass_mut(pass_handle).depth_compare_op = compare_op;
+    }
+
+    /// Let the pass's shaders read a buffer view at `TEXEL_BUFFER_BINDING`.
+    /// Call this right after `add_pass()`, every time the pass is added.
+    pub fn set_pass_texel_buffer(&mut self, pass_handle: PassHandle, buffer_view: &BufferView) {
+        self.get_builder_pass_mut(pass_handle).opt_texel_buffer =
+            Some((buffer_view.vk_buffer_view, buffer_view.kind));
+    }
+
+    /// Allow the pass to set a depth bias with `PassRecorder::set_depth_bias()`,
+    /// e.g. to draw decals over coplanar geometry without z-fighting.
+    pub fn set_pass_depth_biased(&mut self, pass_handle: PassHandle) {
+        self.get_builder_pass_mut(pass_handle).is_depth_biased = true;
+    }
+
+    /* Write this frame's uniforms of a pass that was added with
+    `RING_UNIFORM_BUFFER`. They go into the ring buffer, so unlike uploading
+    to a buffer, this never overwrites data that a frame in flight reads. Call
+    this right after `add_pass()`, every frame. */
+    pub fn write_pass_uniforms<T>(&mut self, pass_handle: PassHandle, data: &[T]) {
+        let offset = self.ring_buffer.write_uniform(data);
+        let ring_vk_buffer = self.ring_buffer.buffer.vk_buffer;
+        self.set_pass_dynamic_uniforms(
+            pass_handle,
+            ring_vk_buffer,
+            std::mem::size_of_val(data),
+            offset as u32,
+        );
+    }
+
+    /// A `UniformBuffer` with a copy for every frame in flight.
+    pub fn new_uniform_buffer<T: Copy>(&self, name: &str) -> UniformBuffer<T> {
+        UniformBuffer::new(name, self.facade.num_frames, &self.gpu, &self.debug_utils)
+    }
+
+    /// Overwrite this frame's copy in `uniform_buffer`. The GPU is done
+    /// reading it, once `begin_frame()` has returned.
+    pub fn write_uniform_buffer<T: Copy>(&self, uniform_buffer: &UniformBuffer<T>, value: &T) {
+        uniform_buffer.write(self.sync_idx, value);
+    }
+
+    /* Read the uniforms of a pass that was added with `RING_UNIFORM_BUFFER`
+    from this frame's copy in `uniform_buffer`, instead of from the ring
+    buffer. Fails if the number of frames in flight has changed since the
+    uniform buffer was created, e.g. after the present mode changed, in which
+    case it must be created again. Call this right after `add_pass()`, every
+    frame. */
+    pub fn set_pass_uniform_buffer<T: Copy>(
+        &mut self,
+        pass_handle: PassHandle,
+        uniform_buffer: &UniformBuffer<T>,
+    ) -> Result<(), String> {
+        if uniform_buffer.num_frames() != self.facade.num_frames {
+            return Err(format!(
+                "Uniform buffer `{}` has copies for {} frames, but {} frames are in flight.",
+                uniform_buffer.buffer.name,
+                uniform_buffer.num_frames(),
+                self.facade.num_frames
+            ));
+        }
+        self.set_pass_dynamic_uniforms(
+            pass_handle,
+            uniform_buffer.buffer.vk_buffer,
+            uniform_buffer.descriptor_range() as usize,
+            uniform_buffer.dynamic_offset(self.sync_idx),
+        );
+        Ok(())
+    }
+
+    fn set_pass_dynamic_uniforms(
+        &mut self,
+        pass_handle: PassHandle,
+        vk_buffer: vk::Buffer,
+        size: usize,
+        offset: u32,
+    ) {
+        let pass = self.get_builder_pass_mut(pass_handle);
+        assert!(
+            pass.uniform_buffer == RING_UNIFORM_BUFFER,
+            "Pass `{}` wasn't added with `RING_UNIFORM_BUFFER`.",
+            pass.name
+        );
+        pass.opt_dynamic_uniforms = Some((vk_buffer, size));
+        self.dynamic_uniform_offsets.push((pass_handle, offset));
+    }
+
+    pub fn new_pass_flag(&mut self, name: &str, is_set: bool) -> PassFlagHandle {
+        self.pass_flags.push((String::from(name), is_set));
+        PassFlagHandle(self.pass_flags.len() - 1)
+    }
+
+    /// Takes effect on the next `begin_pass()`, without rebuilding any graph.
+    pub fn set_pass_flag(&mut self, flag: PassFlagHandle, is_set: bool) {
+        self.pass_flags[flag.0].1 = is_set;
+    }
+
+    pub fn is_pass_flag_set(&self, flag: PassFlagHandle) -> bool {
+        self.pass_flags[flag.0].1
+    }
+
+    /// Execute the pass only while `flag` is set. Call this right after
+    /// `add_pass()`, every time the pass is added.
+    pub fn set_pass_enabled_if(&mut self, pass_handle: PassHandle, flag: PassFlagHandle) {
+        self.get_builder_pass_mut(pass_handle).opt_enabled_if = Some(flag);
+    }
+
+    /// Passes are labeled with `LABEL_COLOR_SCENE` unless set otherwise. Call
+    /// this right after `add_pass()`, every time the pass is added.
+    pub fn set_pass_label_color(&mut self, pass_handle: PassHandle, color: Color) {
+        self.get_builder_pass_mut(pass_handle).label_color = color;
+    }
+
+    /// Output images are written without blending unless set otherwise. Call
+    /// this right after `add_pass()`, every time the pass is added.
+    pub fn set_pass_blend_mode(
+        &mut self,
+        pass_handle: PassHandle,
+        output_idx: usize,
+        blend_mode: BlendMode,
+    ) {
+        let pass = self.get_builder_pass_mut(pass_handle);
+        assert!(
+            output_idx < pass.blend_modes.len(),
+            "Pass `{}` has no output image at index {}.",
+            pass.name,
+            output_idx
+        );
+        pass.blend_modes[output_idx] = blend_mode;
+    }
+
+    /* Replace the image that the pass samples with several, e.g. the layers
+    of a G-buffer. Shaders read them as an array at binding 1. Call this right
+    after `add_pass()`, every time the pass is added. */
+    pub fn set_pass_input_images(
+        &mut self,
+        pass_handle: PassHandle,
+        image_handles: &[ImageHandle],
+        sampler: &Sampler,
+    ) {
+        assert!(
+            !image_handles.is_empty(),
+            "A pass needs at least one input image."
+        );
+        let input_images = image_handles
+            .iter()
+            .map(|image_handle| {
+                let img = self
+                    .image_list
+                    .get_image(*image_handle)
+                    .unwrap_or_else(|e| panic!("{}", e));
+                (img.image.image_view, sampler.vk_sampler)
+            })
+            .collect();
+        self.get_builder_pass_mut(pass_handle).input_images = input_images;
+    }
+
+    /* Sample a single mip of an image, with all of its layers, instead of
+    the pass's input images, e.g. the mip above the one that a downsample pass
+    renders into. The pass that writes the mip leaves it ready for sampling.
+    Call this right after `add_pass()`, every time the pass is added. */
+    pub fn set_pass_input_image_mip(
+        &mut self,
+        pass_handle: PassHandle,
+        image_handle: ImageHandle,
+        mip: u32,
+        sampler: &Sampler,
+    ) -> Result<(), String> {
+        let image = &self.image_list.get_image(image_handle)?.image;
+        let view_type = if image.depth > 1 {
+            vk::ImageViewType::TYPE_3D
+        } else if image.num_layers > 1 {
+            vk::ImageViewType::TYPE_2D_ARRAY
+        } else {
+            vk::ImageViewType::TYPE_2D
+        };
+        let image_view = image.create_view(mip, 1, 0, image.num_layers, view_type)?;
+        self.get_builder_pass_mut(pass_handle).input_images =
+            vec![(image_view, sampler.vk_sampler)];
+        Ok(())
+    }
+
+    /* Render the pass into `num_views` layers of its output and depth
+    images, from the one set with `set_pass_output_layer()` on, e.g. one per
+    eye. Shaders that include view_index.glsl read the layer's index as
+    `VIEW_INDEX`. With multiview, every view is rendered at once, by
+    permutations of the pass's shaders that are compiled with `MULTIVIEW`.
+    Otherwise, `record_pass()` records the pass once per view, so the draws
+    are the same either way. Call this right after `add_pass()`, every time
+    the pass is added. */
+    pub fn set_pass_view_count(
+        &mut self,
+        pass_handle: PassHandle,
+        num_views: u32,
+    ) -> Result<(), String> {
+        let pass = self.get_builder_pass_mut(pass_handle);
+        let pass_name = pass.name.clone();
+        let output_layer = pass.output_layer;
+        let (vertex_shader, fragment_shader) = (pass.vertex_shader, pass.fragment_shader);
+        let image_handles: Vec<ImageHandle> = pass
+            .output_images
+            .iter()
+            .chain(&pass.opt_depth_image)
+            .copied()
+            .collect();
+        if num_views == 0 {
+            return Err(format!("Pass `{}` needs at least one view.", pass_name));
+        }
+        for image_handle in image_handles {
+            let image = &self
+                .image_list
+                .get_image_from_handle(image_handle)
+                .ok_or_else(|| format!("Output image of pass `{}` not found.", pass_name))?
+                .image;
+            if output_layer + num_views > image.num_layers {
+                return Err(format!(
+                    "Pass `{}` renders {} views from layer {} of `{}`, which has {} layers.",
+                    pass_name, num_views, output_layer, image.name, image.num_layers
+                ));
+            }
+        }
+
+        let is_multiview =
+            self.is_multiview_enabled && num_views > 1 && num_views <= MIN_MAX_MULTIVIEW_VIEWS;
+        let shaders = if is_multiview {
+            (
+                self.shader_list
+                    .permutation(vertex_shader, MULTIVIEW_DEFINE)?,
+                self.shader_list
+                    .permutation(fragment_shader, MULTIVIEW_DEFINE)?,
+            )
+        } else {
+            (vertex_shader, fragment_shader)
+        };
+        let pass = self.get_builder_pass_mut(pass_handle);
+        pass.num_views = num_views;
+        pass.is_multiview = is_multiview;
+        pass.vertex_shader = shaders.0;
+        pass.fragment_shader = shaders.1;
+        Ok(())
+    }
+
+    /* Whether passes with several views render them all at once, which needs
+    VK_KHR_multiview. Disabling it renders them one at a time instead, e.g. to
+    compare the two. Passes added afterwards use the new setting. */
+    pub fn set_multiview_enabled(&mut self, is_enabled: bool) {
+        self.is_multiview_enabled = is_enabled && self.gpu.is_multiview_supported;
+    }
+
+    pub fn is_multiview_enabled(&self) -> bool {
+        self.is_multiview_enabled
+    }
+
+    /// Whether new buffers are filled with `DEBUG_FILL_PATTERN`. See
+    /// `DebugFill`. On by default in debug builds.
+    pub fn set_debug_fill_enabled(&self, is_enabled: bool) {
+        self.gpu.debug_fill.set_enabled(is_enabled);
+    }
+
+    fn get_builder_pass_mut(&mut self, pass_handle: PassHandle) -> &mut BuilderPass {
+        let (_, pass) = self
+            .builder_passes
+            .iter_mut()
+            .find(|(handle, _)| *handle == pass_handle)
+            .unwrap_or_else(|| panic!("Pass with handle `{}` not found in graph.", pass_handle.0));
+        pass
+    }
+
+    /* Shaders */
+    pub fn new_shader(
+        &mut self,
+        name: &str,
+        shader_stage: ShaderStage,
+        path: &str,
+    ) -> Result<ShaderHandle, String> {
+        self.shader_list.new_shader(name, shader_stage, path)
+    }
+
+    /// A shader compiled with `defines`, under a name of its own, e.g. to
+    /// select a variant with `#ifdef`.
+    pub fn new_shader_with_defines(
+        &mut self,
+        name: &str,
+        shader_stage: ShaderStage,
+        path: &str,
+        defines: &[&str],
+    ) -> Result<ShaderHandle, String> {
+        self.shader_list
+            .new_shader_with_defines(name, shader_stage, path, defines)
+    }
+
+    /// Wait until the GPU has finished all submitted work, e.g. before
+    /// destroying resources that frames in flight may still use.
+    pub fn wait_idle(&self) {
+        unsafe {
+            self.gpu
+                .device
+                .device_wait_idle()
+                .expect("Failed to wait device idle!");
+        }
+    }
+
+    /// Move an image into the layout for sampling, e.g. after a compute shader
+    /// wrote it. Passes already leave the images that later passes sample in
+    /// this layout.
+    pub fn transition_image_to_sampled(&self, image_handle: ImageHandle) {
+        let internal_image = self
+            .image_list
+            .get_image(image_handle)
+            .unwrap_or_else(|e| panic!("{}", e));
+        internal_image.image.transition(
+            self.command_buffers[self.swapchain_idx],
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+    }
+    /// Like `transition_image_to_sampled()`, for one mip that a pass wrote,
+    /// while passes still render into the others.
+    pub fn transition_image_mip_to_sampled(&self, image_handle: ImageHandle, mip: u32) {
+        let internal_image = self
+            .image_list
+            .get_image(image_handle)
+            .unwrap_or_else(|e| panic!("{}", e));
+        internal_image.image.transition_mips(
+            self.command_buffers[self.swapchain_idx],
+            mip,
+            1,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+    }
+
+    /* Run a compute shader over a storage image, between `begin_frame()` and
+    `end_frame()`, outside of any pass. The shader writes the image at
+    `set = 0, binding = 0` and gets `push_constants`, which can be empty. The
+    image is left in GENERAL layout, so call `transition_image_to_sampled()`
+    before the passes that sample it. */
+    pub fn dispatch_compute<T>(
+        &mut self,
+        shader_handle: ShaderHandle,
+        image_handle: ImageHandle,
+        num_groups: (u32, u32, u32),
+        push_constants: &[T],
+    ) {
+        let image = &self
+            .image_list
+            .get_image(image_handle)
+            .unwrap_or_else(|e| panic!("{}", e))
+            .image;
+        assert!(
+            image.usage.contains(vk::ImageUsageFlags::STORAGE),
+            "Image `{}` wasn't created with `ImageUsage::STORAGE`.",
+            image.name
+        );
+        let command_buffer = self.command_buffers[self.swapchain_idx];
+        image.transition(command_buffer, vk::ImageLayout::GENERAL);
+        let bindings = [ComputeBinding::StorageImage(image.image_view)];
+        self.dispatch_compute_with_bindings(shader_handle, &bindings, num_groups, push_constants);
+    }
+
+    /* Like `dispatch_compute()`, with any images and buffers, which the shader
+    reads at `set = 0` and the binding of their index in `bindings`. Nothing is
+    transitioned, so the images must already be in the layouts that their
+    bindings name, and the caller records the barriers between dispatches. */
+    pub fn dispatch_compute_with_bindings<T>(
+        &mut self,
+        shader_handle: ShaderHandle,
+        bindings: &[ComputeBinding],
+        num_groups: (u32, u32, u32),
+        push_constants: &[T],
+    ) {
+        assert!(
+            !bindings.is_empty(),
+            "Compute shaders are dispatched with at least one binding."
+        );
+        let shader = self
+            .shader_list
+            .get_shader(shader_handle)
+            .unwrap_or_else(|e| panic!("{}", e));
+        let opt_idx = self.compute_pipelines.iter().position(|pipeline| {
+            pipeline.shader_handle == shader_handle && pipeline.bindings == bindings
+        });
+        let idx = match opt_idx {
+            Some(idx) => idx,
+            None => {
+                self.compute_pipelines.push(ComputePipeline::new(
+                    &shader.name,
+                    shader_handle,
+                    shader.vk_shader_module,
+                    bindings,
+                    &self.pipeline_cache,
+                    &self.gpu,
+                ));
+                self.compute_pipelines.len() - 1
+            }
+        };
+
+        let command_buffer = self.command_buffers[self.swapchain_idx];
+        let push_constant_bytes = unsafe {
+            std::slice::from_raw_parts(
+                push_constants.as_ptr() as *const u8,
+                std::mem::size_of_val(push_constants),
+            )
+        };
+        self.compute_pipelines[idx].record_dispatch(
+            command_buffer,
+            push_constant_bytes,
+            num_groups,
+        );
+    }
+
+    /* Buffers */
+    pub fn new_buffer(
+        &mut self,
+        name: &str,
+        size: usize,
+        usage: BufferUsage,
+    ) -> Result<BufferHandle, String> {
+        self.buffer_list
+            .new_buffer(name, size, usage.to_vk(), &self.gpu, &self.debug_utils)
+    }
+
+    fn viewport_size(&self) -> Vec2 {
+        Vec2::new(
+            self.facade.swapchain_width as f32,
+            self.facade.swapchain_height as f32,
+        )
+    }
+
+    /// Ray from the camera through the cursor, for picking and placing objects.
+    pub fn cursor_ray(&self, camera: &Camera) -> Ray {
+        camera.screen_point_to_ray(self.input.cursor_position, self.viewport_size())
+    }
+
+    /// Nearest object under the cursor, of the ones that `bvh` was built from.
+    pub fn pick(&self, camera: &Camera, bvh: &Bvh) -> Option<BvhHit> {
+        bvh.raycast(&self.cursor_ray(camera))
+    }
+
+    /// Window position, in physical pixels, of a point in the world. None if it's behind the camera.
+    pub fn project(&self, camera: &Camera, world_position: Vec3) -> Option<Vec2> {
+        camera.world_to_screen(world_position, self.viewport_size())
+    }
+
+    /// World position of a window position, at a depth buffer value between 0 (near) and 1 (far).
+    pub fn unproject(&self, camera: &Camera, screen_point: Vec2, depth: f32) -> Vec3 {
+        camera.screen_to_world(screen_point, depth, self.viewport_size())
+    }
+
+    pub fn upload_data<T>(
+        &self,
+        buffer_handle: BufferHandle,
+        data: &[T],
+    ) -> Result<(), BufferError> {
+        self.buffer_list.upload_data(buffer_handle, data)
+    }
+
+    /// View part of a buffer that was created with `UNIFORM_TEXEL` or
+    /// `STORAGE_TEXEL` usage as texels. The view must be dropped first.
+    pub fn new_buffer_view(
+        &self,
+        name: &str,
+        buffer_handle: BufferHandle,
+        format: Format,
+        offset: usize,
+        range: usize,
+        kind: TexelBufferKind,
+    ) -> Result<BufferView, String> {
+        let buffer = self.buffer_list.get_buffer(buffer_handle)?;
+        BufferView::new(
+            name,
+            &buffer.name,
+            buffer.vk_buffer,
+            buffer.size,
+            format.to_vk(),
+            offset,
+            range,
+            kind,
+            &self.basis,
+            &self.gpu,
             &self.debug_utils,
         )
+        .map_err(|e| e.to_string())
+    }
+
+    /// Rename a buffer for debugging. It keeps its handle, and can still be
+    /// found by the name it was created with.
+    pub fn set_buffer_debug_name(
+        &mut self,
+        buffer_handle: BufferHandle,
+        name: &str,
+    ) -> Result<(), String> {
+        let buffer = self.buffer_list.list.get_mut(buffer_handle)?;
+        buffer.set_debug_name(name, &self.debug_utils);
+        Ok(())
+    }
+
+    /* Reallocate a buffer if it's smaller than `size` bytes, optionally
+    keeping its contents. Returns true if it was reallocated, in which case the
+    cached graphs, which point to the old buffer, were dropped after waiting
+    for the GPU to go idle. Buffer views of it must be dropped first, and
+    created again. */
+    pub fn grow_buffer(
+        &mut self,
+        buffer_handle: BufferHandle,
+        size: usize,
+        should_keep_contents: bool,
+    ) -> Result<bool, String> {
+        if size <= self.buffer_list.get_buffer(buffer_handle)?.size {
+            return Ok(false);
+        }
+        self.clear_graph_cache();
+        let buffer = self.buffer_list.list.get_mut(buffer_handle)?;
+        Ok(buffer.ensure_capacity(
+            size,
+            should_keep_contents,
+            None,
+            &self.gpu,
+            &self.debug_utils,
+        ))
+    }
+
+    /// Destroy a buffer and the cached graphs, which might point to it. Waits
+    /// for the GPU to go idle. Its handles are stale afterwards, and fail with
+    /// an error that names it.
+    pub fn destroy_buffer(&mut self, buffer_handle: BufferHandle) -> Result<(), String> {
+        self.buffer_list.get_buffer(buffer_handle)?;
+        self.clear_graph_cache();
+        self.buffer_list.destroy_buffer(buffer_handle)
+    }
+
+    /* Record a small write to a device-local buffer into this frame's command
+    buffer. Cheaper than staging for a few bytes, like one object's transform.
+    Writes can't be recorded inside a render pass, so call this between
+    `begin_frame()` and `end_frame()`, before the `record_pass()` that reads
+    the buffer rather than from its closure. */
+    pub fn update_device_local_buffer<T>(
+        &mut self,
+        buffer: &DeviceLocalBuffer,
+        offset: usize,
+        data: &[T],
+    ) -> Result<(), BufferError> {
+        update_device_local(
+            &self.gpu.device,
+            self.command_buffers[self.swapchain_idx],
+            buffer,
+            offset,
+            data,
+        )?;
+        self.inline_update_bytes += std::mem::size_of_val(data) as u64;
+        Ok(())
     }
+
+    /* Images */
+    /// Rename an image for debugging, including in the graph report. It
+    /// keeps its handle, and can still be found by the name it was created with.
+    pub fn set_image_debug_name(
+        &mut self,
+        image_handle: ImageHandle,
+        name: &str,
+    ) -> Result<(), String> {
+        let internal_image = self.image_list.list.get_mut(image_handle)?;
+        internal_image.image.set_debug_name(name, &self.debug_utils);
+        Ok(())
+    }
+
+    /// Like `destroy_buffer()`. Swapchain images belong to the facade, and
+    /// can't be destroyed.
+    pub fn destroy_image(&mut self, image_handle: ImageHandle) -> Result<(), String> {
+        let internal_image = self.image_list.get_image(image_handle)?;
+        if let ImageKind::Swapchain = internal_image.kind {
+            return Err(format!(
+                "Image `{}` belongs to the swapchain, and can't be destroyed.",
+                internal_image.image.name
+            ));
+        }
+        self.clear_graph_cache();
+        self.image_list.destroy_image(image_handle)
+    }
+
+    /// Depth formats get a depth view, and other formats a color view.
+    pub fn new_image(
+        &mut self,
+        name: &str,
+        size: ImageSize,
+        format: Format,
+        usage: ImageUsage,
+    ) -> Result<ImageHandle, String> {
+        self.image_list.new_image(
+            name,
+            size,
+            1,
+            SampleCount::X1,
+            format.to_vk(),
+            usage.to_vk(),
+            format.aspect_flags(),
+            &self.facade,
+            &self.basis,
+            &self.gpu,
+            &self.debug_utils,
+        )
+    }
+    pub fn new_image_relative_size(
+        &mut self,
+        name: &str,
+        scale: f32,
+        format: Format,
+        usage: ImageUsage,
+    ) -> Result<ImageHandle, String> {
+        self.new_image(name, ImageSize::SwapchainRelative(scale), format, usage)
+    }
+    /* A color image that passes render to, e.g. the scene before post
+    processing, and that later passes sample or read back. Call
+    `transition_image_to_sampled()` between the pass that writes it and the
+    ones that read it. */
+    pub fn new_render_target(
+        &mut self,
+        name: &str,
+        size: ImageSize,
+        format: Format,
+    ) -> Result<ImageHandle, String> {
+        self.new_image(
+            name,
+            size,
+            format,
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC,
+        )
+    }
+    /* A color image that passes render to with multisampling, at the highest
+    count up to `requested` that the device supports. Unlike the images that
+    `set_pass_sample_count()` creates, it is resolved by a pass added with
+    `add_resolve_pass()`, which samples it. */
+    pub fn new_multisampled_render_target(
+        &mut self,
+        name: &str,
+        size: ImageSize,
+        format: Format,
+        requested: SampleCount,
+    ) -> Result<ImageHandle, String> {
+        let sample_count = self.supported_sample_count(requested);
+        self.image_list.new_image(
+            name,
+            size,
+            1,
+            sample_count,
+            format.to_vk(),
+            (ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED).to_vk(),
+            format.aspect_flags(),
+            &self.facade,
+            &self.basis,
+            &self.gpu,
+            &self.debug_utils,
+        )
+    }
+    /* Like `new_render_target()`, with `num_mips` mips that passes render
+    into one at a time with `set_pass_output_mip()`, e.g. for a bloom chain.
+    Swapchain-relative targets drop the mips that don't fit after a resize. */
+    pub fn new_render_target_with_mips(
+        &mut self,
+        name: &str,
+        size: ImageSize,
+        format: Format,
+        num_mips: u32,
+    ) -> Result<ImageHandle, String> {
+        self.image_list.new_image(
+            name,
+            size,
+            num_mips,
+            SampleCount::X1,
+            format.to_vk(),
+            (ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC).to_vk(),
+            format.aspect_flags(),
+            &self.facade,
+            &self.basis,
+            &self.gpu,
+            &self.debug_utils,
+        )
+    }
+    /// Loaded with a full mip chain, as linear data.
+    pub fn new_image_from_file(&mut self, name: &str, path: &str) -> Result<ImageHandle, String> {
+        self.new_image_from_file_with_mips(name, path, true)
+    }
+    pub fn new_image_from_file_with_mips(
+        &mut self,
+        name: &str,
+        path: &str,
+        with_mips: bool,
+    ) -> Result<ImageHandle, String> {
+        self.image_list.new_image_from_file(
+            name,
+            path,
+            with_mips,
+            ColorSpace::Linear,
+            &self.basis,
+            &self.gpu,
+            self.command_pool,
+            &self.debug_utils,
+        )
+    }
+    /// Loaded with a full mip chain, e.g. `ColorSpace::Srgb` for albedo maps
+    /// and `ColorSpace::Linear` for normal maps.
+    pub fn new_image_from_file_with_color_space(
+        &mut self,
+        name: &str,
+        path: &str,
+        color_space: ColorSpace,
+    ) -> Result<ImageHandle, String> {
+        self.image_list.new_image_from_file(
+            name,
+            path,
+            true,
+            color_space,
+            &self.basis,
+            &self.gpu,
+            self.command_pool,
+            &self.debug_utils,
+        )
+    }
+    /// With the mips and layers stored in the file, which must not be
+    /// supercompressed.
+    pub fn new_image_from_ktx2(&mut self, name: &str, path: &str) -> Result<ImageHandle, String> {
+        self.image_list.new_image_from_ktx2(
+            name,
+            path,
+            &self.basis,
+            &self.gpu,
+            self.command_pool,
+            &self.debug_utils,
+        )
+    }
+    /// Block-compressed, with the mips and layers stored in the file. BC6H
+    /// and signed formats need a device that can sample them.
+    pub fn new_image_from_dds(&mut self, name: &str, path: &str) -> Result<ImageHandle, String> {
+        self.image_list.new_image_from_dds(
+            name,
+            path,
+            &self.basis,
+            &self.gpu,
+            self.command_pool,
+            &self.debug_utils,
+        )
+    }
+    /* Every mip of an image, e.g. block-compressed data from an offline
+    encoder. Each of `mips` holds all layers of its mip, and BC mips are
+    sized in whole 4x4 blocks. BC images that the device can't sample are
+    decoded to RGBA8, except for BC6H and signed formats, which are an
+    error. */
+    pub fn new_image_from_mip_data(
+        &mut self,
+        name: &str,
+        size: Extent,
+        num_layers: u32,
+        format: Format,
+        mips: &[&[u8]],
+    ) -> Result<ImageHandle, String> {
+        self.image_list.new_image_from_mip_data(
+            name,
+            (size.width, size.height, size.depth),
+            num_layers,
+            format.to_vk(),
+            mips,
+            &self.basis,
+            &self.gpu,
+            self.command_pool,
+            &self.debug_utils,
+        )
+    }
+    /// A `size` with a depth of more than 1 creates a 3D image.
+    pub fn new_image_from_data(
+        &mut self,
+        name: &str,
+        size: Extent,
+        format: Format,
+        data: &[u8],
+    ) -> Result<ImageHandle, String> {
+        if format.is_block_compressed() {
+            return Err(format!(
+                "Image `{}` is block-compressed, so its mips must be given with `new_image_from_mip_data()`.",
+                name
+            ));
+        }
+        self.image_list.new_image_from_data(
+            name,
+            (size.width, size.height, size.depth),
+            format.to_vk(),
+            data,
+            &self.gpu,
+            self.command_pool,
+            &self.debug_utils,
+        )
+    }
+    /* A 2D array, e.g. for the cascades of a shadow map. Shaders sample it
+    as a `sampler2DArray`, and `set_pass_output_layer()` makes a pass render
+    into a single layer. */
+    pub fn new_image_array(
+        &mut self,
+        name: &str,
+        size: Extent,
+        num_layers: u32,
+        format: Format,
+        usage: ImageUsage,
+    ) -> Result<ImageHandle, String> {
+        self.image_list.new_image_array(
+            name,
+            (size.width, size.height),
+            num_layers,
+            format.to_vk(),
+            usage.to_vk(),
+            format.aspect_flags(),
+            &self.basis,
+            &self.gpu,
+            &self.debug_utils,
+        )
+    }
+    /// A sampled 2D array, e.g. of sprites, with the layers one after the
+    /// other in `data`.
+    pub fn new_image_array_from_data(
+        &mut self,
+        name: &str,
+        size: Extent,
+        num_layers: u32,
+        format: Format,
+        data: &[u8],
+    ) -> Result<ImageHandle, String> {
+        self.image_list.new_image_array_from_data(
+            name,
+            (size.width, size.height),
+            num_layers,
+            format.to_vk(),
+            data,
+            &self.gpu,
+            self.command_pool,
+            &self.debug_utils,
+        )
+    }
+    /// Doesn't block. Passes must not sample the image before the ticket completes.
+    pub fn new_image_from_data_async(
+        &mut self,
+        name: &str,
+        size: Extent,
+        format: Format,
+        data: &[u8],
+    ) -> Result<(ImageHandle, UploadTicket), String> {
+        self.image_list.new_image_from_data_async(
+            name,
+            (size.width, size.height, size.depth),
+            format.to_vk(),
+            data,
+            &self.upload_context,
+            &self.gpu,
+            &self.debug_utils,
+        )
+    }
+    /// Upload the arguments of indirect draws. Blocks until the upload is done.
+    pub fn new_indirect_buffer<T: IndirectCommand>(
+        &self,
+        name: &str,
+        commands: &[T],
+    ) -> DeviceLocalBuffer {
+        DeviceLocalBuffer::new(
+            name,
+            commands,
+            BufferUsage::INDIRECT.to_vk(),
+            &self.gpu,
+            self.command_pool,
+            &self.debug_utils,
+        )
+    }
+
+    /// Doesn't block. Passes must not draw with the buffer before the ticket completes.
+    pub fn upload_buffer_async<T>(
+        &self,
+        name: &str,
+        data: &[T],
+        usage: BufferUsage,
+    ) -> (DeviceLocalBuffer, UploadTicket) {
+        self.upload_context
+            .upload_buffer(name, data, usage.to_vk(), &self.gpu, &self.debug_utils)
+    }
+
+    /* Write parts of an image, like `update_device_local_buffer()`, by
+    recording the copy into this frame's command buffer. Passes recorded
+    afterwards in the frame sample the new data. Call it between
+    `begin_frame()` and `end_frame()`, outside of `record_pass()`. */
+    pub fn update_image_regions(
+        &mut self,
+        image_handle: ImageHandle,
+        regions: &[ImageRegion],
+    ) -> Result<(), String> {
+        let internal_image = self.image_list.get_image(image_handle)?;
+        let opt_staging_chunk = internal_image.image.record_update_regions(
+            regions,
+            self.command_buffers[self.swapchain_idx],
+            &self.gpu,
+            &self.debug_utils,
+        )?;
+        if let Some(staging_chunk) = opt_staging_chunk {
+            self.frame_staging_chunks[self.sync_idx].push(staging_chunk);
+        }
+        Ok(())
+    }
+}
+
+fn new_ring_buffer(num_frames: usize, gpu: &Gpu, debug_utils: &DebugUtils) -> RingBuffer {
+    RingBuffer::new(
+        "ring_buffer",
+        RING_BUFFER_SIZE_PER_FRAME * num_frames,
+        num_frames,
+        vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::VERTEX_BUFFER,
+        gpu,
+        debug_utils,
+    )
+}
+
+fn log_surface_format(preference: SurfaceFormatPreference, facade: &Facade) {
+    println!(
+        "Swapchain format: {:?} in {:?}, for preference {:?}.",
+        facade.surface_format.format, facade.surface_format.color_space, preference
+    );
 }
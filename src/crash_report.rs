@@ -0,0 +1,126 @@
+use crate::*;
+use std::collections::VecDeque;
+use std::sync::{Mutex, Once, TryLockError};
+
+pub const CRASH_DIR: &str = "crash";
+// Panics on purpose after the first frame, to check what a crash bundle holds
+const DEBUG_PANIC_ARG: &str = "--debug-panic";
+const NUM_BREADCRUMBS: usize = 64;
+const NUM_FRAME_STATS: usize = 120;
+
+/* What a crash bundle is written from. The context copies its CPU-side state
+in here as it goes, since the panic hook can't reach the context, and the
+Vulkan objects may be in any state by the time it runs. */
+#[derive(Default)]
+struct CrashState {
+    capabilities: String,
+    breadcrumbs: VecDeque<String>, // Notable events, oldest first
+    frame_stats: VecDeque<String>, // Of the latest frames, oldest first
+    last_submission: Vec<String>,  // The passes recorded into the last submitted frame
+}
+
+static CRASH_STATE: Mutex<Option<CrashState>> = Mutex::new(None);
+static INSTALL_HOOK: Once = Once::new();
+
+fn with_state(f: impl FnOnce(&mut CrashState)) {
+    // A panic while the lock was held doesn't make the records wrong
+    let mut guard = CRASH_STATE.lock().unwrap_or_else(|e| e.into_inner());
+    f(guard.get_or_insert_with(CrashState::default));
+}
+
+/* Write a crash bundle on every panic, before the previous hook runs. The
+context installs this when it's created. Only the first call installs it. */
+pub fn install_crash_handler() {
+    INSTALL_HOOK.call_once(|| {
+        let previous_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            // A panic in here would abort, so failures are only printed
+            let backtrace = std::backtrace::Backtrace::force_capture().to_string();
+            match write_crash_bundle(&info.to_string(), &backtrace) {
+                Ok(dir) => println!("Saved a crash bundle to `{}`.", dir),
+                Err(e) => println!("Couldn't save a crash bundle: {}", e),
+            }
+            previous_hook(info);
+        }));
+    });
+}
+
+pub fn set_crash_capabilities(capabilities: String) {
+    with_state(|state| state.capabilities = capabilities);
+}
+
+/// Note an event that helps to explain a crash, like a swapchain recreation.
+pub fn add_breadcrumb(breadcrumb: &str) {
+    with_state(|state| {
+        if state.breadcrumbs.len() == NUM_BREADCRUMBS {
+            state.breadcrumbs.pop_front();
+        }
+        state.breadcrumbs.push_back(String::from(breadcrumb));
+    });
+}
+
+pub fn record_crash_frame(stats: &FrameStats, pass_names: Vec<String>) {
+    let stats_text = stats.to_text();
+    with_state(|state| {
+        if state.frame_stats.len() == NUM_FRAME_STATS {
+            state.frame_stats.pop_front();
+        }
+        state.frame_stats.push_back(stats_text);
+        state.last_submission = pass_names;
+    });
+}
+
+pub fn is_debug_panic_requested() -> bool {
+    std::env::args().any(|arg| arg == DEBUG_PANIC_ARG)
+}
+
+/* Write the bundle into a directory under `CRASH_DIR` that is named by the
+time, and return its path. Each section is a plain text file. */
+fn write_crash_bundle(message: &str, backtrace: &str) -> Result<String, String> {
+    let millis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or(0);
+    let dir = format!("{}/crash_{}", CRASH_DIR, millis);
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Couldn't create `{}`: {}", dir, e))?;
+
+    let mut files = vec![(
+        "panic.txt",
+        format!("{}\n\nBacktrace\n{}", message, backtrace),
+    )];
+    let messages = recent_validation_messages();
+    files.push((
+        "validation.txt",
+        format!(
+            "{} errors in total\n{}",
+            num_validation_errors(),
+            messages.join("\n")
+        ),
+    ));
+    // Don't wait on a lock that the panicking thread may hold
+    let opt_guard = match CRASH_STATE.try_lock() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    };
+    if let Some(guard) = opt_guard {
+        if let Some(state) = guard.as_ref() {
+            let join = |lines: &VecDeque<String>, separator: &str| {
+                lines
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<String>>()
+                    .join(separator)
+            };
+            files.push(("capabilities.txt", state.capabilities.clone()));
+            files.push(("breadcrumbs.txt", join(&state.breadcrumbs, "\n")));
+            files.push(("frame_stats.txt", join(&state.frame_stats, "\n\n")));
+            files.push(("last_submission.txt", state.last_submission.join("\n")));
+        }
+    }
+    for (file_name, contents) in files {
+        let path = format!("{}/{}", dir, file_name);
+        std::fs::write(&path, contents).map_err(|e| format!("Couldn't write `{}`: {}", path, e))?;
+    }
+    Ok(dir)
+}
@@ -0,0 +1,192 @@
+use crate::*;
+
+const DDS_MAGIC: [u8; 4] = *b"DDS ";
+const HEADER_SIZE: usize = 4 + 124; // Magic and header
+const DX10_HEADER_SIZE: usize = 20;
+const DDPF_FOURCC: u32 = 0x4;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_VOLUME: u32 = 0x20_0000;
+const DX10_MISC_TEXTURECUBE: u32 = 0x4;
+
+/* The parts of a DDS file that an upload needs. Only block-compressed
+formats are recognized, from their FourCC or DXGI format. Mip data is read
+from the file's bytes, starting at `data_offset`. DDS stores each layer's mip
+chain in turn, unlike KTX2, which stores each mip's layers in turn. */
+#[derive(Clone, Debug)]
+pub struct DdsHeader {
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32, // 1 unless it's a volume texture
+    pub num_mips: u32,
+    pub num_layers: u32,
+    pub is_cube_map: bool,
+    pub data_offset: usize,
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut le_bytes = [0; 4];
+    le_bytes.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(le_bytes)
+}
+
+fn fourcc_to_vk(fourcc: &[u8]) -> Option<vk::Format> {
+    match fourcc {
+        b"DXT1" => Some(vk::Format::BC1_RGBA_UNORM_BLOCK),
+        b"DXT2" | b"DXT3" => Some(vk::Format::BC2_UNORM_BLOCK),
+        b"DXT4" | b"DXT5" => Some(vk::Format::BC3_UNORM_BLOCK),
+        b"ATI1" | b"BC4U" => Some(vk::Format::BC4_UNORM_BLOCK),
+        b"BC4S" => Some(vk::Format::BC4_SNORM_BLOCK),
+        b"ATI2" | b"BC5U" => Some(vk::Format::BC5_UNORM_BLOCK),
+        b"BC5S" => Some(vk::Format::BC5_SNORM_BLOCK),
+        _ => None,
+    }
+}
+
+fn dxgi_format_to_vk(dxgi_format: u32) -> Option<vk::Format> {
+    match dxgi_format {
+        70 | 71 => Some(vk::Format::BC1_RGBA_UNORM_BLOCK),
+        72 => Some(vk::Format::BC1_RGBA_SRGB_BLOCK),
+        73 | 74 => Some(vk::Format::BC2_UNORM_BLOCK),
+        75 => Some(vk::Format::BC2_SRGB_BLOCK),
+        76 | 77 => Some(vk::Format::BC3_UNORM_BLOCK),
+        78 => Some(vk::Format::BC3_SRGB_BLOCK),
+        79 | 80 => Some(vk::Format::BC4_UNORM_BLOCK),
+        81 => Some(vk::Format::BC4_SNORM_BLOCK),
+        82 | 83 => Some(vk::Format::BC5_UNORM_BLOCK),
+        84 => Some(vk::Format::BC5_SNORM_BLOCK),
+        94 | 95 => Some(vk::Format::BC6H_UFLOAT_BLOCK),
+        96 => Some(vk::Format::BC6H_SFLOAT_BLOCK),
+        97 | 98 => Some(vk::Format::BC7_UNORM_BLOCK),
+        99 => Some(vk::Format::BC7_SRGB_BLOCK),
+        _ => None,
+    }
+}
+
+pub fn parse_dds(bytes: &[u8]) -> Result<DdsHeader, String> {
+    if bytes.len() < HEADER_SIZE || bytes[..4] != DDS_MAGIC {
+        return Err(String::from("Not a DDS file."));
+    }
+    let pixel_format_flags = read_u32(bytes, 80);
+    if pixel_format_flags & DDPF_FOURCC == 0 {
+        return Err(String::from(
+            "The file isn't block-compressed, which isn't supported.",
+        ));
+    }
+    let caps2 = read_u32(bytes, 112);
+    let fourcc = &bytes[84..88];
+    let mut is_cube_map = caps2 & DDSCAPS2_CUBEMAP != 0;
+    let (format, num_layers, data_offset) = if fourcc == b"DX10" {
+        if bytes.len() < HEADER_SIZE + DX10_HEADER_SIZE {
+            return Err(String::from("The DX10 header is truncated."));
+        }
+        let dxgi_format = read_u32(bytes, HEADER_SIZE);
+        let format = dxgi_format_to_vk(dxgi_format)
+            .ok_or_else(|| format!("DXGI format {} isn't supported.", dxgi_format))?;
+        is_cube_map |= read_u32(bytes, HEADER_SIZE + 8) & DX10_MISC_TEXTURECUBE != 0;
+        let num_layers = read_u32(bytes, HEADER_SIZE + 12).max(1);
+        (format, num_layers, HEADER_SIZE + DX10_HEADER_SIZE)
+    } else {
+        let format = fourcc_to_vk(fourcc).ok_or_else(|| {
+            format!(
+                "FourCC `{}` isn't supported.",
+                String::from_utf8_lossy(fourcc)
+            )
+        })?;
+        (format, 1, HEADER_SIZE)
+    };
+    // Counts of 0 mean that the field isn't used
+    let header = DdsHeader {
+        format,
+        width: read_u32(bytes, 16),
+        height: read_u32(bytes, 12).max(1),
+        depth: if caps2 & DDSCAPS2_VOLUME != 0 {
+            read_u32(bytes, 24).max(1)
+        } else {
+            1
+        },
+        num_mips: read_u32(bytes, 28).max(1),
+        num_layers,
+        is_cube_map,
+        data_offset,
+    };
+    if header.width == 0 {
+        return Err(String::from("The image has no width."));
+    }
+    let max_mips = num_mips_for_size(header.width, header.height.max(header.depth));
+    if header.num_mips > max_mips {
+        return Err(format!(
+            "The image has {} mips, but its size allows up to {}.",
+            header.num_mips, max_mips
+        ));
+    }
+    Ok(header)
+}
+
+impl Image {
+    /* Load a block-compressed DDS file with its mips as they are stored. 2D
+    images, arrays and 3D images are supported, but not cube maps. BC formats
+    that the device can't sample are decoded to RGBA8. */
+    pub fn new_from_dds(
+        path: &std::path::Path,
+        name: &str,
+        basis: &Basis,
+        gpu: &Gpu,
+        command_pool: vk::CommandPool,
+        debug_utils: &DebugUtils,
+    ) -> Result<Image, String> {
+        let bytes = std::fs::read(path)
+            .map_err(|e| format!("Failed to read `{}`: {}", path.display(), e))?;
+        let header =
+            parse_dds(&bytes).map_err(|e| format!("Failed to load `{}`: {}", path.display(), e))?;
+        if header.is_cube_map {
+            return Err(format!(
+                "`{}` is a cube map, which isn't supported.",
+                path.display()
+            ));
+        }
+
+        // Regroup the layers' mip chains into mips of every layer
+        let mip_sizes: Vec<usize> = (0..header.num_mips)
+            .map(|mip_level| {
+                mip_size_in_bytes(
+                    header.format,
+                    (header.width >> mip_level).max(1),
+                    (header.height >> mip_level).max(1),
+                    (header.depth >> mip_level).max(1),
+                )
+                .unwrap()
+            })
+            .collect();
+        let layer_size: usize = mip_sizes.iter().sum();
+        let data_end = header.data_offset + layer_size * header.num_layers as usize;
+        if bytes.len() < data_end {
+            return Err(format!(
+                "Failed to load `{}`: The mip data is truncated.",
+                path.display()
+            ));
+        }
+        let mut mips = vec![Vec::new(); header.num_mips as usize];
+        for layer in 0..header.num_layers as usize {
+            let mut offset = header.data_offset + layer * layer_size;
+            for (mip, mip_size) in mips.iter_mut().zip(mip_sizes.iter()) {
+                mip.extend_from_slice(&bytes[offset..offset + mip_size]);
+                offset += mip_size;
+            }
+        }
+        let mips: Vec<&[u8]> = mips.iter().map(|mip| mip.as_slice()).collect();
+
+        Image::new_from_mip_data(
+            name,
+            (header.width, header.height, header.depth),
+            header.num_layers,
+            header.format,
+            &mips,
+            basis,
+            gpu,
+            command_pool,
+            debug_utils,
+        )
+        .map_err(|e| format!("Failed to load `{}`: {}", path.display(), e))
+    }
+}
@@ -1,6 +1,34 @@
 use crate::*;
 use ash::vk::Handle;
+use std::collections::VecDeque;
 use std::os::raw::c_void;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// Command buffer label colors, so that captures are color-coded by system
+pub const LABEL_COLOR_SCENE: Color = Color::from_linear_f32(0.2, 0.4, 1.0, 1.0);
+pub const LABEL_COLOR_SHADOWS: Color = Color::from_linear_f32(0.5, 0.2, 0.9, 1.0);
+pub const LABEL_COLOR_POST: Color = Color::from_linear_f32(1.0, 0.5, 0.1, 1.0);
+pub const LABEL_COLOR_UI: Color = Color::from_linear_f32(0.2, 0.9, 0.3, 1.0);
+pub const LABEL_COLOR_UPLOAD: Color = Color::from_linear_f32(0.6, 0.6, 0.6, 1.0);
+pub const LABEL_COLOR_DEBUG: Color = Color::from_linear_f32(1.0, 0.1, 0.4, 1.0);
+
+// Errors reported through the messenger callback, for stress tests to check
+static NUM_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn num_validation_errors() -> usize {
+    NUM_ERRORS.load(Ordering::Relaxed)
+}
+
+// The latest warnings and errors, for pasting into bug reports
+const NUM_RECENT_MESSAGES: usize = 16;
+static RECENT_MESSAGES: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Oldest first.
+pub fn recent_validation_messages() -> Vec<String> {
+    let messages = RECENT_MESSAGES.lock().unwrap_or_else(|e| e.into_inner());
+    messages.iter().cloned().collect()
+}
 
 pub struct DebugUtils {
     device: ash::Device,
@@ -15,6 +43,7 @@ impl Drop for DebugUtils {
             if self.enable_messenger_callback {
                 self.ext
                     .destroy_debug_utils_messenger(self.debug_messenger, None);
+                audit_destroyed(VkObjectKind::DebugMessenger, self.debug_messenger);
             }
         }
     }
@@ -43,10 +72,20 @@ impl DebugUtils {
                     p_user_data: ptr::null_mut(),
                 };
 
-                unsafe {
+                let debug_messenger = unsafe {
                     ext.create_debug_utils_messenger(&messenger_ci, None)
                         .expect("Debug Utils Callback")
-                }
+                };
+                audit_created(
+                    VkObjectKind::DebugMessenger,
+                    debug_messenger,
+                    &[vk_object_id(
+                        VkObjectKind::Instance,
+                        basis.instance.handle(),
+                    )],
+                    "debug_messenger",
+                );
+                debug_messenger
             }
         };
 
@@ -59,11 +98,14 @@ impl DebugUtils {
     }
 
     fn set_object_name(&self, vk_raw_handle: u64, object_type: vk::ObjectType, name: &str) {
-        let c_name = CString::new(name).unwrap();
+        self.set_object_name_cstr(vk_raw_handle, object_type, &CString::new(name).unwrap());
+    }
+
+    fn set_object_name_cstr(&self, vk_raw_handle: u64, object_type: vk::ObjectType, name: &CStr) {
         let info = ash::vk::DebugUtilsObjectNameInfoEXT::builder()
             .object_type(object_type)
             .object_handle(vk_raw_handle)
-            .object_name(&c_name)
+            .object_name(name)
             .build();
         unsafe {
             self.ext
@@ -80,9 +122,64 @@ impl DebugUtils {
         self.set_object_name(vk_buffer.as_raw(), vk::ObjectType::BUFFER, name);
     }
 
+    pub fn set_image_view_name(&self, image_view: vk::ImageView, name: &str) {
+        self.set_object_name(image_view.as_raw(), vk::ObjectType::IMAGE_VIEW, name);
+    }
+
+    pub fn set_buffer_view_name(&self, buffer_view: vk::BufferView, name: &str) {
+        self.set_object_name(buffer_view.as_raw(), vk::ObjectType::BUFFER_VIEW, name);
+    }
+
+    pub fn set_sampler_name(&self, vk_sampler: vk::Sampler, name: &str) {
+        self.set_object_name(vk_sampler.as_raw(), vk::ObjectType::SAMPLER, name);
+    }
+
+    pub fn set_memory_name(&self, memory: vk::DeviceMemory, name: &str) {
+        self.set_object_name(memory.as_raw(), vk::ObjectType::DEVICE_MEMORY, name);
+    }
+
     pub fn set_command_buffer_name(&self, vk_cmd_buf: vk::CommandBuffer, name: &str) {
         self.set_object_name(vk_cmd_buf.as_raw(), vk::ObjectType::COMMAND_BUFFER, name);
     }
+
+    /// Same as `set_command_buffer_name()`, but doesn't allocate. For names set every frame.
+    pub fn set_command_buffer_name_cstr(&self, vk_cmd_buf: vk::CommandBuffer, name: &CStr) {
+        self.set_object_name_cstr(vk_cmd_buf.as_raw(), vk::ObjectType::COMMAND_BUFFER, name);
+    }
+
+    /// Open a labeled region in the command buffer. Must be paired with `end_label()`.
+    pub fn begin_label(&self, vk_cmd_buf: vk::CommandBuffer, name: &str, color: Color) {
+        self.begin_label_cstr(vk_cmd_buf, &CString::new(name).unwrap(), color);
+    }
+
+    /// Same as `begin_label()`, but doesn't allocate. For labels recorded every frame.
+    pub fn begin_label_cstr(&self, vk_cmd_buf: vk::CommandBuffer, name: &CStr, color: Color) {
+        let label = vk::DebugUtilsLabelEXT::builder()
+            .label_name(name)
+            .color(color.to_linear_f32())
+            .build();
+        unsafe {
+            self.ext.cmd_begin_debug_utils_label(vk_cmd_buf, &label);
+        }
+    }
+
+    pub fn end_label(&self, vk_cmd_buf: vk::CommandBuffer) {
+        unsafe {
+            self.ext.cmd_end_debug_utils_label(vk_cmd_buf);
+        }
+    }
+
+    /// A single labeled point in the command buffer, e.g. to mark a draw.
+    pub fn insert_label(&self, vk_cmd_buf: vk::CommandBuffer, name: &str, color: Color) {
+        let c_name = CString::new(name).unwrap();
+        let label = vk::DebugUtilsLabelEXT::builder()
+            .label_name(&c_name)
+            .color(color.to_linear_f32())
+            .build();
+        unsafe {
+            self.ext.cmd_insert_debug_utils_label(vk_cmd_buf, &label);
+        }
+    }
 }
 
 // Debug callbacks
@@ -105,8 +202,25 @@ unsafe extern "system" fn vulkan_debug_utils_callback(
         vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION => "[Validation]",
         _ => "[Unknown]",
     };
+    if message_severity == vk::DebugUtilsMessageSeverityFlagsEXT::ERROR {
+        NUM_ERRORS.fetch_add(1, Ordering::Relaxed);
+    }
     let message = CStr::from_ptr((*p_callback_data).p_message);
     println!("[Debug]{}{}{:?}", severity, types, message);
+    if message_severity == vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+        || message_severity == vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+    {
+        let mut messages = RECENT_MESSAGES.lock().unwrap_or_else(|e| e.into_inner());
+        if messages.len() == NUM_RECENT_MESSAGES {
+            messages.pop_front();
+        }
+        messages.push_back(format!(
+            "{}{} {}",
+            severity,
+            types,
+            message.to_string_lossy()
+        ));
+    }
 
     vk::FALSE
 }
@@ -0,0 +1,106 @@
+use crate::*;
+
+pub const ERROR_FRAGMENT_SHADER_PATH: &str = "error.frag";
+pub const CHECKERBOARD_SIZE: u32 = 64;
+const CHECKERBOARD_CELL_SIZE: u32 = 8;
+pub const DITHER_SIZE: u32 = 8; // Must be a power of two
+
+/* Images that the context always has, to stand in for missing content. They
+go through the same creation path as any other image, so creating them also
+smoke-tests image uploads at startup. */
+pub struct DefaultResources {
+    pub white_image: ImageHandle,
+    pub black_image: ImageHandle,
+    pub flat_normal_image: ImageHandle, // Tangent-space +Z, for missing normal maps
+    pub checkerboard_image: ImageHandle, // Magenta and black, for missing textures
+    pub dither_image: ImageHandle,      // Ordered dither thresholds, tiled by screen position
+}
+
+impl DefaultResources {
+    pub fn new(
+        image_list: &mut ImageList,
+        gpu: &Gpu,
+        command_pool: vk::CommandPool,
+        debug_utils: &DebugUtils,
+    ) -> DefaultResources {
+        let mut new_image = |name: &str, size: u32, texels: &[u8]| {
+            image_list
+                .new_image_from_data(
+                    name,
+                    (size, size, 1),
+                    vk::Format::R8G8B8A8_UNORM,
+                    texels,
+                    gpu,
+                    command_pool,
+                    debug_utils,
+                )
+                .expect("Failed to create default image.")
+        };
+
+        DefaultResources {
+            white_image: new_image("image_default_white", 1, &[255, 255, 255, 255]),
+            black_image: new_image("image_default_black", 1, &[0, 0, 0, 255]),
+            flat_normal_image: new_image("image_default_flat_normal", 1, &[128, 128, 255, 255]),
+            checkerboard_image: new_image(
+                "image_default_checkerboard",
+                CHECKERBOARD_SIZE,
+                &checkerboard_texels(CHECKERBOARD_SIZE),
+            ),
+            dither_image: new_image(
+                "image_default_dither",
+                DITHER_SIZE,
+                &dither_texels(DITHER_SIZE),
+            ),
+        }
+    }
+}
+
+/// RGBA8 texels of a magenta and black checkerboard.
+pub fn checkerboard_texels(size: u32) -> Vec<u8> {
+    let mut texels = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            let is_magenta =
+                (x / CHECKERBOARD_CELL_SIZE + y / CHECKERBOARD_CELL_SIZE).is_multiple_of(2);
+            if is_magenta {
+                texels.extend_from_slice(&[255, 0, 255, 255]);
+            } else {
+                texels.extend_from_slice(&[0, 0, 0, 255]);
+            }
+        }
+    }
+    texels
+}
+
+/* RGBA8 texels of a Bayer matrix. Every texel holds a different threshold
+in (0, 1), spread evenly, so that adding `threshold - 0.5` LSB before
+quantization breaks up banding without changing the local mean. */
+pub fn dither_texels(size: u32) -> Vec<u8> {
+    // Grow the matrix by doubling: M(2n) = [4M, 4M + 2; 4M + 3, 4M + 1]
+    let mut matrix = vec![0u32];
+    let mut n = 1;
+    while n < size {
+        let mut grown = vec![0u32; (4 * n * n) as usize];
+        for y in 0..2 * n {
+            for x in 0..2 * n {
+                let offset = match (x / n, y / n) {
+                    (0, 0) => 0,
+                    (1, 0) => 2,
+                    (0, _) => 3,
+                    _ => 1,
+                };
+                grown[(y * 2 * n + x) as usize] = 4 * matrix[(y % n * n + x % n) as usize] + offset;
+            }
+        }
+        matrix = grown;
+        n *= 2;
+    }
+
+    let num_levels = size * size;
+    let mut texels = Vec::with_capacity((num_levels * 4) as usize);
+    for index in matrix {
+        let threshold = ((2 * index + 1) * 255 / (2 * num_levels)) as u8;
+        texels.extend_from_slice(&[threshold, threshold, threshold, 255]);
+    }
+    texels
+}
@@ -1,9 +1,5 @@
-use ash::version::DeviceV1_0;
-use ash::vk;
 use glam::*;
-use std::f32::consts::PI;
-
-const DEGREES_TO_RADIANS: f32 = PI / 180.0;
+use winit::event::VirtualKeyCode;
 
 #[allow(dead_code)]
 struct UniformBuffer {
@@ -12,47 +8,46 @@ struct UniformBuffer {
     elapsed_seconds: f32,
     viewport_w: f32,
     viewport_h: f32,
+    dither_lsb: f32,     // Size of one output step, or 0 to disable dithering
+    is_output_srgb: f32, // 1 if the swapchain encodes to sRGB on write
+    tonemap_operator: f32,
+    exposure_scale: f32,
+}
+
+// Applied by the post pass, which writes to the swapchain
+struct PostSettings {
+    is_dither_enabled: bool,
+    tonemap_operator: graphene::TonemapOperator,
+    exposure_ev: f32,
 }
 
 fn execute_pass(
-    ctx: &mut graphene::Context,
+    recorder: &graphene::PassRecorder,
     elapsed_seconds: f32,
+    camera: &graphene::Camera,
     uniform_buffer: graphene::BufferHandle,
-    cmd_buf: vk::CommandBuffer,
     mesh: &graphene::Mesh,
+    opt_indirect_buffer: Option<&graphene::DeviceLocalBuffer>,
+    post_settings: &PostSettings,
 ) {
+    let ctx = recorder.context();
     // Update uniform buffer
     {
-        let cam_pos = Vec3::new(0.0, -4.5, 0.0);
-        let cam_rot = Quat::from_rotation_z((elapsed_seconds * 1.5).sin() * 0.1 * PI);
         let obj_pos = Vec3::new(0.0, 0.0, 0.0);
-        let obj_rot = Quat::from_rotation_z(elapsed_seconds * 0.3);
+        let obj_rot = Quat::from_rotation_y(elapsed_seconds * 0.3);
         let obj_scale = Vec3::new(1.0, 1.0, 1.0);
 
-        let mtx_rot_scale = Mat4::from_quat(obj_rot)
-            * Mat4::from_scale(obj_scale)
-            * Mat4::from_rotation_x(90.0 * DEGREES_TO_RADIANS);
-        let mtx_obj_to_world = Mat4::from_rotation_x(90.0 * DEGREES_TO_RADIANS)
-            * Mat4::from_translation(obj_pos)
-            * mtx_rot_scale;
-        let mtx_world_to_view = Mat4::from_rotation_x(90.0 * DEGREES_TO_RADIANS)
-            * Mat4::from_quat(cam_rot)
-            * Mat4::from_translation(-cam_pos)
-            * Mat4::from_rotation_x(-90.0 * DEGREES_TO_RADIANS);
+        let mtx_rot_scale = Mat4::from_quat(obj_rot) * Mat4::from_scale(obj_scale);
+        let mtx_obj_to_world = Mat4::from_translation(obj_pos) * mtx_rot_scale;
+        let mtx_world_to_view = camera.world_to_view();
         let mtx_view_to_clip = {
             let width = ctx.facade.swapchain_width;
             let height = ctx.facade.swapchain_height;
-            Mat4::perspective_lh(
-                60.0 * DEGREES_TO_RADIANS,
-                width as f32 / height as f32,
-                0.01,
-                100.0,
-            )
+            camera.view_to_clip(width as f32 / height as f32)
         };
 
         /* This matrix is an orthogonal matrix if scaling is uniform, in
         which case the inverse transpose is the same as the matrix itself.
-        // Pass 0
         However, we want to support non-uniform scaling, so we
         do the inverse transpose. */
         let mtx_norm_obj_to_world = mtx_rot_scale.inverse().transpose();
@@ -63,35 +58,180 @@ fn execute_pass(
             elapsed_seconds,
             viewport_w: ctx.facade.swapchain_width as f32,
             viewport_h: ctx.facade.swapchain_height as f32,
+            dither_lsb: if post_settings.is_dither_enabled {
+                1.0 / 255.0
+            } else {
+                0.0
+            },
+            is_output_srgb: if ctx.facade.is_srgb() { 1.0 } else { 0.0 },
+            tonemap_operator: post_settings.tonemap_operator.to_shader_value(),
+            exposure_scale: graphene::exposure_scale(post_settings.exposure_ev),
         }];
 
-        ctx.upload_data(uniform_buffer, &ubos);
+        ctx.upload_data(uniform_buffer, &ubos)
+            .unwrap_or_else(|e| panic!("Failed to upload uniforms: {}", e));
     }
-    // Bind index and vertex buffers
-    unsafe {
-        {
-            let vertex_buffers = [mesh.vertex_buffer.vk_buffer];
-            let offsets = [0_u64];
-            ctx.gpu
-                .device
-                .cmd_bind_vertex_buffers(cmd_buf, 0, &vertex_buffers, &offsets);
-            ctx.gpu.device.cmd_bind_index_buffer(
-                cmd_buf,
-                mesh.index_buffer.vk_buffer,
-                0,
-                vk::IndexType::UINT32,
-            );
-        }
+    recorder.bind_vertex_buffer(&mesh.vertex_buffer);
+    recorder.bind_index_buffer(&mesh.index_buffer);
+    match opt_indirect_buffer {
+        Some(indirect_buffer) => recorder.draw_indexed_indirect(indirect_buffer, 0, 1),
+        None => recorder.draw_indexed(mesh.index_buffer.num_elements as u32),
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum RenderPath {
+    Forward,
+    Deferred,
+}
+
+struct Resources {
+    shader_vertex: graphene::ShaderHandle,
+    shader_fullscreen_triangle_vertex: graphene::ShaderHandle,
+    shader_default: graphene::ShaderHandle,
+    shader_gbuffer: graphene::ShaderHandle,
+    shader_deferred_lighting: graphene::ShaderHandle,
+    shader_aberration: graphene::ShaderHandle,
+    shader_msaa_resolve: graphene::ShaderHandle,
+    depth_image: graphene::ImageHandle,
+    temp_image: graphene::ImageHandle,
+    gbuffer_images: Vec<graphene::ImageHandle>, // Albedo, normal, roughness/metalness
+    environment_image: graphene::ImageHandle,
+    environment_sampler: std::rc::Rc<graphene::Sampler>,
+    uniform_buffers: Vec<graphene::BufferHandle>,
+    is_mesh_double_sided: bool,
+    sample_count: graphene::SampleCount, // Of the forward pass
+    opt_msaa_image: Option<graphene::ImageHandle>, // Resolved by a pass, when multisampling
+    is_resolved_by_pass: bool,           // Instead of by the forward pass's render pass
+}
+
+struct Passes {
+    opt_gbuffer: Option<graphene::PassHandle>, // Only on the deferred path
+    lit: graphene::PassHandle,                 // Forward shading, or deferred lighting
+    opt_resolve: Option<graphene::PassHandle>, // Resolves the forward pass's samples
+    post: graphene::PassHandle,
+    opt_synthetic_load: Option<graphene::PassHandle>,
+}
 
-        ctx.gpu
-            .device
-            .cmd_draw_indexed(cmd_buf, mesh.index_buffer.num_elements as u32, 1, 0, 0, 0);
+fn add_passes(
+    ctx: &mut graphene::Context,
+    res: &Resources,
+    swapchain_idx: usize,
+    render_path: RenderPath,
+    opt_synthetic_load: Option<&graphene::SyntheticLoad>,
+    elapsed_seconds: f32,
+) -> Passes {
+    let uniform_buffer = res.uniform_buffers[swapchain_idx];
+    let background_color = graphene::Color::from_srgb8(32, 32, 40);
+    let (opt_gbuffer, pass_lit, opt_resolve) = match render_path {
+        RenderPath::Forward => {
+            let opt_resolved_msaa_image = res.opt_msaa_image.filter(|_| res.is_resolved_by_pass);
+            let pass_lit = ctx
+                .add_pass(
+                    "lit",
+                    res.shader_vertex,
+                    res.shader_default,
+                    &[opt_resolved_msaa_image.unwrap_or(res.temp_image)],
+                    background_color,
+                    Some(res.depth_image),
+                    uniform_buffer,
+                    res.environment_image,
+                    &res.environment_sampler,
+                )
+                .unwrap();
+            ctx.set_pass_double_sided(pass_lit, res.is_mesh_double_sided);
+            let opt_resolve = match opt_resolved_msaa_image {
+                Some(msaa_image) => Some(
+                    ctx.add_resolve_pass(
+                        "msaa_resolve",
+                        res.shader_fullscreen_triangle_vertex,
+                        res.shader_msaa_resolve,
+                        msaa_image,
+                        res.temp_image,
+                        uniform_buffer,
+                        &res.environment_sampler,
+                    )
+                    .unwrap_or_else(|e| panic!("{}", e)),
+                ),
+                None => {
+                    ctx.set_pass_sample_count(pass_lit, res.sample_count);
+                    None
+                }
+            };
+            (None, pass_lit, opt_resolve)
+        }
+        RenderPath::Deferred => {
+            // The albedo is cleared to the background, and lighting leaves the
+            // texels without geometry unlit
+            let pass_gbuffer = ctx
+                .add_pass(
+                    "gbuffer",
+                    res.shader_vertex,
+                    res.shader_gbuffer,
+                    &res.gbuffer_images,
+                    graphene::Color::TRANSPARENT,
+                    Some(res.depth_image),
+                    uniform_buffer,
+                    res.environment_image,
+                    &res.environment_sampler,
+                )
+                .unwrap();
+            ctx.set_pass_double_sided(pass_gbuffer, res.is_mesh_double_sided);
+            let pass_lit = ctx
+                .add_pass(
+                    "deferred_lighting",
+                    res.shader_fullscreen_triangle_vertex,
+                    res.shader_deferred_lighting,
+                    &[res.temp_image],
+                    background_color,
+                    None,
+                    uniform_buffer,
+                    res.environment_image,
+                    &res.environment_sampler,
+                )
+                .unwrap();
+            ctx.set_pass_input_images(pass_lit, &res.gbuffer_images, &res.environment_sampler);
+            (Some(pass_gbuffer), pass_lit, None)
+        }
+    };
+    let opt_synthetic_load_pass =
+        opt_synthetic_load.map(|load| load.add_pass(ctx, elapsed_seconds));
+    let pass_post = ctx
+        .add_pass(
+            "post",
+            res.shader_fullscreen_triangle_vertex,
+            res.shader_aberration,
+            &[ctx.facade.swapchain_images[swapchain_idx]],
+            graphene::Color::BLACK,
+            Some(res.depth_image),
+            uniform_buffer,
+            res.temp_image,
+            &res.environment_sampler,
+        )
+        .unwrap();
+    ctx.set_pass_label_color(pass_post, graphene::LABEL_COLOR_POST);
+    // The dither thresholds are read with texelFetch(), so the sampler doesn't matter
+    let post_input_images = [res.temp_image, ctx.default_resources.dither_image];
+    ctx.set_pass_input_images(pass_post, &post_input_images, &res.environment_sampler);
+    Passes {
+        opt_gbuffer,
+        lit: pass_lit,
+        opt_resolve,
+        post: pass_post,
+        opt_synthetic_load: opt_synthetic_load_pass,
     }
 }
 
 fn main() {
+    run();
+    // The context and everything created with it are gone by now
+    graphene::report_teardown_audit();
+}
+
+fn run() {
     let mut ctx = graphene::Context::new();
     let start_instant = std::time::Instant::now();
+    let mut last_frame_instant = start_instant;
 
     // TODO: Having to pass in debug_utils here is a little messy. Streamline.
     let mesh = graphene::Mesh::load(
@@ -101,25 +241,41 @@ fn main() {
         ctx.command_pool,
         &ctx.debug_utils,
     );
+    let depth_format = ctx.depth_format();
     let depth_image = ctx
         .new_image_relative_size(
             "image_depth",
             1.0,
-            vk::Format::D32_SFLOAT,
-            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
-            vk::ImageAspectFlags::DEPTH,
+            depth_format,
+            graphene::ImageUsage::DEPTH_ATTACHMENT,
         )
         .unwrap();
+    // Linear HDR, tonemapped by the post pass
     let temp_image = ctx
-        .new_image_relative_size(
+        .new_render_target(
             "image_temp",
-            1.0,
-            vk::Format::R8G8B8A8_SRGB,
-            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::COLOR_ATTACHMENT,
-            vk::ImageAspectFlags::COLOR,
+            graphene::ImageSize::SwapchainRelative(1.0),
+            graphene::Format::R16G16B16A16Sfloat,
         )
-        .unwrap();
-    let environment_sampler = graphene::Sampler::new(&ctx.gpu);
+        .unwrap_or_else(|e| panic!("{}", e));
+    let gbuffer_formats = [
+        ("image_gbuffer_albedo", graphene::Format::R8G8B8A8Unorm),
+        ("image_gbuffer_normal", graphene::Format::R16G16B16A16Sfloat),
+        ("image_gbuffer_material", graphene::Format::R8G8Unorm),
+    ];
+    let gbuffer_images: Vec<graphene::ImageHandle> = gbuffer_formats
+        .iter()
+        .map(|(name, format)| {
+            ctx.new_image_relative_size(
+                name,
+                1.0,
+                *format,
+                graphene::ImageUsage::SAMPLED | graphene::ImageUsage::COLOR_ATTACHMENT,
+            )
+            .unwrap()
+        })
+        .collect();
+    let environment_sampler = ctx.sampler(&graphene::SamplerDesc::default());
     let environment_image = ctx
         .new_image_from_file(
             "image_environment_map",
@@ -148,6 +304,20 @@ fn main() {
             "default.frag",
         )
         .unwrap();
+    let shader_gbuffer = ctx
+        .new_shader(
+            "shader_gbuffer",
+            graphene::ShaderStage::Fragment,
+            "gbuffer.frag",
+        )
+        .unwrap();
+    let shader_deferred_lighting = ctx
+        .new_shader(
+            "shader_deferred_lighting",
+            graphene::ShaderStage::Fragment,
+            "deferred_lighting.frag",
+        )
+        .unwrap();
     let shader_aberration = ctx
         .new_shader(
             "shader_aberration",
@@ -155,6 +325,13 @@ fn main() {
             "chromatic_aberration.frag",
         )
         .unwrap();
+    let shader_msaa_resolve = ctx
+        .new_shader(
+            "shader_msaa_resolve",
+            graphene::ShaderStage::Fragment,
+            "msaa_resolve.frag",
+        )
+        .unwrap();
 
     // TODO: Avoid having to create the vec. Automatically
     // creating a unique uniform buffer per frame
@@ -163,77 +340,511 @@ fn main() {
             ctx.new_buffer(
                 &format!("buffer_uniform_{}", i),
                 std::mem::size_of::<UniformBuffer>(),
-                vk::BufferUsageFlags::UNIFORM_BUFFER,
+                graphene::BufferUsage::UNIFORM,
             )
             .unwrap()
         })
         .collect();
 
+    // `--msaa` renders the forward path with 4x multisampling, or less if unsupported
+    let sample_count = if std::env::args().any(|arg| arg == "--msaa") {
+        ctx.supported_sample_count(graphene::SampleCount::X4)
+    } else {
+        graphene::SampleCount::X1
+    };
+    let opt_msaa_image = if sample_count.is_multisampled() {
+        Some(
+            ctx.new_multisampled_render_target(
+                "image_msaa",
+                graphene::ImageSize::SwapchainRelative(1.0),
+                graphene::Format::R16G16B16A16Sfloat,
+                sample_count,
+            )
+            .unwrap_or_else(|e| panic!("{}", e)),
+        )
+    } else {
+        None
+    };
+
+    let mut resources = Resources {
+        shader_vertex,
+        shader_fullscreen_triangle_vertex,
+        shader_default,
+        shader_gbuffer,
+        shader_deferred_lighting,
+        shader_aberration,
+        shader_msaa_resolve,
+        depth_image,
+        temp_image,
+        gbuffer_images,
+        environment_image,
+        environment_sampler,
+        uniform_buffers,
+        is_mesh_double_sided: mesh.is_double_sided,
+        sample_count,
+        opt_msaa_image,
+        is_resolved_by_pass: false,
+    };
+
+    // `--indirect-draws` draws the mesh with arguments from a buffer, so that the path stays tested
+    let opt_indirect_buffer = if std::env::args().any(|arg| arg == "--indirect-draws") {
+        let commands = [graphene::DrawIndexedIndirectCommand {
+            num_indices: mesh.index_buffer.num_elements as u32,
+            num_instances: 1,
+            ..Default::default()
+        }];
+        Some(ctx.new_indirect_buffer("buffer_draw_arguments", &commands))
+    } else {
+        None
+    };
+
+    /* `--deferred` starts on the deferred shading path, and G switches
+    between paths. Only the starting path is warmed up, so switching builds
+    the other path's graphs on first use. */
+    let mut render_path = if std::env::args().any(|arg| arg == "--deferred") {
+        RenderPath::Deferred
+    } else {
+        RenderPath::Forward
+    };
+
+    // Dithering is only applied on 8-bit swapchains. B toggles it, to compare banding.
+    let mut is_dither_requested = true;
+    // T cycles the tonemapping operator, and PageUp/PageDown change the exposure
+    let mut tonemap_operator = graphene::TonemapOperator::Aces;
+    let mut exposure_ev = 0.0_f32;
+
+    /* `--synthetic-load MS` adds a fullscreen pass of busy work after the
+    scene, tuned every frame until frames take MS milliseconds, to test
+    pacing and throttling under load. Run without vsync, because the tuning
+    measures whole frames. Only debug builds and builds with the
+    `synthetic-load` feature allow it. */
+    let mut opt_synthetic_load = {
+        let args: Vec<String> = std::env::args().collect();
+        args.iter()
+            .position(|arg| arg == "--synthetic-load")
+            .map(|idx| {
+                let target_ms = args
+                    .get(idx + 1)
+                    .and_then(|n| n.parse().ok())
+                    .expect("Expected a number of milliseconds after --synthetic-load.");
+                graphene::SyntheticLoad::new(&mut ctx, 256, Some(target_ms))
+                    .unwrap_or_else(|e| panic!("{}", e))
+            })
+    };
+
+    // Build the graph for every swapchain image up front, so that no pipelines
+    // get created in the middle of the frame loop.
+    for i in 0..ctx.facade.num_frames {
+        println!("Warming up graph {}/{}", i + 1, ctx.facade.num_frames);
+        add_passes(
+            &mut ctx,
+            &resources,
+            i,
+            render_path,
+            opt_synthetic_load.as_ref(),
+            0.0,
+        );
+        ctx.warmup_graph();
+    }
+    ctx.finish_warmup();
+
+    // Orbit around the mesh. 1/2/3 switch between orbit, fly and first-person
+    // cameras, and F frames the mesh.
+    let mut camera_rig = graphene::CameraRig::new_orbit(Vec3::zero(), 4.5, 0.0, 0.0);
+    camera_rig.focus_on(&mesh.aabb);
+
+    /* `--stress-resize N` recreates the resolution-dependent state N times,
+    rendering a few frames after each, and fails if any kind of GPU object
+    has more live instances than after the first cycle. */
+    let opt_stress_resize_cycles: Option<usize> = {
+        let args: Vec<String> = std::env::args().collect();
+        args.iter()
+            .position(|arg| arg == "--stress-resize")
+            .map(|idx| {
+                args.get(idx + 1)
+                    .and_then(|n| n.parse().ok())
+                    .expect("Expected a number of cycles after --stress-resize.")
+            })
+    };
+    let mut stress_frame_count = 0;
+    let mut opt_stress_baseline = None;
+
+    /* `--stress-sizes` resizes the window through sizes that are known to
+    break extent math, rendering a few frames at each, and fails on any
+    validation error. */
+    const STRESS_SIZES: [(u32, u32); 7] = [
+        (1, 37),
+        (37, 1),
+        (1, 1),
+        (2, 2),
+        (7680, 1440),
+        (16384, 16384),
+        (800, 600),
+    ];
+    let is_stress_sizes = std::env::args().any(|arg| arg == "--stress-sizes");
+    let mut stress_size_frame_count = 0;
+
+    /* `--benchmark-frames N` renders N frames with a fixed timestep, then
+    prints frame time statistics and writes them to _cache/benchmark.json.
+    With `--camera-path FILE`, the camera follows a recorded path instead of
+    being controlled by input. `--record-camera-path FILE` records a path: K
+    adds the current camera as a keyframe, and the path is saved on exit. */
+    const BENCHMARK_DT: f32 = 1.0 / 60.0;
+    let opt_arg_value = |name: &str| -> Option<String> {
+        let args: Vec<String> = std::env::args().collect();
+        args.iter().position(|arg| arg == name).map(|idx| {
+            args.get(idx + 1)
+                .cloned()
+                .unwrap_or_else(|| panic!("Expected a value after {}.", name))
+        })
+    };
+    let opt_benchmark_frames: Option<usize> = opt_arg_value("--benchmark-frames").map(|n| {
+        n.parse()
+            .expect("Expected a number of frames after --benchmark-frames.")
+    });
+    let opt_camera_path = opt_arg_value("--camera-path")
+        .map(|path| graphene::CameraPath::load(&path).unwrap_or_else(|e| panic!("{}", e)));
+    let opt_record_path = opt_arg_value("--record-camera-path");
+    // Generous for a scene this small, so that an alert points at a regression
+    ctx.set_frame_budgets(graphene::FrameBudgets {
+        opt_record_ms: Some(4.0),
+        opt_upload_bytes: Some(8 * 1024 * 1024),
+        opt_draws: Some(256),
+        opt_gpu_ms: Some(8.0),
+        opt_barriers: Some(64),
+        ..Default::default()
+    });
+    // The refresh range of a variable refresh rate display, e.g. `--vrr-range 48-144`
+    if let Some(range) = opt_arg_value("--vrr-range") {
+        let parse = |hz: Option<&str>| -> f32 {
+            hz.and_then(|hz| hz.trim().parse().ok())
+                .expect("Expected a range like 48-144 after --vrr-range.")
+        };
+        let mut bounds = range.split('-');
+        ctx.frame_pacer.min_refresh_hz = parse(bounds.next());
+        ctx.frame_pacer.max_refresh_hz = parse(bounds.next());
+    }
+    let mut recorded_path = graphene::CameraPath::new();
+    let mut frame_idx = 0;
+    let mut frame_times = Vec::new();
+    let mut opt_last_frame_start = None;
+
     loop {
         if !ctx.begin_frame() {
             break;
         }
 
-        let elapsed_seconds = start_instant.elapsed().as_secs_f32();
-        let cmd_buf = ctx.command_buffers[ctx.swapchain_idx];
+        let (elapsed_seconds, dt) = if opt_benchmark_frames.is_some() {
+            (frame_idx as f32 * BENCHMARK_DT, BENCHMARK_DT)
+        } else {
+            (
+                start_instant.elapsed().as_secs_f32(),
+                last_frame_instant.elapsed().as_secs_f32(),
+            )
+        };
+        last_frame_instant = std::time::Instant::now();
+        if let Some(last_frame_start) = opt_last_frame_start {
+            frame_times.push(last_frame_instant - last_frame_start);
+        }
+        opt_last_frame_start = Some(last_frame_instant);
+        frame_idx += 1;
+
+        if ctx.input.was_key_pressed(VirtualKeyCode::Key1) {
+            camera_rig.set_mode(graphene::CameraMode::Orbit);
+        }
+        if ctx.input.was_key_pressed(VirtualKeyCode::Key2) {
+            camera_rig.set_mode(graphene::CameraMode::Fly);
+        }
+        if ctx.input.was_key_pressed(VirtualKeyCode::Key3) {
+            camera_rig.set_mode(graphene::CameraMode::FirstPerson);
+        }
+        if ctx.input.was_key_pressed(VirtualKeyCode::F) {
+            camera_rig.focus_on(&mesh.aabb);
+        }
+        if ctx.input.was_key_pressed(VirtualKeyCode::G) {
+            render_path = match render_path {
+                RenderPath::Forward => RenderPath::Deferred,
+                RenderPath::Deferred => RenderPath::Forward,
+            };
+            println!("Render path: {:?}", render_path);
+        }
+        if ctx.input.was_key_pressed(VirtualKeyCode::B) {
+            is_dither_requested = !is_dither_requested;
+            println!(
+                "Dithering {}.",
+                if !ctx.facade.needs_dithering() {
+                    "not needed on this swapchain"
+                } else if is_dither_requested {
+                    "enabled"
+                } else {
+                    "disabled"
+                }
+            );
+        }
+        /* With `--msaa`, R switches between the render pass resolve and a
+        resolve pass that weights the samples by brightness. The difference
+        shows on the edges of the mesh against a bright environment, e.g.
+        with the exposure raised. */
+        if resources.opt_msaa_image.is_some() && ctx.input.was_key_pressed(VirtualKeyCode::R) {
+            resources.is_resolved_by_pass = !resources.is_resolved_by_pass;
+            println!(
+                "MSAA resolve: {}",
+                if resources.is_resolved_by_pass {
+                    "weighted, in a pass"
+                } else {
+                    "averaged, in the render pass"
+                }
+            );
+        }
+        if ctx.input.was_key_pressed(VirtualKeyCode::T) {
+            tonemap_operator = tonemap_operator.next();
+            println!("Tonemapping: {:?}", tonemap_operator);
+        }
+        let old_exposure_ev = exposure_ev;
+        if ctx.input.was_key_pressed(VirtualKeyCode::PageUp) {
+            exposure_ev += 0.5;
+        }
+        if ctx.input.was_key_pressed(VirtualKeyCode::PageDown) {
+            exposure_ev -= 0.5;
+        }
+        if exposure_ev != old_exposure_ev {
+            println!("Exposure: {:+.1} EV", exposure_ev);
+        }
+        // N cycles the anisotropy of the environment sampler
+        if ctx.input.was_key_pressed(VirtualKeyCode::N) {
+            let anisotropy = resources.environment_sampler.desc.anisotropy.next();
+            // Cached samplers aren't destroyed, so the cached graphs stay valid
+            resources.environment_sampler = ctx.sampler(&graphene::SamplerDesc {
+                anisotropy,
+                ..graphene::SamplerDesc::default()
+            });
+            println!(
+                "Anisotropy: {:?} ({}x after clamping to the device).",
+                anisotropy, resources.environment_sampler.max_anisotropy
+            );
+        }
+        if ctx.input.was_key_pressed(VirtualKeyCode::L) {
+            ctx.latency_tracker.is_enabled = !ctx.latency_tracker.is_enabled;
+            println!(
+                "Click latency test {}.",
+                if ctx.latency_tracker.is_enabled {
+                    "enabled"
+                } else {
+                    "disabled"
+                }
+            );
+        }
+        if ctx.input.was_key_pressed(VirtualKeyCode::M) {
+            println!("{}", ctx.gpu.memory_report());
+        }
+        // V toggles pacing for displays with variable refresh rate
+        if ctx.input.was_key_pressed(VirtualKeyCode::V) {
+            ctx.frame_pacer.print_summary();
+            let mode = match ctx.frame_pacer.mode {
+                graphene::PacingMode::Fifo => graphene::PacingMode::AdaptiveSync,
+                graphene::PacingMode::AdaptiveSync => graphene::PacingMode::Fifo,
+            };
+            ctx.set_pacing_mode(mode);
+            println!(
+                "Pacing mode: {:?} (present mode {:?}, {:.0}-{:.0} Hz).",
+                mode,
+                ctx.facade.present_mode,
+                ctx.frame_pacer.min_refresh_hz,
+                ctx.frame_pacer.max_refresh_hz
+            );
+        }
+        let mut camera = *camera_rig.update(&ctx.input, dt);
+        if let Some(camera_path) = &opt_camera_path {
+            let (position, rotation) = camera_path.sample(elapsed_seconds);
+            camera.position = position;
+            camera.rotation = rotation;
+        }
+        if opt_record_path.is_some() && ctx.input.was_key_pressed(VirtualKeyCode::K) {
+            recorded_path.add_keyframe(elapsed_seconds, camera.position, camera.rotation);
+            println!(
+                "Added camera keyframe {} at {:.2} s.",
+                recorded_path.keyframes.len(),
+                elapsed_seconds
+            );
+        }
+        let uniform_buffer = resources.uniform_buffers[ctx.swapchain_idx];
 
-        let uniform_buffer = uniform_buffers[ctx.swapchain_idx];
+        let post_settings = PostSettings {
+            is_dither_enabled: is_dither_requested && ctx.facade.needs_dithering(),
+            tonemap_operator,
+            exposure_ev,
+        };
 
         // Build and execute render graph
-        let pass_lit = ctx
-            .add_pass(
-                "lit",
-                shader_vertex,
-                shader_default,
-                &[temp_image],
-                Some(depth_image),
-                uniform_buffer,
-                environment_image,
-                &environment_sampler,
-            )
-            .unwrap();
-        let pass_post = ctx
-            .add_pass(
-                "post",
-                shader_fullscreen_triangle_vertex,
-                shader_aberration,
-                &[ctx.facade.swapchain_images[ctx.swapchain_idx]],
-                Some(depth_image),
+        let swapchain_idx = ctx.swapchain_idx;
+        if let Some(synthetic_load) = &mut opt_synthetic_load {
+            synthetic_load.update(std::time::Duration::from_secs_f32(dt));
+        }
+        let passes = add_passes(
+            &mut ctx,
+            &resources,
+            swapchain_idx,
+            render_path,
+            opt_synthetic_load.as_ref(),
+            elapsed_seconds,
+        );
+        let graph = ctx.build_graph();
+        let draw_mesh = |recorder: &graphene::PassRecorder| {
+            execute_pass(
+                recorder,
+                elapsed_seconds,
+                &camera,
                 uniform_buffer,
-                temp_image,
-                &environment_sampler,
+                &mesh,
+                opt_indirect_buffer.as_ref(),
+                &post_settings,
             )
-            .unwrap();
+        };
+        if let Some(pass_gbuffer) = passes.opt_gbuffer {
+            ctx.record_pass(graph, pass_gbuffer, draw_mesh);
+            for gbuffer_image in &resources.gbuffer_images {
+                // TODO: Do this automatically in the render graph
+                ctx.transition_image_to_sampled(*gbuffer_image);
+            }
+            ctx.record_pass(graph, passes.lit, |recorder| recorder.draw(3));
+        } else {
+            ctx.record_pass(graph, passes.lit, draw_mesh);
+            if let Some(pass_resolve) = passes.opt_resolve {
+                ctx.record_pass(graph, pass_resolve, |recorder| recorder.draw(3));
+            }
+        }
+        if let Some(pass_synthetic_load) = passes.opt_synthetic_load {
+            ctx.record_pass(graph, pass_synthetic_load, |recorder| recorder.draw(3));
+        }
+        ctx.transition_image_to_sampled(resources.temp_image);
+        // Post
+        ctx.record_pass(graph, passes.post, |recorder| recorder.draw(3));
 
-        let graph = ctx.build_graph();
-        // Pass 0
-        ctx.begin_pass(graph, pass_lit);
-        execute_pass(&mut ctx, elapsed_seconds, uniform_buffer, cmd_buf, &mesh);
-        ctx.end_pass(graph);
-        // Layout transition (TODO: Do this automatically in the render graph)
-        {
-            let img = ctx.image_list.get_image_from_handle(temp_image).unwrap();
-            img.image.transition_image_layout(
-                vk::ImageLayout::UNDEFINED,
-                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-                cmd_buf,
+        ctx.end_frame();
+
+        if let Some(benchmark_frames) = opt_benchmark_frames {
+            if frame_idx >= benchmark_frames {
+                break;
+            }
+        }
+
+        if is_stress_sizes {
+            const FRAMES_PER_SIZE: usize = 5;
+            assert_eq!(
+                graphene::num_validation_errors(),
+                0,
+                "Validation errors at {}x{}.",
+                ctx.facade.swapchain_width,
+                ctx.facade.swapchain_height
             );
+            if stress_size_frame_count % FRAMES_PER_SIZE == 0 {
+                let size_idx = stress_size_frame_count / FRAMES_PER_SIZE;
+                if size_idx == STRESS_SIZES.len() {
+                    println!("Rendered at {} window sizes.", STRESS_SIZES.len());
+                    break;
+                }
+                let (width, height) = STRESS_SIZES[size_idx];
+                ctx.set_window_size(width, height);
+            }
+            stress_size_frame_count += 1;
         }
-        // Pass 1
-        ctx.begin_pass(graph, pass_post);
-        unsafe {
-            ctx.gpu.device.cmd_draw(cmd_buf, 3, 1, 0, 0);
+
+        if let Some(num_cycles) = opt_stress_resize_cycles {
+            const FRAMES_PER_CYCLE: usize = 3;
+            stress_frame_count += 1;
+            if stress_frame_count % FRAMES_PER_CYCLE == 0 {
+                let cycle = stress_frame_count / FRAMES_PER_CYCLE;
+                if cycle == 2 {
+                    opt_stress_baseline = Some(graphene::LiveObjects::snapshot());
+                }
+                if cycle > num_cycles.max(2) {
+                    let live_objects = graphene::LiveObjects::snapshot();
+                    let growth = live_objects.growth_since(&opt_stress_baseline.unwrap());
+                    assert!(
+                        growth.is_empty(),
+                        "GPU objects leaked over {} resize cycles: {:?}",
+                        num_cycles,
+                        growth
+                    );
+                    println!(
+                        "No GPU objects leaked over {} resize cycles. {:?}",
+                        num_cycles, live_objects
+                    );
+                    break;
+                }
+                ctx.recreate_resolution_dependent_state();
+            }
         }
-        ctx.end_pass(graph);
+    }
 
-        ctx.end_frame();
+    // `--graph-report` saves a report of the last frame's graph on exit. F3 saves one at any time.
+    if std::env::args().any(|arg| arg == "--graph-report") {
+        match ctx.export_graph_report(graphene::GRAPH_REPORT_PATH) {
+            Ok(()) => println!("Saved graph report to `{}`.", graphene::GRAPH_REPORT_PATH),
+            Err(e) => println!("{}", e),
+        }
+    }
+    if let Some(record_path) = &opt_record_path {
+        match recorded_path.save(record_path) {
+            Ok(()) => println!(
+                "Saved {} camera keyframes to `{}`.",
+                recorded_path.keyframes.len(),
+                record_path
+            ),
+            Err(e) => println!("{}", e),
+        }
+    }
+    if opt_benchmark_frames.is_some() {
+        if let Some(summary) = graphene::FrameTimeSummary::new(&frame_times) {
+            println!("Benchmark: {}", summary.to_text());
+            println!(
+                "Frames over budget: {} of {}.",
+                ctx.budget_monitor.num_frames_over_budget, ctx.budget_monitor.num_frames
+            );
+            let json = format!(
+                "{{\n  \"frame_times\": {},\n  \"graphs_built\": {},\n  \"graphs_built_after_warmup\": {},\n  \"frames_over_budget\": {}\n}}\n",
+                summary.to_json(),
+                ctx.graph_stats.graphs_built_during_warmup
+                    + ctx.graph_stats.graphs_built_after_warmup,
+                ctx.graph_stats.graphs_built_after_warmup,
+                ctx.budget_monitor.num_frames_over_budget
+            );
+            let result = std::fs::create_dir_all("_cache")
+                .and_then(|_| std::fs::write("_cache/benchmark.json", json));
+            match result {
+                Ok(()) => println!("Saved benchmark results to `_cache/benchmark.json`."),
+                Err(e) => println!("Couldn't save benchmark results: {}", e),
+            }
+        }
     }
 
+    ctx.latency_tracker.print_summary();
+    ctx.frame_pacer.print_summary();
+    let stats = &ctx.graph_stats;
+    println!(
+        "Graphs built: {} during warmup, {} after warmup, {} evicted.",
+        stats.graphs_built_during_warmup, stats.graphs_built_after_warmup, stats.graphs_evicted
+    );
+    println!(
+        "Graph cache: {} hits, {} misses, {:.2} ms spent building. {} passes skipped.",
+        stats.cache_hits,
+        stats.cache_misses,
+        stats.total_build_duration.as_secs_f64() * 1000.0,
+        stats.passes_skipped
+    );
+    let staging_stats = ctx.gpu.staging_pool.stats();
+    let mut staging_text = String::from("Staging: ");
+    graphene::write_count(&mut staging_text, staging_stats.num_allocations as u64, 0);
+    staging_text.push_str(" uploads from ");
+    graphene::write_count(&mut staging_text, staging_stats.num_blocks as u64, 0);
+    staging_text.push_str(" blocks, ");
+    graphene::write_bytes(&mut staging_text, staging_stats.total_bytes as u64, 0);
+    staging_text.push_str(" total, ");
+    graphene::write_bytes(&mut staging_text, staging_stats.peak_bytes_in_use as u64, 0);
+    staging_text.push_str(" peak.");
+    println!("{}", staging_text);
+    println!("{}", ctx.gpu.memory_report().summary());
+
     // TODO: Remove the necessity for this sync
-    unsafe {
-        ctx.gpu
-            .device
-            .device_wait_idle()
-            .expect("Failed to wait device idle!");
-    }
+    ctx.wait_idle();
 }
@@ -0,0 +1,148 @@
+use glam::*;
+use graphene::prelude::*;
+use winit::event::VirtualKeyCode;
+
+const VOLUME_SIZE: u32 = 128;
+
+#[allow(dead_code)]
+struct UniformBuffer {
+    mtx_clip_to_world: Mat4,
+    camera_position: Vec4,
+    step_count: f32,
+    density_scale: f32,
+    viewport_w: f32,
+    viewport_h: f32,
+}
+
+/// A noisy torus, so that there is something to look through.
+fn generate_density_volume(size: u32) -> Vec<u8> {
+    let mut density = Vec::with_capacity((size * size * size) as usize);
+    for z in 0..size {
+        for y in 0..size {
+            for x in 0..size {
+                let p = (Vec3::new(x as f32, y as f32, z as f32) + Vec3::splat(0.5)) / size as f32
+                    * 2.0
+                    - Vec3::one();
+                let ring_distance = (p.x() * p.x() + p.z() * p.z()).sqrt() - 0.6;
+                let torus_distance = (ring_distance * ring_distance + p.y() * p.y()).sqrt() - 0.25;
+                let noise = (p.x() * 17.0).sin() * (p.y() * 13.0).sin() * (p.z() * 11.0).sin();
+                let d = (1.0 - torus_distance.max(0.0) * 8.0).clamp(0.0, 1.0) * (0.7 + 0.3 * noise);
+                density.push((d.clamp(0.0, 1.0) * 255.0) as u8);
+            }
+        }
+    }
+    density
+}
+
+fn main() {
+    run();
+    // The context and everything created with it are gone by now
+    report_teardown_audit();
+}
+
+fn run() {
+    let mut ctx = Context::new();
+    let mut last_frame_instant = std::time::Instant::now();
+
+    let volume_image = ctx
+        .new_image_from_data(
+            "image_volume",
+            Extent::new_3d(VOLUME_SIZE, VOLUME_SIZE, VOLUME_SIZE),
+            Format::R8Unorm,
+            &generate_density_volume(VOLUME_SIZE),
+        )
+        .unwrap();
+    let volume_sampler = Sampler::new(&ctx.gpu);
+
+    let shader_fullscreen_triangle_vertex = ctx
+        .new_shader(
+            "fullscreen_triangle_vertex",
+            ShaderStage::Vertex,
+            "fullscreen_triangle.vert",
+        )
+        .unwrap();
+    let shader_volume = ctx
+        .new_shader("shader_volume", ShaderStage::Fragment, "volume.frag")
+        .unwrap();
+
+    // Up/Down change the step count and Left/Right change the density.
+    let mut step_count = 128.0_f32;
+    let mut density_scale = 8.0_f32;
+
+    let volume_bounds = Aabb {
+        min: -Vec3::one(),
+        max: Vec3::one(),
+    };
+    let mut camera_rig = CameraRig::new_orbit(Vec3::zero(), 4.0, 0.5, 0.3);
+    camera_rig.focus_on(&volume_bounds);
+
+    loop {
+        if !ctx.begin_frame() {
+            break;
+        }
+
+        let dt = last_frame_instant.elapsed().as_secs_f32();
+        last_frame_instant = std::time::Instant::now();
+
+        // Tune the raymarching
+        {
+            let old_settings = (step_count, density_scale);
+            if ctx.input.was_key_pressed(VirtualKeyCode::Up) {
+                step_count = (step_count * 1.25).min(1024.0);
+            }
+            if ctx.input.was_key_pressed(VirtualKeyCode::Down) {
+                step_count = (step_count / 1.25).max(8.0);
+            }
+            if ctx.input.was_key_pressed(VirtualKeyCode::Right) {
+                density_scale *= 1.25;
+            }
+            if ctx.input.was_key_pressed(VirtualKeyCode::Left) {
+                density_scale /= 1.25;
+            }
+            if old_settings != (step_count, density_scale) {
+                println!(
+                    "Step count: {:.0}, density scale: {:.2}",
+                    step_count, density_scale
+                );
+            }
+        }
+        let camera = *camera_rig.update(&ctx.input, dt);
+
+        // Build and execute render graph
+        let pass_volume = ctx
+            .add_pass(
+                "volume",
+                shader_fullscreen_triangle_vertex,
+                shader_volume,
+                &[ctx.facade.swapchain_images[ctx.swapchain_idx]],
+                Color::BLACK,
+                None,
+                RING_UNIFORM_BUFFER,
+                volume_image,
+                &volume_sampler,
+            )
+            .unwrap();
+        // The uniforms go into the ring buffer, so no frame in flight is overwritten
+        {
+            let width = ctx.facade.swapchain_width as f32;
+            let height = ctx.facade.swapchain_height as f32;
+            let mtx_world_to_clip = camera.view_to_clip(width / height) * camera.world_to_view();
+            let ubos = [UniformBuffer {
+                mtx_clip_to_world: mtx_world_to_clip.inverse(),
+                camera_position: camera.position.extend(1.0),
+                step_count,
+                density_scale,
+                viewport_w: width,
+                viewport_h: height,
+            }];
+            ctx.write_pass_uniforms(pass_volume, &ubos);
+        }
+        let graph = ctx.build_graph();
+        ctx.record_pass(graph, pass_volume, |recorder| recorder.draw(3));
+
+        ctx.end_frame();
+    }
+
+    // TODO: Remove the necessity for this sync
+    ctx.wait_idle();
+}
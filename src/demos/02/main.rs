@@ -0,0 +1,192 @@
+use graphene::prelude::*;
+use winit::event::VirtualKeyCode;
+
+const DEFAULT_NUM_PALETTE_COLORS: usize = 4096;
+const MIN_NUM_PALETTE_COLORS: usize = 16;
+const MAX_NUM_PALETTE_COLORS: usize = 65536; // The minimum of `maxTexelBufferElements`
+
+#[allow(dead_code)]
+struct UniformBuffer {
+    elapsed_seconds: f32,
+    viewport_w: f32,
+    viewport_h: f32,
+    num_colors: f32,
+}
+
+/// A table of RGBA floats, far too big for a uniform buffer's guaranteed size.
+fn generate_palette(num_colors: usize) -> Vec<f32> {
+    let mut palette = Vec::with_capacity(4 * num_colors);
+    for i in 0..num_colors {
+        let t = i as f32 / (num_colors - 1) as f32;
+        let phase = std::f32::consts::TAU * t;
+        palette.push(0.5 + 0.5 * phase.cos());
+        palette.push(0.5 + 0.5 * (phase + 2.0).cos());
+        palette.push(0.5 + 0.5 * (phase + 4.0).cos());
+        palette.push(1.0);
+    }
+    palette
+}
+
+fn main() {
+    run();
+    // The context and everything created with it are gone by now
+    report_teardown_audit();
+}
+
+fn run() {
+    let mut ctx = Context::new();
+    let start_instant = std::time::Instant::now();
+
+    let mut num_colors = DEFAULT_NUM_PALETTE_COLORS;
+    let palette = generate_palette(num_colors);
+    let palette_size = std::mem::size_of_val(&palette[..]);
+    let palette_buffer = ctx
+        .new_buffer("buffer_palette", palette_size, BufferUsage::UNIFORM_TEXEL)
+        .unwrap();
+    ctx.upload_data(palette_buffer, &palette).unwrap();
+    let mut palette_view = ctx
+        .new_buffer_view(
+            "buffer_view_palette",
+            palette_buffer,
+            Format::R32G32B32A32Sfloat,
+            0,
+            palette_size,
+            TexelBufferKind::Uniform,
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+    let sampler = Sampler::new(&ctx.gpu);
+
+    let shader_fullscreen_triangle_vertex = ctx
+        .new_shader(
+            "fullscreen_triangle_vertex",
+            ShaderStage::Vertex,
+            "fullscreen_triangle.vert",
+        )
+        .unwrap();
+    let shader_texel_buffer = ctx
+        .new_shader(
+            "shader_texel_buffer",
+            ShaderStage::Fragment,
+            "texel_buffer.frag",
+        )
+        .unwrap();
+    // I inverts the colors, with a post pass that samples the palette's target
+    let shader_invert = ctx
+        .new_shader("shader_invert", ShaderStage::Fragment, "invert.frag")
+        .unwrap();
+    let palette_target = ctx
+        .new_render_target(
+            "image_palette",
+            ImageSize::SwapchainRelative(1.0),
+            Format::R8G8B8A8Unorm,
+        )
+        .unwrap();
+    let mut is_inverted = false;
+
+    loop {
+        if !ctx.begin_frame() {
+            break;
+        }
+
+        if ctx.input.was_key_pressed(VirtualKeyCode::I) {
+            is_inverted = !is_inverted;
+        }
+        // Up and Down change the number of colors. The buffer only grows.
+        let old_num_colors = num_colors;
+        if ctx.input.was_key_pressed(VirtualKeyCode::Up) {
+            num_colors = (num_colors * 2).min(MAX_NUM_PALETTE_COLORS);
+        }
+        if ctx.input.was_key_pressed(VirtualKeyCode::Down) {
+            num_colors = (num_colors / 2).max(MIN_NUM_PALETTE_COLORS);
+        }
+        if num_colors != old_num_colors {
+            // Frames in flight might still read the old view
+            ctx.wait_idle();
+            drop(palette_view);
+            let palette = generate_palette(num_colors);
+            let palette_size = std::mem::size_of_val(&palette[..]);
+            let is_reallocated = ctx
+                .grow_buffer(palette_buffer, palette_size, false)
+                .unwrap_or_else(|e| panic!("{}", e));
+            ctx.upload_data(palette_buffer, &palette).unwrap();
+            palette_view = ctx
+                .new_buffer_view(
+                    "buffer_view_palette",
+                    palette_buffer,
+                    Format::R32G32B32A32Sfloat,
+                    0,
+                    palette_size,
+                    TexelBufferKind::Uniform,
+                )
+                .unwrap_or_else(|e| panic!("{}", e));
+            println!(
+                "Palette colors: {}{}",
+                num_colors,
+                if is_reallocated {
+                    ", in a reallocated buffer"
+                } else {
+                    ""
+                }
+            );
+        }
+        let swapchain_image = ctx.facade.swapchain_images[ctx.swapchain_idx];
+
+        // The palette is read from the texel buffer, so the bound image is unused
+        let pass_palette = ctx
+            .add_pass(
+                "palette",
+                shader_fullscreen_triangle_vertex,
+                shader_texel_buffer,
+                &[if is_inverted {
+                    palette_target
+                } else {
+                    swapchain_image
+                }],
+                Color::BLACK,
+                None,
+                RING_UNIFORM_BUFFER,
+                ctx.default_resources.white_image,
+                &sampler,
+            )
+            .unwrap();
+        ctx.set_pass_texel_buffer(pass_palette, &palette_view);
+        let ubos = [UniformBuffer {
+            elapsed_seconds: start_instant.elapsed().as_secs_f32(),
+            viewport_w: ctx.facade.swapchain_width as f32,
+            viewport_h: ctx.facade.swapchain_height as f32,
+            num_colors: num_colors as f32,
+        }];
+        ctx.write_pass_uniforms(pass_palette, &ubos);
+        let opt_pass_invert = if is_inverted {
+            let pass_invert = ctx
+                .add_pass(
+                    "invert",
+                    shader_fullscreen_triangle_vertex,
+                    shader_invert,
+                    &[swapchain_image],
+                    Color::BLACK,
+                    None,
+                    RING_UNIFORM_BUFFER,
+                    palette_target,
+                    &sampler,
+                )
+                .unwrap();
+            ctx.set_pass_label_color(pass_invert, LABEL_COLOR_POST);
+            ctx.write_pass_uniforms(pass_invert, &ubos);
+            Some(pass_invert)
+        } else {
+            None
+        };
+        let graph = ctx.build_graph();
+        ctx.record_pass(graph, pass_palette, |recorder| recorder.draw(3));
+        if let Some(pass_invert) = opt_pass_invert {
+            ctx.transition_image_to_sampled(palette_target);
+            ctx.record_pass(graph, pass_invert, |recorder| recorder.draw(3));
+        }
+
+        ctx.end_frame();
+    }
+
+    // TODO: Remove the necessity for this sync
+    ctx.wait_idle();
+}
@@ -0,0 +1,171 @@
+use graphene::prelude::*;
+
+const GRADIENT_SIZE: u32 = 256;
+const GRADIENT_GROUP_SIZE: u32 = 8; // The local size in gradient.comp
+const NUM_BLOOM_MIPS: u32 = 6; // Of the scene target, each one rendered from the one above
+
+#[allow(dead_code)]
+struct UniformBuffer {
+    elapsed_seconds: f32,
+    viewport_w: f32,
+    viewport_h: f32,
+    bloom_mip: f32, // Which mip of the scene target bloom_composite.frag adds
+}
+
+fn main() {
+    run();
+    // The context and everything created with it are gone by now
+    report_teardown_audit();
+}
+
+fn run() {
+    let mut ctx = Context::new();
+    let start_instant = std::time::Instant::now();
+
+    // Written by the compute shader every frame, and then sampled by the triangle
+    let gradient_image = ctx
+        .new_image(
+            "image_gradient",
+            ImageSize::Absolute(Extent::new_2d(GRADIENT_SIZE, GRADIENT_SIZE)),
+            Format::R8G8B8A8Unorm,
+            ImageUsage::STORAGE | ImageUsage::SAMPLED,
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+    let sampler = Sampler::new(&ctx.gpu);
+
+    let shader_gradient = ctx
+        .new_shader("shader_gradient", ShaderStage::Compute, "gradient.comp")
+        .unwrap();
+    let shader_textured_triangle_vertex = ctx
+        .new_shader(
+            "textured_triangle_vertex",
+            ShaderStage::Vertex,
+            "textured_triangle.vert",
+        )
+        .unwrap();
+    let shader_textured_triangle_fragment = ctx
+        .new_shader(
+            "textured_triangle_fragment",
+            ShaderStage::Fragment,
+            "textured_triangle.frag",
+        )
+        .unwrap();
+    // The triangle is rendered into the scene target, and then glows with a bloom chain
+    let scene_target = ctx
+        .new_render_target_with_mips(
+            "image_scene",
+            ImageSize::SwapchainRelative(1.0),
+            Format::R8G8B8A8Unorm,
+            NUM_BLOOM_MIPS,
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+    let shader_fullscreen_triangle_vertex = ctx
+        .new_shader(
+            "fullscreen_triangle_vertex",
+            ShaderStage::Vertex,
+            "fullscreen_triangle.vert",
+        )
+        .unwrap();
+    let shader_downsample = ctx
+        .new_shader(
+            "shader_downsample",
+            ShaderStage::Fragment,
+            "downsample.frag",
+        )
+        .unwrap();
+    let shader_bloom_composite = ctx
+        .new_shader(
+            "shader_bloom_composite",
+            ShaderStage::Fragment,
+            "bloom_composite.frag",
+        )
+        .unwrap();
+
+    loop {
+        if !ctx.begin_frame() {
+            break;
+        }
+
+        let elapsed_seconds = start_instant.elapsed().as_secs_f32();
+        let num_groups = GRADIENT_SIZE.div_ceil(GRADIENT_GROUP_SIZE);
+        ctx.dispatch_compute(
+            shader_gradient,
+            gradient_image,
+            (num_groups, num_groups, 1),
+            &[elapsed_seconds],
+        );
+        ctx.transition_image_to_sampled(gradient_image);
+
+        let ubos = [UniformBuffer {
+            elapsed_seconds,
+            viewport_w: ctx.facade.swapchain_width as f32,
+            viewport_h: ctx.facade.swapchain_height as f32,
+            bloom_mip: (NUM_BLOOM_MIPS - 1) as f32,
+        }];
+        let pass_triangle = ctx
+            .add_pass(
+                "triangle",
+                shader_textured_triangle_vertex,
+                shader_textured_triangle_fragment,
+                &[scene_target],
+                Color::BLACK,
+                None,
+                RING_UNIFORM_BUFFER,
+                gradient_image,
+                &sampler,
+            )
+            .unwrap();
+        ctx.write_pass_uniforms(pass_triangle, &ubos);
+        let downsample_passes: Vec<PassHandle> = (1..NUM_BLOOM_MIPS)
+            .map(|mip| {
+                let pass_downsample = ctx
+                    .add_pass(
+                        &format!("downsample_{}", mip),
+                        shader_fullscreen_triangle_vertex,
+                        shader_downsample,
+                        &[scene_target],
+                        Color::BLACK,
+                        None,
+                        RING_UNIFORM_BUFFER,
+                        scene_target,
+                        &sampler,
+                    )
+                    .unwrap();
+                ctx.set_pass_label_color(pass_downsample, LABEL_COLOR_POST);
+                ctx.set_pass_output_mip(pass_downsample, mip);
+                ctx.set_pass_input_image_mip(pass_downsample, scene_target, mip - 1, &sampler)
+                    .unwrap();
+                ctx.write_pass_uniforms(pass_downsample, &ubos);
+                pass_downsample
+            })
+            .collect();
+        let pass_composite = ctx
+            .add_pass(
+                "bloom_composite",
+                shader_fullscreen_triangle_vertex,
+                shader_bloom_composite,
+                &[ctx.facade.swapchain_images[ctx.swapchain_idx]],
+                Color::BLACK,
+                None,
+                RING_UNIFORM_BUFFER,
+                scene_target,
+                &sampler,
+            )
+            .unwrap();
+        ctx.set_pass_label_color(pass_composite, LABEL_COLOR_POST);
+        ctx.write_pass_uniforms(pass_composite, &ubos);
+        let graph = ctx.build_graph();
+        ctx.record_pass(graph, pass_triangle, |recorder| recorder.draw(3));
+        for (mip, pass_downsample) in (1..NUM_BLOOM_MIPS).zip(downsample_passes) {
+            ctx.transition_image_mip_to_sampled(scene_target, mip - 1);
+            ctx.record_pass(graph, pass_downsample, |recorder| recorder.draw(3));
+        }
+        ctx.transition_image_to_sampled(scene_target);
+        ctx.record_pass(graph, pass_composite, |recorder| recorder.draw(3));
+
+        ctx.end_frame();
+    }
+
+    // TODO: Remove the necessity for this sync
+    ctx.wait_idle();
+}
@@ -0,0 +1,268 @@
+use glam::*;
+use graphene::prelude::*;
+use winit::event::{MouseButton, VirtualKeyCode};
+
+const GRID_SIZE: usize = 24; // Cubes along each side of the grid behind the wall
+const CUBE_SPACING: f32 = 1.5;
+const CUBE_HALF_SIZE: f32 = 0.4;
+const NUM_VERTICES_PER_BOX: usize = 24; // Four per face, so that faces have their own normals
+const STATS_INTERVAL_SECONDS: f32 = 1.0;
+const CAMERA_DISTANCE: f32 = 40.0;
+const CAMERA_PITCH: f32 = -0.15;
+
+#[allow(dead_code)]
+struct UniformBuffer {
+    // Matches the layout that default.vert declares
+    mtx_obj_to_clip: Mat4,
+    mtx_norm_obj_to_world: Mat4,
+    elapsed_seconds: f32,
+    viewport_w: f32,
+    viewport_h: f32,
+}
+
+/* Append a box, whose vertices are in world space so that every box can be
+drawn with the same indices and its own vertex offset. */
+fn add_box(
+    center: Vec3,
+    half_extents: Vec3,
+    vertices: &mut Vec<f32>,
+    commands: &mut Vec<DrawIndexedIndirectCommand>,
+    bounding_spheres: &mut Vec<(Vec3, f32)>,
+    aabbs: &mut Vec<Aabb>,
+) {
+    let vertex_offset = (vertices.len() / 6) as i32;
+    let axes = [Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z()];
+    for axis_idx in 0..3 {
+        for sign in &[-1.0, 1.0] {
+            let normal = axes[axis_idx] * *sign;
+            let u = axes[(axis_idx + 1) % 3];
+            let v = axes[(axis_idx + 2) % 3];
+            for (a, b) in &[(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                let position = center + (normal + u * *a + v * *b) * half_extents;
+                vertices.extend_from_slice(&[position.x(), position.y(), position.z()]);
+                vertices.extend_from_slice(&[normal.x(), normal.y(), normal.z()]);
+            }
+        }
+    }
+    commands.push(DrawIndexedIndirectCommand {
+        num_indices: 36,
+        num_instances: 1,
+        first_index: 0,
+        vertex_offset,
+        first_instance: 0,
+    });
+    bounding_spheres.push((center, half_extents.length()));
+    aabbs.push(Aabb {
+        min: center - half_extents,
+        max: center + half_extents,
+    });
+}
+
+fn main() {
+    run();
+    // The context and everything created with it are gone by now
+    report_teardown_audit();
+}
+
+/* A wall that hides most of a grid of cubes, which are culled by the GPU
+against the last frame's depth. Drag with the left mouse button to orbit
+around the wall. Space cuts to the far side, O toggles the occlusion test,
+and P toggles skipping it after cuts, to show the popping that it prevents.
+Right-clicking prints the object under the cursor. The culling stats are
+printed every second. */
+fn run() {
+    let mut ctx = Context::new();
+    let start_instant = std::time::Instant::now();
+
+    // The wall comes first, and then the cubes behind it, some tall enough to peek over
+    let mut vertices = Vec::new();
+    let mut commands = Vec::new();
+    let mut bounding_spheres = Vec::new();
+    let mut aabbs = Vec::new();
+    add_box(
+        Vec3::new(0.0, 2.5, 0.0),
+        Vec3::new(20.0, 3.0, 0.25),
+        &mut vertices,
+        &mut commands,
+        &mut bounding_spheres,
+        &mut aabbs,
+    );
+    for z in 0..GRID_SIZE {
+        for x in 0..GRID_SIZE {
+            let height = if (x * 7 + z * 3) % 11 == 0 { 9.0 } else { 1.0 };
+            let center = Vec3::new(
+                (x as f32 - (GRID_SIZE - 1) as f32 * 0.5) * CUBE_SPACING,
+                CUBE_HALF_SIZE * height - 0.5,
+                -3.0 - z as f32 * CUBE_SPACING,
+            );
+            add_box(
+                center,
+                Vec3::new(CUBE_HALF_SIZE, CUBE_HALF_SIZE * height, CUBE_HALF_SIZE),
+                &mut vertices,
+                &mut commands,
+                &mut bounding_spheres,
+                &mut aabbs,
+            );
+        }
+    }
+    let bvh = Bvh::new(&aabbs);
+    let indices: Vec<u32> = (0..NUM_VERTICES_PER_BOX as u32 / 4)
+        .flat_map(|face| {
+            let first = face * 4;
+            vec![first, first + 1, first + 2, first, first + 2, first + 3]
+        })
+        .collect();
+    let vertex_buffer = graphene::DeviceLocalBuffer::new(
+        "vertices_boxes",
+        &vertices,
+        BufferUsage::VERTEX.to_vk(),
+        &ctx.gpu,
+        ctx.command_pool,
+        &ctx.debug_utils,
+    );
+    let index_buffer = graphene::DeviceLocalBuffer::new(
+        "indices_box",
+        &indices,
+        BufferUsage::INDEX.to_vk(),
+        &ctx.gpu,
+        ctx.command_pool,
+        &ctx.debug_utils,
+    );
+
+    // Sampled by the culler, so it needs a format without stencil
+    let depth_image = ctx
+        .new_image_relative_size(
+            "image_depth",
+            1.0,
+            Format::D32Sfloat,
+            ImageUsage::DEPTH_ATTACHMENT | ImageUsage::SAMPLED,
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+    let mut culler = OcclusionCuller::new(
+        &mut ctx,
+        "culler_boxes",
+        depth_image,
+        &bounding_spheres,
+        &commands,
+    )
+    .unwrap_or_else(|e| panic!("{}", e));
+    println!(
+        "Culling {} objects, {}.",
+        culler.num_objects,
+        if culler.is_compacted {
+            "compacted and drawn with an indirect count"
+        } else {
+            "drawn with no instances when culled"
+        }
+    );
+    let shader_vertex = ctx
+        .new_shader("shader_vertex", ShaderStage::Vertex, "default.vert")
+        .unwrap();
+    let shader_lambert = ctx
+        .new_shader("shader_lambert", ShaderStage::Fragment, "lambert.frag")
+        .unwrap();
+    let sampler = Sampler::new(&ctx.gpu);
+
+    let camera_target = Vec3::new(0.0, 1.0, -18.0);
+    let mut camera_yaw = 0.0;
+    let mut camera_rig =
+        CameraRig::new_orbit(camera_target, CAMERA_DISTANCE, camera_yaw, CAMERA_PITCH);
+    let mut last_frame_instant = std::time::Instant::now();
+    let mut last_stats_seconds = 0.0;
+
+    loop {
+        if !ctx.begin_frame() {
+            break;
+        }
+
+        let elapsed_seconds = start_instant.elapsed().as_secs_f32();
+        let dt = last_frame_instant.elapsed().as_secs_f32();
+        last_frame_instant = std::time::Instant::now();
+        if ctx.input.was_key_pressed(VirtualKeyCode::Space) {
+            camera_yaw += std::f32::consts::PI;
+            camera_rig =
+                CameraRig::new_orbit(camera_target, CAMERA_DISTANCE, camera_yaw, CAMERA_PITCH);
+        }
+        if ctx.input.was_key_pressed(VirtualKeyCode::O) {
+            culler.is_enabled = !culler.is_enabled;
+            println!(
+                "Occlusion culling {}.",
+                if culler.is_enabled { "on" } else { "off" }
+            );
+        }
+        if ctx.input.was_key_pressed(VirtualKeyCode::P) {
+            culler.is_cut_forced_visible = !culler.is_cut_forced_visible;
+            println!(
+                "Cuts are {}.",
+                if culler.is_cut_forced_visible {
+                    "drawn without the occlusion test"
+                } else {
+                    "tested against the old depth"
+                }
+            );
+        }
+        let camera = *camera_rig.update(&ctx.input, dt);
+        if ctx.input.was_mouse_button_pressed(MouseButton::Right) {
+            match ctx.pick(&camera, &bvh) {
+                Some(BvhHit { object_idx: 0, .. }) => println!("Picked the wall."),
+                Some(hit) => println!(
+                    "Picked cube {} of {}, {:.1} away.",
+                    hit.object_idx,
+                    bvh.num_objects() - 1,
+                    hit.distance
+                ),
+                None => println!("Picked nothing."),
+            }
+        }
+        if elapsed_seconds - last_stats_seconds > STATS_INTERVAL_SECONDS {
+            last_stats_seconds = elapsed_seconds;
+            let stats = culler.last_stats;
+            println!(
+                "Visible: {}, outside the frustum: {}, occluded: {}. Frames drawn without the occlusion test: {}.",
+                stats.num_visible,
+                stats.num_outside_frustum,
+                stats.num_occluded,
+                culler.num_forced_visible_frames
+            );
+        }
+
+        culler.cull(&mut ctx, &camera);
+        let aspect_ratio = ctx.facade.swapchain_width as f32 / ctx.facade.swapchain_height as f32;
+        let ubos = [UniformBuffer {
+            mtx_obj_to_clip: camera.view_to_clip(aspect_ratio) * camera.world_to_view(),
+            mtx_norm_obj_to_world: Mat4::identity(),
+            elapsed_seconds,
+            viewport_w: ctx.facade.swapchain_width as f32,
+            viewport_h: ctx.facade.swapchain_height as f32,
+        }];
+        let pass_boxes = ctx
+            .add_pass(
+                "boxes",
+                shader_vertex,
+                shader_lambert,
+                &[ctx.facade.swapchain_images[ctx.swapchain_idx]],
+                Color::from_srgb8(32, 32, 40),
+                Some(depth_image),
+                RING_UNIFORM_BUFFER,
+                ctx.default_resources.white_image,
+                &sampler,
+            )
+            .unwrap();
+        ctx.set_pass_label_color(pass_boxes, LABEL_COLOR_SCENE);
+        // Both windings, since the boxes are generated without regard to the cull mode
+        ctx.set_pass_double_sided(pass_boxes, true);
+        ctx.write_pass_uniforms(pass_boxes, &ubos);
+        let graph = ctx.build_graph();
+        ctx.record_pass(graph, pass_boxes, |recorder| {
+            recorder.bind_vertex_buffer(&vertex_buffer);
+            recorder.bind_index_buffer(&index_buffer);
+            culler.draw(recorder);
+        });
+        culler.build_depth_pyramid(&mut ctx);
+
+        ctx.end_frame();
+    }
+
+    // TODO: Remove the necessity for this sync
+    ctx.wait_idle();
+}
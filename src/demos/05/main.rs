@@ -0,0 +1,288 @@
+use glam::*;
+use graphene::prelude::*;
+use winit::event::VirtualKeyCode;
+
+const NUM_EYES: u32 = 2;
+const NUM_BOXES: usize = 9; // Around a ring, so that they are at different depths
+const RING_RADIUS: f32 = 3.0;
+const CAMERA_DISTANCE: f32 = 9.0;
+const CAMERA_PITCH: f32 = -0.3;
+const DEFAULT_EYE_SEPARATION: f32 = 0.2;
+const MAX_EYE_SEPARATION: f32 = 2.0;
+const EYE_SEPARATION_STEP: f32 = 1.25; // Each key press scales the separation by this
+const VALIDATE_FALLBACK_ARG: &str = "--validate-fallback";
+const VALIDATION_TOLERANCE: u8 = 1; // Per channel, for rounding differences between the paths
+
+#[allow(dead_code)]
+struct UniformBuffer {
+    // Matches the layout that stereo.vert declares
+    mtx_world_to_clip: [Mat4; NUM_EYES as usize],
+    elapsed_seconds: f32,
+    viewport_w: f32,
+    viewport_h: f32,
+}
+
+// Append a box whose vertices are in world space, with its own normals per face
+fn add_box(center: Vec3, half_extents: Vec3, vertices: &mut Vec<f32>, indices: &mut Vec<u32>) {
+    let axes = [Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z()];
+    for axis_idx in 0..3 {
+        for sign in &[-1.0, 1.0] {
+            let normal = axes[axis_idx] * *sign;
+            let u = axes[(axis_idx + 1) % 3];
+            let v = axes[(axis_idx + 2) % 3];
+            let first = (vertices.len() / 6) as u32;
+            for (a, b) in &[(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                let position = center + (normal + u * *a + v * *b) * half_extents;
+                vertices.extend_from_slice(&[position.x(), position.y(), position.z()]);
+                vertices.extend_from_slice(&[normal.x(), normal.y(), normal.z()]);
+            }
+            indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+        }
+    }
+}
+
+fn main() {
+    let is_validated = run();
+    // The context and everything created with it are gone by now
+    report_teardown_audit();
+    if !is_validated {
+        std::process::exit(1);
+    }
+}
+
+/* A ring of boxes in stereo. Both eyes are rendered by one pass into the
+layers of an array, with multiview if the device has it, and placed side by
+side. Drag with the left mouse button to orbit, Up and Down change the
+distance between the eyes, and M switches between multiview and rendering the
+eyes one at a time.
+
+With `--validate-fallback`, a frame is rendered both ways and the eyes are
+compared. Returns false if they differ. */
+fn run() -> bool {
+    let mut ctx = Context::new();
+    let is_validating = std::env::args().any(|arg| arg == VALIDATE_FALLBACK_ARG);
+    let start_instant = std::time::Instant::now();
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    add_box(
+        Vec3::new(0.0, -0.6, 0.0),
+        Vec3::new(5.0, 0.1, 5.0),
+        &mut vertices,
+        &mut indices,
+    );
+    for box_idx in 0..NUM_BOXES {
+        let angle = box_idx as f32 / NUM_BOXES as f32 * std::f32::consts::PI * 2.0;
+        let height = 0.5 + 0.25 * (box_idx % 3) as f32;
+        add_box(
+            Vec3::new(
+                angle.cos() * RING_RADIUS,
+                height - 0.5,
+                angle.sin() * RING_RADIUS,
+            ),
+            Vec3::new(0.4, height, 0.4),
+            &mut vertices,
+            &mut indices,
+        );
+    }
+    let vertex_buffer = graphene::DeviceLocalBuffer::new(
+        "vertices_boxes",
+        &vertices,
+        BufferUsage::VERTEX.to_vk(),
+        &ctx.gpu,
+        ctx.command_pool,
+        &ctx.debug_utils,
+    );
+    let index_buffer = graphene::DeviceLocalBuffer::new(
+        "indices_boxes",
+        &indices,
+        BufferUsage::INDEX.to_vk(),
+        &ctx.gpu,
+        ctx.command_pool,
+        &ctx.debug_utils,
+    );
+
+    // Each eye gets half of the window it started with, and is scaled to fit after resizes
+    let eye_size = Extent::new_2d(
+        (ctx.facade.swapchain_width / NUM_EYES).max(1),
+        ctx.facade.swapchain_height,
+    );
+    let stereo_image = ctx
+        .new_image_array(
+            "image_stereo",
+            eye_size,
+            NUM_EYES,
+            Format::R8G8B8A8Unorm,
+            ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC,
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+    let depth_format = ctx.depth_format();
+    let stereo_depth_image = ctx
+        .new_image_array(
+            "image_stereo_depth",
+            eye_size,
+            NUM_EYES,
+            depth_format,
+            ImageUsage::DEPTH_ATTACHMENT,
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+    let shader_stereo = ctx
+        .new_shader("shader_stereo", ShaderStage::Vertex, "stereo.vert")
+        .unwrap();
+    let shader_lambert = ctx
+        .new_shader("shader_lambert", ShaderStage::Fragment, "lambert.frag")
+        .unwrap();
+    let shader_fullscreen_triangle_vertex = ctx
+        .new_shader(
+            "fullscreen_triangle_vertex",
+            ShaderStage::Vertex,
+            "fullscreen_triangle.vert",
+        )
+        .unwrap();
+    let shader_stereo_composite = ctx
+        .new_shader(
+            "shader_stereo_composite",
+            ShaderStage::Fragment,
+            "stereo_composite.frag",
+        )
+        .unwrap();
+    let sampler = Sampler::new(&ctx.gpu);
+    if !ctx.is_multiview_enabled() {
+        println!("VK_KHR_multiview isn't supported, so the eyes are rendered one at a time.");
+        if is_validating {
+            println!("There is nothing to validate the fallback against.");
+            ctx.wait_idle();
+            return true;
+        }
+    }
+
+    let mut camera_rig = CameraRig::new_orbit(Vec3::zero(), CAMERA_DISTANCE, 0.0, CAMERA_PITCH);
+    let mut camera = *camera_rig.update(&ctx.input, 0.0);
+    let mut eye_separation = DEFAULT_EYE_SEPARATION;
+    let mut last_frame_instant = std::time::Instant::now();
+    // The eyes that multiview rendered, once they have been read back
+    let mut opt_multiview_texels: Option<Vec<u8>> = None;
+    let mut is_validated = true;
+
+    loop {
+        if !ctx.begin_frame() {
+            break;
+        }
+
+        let dt = last_frame_instant.elapsed().as_secs_f32();
+        last_frame_instant = std::time::Instant::now();
+        // The validation frames are rendered with the same camera and time
+        let elapsed_seconds = if is_validating {
+            0.0
+        } else {
+            camera = *camera_rig.update(&ctx.input, dt);
+            start_instant.elapsed().as_secs_f32()
+        };
+        if ctx.input.was_key_pressed(VirtualKeyCode::Up) {
+            eye_separation = (eye_separation * EYE_SEPARATION_STEP).min(MAX_EYE_SEPARATION);
+            println!("Eye separation: {:.3}", eye_separation);
+        }
+        if ctx.input.was_key_pressed(VirtualKeyCode::Down) {
+            eye_separation /= EYE_SEPARATION_STEP;
+            println!("Eye separation: {:.3}", eye_separation);
+        }
+        if ctx.input.was_key_pressed(VirtualKeyCode::M) {
+            let is_enabled = !ctx.is_multiview_enabled();
+            ctx.set_multiview_enabled(is_enabled);
+            println!(
+                "Rendering the eyes {}.",
+                if ctx.is_multiview_enabled() {
+                    "at once, with multiview"
+                } else {
+                    "one at a time"
+                }
+            );
+        }
+
+        // The eyes are offset to either side of the camera, looking the same way
+        let aspect_ratio = eye_size.width as f32 / eye_size.height as f32;
+        let mtx_view_to_clip = camera.view_to_clip(aspect_ratio);
+        let eye_to_clip = |side: f32| {
+            let eye_offset = Vec3::new(-side * eye_separation * 0.5, 0.0, 0.0);
+            mtx_view_to_clip * Mat4::from_translation(eye_offset) * camera.world_to_view()
+        };
+        let ubos = [UniformBuffer {
+            mtx_world_to_clip: [eye_to_clip(-1.0), eye_to_clip(1.0)],
+            elapsed_seconds,
+            viewport_w: ctx.facade.swapchain_width as f32,
+            viewport_h: ctx.facade.swapchain_height as f32,
+        }];
+        let pass_stereo = ctx
+            .add_pass(
+                "stereo",
+                shader_stereo,
+                shader_lambert,
+                &[stereo_image],
+                Color::from_srgb8(32, 32, 40),
+                Some(stereo_depth_image),
+                RING_UNIFORM_BUFFER,
+                ctx.default_resources.white_image,
+                &sampler,
+            )
+            .unwrap();
+        ctx.set_pass_label_color(pass_stereo, LABEL_COLOR_SCENE);
+        ctx.set_pass_view_count(pass_stereo, NUM_EYES)
+            .unwrap_or_else(|e| panic!("{}", e));
+        ctx.write_pass_uniforms(pass_stereo, &ubos);
+        let pass_composite = ctx
+            .add_pass(
+                "stereo_composite",
+                shader_fullscreen_triangle_vertex,
+                shader_stereo_composite,
+                &[ctx.facade.swapchain_images[ctx.swapchain_idx]],
+                Color::BLACK,
+                None,
+                RING_UNIFORM_BUFFER,
+                stereo_image,
+                &sampler,
+            )
+            .unwrap();
+        ctx.set_pass_label_color(pass_composite, LABEL_COLOR_POST);
+        ctx.write_pass_uniforms(pass_composite, &ubos);
+        let graph = ctx.build_graph();
+        ctx.record_pass(graph, pass_stereo, |recorder| {
+            recorder.bind_vertex_buffer(&vertex_buffer);
+            recorder.bind_index_buffer(&index_buffer);
+            recorder.draw_indexed(indices.len() as u32);
+        });
+        ctx.transition_image_to_sampled(stereo_image);
+        ctx.record_pass(graph, pass_composite, |recorder| recorder.draw(3));
+
+        ctx.end_frame();
+
+        if is_validating {
+            let texels = ctx
+                .read_back_image(stereo_image)
+                .unwrap_or_else(|e| panic!("{}", e));
+            if let Some(multiview_texels) = &opt_multiview_texels {
+                let num_mismatches = multiview_texels
+                    .iter()
+                    .zip(&texels)
+                    .filter(|(a, b)| (**a as i32 - **b as i32).abs() > VALIDATION_TOLERANCE as i32)
+                    .count();
+                is_validated = num_mismatches == 0;
+                if is_validated {
+                    println!("Multiview and the fallback rendered the same eyes.");
+                } else {
+                    println!(
+                        "Multiview and the fallback differ in {} of {} channels.",
+                        num_mismatches,
+                        texels.len()
+                    );
+                }
+                break;
+            }
+            opt_multiview_texels = Some(texels);
+            ctx.set_multiview_enabled(false);
+        }
+    }
+
+    // TODO: Remove the necessity for this sync
+    ctx.wait_idle();
+    is_validated
+}
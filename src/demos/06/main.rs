@@ -0,0 +1,121 @@
+use glam::*;
+use graphene::prelude::*;
+use winit::event::VirtualKeyCode;
+
+const RADIANS_PER_SECOND: f32 = 1.0;
+const CAMERA_DISTANCE: f32 = 3.0;
+
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+struct Uniforms {
+    // Matches the layout that default.vert declares
+    mtx_obj_to_clip: Mat4,
+    mtx_norm_obj_to_world: Mat4,
+    elapsed_seconds: f32,
+    viewport_w: f32,
+    viewport_h: f32,
+}
+
+fn main() {
+    run();
+    // The context and everything created with it are gone by now
+    report_teardown_audit();
+}
+
+/* A triangle that spins around its vertical axis, with its transform written
+to a `UniformBuffer` every frame. Space pauses the rotation. */
+fn run() {
+    let mut ctx = Context::new();
+    let start_instant = std::time::Instant::now();
+
+    // Positions and normals, facing the camera before it rotates
+    let vertices: [f32; 18] = [
+        0.0, 0.8, 0.0, 0.0, 0.0, 1.0, //
+        -0.8, -0.6, 0.0, 0.0, 0.0, 1.0, //
+        0.8, -0.6, 0.0, 0.0, 0.0, 1.0, //
+    ];
+    let vertex_buffer = graphene::DeviceLocalBuffer::new(
+        "vertices_triangle",
+        &vertices,
+        BufferUsage::VERTEX.to_vk(),
+        &ctx.gpu,
+        ctx.command_pool,
+        &ctx.debug_utils,
+    );
+    let mut uniform_buffer = ctx.new_uniform_buffer::<Uniforms>("uniform_buffer_triangle");
+    let shader_vertex = ctx
+        .new_shader("shader_vertex", ShaderStage::Vertex, "default.vert")
+        .unwrap();
+    let shader_lambert = ctx
+        .new_shader("shader_lambert", ShaderStage::Fragment, "lambert.frag")
+        .unwrap();
+    let sampler = Sampler::new(&ctx.gpu);
+
+    let camera =
+        *CameraRig::new_orbit(Vec3::zero(), CAMERA_DISTANCE, 0.0, 0.0).update(&ctx.input, 0.0);
+    let mut angle = 0.0_f32;
+    let mut is_paused = false;
+    let mut last_frame_instant = std::time::Instant::now();
+
+    loop {
+        if !ctx.begin_frame() {
+            break;
+        }
+
+        let dt = last_frame_instant.elapsed().as_secs_f32();
+        last_frame_instant = std::time::Instant::now();
+        if ctx.input.was_key_pressed(VirtualKeyCode::Space) {
+            is_paused = !is_paused;
+        }
+        if !is_paused {
+            angle = (angle + dt * RADIANS_PER_SECOND) % std::f32::consts::TAU;
+        }
+
+        let mtx_obj_to_world = Mat4::from_rotation_y(angle);
+        let aspect_ratio = ctx.facade.swapchain_width as f32 / ctx.facade.swapchain_height as f32;
+        let uniforms = Uniforms {
+            mtx_obj_to_clip: camera.view_to_clip(aspect_ratio)
+                * camera.world_to_view()
+                * mtx_obj_to_world,
+            mtx_norm_obj_to_world: mtx_obj_to_world,
+            elapsed_seconds: start_instant.elapsed().as_secs_f32(),
+            viewport_w: ctx.facade.swapchain_width as f32,
+            viewport_h: ctx.facade.swapchain_height as f32,
+        };
+        // The frames in flight change with the present mode
+        if uniform_buffer.num_frames() != ctx.facade.num_frames {
+            ctx.wait_idle();
+            uniform_buffer = ctx.new_uniform_buffer("uniform_buffer_triangle");
+        }
+        ctx.write_uniform_buffer(&uniform_buffer, &uniforms);
+
+        let pass_triangle = ctx
+            .add_pass(
+                "triangle",
+                shader_vertex,
+                shader_lambert,
+                &[ctx.facade.swapchain_images[ctx.swapchain_idx]],
+                Color::from_srgb8(32, 32, 40),
+                None,
+                RING_UNIFORM_BUFFER,
+                ctx.default_resources.white_image,
+                &sampler,
+            )
+            .unwrap();
+        ctx.set_pass_label_color(pass_triangle, LABEL_COLOR_SCENE);
+        // The back is visible for half of every turn
+        ctx.set_pass_double_sided(pass_triangle, true);
+        ctx.set_pass_uniform_buffer(pass_triangle, &uniform_buffer)
+            .unwrap_or_else(|e| panic!("{}", e));
+        let graph = ctx.build_graph();
+        ctx.record_pass(graph, pass_triangle, |recorder| {
+            recorder.bind_vertex_buffer(&vertex_buffer);
+            recorder.draw(3);
+        });
+
+        ctx.end_frame();
+    }
+
+    // TODO: Remove the necessity for this sync
+    ctx.wait_idle();
+}
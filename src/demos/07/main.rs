@@ -0,0 +1,329 @@
+use glam::*;
+use graphene::prelude::*;
+use winit::event::VirtualKeyCode;
+
+const NUM_BOXES: usize = 12; // Around a ring, so that there is something behind the camera
+const RING_RADIUS: f32 = 4.0;
+const CAMERA_DISTANCE: f32 = 2.5;
+const CAMERA_PITCH: f32 = -0.15;
+const MIRROR_UPDATE_RATES: [Option<f32>; 4] = [None, Some(30.0), Some(15.0), Some(5.0)];
+const MIRROR_RENDER_SCALES: [f32; 3] = [0.5, 0.25, 0.125];
+const MINIMAP_UPDATE_HZ: f32 = 4.0;
+const MINIMAP_HEIGHT: f32 = 12.0;
+const INDICES_PER_BOX: u32 = 36;
+
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+struct Uniforms {
+    // Matches the layout that default.vert declares
+    mtx_obj_to_clip: Mat4,
+    mtx_norm_obj_to_world: Mat4,
+    elapsed_seconds: f32,
+    viewport_w: f32,
+    viewport_h: f32,
+}
+
+// Append a box whose vertices are in world space, with its own normals per face
+fn add_box(center: Vec3, half_extents: Vec3, vertices: &mut Vec<f32>, indices: &mut Vec<u32>) {
+    let axes = [Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z()];
+    for axis_idx in 0..3 {
+        for sign in &[-1.0, 1.0] {
+            let normal = axes[axis_idx] * *sign;
+            let u = axes[(axis_idx + 1) % 3];
+            let v = axes[(axis_idx + 2) % 3];
+            let first = (vertices.len() / 6) as u32;
+            for (a, b) in &[(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                let position = center + (normal + u * *a + v * *b) * half_extents;
+                vertices.extend_from_slice(&[position.x(), position.y(), position.z()]);
+                vertices.extend_from_slice(&[normal.x(), normal.y(), normal.z()]);
+            }
+            indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+        }
+    }
+}
+
+fn main() {
+    run();
+    // The context and everything created with it are gone by now
+    report_teardown_audit();
+}
+
+/* A ring of boxes, with a rear-view mirror at the top of the window and a
+minimap in its corner. Each view renders through its own camera and pass, and
+the mirror and the minimap update at lower rates than the main view. Every
+view sorts the boxes by its own depth before drawing them. Drag
+with the left mouse button to orbit. R shows and hides the mirror, M the
+minimap, U cycles the mirror's update rate, and S its render scale. */
+fn run() {
+    let mut ctx = Context::new();
+    let start_instant = std::time::Instant::now();
+
+    // The floor, and then the ring
+    let mut boxes = vec![(Vec3::new(0.0, -0.6, 0.0), Vec3::new(6.0, 0.1, 6.0))];
+    for box_idx in 0..NUM_BOXES {
+        let angle = box_idx as f32 / NUM_BOXES as f32 * std::f32::consts::PI * 2.0;
+        let height = 0.4 + 0.3 * (box_idx % 4) as f32;
+        boxes.push((
+            Vec3::new(
+                angle.cos() * RING_RADIUS,
+                height - 0.5,
+                angle.sin() * RING_RADIUS,
+            ),
+            Vec3::new(0.4, height, 0.4),
+        ));
+    }
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for (center, half_extents) in &boxes {
+        add_box(*center, *half_extents, &mut vertices, &mut indices);
+    }
+    let vertex_buffer = graphene::DeviceLocalBuffer::new(
+        "vertices_boxes",
+        &vertices,
+        BufferUsage::VERTEX.to_vk(),
+        &ctx.gpu,
+        ctx.command_pool,
+        &ctx.debug_utils,
+    );
+    let index_buffer = graphene::DeviceLocalBuffer::new(
+        "indices_boxes",
+        &indices,
+        BufferUsage::INDEX.to_vk(),
+        &ctx.gpu,
+        ctx.command_pool,
+        &ctx.debug_utils,
+    );
+    // One draw per box, so that every view can sort them by its own depth
+    let draw_commands: Vec<_> = (0..boxes.len() as u32)
+        .map(|draw_idx| DrawIndexedIndirectCommand {
+            num_indices: INDICES_PER_BOX,
+            num_instances: 1,
+            first_index: draw_idx * INDICES_PER_BOX,
+            vertex_offset: 0,
+            first_instance: 0,
+        })
+        .collect();
+    let draw_buffer = ctx.new_indirect_buffer("buffer_box_draws", &draw_commands);
+
+    let shader_vertex = ctx
+        .new_shader("shader_vertex", ShaderStage::Vertex, "default.vert")
+        .unwrap();
+    let shader_lambert = ctx
+        .new_shader("shader_lambert", ShaderStage::Fragment, "lambert.frag")
+        .unwrap();
+    let sampler = Sampler::new(&ctx.gpu);
+
+    let mut composite = ViewportComposite::new(&mut ctx).unwrap_or_else(|e| panic!("{}", e));
+    let main_image = composite
+        .new_main_image(&mut ctx, "image_main")
+        .unwrap_or_else(|e| panic!("{}", e));
+    let depth_format = ctx.depth_format();
+    let main_depth_image = ctx
+        .new_image_relative_size(
+            "image_main_depth",
+            1.0,
+            depth_format,
+            ImageUsage::DEPTH_ATTACHMENT,
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+    let mirror = composite
+        .add_inset(
+            &mut ctx,
+            "mirror",
+            InsetRect::new(0.3, 0.03, 0.4, 0.2),
+            MIRROR_RENDER_SCALES[0],
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+    let minimap = composite
+        .add_inset(
+            &mut ctx,
+            "minimap",
+            InsetRect::new(0.77, 0.72, 0.2, 0.25),
+            0.25,
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+    for (inset, border_color) in &[
+        (mirror, Color::from_srgb8(40, 40, 40)),
+        (minimap, Color::from_srgb8(200, 200, 200)),
+    ] {
+        let inset = composite.inset_mut(*inset);
+        inset.border_color = *border_color;
+        inset.border_width = 3.0;
+    }
+    let mut mirror_rate_idx = 2;
+    let mut mirror_scale_idx = 0;
+    composite.inset_mut(mirror).opt_update_hz = MIRROR_UPDATE_RATES[mirror_rate_idx];
+    composite.inset_mut(minimap).opt_update_hz = Some(MINIMAP_UPDATE_HZ);
+
+    let mut camera_rig =
+        CameraRig::new_orbit(Vec3::new(0.0, 0.3, 0.0), CAMERA_DISTANCE, 0.0, CAMERA_PITCH);
+    let mut last_frame_instant = std::time::Instant::now();
+    let mut num_frames = 0_usize;
+    let mut num_mirror_updates = 0_usize;
+    let mut last_report_instant = std::time::Instant::now();
+
+    loop {
+        if !ctx.begin_frame() {
+            break;
+        }
+
+        let dt = last_frame_instant.elapsed().as_secs_f32();
+        last_frame_instant = std::time::Instant::now();
+        let elapsed_seconds = start_instant.elapsed().as_secs_f64();
+        let camera = *camera_rig.update(&ctx.input, dt);
+
+        if ctx.input.was_key_pressed(VirtualKeyCode::R) {
+            let inset = composite.inset_mut(mirror);
+            inset.is_visible = !inset.is_visible;
+        }
+        if ctx.input.was_key_pressed(VirtualKeyCode::M) {
+            let inset = composite.inset_mut(minimap);
+            inset.is_visible = !inset.is_visible;
+        }
+        if ctx.input.was_key_pressed(VirtualKeyCode::U) {
+            mirror_rate_idx = (mirror_rate_idx + 1) % MIRROR_UPDATE_RATES.len();
+            composite.inset_mut(mirror).opt_update_hz = MIRROR_UPDATE_RATES[mirror_rate_idx];
+            match MIRROR_UPDATE_RATES[mirror_rate_idx] {
+                Some(hz) => println!("The mirror updates at {} Hz.", hz),
+                None => println!("The mirror updates every frame."),
+            }
+        }
+        if ctx.input.was_key_pressed(VirtualKeyCode::S) {
+            mirror_scale_idx = (mirror_scale_idx + 1) % MIRROR_RENDER_SCALES.len();
+            let render_scale = MIRROR_RENDER_SCALES[mirror_scale_idx];
+            composite
+                .set_render_scale(&mut ctx, mirror, render_scale)
+                .unwrap_or_else(|e| panic!("{}", e));
+            println!(
+                "The mirror renders at {} of the window's size.",
+                render_scale
+            );
+        }
+        composite.update(&mut ctx, elapsed_seconds);
+
+        // Looking back from the main camera, and down at the ring from above
+        let mirror_camera = Camera::new(
+            camera.position,
+            camera.rotation * Quat::from_rotation_y(std::f32::consts::PI),
+        );
+        let mut minimap_camera = Camera::new(
+            Vec3::new(0.0, MINIMAP_HEIGHT, 0.0),
+            Quat::from_rotation_x(-std::f32::consts::FRAC_PI_2),
+        );
+        minimap_camera.fov_y = 50.0_f32.to_radians();
+
+        let views = [
+            ("main", camera, main_image, main_depth_image, None),
+            (
+                "mirror",
+                mirror_camera,
+                composite.image(mirror),
+                composite.depth_image(mirror),
+                Some(mirror),
+            ),
+            (
+                "minimap",
+                minimap_camera,
+                composite.image(minimap),
+                composite.depth_image(minimap),
+                Some(minimap),
+            ),
+        ];
+        let mut view_passes = Vec::new();
+        for (name, view_camera, image, depth_image, opt_inset) in &views {
+            // Insets are stretched over their rectangles, so they use those aspect ratios
+            let aspect_ratio = match opt_inset {
+                Some(inset) => {
+                    let rect = composite.inset(*inset).rect;
+                    (rect.width * ctx.facade.swapchain_width as f32)
+                        / (rect.height * ctx.facade.swapchain_height as f32)
+                }
+                None => ctx.facade.swapchain_width as f32 / ctx.facade.swapchain_height as f32,
+            };
+            let uniforms = [Uniforms {
+                mtx_obj_to_clip: view_camera.view_to_clip(aspect_ratio)
+                    * view_camera.world_to_view(),
+                mtx_norm_obj_to_world: Mat4::identity(),
+                elapsed_seconds: elapsed_seconds as f32,
+                viewport_w: ctx.facade.swapchain_width as f32,
+                viewport_h: ctx.facade.swapchain_height as f32,
+            }];
+            let pass = ctx
+                .add_pass(
+                    name,
+                    shader_vertex,
+                    shader_lambert,
+                    &[*image],
+                    Color::from_srgb8(110, 150, 200),
+                    Some(*depth_image),
+                    RING_UNIFORM_BUFFER,
+                    ctx.default_resources.white_image,
+                    &sampler,
+                )
+                .unwrap();
+            if let Some(inset) = opt_inset {
+                ctx.set_pass_enabled_if(pass, composite.update_flag(*inset));
+            }
+            ctx.write_pass_uniforms(pass, &uniforms);
+            view_passes.push(pass);
+        }
+        let swapchain_image = ctx.facade.swapchain_images[ctx.swapchain_idx];
+        let pass_composite = composite
+            .add_composite_pass(&mut ctx, main_image, swapchain_image, &sampler)
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        let graph = ctx.build_graph();
+        for (pass, (_, view_camera, _, _, _)) in view_passes.iter().zip(&views) {
+            let world_to_view = view_camera.world_to_view();
+            let draws = ctx
+                .frame_arena
+                .alloc_from_iter(boxes.iter().enumerate().map(|(draw_idx, (center, _))| {
+                    // The boxes come in four heights, which stand in for materials here
+                    let material_bucket = match draw_idx {
+                        0 => 0,
+                        _ => 1 + (draw_idx - 1) % 4,
+                    };
+                    let depth = world_to_view.transform_point3(*center).z() / view_camera.z_far;
+                    let key = SortKey::new(DrawLayer::Opaque, 0, material_bucket as u64, depth);
+                    (key, draw_idx)
+                }));
+            ctx.record_sorted_pass(graph, *pass, draws, |recorder, sorted_draws| {
+                recorder.bind_vertex_buffer(&vertex_buffer);
+                recorder.bind_index_buffer(&index_buffer);
+                for (_, draw_idx) in sorted_draws {
+                    let offset = draw_idx * std::mem::size_of::<DrawIndexedIndirectCommand>();
+                    recorder.draw_indexed_indirect(&draw_buffer, offset, 1);
+                }
+            });
+        }
+        if composite.is_updated(&ctx, mirror) {
+            num_mirror_updates += 1;
+        }
+        for (_, _, image, _, _) in &views {
+            ctx.transition_image_to_sampled(*image);
+        }
+        ctx.record_pass(graph, pass_composite, |recorder| recorder.draw(3));
+
+        ctx.end_frame();
+
+        num_frames += 1;
+        if last_report_instant.elapsed().as_secs_f32() >= 5.0 {
+            println!(
+                "Rendered {} frames and {} mirror updates in the last 5 seconds.",
+                num_frames, num_mirror_updates
+            );
+            for (name, num_unsorted, num_sorted) in &ctx.last_frame_stats.pass_state_changes {
+                println!(
+                    "State changes in `{}`: {} in submission order, {} sorted.",
+                    name, num_unsorted, num_sorted
+                );
+            }
+            num_frames = 0;
+            num_mirror_updates = 0;
+            last_report_instant = std::time::Instant::now();
+        }
+    }
+
+    // TODO: Remove the necessity for this sync
+    ctx.wait_idle();
+}
@@ -0,0 +1,161 @@
+use glam::*;
+use graphene::prelude::*;
+use winit::event::VirtualKeyCode;
+
+const VIDEO_DIRECTORY: &str = "_cache/video_frames";
+const VIDEO_WIDTH: u32 = 192;
+const VIDEO_HEIGHT: u32 = 108;
+const NUM_VIDEO_FRAMES: usize = 48;
+const VIDEO_FRAMES_PER_SECOND: f32 = 24.0;
+const PLAYBACK_SPEEDS: [f32; 4] = [1.0, 2.0, 4.0, 0.5]; // 4x is faster than most decoders keep up with
+const CAMERA_DISTANCE: f32 = 3.0;
+
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+struct Uniforms {
+    // Matches the layout that video_quad.vert declares
+    mtx_obj_to_clip: Mat4,
+}
+
+/* Writes a short clip of a ball bouncing across a gradient, with a progress
+bar along the bottom, unless an earlier run already has. */
+fn write_video_frames() -> Result<(), String> {
+    let last_frame_path = format!("{}/frame_{:04}.png", VIDEO_DIRECTORY, NUM_VIDEO_FRAMES - 1);
+    if std::path::Path::new(&last_frame_path).is_file() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(VIDEO_DIRECTORY)
+        .map_err(|e| format!("Failed to create `{}`: {}", VIDEO_DIRECTORY, e))?;
+    let (width, height) = (VIDEO_WIDTH as f32, VIDEO_HEIGHT as f32);
+    let ball_radius = height * 0.12;
+    for frame_idx in 0..NUM_VIDEO_FRAMES {
+        let t = frame_idx as f32 / NUM_VIDEO_FRAMES as f32;
+        let ball_x = width * (0.5 - 0.35 * (t * std::f32::consts::TAU).cos());
+        let ball_y = ball_radius
+            + (height - 3.0 * ball_radius) * (t * std::f32::consts::TAU * 2.0).sin().abs();
+        let frame = image::RgbaImage::from_fn(VIDEO_WIDTH, VIDEO_HEIGHT, |x, y| {
+            let (x, y) = (x as f32, y as f32);
+            // Image rows go down, and the ball bounces up
+            let (dx, dy) = (x - ball_x, (height - y) - ball_y);
+            if dx * dx + dy * dy < ball_radius * ball_radius {
+                image::Rgba([250, 200, 40, 255])
+            } else if y >= height - 6.0 && x < t * width {
+                image::Rgba([240, 240, 240, 255])
+            } else {
+                image::Rgba([
+                    (30.0 + 60.0 * x / width) as u8,
+                    40,
+                    (120.0 - 60.0 * y / height) as u8,
+                    255,
+                ])
+            }
+        });
+        let path = format!("{}/frame_{:04}.png", VIDEO_DIRECTORY, frame_idx);
+        frame
+            .save(&path)
+            .map_err(|e| format!("Failed to write `{}`: {}", path, e))?;
+    }
+    Ok(())
+}
+
+fn main() {
+    write_video_frames().unwrap_or_else(|e| panic!("{}", e));
+    run();
+    // The context and everything created with it are gone by now
+    report_teardown_audit();
+}
+
+/* A screen in the scene that plays back a clip of numbered frames with
+`VideoImage`. Frames are decoded on a background thread and streamed into the
+image, and the ones that decoding can't keep up with are skipped. Drag with
+the left mouse button to orbit. Space pauses playback, and S cycles the
+playback speed. */
+fn run() {
+    let mut ctx = Context::new();
+
+    let mut video = VideoImage::new(
+        &mut ctx,
+        "image_video",
+        VIDEO_DIRECTORY,
+        VIDEO_FRAMES_PER_SECOND,
+        true,
+    )
+    .unwrap_or_else(|e| panic!("{}", e));
+    let shader_vertex = ctx
+        .new_shader("shader_video_quad", ShaderStage::Vertex, "video_quad.vert")
+        .unwrap();
+    let shader_fragment = ctx
+        .new_shader(
+            "shader_textured",
+            ShaderStage::Fragment,
+            "textured_triangle.frag",
+        )
+        .unwrap();
+    let sampler = Sampler::new(&ctx.gpu);
+
+    let mut camera_rig = CameraRig::new_orbit(Vec3::zero(), CAMERA_DISTANCE, 0.4, 0.1);
+    let mtx_obj_to_world = Mat4::from_scale(Vec3::new(
+        VIDEO_WIDTH as f32 / VIDEO_HEIGHT as f32,
+        1.0,
+        1.0,
+    ));
+    let mut playback_seconds = 0.0_f32;
+    let mut is_paused = false;
+    let mut speed_idx = 0;
+    let mut last_frame_instant = std::time::Instant::now();
+
+    loop {
+        if !ctx.begin_frame() {
+            break;
+        }
+
+        let dt = last_frame_instant.elapsed().as_secs_f32();
+        last_frame_instant = std::time::Instant::now();
+        if ctx.input.was_key_pressed(VirtualKeyCode::Space) {
+            is_paused = !is_paused;
+        }
+        if ctx.input.was_key_pressed(VirtualKeyCode::S) {
+            speed_idx = (speed_idx + 1) % PLAYBACK_SPEEDS.len();
+            println!("Playing back at {}x.", PLAYBACK_SPEEDS[speed_idx]);
+        }
+        // Speeding up moves the playback time faster, since it must only move forward
+        if !is_paused {
+            playback_seconds += dt * PLAYBACK_SPEEDS[speed_idx];
+        }
+        video.update(&mut ctx, playback_seconds);
+        let camera = *camera_rig.update(&ctx.input, dt);
+
+        let pass_screen = ctx
+            .add_pass(
+                "screen",
+                shader_vertex,
+                shader_fragment,
+                &[ctx.facade.swapchain_images[ctx.swapchain_idx]],
+                Color::from_srgb8(32, 32, 40),
+                None,
+                RING_UNIFORM_BUFFER,
+                video.image,
+                &sampler,
+            )
+            .unwrap();
+        ctx.set_pass_label_color(pass_screen, LABEL_COLOR_SCENE);
+        // The back of the screen shows the frames mirrored
+        ctx.set_pass_double_sided(pass_screen, true);
+        let aspect_ratio = ctx.facade.swapchain_width as f32 / ctx.facade.swapchain_height as f32;
+        ctx.write_pass_uniforms(
+            pass_screen,
+            &[Uniforms {
+                mtx_obj_to_clip: camera.view_to_clip(aspect_ratio)
+                    * camera.world_to_view()
+                    * mtx_obj_to_world,
+            }],
+        );
+        let graph = ctx.build_graph();
+        ctx.record_pass(graph, pass_screen, |recorder| recorder.draw(6));
+
+        ctx.end_frame();
+    }
+
+    // TODO: Remove the necessity for this sync
+    ctx.wait_idle();
+}
@@ -1,11 +1,70 @@
 use crate::*;
 
+const MAX_SWAPCHAIN_ATTEMPTS: usize = 5;
+// Multiplied by the number of attempts so far
+const SWAPCHAIN_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+/* Which kind of swapchain format to ask the surface for. With `Srgb`,
+shaders write linear colors and the swapchain encodes them on write. With
+`Unorm`, values are presented as they are written, for apps that encode
+gamma themselves, e.g. in a tonemap pass, or that blit UI which was rendered
+in display space. If the surface has no format of the preferred kind, the
+other kind is used, and then whichever format the surface lists first:
+
+- `Srgb`: B8G8R8A8_SRGB, R8G8B8A8_SRGB, B8G8R8A8_UNORM, R8G8B8A8_UNORM
+- `Unorm`: B8G8R8A8_UNORM, R8G8B8A8_UNORM, B8G8R8A8_SRGB, R8G8B8A8_SRGB
+- `Exact`: the given pair, and then the same order as `Srgb`
+
+All of these are in the SRGB_NONLINEAR color space, apart from `Exact`. */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SurfaceFormatPreference {
+    Srgb,
+    Unorm,
+    Exact(vk::Format, vk::ColorSpaceKHR),
+}
+
+pub fn choose_surface_format(
+    surface_formats: &[vk::SurfaceFormatKHR],
+    preference: SurfaceFormatPreference,
+) -> vk::SurfaceFormatKHR {
+    const SRGB_FORMATS: [vk::Format; 2] = [vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_SRGB];
+    const UNORM_FORMATS: [vk::Format; 2] = [vk::Format::B8G8R8A8_UNORM, vk::Format::R8G8B8A8_UNORM];
+    let nonlinear = |format: vk::Format| (format, vk::ColorSpaceKHR::SRGB_NONLINEAR);
+    let mut candidates = Vec::new();
+    match preference {
+        SurfaceFormatPreference::Srgb => {
+            candidates.extend(SRGB_FORMATS.iter().copied().map(nonlinear));
+            candidates.extend(UNORM_FORMATS.iter().copied().map(nonlinear));
+        }
+        SurfaceFormatPreference::Unorm => {
+            candidates.extend(UNORM_FORMATS.iter().copied().map(nonlinear));
+            candidates.extend(SRGB_FORMATS.iter().copied().map(nonlinear));
+        }
+        SurfaceFormatPreference::Exact(format, color_space) => {
+            candidates.push((format, color_space));
+            candidates.extend(SRGB_FORMATS.iter().copied().map(nonlinear));
+            candidates.extend(UNORM_FORMATS.iter().copied().map(nonlinear));
+        }
+    }
+    candidates
+        .iter()
+        .find_map(|&(format, color_space)| {
+            surface_formats
+                .iter()
+                .find(|f| f.format == format && f.color_space == color_space)
+        })
+        .copied()
+        .unwrap_or(surface_formats[0])
+}
+
 pub struct Facade {
-    device: ash::Device,
     // Surface info. Changes with resolution.
     pub surface_caps: vk::SurfaceCapabilitiesKHR,
     pub surface_formats: Vec<vk::SurfaceFormatKHR>,
+    pub surface_format: vk::SurfaceFormatKHR, // The one that the swapchain uses
+    pub present_mode: vk::PresentModeKHR,
     // Swapchain
+    pub swapchain_usage: vk::ImageUsageFlags, // TRANSFER_SRC if the surface supports it, for screenshots
     pub num_frames: usize,
     pub swapchain_width: u32,
     pub swapchain_height: u32,
@@ -23,112 +82,77 @@ pub struct Facade {
 
 impl Facade {
     pub fn new(
-        basis: &Basis,
+        basis: &mut Basis,
         gpu: &Gpu,
         window: &winit::window::Window,
+        present_mode: vk::PresentModeKHR,
+        surface_format_preference: SurfaceFormatPreference,
         image_list: &mut ImageList,
         debug_utils: &DebugUtils,
     ) -> Facade {
         let device = gpu.device.clone();
         let ext_swapchain = ash::extensions::khr::Swapchain::new(&basis.instance, &device);
 
-        // # Get surface info
-        let surface_caps = unsafe {
-            basis
-                .ext_surface
-                .get_physical_device_surface_capabilities(gpu.physical_device, basis.surface)
-                .expect("Failed to query for surface capabilities.")
-        };
-
-        let surface_formats = unsafe {
-            basis
-                .ext_surface
-                .get_physical_device_surface_formats(gpu.physical_device, basis.surface)
-                .expect("Failed to query for surface formats.")
-        };
-
-        // # Create swapchain
-        let (num_frames, swapchain, swapchain_format, swapchain_extent, swapchain_images) = {
-            // Set number of images in swapchain
-            let num_frames = surface_caps.min_image_count + 1;
-
-            // Choose swapchain format (i.e. color buffer format)
-            let (swapchain_format, swapchain_color_space) = {
-                let surface_format: vk::SurfaceFormatKHR = {
-                    *surface_formats
-                        .iter()
-                        .find(|&f| {
-                            f.format == vk::Format::B8G8R8A8_SRGB
-                                && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-                        })
-                        .unwrap_or(&surface_formats[0])
-                };
-                (surface_format.format, surface_format.color_space)
+        /* Creating the swapchain can fail while the window system is in flux,
+        e.g. when the compositor restarts. Transient failures are retried
+        with fresh surface capabilities, and failures of the surface itself
+        are retried with a new surface. */
+        let mut num_attempts = 0;
+        let created = loop {
+            num_attempts += 1;
+            let result = create_swapchain(
+                basis,
+                gpu,
+                window,
+                &ext_swapchain,
+                present_mode,
+                surface_format_preference,
+            );
+            let error = match result {
+                Ok(created) => break created,
+                Err(error) => error,
             };
-
-            // Choose extent
-            let extent = {
-                if surface_caps.current_extent.width == u32::max_value() {
-                    let window_size = window.inner_size();
-                    vk::Extent2D {
-                        width: (window_size.width as u32)
-                            .max(surface_caps.min_image_extent.width)
-                            .min(surface_caps.max_image_extent.width),
-                        height: (window_size.height as u32)
-                            .max(surface_caps.min_image_extent.height)
-                            .min(surface_caps.max_image_extent.height),
-                    }
-                } else {
-                    surface_caps.current_extent
+            let is_surface_error = match error {
+                vk::Result::ERROR_SURFACE_LOST_KHR | vk::Result::ERROR_NATIVE_WINDOW_IN_USE_KHR => {
+                    true
+                }
+                vk::Result::ERROR_OUT_OF_DATE_KHR | vk::Result::ERROR_INITIALIZATION_FAILED => {
+                    false
                 }
+                _ => panic!("Failed to create swapchain: {}", error),
             };
-
-            // Present mode
-            let present_mode: vk::PresentModeKHR = vk::PresentModeKHR::FIFO;
-
-            let mut info = vk::SwapchainCreateInfoKHR::builder()
-                .surface(basis.surface)
-                .min_image_count(num_frames)
-                .image_format(swapchain_format)
-                .image_color_space(swapchain_color_space)
-                .image_extent(extent)
-                .image_array_layers(1)
-                .image_usage(
-                    vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
-                )
-                // TODO: Investigate:
-                // The vulkan tutorial sets this as `pre_transform(gpu.surface_caps.current_transform)`.
-                .pre_transform(vk::SurfaceTransformFlagsKHR::IDENTITY)
-                .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-                .present_mode(present_mode)
-                .clipped(true); // Allow Vulkan to discard operations outside of the renderable space
-
-            // Sharing mode
-            let indices = [gpu.graphics_queue_idx, gpu.present_queue_idx];
-            if gpu.graphics_queue_idx != gpu.present_queue_idx {
-                info = info
-                    .image_sharing_mode(vk::SharingMode::CONCURRENT)
-                    .queue_family_indices(&indices);
+            if num_attempts == MAX_SWAPCHAIN_ATTEMPTS {
+                panic!(
+                    "Failed to create swapchain after {} attempts: {}",
+                    num_attempts, error
+                );
+            }
+            if num_attempts == 1 {
+                println!("Failed to create swapchain: {}. Retrying.", error);
             } else {
-                // Graphics and present are the same queue, so it can have
-                // exclusive access to the swapchain
-                info = info.image_sharing_mode(vk::SharingMode::EXCLUSIVE);
+                println!(
+                    "Failed to create swapchain again: {} (attempt {} of {}).",
+                    error, num_attempts, MAX_SWAPCHAIN_ATTEMPTS
+                );
+            }
+            if is_surface_error {
+                println!("Recreating the surface.");
+                basis.recreate_surface(window);
+            } else {
+                std::thread::sleep(SWAPCHAIN_RETRY_INTERVAL * num_attempts as u32);
             }
-
-            let swapchain = unsafe {
-                ext_swapchain
-                    .create_swapchain(&info, None)
-                    .expect("Failed to create swapchain.")
-            };
-
-            let images = unsafe {
-                ext_swapchain
-                    .get_swapchain_images(swapchain)
-                    .expect("Failed to get swapchain images.")
-            };
-
-            (num_frames, swapchain, swapchain_format, extent, images)
         };
+        let CreatedSwapchain {
+            surface_caps,
+            surface_formats,
+            num_frames,
+            swapchain_usage,
+            swapchain,
+            surface_format,
+            swapchain_extent,
+            swapchain_images,
+        } = created;
+        let swapchain_format = surface_format.format;
 
         // # Create swapchain image views
         let swapchain_imageviews = {
@@ -153,11 +177,21 @@ impl Facade {
                             layer_count: 1,
                         });
 
-                    unsafe {
+                    let image_view = unsafe {
                         device
                             .create_image_view(&info, None)
                             .expect("Failed to create image view.")
-                    }
+                    };
+                    audit_created(
+                        VkObjectKind::ImageView,
+                        image_view,
+                        &[
+                            device_id(&device),
+                            vk_object_id(VkObjectKind::Swapchain, swapchain),
+                        ],
+                        "swapchain_image_view",
+                    );
+                    image_view
                 })
                 .collect();
 
@@ -168,34 +202,41 @@ impl Facade {
         let swapchain_images = (0..num_frames)
             .map(|i| {
                 let name = String::from(&format!("image_swapchain_{}", i));
-                let hash: u64 = {
-                    let mut hasher = DefaultHasher::new();
-                    name.hash(&mut hasher);
-                    hasher.finish()
-                };
                 debug_utils.set_image_name(swapchain_images[i as usize], &name);
-                let handle = ImageHandle(hash);
+                track_created(&LIVE_IMAGES);
                 let image = Image {
                     width: swapchain_extent.width,
                     height: swapchain_extent.height,
+                    depth: 1,
+                    num_mips: 1,
+                    num_layers: 1,
+                    samples: vk::SampleCountFlags::TYPE_1,
                     format: swapchain_format,
                     usage: vk::ImageUsageFlags::empty(),
                     aspect_flags: vk::ImageAspectFlags::empty(),
                     vk_image: swapchain_images[i as usize],
                     image_view: swapchain_imageviews[i as usize],
-                    opt_device_memory: None, // This memory is not allocated by us. It is part of the swapchain.
+                    layer_views: Vec::new(),
+                    views: std::cell::RefCell::new(Vec::new()),
+                    opt_allocation: None, // This memory is not allocated by us. It is part of the swapchain.
+                    opt_upload_ticket: None,
+                    layouts: std::cell::RefCell::new(vec![vk::ImageLayout::UNDEFINED]),
+                    num_barriers: std::cell::Cell::new(0),
                     device: device.clone(),
-                    name,
+                    allocator: gpu.allocator.clone(),
+                    name: name.clone(),
                 };
-                image_list.list.push((
-                    handle,
-                    InternalImage {
-                        image,
-                        kind: ImageKind::Swapchain,
-                    },
-                ));
-
-                handle
+                // Handles of the previous swapchain's images are stale, and these are new
+                image_list
+                    .list
+                    .insert(
+                        &name,
+                        InternalImage {
+                            image,
+                            kind: ImageKind::Swapchain,
+                        },
+                    )
+                    .unwrap_or_else(|e| panic!("{}", e))
             })
             .collect();
 
@@ -208,28 +249,19 @@ impl Facade {
             let mut image_available_semaphores = Vec::new();
             let mut render_finished_semaphores = Vec::new();
             let mut command_buffer_complete_fences = Vec::new();
-            let semaphore_create_info = vk::SemaphoreCreateInfo::builder();
-            let fence_create_info =
-                vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED);
-
-            for _ in 0..num_frames {
-                unsafe {
-                    image_available_semaphores.push(
-                        device
-                            .create_semaphore(&semaphore_create_info, None)
-                            .expect("Failed to create Semaphore Object!"),
-                    );
-                    render_finished_semaphores.push(
-                        device
-                            .create_semaphore(&semaphore_create_info, None)
-                            .expect("Failed to create Semaphore Object!"),
-                    );
-                    command_buffer_complete_fences.push(
-                        device
-                            .create_fence(&fence_create_info, None)
-                            .expect("Failed to create Fence Object!"),
-                    );
-                }
+            for i in 0..num_frames {
+                image_available_semaphores.push(
+                    gpu.sync_pool
+                        .semaphore(&format!("semaphore_image_available_{}", i)),
+                );
+                render_finished_semaphores.push(
+                    gpu.sync_pool
+                        .semaphore(&format!("semaphore_render_finished_{}", i)),
+                );
+                command_buffer_complete_fences.push(
+                    gpu.sync_pool
+                        .fence(&format!("fence_command_buffer_complete_{}", i), true),
+                );
             }
             (
                 image_available_semaphores,
@@ -239,9 +271,11 @@ impl Facade {
         };
 
         Facade {
-            device,
             surface_caps,
             surface_formats,
+            surface_format,
+            present_mode,
+            swapchain_usage,
             num_frames: num_frames as usize,
             swapchain_width: swapchain_extent.width,
             swapchain_height: swapchain_extent.height,
@@ -254,22 +288,248 @@ impl Facade {
         }
     }
 
-    pub fn destroy(&self, image_list: &mut ImageList) {
-        unsafe {
-            for i in 0..self.num_frames {
-                self.device
-                    .destroy_semaphore(self.image_available_semaphores[i], None);
-                self.device
-                    .destroy_semaphore(self.render_finished_semaphores[i], None);
-                self.device
-                    .destroy_fence(self.command_buffer_complete_fences[i], None);
-            }
+    /// Whether the swapchain images can be copied into a screenshot.
+    pub fn is_screenshot_supported(&self) -> bool {
+        self.swapchain_usage
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+            && screenshot_texel_size(self.surface_format.format).is_ok()
+    }
 
-            self.ext_swapchain.destroy_swapchain(self.swapchain, None);
+    /// True for 8-bit swapchains, where smooth gradients band unless dithered.
+    /// 10-bit and HDR formats are fine without it.
+    pub fn needs_dithering(&self) -> bool {
+        matches!(
+            self.surface_format.format,
+            vk::Format::B8G8R8A8_UNORM
+                | vk::Format::B8G8R8A8_SRGB
+                | vk::Format::R8G8B8A8_UNORM
+                | vk::Format::R8G8B8A8_SRGB
+                | vk::Format::A8B8G8R8_UNORM_PACK32
+                | vk::Format::A8B8G8R8_SRGB_PACK32
+        )
+    }
+
+    /// Whether the swapchain encodes to sRGB on write.
+    pub fn is_srgb(&self) -> bool {
+        matches!(
+            self.surface_format.format,
+            vk::Format::B8G8R8A8_SRGB
+                | vk::Format::R8G8B8A8_SRGB
+                | vk::Format::A8B8G8R8_SRGB_PACK32
+        )
+    }
+
+    pub fn destroy(&self, gpu: &Gpu, image_list: &mut ImageList) {
+        for i in 0..self.num_frames {
+            gpu.sync_pool
+                .release_semaphore(self.image_available_semaphores[i]);
+            gpu.sync_pool
+                .release_semaphore(self.render_finished_semaphores[i]);
+            gpu.sync_pool
+                .release_fence(self.command_buffer_complete_fences[i]);
         }
-        // Delete swapchain images from image list
+        // Delete swapchain images from image list, which destroys their views
         image_list
             .list
-            .retain(|(_, internal_image)| internal_image.kind != ImageKind::Swapchain);
+            .retain(|internal_image| internal_image.kind != ImageKind::Swapchain);
+        unsafe {
+            self.ext_swapchain.destroy_swapchain(self.swapchain, None);
+        }
+        audit_destroyed(VkObjectKind::Swapchain, self.swapchain);
+    }
+}
+
+struct CreatedSwapchain {
+    surface_caps: vk::SurfaceCapabilitiesKHR,
+    surface_formats: Vec<vk::SurfaceFormatKHR>,
+    num_frames: u32,
+    swapchain_usage: vk::ImageUsageFlags,
+    swapchain: vk::SwapchainKHR,
+    surface_format: vk::SurfaceFormatKHR,
+    swapchain_extent: vk::Extent2D,
+    swapchain_images: Vec<vk::Image>,
+}
+
+// One attempt, which leaves nothing behind if it fails
+fn create_swapchain(
+    basis: &Basis,
+    gpu: &Gpu,
+    window: &winit::window::Window,
+    ext_swapchain: &ash::extensions::khr::Swapchain,
+    present_mode: vk::PresentModeKHR,
+    surface_format_preference: SurfaceFormatPreference,
+) -> Result<CreatedSwapchain, vk::Result> {
+    // # Get surface info
+    // A recreated surface has to be checked again before it can be presented to
+    let is_present_supported = unsafe {
+        basis.ext_surface.get_physical_device_surface_support(
+            gpu.physical_device,
+            gpu.present_queue_idx,
+            basis.surface,
+        )
+    };
+    if !is_present_supported {
+        return Err(vk::Result::ERROR_SURFACE_LOST_KHR);
+    }
+    let surface_caps = unsafe {
+        basis
+            .ext_surface
+            .get_physical_device_surface_capabilities(gpu.physical_device, basis.surface)?
+    };
+
+    let surface_formats = unsafe {
+        basis
+            .ext_surface
+            .get_physical_device_surface_formats(gpu.physical_device, basis.surface)?
+    };
+
+    // Screenshots copy from the swapchain images, and are disabled if they can't
+    let swapchain_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT
+        | (surface_caps.supported_usage_flags & vk::ImageUsageFlags::TRANSFER_SRC);
+
+    // # Create swapchain
+    let (num_frames, swapchain, surface_format, swapchain_extent, swapchain_images) = {
+        // Set number of images in swapchain
+        let num_frames = surface_caps.min_image_count + 1;
+
+        // Choose swapchain format (i.e. color buffer format)
+        let surface_format = choose_surface_format(&surface_formats, surface_format_preference);
+
+        let window_size = window.inner_size();
+        let extent = choose_swapchain_extent(&surface_caps, window_size.width, window_size.height);
+
+        let mut info = vk::SwapchainCreateInfoKHR::builder()
+            .surface(basis.surface)
+            .min_image_count(num_frames)
+            .image_format(surface_format.format)
+            .image_color_space(surface_format.color_space)
+            .image_extent(extent)
+            .image_array_layers(1)
+            .image_usage(swapchain_usage)
+            // TODO: Investigate:
+            // The vulkan tutorial sets this as `pre_transform(gpu.surface_caps.current_transform)`.
+            .pre_transform(vk::SurfaceTransformFlagsKHR::IDENTITY)
+            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+            .present_mode(present_mode)
+            .clipped(true); // Allow Vulkan to discard operations outside of the renderable space
+
+        // Sharing mode
+        let indices = [gpu.graphics_queue_idx, gpu.present_queue_idx];
+        if gpu.graphics_queue_idx != gpu.present_queue_idx {
+            info = info
+                .image_sharing_mode(vk::SharingMode::CONCURRENT)
+                .queue_family_indices(&indices);
+        } else {
+            // Graphics and present are the same queue, so it can have
+            // exclusive access to the swapchain
+            info = info.image_sharing_mode(vk::SharingMode::EXCLUSIVE);
+        }
+
+        let swapchain = unsafe { ext_swapchain.create_swapchain(&info, None)? };
+
+        // Don't leak the swapchain if this attempt fails halfway
+        let images = unsafe {
+            ext_swapchain
+                .get_swapchain_images(swapchain)
+                .inspect_err(|_| ext_swapchain.destroy_swapchain(swapchain, None))?
+        };
+
+        audit_created(
+            VkObjectKind::Swapchain,
+            swapchain,
+            &[
+                device_id(&gpu.device),
+                vk_object_id(VkObjectKind::Surface, basis.surface),
+            ],
+            "swapchain",
+        );
+        (num_frames, swapchain, surface_format, extent, images)
+    };
+
+    Ok(CreatedSwapchain {
+        surface_caps,
+        surface_formats,
+        num_frames,
+        swapchain_usage,
+        swapchain,
+        surface_format,
+        swapchain_extent,
+        swapchain_images,
+    })
+}
+
+/* The surface's extent, or the window's if the surface leaves it to the
+swapchain, within the surface's limits. Never zero, since zero-sized
+swapchains aren't allowed, e.g. while the window is minimized. */
+fn choose_swapchain_extent(
+    surface_caps: &vk::SurfaceCapabilitiesKHR,
+    window_width: u32,
+    window_height: u32,
+) -> vk::Extent2D {
+    let extent = if surface_caps.current_extent.width == u32::MAX {
+        vk::Extent2D {
+            width: window_width
+                .max(surface_caps.min_image_extent.width)
+                .min(surface_caps.max_image_extent.width),
+            height: window_height
+                .max(surface_caps.min_image_extent.height)
+                .min(surface_caps.max_image_extent.height),
+        }
+    } else {
+        surface_caps.current_extent
+    };
+    vk::Extent2D {
+        width: extent.width.max(1),
+        height: extent.height.max(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn surface_caps(
+        current: (u32, u32),
+        min: (u32, u32),
+        max: (u32, u32),
+    ) -> vk::SurfaceCapabilitiesKHR {
+        let extent = |(width, height)| vk::Extent2D { width, height };
+        vk::SurfaceCapabilitiesKHR {
+            current_extent: extent(current),
+            min_image_extent: extent(min),
+            max_image_extent: extent(max),
+            ..Default::default()
+        }
+    }
+
+    fn size(extent: vk::Extent2D) -> (u32, u32) {
+        (extent.width, extent.height)
+    }
+
+    #[test]
+    fn the_surface_extent_is_used_when_it_has_one() {
+        let caps = surface_caps((1366, 767), (1, 1), (16384, 16384));
+        assert_eq!(size(choose_swapchain_extent(&caps, 800, 600)), (1366, 767));
+        // Minimized windows on Windows report a zero current extent
+        let caps = surface_caps((0, 0), (0, 0), (0, 0));
+        assert_eq!(size(choose_swapchain_extent(&caps, 0, 0)), (1, 1));
+    }
+
+    #[test]
+    fn window_sizes_are_clamped_to_the_surface_limits() {
+        let caps = surface_caps((u32::MAX, u32::MAX), (1, 1), (16384, 16384));
+        let sizes = [
+            ((1, 37), (1, 37)),
+            ((37, 1), (37, 1)),
+            ((0, 0), (1, 1)),
+            ((0, 601), (1, 601)),
+            ((7680, 1440), (7680, 1440)),
+            ((16385, 99999), (16384, 16384)),
+        ];
+        for &((w, h), expected) in &sizes {
+            assert_eq!(size(choose_swapchain_extent(&caps, w, h)), expected);
+        }
+        let caps = surface_caps((u32::MAX, u32::MAX), (64, 48), (4096, 2160));
+        assert_eq!(size(choose_swapchain_extent(&caps, 3, 5000)), (64, 2160));
     }
 }
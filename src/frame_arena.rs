@@ -0,0 +1,312 @@
+use std::ffi::CStr;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+
+const CHUNK_SIZE: usize = std::mem::size_of::<u128>(); // Also the largest alignment supported
+
+/* A slice allocated in a `FrameArena`, valid until the arena is reset. It
+remembers the arena's generation, so using it in a later frame panics instead
+of reading what that frame allocated in its place. */
+pub struct ArenaSlice<T> {
+    offset: usize, // In bytes, from the start of the arena
+    len: usize,    // In elements
+    generation: u32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+// Implemented by hand, so that they don't require `T` to implement them
+impl<T> Copy for ArenaSlice<T> {}
+
+impl<T> Clone for ArenaSlice<T> {
+    fn clone(&self) -> ArenaSlice<T> {
+        *self
+    }
+}
+
+impl<T> fmt::Debug for ArenaSlice<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} elements at {} in generation {}",
+            self.len, self.offset, self.generation
+        )
+    }
+}
+
+impl<T> ArenaSlice<T> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/* A bump allocator for data that lives for one frame, like draw lists and
+formatted labels. Allocating moves a cursor, and `reset()` at the start of the
+next frame frees everything at once. The storage grows when a frame needs more
+than the last one, and is kept, so after the first frames nothing is allocated
+on the heap. Allocations are referred to by `ArenaSlice`s rather than
+references, so that the storage can grow while they are alive. Their
+generations are checked in every build, since a stale slice could otherwise
+read the bytes of another type. */
+pub struct FrameArena {
+    chunks: Vec<MaybeUninit<u128>>, // Aligned storage, holding values of any type
+    cursor: usize,                  // In bytes
+    generation: u32,
+    high_water_bytes: usize, // The most that any frame has allocated so far
+}
+
+impl FrameArena {
+    pub fn new(capacity_bytes: usize) -> FrameArena {
+        FrameArena {
+            chunks: vec![MaybeUninit::zeroed(); capacity_bytes.div_ceil(CHUNK_SIZE)],
+            cursor: 0,
+            generation: 0,
+            high_water_bytes: 0,
+        }
+    }
+
+    /// Free everything that was allocated, and make their slices stale. Call
+    /// at the start of every frame.
+    pub fn reset(&mut self) {
+        self.high_water_bytes = self.high_water_bytes.max(self.cursor);
+        self.cursor = 0;
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    pub fn bytes_used(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn capacity_bytes(&self) -> usize {
+        self.chunks.len() * CHUNK_SIZE
+    }
+
+    pub fn high_water_bytes(&self) -> usize {
+        self.high_water_bytes.max(self.cursor)
+    }
+
+    pub fn alloc_slice<T: Copy>(&mut self, values: &[T]) -> ArenaSlice<T> {
+        let offset = self.reserve::<T>(values.len());
+        unsafe {
+            (self.base_mut_ptr().add(offset) as *mut T)
+                .copy_from_nonoverlapping(values.as_ptr(), values.len());
+        }
+        self.slice_at(offset, values.len())
+    }
+
+    /// Like `alloc_slice()`, without collecting the values first.
+    pub fn alloc_from_iter<T: Copy>(
+        &mut self,
+        values: impl IntoIterator<Item = T>,
+    ) -> ArenaSlice<T> {
+        let offset = self.reserve::<T>(0);
+        let mut len = 0;
+        for value in values {
+            // The slice is the last allocation, so it can grow in place
+            self.reserve_at(
+                offset + len * std::mem::size_of::<T>(),
+                std::mem::size_of::<T>(),
+            );
+            unsafe {
+                (self.base_mut_ptr().add(offset) as *mut T)
+                    .add(len)
+                    .write(value);
+            }
+            len += 1;
+        }
+        self.slice_at(offset, len)
+    }
+
+    /// Format text, e.g. a label, with a terminating NUL for `get_cstr()`.
+    pub fn alloc_fmt(&mut self, args: fmt::Arguments) -> ArenaSlice<u8> {
+        struct ArenaWriter<'a> {
+            arena: &'a mut FrameArena,
+            offset: usize,
+            len: usize,
+        }
+        impl fmt::Write for ArenaWriter<'_> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                let end = self.offset + self.len;
+                self.arena.reserve_at(end, s.len());
+                self.arena
+                    .bytes_mut(end, s.len())
+                    .copy_from_slice(s.as_bytes());
+                self.len += s.len();
+                Ok(())
+            }
+        }
+        let offset = self.reserve::<u8>(0);
+        let mut writer = ArenaWriter {
+            arena: self,
+            offset,
+            len: 0,
+        };
+        fmt::write(&mut writer, args).expect("Formatting into the frame arena failed.");
+        let _ = fmt::Write::write_str(&mut writer, "\0");
+        let len = writer.len;
+        self.slice_at(offset, len)
+    }
+
+    pub fn get<T>(&self, slice: ArenaSlice<T>) -> &[T] {
+        self.check_generation(&slice);
+        unsafe {
+            std::slice::from_raw_parts(self.base_ptr().add(slice.offset) as *const T, slice.len)
+        }
+    }
+
+    pub fn get_mut<T>(&mut self, slice: ArenaSlice<T>) -> &mut [T] {
+        self.check_generation(&slice);
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                self.base_mut_ptr().add(slice.offset) as *mut T,
+                slice.len,
+            )
+        }
+    }
+
+    /// Text from `alloc_fmt()`. Panics if it contains a NUL of its own.
+    pub fn get_cstr(&self, slice: ArenaSlice<u8>) -> &CStr {
+        CStr::from_bytes_with_nul(self.get(slice)).expect("Formatted text contains a NUL.")
+    }
+
+    fn check_generation<T>(&self, slice: &ArenaSlice<T>) {
+        assert_eq!(
+            slice.generation, self.generation,
+            "An arena slice of frame {} was used in frame {}, after the arena was reset.",
+            slice.generation, self.generation
+        );
+    }
+
+    fn base_ptr(&self) -> *const u8 {
+        self.chunks.as_ptr() as *const u8
+    }
+
+    fn base_mut_ptr(&mut self) -> *mut u8 {
+        self.chunks.as_mut_ptr() as *mut u8
+    }
+
+    fn bytes_mut(&mut self, offset: usize, len: usize) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.base_mut_ptr().add(offset), len) }
+    }
+
+    // Aligns the cursor for `T`, and returns the offset of `len` elements after it
+    fn reserve<T>(&mut self, len: usize) -> usize {
+        let alignment = std::mem::align_of::<T>();
+        assert!(
+            alignment <= CHUNK_SIZE,
+            "The frame arena only aligns to {} bytes.",
+            CHUNK_SIZE
+        );
+        let offset = self.cursor.next_multiple_of(alignment);
+        let num_bytes = len
+            .checked_mul(std::mem::size_of::<T>())
+            .expect("Frame arena allocation is too large.");
+        self.reserve_at(offset, num_bytes);
+        offset
+    }
+
+    // Moves the cursor to the end of `num_bytes` at `offset`, growing the storage to fit them
+    fn reserve_at(&mut self, offset: usize, num_bytes: usize) {
+        let end = offset
+            .checked_add(num_bytes)
+            .expect("Frame arena allocation is too large.");
+        if end > self.capacity_bytes() {
+            let num_chunks = end.div_ceil(CHUNK_SIZE).max(2 * self.chunks.len());
+            self.chunks.resize(num_chunks, MaybeUninit::zeroed());
+        }
+        self.cursor = self.cursor.max(end);
+    }
+
+    fn slice_at<T>(&self, offset: usize, len: usize) -> ArenaSlice<T> {
+        ArenaSlice {
+            offset,
+            len,
+            generation: self.generation,
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slices_keep_their_values_while_the_storage_grows() {
+        let mut arena = FrameArena::new(16);
+        let a = arena.alloc_slice(&[1_u8, 2, 3]);
+        let b = arena.alloc_slice(&[10_u64, 20]);
+        let c = arena.alloc_from_iter((0..1000).map(|i| i as f32));
+        assert!(arena.capacity_bytes() >= 4000);
+        assert_eq!(arena.get(a), &[1, 2, 3]);
+        assert_eq!(arena.get(b), &[10, 20]);
+        assert_eq!(arena.get(c).len(), 1000);
+        assert_eq!(arena.get(c)[999], 999.0);
+        arena.get_mut(b)[1] = 30;
+        assert_eq!(arena.get(b), &[10, 30]);
+    }
+
+    #[test]
+    fn allocations_are_aligned_for_their_type() {
+        let mut arena = FrameArena::new(256);
+        arena.alloc_slice(&[1_u8]);
+        let b = arena.alloc_slice(&[1_u64]);
+        assert_eq!(
+            arena.get(b).as_ptr() as usize % std::mem::align_of::<u64>(),
+            0
+        );
+        arena.alloc_slice(&[1_u8, 2, 3]);
+        let d = arena.alloc_slice(&[1_u128]);
+        assert_eq!(
+            arena.get(d).as_ptr() as usize % std::mem::align_of::<u128>(),
+            0
+        );
+    }
+
+    #[test]
+    fn reset_reuses_the_storage() {
+        let mut arena = FrameArena::new(0);
+        arena.alloc_from_iter(0..100_u32);
+        let capacity = arena.capacity_bytes();
+        assert_eq!(arena.bytes_used(), 400);
+        arena.reset();
+        assert_eq!(arena.bytes_used(), 0);
+        assert_eq!(arena.high_water_bytes(), 400);
+        arena.alloc_from_iter(0..100_u32);
+        assert_eq!(arena.capacity_bytes(), capacity);
+    }
+
+    #[test]
+    #[should_panic(expected = "after the arena was reset")]
+    fn stale_slices_panic() {
+        let mut arena = FrameArena::new(64);
+        let a = arena.alloc_slice(&[true]);
+        arena.reset();
+        arena.alloc_slice(&[2_u8]);
+        arena.get(a);
+    }
+
+    #[test]
+    fn formatted_text_is_nul_terminated() {
+        let mut arena = FrameArena::new(4);
+        let label = arena.alloc_fmt(format_args!("command_buffer_{}", 12));
+        let other = arena.alloc_slice(&[7_u8]);
+        assert_eq!(arena.get_cstr(label).to_str(), Ok("command_buffer_12"));
+        assert_eq!(arena.get(other), &[7]);
+        let empty = arena.alloc_fmt(format_args!(""));
+        assert_eq!(arena.get_cstr(empty).to_bytes(), b"");
+    }
+
+    #[test]
+    fn empty_slices_are_valid() {
+        let mut arena = FrameArena::new(0);
+        let a = arena.alloc_slice::<u32>(&[]);
+        let b = arena.alloc_from_iter(std::iter::empty::<u64>());
+        assert!(arena.get(a).is_empty() && a.is_empty());
+        assert!(arena.get(b).is_empty());
+    }
+}
@@ -0,0 +1,600 @@
+use crate::*;
+
+/// What the context measured while recording a frame.
+#[derive(Clone, Debug, Default)]
+pub struct FrameStats {
+    pub record_ms: f32, // CPU time from the end of `begin_frame()` to `end_frame()`
+    pub pass_record_ms: Vec<(String, f32)>, // CPU time in `record_pass()`, by pass
+    pub pass_draws: Vec<(String, usize)>,
+    pub upload_bytes: Vec<(&'static str, u64)>, // By category, e.g. "staging"
+    // GPU time of the last frame with the same sync index, None until one has been measured
+    pub opt_gpu_ms: Option<f32>,
+    pub pass_gpu_ms: Vec<(String, f32)>,
+    pub image_barriers: Vec<(String, usize)>, // Layout transitions, by image that had any
+    // Pipeline and material changes of passes recorded with `record_sorted_pass()`, before and
+    // after their draws were sorted
+    pub pass_state_changes: Vec<(String, usize, usize)>,
+}
+
+impl FrameStats {
+    pub fn num_draws(&self) -> usize {
+        self.pass_draws.iter().map(|(_, num_draws)| num_draws).sum()
+    }
+
+    pub fn total_upload_bytes(&self) -> u64 {
+        self.upload_bytes.iter().map(|(_, bytes)| bytes).sum()
+    }
+
+    pub fn num_barriers(&self) -> usize {
+        self.image_barriers
+            .iter()
+            .map(|(_, num_barriers)| num_barriers)
+            .sum()
+    }
+
+    /// Every measurement, largest first within each section.
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("CPU recording: ");
+        write_ms(&mut text, self.record_ms, 0);
+        let mut pass_record_ms = self.pass_record_ms.clone();
+        pass_record_ms.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        for (name, ms) in &pass_record_ms {
+            text.push_str("\n  ");
+            write_ms(&mut text, *ms, 8);
+            text.push_str("  ");
+            text.push_str(name);
+        }
+
+        text.push_str("\nDraws: ");
+        write_count(&mut text, self.num_draws() as u64, 0);
+        let mut pass_draws = self.pass_draws.clone();
+        pass_draws.sort_by_key(|(_, num_draws)| std::cmp::Reverse(*num_draws));
+        for (name, num_draws) in &pass_draws {
+            text.push_str("\n  ");
+            write_count(&mut text, *num_draws as u64, 11);
+            text.push_str("  ");
+            text.push_str(name);
+        }
+
+        text.push_str("\nUploads: ");
+        write_bytes(&mut text, self.total_upload_bytes(), 0);
+        let mut upload_bytes = self.upload_bytes.clone();
+        upload_bytes.sort_by_key(|(_, bytes)| std::cmp::Reverse(*bytes));
+        for (category, bytes) in &upload_bytes {
+            text.push_str("\n  ");
+            write_bytes(&mut text, *bytes, 8);
+            text.push_str("  ");
+            text.push_str(category);
+        }
+
+        text.push_str("\nGPU: ");
+        match self.opt_gpu_ms {
+            Some(gpu_ms) => write_ms(&mut text, gpu_ms, 0),
+            None => text.push_str("not measured"),
+        }
+        let mut pass_gpu_ms = self.pass_gpu_ms.clone();
+        pass_gpu_ms.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        for (name, ms) in &pass_gpu_ms {
+            text.push_str("\n  ");
+            write_ms(&mut text, *ms, 8);
+            text.push_str("  ");
+            text.push_str(name);
+        }
+
+        text.push_str("\nBarriers: ");
+        write_count(&mut text, self.num_barriers() as u64, 0);
+        let mut image_barriers = self.image_barriers.clone();
+        image_barriers.sort_by_key(|(_, num_barriers)| std::cmp::Reverse(*num_barriers));
+        for (name, num_barriers) in &image_barriers {
+            text.push_str("\n  ");
+            write_count(&mut text, *num_barriers as u64, 11);
+            text.push_str("  ");
+            text.push_str(name);
+        }
+
+        if !self.pass_state_changes.is_empty() {
+            text.push_str("\nState changes, before and after sorting:");
+            for (name, num_unsorted, num_sorted) in &self.pass_state_changes {
+                text.push_str("\n  ");
+                write_count(&mut text, *num_unsorted as u64, 5);
+                text.push_str(" -> ");
+                write_count(&mut text, *num_sorted as u64, 2);
+                text.push_str("  ");
+                text.push_str(name);
+            }
+        }
+        text
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BudgetKind {
+    RecordMs,
+    UploadBytes,
+    Draws,
+    GpuMs,
+    Barriers,
+}
+
+const BUDGET_KINDS: [BudgetKind; 5] = [
+    BudgetKind::RecordMs,
+    BudgetKind::UploadBytes,
+    BudgetKind::Draws,
+    BudgetKind::GpuMs,
+    BudgetKind::Barriers,
+];
+
+/// Limits per frame. None disables a budget.
+#[derive(Copy, Clone, Debug)]
+pub struct FrameBudgets {
+    pub opt_record_ms: Option<f32>,
+    pub opt_upload_bytes: Option<u64>,
+    pub opt_draws: Option<usize>,
+    pub opt_gpu_ms: Option<f32>, // Frames whose GPU time wasn't measured don't count
+    pub opt_barriers: Option<usize>,
+    // Frames in a row that must be over, or back under, a budget before the alert changes
+    pub num_frames_to_alert: usize,
+}
+
+impl Default for FrameBudgets {
+    fn default() -> FrameBudgets {
+        FrameBudgets {
+            opt_record_ms: None,
+            opt_upload_bytes: None,
+            opt_draws: None,
+            opt_gpu_ms: None,
+            opt_barriers: None,
+            num_frames_to_alert: 30,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BudgetAlert {
+    pub kind: BudgetKind,
+    pub value: f64,
+    pub budget: f64,
+    pub worst_offender: String, // The pass or category that contributed the most
+    pub worst_offender_value: f64,
+}
+
+impl BudgetAlert {
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let write_value = |text: &mut String, value: f64| match self.kind {
+            BudgetKind::RecordMs | BudgetKind::GpuMs => write_ms(text, value as f32, 0),
+            BudgetKind::UploadBytes => write_bytes(text, value as u64, 0),
+            BudgetKind::Draws | BudgetKind::Barriers => write_count(text, value as u64, 0),
+        };
+        text.push_str(match self.kind {
+            BudgetKind::RecordMs => "CPU recording was ",
+            BudgetKind::UploadBytes => "Uploads were ",
+            BudgetKind::Draws => "Draws were ",
+            BudgetKind::GpuMs => "GPU time was ",
+            BudgetKind::Barriers => "Barriers were ",
+        });
+        write_value(&mut text, self.value);
+        text.push_str(", over the budget of ");
+        write_value(&mut text, self.budget);
+        text.push_str(". ");
+        text.push_str(match self.kind {
+            BudgetKind::RecordMs => "Slowest pass: `",
+            BudgetKind::UploadBytes => "Biggest category: `",
+            BudgetKind::Draws => "Most draws in pass: `",
+            BudgetKind::GpuMs => "Slowest pass on the GPU: `",
+            BudgetKind::Barriers => "Most barriers on image: `",
+        });
+        text.push_str(&self.worst_offender);
+        text.push_str("` (");
+        write_value(&mut text, self.worst_offender_value);
+        text.push_str(").");
+        text
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BudgetEvent {
+    Raised(BudgetAlert),
+    Cleared(BudgetKind),
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+struct BudgetState {
+    num_frames_in_a_row: usize, // Over the budget while not alerting, under it while alerting
+    is_alerting: bool,
+}
+
+/* Checks every frame's stats against the budgets. A budget raises an alert
+once it has been exceeded for `num_frames_to_alert` frames in a row, and
+clears it once it has been met for as many frames in a row, so a value that
+hovers around the budget doesn't flap. Only these transitions are returned,
+so they can be logged without repeating every frame. */
+pub struct BudgetMonitor {
+    pub budgets: FrameBudgets,
+    states: [BudgetState; BUDGET_KINDS.len()],
+    active_alerts: Vec<BudgetAlert>,
+    pub num_frames: usize,
+    pub num_frames_over_budget: usize, // Frames that exceeded any budget
+}
+
+impl BudgetMonitor {
+    pub fn new(budgets: FrameBudgets) -> BudgetMonitor {
+        BudgetMonitor {
+            budgets,
+            states: [BudgetState::default(); BUDGET_KINDS.len()],
+            active_alerts: Vec::new(),
+            num_frames: 0,
+            num_frames_over_budget: 0,
+        }
+    }
+
+    /// The latest alert of each budget that is alerting.
+    pub fn active_alerts(&self) -> &[BudgetAlert] {
+        &self.active_alerts
+    }
+
+    pub fn evaluate(&mut self, stats: &FrameStats) -> Vec<BudgetEvent> {
+        let mut events = Vec::new();
+        let mut is_frame_over_budget = false;
+        for (kind_idx, kind) in BUDGET_KINDS.iter().enumerate() {
+            let opt_alert = match self.check(*kind, stats) {
+                Some(opt_alert) => opt_alert,
+                None => continue, // Disabled
+            };
+            is_frame_over_budget |= opt_alert.is_some();
+            let state = &mut self.states[kind_idx];
+            // Counts frames that point towards the other state
+            if opt_alert.is_some() != state.is_alerting {
+                state.num_frames_in_a_row += 1;
+            } else {
+                state.num_frames_in_a_row = 0;
+            }
+            let is_changing = state.num_frames_in_a_row >= self.budgets.num_frames_to_alert.max(1);
+            if is_changing {
+                state.is_alerting = !state.is_alerting;
+                state.num_frames_in_a_row = 0;
+            }
+
+            match opt_alert {
+                Some(alert) if state.is_alerting => {
+                    if is_changing {
+                        events.push(BudgetEvent::Raised(alert.clone()));
+                    }
+                    self.active_alerts.retain(|active| active.kind != *kind);
+                    self.active_alerts.push(alert);
+                }
+                None if is_changing => {
+                    self.active_alerts.retain(|active| active.kind != *kind);
+                    events.push(BudgetEvent::Cleared(*kind));
+                }
+                _ => {
+                    // Still alerting until it has been met for long enough, or not yet alerting
+                }
+            }
+        }
+        self.num_frames += 1;
+        if is_frame_over_budget {
+            self.num_frames_over_budget += 1;
+        }
+        events
+    }
+
+    // None if the budget is disabled or there's nothing to check, Some(None) if it's met
+    fn check(&self, kind: BudgetKind, stats: &FrameStats) -> Option<Option<BudgetAlert>> {
+        let (value, budget, opt_worst) = match kind {
+            BudgetKind::RecordMs => (
+                stats.record_ms as f64,
+                self.budgets.opt_record_ms? as f64,
+                worst_offender(
+                    stats
+                        .pass_record_ms
+                        .iter()
+                        .map(|(name, ms)| (name.as_str(), *ms as f64)),
+                ),
+            ),
+            BudgetKind::UploadBytes => (
+                stats.total_upload_bytes() as f64,
+                self.budgets.opt_upload_bytes? as f64,
+                worst_offender(
+                    stats
+                        .upload_bytes
+                        .iter()
+                        .map(|(category, bytes)| (*category, *bytes as f64)),
+                ),
+            ),
+            BudgetKind::Draws => (
+                stats.num_draws() as f64,
+                self.budgets.opt_draws? as f64,
+                worst_offender(
+                    stats
+                        .pass_draws
+                        .iter()
+                        .map(|(name, num_draws)| (name.as_str(), *num_draws as f64)),
+                ),
+            ),
+            BudgetKind::GpuMs => {
+                let budget = self.budgets.opt_gpu_ms? as f64;
+                (
+                    stats.opt_gpu_ms? as f64,
+                    budget,
+                    worst_offender(
+                        stats
+                            .pass_gpu_ms
+                            .iter()
+                            .map(|(name, ms)| (name.as_str(), *ms as f64)),
+                    ),
+                )
+            }
+            BudgetKind::Barriers => (
+                stats.num_barriers() as f64,
+                self.budgets.opt_barriers? as f64,
+                worst_offender(
+                    stats
+                        .image_barriers
+                        .iter()
+                        .map(|(name, num_barriers)| (name.as_str(), *num_barriers as f64)),
+                ),
+            ),
+        };
+        if value <= budget {
+            return Some(None);
+        }
+        let (worst_offender, worst_offender_value) =
+            opt_worst.unwrap_or_else(|| (String::from("none"), 0.0));
+        Some(Some(BudgetAlert {
+            kind,
+            value,
+            budget,
+            worst_offender,
+            worst_offender_value,
+        }))
+    }
+}
+
+// The first of the largest, so that ties go to the earlier pass
+fn worst_offender<'a>(values: impl Iterator<Item = (&'a str, f64)>) -> Option<(String, f64)> {
+    let mut opt_worst: Option<(&str, f64)> = None;
+    for (name, value) in values {
+        if opt_worst.is_none_or(|(_, worst_value)| value > worst_value) {
+            opt_worst = Some((name, value));
+        }
+    }
+    opt_worst.map(|(name, value)| (String::from(name), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budgets(num_frames_to_alert: usize) -> FrameBudgets {
+        FrameBudgets {
+            opt_draws: Some(100),
+            num_frames_to_alert,
+            ..Default::default()
+        }
+    }
+
+    fn stats_with_draws(pass_draws: &[(&str, usize)]) -> FrameStats {
+        FrameStats {
+            pass_draws: pass_draws
+                .iter()
+                .map(|(name, num_draws)| (String::from(*name), *num_draws))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    // The events of every frame in which there were any, by frame index
+    fn evaluate_all(
+        monitor: &mut BudgetMonitor,
+        stream: &[FrameStats],
+    ) -> Vec<(usize, Vec<BudgetEvent>)> {
+        stream
+            .iter()
+            .enumerate()
+            .map(|(frame_idx, stats)| (frame_idx, monitor.evaluate(stats)))
+            .filter(|(_, events)| !events.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn alerts_are_raised_and_cleared_after_enough_frames_in_a_row() {
+        let over = stats_with_draws(&[("scene", 150)]);
+        let under = stats_with_draws(&[("scene", 50)]);
+        let mut stream = vec![under.clone(); 2];
+        stream.extend(vec![over.clone(); 3]);
+        stream.extend(vec![under.clone(); 3]);
+
+        let mut monitor = BudgetMonitor::new(budgets(3));
+        let events = evaluate_all(&mut monitor, &stream);
+        assert_eq!(events.len(), 2, "{:?}", events);
+        assert_eq!(events[0].0, 4);
+        match &events[0].1[..] {
+            [BudgetEvent::Raised(alert)] => {
+                assert_eq!(alert.kind, BudgetKind::Draws);
+                assert_eq!(alert.value, 150.0);
+                assert_eq!(alert.budget, 100.0);
+                assert_eq!(alert.worst_offender, "scene");
+            }
+            other => panic!("Expected one raised alert, got {:?}", other),
+        }
+        assert_eq!(
+            events[1],
+            (7, vec![BudgetEvent::Cleared(BudgetKind::Draws)])
+        );
+        assert!(monitor.active_alerts().is_empty());
+        assert_eq!(monitor.num_frames, 8);
+        assert_eq!(monitor.num_frames_over_budget, 3);
+    }
+
+    #[test]
+    fn values_hovering_around_the_budget_dont_flap() {
+        let over = stats_with_draws(&[("scene", 101)]);
+        let under = stats_with_draws(&[("scene", 100)]); // Exactly on budget is within it
+        let stream: Vec<FrameStats> = (0..20)
+            .map(|frame_idx| {
+                if frame_idx % 3 == 2 {
+                    under.clone()
+                } else {
+                    over.clone()
+                }
+            })
+            .collect();
+
+        let mut monitor = BudgetMonitor::new(budgets(3));
+        assert!(evaluate_all(&mut monitor, &stream).is_empty());
+        assert!(monitor.active_alerts().is_empty());
+        assert_eq!(monitor.num_frames_over_budget, 14);
+
+        // Once alerting, single frames under budget don't clear it either
+        let mut stream = vec![over.clone(); 3];
+        stream.extend((0..20).map(|frame_idx| {
+            if frame_idx % 2 == 0 {
+                under.clone()
+            } else {
+                over.clone()
+            }
+        }));
+        let mut monitor = BudgetMonitor::new(budgets(3));
+        let events = evaluate_all(&mut monitor, &stream);
+        assert_eq!(events.len(), 1, "{:?}", events);
+        assert_eq!(monitor.active_alerts().len(), 1);
+    }
+
+    #[test]
+    fn active_alerts_follow_the_latest_frame() {
+        let mut monitor = BudgetMonitor::new(budgets(1));
+        monitor.evaluate(&stats_with_draws(&[("scene", 150)]));
+        monitor.evaluate(&stats_with_draws(&[("scene", 80), ("ui", 90)]));
+        let alerts = monitor.active_alerts();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].value, 170.0);
+        assert_eq!(alerts[0].worst_offender, "ui");
+        assert_eq!(alerts[0].worst_offender_value, 90.0);
+    }
+
+    #[test]
+    fn ties_go_to_the_earlier_pass() {
+        let mut monitor = BudgetMonitor::new(budgets(1));
+        let events = monitor.evaluate(&stats_with_draws(&[
+            ("shadows", 40),
+            ("scene", 70),
+            ("ui", 70),
+            ("post", 70),
+        ]));
+        match &events[..] {
+            [BudgetEvent::Raised(alert)] => {
+                assert_eq!(alert.worst_offender, "scene");
+                assert_eq!(alert.worst_offender_value, 70.0);
+            }
+            other => panic!("Expected one raised alert, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn alerts_without_offenders_name_none() {
+        let mut monitor = BudgetMonitor::new(FrameBudgets {
+            opt_record_ms: Some(1.0),
+            num_frames_to_alert: 1,
+            ..Default::default()
+        });
+        let events = monitor.evaluate(&FrameStats {
+            record_ms: 2.0,
+            ..Default::default()
+        });
+        match &events[..] {
+            [BudgetEvent::Raised(alert)] => {
+                assert_eq!(alert.worst_offender, "none");
+                assert_eq!(alert.worst_offender_value, 0.0);
+            }
+            other => panic!("Expected one raised alert, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn disabled_budgets_are_ignored() {
+        let mut monitor = BudgetMonitor::new(FrameBudgets {
+            num_frames_to_alert: 1,
+            ..Default::default()
+        });
+        let stats = FrameStats {
+            record_ms: 1000.0,
+            pass_draws: vec![(String::from("scene"), 1_000_000)],
+            upload_bytes: vec![("staging", u64::MAX / 2)],
+            opt_gpu_ms: Some(1000.0),
+            image_barriers: vec![(String::from("image_color"), 1000)],
+            ..Default::default()
+        };
+        for _ in 0..10 {
+            assert!(monitor.evaluate(&stats).is_empty());
+        }
+        assert_eq!(monitor.num_frames, 10);
+        assert_eq!(monitor.num_frames_over_budget, 0);
+    }
+
+    #[test]
+    fn gpu_time_counts_only_once_measured() {
+        let mut monitor = BudgetMonitor::new(FrameBudgets {
+            opt_gpu_ms: Some(10.0),
+            num_frames_to_alert: 2,
+            ..Default::default()
+        });
+        let over = FrameStats {
+            opt_gpu_ms: Some(12.0),
+            pass_gpu_ms: vec![
+                (String::from("shadows"), 3.0),
+                (String::from("scene"), 8.0),
+                (String::from("post"), 1.0),
+            ],
+            ..Default::default()
+        };
+        // An unmeasured frame neither raises the alert nor breaks the run of frames over it
+        assert!(monitor.evaluate(&over).is_empty());
+        assert!(monitor.evaluate(&FrameStats::default()).is_empty());
+        let events = monitor.evaluate(&over);
+        match &events[..] {
+            [BudgetEvent::Raised(alert)] => {
+                assert_eq!(alert.kind, BudgetKind::GpuMs);
+                assert_eq!(alert.value, 12.0);
+                assert_eq!(alert.worst_offender, "scene");
+                assert_eq!(
+                    alert.to_text(),
+                    "GPU time was 12.00 ms, over the budget of 10.00 ms. Slowest pass on the GPU: `scene` (8.00 ms)."
+                );
+            }
+            other => panic!("Expected one raised alert, got {:?}", other),
+        }
+        assert_eq!(monitor.num_frames_over_budget, 2);
+    }
+
+    #[test]
+    fn barriers_are_budgeted_by_image() {
+        let mut monitor = BudgetMonitor::new(FrameBudgets {
+            opt_barriers: Some(4),
+            num_frames_to_alert: 1,
+            ..Default::default()
+        });
+        let stats = FrameStats {
+            image_barriers: vec![
+                (String::from("image_depth"), 2),
+                (String::from("image_hdr"), 3),
+                (String::from("image_bloom"), 3),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(stats.num_barriers(), 8);
+        let events = monitor.evaluate(&stats);
+        match &events[..] {
+            [BudgetEvent::Raised(alert)] => {
+                assert_eq!(alert.kind, BudgetKind::Barriers);
+                assert_eq!(alert.value, 8.0);
+                assert_eq!(alert.worst_offender, "image_hdr");
+            }
+            other => panic!("Expected one raised alert, got {:?}", other),
+        }
+        assert_eq!(
+            monitor.evaluate(&FrameStats::default()),
+            vec![BudgetEvent::Cleared(BudgetKind::Barriers)]
+        );
+    }
+}
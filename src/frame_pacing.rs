@@ -0,0 +1,139 @@
+use crate::*;
+use std::time::{Duration, Instant};
+
+// Smoothing factor of the frame time average. Lower reacts slower to spikes.
+const FRAME_TIME_EMA_WEIGHT: f32 = 0.1;
+// Present intervals are counted in 1 ms buckets, with the last one catching the rest
+const NUM_INTERVAL_BUCKETS: usize = 50;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PacingMode {
+    Fifo, // Present on vblanks
+    /* For displays with variable refresh rate. Presents as soon as a frame is
+    ready, within the display's range of frame times, instead of waiting for
+    the next fixed vblank. */
+    AdaptiveSync,
+}
+
+/* Decides the present mode and when to present. In `AdaptiveSync` mode, the
+swapchain uses FIFO_RELAXED, which presents a late frame right away instead of
+holding it for a vblank. On a display with variable refresh rate, every
+present is then shown as soon as it arrives. MAILBOX isn't used, since it
+discards frames on fixed-refresh displays. Without FIFO_RELAXED, and on
+fixed-refresh displays, the mode behaves like `Fifo`.
+
+Presents are held back until `1 / max_refresh_hz` has passed since the last
+one, which keeps the frame rate inside the range. With `is_smoothing_enabled`,
+they are held until the average frame time instead, so that one slow frame
+followed by fast ones doesn't swing the refresh rate between the ends of the
+range. The range can't be queried, so it's configured. */
+pub struct FramePacer {
+    pub mode: PacingMode,
+    pub min_refresh_hz: f32,
+    pub max_refresh_hz: f32,
+    pub is_smoothing_enabled: bool,
+    avg_frame_time: f32, // Seconds, from the last present to being ready for the next
+    opt_last_present_instant: Option<Instant>,
+    interval_buckets: [usize; NUM_INTERVAL_BUCKETS],
+    num_intervals: usize,
+    interval_sum: Duration,
+}
+
+impl FramePacer {
+    pub fn new(opt_display_refresh_hz: Option<u16>) -> FramePacer {
+        let max_refresh_hz = opt_display_refresh_hz.map_or(144.0, |hz| hz as f32);
+        FramePacer {
+            mode: PacingMode::Fifo,
+            min_refresh_hz: 48.0,
+            max_refresh_hz,
+            is_smoothing_enabled: true,
+            avg_frame_time: 1.0 / max_refresh_hz,
+            opt_last_present_instant: None,
+            interval_buckets: [0; NUM_INTERVAL_BUCKETS],
+            num_intervals: 0,
+            interval_sum: Duration::from_secs(0),
+        }
+    }
+
+    pub fn present_mode(&self, available: &[vk::PresentModeKHR]) -> vk::PresentModeKHR {
+        match self.mode {
+            PacingMode::AdaptiveSync if available.contains(&vk::PresentModeKHR::FIFO_RELAXED) => {
+                vk::PresentModeKHR::FIFO_RELAXED
+            }
+            // FIFO support is required by the spec
+            _ => vk::PresentModeKHR::FIFO,
+        }
+    }
+
+    /// Sleep until the frame should be presented. Does nothing in `Fifo` mode,
+    /// where the swapchain does the waiting.
+    pub fn wait_before_present(&mut self) {
+        if self.mode != PacingMode::AdaptiveSync {
+            return;
+        }
+        let last_present_instant = match self.opt_last_present_instant {
+            Some(instant) => instant,
+            None => return,
+        };
+        let frame_time = last_present_instant.elapsed().as_secs_f32();
+        self.avg_frame_time += (frame_time - self.avg_frame_time) * FRAME_TIME_EMA_WEIGHT;
+
+        let min_interval = 1.0 / self.max_refresh_hz.max(1.0);
+        let max_interval = 1.0 / self.min_refresh_hz.clamp(1.0, self.max_refresh_hz.max(1.0));
+        let target_interval = if self.is_smoothing_enabled {
+            self.avg_frame_time.clamp(min_interval, max_interval)
+        } else {
+            min_interval
+        };
+        if frame_time < target_interval {
+            std::thread::sleep(Duration::from_secs_f32(target_interval - frame_time));
+        }
+    }
+
+    pub fn on_present(&mut self) {
+        let now = Instant::now();
+        if let Some(last_present_instant) = self.opt_last_present_instant {
+            let interval = now - last_present_instant;
+            let bucket_idx = (interval.as_millis() as usize).min(NUM_INTERVAL_BUCKETS - 1);
+            self.interval_buckets[bucket_idx] += 1;
+            self.num_intervals += 1;
+            self.interval_sum += interval;
+        }
+        self.opt_last_present_instant = Some(now);
+    }
+
+    /// Forget the recorded present intervals, e.g. when switching modes.
+    pub fn reset_stats(&mut self) {
+        self.interval_buckets = [0; NUM_INTERVAL_BUCKETS];
+        self.num_intervals = 0;
+        self.interval_sum = Duration::from_secs(0);
+        self.opt_last_present_instant = None;
+    }
+
+    /// Print a histogram of the intervals between presents.
+    pub fn print_summary(&self) {
+        if self.num_intervals == 0 {
+            return;
+        }
+        println!(
+            "Present intervals in {:?} mode over {} frames: mean {:.2} ms",
+            self.mode,
+            self.num_intervals,
+            self.interval_sum.as_secs_f64() * 1000.0 / self.num_intervals as f64,
+        );
+        const MAX_BAR_LEN: usize = 40;
+        let max_count = *self.interval_buckets.iter().max().unwrap();
+        for (bucket_idx, count) in self.interval_buckets.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            let bar_len = (count * MAX_BAR_LEN).div_ceil(max_count);
+            let label = if bucket_idx == NUM_INTERVAL_BUCKETS - 1 {
+                format!("{:>3}+ ms", bucket_idx)
+            } else {
+                format!("{:>3}-{} ms", bucket_idx, bucket_idx + 1)
+            };
+            println!("  {:>9} {:>6} {}", label, count, "#".repeat(bar_len));
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+/// Statistics over a run of frame times, e.g. for comparing benchmark runs.
+#[derive(Copy, Clone, Debug)]
+pub struct FrameTimeSummary {
+    pub num_frames: usize,
+    pub mean_ms: f64,
+    pub median_ms: f64,
+    pub p99_ms: f64,
+    pub min_ms: f64,
+    pub max_ms: f64,
+    pub std_dev_ms: f64,
+}
+
+impl FrameTimeSummary {
+    pub fn new(frame_times: &[Duration]) -> Option<FrameTimeSummary> {
+        if frame_times.is_empty() {
+            return None;
+        }
+        let mut frame_times_ms: Vec<f64> = frame_times
+            .iter()
+            .map(|duration| duration.as_secs_f64() * 1000.0)
+            .collect();
+        frame_times_ms.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let num_frames = frame_times_ms.len();
+        let mean_ms = frame_times_ms.iter().sum::<f64>() / num_frames as f64;
+        let variance = frame_times_ms
+            .iter()
+            .map(|ms| (ms - mean_ms) * (ms - mean_ms))
+            .sum::<f64>()
+            / num_frames as f64;
+        let percentile = |p: f64| {
+            let idx = ((num_frames - 1) as f64 * p).round() as usize;
+            frame_times_ms[idx]
+        };
+        Some(FrameTimeSummary {
+            num_frames,
+            mean_ms,
+            median_ms: percentile(0.5),
+            p99_ms: percentile(0.99),
+            min_ms: frame_times_ms[0],
+            max_ms: frame_times_ms[num_frames - 1],
+            std_dev_ms: variance.sqrt(),
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        format!(
+            "{} frames: mean {:.3} ms, median {:.3} ms, 99th percentile {:.3} ms, min {:.3} ms, max {:.3} ms, std dev {:.3} ms",
+            self.num_frames,
+            self.mean_ms,
+            self.median_ms,
+            self.p99_ms,
+            self.min_ms,
+            self.max_ms,
+            self.std_dev_ms
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"num_frames\": {}, \"mean_ms\": {}, \"median_ms\": {}, \"p99_ms\": {}, \"min_ms\": {}, \"max_ms\": {}, \"std_dev_ms\": {}}}",
+            self.num_frames,
+            self.mean_ms,
+            self.median_ms,
+            self.p99_ms,
+            self.min_ms,
+            self.max_ms,
+            self.std_dev_ms
+        )
+    }
+}
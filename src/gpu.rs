@@ -1,5 +1,16 @@
 use crate::*;
-use std::os::raw::c_char;
+use std::os::raw::{c_char, c_void};
+use std::path::Path;
+use std::rc::Rc;
+
+const GPU_SELECTION_ENV_VAR: &str = "GRAPHENE_GPU";
+const PUSH_DESCRIPTOR_EXT: &str = "VK_KHR_push_descriptor";
+const DRAW_INDIRECT_COUNT_EXT: &str = "VK_KHR_draw_indirect_count";
+const MULTIVIEW_EXT: &str = "VK_KHR_multiview";
+const GPU_SELECTION_PATH: &str = "_cache/gpu_selection.txt";
+// Set to 1, or pass the argument, to stage every upload even with unified memory
+const FORCE_STAGING_ENV_VAR: &str = "GRAPHENE_FORCE_STAGING";
+const FORCE_STAGING_ARG: &str = "--force-staging";
 
 pub struct Gpu {
     // Physical device
@@ -7,26 +18,62 @@ pub struct Gpu {
     pub exts: Vec<vk::ExtensionProperties>,
     pub present_modes: Vec<vk::PresentModeKHR>,
     pub memory_properties: vk::PhysicalDeviceMemoryProperties,
-    pub _properties: vk::PhysicalDeviceProperties,
+    pub properties: vk::PhysicalDeviceProperties,
     pub graphics_queue_idx: u32,
     pub present_queue_idx: u32,
+    // A transfer-only family if there is one, so that uploads can run while
+    // the graphics queue renders. Otherwise the graphics family.
+    pub transfer_queue_idx: u32,
+    pub queue_family_properties: Vec<vk::QueueFamilyProperties>,
     // Logical device
     pub device: ash::Device,
     pub graphics_queue: vk::Queue,
     pub present_queue: vk::Queue,
+    pub transfer_queue: vk::Queue,
+    pub transfer_command_pool: vk::CommandPool,
+    pub enabled_exts: Vec<String>,
+    pub enabled_features: vk::PhysicalDeviceFeatures,
+    pub depth_format: vk::Format, // The first of `DEPTH_FORMATS` that can be a depth attachment
+    pub sync_pool: SyncPool,
+    pub allocator: Rc<Allocator>, // Shared with the resources, which free their memory on drop
+    pub staging_pool: StagingPool,
+    pub debug_fill: Rc<DebugFill>, // Shared with device-local buffers, which cancel their fills on drop
+    is_device_local_written_directly: bool, // Resolved once, since it's checked for every upload
+    // Extensions
+    pub opt_push_descriptor_fn: Option<vk::KhrPushDescriptorFn>, // None if VK_KHR_push_descriptor isn't supported
+    pub opt_draw_indirect_count_fn: Option<vk::KhrDrawIndirectCountFn>, // None if VK_KHR_draw_indirect_count isn't supported
+    pub is_multiview_supported: bool, // VK_KHR_multiview, whose feature is required with it
 }
 
 impl Drop for Gpu {
     fn drop(&mut self) {
+        self.staging_pool.destroy(&self.sync_pool);
+        // Everything else should have been destroyed by now
+        let leaked_allocations = self.allocator.live_allocations();
+        if !leaked_allocations.is_empty() {
+            println!(
+                "Leaked {} GPU memory allocations:",
+                leaked_allocations.len()
+            );
+            for allocation in &leaked_allocations {
+                println!("  {} ({} bytes)", allocation.name, allocation.size);
+            }
+        }
+        self.allocator.destroy();
+        self.sync_pool.destroy();
         unsafe {
+            self.device
+                .destroy_command_pool(self.transfer_command_pool, None);
+            audit_destroyed(VkObjectKind::CommandPool, self.transfer_command_pool);
             self.device.destroy_device(None);
+            audit_destroyed(VkObjectKind::Device, self.device.handle());
         }
     }
 }
 
 impl Gpu {
     pub fn new(basis: &Basis) -> Gpu {
-        let required_exts = vec![String::from("VK_KHR_swapchain")];
+        let required_exts = [String::from("VK_KHR_swapchain")];
 
         // # Enumerate eligible GPUs
         struct CandidateGpu {
@@ -37,6 +84,8 @@ impl Gpu {
             properties: vk::PhysicalDeviceProperties,
             graphics_queue_idx: u32,
             present_queue_idx: u32,
+            transfer_queue_idx: u32,
+            queue_families: Vec<vk::QueueFamilyProperties>,
         }
         let candidate_gpus: Vec<CandidateGpu> = {
             let physical_devices = unsafe {
@@ -125,6 +174,15 @@ impl Gpu {
                     continue;
                 }
 
+                // Dedicated transfer families are the ones without graphics or compute
+                let opt_transfer_queue_idx = queue_families.iter().position(|&fam| {
+                    fam.queue_count > 0
+                        && fam.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                        && !fam
+                            .queue_flags
+                            .intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+                });
+
                 if let Some(graphics_queue_idx) = opt_graphics_queue_idx {
                     if let Some(present_queue_idx) = opt_present_queue_idx {
                         candidate_gpus.push(CandidateGpu {
@@ -135,6 +193,9 @@ impl Gpu {
                             properties,
                             graphics_queue_idx: graphics_queue_idx as u32,
                             present_queue_idx: present_queue_idx as u32,
+                            transfer_queue_idx: opt_transfer_queue_idx.unwrap_or(graphics_queue_idx)
+                                as u32,
+                            queue_families: queue_families.clone(),
                         });
                     }
                 }
@@ -145,18 +206,32 @@ impl Gpu {
 
         // # Create a logical device, queues, the command pool, sync primitives, and the final gpu struct
         #[allow(clippy::let_and_return)]
-        let gpu = {
+        let mut gpu = {
             // Pick the most eligible of the candidate GPU.
-            // Currently, we just pick the first one.
+            // Unless the user has chosen one, we just pick the first one.
             // TODO: Might want to pick the most powerful GPU in the future.
-            let cgpu = candidate_gpus
-                .first()
-                .expect("Failed to find a suitable GPU.");
+            assert!(!candidate_gpus.is_empty(), "Failed to find a suitable GPU.");
+            println!("Available GPUs:");
+            for (i, cgpu) in candidate_gpus.iter().enumerate() {
+                println!(
+                    "    {}: {}",
+                    i,
+                    adapter_description(&cgpu.properties, &cgpu.memory_properties)
+                );
+            }
+            let candidate_properties: Vec<vk::PhysicalDeviceProperties> =
+                candidate_gpus.iter().map(|cgpu| cgpu.properties).collect();
+            let cgpu = &candidate_gpus[select_gpu_idx(&candidate_properties)];
+            println!(
+                "Using GPU: {}",
+                adapter_description(&cgpu.properties, &cgpu.memory_properties)
+            );
 
             use std::collections::HashSet;
             let mut unique_queue_families = HashSet::new();
             unique_queue_families.insert(cgpu.graphics_queue_idx);
             unique_queue_families.insert(cgpu.present_queue_idx);
+            unique_queue_families.insert(cgpu.transfer_queue_idx);
 
             let queue_priorities = [1.0_f32];
             let mut queue_create_infos = vec![];
@@ -172,27 +247,75 @@ impl Gpu {
                 queue_create_infos.push(queue_create_info);
             }
 
+            let supported_features = unsafe {
+                basis
+                    .instance
+                    .get_physical_device_features(cgpu.physical_device)
+            };
             let physical_device_features = vk::PhysicalDeviceFeatures {
-                sampler_anisotropy: vk::TRUE, // enable anisotropy device feature from Chapter-24.
+                // Without it, samplers fall back to no anisotropy
+                sampler_anisotropy: supported_features.sampler_anisotropy,
+                // Needed for shader assertions, which append to a storage buffer
+                fragment_stores_and_atomics: supported_features.fragment_stores_and_atomics,
+                // Without it, indirect draws are limited to one per call
+                multi_draw_indirect: supported_features.multi_draw_indirect,
+                // Without it, BC-compressed images are decoded to RGBA8 on load
+                texture_compression_bc: supported_features.texture_compression_bc,
+                // Without it, passes set to shade every sample are shaded once per texel
+                sample_rate_shading: supported_features.sample_rate_shading,
                 ..Default::default()
             };
 
-            let raw_ext_names: Vec<CString> = required_exts
+            // Optional extensions are enabled if the GPU supports them
+            let is_push_descriptor_supported = cgpu
+                .exts
+                .iter()
+                .any(|ext| vk_to_string(&ext.extension_name) == PUSH_DESCRIPTOR_EXT);
+            let is_draw_indirect_count_supported = cgpu
+                .exts
+                .iter()
+                .any(|ext| vk_to_string(&ext.extension_name) == DRAW_INDIRECT_COUNT_EXT);
+            let is_multiview_supported = basis.is_properties_2_enabled
+                && cgpu
+                    .exts
+                    .iter()
+                    .any(|ext| vk_to_string(&ext.extension_name) == MULTIVIEW_EXT);
+            let mut enabled_exts = required_exts.to_vec();
+            if is_push_descriptor_supported {
+                enabled_exts.push(String::from(PUSH_DESCRIPTOR_EXT));
+            }
+            if is_draw_indirect_count_supported {
+                enabled_exts.push(String::from(DRAW_INDIRECT_COUNT_EXT));
+            }
+            if is_multiview_supported {
+                enabled_exts.push(String::from(MULTIVIEW_EXT));
+            }
+
+            let raw_ext_names: Vec<CString> = enabled_exts
                 .iter()
                 .map(|ext| CString::new(ext.to_string()).unwrap())
                 .collect();
             let ext_names: Vec<*const c_char> =
                 raw_ext_names.iter().map(|ext| ext.as_ptr()).collect();
 
+            // Devices with the extension support the feature, but it still has to be enabled
+            let multiview_features = vk::PhysicalDeviceMultiviewFeatures {
+                multiview: vk::TRUE,
+                ..Default::default()
+            };
             let device_create_info = vk::DeviceCreateInfo {
                 s_type: vk::StructureType::DEVICE_CREATE_INFO,
-                p_next: ptr::null(),
+                p_next: if is_multiview_supported {
+                    &multiview_features as *const _ as *const c_void
+                } else {
+                    ptr::null()
+                },
                 flags: vk::DeviceCreateFlags::empty(),
                 queue_create_info_count: queue_create_infos.len() as u32,
                 p_queue_create_infos: queue_create_infos.as_ptr(),
                 enabled_layer_count: 0,
                 pp_enabled_layer_names: ptr::null(),
-                enabled_extension_count: required_exts.len() as u32,
+                enabled_extension_count: enabled_exts.len() as u32,
                 pp_enabled_extension_names: ext_names.as_ptr(),
                 p_enabled_features: &physical_device_features,
             };
@@ -203,24 +326,312 @@ impl Gpu {
                     .create_device(cgpu.physical_device, &device_create_info, None)
                     .expect("Failed to create logical Device!")
             };
+            audit_created(
+                VkObjectKind::Device,
+                device.handle(),
+                &[vk_object_id(
+                    VkObjectKind::Instance,
+                    basis.instance.handle(),
+                )],
+                "device",
+            );
 
             let graphics_queue = unsafe { device.get_device_queue(cgpu.graphics_queue_idx, 0) };
             let present_queue = unsafe { device.get_device_queue(cgpu.present_queue_idx, 0) };
+            let transfer_queue = unsafe { device.get_device_queue(cgpu.transfer_queue_idx, 0) };
+            let transfer_command_pool = {
+                let info = vk::CommandPoolCreateInfo::builder()
+                    .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+                    .queue_family_index(cgpu.transfer_queue_idx);
+                let transfer_command_pool = unsafe {
+                    device
+                        .create_command_pool(&info, None)
+                        .expect("Failed to create command pool")
+                };
+                audit_created(
+                    VkObjectKind::CommandPool,
+                    transfer_command_pool,
+                    &[device_id(&device)],
+                    "transfer_command_pool",
+                );
+                transfer_command_pool
+            };
+
+            let opt_push_descriptor_fn = if is_push_descriptor_supported {
+                Some(vk::KhrPushDescriptorFn::load(|name| unsafe {
+                    std::mem::transmute(
+                        basis
+                            .instance
+                            .get_device_proc_addr(device.handle(), name.as_ptr()),
+                    )
+                }))
+            } else {
+                None
+            };
+            let opt_draw_indirect_count_fn = if is_draw_indirect_count_supported {
+                Some(vk::KhrDrawIndirectCountFn::load(|name| unsafe {
+                    std::mem::transmute(
+                        basis
+                            .instance
+                            .get_device_proc_addr(device.handle(), name.as_ptr()),
+                    )
+                }))
+            } else {
+                None
+            };
+            let depth_format = DEPTH_FORMATS
+                .iter()
+                .map(|format| format.to_vk())
+                .find(|format| {
+                    let format_properties = unsafe {
+                        basis
+                            .instance
+                            .get_physical_device_format_properties(cgpu.physical_device, *format)
+                    };
+                    format_properties
+                        .optimal_tiling_features
+                        .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+                })
+                .expect("The GPU supports none of the depth formats.");
+            let sync_pool = SyncPool::new(device.clone());
+            let allocator =
+                Allocator::new(device.clone(), cgpu.memory_properties, &cgpu.properties);
 
             Gpu {
                 physical_device: cgpu.physical_device,
                 exts: cgpu.exts.clone(),
                 present_modes: cgpu.present_modes.clone(),
                 memory_properties: cgpu.memory_properties,
-                _properties: cgpu.properties,
+                properties: cgpu.properties,
                 graphics_queue_idx: cgpu.graphics_queue_idx,
                 present_queue_idx: cgpu.present_queue_idx,
+                transfer_queue_idx: cgpu.transfer_queue_idx,
+                queue_family_properties: cgpu.queue_families.clone(),
                 device,
                 graphics_queue,
                 present_queue,
+                transfer_queue,
+                transfer_command_pool,
+                enabled_exts,
+                enabled_features: physical_device_features,
+                depth_format,
+                sync_pool,
+                allocator: Rc::new(allocator),
+                staging_pool: StagingPool::new(),
+                debug_fill: Rc::new(DebugFill::new()),
+                is_device_local_written_directly: false,
+                opt_push_descriptor_fn,
+                opt_draw_indirect_count_fn,
+                is_multiview_supported,
             }
         };
+        let is_staging_forced = std::env::var(FORCE_STAGING_ENV_VAR)
+            .is_ok_and(|value| value == "1")
+            || std::env::args().any(|arg| arg == FORCE_STAGING_ARG);
+        gpu.is_device_local_written_directly = gpu.has_unified_memory() && !is_staging_forced;
 
         gpu
     }
+
+    /// The highest count up to `requested` that both color and depth
+    /// attachments support.
+    pub fn supported_sample_count(&self, requested: SampleCount) -> SampleCount {
+        let limits = &self.properties.limits;
+        let supported =
+            limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+        let mut sample_count = requested;
+        while !supported.contains(sample_count.to_vk()) {
+            sample_count = sample_count.lower();
+        }
+        sample_count
+    }
+
+    /// How many draws one indirect draw call can issue.
+    pub fn max_indirect_draws(&self) -> u32 {
+        if self.enabled_features.multi_draw_indirect == vk::TRUE {
+            self.properties.limits.max_draw_indirect_count
+        } else {
+            1
+        }
+    }
+
+    pub fn memory_report(&self) -> MemoryReport {
+        MemoryReport::new(&self.allocator)
+    }
+
+    pub fn has_dedicated_transfer_queue(&self) -> bool {
+        self.transfer_queue_idx != self.graphics_queue_idx
+    }
+
+    /* True on integrated GPUs that have memory which is both device-local
+    and host-visible. Buffers there can be written in place, without a staging
+    copy. Discrete GPUs may also expose a small host-visible window into their
+    own memory, but it's too small to put every buffer in. */
+    pub fn has_unified_memory(&self) -> bool {
+        let unified_flags =
+            vk::MemoryPropertyFlags::DEVICE_LOCAL | vk::MemoryPropertyFlags::HOST_VISIBLE;
+        self.properties.device_type == vk::PhysicalDeviceType::INTEGRATED_GPU
+            && self.memory_properties.memory_types
+                [..self.memory_properties.memory_type_count as usize]
+                .iter()
+                .any(|memory_type| memory_type.property_flags.contains(unified_flags))
+    }
+
+    /// Whether device-local buffers are written in place instead of staged.
+    /// Decided when the GPU is created.
+    pub fn writes_device_local_directly(&self) -> bool {
+        self.is_device_local_written_directly
+    }
+
+    /* Record commands with `record` and submit them to the transfer queue.
+    Returns right away. Pass the result to `finish_transfer()` to wait for the
+    commands to complete. Resources that are used on another queue family
+    afterwards need an ownership transfer, unless they are concurrently
+    shared. */
+    pub fn submit_transfer(&self, record: impl FnOnce(vk::CommandBuffer)) -> PendingTransfer {
+        let command_buffer =
+            begin_single_use_command_buffer(&self.device, self.transfer_command_pool);
+        record(command_buffer);
+        let fence = self.sync_pool.fence("fence_transfer", false);
+        unsafe {
+            self.device
+                .end_command_buffer(command_buffer)
+                .expect("Failed to record end-command-buffer");
+            let command_buffers = [command_buffer];
+            let submit_info = [vk::SubmitInfo {
+                command_buffer_count: command_buffers.len() as u32,
+                p_command_buffers: command_buffers.as_ptr(),
+                ..Default::default()
+            }];
+            self.device
+                .queue_submit(self.transfer_queue, &submit_info, fence)
+                .expect("Failed to Queue Submit!");
+        }
+        PendingTransfer {
+            command_buffer,
+            fence,
+        }
+    }
+
+    /// Wait for a transfer to complete, and recycle what it was submitted with.
+    pub fn finish_transfer(&self, transfer: PendingTransfer) {
+        unsafe {
+            self.device
+                .wait_for_fences(&[transfer.fence], true, u64::MAX)
+                .expect("Failed to wait for Fence.");
+            self.device
+                .free_command_buffers(self.transfer_command_pool, &[transfer.command_buffer]);
+        }
+        self.sync_pool.release_fence(transfer.fence);
+    }
+}
+
+/// Commands that were submitted to the transfer queue, and might still be executing.
+pub struct PendingTransfer {
+    command_buffer: vk::CommandBuffer,
+    pub fence: vk::Fence, // Signaled once the commands complete
+}
+
+/// Identifies an adapter across restarts. Unlike the enumeration index, this
+/// survives adapters being reordered, e.g. after a driver update.
+fn adapter_key(properties: &vk::PhysicalDeviceProperties) -> String {
+    format!("{:04x}:{:04x}", properties.vendor_id, properties.device_id)
+}
+
+pub fn version_to_string(vendor_id: u32, version: u32) -> String {
+    const VENDOR_ID_NVIDIA: u32 = 0x10de;
+    if vendor_id == VENDOR_ID_NVIDIA {
+        // NVIDIA packs its driver version as 10.8.8.6 bits
+        format!(
+            "{}.{}.{}",
+            version >> 22,
+            (version >> 14) & 0xff,
+            (version >> 6) & 0xff
+        )
+    } else {
+        format!(
+            "{}.{}.{}",
+            ash::vk_version_major!(version),
+            ash::vk_version_minor!(version),
+            ash::vk_version_patch!(version)
+        )
+    }
+}
+
+pub fn adapter_description(
+    properties: &vk::PhysicalDeviceProperties,
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+) -> String {
+    let device_local_bytes: u64 = memory_properties.memory_heaps
+        [..memory_properties.memory_heap_count as usize]
+        .iter()
+        .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+        .map(|heap| heap.size)
+        .sum();
+    let mut memory_text = String::new();
+    write_bytes(&mut memory_text, device_local_bytes, 0);
+    format!(
+        "{} ({:?}, {}, id {}, driver {}, Vulkan {})",
+        vk_to_string(&properties.device_name),
+        properties.device_type,
+        memory_text,
+        adapter_key(properties),
+        version_to_string(properties.vendor_id, properties.driver_version),
+        version_to_string(0, properties.api_version),
+    )
+}
+
+/* The GPU can be chosen by setting the GRAPHENE_GPU environment variable to
+its index in the printed list. The choice is remembered in the _cache
+directory, so it only needs to be made once. Setting GRAPHENE_GPU=default
+forgets the choice. */
+fn select_gpu_idx(candidates: &[vk::PhysicalDeviceProperties]) -> usize {
+    // Explicit choice
+    if let Ok(value) = std::env::var(GPU_SELECTION_ENV_VAR) {
+        if value == "default" {
+            let _ = std::fs::remove_file(GPU_SELECTION_PATH);
+        } else {
+            match value.parse::<usize>() {
+                Ok(idx) if idx < candidates.len() => {
+                    let is_saved = Path::new(GPU_SELECTION_PATH)
+                        .parent()
+                        .map_or(Ok(()), std::fs::create_dir_all)
+                        .and_then(|_| {
+                            std::fs::write(GPU_SELECTION_PATH, adapter_key(&candidates[idx]))
+                        })
+                        .is_ok();
+                    if !is_saved {
+                        println!(
+                            "Failed to remember the GPU choice in `{}`.",
+                            GPU_SELECTION_PATH
+                        );
+                    }
+                    return idx;
+                }
+                _ => println!(
+                    "Ignoring {}=`{}`. Expected `default` or an index below {}.",
+                    GPU_SELECTION_ENV_VAR,
+                    value,
+                    candidates.len()
+                ),
+            }
+        }
+    }
+
+    // Remembered choice
+    if let Ok(saved_key) = std::fs::read_to_string(GPU_SELECTION_PATH) {
+        let saved_key = saved_key.trim();
+        if let Some(idx) = candidates
+            .iter()
+            .position(|properties| adapter_key(properties) == saved_key)
+        {
+            return idx;
+        }
+        println!(
+            "The remembered GPU `{}` is no longer present. Falling back to the default GPU.",
+            saved_key
+        );
+    }
+
+    0
 }
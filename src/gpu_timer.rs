@@ -0,0 +1,218 @@
+use crate::*;
+use std::cell::RefCell;
+
+// Passes past this many in a frame aren't timed, but still count towards the frame's time
+const MAX_TIMED_PASSES_PER_FRAME: usize = 64;
+// The frame's start and end, and then the start and end of each timed pass
+const NUM_QUERIES_PER_FRAME: u32 = 2 + 2 * MAX_TIMED_PASSES_PER_FRAME as u32;
+
+/// GPU time of a frame, and of the passes in it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GpuFrameTimes {
+    pub total_ms: f32,
+    pub pass_ms: Vec<(String, f32)>,
+}
+
+#[derive(Default)]
+struct TimedFrame {
+    pass_names: Vec<String>, // Of the passes that were timed, in order
+    is_pass_open: bool,      // Between begin_pass() and end_pass() of a timed pass
+    is_recorded: bool,       // Set once the frame's end has been written
+}
+
+/* How long the GPU spends on frames and passes, from timestamp queries. Each
+frame in flight has its own range of queries, which is read back once the
+frame's fence has signaled, so reading never waits for the GPU. The times are
+of the frame that last used a sync index, which is a few frames behind the one
+being recorded. Pass timestamps are written outside render passes, since
+multiview render passes would write one per view. Does nothing if the
+graphics queue has no timestamps. */
+pub struct GpuTimer {
+    opt_query_pool: Option<vk::QueryPool>, // None if timestamps aren't supported
+    ns_per_tick: f64,
+    valid_mask: u64, // Of the bits that the queue's timestamps have
+    frames: RefCell<Vec<TimedFrame>>,
+    device: ash::Device,
+}
+
+impl Drop for GpuTimer {
+    fn drop(&mut self) {
+        if let Some(query_pool) = self.opt_query_pool {
+            unsafe {
+                self.device.destroy_query_pool(query_pool, None);
+            }
+            audit_destroyed(VkObjectKind::QueryPool, query_pool);
+        }
+    }
+}
+
+impl GpuTimer {
+    pub fn new(num_frames: usize, gpu: &Gpu) -> GpuTimer {
+        let valid_bits =
+            gpu.queue_family_properties[gpu.graphics_queue_idx as usize].timestamp_valid_bits;
+        let opt_query_pool = if valid_bits > 0 {
+            let create_info = vk::QueryPoolCreateInfo::builder()
+                .query_type(vk::QueryType::TIMESTAMP)
+                .query_count(num_frames as u32 * NUM_QUERIES_PER_FRAME);
+            let query_pool = unsafe {
+                gpu.device
+                    .create_query_pool(&create_info, None)
+                    .expect("Failed to create query pool.")
+            };
+            audit_created(
+                VkObjectKind::QueryPool,
+                query_pool,
+                &[device_id(&gpu.device)],
+                "query_pool_gpu_timer",
+            );
+            Some(query_pool)
+        } else {
+            None
+        };
+        GpuTimer {
+            opt_query_pool,
+            ns_per_tick: gpu.properties.limits.timestamp_period as f64,
+            valid_mask: if valid_bits >= 64 {
+                u64::MAX
+            } else {
+                (1 << valid_bits) - 1
+            },
+            frames: RefCell::new((0..num_frames).map(|_| TimedFrame::default()).collect()),
+            device: gpu.device.clone(),
+        }
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.frames.borrow().len()
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.opt_query_pool.is_some()
+    }
+
+    /* The times of the last frame that used `sync_idx`, which the GPU must be
+    done with. None if it wasn't timed, e.g. because it's the first frame with
+    this sync index. */
+    pub fn read_back(&self, sync_idx: usize) -> Option<GpuFrameTimes> {
+        let query_pool = self.opt_query_pool?;
+        let frames = self.frames.borrow();
+        let frame = &frames[sync_idx];
+        if !frame.is_recorded {
+            return None;
+        }
+        let num_queries = 2 + 2 * frame.pass_names.len();
+        let mut ticks = vec![0_u64; num_queries];
+        unsafe {
+            self.device.get_query_pool_results(
+                query_pool,
+                sync_idx as u32 * NUM_QUERIES_PER_FRAME,
+                num_queries as u32,
+                &mut ticks,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        }
+        .ok()?;
+        let ms_between = |start: u64, end: u64| {
+            (end.wrapping_sub(start) & self.valid_mask) as f64 * self.ns_per_tick / 1_000_000.0
+        };
+        Some(GpuFrameTimes {
+            total_ms: ms_between(ticks[0], ticks[1]) as f32,
+            pass_ms: frame
+                .pass_names
+                .iter()
+                .enumerate()
+                .map(|(pass_idx, name)| {
+                    let start = ticks[2 + 2 * pass_idx];
+                    let end = ticks[3 + 2 * pass_idx];
+                    (name.clone(), ms_between(start, end) as f32)
+                })
+                .collect(),
+        })
+    }
+
+    /// Start timing a frame, right after its command buffer has begun, and
+    /// after reading back the times of the last frame with `sync_idx`.
+    pub fn begin_frame(&self, command_buffer: vk::CommandBuffer, sync_idx: usize) {
+        let frame = &mut self.frames.borrow_mut()[sync_idx];
+        frame.pass_names.clear();
+        frame.is_pass_open = false;
+        frame.is_recorded = false;
+        if let Some(query_pool) = self.opt_query_pool {
+            let first_query = sync_idx as u32 * NUM_QUERIES_PER_FRAME;
+            unsafe {
+                self.device.cmd_reset_query_pool(
+                    command_buffer,
+                    query_pool,
+                    first_query,
+                    NUM_QUERIES_PER_FRAME,
+                );
+                self.device.cmd_write_timestamp(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    query_pool,
+                    first_query,
+                );
+            }
+        }
+    }
+
+    /// Right before the command buffer ends.
+    pub fn end_frame(&self, command_buffer: vk::CommandBuffer, sync_idx: usize) {
+        if let Some(query_pool) = self.opt_query_pool {
+            unsafe {
+                self.device.cmd_write_timestamp(
+                    command_buffer,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    query_pool,
+                    sync_idx as u32 * NUM_QUERIES_PER_FRAME + 1,
+                );
+            }
+            self.frames.borrow_mut()[sync_idx].is_recorded = true;
+        }
+    }
+
+    /// Outside the pass's render pass, before it begins.
+    pub fn begin_pass(&self, command_buffer: vk::CommandBuffer, sync_idx: usize, name: &str) {
+        let query_pool = match self.opt_query_pool {
+            Some(query_pool) => query_pool,
+            None => return,
+        };
+        let frame = &mut self.frames.borrow_mut()[sync_idx];
+        if frame.pass_names.len() == MAX_TIMED_PASSES_PER_FRAME {
+            return;
+        }
+        let query = sync_idx as u32 * NUM_QUERIES_PER_FRAME + 2 + 2 * frame.pass_names.len() as u32;
+        unsafe {
+            self.device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                query_pool,
+                query,
+            );
+        }
+        frame.pass_names.push(String::from(name));
+        frame.is_pass_open = true;
+    }
+
+    /// Outside the pass's render pass, after it ends.
+    pub fn end_pass(&self, command_buffer: vk::CommandBuffer, sync_idx: usize) {
+        let query_pool = match self.opt_query_pool {
+            Some(query_pool) => query_pool,
+            None => return,
+        };
+        let frame = &mut self.frames.borrow_mut()[sync_idx];
+        if !frame.is_pass_open {
+            return; // Not timed
+        }
+        let query = sync_idx as u32 * NUM_QUERIES_PER_FRAME + 1 + 2 * frame.pass_names.len() as u32;
+        unsafe {
+            self.device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                query_pool,
+                query,
+            );
+        }
+        frame.is_pass_open = false;
+    }
+}
@@ -0,0 +1,210 @@
+use crate::*;
+use std::time::Duration;
+
+// Layout of the graph drawing, in pixels
+const NODE_WIDTH: usize = 180;
+const NODE_HEIGHT: usize = 36;
+const NODE_SPACING: usize = 30;
+const IMAGE_ROW_Y: usize = 20;
+const PASS_ROW_Y: usize = 160;
+const CHART_WIDTH: usize = 800;
+const CHART_HEIGHT: usize = 160;
+
+pub struct ReportPass {
+    pub name: String,
+    pub input_images: Vec<String>,
+    pub output_images: Vec<String>, // Including the depth image
+    pub is_conditional: bool,
+}
+
+/* A single self-contained HTML file with the current frame's passes and the
+images that connect them, recent frame times, graph cache statistics and the
+capability report. Hovering over a node shows its details. Everything is
+inline, so the file can be sent as is. */
+pub fn graph_report_html(
+    passes: &[ReportPass],
+    graph_stats: &GraphStats,
+    frame_times: &[Duration],
+    capability_report: &CapabilityReport,
+) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Frame graph report</title>\n\
+         <style>\nbody { font-family: sans-serif; margin: 24px; }\n\
+         table { border-collapse: collapse; }\n\
+         td, th { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }\n\
+         svg text { font-size: 12px; }\n</style>\n</head>\n<body>\n",
+    );
+    html.push_str(&format!(
+        "<h1>Frame graph report</h1>\n<p>{}</p>\n",
+        html_escape(&capability_report.device_name)
+    ));
+
+    html.push_str("<h2>Passes</h2>\n");
+    html.push_str(&graph_svg(passes));
+
+    html.push_str("<h2>Frame times</h2>\n");
+    html.push_str(&frame_time_chart_svg(frame_times));
+    if let Some(summary) = FrameTimeSummary::new(frame_times) {
+        html.push_str(&format!("<p>{}</p>\n", html_escape(&summary.to_text())));
+    }
+
+    html.push_str("<h2>Graph cache</h2>\n<table>\n");
+    let rows = [
+        (
+            "Graphs built during warmup",
+            graph_stats.graphs_built_during_warmup.to_string(),
+        ),
+        (
+            "Graphs built after warmup",
+            graph_stats.graphs_built_after_warmup.to_string(),
+        ),
+        ("Graphs evicted", graph_stats.graphs_evicted.to_string()),
+        ("Cache hits", graph_stats.cache_hits.to_string()),
+        ("Cache misses", graph_stats.cache_misses.to_string()),
+        ("Passes skipped", graph_stats.passes_skipped.to_string()),
+        ("Time spent building", {
+            let mut text = String::new();
+            write_ms(
+                &mut text,
+                graph_stats.total_build_duration.as_secs_f32() * 1000.0,
+                0,
+            );
+            text
+        }),
+    ];
+    for (name, value) in &rows {
+        html.push_str(&format!("<tr><th>{}</th><td>{}</td></tr>\n", name, value));
+    }
+    html.push_str("</table>\n");
+
+    html.push_str(&format!(
+        "<h2>Capabilities</h2>\n<pre>{}</pre>\n</body>\n</html>\n",
+        html_escape(&capability_report.to_text())
+    ));
+    html
+}
+
+// Images in a row on top, passes in a row below, and an arrow for every read and write
+fn graph_svg(passes: &[ReportPass]) -> String {
+    let mut image_names: Vec<&str> = Vec::new();
+    for pass in passes {
+        for name in pass.input_images.iter().chain(&pass.output_images) {
+            if !image_names.contains(&name.as_str()) {
+                image_names.push(name);
+            }
+        }
+    }
+    let node_x = |idx: usize| NODE_SPACING + idx * (NODE_WIDTH + NODE_SPACING);
+    let num_columns = image_names.len().max(passes.len()).max(1);
+    let width = node_x(num_columns);
+    let height = PASS_ROW_Y + NODE_HEIGHT + NODE_SPACING;
+
+    let mut svg = format!(
+        "<svg width=\"{}\" height=\"{}\" xmlns=\"http://www.w3.org/2000/svg\">\n\
+         <defs><marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" markerWidth=\"6\" markerHeight=\"6\" orient=\"auto\">\
+         <path d=\"M 0 0 L 10 5 L 0 10 z\" fill=\"#666\"/></marker></defs>\n",
+        width, height
+    );
+    let image_idx = |name: &str| image_names.iter().position(|n| *n == name).unwrap();
+    let center_x = |idx: usize| node_x(idx) + NODE_WIDTH / 2;
+    for (pass_idx, pass) in passes.iter().enumerate() {
+        for name in &pass.input_images {
+            svg.push_str(&format!(
+                "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"#4a7\" marker-end=\"url(#arrow)\"/>\n",
+                center_x(image_idx(name)),
+                IMAGE_ROW_Y + NODE_HEIGHT,
+                center_x(pass_idx) - 10,
+                PASS_ROW_Y
+            ));
+        }
+        for name in &pass.output_images {
+            svg.push_str(&format!(
+                "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"#c74\" marker-end=\"url(#arrow)\"/>\n",
+                center_x(pass_idx) + 10,
+                PASS_ROW_Y,
+                center_x(image_idx(name)),
+                IMAGE_ROW_Y + NODE_HEIGHT
+            ));
+        }
+    }
+    let mut push_node = |x: usize, y: usize, label: &str, tooltip: &str, fill: &str| {
+        svg.push_str(&format!(
+            "<g><title>{}</title><rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"6\" fill=\"{}\" stroke=\"#333\"/>\
+             <text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text></g>\n",
+            html_escape(tooltip),
+            x,
+            y,
+            NODE_WIDTH,
+            NODE_HEIGHT,
+            fill,
+            x + NODE_WIDTH / 2,
+            y + NODE_HEIGHT / 2 + 4,
+            html_escape(label)
+        ));
+    };
+    for (idx, name) in image_names.iter().enumerate() {
+        push_node(node_x(idx), IMAGE_ROW_Y, name, name, "#eef");
+    }
+    for (idx, pass) in passes.iter().enumerate() {
+        let tooltip = format!(
+            "Pass {}: {}\nReads: {}\nWrites: {}{}",
+            idx,
+            pass.name,
+            pass.input_images.join(", "),
+            pass.output_images.join(", "),
+            if pass.is_conditional {
+                "\nConditional on a flag"
+            } else {
+                ""
+            }
+        );
+        let fill = if pass.is_conditional { "#ffd" } else { "#fed" };
+        push_node(node_x(idx), PASS_ROW_Y, &pass.name, &tooltip, fill);
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn frame_time_chart_svg(frame_times: &[Duration]) -> String {
+    let frame_times_ms: Vec<f64> = frame_times
+        .iter()
+        .map(|t| t.as_secs_f64() * 1000.0)
+        .collect();
+    let max_ms = frame_times_ms.iter().cloned().fold(1.0, f64::max);
+    let step = CHART_WIDTH as f64 / frame_times_ms.len().max(2) as f64;
+    let points: Vec<String> = frame_times_ms
+        .iter()
+        .enumerate()
+        .map(|(i, ms)| {
+            format!(
+                "{:.1},{:.1}",
+                i as f64 * step,
+                CHART_HEIGHT as f64 * (1.0 - ms / max_ms)
+            )
+        })
+        .collect();
+    format!(
+        "<svg width=\"{w}\" height=\"{h}\" xmlns=\"http://www.w3.org/2000/svg\">\n\
+         <rect width=\"{w}\" height=\"{h}\" fill=\"#f8f8f8\" stroke=\"#ccc\"/>\n\
+         <polyline points=\"{points}\" fill=\"none\" stroke=\"#36c\"/>\n\
+         <text x=\"4\" y=\"14\">{max:.2} ms</text>\n</svg>\n",
+        w = CHART_WIDTH,
+        h = CHART_HEIGHT,
+        points = points.join(" "),
+        max = max_ms
+    )
+}
+
+fn html_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
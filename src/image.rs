@@ -1,31 +1,98 @@
 use crate::*;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
 
 pub struct Image {
     pub width: u32,
     pub height: u32,
+    pub depth: u32,                    // 1 for 2D images
+    pub num_mips: u32,                 // The view covers all of them
+    pub num_layers: u32,               // More than 1 for 2D arrays, whose view covers all of them
+    pub samples: vk::SampleCountFlags, // TYPE_1 unless the image is resolved into another
     pub format: vk::Format,
     pub usage: vk::ImageUsageFlags,
     pub aspect_flags: vk::ImageAspectFlags,
     pub vk_image: vk::Image,
     pub image_view: vk::ImageView,
-    pub opt_device_memory: Option<vk::DeviceMemory>, // None if we didn't manually allocate memory, e.g. in the case of swapchain images
+    pub layer_views: Vec<vk::ImageView>, // One 2D view per layer of an array, e.g. to render into
+    pub views: RefCell<Vec<(ImageViewRange, vk::ImageView)>>, // Made by create_view(), destroyed with the image
+    pub opt_allocation: Option<Allocation>, // None if we didn't manually allocate memory, e.g. in the case of swapchain images
+    pub opt_upload_ticket: Option<UploadTicket>, // Set if the data is uploaded asynchronously
+    // One per layer of every mip, at `mip * num_layers + layer`, once the commands recorded so far execute
+    pub layouts: RefCell<Vec<vk::ImageLayout>>,
+    pub num_barriers: Cell<usize>, // Recorded by transition_layers() since take_num_barriers()
     pub name: String,
     pub device: ash::Device,
+    pub allocator: Rc<Allocator>,
+}
+
+/// The mips and layers that a view created with `Image::create_view()` covers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ImageViewRange {
+    pub base_mip: u32,
+    pub num_mips: u32,
+    pub base_layer: u32,
+    pub num_layers: u32,
+    pub view_type: vk::ImageViewType,
+}
+
+/// A sub-rectangle of texels to write into an existing image. `data` starts at
+/// the top-left texel of the rectangle. `row_length` is the distance between
+/// rows in `data`, in texels, so that a sub-rectangle of a larger CPU-side
+/// image can be passed without copying it out first. Zero means tightly packed.
+pub struct ImageRegion<'a> {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub data: &'a [u8],
+    pub row_length: u32,
+    pub layer: u32, // Of an array, and 0 otherwise
 }
 
 impl Drop for Image {
     fn drop(&mut self) {
+        track_destroyed(&LIVE_IMAGES);
         unsafe {
             self.device.destroy_image_view(self.image_view, None);
-            if let Some(mem) = self.opt_device_memory {
+            audit_destroyed(VkObjectKind::ImageView, self.image_view);
+            for layer_view in &self.layer_views {
+                self.device.destroy_image_view(*layer_view, None);
+                audit_destroyed(VkObjectKind::ImageView, *layer_view);
+            }
+            for (_, view) in self.views.borrow().iter() {
+                self.device.destroy_image_view(*view, None);
+                audit_destroyed(VkObjectKind::ImageView, *view);
+            }
+            if let Some(allocation) = &self.opt_allocation {
                 self.device.destroy_image(self.vk_image, None); // Only destroy the image if we allocated it in the first place
-                self.device.free_memory(mem, None);
+                audit_destroyed(VkObjectKind::Image, self.vk_image);
+                self.allocator.free(allocation);
             }
         }
     }
 }
 
 impl Image {
+    /* Give the image a new name, e.g. once a pooled or streamed image is put
+    to a different use. Debuggers, validation messages, the memory report and
+    the teardown audit all show the new name from then on. */
+    pub fn set_debug_name(&mut self, name: &str, debug_utils: &DebugUtils) {
+        debug_utils.set_image_name(self.vk_image, name);
+        debug_utils.set_image_view_name(self.image_view, name);
+        audit_renamed(VkObjectKind::Image, self.vk_image, name);
+        audit_renamed(VkObjectKind::ImageView, self.image_view, name);
+        for (layer, layer_view) in self.layer_views.iter().enumerate() {
+            let layer_name = format!("{}_layer{}", name, layer);
+            debug_utils.set_image_view_name(*layer_view, &layer_name);
+            audit_renamed(VkObjectKind::ImageView, *layer_view, &layer_name);
+        }
+        if let Some(allocation) = &self.opt_allocation {
+            self.allocator.rename(allocation, name, debug_utils);
+        }
+        self.name = String::from(name);
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         name: &str,
@@ -36,22 +103,139 @@ impl Image {
         aspect_flags: vk::ImageAspectFlags,
         gpu: &Gpu,
         debug_utils: &DebugUtils,
+    ) -> Image {
+        Image::new_with_depth(
+            name,
+            width,
+            height,
+            1,
+            format,
+            usage,
+            aspect_flags,
+            gpu,
+            debug_utils,
+        )
+    }
+
+    /// A 2D color target that passes render to, and that later passes sample.
+    /// It can also be read back, e.g. to test what was rendered.
+    pub fn new_render_target(
+        name: &str,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> Image {
+        Image::new(
+            name,
+            width,
+            height,
+            format,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::ImageAspectFlags::COLOR,
+            gpu,
+            debug_utils,
+        )
+    }
+
+    /// A 2D render target that is resolved into a single-sampled image.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_multisampled(
+        name: &str,
+        width: u32,
+        height: u32,
+        samples: SampleCount,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        aspect_flags: vk::ImageAspectFlags,
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> Image {
+        Image::new_with_mips(
+            name,
+            width,
+            height,
+            1,
+            1,
+            1,
+            samples.to_vk(),
+            format,
+            usage,
+            aspect_flags,
+            gpu,
+            debug_utils,
+        )
+    }
+
+    /// Creates a 3D image if `depth` is more than 1, and a 2D image otherwise.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_depth(
+        name: &str,
+        width: u32,
+        height: u32,
+        depth: u32,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        aspect_flags: vk::ImageAspectFlags,
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> Image {
+        Image::new_with_mips(
+            name,
+            width,
+            height,
+            depth,
+            1,
+            1,
+            vk::SampleCountFlags::TYPE_1,
+            format,
+            usage,
+            aspect_flags,
+            gpu,
+            debug_utils,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new_with_mips(
+        name: &str,
+        width: u32,
+        height: u32,
+        depth: u32,
+        num_mips: u32,
+        num_layers: u32,
+        samples: vk::SampleCountFlags,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        aspect_flags: vk::ImageAspectFlags,
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
     ) -> Image {
         let device = gpu.device.clone();
+        let (image_type, view_type) = if depth > 1 {
+            (vk::ImageType::TYPE_3D, vk::ImageViewType::TYPE_3D)
+        } else if num_layers > 1 {
+            (vk::ImageType::TYPE_2D, vk::ImageViewType::TYPE_2D_ARRAY)
+        } else {
+            (vk::ImageType::TYPE_2D, vk::ImageViewType::TYPE_2D)
+        };
 
         let image_create_info = vk::ImageCreateInfo::builder()
-            .image_type(vk::ImageType::TYPE_2D)
+            .image_type(image_type)
             .format(format)
-            .mip_levels(1)
-            .array_layers(1)
-            .samples(vk::SampleCountFlags::TYPE_1)
+            .mip_levels(num_mips)
+            .array_layers(num_layers)
+            .samples(samples)
             .tiling(vk::ImageTiling::OPTIMAL)
             .usage(usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE)
             .extent(vk::Extent3D {
                 width,
                 height,
-                depth: 1,
+                depth,
             });
 
         let vk_image = unsafe {
@@ -59,120 +243,236 @@ impl Image {
                 .create_image(&image_create_info, None)
                 .expect("Failed to create image.")
         };
+        audit_created(VkObjectKind::Image, vk_image, &[device_id(&device)], name);
 
-        let image_memory_requirement = unsafe { device.get_image_memory_requirements(vk_image) };
-        let memory_type_index = gpu
-            .memory_properties
-            .memory_types
-            .iter()
-            .enumerate()
-            .position(|(i, &memory_type)| {
-                (image_memory_requirement.memory_type_bits & (1 << i)) > 0
-                    && memory_type
-                        .property_flags
-                        .contains(vk::MemoryPropertyFlags::DEVICE_LOCAL)
-            })
-            .expect("Failed to find suitable memory type.") as u32;
-
-        let memory_allocate_info = vk::MemoryAllocateInfo::builder()
-            .allocation_size(image_memory_requirement.size)
-            .memory_type_index(memory_type_index);
-        let device_memory = unsafe {
-            device
-                .allocate_memory(&memory_allocate_info, None)
-                .expect("Failed to allocate image memory.")
-        };
-
-        unsafe {
-            device
-                .bind_image_memory(vk_image, device_memory, 0)
-                .expect("Failed to bind image memory.");
-        }
+        let allocation =
+            gpu.allocator
+                .allocate_for_image(name, vk_image, vk::MemoryPropertyFlags::DEVICE_LOCAL);
 
-        let image_view = {
+        let new_view = |view_type: vk::ImageViewType,
+                        base_array_layer: u32,
+                        layer_count: u32,
+                        view_name: &str| {
             let imageview_create_info = vk::ImageViewCreateInfo::builder()
-                .view_type(vk::ImageViewType::TYPE_2D)
+                .view_type(view_type)
                 .format(format)
                 .subresource_range(vk::ImageSubresourceRange {
                     aspect_mask: aspect_flags,
                     base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1,
+                    level_count: num_mips,
+                    base_array_layer,
+                    layer_count,
                 })
                 .image(vk_image);
 
-            unsafe {
+            let image_view = unsafe {
                 gpu.device
                     .create_image_view(&imageview_create_info, None)
                     .expect("Failed to create Image View!")
-            }
+            };
+            audit_created(
+                VkObjectKind::ImageView,
+                image_view,
+                &[
+                    device_id(&device),
+                    vk_object_id(VkObjectKind::Image, vk_image),
+                ],
+                view_name,
+            );
+            image_view
+        };
+        let image_view = new_view(view_type, 0, num_layers, name);
+        let layer_views = if num_layers > 1 {
+            (0..num_layers)
+                .map(|layer| {
+                    let layer_name = format!("{}_layer{}", name, layer);
+                    let layer_view = new_view(vk::ImageViewType::TYPE_2D, layer, 1, &layer_name);
+                    debug_utils.set_image_view_name(layer_view, &layer_name);
+                    layer_view
+                })
+                .collect()
+        } else {
+            Vec::new()
         };
 
         debug_utils.set_image_name(vk_image, name);
 
+        track_created(&LIVE_IMAGES);
         Image {
             width,
             height,
+            depth,
+            num_mips,
+            num_layers,
+            samples,
             format,
             usage,
             aspect_flags,
             vk_image,
             image_view,
-            opt_device_memory: Some(device_memory),
+            layer_views,
+            views: RefCell::new(Vec::new()),
+            opt_allocation: Some(allocation),
+            opt_upload_ticket: None,
+            layouts: RefCell::new(vec![
+                vk::ImageLayout::UNDEFINED;
+                (num_mips * num_layers) as usize
+            ]),
+            num_barriers: Cell::new(0),
             device,
+            allocator: gpu.allocator.clone(),
             name: String::from(name),
         }
     }
 
-    pub fn transition_image_layout(
+    /// Layouts are tracked per layer of every mip.
+    pub fn layout(&self, mip: u32, layer: u32) -> vk::ImageLayout {
+        self.layouts.borrow()[(mip * self.num_layers + layer) as usize]
+    }
+
+    /// Track a layout that was reached without `transition()`, e.g. the final
+    /// layout of a render pass.
+    pub fn set_layout(
         &self,
-        old_layout: vk::ImageLayout,
-        new_layout: vk::ImageLayout,
-        command_buffer: vk::CommandBuffer,
+        base_mip: u32,
+        num_mips: u32,
+        base_layer: u32,
+        num_layers: u32,
+        layout: vk::ImageLayout,
     ) {
-        let src_access_mask;
-        let dst_access_mask;
-        let source_stage;
-        let destination_stage;
-
-        if old_layout == vk::ImageLayout::UNDEFINED
-            && new_layout == vk::ImageLayout::TRANSFER_DST_OPTIMAL
-        {
-            src_access_mask = vk::AccessFlags::empty();
-            dst_access_mask = vk::AccessFlags::TRANSFER_WRITE;
-            source_stage = vk::PipelineStageFlags::TOP_OF_PIPE;
-            destination_stage = vk::PipelineStageFlags::TRANSFER;
-        } else if (old_layout == vk::ImageLayout::TRANSFER_DST_OPTIMAL
-            || old_layout == vk::ImageLayout::UNDEFINED)
-            && new_layout == vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
-        {
-            src_access_mask = vk::AccessFlags::TRANSFER_WRITE;
-            dst_access_mask = vk::AccessFlags::SHADER_READ;
-            source_stage = vk::PipelineStageFlags::TRANSFER;
-            destination_stage = vk::PipelineStageFlags::FRAGMENT_SHADER;
-        } else {
-            panic!("Unsupported layout transition!")
+        let mut layouts = self.layouts.borrow_mut();
+        for mip in base_mip..base_mip + num_mips {
+            let mip_start = (mip * self.num_layers) as usize;
+            let range =
+                mip_start + base_layer as usize..mip_start + (base_layer + num_layers) as usize;
+            for layer_layout in &mut layouts[range] {
+                *layer_layout = layout;
+            }
         }
+    }
+
+    /// Move every mip and layer to `new_layout`, like `transition_layers()`.
+    pub fn transition(&self, command_buffer: vk::CommandBuffer, new_layout: vk::ImageLayout) {
+        self.transition_mips(command_buffer, 0, self.num_mips, new_layout);
+    }
 
-        let image_barriers = [vk::ImageMemoryBarrier {
-            s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
-            p_next: ptr::null(),
-            src_access_mask,
-            dst_access_mask,
-            old_layout,
+    /// Move all layers of some mips to `new_layout`, like `transition_layers()`.
+    pub fn transition_mips(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        base_mip: u32,
+        num_mips: u32,
+        new_layout: vk::ImageLayout,
+    ) {
+        self.transition_layers(
+            command_buffer,
+            base_mip,
+            num_mips,
+            0,
+            self.num_layers,
             new_layout,
-            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-            image: self.vk_image,
-            subresource_range: vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                base_mip_level: 0,
-                level_count: 1,
-                base_array_layer: 0,
-                layer_count: 1,
-            },
-        }];
+        );
+    }
+
+    /* Record a barrier that moves some layers of some mips from their tracked
+    layouts to `new_layout`, and track it. The access masks and stages come
+    from what each layout is used for. Layouts without a known use get a
+    barrier on all commands, which is correct but stalls more than needed.
+    Layers of a mip that are in the same layout share a barrier. Layers that
+    are already in `new_layout` are left alone, and if all of them are,
+    nothing is recorded. */
+    pub fn transition_layers(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        base_mip: u32,
+        num_mips: u32,
+        base_layer: u32,
+        num_layers: u32,
+        new_layout: vk::ImageLayout,
+    ) {
+        assert!(
+            num_mips > 0 && base_mip + num_mips <= self.num_mips,
+            "Image `{}` has {} mips, so mips {} to {} can't be transitioned.",
+            self.name,
+            self.num_mips,
+            base_mip,
+            base_mip + num_mips
+        );
+        assert!(
+            num_layers > 0 && base_layer + num_layers <= self.num_layers,
+            "Image `{}` has {} layers, so layers {} to {} can't be transitioned.",
+            self.name,
+            self.num_layers,
+            base_layer,
+            base_layer + num_layers
+        );
+        assert_ne!(
+            new_layout,
+            vk::ImageLayout::UNDEFINED,
+            "Image `{}` can't be transitioned to UNDEFINED.",
+            self.name
+        );
+        let mut source_stage = vk::PipelineStageFlags::empty();
+        let mut destination_stage = vk::PipelineStageFlags::empty();
+        let mut image_barriers = Vec::new();
+        for mip in base_mip..base_mip + num_mips {
+            // Runs of layers in the same layout
+            let mut run_start = base_layer;
+            while run_start < base_layer + num_layers {
+                let old_layout = self.layout(mip, run_start);
+                let mut run_end = run_start + 1;
+                while run_end < base_layer + num_layers && self.layout(mip, run_end) == old_layout {
+                    run_end += 1;
+                }
+                let (first_layer, layer_count) = (run_start, run_end - run_start);
+                run_start = run_end;
+                if old_layout == new_layout {
+                    continue;
+                }
+                let ((src_access_mask, src_stage), (dst_access_mask, dst_stage)) = match (
+                    layout_usage(old_layout),
+                    layout_usage(new_layout),
+                ) {
+                    (Some(src), Some(dst)) => (src, dst),
+                    _ => {
+                        if cfg!(debug_assertions) {
+                            println!(
+                                "Transitioning mip {} of image `{}` from {:?} to {:?} with a full barrier.",
+                                mip, self.name, old_layout, new_layout
+                            );
+                        }
+                        let all = (
+                            vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+                            vk::PipelineStageFlags::ALL_COMMANDS,
+                        );
+                        (all, all)
+                    }
+                };
+                source_stage |= src_stage;
+                destination_stage |= dst_stage;
+                image_barriers.push(vk::ImageMemoryBarrier {
+                    s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
+                    p_next: ptr::null(),
+                    src_access_mask,
+                    dst_access_mask,
+                    old_layout,
+                    new_layout,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    image: self.vk_image,
+                    subresource_range: vk::ImageSubresourceRange {
+                        aspect_mask: self.aspect_flags,
+                        base_mip_level: mip,
+                        level_count: 1,
+                        base_array_layer: first_layer,
+                        layer_count,
+                    },
+                });
+            }
+        }
+        if image_barriers.is_empty() {
+            return;
+        }
 
         unsafe {
             self.device.cmd_pipeline_barrier(
@@ -185,95 +485,1375 @@ impl Image {
                 &image_barriers,
             );
         }
+        self.num_barriers
+            .set(self.num_barriers.get() + image_barriers.len());
+        self.set_layout(base_mip, num_mips, base_layer, num_layers, new_layout);
+    }
+
+    /* Whether `view` is a view of the image that covers `mip` and any of the
+    layers from `base_layer` on, e.g. to find out if a pass samples what
+    another one renders. */
+    pub fn is_covered_by_view(
+        &self,
+        view: vk::ImageView,
+        mip: u32,
+        base_layer: u32,
+        num_layers: u32,
+    ) -> bool {
+        let overlaps = |view_base_layer: u32, view_num_layers: u32| {
+            view_base_layer < base_layer + num_layers
+                && base_layer < view_base_layer + view_num_layers
+        };
+        if view == self.image_view {
+            return true;
+        }
+        if let Some(layer) = self
+            .layer_views
+            .iter()
+            .position(|layer_view| *layer_view == view)
+        {
+            return overlaps(layer as u32, 1);
+        }
+        self.views.borrow().iter().any(|(range, range_view)| {
+            *range_view == view
+                && (range.base_mip..range.base_mip + range.num_mips).contains(&mip)
+                && overlaps(range.base_layer, range.num_layers)
+        })
+    }
+
+    /// The barriers that `transition_layers()` recorded since the last call, e.g.
+    /// in the current frame.
+    pub fn take_num_barriers(&self) -> usize {
+        self.num_barriers.replace(0)
+    }
+
+    /* A view of some of the image's mips and layers, e.g. to render into one
+    mip of a downsample chain, or to sample the mip above it. Views are owned
+    by the image and destroyed with it, and asking for the same range again
+    returns the same view. The range must lie within the image, and fit the
+    view type: 2D views cover one layer, 3D views a 3D image, and array views
+    any number of layers. */
+    pub fn create_view(
+        &self,
+        base_mip: u32,
+        num_mips: u32,
+        base_layer: u32,
+        num_layers: u32,
+        view_type: vk::ImageViewType,
+    ) -> Result<vk::ImageView, String> {
+        if num_mips == 0 || base_mip + num_mips > self.num_mips {
+            return Err(format!(
+                "Image `{}` has {} mips, so it can't have a view of mips {} to {}.",
+                self.name,
+                self.num_mips,
+                base_mip,
+                base_mip + num_mips
+            ));
+        }
+        if num_layers == 0 || base_layer + num_layers > self.num_layers {
+            return Err(format!(
+                "Image `{}` has {} layers, so it can't have a view of layers {} to {}.",
+                self.name,
+                self.num_layers,
+                base_layer,
+                base_layer + num_layers
+            ));
+        }
+        let is_type_valid = match view_type {
+            vk::ImageViewType::TYPE_2D => self.depth == 1 && num_layers == 1,
+            vk::ImageViewType::TYPE_2D_ARRAY => self.depth == 1,
+            vk::ImageViewType::TYPE_3D => self.depth > 1,
+            _ => false,
+        };
+        if !is_type_valid {
+            return Err(format!(
+                "Image `{}` can't have a {:?} view of {} layers.",
+                self.name, view_type, num_layers
+            ));
+        }
+
+        let range = ImageViewRange {
+            base_mip,
+            num_mips,
+            base_layer,
+            num_layers,
+            view_type,
+        };
+        if let Some((_, view)) = self.views.borrow().iter().find(|(r, _)| *r == range) {
+            return Ok(*view);
+        }
+        let view_create_info = vk::ImageViewCreateInfo::builder()
+            .view_type(view_type)
+            .format(self.format)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: self.aspect_flags,
+                base_mip_level: base_mip,
+                level_count: num_mips,
+                base_array_layer: base_layer,
+                layer_count: num_layers,
+            })
+            .image(self.vk_image);
+        let view = unsafe {
+            self.device
+                .create_image_view(&view_create_info, None)
+                .expect("Failed to create Image View!")
+        };
+        audit_created(
+            VkObjectKind::ImageView,
+            view,
+            &[
+                device_id(&self.device),
+                vk_object_id(VkObjectKind::Image, self.vk_image),
+            ],
+            &format!("{}_mip{}_layer{}", self.name, base_mip, base_layer),
+        );
+        self.views.borrow_mut().push((range, view));
+        Ok(view)
+    }
+
+    /// The view to render into one mip and layer with. Images with a single
+    /// mip use their usual views.
+    pub fn attachment_view(&self, mip: u32, layer: u32) -> vk::ImageView {
+        let layer = if self.num_layers > 1 { layer } else { 0 };
+        if self.num_mips == 1 {
+            self.layer_view(layer)
+        } else {
+            self.create_view(mip, 1, layer, 1, vk::ImageViewType::TYPE_2D)
+                .unwrap_or_else(|e| panic!("{}", e))
+        }
+    }
+
+    /* Decode a PNG, JPEG or any other format that the `image` crate reads,
+    and upload it as a sampled sRGB image named after the file's stem. Like
+    `new_from_image()`, rows are flipped and sources without alpha get an
+    opaque one, and `with_mips` generates a full mip chain. */
+    pub fn from_file(
+        path: &str,
+        with_mips: bool,
+        basis: &Basis,
+        gpu: &Gpu,
+        command_pool: vk::CommandPool,
+        debug_utils: &DebugUtils,
+    ) -> Result<Image, String> {
+        let path = std::path::Path::new(path);
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string());
+        Image::new_from_image(
+            gpu,
+            path,
+            command_pool,
+            &name,
+            vk::Format::R8G8B8A8_SRGB,
+            with_mips,
+            basis,
+            debug_utils,
+        )
     }
 
+    /* `format` must be `R8G8B8A8_UNORM` or `R8G8B8A8_SRGB`, which the file's
+    texels are converted to. With `with_mips`, the levels below the first are
+    blitted from the one above. Formats that can't be blitted to and from
+    get a single level, with a warning. */
+    #[allow(clippy::too_many_arguments)]
     pub fn new_from_image(
         gpu: &Gpu,
         path: &std::path::Path,
         command_pool: vk::CommandPool,
         name: &str,
+        format: vk::Format,
+        with_mips: bool,
+        basis: &Basis,
         debug_utils: &DebugUtils,
-    ) -> Image {
+    ) -> Result<Image, String> {
         use ::image::GenericImageView;
-        let mut image_object = ::image::open(path).unwrap();
+        if format != vk::Format::R8G8B8A8_UNORM && format != vk::Format::R8G8B8A8_SRGB {
+            return Err(format!(
+                "Can't load image `{}` as {:?}. Files are loaded as 8-bit RGBA.",
+                path.display(),
+                format
+            ));
+        }
+        let mut image_object = ::image::open(path)
+            .map_err(|e| format!("Failed to load image `{}`: {}", path.display(), e))?;
         image_object = image_object.flipv();
 
         let (image_width, image_height) = (image_object.width(), image_object.height());
-        let image_size =
-            std::mem::size_of::<u8>() * image_width as usize * image_height as usize * 4;
-        let image_data = image_object.to_rgba().into_raw();
+        let image_data = image_object.to_rgba8().into_raw();
 
-        if image_size == 0 {
-            panic!("Failed to load image.")
+        if image_data.is_empty() {
+            return Err(format!("Image `{}` is empty.", path.display()));
         }
 
-        let image = Image::new(
+        let num_mips = if !with_mips {
+            1
+        } else if is_blit_supported(format, basis, gpu) {
+            num_mips_for_size(image_width, image_height)
+        } else {
+            println!(
+                "WARNING: Format {:?} can't be blitted on this device. Image `{}` gets a single mip.",
+                format, name
+            );
+            1
+        };
+
+        Ok(Image::new_from_data_with_mips(
             name,
-            image_width,
-            image_height,
-            vk::Format::R8G8B8A8_UNORM, // TODO: Derive format from file or take as an argument
-            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
-            vk::ImageAspectFlags::COLOR,
+            (image_width, image_height, 1),
+            num_mips,
+            1,
+            format,
+            &image_data,
+            gpu,
+            command_pool,
+            debug_utils,
+        ))
+    }
+
+    /* Create a sampled image in SHADER_READ_ONLY_OPTIMAL layout, filled with
+    tightly packed texel data, ordered by row and then by slice. */
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_from_data(
+        name: &str,
+        size: (u32, u32, u32),
+        format: vk::Format,
+        data: &[u8],
+        gpu: &Gpu,
+        command_pool: vk::CommandPool,
+        debug_utils: &DebugUtils,
+    ) -> Image {
+        Image::new_from_data_with_mips(
+            name,
+            size,
+            1,
+            1,
+            format,
+            data,
+            gpu,
+            command_pool,
+            debug_utils,
+        )
+    }
+
+    /* Create a sampled 2D array, like `new_from_data()`, with the layers one
+    after the other in `data`. Fails if the device doesn't support as many
+    layers, or the format's texel size isn't known. */
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_array_from_data(
+        name: &str,
+        (width, height): (u32, u32),
+        num_layers: u32,
+        format: vk::Format,
+        data: &[u8],
+        gpu: &Gpu,
+        command_pool: vk::CommandPool,
+        debug_utils: &DebugUtils,
+    ) -> Result<Image, String> {
+        check_num_layers(name, num_layers, gpu)?;
+        check_image_extent(name, (width, height, 1), gpu)?;
+        if format_texel_size(format).is_none() {
+            return Err(format!(
+                "Can't upload layers of image array `{}`, since the size of {:?} texels isn't known.",
+                name, format
+            ));
+        }
+        Ok(Image::new_from_data_with_mips(
+            name,
+            (width, height, 1),
+            1,
+            num_layers,
+            format,
+            data,
+            gpu,
+            command_pool,
+            debug_utils,
+        ))
+    }
+
+    /* A 2D array to render into, one layer at a time, e.g. a cascade of a
+    shadow map. `layer_views[n]` is the view to render into layer n with, and
+    `image_view` samples all of them. */
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_array(
+        name: &str,
+        width: u32,
+        height: u32,
+        num_layers: u32,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        aspect_flags: vk::ImageAspectFlags,
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> Result<Image, String> {
+        check_num_layers(name, num_layers, gpu)?;
+        check_image_extent(name, (width, height, 1), gpu)?;
+        Ok(Image::new_with_mips(
+            name,
+            width,
+            height,
+            1,
+            1,
+            num_layers,
+            vk::SampleCountFlags::TYPE_1,
+            format,
+            usage,
+            aspect_flags,
+            gpu,
+            debug_utils,
+        ))
+    }
+
+    /// The view of one layer, or the image's only view if it isn't an array.
+    pub fn layer_view(&self, layer: u32) -> vk::ImageView {
+        if self.layer_views.is_empty() {
+            self.image_view
+        } else {
+            self.layer_views[layer as usize]
+        }
+    }
+
+    // `data` fills the first mip, and the rest are generated from it
+    #[allow(clippy::too_many_arguments)]
+    fn new_from_data_with_mips(
+        name: &str,
+        (width, height, depth): (u32, u32, u32),
+        num_mips: u32,
+        num_layers: u32,
+        format: vk::Format,
+        data: &[u8],
+        gpu: &Gpu,
+        command_pool: vk::CommandPool,
+        debug_utils: &DebugUtils,
+    ) -> Image {
+        let image = Image::new_for_data(
+            name,
+            (width, height, depth),
+            num_mips,
+            num_layers,
+            format,
+            data,
             gpu,
             debug_utils,
         );
 
-        let staging_buffer = HostVisibleBuffer::new(
-            "image_staging_buffer",
-            image_size,
-            vk::BufferUsageFlags::TRANSFER_SRC,
+        let staging_chunk = gpu.staging_pool.allocate(data, gpu, debug_utils);
+
+        let command_buffer = begin_single_use_command_buffer(&gpu.device, command_pool);
+        debug_utils.begin_label(
+            command_buffer,
+            &format!("upload {}", name),
+            LABEL_COLOR_UPLOAD,
+        );
+
+        image.record_initial_upload(&staging_chunk, command_buffer);
+
+        debug_utils.end_label(command_buffer);
+        end_single_use_command_buffer(command_buffer, command_pool, gpu);
+        gpu.staging_pool.free(staging_chunk);
+
+        image
+    }
+
+    /* Upload every mip as it is given, so nothing is blitted. Each of `mips`
+    holds all layers of its mip, one after another. Block-compressed mips are
+    sized by 4x4 blocks, including the partial blocks at the edges of mips
+    whose sizes aren't multiples of 4. If the device can't sample a BC format,
+    the mips are decoded to RGBA8 first. */
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_from_mip_data(
+        name: &str,
+        (width, height, depth): (u32, u32, u32),
+        num_layers: u32,
+        format: vk::Format,
+        mips: &[&[u8]],
+        basis: &Basis,
+        gpu: &Gpu,
+        command_pool: vk::CommandPool,
+        debug_utils: &DebugUtils,
+    ) -> Result<Image, String> {
+        check_num_mips(name, (width, height, depth), mips.len())?;
+        check_num_layers(name, num_layers, gpu)?;
+        check_image_extent(name, (width, height, depth), gpu)?;
+        let mip_extent = |mip_level: usize| {
+            (
+                (width >> mip_level).max(1),
+                (height >> mip_level).max(1),
+                (depth >> mip_level).max(1),
+            )
+        };
+        for (mip_level, mip) in mips.iter().enumerate() {
+            let (mip_width, mip_height, mip_depth) = mip_extent(mip_level);
+            let layer_size = mip_size_in_bytes(format, mip_width, mip_height, mip_depth)
+                .ok_or_else(|| format!("Uploading {:?} isn't supported.", format))?;
+            if mip.len() != layer_size * num_layers as usize {
+                return Err(format!(
+                    "Mip {} of image `{}` is {} bytes, but {}x{}x{} {:?} with {} layers is {} bytes.",
+                    mip_level,
+                    name,
+                    mip.len(),
+                    mip_width,
+                    mip_height,
+                    mip_depth,
+                    format,
+                    num_layers,
+                    layer_size * num_layers as usize
+                ));
+            }
+        }
+
+        let format_properties = unsafe {
+            basis
+                .instance
+                .get_physical_device_format_properties(gpu.physical_device, format)
+        };
+        let is_sampleable = format_properties
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::SAMPLED_IMAGE);
+        let is_bc_enabled = gpu.enabled_features.texture_compression_bc == vk::TRUE;
+        let is_bc = bc_block_bytes(format).is_some();
+        let (upload_format, decoded_mips) = if is_bc && (!is_bc_enabled || !is_sampleable) {
+            let decoded_format = bc_decoded_format(format).ok_or_else(|| {
+                format!(
+                    "Image `{}` has format {:?}, which this device can't sample or decode.",
+                    name, format
+                )
+            })?;
+            println!(
+                "WARNING: Decoding image `{}` from {:?} to {:?}, since this device can't sample it.",
+                name, format, decoded_format
+            );
+            // Decoded slice by slice, since blocks don't span layers or depth slices
+            let mut decoded_mips = Vec::with_capacity(mips.len());
+            for (mip_level, mip) in mips.iter().enumerate() {
+                let (mip_width, mip_height, _) = mip_extent(mip_level);
+                let slice_size = mip_size_in_bytes(format, mip_width, mip_height, 1).unwrap();
+                let mut decoded_mip = Vec::new();
+                for slice in mip.chunks_exact(slice_size) {
+                    decoded_mip.extend(decode_bc_to_rgba8(format, mip_width, mip_height, slice)?);
+                }
+                decoded_mips.push(decoded_mip);
+            }
+            (decoded_format, decoded_mips)
+        } else if !is_sampleable {
+            return Err(format!(
+                "Image `{}` has format {:?}, which this device can't sample.",
+                name, format
+            ));
+        } else {
+            (format, Vec::new())
+        };
+        let mips: Vec<&[u8]> = if decoded_mips.is_empty() {
+            mips.to_vec()
+        } else {
+            decoded_mips.iter().map(|mip| mip.as_slice()).collect()
+        };
+
+        let image = Image::new_with_mips(
+            name,
+            width,
+            height,
+            depth,
+            mips.len() as u32,
+            num_layers,
+            vk::SampleCountFlags::TYPE_1,
+            upload_format,
+            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            vk::ImageAspectFlags::COLOR,
             gpu,
             debug_utils,
         );
-        staging_buffer.upload_data(&image_data, 0);
+
+        /* Stage all mips in a single chunk. Offsets are kept multiples of 16,
+        which covers the 4-byte and block size alignment of copy regions.
+        Each region's extent is its mip's size in texels, even when the
+        blocks at its edges extend past it. */
+        let mut data = Vec::new();
+        let mut mip_offsets = Vec::with_capacity(mips.len());
+        for mip in &mips {
+            mip_offsets.push(data.len());
+            data.extend_from_slice(mip);
+            data.resize(data.len().next_multiple_of(16), 0);
+        }
+        let staging_chunk = gpu.staging_pool.allocate(&data, gpu, debug_utils);
+        let buffer_image_regions: Vec<vk::BufferImageCopy> = mip_offsets
+            .iter()
+            .enumerate()
+            .map(|(mip_level, mip_offset)| {
+                let (mip_width, mip_height, mip_depth) = mip_extent(mip_level);
+                vk::BufferImageCopy {
+                    image_subresource: vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: mip_level as u32,
+                        base_array_layer: 0,
+                        layer_count: num_layers,
+                    },
+                    image_extent: vk::Extent3D {
+                        width: mip_width,
+                        height: mip_height,
+                        depth: mip_depth,
+                    },
+                    buffer_offset: (staging_chunk.offset + mip_offset) as u64,
+                    buffer_image_height: 0,
+                    buffer_row_length: 0,
+                    image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+                }
+            })
+            .collect();
 
         let command_buffer = begin_single_use_command_buffer(&gpu.device, command_pool);
+        debug_utils.begin_label(
+            command_buffer,
+            &format!("upload {}", name),
+            LABEL_COLOR_UPLOAD,
+        );
+        image.transition(command_buffer, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        unsafe {
+            gpu.device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging_chunk.vk_buffer,
+                image.vk_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &buffer_image_regions,
+            );
+        }
+        image.transition(command_buffer, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        debug_utils.end_label(command_buffer);
+        end_single_use_command_buffer(command_buffer, command_pool, gpu);
+        gpu.staging_pool.free(staging_chunk);
 
-        image.transition_image_layout(
-            vk::ImageLayout::UNDEFINED,
+        Ok(image)
+    }
+
+    /// Sized for `data`, which is left to the caller to copy into the first mip.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new_for_data(
+        name: &str,
+        (width, height, depth): (u32, u32, u32),
+        num_mips: u32,
+        num_layers: u32,
+        format: vk::Format,
+        data: &[u8],
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> Image {
+        if let Some(texel_size) = format_texel_size(format) {
+            assert_eq!(
+                data.len(),
+                width as usize
+                    * height as usize
+                    * depth as usize
+                    * num_layers as usize
+                    * texel_size,
+                "Data size doesn't match the size of image `{}`.",
+                name
+            );
+        }
+
+        // Mips are blitted from the level above
+        let mut usage = vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED;
+        if num_mips > 1 {
+            usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+        }
+        Image::new_with_mips(
+            name,
+            width,
+            height,
+            depth,
+            num_mips,
+            num_layers,
+            vk::SampleCountFlags::TYPE_1,
+            format,
+            usage,
+            vk::ImageAspectFlags::COLOR,
+            gpu,
+            debug_utils,
+        )
+    }
+
+    /// Copy the whole image out of a staging chunk, and leave it ready for sampling.
+    pub(crate) fn record_initial_upload(
+        &self,
+        staging_chunk: &StagingChunk,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        self.transition(command_buffer, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+
+        // Copy buffer to image, one region per layer
+        {
+            let layer_size = self.width as usize
+                * self.height as usize
+                * self.depth as usize
+                * format_texel_size(self.format).unwrap_or(0);
+            let buffer_image_regions: Vec<vk::BufferImageCopy> = (0..self.num_layers)
+                .map(|layer| vk::BufferImageCopy {
+                    image_subresource: vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: 0,
+                        base_array_layer: layer,
+                        layer_count: 1,
+                    },
+                    image_extent: vk::Extent3D {
+                        width: self.width,
+                        height: self.height,
+                        depth: self.depth,
+                    },
+                    buffer_offset: (staging_chunk.offset + layer as usize * layer_size) as u64,
+                    buffer_image_height: 0,
+                    buffer_row_length: 0,
+                    image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+                })
+                .collect();
+
+            unsafe {
+                self.device.cmd_copy_buffer_to_image(
+                    command_buffer,
+                    staging_chunk.vk_buffer,
+                    self.vk_image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &buffer_image_regions,
+                );
+            }
+        }
+
+        self.record_finish_upload(command_buffer, 0, self.num_layers);
+    }
+
+    /* Every mip of the layers must be in TRANSFER_DST_OPTIMAL layout, with the
+    first one written. The rest are generated from it, and all of them end in
+    SHADER_READ_ONLY_OPTIMAL. */
+    fn record_finish_upload(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        base_layer: u32,
+        num_layers: u32,
+    ) {
+        if self.num_mips <= 1 {
+            self.transition_layers(
+                command_buffer,
+                0,
+                1,
+                base_layer,
+                num_layers,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            );
+            return;
+        }
+
+        let mip_barrier = |mip_level: u32,
+                           old_layout: vk::ImageLayout,
+                           new_layout: vk::ImageLayout,
+                           src_access_mask: vk::AccessFlags,
+                           dst_access_mask: vk::AccessFlags| {
+            vk::ImageMemoryBarrier::builder()
+                .old_layout(old_layout)
+                .new_layout(new_layout)
+                .src_access_mask(src_access_mask)
+                .dst_access_mask(dst_access_mask)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(self.vk_image)
+                .subresource_range(vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: mip_level,
+                    level_count: 1,
+                    base_array_layer: base_layer,
+                    layer_count: num_layers,
+                })
+                .build()
+        };
+        let mip_offset = |mip_level: u32| vk::Offset3D {
+            x: (self.width >> mip_level).max(1) as i32,
+            y: (self.height >> mip_level).max(1) as i32,
+            z: (self.depth >> mip_level).max(1) as i32,
+        };
+        let mip_layers = |mip_level: u32| vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level,
+            base_array_layer: base_layer,
+            layer_count: num_layers,
+        };
+
+        for mip_level in 1..self.num_mips {
+            let src_level = mip_level - 1;
+            let to_transfer_src = [mip_barrier(
+                src_level,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::TRANSFER_READ,
+            )];
+            let to_shader_read = [mip_barrier(
+                src_level,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::AccessFlags::TRANSFER_READ,
+                vk::AccessFlags::SHADER_READ,
+            )];
+            let blit_regions = [vk::ImageBlit {
+                src_subresource: mip_layers(src_level),
+                src_offsets: [vk::Offset3D { x: 0, y: 0, z: 0 }, mip_offset(src_level)],
+                dst_subresource: mip_layers(mip_level),
+                dst_offsets: [vk::Offset3D { x: 0, y: 0, z: 0 }, mip_offset(mip_level)],
+            }];
+            unsafe {
+                self.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &to_transfer_src,
+                );
+                self.device.cmd_blit_image(
+                    command_buffer,
+                    self.vk_image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    self.vk_image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &blit_regions,
+                    vk::Filter::LINEAR,
+                );
+                self.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &to_shader_read,
+                );
+            }
+        }
+
+        // The last mip is only ever blitted to
+        let last_to_shader_read = [mip_barrier(
+            self.num_mips - 1,
             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::AccessFlags::SHADER_READ,
+        )];
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &last_to_shader_read,
+            );
+        }
+        self.set_layout(
+            0,
+            self.num_mips,
+            base_layer,
+            num_layers,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+    }
+
+    /* Copy the first mip back to the CPU as tightly packed RGBA8, with the
+    layers of arrays one after another, e.g. to compare a rendered image
+    against a reference. The image
+    must have been created with TRANSFER_SRC usage, and is put back in its
+    tracked layout afterwards. Blocks until the copy completes, so it's meant
+    for tests and tools rather than for every frame. */
+    pub fn read_back(
+        &self,
+        gpu: &Gpu,
+        command_pool: vk::CommandPool,
+        debug_utils: &DebugUtils,
+    ) -> Result<Vec<u8>, String> {
+        if !self.usage.contains(vk::ImageUsageFlags::TRANSFER_SRC) {
+            return Err(format!(
+                "Image `{}` can't be read back, since it wasn't created with TRANSFER_SRC usage.",
+                self.name
+            ));
+        }
+        // Put back afterwards, layer by layer
+        let layouts: Vec<vk::ImageLayout> = (0..self.num_layers)
+            .map(|layer| self.layout(0, layer))
+            .collect();
+        if layouts.contains(&vk::ImageLayout::UNDEFINED) {
+            return Err(format!(
+                "Image `{}` can't be read back, since nothing has been written to it.",
+                self.name
+            ));
+        }
+        let texel_size = format_texel_size(self.format).ok_or_else(|| {
+            format!(
+                "Image `{}` has format {:?}, which can't be read back.",
+                self.name, self.format
+            )
+        })?;
+        let row_pitch = self.width as usize * texel_size;
+        // The layers are copied one after another, as if they were stacked rows
+        let num_rows = self.height * self.num_layers;
+        let readback_buffer = HostVisibleBuffer::new_host_cached(
+            &format!("{}_readback", self.name),
+            row_pitch * num_rows as usize,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            gpu,
+            debug_utils,
+        );
+
+        // Make the copy visible to the host
+        let host_barriers = [vk::MemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::HOST_READ,
+            ..Default::default()
+        }];
+        let copy_regions = [vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: self.aspect_flags,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: self.num_layers,
+            },
+            image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+            image_extent: vk::Extent3D {
+                width: self.width,
+                height: self.height,
+                depth: 1,
+            },
+        }];
+
+        let command_buffer = begin_single_use_command_buffer(&gpu.device, command_pool);
+        debug_utils.begin_label(
             command_buffer,
+            &format!("read back {}", self.name),
+            LABEL_COLOR_DEBUG,
         );
+        self.transition_mips(command_buffer, 0, 1, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        unsafe {
+            gpu.device.cmd_copy_image_to_buffer(
+                command_buffer,
+                self.vk_image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                readback_buffer.vk_buffer,
+                &copy_regions,
+            );
+            gpu.device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &host_barriers,
+                &[],
+                &[],
+            );
+        }
+        for (layer, layout) in layouts.iter().enumerate() {
+            self.transition_layers(command_buffer, 0, 1, layer as u32, 1, *layout);
+        }
+        debug_utils.end_label(command_buffer);
+        end_single_use_command_buffer(command_buffer, command_pool, gpu);
 
-        // Copy buffer to image
-        {
-            let buffer_image_regions = [vk::BufferImageCopy {
+        let mut texels = vec![0u8; row_pitch * num_rows as usize];
+        readback_buffer
+            .download_data(&mut texels, 0)
+            .map_err(|e| e.to_string())?;
+        texels_to_rgba8(&texels, self.width, num_rows, row_pitch, self.format)
+    }
+
+    /* Record a write of parts of an image that is in SHADER_READ_ONLY_OPTIMAL
+    layout, e.g. one loaded with `new_from_image()`, into `command_buffer`,
+    outside any render pass. Only the texels inside the regions are staged,
+    all of them are copied with one command, and only the layers that they
+    touch are transitioned, so passes recorded afterwards sample the new data
+    while the other layers are left alone. An image with mips has the mips of
+    the touched layers regenerated. Returns the staging chunk, which must be
+    kept until the command buffer has completed, or None if the regions are
+    empty. */
+    pub fn record_update_regions(
+        &self,
+        regions: &[ImageRegion],
+        command_buffer: vk::CommandBuffer,
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> Result<Option<StagingChunk>, String> {
+        if self.depth > 1 {
+            return Err(format!(
+                "Updating regions of 3D image `{}` is not supported.",
+                self.name
+            ));
+        }
+        let texel_size = format_texel_size(self.format).ok_or_else(|| {
+            format!(
+                "Updating regions of image `{}` with format `{:?}` is not supported.",
+                self.name, self.format
+            )
+        })?;
+
+        // Validate regions and pack the texels of each region tightly
+        let mut staging_data = Vec::new();
+        let mut buffer_image_regions = Vec::with_capacity(regions.len());
+        for region in regions {
+            if region.x as u64 + region.width as u64 > self.width as u64
+                || region.y as u64 + region.height as u64 > self.height as u64
+            {
+                return Err(format!(
+                    "Region ({}, {}, {}x{}) is out of bounds of image `{}` ({}x{}).",
+                    region.x,
+                    region.y,
+                    region.width,
+                    region.height,
+                    self.name,
+                    self.width,
+                    self.height
+                ));
+            }
+            if region.layer >= self.num_layers {
+                return Err(format!(
+                    "Region is in layer {}, but image `{}` has {} layers.",
+                    region.layer, self.name, self.num_layers
+                ));
+            }
+            let row_length = if region.row_length == 0 {
+                region.width
+            } else {
+                region.row_length
+            };
+            if row_length < region.width {
+                return Err(format!(
+                    "Row length {} is smaller than the region width {}.",
+                    row_length, region.width
+                ));
+            }
+            if region.width == 0 || region.height == 0 {
+                continue;
+            }
+            let row_size = region.width as usize * texel_size;
+            let row_pitch = row_length as usize * texel_size;
+            let required_size = (region.height as usize - 1) * row_pitch + row_size;
+            if region.data.len() < required_size {
+                return Err(format!(
+                    "Region data is {} bytes, but {} bytes are needed.",
+                    region.data.len(),
+                    required_size
+                ));
+            }
+
+            buffer_image_regions.push(vk::BufferImageCopy {
                 image_subresource: vk::ImageSubresourceLayers {
                     aspect_mask: vk::ImageAspectFlags::COLOR,
                     mip_level: 0,
-                    base_array_layer: 0,
+                    base_array_layer: region.layer,
                     layer_count: 1,
                 },
                 image_extent: vk::Extent3D {
-                    width: image_width,
-                    height: image_height,
+                    width: region.width,
+                    height: region.height,
                     depth: 1,
                 },
-                buffer_offset: 0,
+                buffer_offset: staging_data.len() as u64,
                 buffer_image_height: 0,
                 buffer_row_length: 0,
-                image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
-            }];
-
-            unsafe {
-                gpu.device.cmd_copy_buffer_to_image(
-                    command_buffer,
-                    staging_buffer.vk_buffer,
-                    image.vk_image,
-                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                    &buffer_image_regions,
-                );
+                image_offset: vk::Offset3D {
+                    x: region.x as i32,
+                    y: region.y as i32,
+                    z: 0,
+                },
+            });
+            for row in 0..region.height as usize {
+                let start = row * row_pitch;
+                staging_data.extend_from_slice(&region.data[start..start + row_size]);
             }
         }
+        if buffer_image_regions.is_empty() {
+            return Ok(None);
+        }
 
-        image.transition_image_layout(
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        let staging_chunk = gpu.staging_pool.allocate(&staging_data, gpu, debug_utils);
+        for region in &mut buffer_image_regions {
+            region.buffer_offset += staging_chunk.offset as u64;
+        }
+
+        let layer_runs = touched_layer_runs(
+            buffer_image_regions
+                .iter()
+                .map(|region| region.image_subresource.base_array_layer),
+        );
+        // Mips below the first are regenerated, so they are written too
+        debug_utils.begin_label(
             command_buffer,
+            &format!("upload regions {}", self.name),
+            LABEL_COLOR_UPLOAD,
         );
+        for &(base_layer, num_layers) in &layer_runs {
+            self.transition_layers(
+                command_buffer,
+                0,
+                self.num_mips,
+                base_layer,
+                num_layers,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            );
+        }
+        unsafe {
+            gpu.device.cmd_copy_buffer_to_image(
+                command_buffer,
+                staging_chunk.vk_buffer,
+                self.vk_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &buffer_image_regions,
+            );
+        }
+        for &(base_layer, num_layers) in &layer_runs {
+            self.record_finish_upload(command_buffer, base_layer, num_layers);
+        }
+        debug_utils.end_label(command_buffer);
 
-        end_single_use_command_buffer(command_buffer, command_pool, gpu);
+        Ok(Some(staging_chunk))
+    }
+}
 
-        image
+/// The layers among `layers` as runs of (base_layer, num_layers), in order.
+fn touched_layer_runs(layers: impl Iterator<Item = u32>) -> Vec<(u32, u32)> {
+    let mut layers: Vec<u32> = layers.collect();
+    layers.sort_unstable();
+    layers.dedup();
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for layer in layers {
+        match runs.last_mut() {
+            Some((base_layer, num_layers)) if *base_layer + *num_layers == layer => {
+                *num_layers += 1;
+            }
+            _ => runs.push((layer, 1)),
+        }
+    }
+    runs
+}
+
+/// Size of a single texel in bytes, for the formats we know how to upload.
+pub(crate) fn format_texel_size(format: vk::Format) -> Option<usize> {
+    match format {
+        vk::Format::R8_UNORM | vk::Format::R8_SRGB => Some(1),
+        vk::Format::R8G8_UNORM | vk::Format::R8G8_SRGB => Some(2),
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::R32_SFLOAT => Some(4),
+        vk::Format::R16G16B16A16_SFLOAT => Some(8),
+        vk::Format::R32G32B32A32_SFLOAT => Some(16),
+        _ => None,
+    }
+}
+
+/* The accesses that commands make to an image in `layout`, and the stages
+that make them. Used both for the accesses before a transition, which the
+barrier waits on, and for the ones after, which wait on the barrier. */
+fn layout_usage(layout: vk::ImageLayout) -> Option<(vk::AccessFlags, vk::PipelineStageFlags)> {
+    match layout {
+        vk::ImageLayout::UNDEFINED => Some((
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+        )),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => Some((
+            vk::AccessFlags::TRANSFER_WRITE,
+            vk::PipelineStageFlags::TRANSFER,
+        )),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => Some((
+            vk::AccessFlags::TRANSFER_READ,
+            vk::PipelineStageFlags::TRANSFER,
+        )),
+        // Sampled by passes, and by compute shaders like the depth pyramid's
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => Some((
+            vk::AccessFlags::SHADER_READ,
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+        )),
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => Some((
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        )),
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => Some((
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        )),
+        vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL => Some((
+            vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::SHADER_READ,
+            vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::FRAGMENT_SHADER,
+        )),
+        // Compute shaders write storage images in it
+        vk::ImageLayout::GENERAL => Some((
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+        )),
+        // Graph passes leave their outputs in it, right after writing them
+        vk::ImageLayout::PRESENT_SRC_KHR => Some((
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        )),
+        _ => None,
+    }
+}
+
+/// The number of mips down to 1x1, i.e. `floor(log2(max(width, height))) + 1`.
+pub(crate) fn num_mips_for_size(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+/// Whether mips of `format` can be generated by linearly filtered blits.
+pub fn is_blit_supported(format: vk::Format, basis: &Basis, gpu: &Gpu) -> bool {
+    let format_properties = unsafe {
+        basis
+            .instance
+            .get_physical_device_format_properties(gpu.physical_device, format)
+    };
+    format_properties.optimal_tiling_features.contains(
+        vk::FormatFeatureFlags::BLIT_SRC
+            | vk::FormatFeatureFlags::BLIT_DST
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+    )
+}
+
+fn unorm_sibling(format: vk::Format) -> Option<vk::Format> {
+    match format {
+        vk::Format::R8_SRGB => Some(vk::Format::R8_UNORM),
+        vk::Format::R8G8_SRGB => Some(vk::Format::R8G8_UNORM),
+        vk::Format::R8G8B8A8_SRGB => Some(vk::Format::R8G8B8A8_UNORM),
+        vk::Format::B8G8R8A8_SRGB => Some(vk::Format::B8G8R8A8_UNORM),
+        _ => None,
+    }
+}
+
+/* The format to create an image with, given the format that was asked for.
+Many devices can't use sRGB formats for storage, so storage images fall back
+to the UNORM sibling, which holds the same bytes. Shaders then read and write
+the encoded values, without any conversion. Other usages are checked with
+`check_format_usage()`. */
+pub fn resolve_image_format(
+    name: &str,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    basis: &Basis,
+    gpu: &Gpu,
+) -> Result<vk::Format, String> {
+    if let Some(unorm_format) = unorm_sibling(format) {
+        if usage.contains(vk::ImageUsageFlags::STORAGE)
+            && check_format_usage(name, format, usage, basis, gpu).is_err()
+            && check_format_usage(name, unorm_format, usage, basis, gpu).is_ok()
+        {
+            println!(
+                "Image `{}` uses {:?} instead of {:?}, since this device can't use the latter for storage.",
+                name, unorm_format, format
+            );
+            return Ok(unorm_format);
+        }
+    }
+    check_format_usage(name, format, usage, basis, gpu)?;
+    Ok(format)
+}
+
+/* Whether the device supports every use in `usage` for optimally tiled
+images of `format`. An error names the first unsupported use, e.g. for an
+HDR render target on a device that can't render to its format. */
+pub fn check_format_usage(
+    name: &str,
+    format: vk::Format,
+    usage: vk::ImageUsageFlags,
+    basis: &Basis,
+    gpu: &Gpu,
+) -> Result<(), String> {
+    let format_properties = unsafe {
+        basis
+            .instance
+            .get_physical_device_format_properties(gpu.physical_device, format)
+    };
+    let required_features = [
+        (
+            vk::ImageUsageFlags::SAMPLED,
+            vk::FormatFeatureFlags::SAMPLED_IMAGE,
+            "sample",
+        ),
+        (
+            vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            vk::FormatFeatureFlags::COLOR_ATTACHMENT,
+            "render to",
+        ),
+        (
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+            "use for depth",
+        ),
+        (
+            vk::ImageUsageFlags::STORAGE,
+            vk::FormatFeatureFlags::STORAGE_IMAGE,
+            "use for storage",
+        ),
+    ];
+    for (required_usage, feature, description) in &required_features {
+        if usage.contains(*required_usage)
+            && !format_properties.optimal_tiling_features.contains(*feature)
+        {
+            return Err(format!(
+                "Image `{}` has format {:?}, which this device can't {}.",
+                name, format, description
+            ));
+        }
+    }
+    Ok(())
+}
+
+/* Images with a depth above 1 are 3D, which devices usually limit to far
+smaller sizes than 2D images, e.g. 2048 rather than 16384 texels per side. */
+pub fn check_image_extent(name: &str, size: (u32, u32, u32), gpu: &Gpu) -> Result<(), String> {
+    check_image_extent_within(name, size, &gpu.properties.limits)
+}
+
+fn check_image_extent_within(
+    name: &str,
+    (width, height, depth): (u32, u32, u32),
+    limits: &vk::PhysicalDeviceLimits,
+) -> Result<(), String> {
+    let (max_dimension, kind) = if depth > 1 {
+        (limits.max_image_dimension3_d, "3D")
+    } else {
+        (limits.max_image_dimension2_d, "2D")
+    };
+    let is_valid = |dimension: u32| dimension > 0 && dimension <= max_dimension;
+    if !is_valid(width) || !is_valid(height) || !is_valid(depth) {
+        return Err(format!(
+            "Image `{}` is {}x{}x{}, but the device supports {} images of 1 to {} texels per side.",
+            name, width, height, depth, kind, max_dimension
+        ));
+    }
+    Ok(())
+}
+
+fn check_num_layers(name: &str, num_layers: u32, gpu: &Gpu) -> Result<(), String> {
+    check_num_layers_within(name, num_layers, &gpu.properties.limits)
+}
+
+fn check_num_layers_within(
+    name: &str,
+    num_layers: u32,
+    limits: &vk::PhysicalDeviceLimits,
+) -> Result<(), String> {
+    let max_layers = limits.max_image_array_layers;
+    if num_layers == 0 || num_layers > max_layers {
+        return Err(format!(
+            "Image array `{}` has {} layers, but the device supports 1 to {}.",
+            name, num_layers, max_layers
+        ));
+    }
+    Ok(())
+}
+
+// From the full size down to 1x1x1, or fewer
+fn check_num_mips(
+    name: &str,
+    (width, height, depth): (u32, u32, u32),
+    num_mips: usize,
+) -> Result<(), String> {
+    let max_mips = num_mips_for_size(width, height.max(depth));
+    if num_mips == 0 || num_mips > max_mips as usize {
+        return Err(format!(
+            "Image `{}` has {} mips, but a {}x{}x{} image has 1 to {}.",
+            name, num_mips, width, height, depth, max_mips
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> vk::PhysicalDeviceLimits {
+        vk::PhysicalDeviceLimits {
+            max_image_dimension2_d: 16384,
+            max_image_dimension3_d: 2048,
+            max_image_array_layers: 256,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn extents_are_checked_against_the_2d_limit() {
+        let limits = limits();
+        for &size in &[(1, 1, 1), (1, 37, 1), (16384, 16384, 1), (16384, 1, 1)] {
+            assert_eq!(check_image_extent_within("image", size, &limits), Ok(()));
+        }
+        for &size in &[(16385, 1, 1), (1, 16385, 1), (u32::MAX, u32::MAX, 1)] {
+            let message = check_image_extent_within("image", size, &limits).unwrap_err();
+            assert!(message.contains("2D images of 1 to 16384"), "{}", message);
+        }
+    }
+
+    #[test]
+    fn extents_with_depth_are_checked_against_the_3d_limit() {
+        let limits = limits();
+        assert_eq!(
+            check_image_extent_within("volume", (2048, 2048, 2048), &limits),
+            Ok(())
+        );
+        assert_eq!(
+            check_image_extent_within("volume", (1, 1, 2), &limits),
+            Ok(())
+        );
+        // Within the 2D limit, but not the 3D one
+        for &size in &[(4096, 16, 16), (16, 16, 2049)] {
+            let message = check_image_extent_within("volume", size, &limits).unwrap_err();
+            assert!(message.contains("3D images of 1 to 2048"), "{}", message);
+        }
+    }
+
+    #[test]
+    fn zero_extents_are_errors() {
+        let limits = limits();
+        for &size in &[(0, 0, 0), (0, 64, 1), (64, 0, 1), (64, 64, 0), (0, 64, 64)] {
+            assert!(check_image_extent_within("image", size, &limits).is_err());
+        }
+    }
+
+    #[test]
+    fn layers_are_checked_against_the_limit() {
+        let limits = limits();
+        assert_eq!(check_num_layers_within("array", 1, &limits), Ok(()));
+        assert_eq!(check_num_layers_within("array", 6, &limits), Ok(()));
+        assert_eq!(check_num_layers_within("array", 256, &limits), Ok(()));
+        assert!(check_num_layers_within("array", 0, &limits).is_err());
+        let message = check_num_layers_within("array", 257, &limits).unwrap_err();
+        assert_eq!(
+            message,
+            "Image array `array` has 257 layers, but the device supports 1 to 256."
+        );
+    }
+
+    #[test]
+    fn mip_counts_are_bounded_by_the_largest_side() {
+        let counts = [
+            ((1, 1, 1), 1),
+            ((2, 1, 1), 2),
+            ((256, 256, 1), 9),
+            ((256, 1, 1), 9),
+            ((1, 256, 1), 9),
+            ((300, 17, 1), 9), // Non-powers of two round down
+            ((16, 16, 64), 7), // Depth counts too
+            ((16384, 16384, 1), 15),
+        ];
+        for &(size, max_mips) in &counts {
+            assert_eq!(check_num_mips("image", size, 1), Ok(()));
+            assert_eq!(check_num_mips("image", size, max_mips), Ok(()));
+            let message = check_num_mips("image", size, max_mips + 1).unwrap_err();
+            assert!(
+                message.ends_with(&format!("has 1 to {}.", max_mips)),
+                "{}",
+                message
+            );
+        }
+        assert!(check_num_mips("image", (256, 256, 1), 0).is_err());
+        // Zero sizes are left to the extent check
+        assert_eq!(check_num_mips("image", (0, 0, 0), 1), Ok(()));
     }
 }
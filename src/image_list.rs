@@ -12,101 +12,586 @@ pub struct InternalImage {
     pub kind: ImageKind,
 }
 
+// The file that an image was loaded from, for hot reloading
+struct ImageSource {
+    handle: ImageHandle,
+    path: String,
+    opt_content_hash: Option<u64>, // None if the file couldn't be read
+}
+
 pub struct ImageList {
-    pub list: Vec<(ImageHandle, InternalImage)>,
+    pub list: Registry<InternalImage>,
+    sources: Vec<ImageSource>,
 }
 
 impl ImageList {
     pub fn new() -> ImageList {
-        ImageList { list: Vec::new() }
+        ImageList {
+            list: Registry::new("image"),
+            sources: Vec::new(),
+        }
     }
 
     #[allow(clippy::too_many_arguments)]
-    pub fn new_image_relative_size(
+    pub fn new_image(
         &mut self,
         name: &str,
-        scale: f32,
+        size: ImageSize,
+        num_mips: u32,        // Rendered to one at a time, e.g. for a downsample chain
+        samples: SampleCount, // Above `X1`, the image has a single mip
         format: vk::Format,
         usage: vk::ImageUsageFlags,
         aspect_flags: vk::ImageAspectFlags,
         facade: &Facade,
+        basis: &Basis,
         gpu: &Gpu,
         debug_utils: &DebugUtils,
     ) -> Result<ImageHandle, String> {
-        // Hash
-        let handle = {
-            let mut hasher = DefaultHasher::new();
-            name.hash(&mut hasher);
-            ImageHandle(hasher.finish())
-        };
         // Error if name already exists
-        if self.get_image_from_handle(handle).is_some() {
+        if self.list.find(name).is_some() {
             return Err(format!(
                 "An image with the same name `{}` already exists in the context.",
                 name
             ));
         }
+        let format = resolve_image_format(name, format, usage, basis, gpu)?;
         // Create new image
-        let w = (facade.swapchain_width as f32 * scale) as u32;
-        let h = (facade.swapchain_height as f32 * scale) as u32;
-        let image = Image::new(name, w, h, format, usage, aspect_flags, gpu, &debug_utils);
-        self.list.push((
+        let ((w, h, d), kind) = match size {
+            ImageSize::Absolute(extent) => (
+                (extent.width, extent.height, extent.depth),
+                ImageKind::AbsoluteSized,
+            ),
+            ImageSize::SwapchainRelative(scale) => {
+                let (w, h) = relative_image_size_checked(name, scale, facade, gpu);
+                ((w, h, 1), ImageKind::RelativeSized { scale })
+            }
+        };
+        check_image_extent(name, (w, h, d), gpu)?;
+        let max_mips = if samples.is_multisampled() {
+            1
+        } else {
+            num_mips_for_size(w, h.max(d))
+        };
+        if num_mips == 0 || num_mips > max_mips {
+            return Err(format!(
+                "Image `{}` has {} mips, but a {}x{}x{} image has 1 to {}.",
+                name, num_mips, w, h, d, max_mips
+            ));
+        }
+        let image = Image::new_with_mips(
+            name,
+            w,
+            h,
+            d,
+            num_mips,
+            1,
+            samples.to_vk(),
+            format,
+            usage,
+            aspect_flags,
+            gpu,
+            debug_utils,
+        );
+        let handle = self.list.insert(name, InternalImage { image, kind })?;
+
+        Ok(handle)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_image_from_file(
+        &mut self,
+        name: &str,
+        path: &str,
+        with_mips: bool,
+        color_space: ColorSpace,
+        basis: &Basis,
+        gpu: &Gpu,
+        command_pool: vk::CommandPool,
+        debug_utils: &DebugUtils,
+    ) -> Result<ImageHandle, String> {
+        // Error if name already exists
+        if self.list.find(name).is_some() {
+            return Err(format!(
+                "An image with the same name `{}` already exists in the context.",
+                name
+            ));
+        }
+        // Create new image. Stand in with a checkerboard if the file can't be loaded.
+        let image = Image::new_from_image(
+            gpu,
+            std::path::Path::new(&path),
+            command_pool,
+            name,
+            color_space.rgba8_format(),
+            with_mips,
+            basis,
+            debug_utils,
+        )
+        .unwrap_or_else(|err| {
+            println!("{} Using a checkerboard for image `{}`.", err, name);
+            Image::new_from_data(
+                name,
+                (CHECKERBOARD_SIZE, CHECKERBOARD_SIZE, 1),
+                color_space.rgba8_format(),
+                &checkerboard_texels(CHECKERBOARD_SIZE),
+                gpu,
+                command_pool,
+                debug_utils,
+            )
+        });
+        let handle = self.list.insert(
+            name,
+            InternalImage {
+                image,
+                kind: ImageKind::AbsoluteSized,
+            },
+        )?;
+        self.sources.push(ImageSource {
             handle,
+            path: String::from(path),
+            opt_content_hash: content_hash(path),
+        });
+
+        Ok(handle)
+    }
+
+    /// Unlike `new_image_from_file()`, a file that can't be loaded is an error.
+    pub fn new_image_from_ktx2(
+        &mut self,
+        name: &str,
+        path: &str,
+        basis: &Basis,
+        gpu: &Gpu,
+        command_pool: vk::CommandPool,
+        debug_utils: &DebugUtils,
+    ) -> Result<ImageHandle, String> {
+        // Error if name already exists
+        if self.list.find(name).is_some() {
+            return Err(format!(
+                "An image with the same name `{}` already exists in the context.",
+                name
+            ));
+        }
+        let image = Image::new_from_ktx2(
+            std::path::Path::new(path),
+            name,
+            basis,
+            gpu,
+            command_pool,
+            debug_utils,
+        )?;
+        let handle = self.list.insert(
+            name,
             InternalImage {
                 image,
-                kind: ImageKind::RelativeSized { scale },
+                kind: ImageKind::AbsoluteSized,
             },
-        ));
+        )?;
 
         Ok(handle)
     }
 
-    pub fn new_image_from_file(
+    pub fn new_image_from_dds(
         &mut self,
         name: &str,
         path: &str,
+        basis: &Basis,
+        gpu: &Gpu,
+        command_pool: vk::CommandPool,
+        debug_utils: &DebugUtils,
+    ) -> Result<ImageHandle, String> {
+        // Error if name already exists
+        if self.list.find(name).is_some() {
+            return Err(format!(
+                "An image with the same name `{}` already exists in the context.",
+                name
+            ));
+        }
+        let image = Image::new_from_dds(
+            std::path::Path::new(path),
+            name,
+            basis,
+            gpu,
+            command_pool,
+            debug_utils,
+        )?;
+        let handle = self.list.insert(
+            name,
+            InternalImage {
+                image,
+                kind: ImageKind::AbsoluteSized,
+            },
+        )?;
+
+        Ok(handle)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_image_from_data(
+        &mut self,
+        name: &str,
+        size: (u32, u32, u32),
+        format: vk::Format,
+        data: &[u8],
         gpu: &Gpu,
         command_pool: vk::CommandPool,
         debug_utils: &DebugUtils,
     ) -> Result<ImageHandle, String> {
-        // Hash
-        let handle = {
-            let mut hasher = DefaultHasher::new();
-            name.hash(&mut hasher);
-            ImageHandle(hasher.finish())
-        };
         // Error if name already exists
-        if self.get_image_from_handle(handle).is_some() {
+        if self.list.find(name).is_some() {
             return Err(format!(
                 "An image with the same name `{}` already exists in the context.",
                 name
             ));
         }
+        check_image_extent(name, size, gpu)?;
         // Create new image
-        let image = Image::new_from_image(
+        let image = Image::new_from_data(name, size, format, data, gpu, command_pool, debug_utils);
+        let handle = self.list.insert(
+            name,
+            InternalImage {
+                image,
+                kind: ImageKind::AbsoluteSized,
+            },
+        )?;
+
+        Ok(handle)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_image_from_mip_data(
+        &mut self,
+        name: &str,
+        size: (u32, u32, u32),
+        num_layers: u32,
+        format: vk::Format,
+        mips: &[&[u8]],
+        basis: &Basis,
+        gpu: &Gpu,
+        command_pool: vk::CommandPool,
+        debug_utils: &DebugUtils,
+    ) -> Result<ImageHandle, String> {
+        // Error if name already exists
+        if self.list.find(name).is_some() {
+            return Err(format!(
+                "An image with the same name `{}` already exists in the context.",
+                name
+            ));
+        }
+        let image = Image::new_from_mip_data(
+            name,
+            size,
+            num_layers,
+            format,
+            mips,
+            basis,
             gpu,
-            std::path::Path::new(&path),
             command_pool,
+            debug_utils,
+        )?;
+        let handle = self.list.insert(
             name,
-            &debug_utils,
-        );
-        self.list.push((
-            handle,
             InternalImage {
                 image,
                 kind: ImageKind::AbsoluteSized,
             },
-        ));
+        )?;
 
         Ok(handle)
     }
 
-    pub fn get_image_from_handle(&self, image_handle: ImageHandle) -> Option<&InternalImage> {
-        for (handle, internal_image) in &self.list {
-            if *handle == image_handle {
-                return Some(internal_image);
+    /// A 2D array whose layers are rendered to one at a time.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_image_array(
+        &mut self,
+        name: &str,
+        (width, height): (u32, u32),
+        num_layers: u32,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        aspect_flags: vk::ImageAspectFlags,
+        basis: &Basis,
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> Result<ImageHandle, String> {
+        // Error if name already exists
+        if self.list.find(name).is_some() {
+            return Err(format!(
+                "An image with the same name `{}` already exists in the context.",
+                name
+            ));
+        }
+        let format = resolve_image_format(name, format, usage, basis, gpu)?;
+        let image = Image::new_array(
+            name,
+            width,
+            height,
+            num_layers,
+            format,
+            usage,
+            aspect_flags,
+            gpu,
+            debug_utils,
+        )?;
+        let handle = self.list.insert(
+            name,
+            InternalImage {
+                image,
+                kind: ImageKind::AbsoluteSized,
+            },
+        )?;
+
+        Ok(handle)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_image_array_from_data(
+        &mut self,
+        name: &str,
+        size: (u32, u32),
+        num_layers: u32,
+        format: vk::Format,
+        data: &[u8],
+        gpu: &Gpu,
+        command_pool: vk::CommandPool,
+        debug_utils: &DebugUtils,
+    ) -> Result<ImageHandle, String> {
+        // Error if name already exists
+        if self.list.find(name).is_some() {
+            return Err(format!(
+                "An image with the same name `{}` already exists in the context.",
+                name
+            ));
+        }
+        let image = Image::new_array_from_data(
+            name,
+            size,
+            num_layers,
+            format,
+            data,
+            gpu,
+            command_pool,
+            debug_utils,
+        )?;
+        let handle = self.list.insert(
+            name,
+            InternalImage {
+                image,
+                kind: ImageKind::AbsoluteSized,
+            },
+        )?;
+
+        Ok(handle)
+    }
+
+    /// Like `new_image_from_data()`, but the copy is submitted at the start of
+    /// the next frame instead of being waited on.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_image_from_data_async(
+        &mut self,
+        name: &str,
+        size: (u32, u32, u32),
+        format: vk::Format,
+        data: &[u8],
+        upload_context: &UploadContext,
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> Result<(ImageHandle, UploadTicket), String> {
+        // Error if name already exists
+        if self.list.find(name).is_some() {
+            return Err(format!(
+                "An image with the same name `{}` already exists in the context.",
+                name
+            ));
+        }
+        check_image_extent(name, size, gpu)?;
+        let (image, ticket) =
+            upload_context.upload_image(name, size, format, data, gpu, debug_utils);
+        let handle = self.list.insert(
+            name,
+            InternalImage {
+                image,
+                kind: ImageKind::AbsoluteSized,
+            },
+        )?;
+
+        Ok((handle, ticket))
+    }
+
+    /* Reload the images whose files have changed. A file that fails to load,
+    e.g. because it is still being written, keeps the previous image, and is
+    retried on the next change. Returns true if any image was replaced, in
+    which case the graphs that point to the old images have to be dropped.
+
+    The GPU must be idle, since the replaced images are destroyed. */
+    pub fn hot_reload(
+        &mut self,
+        basis: &Basis,
+        gpu: &Gpu,
+        command_pool: vk::CommandPool,
+        debug_utils: &DebugUtils,
+    ) -> bool {
+        let mut is_any_image_replaced = false;
+        for source in &mut self.sources {
+            let opt_content_hash = content_hash(&source.path);
+            if opt_content_hash.is_none() || opt_content_hash == source.opt_content_hash {
+                continue;
             }
+            // Images that have been destroyed aren't reloaded
+            let internal_image = match self.list.get_mut(source.handle) {
+                Ok(internal_image) => internal_image,
+                Err(_) => continue,
+            };
+            match Image::new_from_image(
+                gpu,
+                std::path::Path::new(&source.path),
+                command_pool,
+                &internal_image.image.name,
+                internal_image.image.format,
+                internal_image.image.num_mips > 1,
+                basis,
+                debug_utils,
+            ) {
+                Ok(image) => {
+                    println!("Reloaded image `{}`.", image.name);
+                    internal_image.image = image;
+                    source.opt_content_hash = opt_content_hash;
+                    is_any_image_replaced = true;
+                }
+                Err(err) => println!(
+                    "{} Keeping the previous version of image `{}`.",
+                    err, internal_image.image.name
+                ),
+            }
+        }
+        is_any_image_replaced
+    }
+
+    /// Fails with an error that names the image if it has been destroyed.
+    pub fn get_image(&self, image_handle: ImageHandle) -> Result<&InternalImage, String> {
+        self.list.get(image_handle)
+    }
+
+    pub fn get_image_from_handle_mut(
+        &mut self,
+        image_handle: ImageHandle,
+    ) -> Option<&mut InternalImage> {
+        self.list.get_mut(image_handle).ok()
+    }
+
+    pub fn get_image_from_handle(&self, image_handle: ImageHandle) -> Option<&InternalImage> {
+        self.list.get(image_handle).ok()
+    }
+
+    /// Destroy an image, which the GPU must be done with. Its handles are stale
+    /// from now on.
+    pub fn destroy_image(&mut self, image_handle: ImageHandle) -> Result<(), String> {
+        self.list.remove(image_handle)?;
+        self.sources.retain(|source| source.handle != image_handle);
+        Ok(())
+    }
+}
+
+fn content_hash(path: &str) -> Option<u64> {
+    let bytes = std::fs::read(path).ok()?;
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/* Size of an image that is `scale` times the swapchain size. Never zero, so
+that tiny windows and small scales don't produce empty images, and never
+larger than `max_dimension`. */
+pub fn relative_image_size(
+    swapchain_width: u32,
+    swapchain_height: u32,
+    scale: f32,
+    max_dimension: u32,
+) -> (u32, u32) {
+    let scaled = |dimension: u32| {
+        let size = (dimension as f32 * scale) as u32; // Saturates on overflow
+        size.clamp(1, max_dimension.max(1))
+    };
+    (scaled(swapchain_width), scaled(swapchain_height))
+}
+
+// Same as above, warning when the size had to be clamped to the device's limit
+pub(crate) fn relative_image_size_checked(
+    name: &str,
+    scale: f32,
+    facade: &Facade,
+    gpu: &Gpu,
+) -> (u32, u32) {
+    let max_dimension = gpu.properties.limits.max_image_dimension2_d;
+    let (w, h) = relative_image_size(
+        facade.swapchain_width,
+        facade.swapchain_height,
+        scale,
+        max_dimension,
+    );
+    let exceeds_limit = |dimension: u32| dimension as f32 * scale > max_dimension as f32;
+    if exceeds_limit(facade.swapchain_width) || exceeds_limit(facade.swapchain_height) {
+        println!(
+            "Image `{}` would be larger than the device's limit of {} pixels. Clamped to {}x{}.",
+            name, max_dimension, w, h
+        );
+    }
+    (w, h)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_DIMENSION: u32 = 16384;
+
+    #[test]
+    fn relative_sizes_scale_the_swapchain() {
+        let sizes = [
+            ((1920, 1080, 1.0), (1920, 1080)),
+            ((1920, 1080, 0.5), (960, 540)),
+            ((1366, 767, 0.5), (683, 383)), // Rounded down
+            ((1366, 767, 0.25), (341, 191)),
+            ((1366, 767, 2.0), (2732, 1534)),
+            ((37, 1, 0.5), (18, 1)),
+        ];
+        for &((w, h, scale), expected) in &sizes {
+            assert_eq!(relative_image_size(w, h, scale, MAX_DIMENSION), expected);
         }
-        None
+    }
+
+    #[test]
+    fn relative_sizes_are_never_zero() {
+        assert_eq!(relative_image_size(0, 0, 1.0, MAX_DIMENSION), (1, 1));
+        assert_eq!(relative_image_size(1, 1, 0.5, MAX_DIMENSION), (1, 1));
+        assert_eq!(relative_image_size(3, 7, 0.1, MAX_DIMENSION), (1, 1));
+        assert_eq!(relative_image_size(800, 600, 0.0, MAX_DIMENSION), (1, 1));
+        assert_eq!(relative_image_size(800, 600, -1.0, MAX_DIMENSION), (1, 1));
+        assert_eq!(
+            relative_image_size(800, 600, f32::NAN, MAX_DIMENSION),
+            (1, 1)
+        );
+        // Even if the limit is
+        assert_eq!(relative_image_size(800, 600, 1.0, 0), (1, 1));
+    }
+
+    #[test]
+    fn relative_sizes_are_clamped_to_the_limit() {
+        assert_eq!(
+            relative_image_size(16384, 16384, 1.0, MAX_DIMENSION),
+            (16384, 16384)
+        );
+        assert_eq!(
+            relative_image_size(7680, 1440, 4.0, MAX_DIMENSION),
+            (16384, 5760)
+        );
+        assert_eq!(
+            relative_image_size(u32::MAX, 3, f32::MAX, MAX_DIMENSION),
+            (16384, 16384)
+        );
+        assert_eq!(
+            relative_image_size(800, 600, f32::INFINITY, MAX_DIMENSION),
+            (16384, 16384)
+        );
     }
 }
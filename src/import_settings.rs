@@ -0,0 +1,190 @@
+use glam::*;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UpAxis {
+    Y,
+    Z,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Handedness {
+    Right,
+    Left,
+}
+
+/* The conventions that an asset was authored in. Loaders bake the conversion
+to glTF's conventions (Y up, right-handed, meters) into the vertex data, so
+that bounds computed from it are already in the engine's space. */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ImportSettings {
+    pub up_axis: UpAxis,
+    pub handedness: Handedness,
+    pub unit_scale: f32, // Meters per source unit, e.g. 0.01 for centimeters
+}
+
+impl Default for ImportSettings {
+    fn default() -> ImportSettings {
+        ImportSettings {
+            up_axis: UpAxis::Y,
+            handedness: Handedness::Right,
+            unit_scale: 1.0,
+        }
+    }
+}
+
+impl ImportSettings {
+    /// Linear transform from the source conventions to glTF's.
+    pub fn to_matrix(&self) -> Mat3 {
+        // With Z up, X stays put and +Y points away from the viewer
+        let (x, y, z) = match self.up_axis {
+            UpAxis::Y => (Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z()),
+            UpAxis::Z => (Vec3::unit_x(), -Vec3::unit_z(), Vec3::unit_y()),
+        };
+        // Mirror the axis that points away from the viewer
+        let (y, z) = match (self.handedness, self.up_axis) {
+            (Handedness::Right, _) => (y, z),
+            (Handedness::Left, UpAxis::Y) => (y, -z),
+            (Handedness::Left, UpAxis::Z) => (-y, z),
+        };
+        Mat3::from_cols(x, y, z) * Mat3::from_scale(Vec3::splat(self.unit_scale))
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == ImportSettings::default()
+    }
+
+    /// A mirroring conversion turns front faces into back faces, so triangles
+    /// must be rewound.
+    pub fn flips_winding(&self) -> bool {
+        self.handedness == Handedness::Left
+    }
+
+    /* Convert positions and normals in place, and rewind the triangles if
+    needed. Normals go through the inverse transpose, which for a rotation
+    with a uniform scale and an optional mirror only differs in length. */
+    pub fn apply(&self, positions: &mut [Vec3], normals: &mut [Vec3], indices: &mut [u32]) {
+        if self.is_identity() {
+            return;
+        }
+        let matrix = self.to_matrix();
+        let normal_matrix = matrix.inverse().transpose();
+        for position in positions {
+            *position = matrix * *position;
+        }
+        for normal in normals {
+            *normal = (normal_matrix * *normal).normalize();
+        }
+        if self.flips_winding() {
+            for triangle in indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Aabb;
+
+    // Corners of a box that isn't symmetric about any axis, in glTF's conventions
+    const BOX_MIN: [f32; 3] = [1.0, 0.0, -1.0];
+    const BOX_MAX: [f32; 3] = [2.0, 3.0, 0.5];
+
+    fn box_corners() -> Vec<Vec3> {
+        (0..8)
+            .map(|i| {
+                let pick = |axis: usize| {
+                    if i & (1 << axis) == 0 {
+                        BOX_MIN[axis]
+                    } else {
+                        BOX_MAX[axis]
+                    }
+                };
+                Vec3::new(pick(0), pick(1), pick(2))
+            })
+            .collect()
+    }
+
+    // Settings, and how they author a point given in glTF's conventions
+    type Convention = (ImportSettings, fn(Vec3) -> Vec3);
+
+    fn assert_close(actual: Vec3, expected: Vec3) {
+        assert!(
+            (actual - expected).length() < 1e-5,
+            "{:?} isn't close to {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn the_same_box_in_three_conventions_has_the_same_bounds() {
+        let y_up_meters = ImportSettings::default();
+        let z_up_meters = ImportSettings {
+            up_axis: UpAxis::Z,
+            ..ImportSettings::default()
+        };
+        let y_up_left_handed_centimeters = ImportSettings {
+            handedness: Handedness::Left,
+            unit_scale: 0.01,
+            ..ImportSettings::default()
+        };
+        let conventions: [Convention; 3] = [
+            (y_up_meters, |p| p),
+            (z_up_meters, |p| Vec3::new(p.x(), -p.z(), p.y())),
+            (y_up_left_handed_centimeters, |p| {
+                Vec3::new(p.x(), p.y(), -p.z()) * 100.0
+            }),
+        ];
+        for (settings, author) in &conventions {
+            let mut positions: Vec<Vec3> = box_corners().into_iter().map(author).collect();
+            settings.apply(&mut positions, &mut [], &mut []);
+            let aabb = Aabb::from_points(positions);
+            assert_close(aabb.min, Vec3::from(BOX_MIN));
+            assert_close(aabb.max, Vec3::from(BOX_MAX));
+        }
+    }
+
+    #[test]
+    fn z_up_left_handed_maps_every_axis() {
+        let settings = ImportSettings {
+            up_axis: UpAxis::Z,
+            handedness: Handedness::Left,
+            unit_scale: 0.01,
+        };
+        let matrix = settings.to_matrix();
+        assert_close(matrix * Vec3::unit_x() * 100.0, Vec3::unit_x());
+        assert_close(matrix * Vec3::unit_y() * 100.0, Vec3::unit_z());
+        assert_close(matrix * Vec3::unit_z() * 100.0, Vec3::unit_y());
+    }
+
+    #[test]
+    fn handedness_flips_reverse_winding() {
+        for up_axis in &[UpAxis::Y, UpAxis::Z] {
+            for handedness in &[Handedness::Right, Handedness::Left] {
+                let settings = ImportSettings {
+                    up_axis: *up_axis,
+                    handedness: *handedness,
+                    unit_scale: 0.5,
+                };
+                // Wound counterclockwise around its normal
+                let mut positions = vec![Vec3::zero(), Vec3::unit_x(), Vec3::unit_y()];
+                let mut normals = vec![Vec3::unit_z(); 3];
+                let mut indices = vec![0, 1, 2];
+                settings.apply(&mut positions, &mut normals, &mut indices);
+
+                let expected_indices = match handedness {
+                    Handedness::Right => [0, 1, 2],
+                    Handedness::Left => [0, 2, 1],
+                };
+                assert_eq!(indices, expected_indices);
+                // The winding still agrees with the normal, so front faces stay front faces
+                let [a, b, c] = [0, 1, 2].map(|i| positions[indices[i] as usize]);
+                let winding_normal = (b - a).cross(c - a).normalize();
+                assert_close(winding_normal, normals[0]);
+                assert!((normals[0].length() - 1.0).abs() < 1e-6);
+            }
+        }
+    }
+}
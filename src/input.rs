@@ -0,0 +1,121 @@
+use glam::*;
+use std::collections::HashSet;
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+
+/// Keyboard and mouse state, accumulated from the window events that the
+/// context pumps in `begin_frame()`.
+pub struct InputState {
+    keys_down: HashSet<VirtualKeyCode>,
+    keys_pressed: HashSet<VirtualKeyCode>, // Keys that went down this frame
+    mouse_buttons_down: HashSet<MouseButton>,
+    mouse_buttons_pressed: HashSet<MouseButton>, // Buttons that went down this frame
+    pub cursor_position: Vec2, // In physical pixels, relative to the top-left of the window
+    pub cursor_delta: Vec2,    // Cursor movement since the last frame, in physical pixels
+    pub scroll_delta: f32,     // Scroll wheel movement since the last frame, in lines
+    pub is_cursor_in_window: bool,
+    pub is_window_focused: bool,
+    pub opt_click_instant: Option<std::time::Instant>, // When a mouse button was first pressed this frame
+}
+
+impl InputState {
+    pub fn new() -> InputState {
+        InputState {
+            keys_down: HashSet::new(),
+            keys_pressed: HashSet::new(),
+            mouse_buttons_down: HashSet::new(),
+            mouse_buttons_pressed: HashSet::new(),
+            cursor_position: Vec2::zero(),
+            cursor_delta: Vec2::zero(),
+            scroll_delta: 0.0,
+            is_cursor_in_window: false,
+            is_window_focused: true,
+            opt_click_instant: None,
+        }
+    }
+
+    /// Clear the state that is only valid for a single frame.
+    pub fn begin_frame(&mut self) {
+        self.keys_pressed.clear();
+        self.mouse_buttons_pressed.clear();
+        self.cursor_delta = Vec2::zero();
+        self.scroll_delta = 0.0;
+        self.opt_click_instant = None;
+    }
+
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput { input, .. } => {
+                if let Some(keycode) = input.virtual_keycode {
+                    match input.state {
+                        ElementState::Pressed => {
+                            // Key repeat sends repeated presses. Only the first one counts.
+                            if self.keys_down.insert(keycode) {
+                                self.keys_pressed.insert(keycode);
+                            }
+                        }
+                        ElementState::Released => {
+                            self.keys_down.remove(&keycode);
+                        }
+                    }
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => {
+                    if self.mouse_buttons_down.insert(*button) {
+                        self.mouse_buttons_pressed.insert(*button);
+                    }
+                    if self.opt_click_instant.is_none() {
+                        self.opt_click_instant = Some(std::time::Instant::now());
+                    }
+                }
+                ElementState::Released => {
+                    self.mouse_buttons_down.remove(button);
+                }
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                let new_position = Vec2::new(position.x as f32, position.y as f32);
+                if self.is_cursor_in_window {
+                    self.cursor_delta += new_position - self.cursor_position;
+                }
+                self.cursor_position = new_position;
+                self.is_cursor_in_window = true;
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.is_cursor_in_window = false;
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                // Pixel deltas come from touchpads. Treat ~20 pixels as one line.
+                self.scroll_delta += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / 20.0,
+                };
+            }
+            WindowEvent::Focused(false) => {
+                // We won't receive the release events, so don't leave keys stuck down.
+                self.keys_down.clear();
+                self.mouse_buttons_down.clear();
+                self.is_window_focused = false;
+            }
+            WindowEvent::Focused(true) => {
+                self.is_window_focused = true;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn is_key_down(&self, keycode: VirtualKeyCode) -> bool {
+        self.keys_down.contains(&keycode)
+    }
+
+    pub fn was_key_pressed(&self, keycode: VirtualKeyCode) -> bool {
+        self.keys_pressed.contains(&keycode)
+    }
+
+    pub fn is_mouse_button_down(&self, button: MouseButton) -> bool {
+        self.mouse_buttons_down.contains(&button)
+    }
+
+    pub fn was_mouse_button_pressed(&self, button: MouseButton) -> bool {
+        self.mouse_buttons_pressed.contains(&button)
+    }
+}
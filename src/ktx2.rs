@@ -0,0 +1,196 @@
+use crate::*;
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a,
+];
+const HEADER_SIZE: usize = 80; // Identifier, header and index
+const LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Ktx2Supercompression {
+    None,
+    BasisLz,
+    Zstd,
+    Zlib,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Ktx2Level {
+    pub offset: usize, // In the file
+    pub size: usize,   // Of every layer together
+}
+
+/* The parts of a KTX2 container that an upload needs. The level data isn't
+copied, and is read from the file's bytes through `levels`, which start at
+mip 0. The data format descriptor and key/value data are skipped. */
+#[derive(Clone, Debug)]
+pub struct Ktx2Header {
+    pub format: vk::Format, // KTX2 stores the `VkFormat` value
+    pub width: u32,
+    pub height: u32, // 1 for 1D textures
+    pub depth: u32,  // 1 for 2D textures
+    pub num_layers: u32,
+    pub num_faces: u32,
+    pub supercompression: Ktx2Supercompression,
+    pub levels: Vec<Ktx2Level>,
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut le_bytes = [0; 4];
+    le_bytes.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(le_bytes)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut le_bytes = [0; 8];
+    le_bytes.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(le_bytes)
+}
+
+pub fn parse_ktx2(bytes: &[u8]) -> Result<Ktx2Header, String> {
+    if bytes.len() < HEADER_SIZE || bytes[..12] != KTX2_IDENTIFIER {
+        return Err(String::from("Not a KTX2 file."));
+    }
+    let supercompression = match read_u32(bytes, 44) {
+        0 => Ktx2Supercompression::None,
+        1 => Ktx2Supercompression::BasisLz,
+        2 => Ktx2Supercompression::Zstd,
+        3 => Ktx2Supercompression::Zlib,
+        scheme => return Err(format!("Unknown supercompression scheme {}.", scheme)),
+    };
+    // Counts of 0 mean that the dimension isn't used
+    let num_levels = read_u32(bytes, 40).max(1) as usize;
+    // Checked before allocating, so that a corrupt count can't ask for a huge Vec
+    let is_level_index_complete = num_levels
+        .checked_mul(LEVEL_INDEX_ENTRY_SIZE)
+        .and_then(|size| size.checked_add(HEADER_SIZE))
+        .is_some_and(|level_index_end| bytes.len() >= level_index_end);
+    if !is_level_index_complete {
+        return Err(String::from("The level index is truncated."));
+    }
+    let mut header = Ktx2Header {
+        format: vk::Format::from_raw(read_u32(bytes, 12) as i32),
+        width: read_u32(bytes, 20),
+        height: read_u32(bytes, 24).max(1),
+        depth: read_u32(bytes, 28).max(1),
+        num_layers: read_u32(bytes, 32).max(1),
+        num_faces: read_u32(bytes, 36),
+        supercompression,
+        levels: Vec::with_capacity(num_levels),
+    };
+    for level_idx in 0..num_levels {
+        let entry_offset = HEADER_SIZE + level_idx * LEVEL_INDEX_ENTRY_SIZE;
+        let offset = read_u64(bytes, entry_offset) as usize;
+        let size = read_u64(bytes, entry_offset + 8) as usize;
+        if offset.checked_add(size).is_none_or(|end| end > bytes.len()) {
+            return Err(format!("The data of mip {} is truncated.", level_idx));
+        }
+        header.levels.push(Ktx2Level { offset, size });
+    }
+    if header.width == 0 {
+        return Err(String::from("The image has no width."));
+    }
+    Ok(header)
+}
+
+impl Image {
+    /* Load a KTX2 file with its mips as they are stored, so nothing is
+    blitted at runtime. 2D images, arrays and 3D images are supported, but
+    not cube maps. BC formats that the device can't sample are decoded to
+    RGBA8, and other unsampleable formats are rejected. Supercompressed files
+    aren't supported, since decoding them would need a zstd, zlib or Basis
+    decoder. */
+    pub fn new_from_ktx2(
+        path: &std::path::Path,
+        name: &str,
+        basis: &Basis,
+        gpu: &Gpu,
+        command_pool: vk::CommandPool,
+        debug_utils: &DebugUtils,
+    ) -> Result<Image, String> {
+        let bytes = std::fs::read(path)
+            .map_err(|e| format!("Failed to read `{}`: {}", path.display(), e))?;
+        let header = parse_ktx2(&bytes)
+            .map_err(|e| format!("Failed to load `{}`: {}", path.display(), e))?;
+        if header.supercompression != Ktx2Supercompression::None {
+            return Err(format!(
+                "`{}` is supercompressed with {:?}, which isn't supported.",
+                path.display(),
+                header.supercompression
+            ));
+        }
+        if header.num_faces > 1 {
+            return Err(format!(
+                "`{}` is a cube map, which isn't supported.",
+                path.display()
+            ));
+        }
+        if header.depth > 1 && header.num_layers > 1 {
+            return Err(format!(
+                "`{}` is an array of 3D images, which Vulkan doesn't support.",
+                path.display()
+            ));
+        }
+        let mips: Vec<&[u8]> = header
+            .levels
+            .iter()
+            .map(|level| &bytes[level.offset..level.offset + level.size])
+            .collect();
+        Image::new_from_mip_data(
+            name,
+            (header.width, header.height, header.depth),
+            header.num_layers,
+            header.format,
+            &mips,
+            basis,
+            gpu,
+            command_pool,
+            debug_utils,
+        )
+        .map_err(|e| format!("Failed to load `{}`: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 4x4 RGBA8 image with `num_levels` in its header, and one level of data
+    fn ktx2_bytes(num_levels: u32) -> Vec<u8> {
+        let data_offset = HEADER_SIZE + LEVEL_INDEX_ENTRY_SIZE;
+        let mut bytes = vec![0; data_offset + 64];
+        bytes[..12].copy_from_slice(&KTX2_IDENTIFIER);
+        let format = vk::Format::R8G8B8A8_UNORM.as_raw() as u32;
+        bytes[12..16].copy_from_slice(&format.to_le_bytes());
+        bytes[20..24].copy_from_slice(&4_u32.to_le_bytes());
+        bytes[24..28].copy_from_slice(&4_u32.to_le_bytes());
+        bytes[40..44].copy_from_slice(&num_levels.to_le_bytes());
+        bytes[HEADER_SIZE..HEADER_SIZE + 8].copy_from_slice(&(data_offset as u64).to_le_bytes());
+        bytes[HEADER_SIZE + 8..HEADER_SIZE + 16].copy_from_slice(&64_u64.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn levels_are_read_from_the_index() {
+        let header = parse_ktx2(&ktx2_bytes(1)).unwrap();
+        assert_eq!(header.format, vk::Format::R8G8B8A8_UNORM);
+        assert_eq!((header.width, header.height, header.depth), (4, 4, 1));
+        assert_eq!(header.levels.len(), 1);
+        assert_eq!(
+            header.levels[0].offset,
+            HEADER_SIZE + LEVEL_INDEX_ENTRY_SIZE
+        );
+        assert_eq!(header.levels[0].size, 64);
+    }
+
+    #[test]
+    fn level_counts_past_the_end_are_rejected() {
+        for num_levels in [5, 1000, u32::MAX] {
+            let err = parse_ktx2(&ktx2_bytes(num_levels)).unwrap_err();
+            assert_eq!(err, "The level index is truncated.", "{}", num_levels);
+        }
+        let mut bytes = ktx2_bytes(1);
+        bytes.truncate(HEADER_SIZE + 10);
+        assert!(parse_ktx2(&bytes).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use std::time::{Duration, Instant};
+
+/* Breakdown of the software part of click-to-photon latency: from the moment
+the click event is received, to the moment the frame that first reacts to it
+is handed to the presentation engine. Time spent in the compositor and the
+display is not visible to us. */
+#[derive(Copy, Clone, Debug)]
+pub struct LatencySample {
+    pub input_to_update: Duration, // Until the frame's events have been pumped
+    pub update_to_record: Duration, // Waiting for a free frame and swapchain image
+    pub record_to_submit: Duration,
+    pub submit_to_present: Duration,
+}
+
+impl LatencySample {
+    pub fn total(&self) -> Duration {
+        self.input_to_update
+            + self.update_to_record
+            + self.record_to_submit
+            + self.submit_to_present
+    }
+}
+
+struct PendingSample {
+    input_instant: Instant,
+    opt_update_instant: Option<Instant>,
+    opt_record_instant: Option<Instant>,
+    opt_submit_instant: Option<Instant>,
+}
+
+/// Measures the latency of mouse clicks while enabled. The context feeds it
+/// the timestamps of each stage of the frame.
+pub struct LatencyTracker {
+    pub is_enabled: bool,
+    pub samples: Vec<LatencySample>,
+    opt_pending: Option<PendingSample>,
+}
+
+impl LatencyTracker {
+    pub fn new() -> LatencyTracker {
+        LatencyTracker {
+            is_enabled: false,
+            samples: Vec::new(),
+            opt_pending: None,
+        }
+    }
+
+    /// True on the frame that is the first to react to a click. This is the
+    /// frame that should flip the screen for an external measurement.
+    pub fn is_measuring_frame(&self) -> bool {
+        self.opt_pending.is_some()
+    }
+
+    pub fn on_input(&mut self, input_instant: Instant) {
+        // Clicks that arrive while a sample is in flight are ignored
+        if self.is_enabled && self.opt_pending.is_none() {
+            self.opt_pending = Some(PendingSample {
+                input_instant,
+                opt_update_instant: None,
+                opt_record_instant: None,
+                opt_submit_instant: None,
+            });
+        }
+    }
+
+    pub fn on_update(&mut self) {
+        if let Some(pending) = &mut self.opt_pending {
+            pending.opt_update_instant = Some(Instant::now());
+        }
+    }
+
+    pub fn on_record(&mut self) {
+        if let Some(pending) = &mut self.opt_pending {
+            pending.opt_record_instant = Some(Instant::now());
+        }
+    }
+
+    pub fn on_submit(&mut self) {
+        if let Some(pending) = &mut self.opt_pending {
+            pending.opt_submit_instant = Some(Instant::now());
+        }
+    }
+
+    pub fn on_present(&mut self) {
+        if let Some(pending) = self.opt_pending.take() {
+            let present_instant = Instant::now();
+            if let (Some(update_instant), Some(record_instant), Some(submit_instant)) = (
+                pending.opt_update_instant,
+                pending.opt_record_instant,
+                pending.opt_submit_instant,
+            ) {
+                let sample = LatencySample {
+                    input_to_update: update_instant - pending.input_instant,
+                    update_to_record: record_instant - update_instant,
+                    record_to_submit: submit_instant - record_instant,
+                    submit_to_present: present_instant - submit_instant,
+                };
+                println!(
+                    "Click latency: {:.2} ms (input -> update {:.2}, update -> record {:.2}, record -> submit {:.2}, submit -> present {:.2})",
+                    as_ms(sample.total()),
+                    as_ms(sample.input_to_update),
+                    as_ms(sample.update_to_record),
+                    as_ms(sample.record_to_submit),
+                    as_ms(sample.submit_to_present),
+                );
+                self.samples.push(sample);
+            }
+        }
+    }
+
+    pub fn print_summary(&self) {
+        if self.samples.is_empty() {
+            return;
+        }
+        let mut totals: Vec<Duration> = self.samples.iter().map(|s| s.total()).collect();
+        totals.sort();
+        let sum: Duration = totals.iter().sum();
+        println!(
+            "Click latency over {} clicks: mean {:.2} ms, median {:.2} ms, min {:.2} ms, max {:.2} ms",
+            totals.len(),
+            as_ms(sum) / totals.len() as f64,
+            as_ms(totals[totals.len() / 2]),
+            as_ms(totals[0]),
+            as_ms(totals[totals.len() - 1]),
+        );
+    }
+}
+
+fn as_ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -1,35 +1,145 @@
+/*! A Vulkan renderer built around a render graph.
+
+The instance, device, swapchain, render graph, buffers, images and shaders
+are always built. Optional parts are behind cargo features, all of which are
+enabled by default:
+
+- `gltf`: `Mesh`, for loading meshes from glTF files.
+- `hot-reload`: Recompiles shaders, reloads images loaded from files, and
+  rebuilds the graphs that use them when files in `./assets` change.
+
+`synthetic-load` is the exception. It allows `SyntheticLoad` in release
+builds, and is off by default so that it can't ship by accident.
+
+Build with `--no-default-features` for the core alone. The supported API is
+collected in `prelude`. */
 #![allow(clippy::new_without_default)]
 
 mod platforms;
 
+pub mod aabb;
+pub use aabb::*;
+pub mod allocator;
+pub use allocator::*;
+pub mod api_types;
+pub use api_types::*;
+pub mod background_policy;
+pub use background_policy::*;
 pub mod basis;
 pub use basis::*;
+pub mod block_compression;
+pub use block_compression::*;
 pub mod buffer;
 pub use buffer::*;
 pub mod buffer_list;
 pub use buffer_list::*;
+pub mod bvh;
+pub use bvh::*;
+pub mod camera;
+pub use camera::*;
+pub mod camera_path;
+pub use camera_path::*;
+pub mod capability_report;
+pub use capability_report::*;
+pub mod clipboard;
+pub use clipboard::*;
+pub mod color;
+pub use color::*;
+pub mod command_channel;
+pub use command_channel::*;
+pub mod compute;
+pub use compute::*;
 pub mod context;
 pub use context::*;
+pub mod crash_report;
+pub use crash_report::*;
+pub mod dds;
+pub use dds::*;
 pub mod debug_utils;
 pub use debug_utils::*;
+pub mod default_resources;
+pub use default_resources::*;
 pub mod facade;
 pub use facade::*;
+pub mod frame_arena;
+pub use frame_arena::*;
+pub mod frame_budget;
+pub use frame_budget::*;
+pub mod frame_pacing;
+pub use frame_pacing::*;
+pub mod frame_time_summary;
+pub use frame_time_summary::*;
 pub mod gpu;
 pub use gpu::*;
+pub mod gpu_timer;
+pub use gpu_timer::*;
+pub mod graph_report;
+pub use graph_report::*;
 pub mod image;
 pub use crate::image::*;
 pub mod image_list;
 pub use image_list::*;
+pub mod import_settings;
+pub use import_settings::*;
+pub mod input;
+pub use input::*;
+pub mod ktx2;
+pub use ktx2::*;
+pub mod latency;
+pub use latency::*;
+pub mod live_objects;
+pub use live_objects::*;
+pub mod memory_report;
+pub use memory_report::*;
+#[cfg(feature = "gltf")]
 pub mod mesh;
+#[cfg(feature = "gltf")]
 pub use mesh::*;
+pub mod mesh_cache;
+pub use mesh_cache::*;
+pub mod monitor_tracker;
+pub use monitor_tracker::*;
+pub mod number_format;
+pub use number_format::*;
+pub mod occlusion_culling;
+pub use occlusion_culling::*;
+pub mod pipeline_cache;
+pub use pipeline_cache::*;
+pub mod prelude;
+pub mod ray;
+pub use ray::*;
 pub mod rdg;
 pub use rdg::*;
+pub mod reference_rasterizer;
+pub use reference_rasterizer::*;
+pub mod registry;
+pub use registry::*;
 pub mod sampler;
 pub use sampler::*;
+pub mod screenshot;
+pub use screenshot::*;
+pub mod shader_debug;
+pub use shader_debug::*;
 pub mod shader_list;
 pub use shader_list::*;
+pub mod sort_key;
+pub use sort_key::*;
+pub mod sync_pool;
+pub use sync_pool::*;
+pub mod synthetic_load;
+pub use synthetic_load::*;
+pub mod teardown_audit;
+pub use teardown_audit::*;
+pub mod tonemap;
+pub use tonemap::*;
+pub mod upload_context;
+pub use upload_context::*;
 pub mod utils;
 pub use utils::*;
+pub mod video_image;
+pub use video_image::*;
+pub mod viewport_composite;
+pub use viewport_composite::*;
 
 use ash::version::DeviceV1_0;
 use ash::version::EntryV1_0;
@@ -0,0 +1,53 @@
+use std::sync::atomic::{AtomicIsize, Ordering};
+
+/* Counts of the GPU objects that are alive right now, for catching leaks in
+paths that destroy and recreate objects, like swapchain recreation. Objects
+increment their counter when created and decrement it when dropped. */
+pub static LIVE_IMAGES: AtomicIsize = AtomicIsize::new(0);
+pub static LIVE_BUFFERS: AtomicIsize = AtomicIsize::new(0);
+pub static LIVE_GRAPHS: AtomicIsize = AtomicIsize::new(0);
+pub static LIVE_SAMPLERS: AtomicIsize = AtomicIsize::new(0);
+
+pub fn track_created(counter: &AtomicIsize) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn track_destroyed(counter: &AtomicIsize) {
+    counter.fetch_sub(1, Ordering::Relaxed);
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LiveObjects {
+    pub images: isize,
+    pub buffers: isize,
+    pub graphs: isize,
+    pub samplers: isize,
+}
+
+impl LiveObjects {
+    pub fn snapshot() -> LiveObjects {
+        LiveObjects {
+            images: LIVE_IMAGES.load(Ordering::Relaxed),
+            buffers: LIVE_BUFFERS.load(Ordering::Relaxed),
+            graphs: LIVE_GRAPHS.load(Ordering::Relaxed),
+            samplers: LIVE_SAMPLERS.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Describe every kind of object that has more live instances than in `baseline`.
+    pub fn growth_since(&self, baseline: &LiveObjects) -> Vec<String> {
+        let counts = [
+            ("images", self.images, baseline.images),
+            ("buffers", self.buffers, baseline.buffers),
+            ("graphs", self.graphs, baseline.graphs),
+            ("samplers", self.samplers, baseline.samplers),
+        ];
+        counts
+            .iter()
+            .filter(|(_, count, baseline_count)| count > baseline_count)
+            .map(|(name, count, baseline_count)| {
+                format!("{}: {} -> {}", name, baseline_count, count)
+            })
+            .collect()
+    }
+}
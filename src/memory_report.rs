@@ -0,0 +1,89 @@
+use crate::*;
+
+/* The device memory in use: every live allocation by the name of its
+resource, and totals per heap. What is still listed at shutdown has leaked.
+Implements `Display` as a table. */
+pub struct MemoryReport {
+    pub allocations: Vec<AllocationInfo>,
+    pub heaps: Vec<HeapStats>,
+}
+
+impl MemoryReport {
+    pub fn new(allocator: &Allocator) -> MemoryReport {
+        MemoryReport {
+            allocations: allocator.live_allocations(),
+            heaps: allocator.stats(),
+        }
+    }
+
+    /// One line per heap, without the allocations.
+    pub fn summary(&self) -> String {
+        let mut lines = Vec::new();
+        for heap in &self.heaps {
+            let mut line = format!(
+                "Heap {}{}: ",
+                heap.heap_idx,
+                if heap.is_device_local {
+                    " (device local)"
+                } else {
+                    ""
+                }
+            );
+            write_bytes(&mut line, heap.used_bytes, 0);
+            line.push_str(" used, ");
+            write_bytes(&mut line, heap.block_bytes, 0);
+            line.push_str(" allocated of ");
+            write_bytes(&mut line, heap.heap_size, 0);
+            let percentage = heap.block_bytes as f64 * 100.0 / heap.heap_size.max(1) as f64;
+            line.push_str(&format!(" ({:.1}%), ", percentage));
+            write_count(&mut line, heap.num_allocations as u64, 0);
+            line.push_str(" allocations in ");
+            write_count(&mut line, heap.num_blocks as u64, 0);
+            line.push_str(" blocks");
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+}
+
+impl std::fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{}", self.summary())?;
+        let name_width = self
+            .allocations
+            .iter()
+            .map(|allocation| allocation.name.len())
+            .max()
+            .unwrap_or(0)
+            .max(4);
+        write!(
+            f,
+            "{:<name_width$} {:>10} {:>4} {:>4} host",
+            "Name",
+            "Size",
+            "Heap",
+            "Type",
+            name_width = name_width
+        )?;
+        let mut size = String::new();
+        for allocation in &self.allocations {
+            size.clear();
+            write_bytes(&mut size, allocation.size, 0);
+            write!(
+                f,
+                "\n{:<name_width$} {:>10} {:>4} {:>4} {}",
+                allocation.name,
+                size,
+                allocation.heap_idx,
+                allocation.memory_type_idx,
+                if allocation.is_host_visible {
+                    "yes"
+                } else {
+                    "no"
+                },
+                name_width = name_width
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -5,6 +5,8 @@ use crate::*;
 pub struct Mesh {
     pub vertex_buffer: DeviceLocalBuffer,
     pub index_buffer: DeviceLocalBuffer,
+    pub aabb: Aabb,            // Object-space bounds
+    pub is_double_sided: bool, // Any of the mesh's materials is double-sided
 }
 
 impl Mesh {
@@ -15,47 +17,43 @@ impl Mesh {
         command_pool: vk::CommandPool,
         debug_utils: &DebugUtils,
     ) -> Mesh {
-        // TODO: Benchmark and optimize
-        let (vertices_data, indices_data) = {
-            let mut vertices_data: Vec<f32> = Vec::new();
-            let mut indices_data: Vec<u32> = Vec::new();
+        Mesh::load_with_settings(
+            name,
+            path,
+            &ImportSettings::default(),
+            gpu,
+            command_pool,
+            debug_utils,
+        )
+    }
 
-            let (gltf, buffers, _) = gltf::import(path).expect("Failed to open mesh.");
-            for mesh in gltf.meshes() {
-                for primitive in mesh.primitives() {
-                    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-                    if let Some(iter_pos) = reader.read_positions() {
-                        if let Some(iter_norm) = reader.read_normals() {
-                            for (pos, norm) in iter_pos.zip(iter_norm) {
-                                vertices_data.extend_from_slice(&pos);
-                                vertices_data.extend_from_slice(&norm);
-                            }
-                        }
-                    }
-                    if let Some(iter) = reader.read_indices() {
-                        match iter {
-                            gltf::mesh::util::ReadIndices::U8(iter_2) => {
-                                for idx in iter_2 {
-                                    indices_data.push(idx as u32);
-                                }
-                            }
-                            gltf::mesh::util::ReadIndices::U16(iter_2) => {
-                                for idx in iter_2 {
-                                    indices_data.push(idx as u32);
-                                }
-                            }
-                            gltf::mesh::util::ReadIndices::U32(iter_2) => {
-                                for idx in iter_2 {
-                                    indices_data.push(idx as u32);
-                                }
-                            }
-                        }
-                    }
+    /// Load a mesh that was authored in other conventions than glTF's.
+    pub fn load_with_settings(
+        name: &str,
+        path: &str,
+        settings: &ImportSettings,
+        gpu: &Gpu,
+        command_pool: vk::CommandPool,
+        debug_utils: &DebugUtils,
+    ) -> Mesh {
+        // Imports are cached, keyed by the source's contents and the settings
+        let opt_source_hash = mesh_source_hash(path, settings);
+        let mesh_data = match opt_source_hash.and_then(read_mesh_cache) {
+            Some(mesh_data) => mesh_data,
+            None => {
+                let mesh_data = import_gltf(path, settings);
+                if let Some(source_hash) = opt_source_hash {
+                    write_mesh_cache(&mesh_data, source_hash);
                 }
+                mesh_data
             }
-
-            (vertices_data, indices_data)
         };
+        let MeshData {
+            vertices: vertices_data,
+            indices: indices_data,
+            aabb,
+            is_double_sided,
+        } = mesh_data;
 
         // # Create and upload the vertex buffer
         let vertex_buffer = DeviceLocalBuffer::new(
@@ -68,18 +66,95 @@ impl Mesh {
         );
 
         // # Create and upload index buffer
-        let index_buffer = DeviceLocalBuffer::new(
-            &format!("buffer_{}_mesh_index", name),
-            &indices_data,
-            vk::BufferUsageFlags::INDEX_BUFFER,
-            gpu,
-            command_pool,
-            debug_utils,
-        );
+        // 16-bit indices take half the memory, when they can address every vertex
+        let index_buffer_name = format!("buffer_{}_mesh_index", name);
+        let num_vertices = vertices_data.len() / 6;
+        let index_buffer = if num_vertices <= u16::MAX as usize + 1 {
+            let indices_data: Vec<u16> = indices_data.iter().map(|&idx| idx as u16).collect();
+            DeviceLocalBuffer::new(
+                &index_buffer_name,
+                &indices_data,
+                vk::BufferUsageFlags::INDEX_BUFFER,
+                gpu,
+                command_pool,
+                debug_utils,
+            )
+        } else {
+            DeviceLocalBuffer::new(
+                &index_buffer_name,
+                &indices_data,
+                vk::BufferUsageFlags::INDEX_BUFFER,
+                gpu,
+                command_pool,
+                debug_utils,
+            )
+        };
 
         Mesh {
             vertex_buffer,
             index_buffer,
+            aabb,
+            is_double_sided,
         }
     }
 }
+
+fn import_gltf(path: &str, settings: &ImportSettings) -> MeshData {
+    let mut positions: Vec<glam::Vec3> = Vec::new();
+    let mut normals: Vec<glam::Vec3> = Vec::new();
+    let mut indices_data: Vec<u32> = Vec::new();
+    let mut is_double_sided = false;
+
+    let (gltf, buffers, _) = gltf::import(path).expect("Failed to open mesh.");
+    for mesh in gltf.meshes() {
+        for primitive in mesh.primitives() {
+            is_double_sided |= primitive.material().double_sided();
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            if let Some(iter_pos) = reader.read_positions() {
+                if let Some(iter_norm) = reader.read_normals() {
+                    for (pos, norm) in iter_pos.zip(iter_norm) {
+                        positions.push(glam::Vec3::from(pos));
+                        normals.push(glam::Vec3::from(norm));
+                    }
+                }
+            }
+            if let Some(iter) = reader.read_indices() {
+                match iter {
+                    gltf::mesh::util::ReadIndices::U8(iter_2) => {
+                        for idx in iter_2 {
+                            indices_data.push(idx as u32);
+                        }
+                    }
+                    gltf::mesh::util::ReadIndices::U16(iter_2) => {
+                        for idx in iter_2 {
+                            indices_data.push(idx as u32);
+                        }
+                    }
+                    gltf::mesh::util::ReadIndices::U32(iter_2) => {
+                        for idx in iter_2 {
+                            indices_data.push(idx);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    settings.apply(&mut positions, &mut normals, &mut indices_data);
+
+    let aabb = Aabb::from_points(positions.iter().copied());
+    let mut vertices_data: Vec<f32> = Vec::with_capacity(positions.len() * 6);
+    for (pos, norm) in positions.iter().zip(&normals) {
+        let pos: [f32; 3] = (*pos).into();
+        let norm: [f32; 3] = (*norm).into();
+        vertices_data.extend_from_slice(&pos);
+        vertices_data.extend_from_slice(&norm);
+    }
+
+    MeshData {
+        vertices: vertices_data,
+        indices: indices_data,
+        aabb,
+        is_double_sided,
+    }
+}
@@ -0,0 +1,315 @@
+use crate::*;
+use glam::Vec3;
+use std::convert::TryFrom;
+
+const MESH_CACHE_DIR: &str = "_cache/meshes";
+const MAGIC: [u8; 4] = *b"GMSH";
+// Bump when the layout or the import changes, so that old caches are re-imported
+const FORMAT_VERSION: u32 = 1;
+const HEADER_SIZE: usize = 68;
+const FLAG_DOUBLE_SIDED: u32 = 1;
+
+/// Mesh data as it's uploaded. Vertices are interleaved positions and normals.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshData {
+    pub vertices: Vec<f32>,
+    pub indices: Vec<u32>,
+    pub aabb: Aabb,
+    pub is_double_sided: bool,
+}
+
+/* Imported meshes, stored under `_cache/meshes` so that later startups skip
+parsing the source. A cache file is named after a hash of the source file's
+contents and the import settings, so editing either one misses the cache.
+
+All values are little-endian. The 68-byte header is:
+    0  magic "GMSH"
+    4  u32 format version
+    8  u64 source hash
+   16  u32 flags, bit 0 set if double-sided
+   20  6 x f32 bounds, min then max
+   44  u64 number of vertex floats
+   52  u64 number of indices
+   60  u64 FNV-1a checksum of the payload
+followed by the payload: the vertex floats, then the u32 indices.
+
+A file with the wrong version or source hash, or whose checksum doesn't
+match, is treated like a missing one, and overwritten after re-importing. */
+pub fn mesh_cache_path(source_hash: u64) -> String {
+    format!("{}/{:016x}.mesh", MESH_CACHE_DIR, source_hash)
+}
+
+/// Hash of what an import depends on. None if the source can't be read.
+pub fn mesh_source_hash(path: &str, settings: &ImportSettings) -> Option<u64> {
+    let mut bytes = std::fs::read(path).ok()?;
+    let up_axis: u8 = match settings.up_axis {
+        UpAxis::Y => 0,
+        UpAxis::Z => 1,
+    };
+    let handedness: u8 = match settings.handedness {
+        Handedness::Right => 0,
+        Handedness::Left => 1,
+    };
+    bytes.push(up_axis);
+    bytes.push(handedness);
+    bytes.extend_from_slice(&settings.unit_scale.to_le_bytes());
+    Some(fnv1a(&bytes))
+}
+
+// Stable across runs and compiler versions, unlike `DefaultHasher`
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+pub fn encode_mesh_cache(mesh_data: &MeshData, source_hash: u64) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(4 * (mesh_data.vertices.len() + mesh_data.indices.len()));
+    for value in &mesh_data.vertices {
+        payload.extend_from_slice(&value.to_le_bytes());
+    }
+    for idx in &mesh_data.indices {
+        payload.extend_from_slice(&idx.to_le_bytes());
+    }
+
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + payload.len());
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&source_hash.to_le_bytes());
+    let flags = if mesh_data.is_double_sided {
+        FLAG_DOUBLE_SIDED
+    } else {
+        0
+    };
+    bytes.extend_from_slice(&flags.to_le_bytes());
+    let bounds: [f32; 6] = [
+        mesh_data.aabb.min.x(),
+        mesh_data.aabb.min.y(),
+        mesh_data.aabb.min.z(),
+        mesh_data.aabb.max.x(),
+        mesh_data.aabb.max.y(),
+        mesh_data.aabb.max.z(),
+    ];
+    for value in &bounds {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes.extend_from_slice(&(mesh_data.vertices.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&(mesh_data.indices.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&fnv1a(&payload).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    bytes
+}
+
+/// None if the data isn't a valid cache of the source with `source_hash`.
+pub fn decode_mesh_cache(bytes: &[u8], source_hash: u64) -> Option<MeshData> {
+    if bytes.len() < HEADER_SIZE || bytes[0..4] != MAGIC {
+        return None;
+    }
+    let read_u32 = |offset: usize| {
+        let mut value = [0_u8; 4];
+        value.copy_from_slice(&bytes[offset..offset + 4]);
+        u32::from_le_bytes(value)
+    };
+    let read_u64 = |offset: usize| {
+        let mut value = [0_u8; 8];
+        value.copy_from_slice(&bytes[offset..offset + 8]);
+        u64::from_le_bytes(value)
+    };
+    let read_f32 = |offset: usize| f32::from_bits(read_u32(offset));
+    if read_u32(4) != FORMAT_VERSION || read_u64(8) != source_hash {
+        return None;
+    }
+
+    let num_vertex_floats = usize::try_from(read_u64(44)).ok()?;
+    let num_indices = usize::try_from(read_u64(52)).ok()?;
+    let payload_size = num_vertex_floats.checked_add(num_indices)?.checked_mul(4)?;
+    let payload = &bytes[HEADER_SIZE..];
+    if payload.len() != payload_size || fnv1a(payload) != read_u64(60) {
+        return None;
+    }
+
+    let words = payload.chunks_exact(4).map(|chunk| {
+        let mut value = [0_u8; 4];
+        value.copy_from_slice(chunk);
+        u32::from_le_bytes(value)
+    });
+    let vertices = words
+        .clone()
+        .take(num_vertex_floats)
+        .map(f32::from_bits)
+        .collect();
+    let indices = words.skip(num_vertex_floats).collect();
+    Some(MeshData {
+        vertices,
+        indices,
+        aabb: Aabb {
+            min: Vec3::new(read_f32(20), read_f32(24), read_f32(28)),
+            max: Vec3::new(read_f32(32), read_f32(36), read_f32(40)),
+        },
+        is_double_sided: read_u32(16) & FLAG_DOUBLE_SIDED != 0,
+    })
+}
+
+pub fn read_mesh_cache(source_hash: u64) -> Option<MeshData> {
+    let bytes = std::fs::read(mesh_cache_path(source_hash)).ok()?;
+    decode_mesh_cache(&bytes, source_hash)
+}
+
+/// Best-effort. Failing to write only makes the next load slower.
+pub fn write_mesh_cache(mesh_data: &MeshData, source_hash: u64) {
+    let path = mesh_cache_path(source_hash);
+    // Write to a temporary file first, so that a crash can't leave a truncated cache
+    let temp_path = format!("{}.tmp", path);
+    let is_saved = std::fs::create_dir_all(MESH_CACHE_DIR)
+        .and_then(|_| std::fs::write(&temp_path, encode_mesh_cache(mesh_data, source_hash)))
+        .and_then(|_| std::fs::rename(&temp_path, &path))
+        .is_ok();
+    if !is_saved {
+        println!("Failed to save mesh cache to `{}`.", path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE_HASH: u64 = 0x0123_4567_89ab_cdef;
+
+    // Values that only survive if they are stored bit for bit
+    fn test_mesh_data() -> MeshData {
+        MeshData {
+            vertices: vec![
+                0.0,
+                -0.0,
+                1.5,
+                -2.25,
+                f32::MIN_POSITIVE / 2.0, // Subnormal
+                f32::MAX,
+                f32::from_bits(0x7fc0_1234), // NaN with a payload
+                f32::INFINITY,
+                3.0,
+                4.0,
+                5.0,
+                6.0,
+            ],
+            indices: vec![0, 1, 0, u32::MAX, 7],
+            aabb: Aabb {
+                min: Vec3::new(-1.0, -0.0, f32::MIN),
+                max: Vec3::new(1.0, 2.0, 3.0),
+            },
+            is_double_sided: true,
+        }
+    }
+
+    fn vertex_bits(mesh_data: &MeshData) -> Vec<u32> {
+        mesh_data
+            .vertices
+            .iter()
+            .map(|value| value.to_bits())
+            .collect()
+    }
+
+    fn aabb_bits(aabb: &Aabb) -> [u32; 6] {
+        [
+            aabb.min.x().to_bits(),
+            aabb.min.y().to_bits(),
+            aabb.min.z().to_bits(),
+            aabb.max.x().to_bits(),
+            aabb.max.y().to_bits(),
+            aabb.max.z().to_bits(),
+        ]
+    }
+
+    #[test]
+    fn round_trip_is_bit_exact() {
+        let mesh_data = test_mesh_data();
+        let bytes = encode_mesh_cache(&mesh_data, SOURCE_HASH);
+        assert_eq!(
+            bytes.len(),
+            HEADER_SIZE + 4 * (mesh_data.vertices.len() + mesh_data.indices.len())
+        );
+        let decoded = decode_mesh_cache(&bytes, SOURCE_HASH).unwrap();
+        assert_eq!(vertex_bits(&decoded), vertex_bits(&mesh_data));
+        assert_eq!(decoded.indices, mesh_data.indices);
+        assert_eq!(aabb_bits(&decoded.aabb), aabb_bits(&mesh_data.aabb));
+        assert!(decoded.is_double_sided);
+        // Encoding again gives the same file
+        assert_eq!(encode_mesh_cache(&decoded, SOURCE_HASH), bytes);
+    }
+
+    #[test]
+    fn round_trip_keeps_empty_single_sided_meshes() {
+        let mesh_data = MeshData {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            aabb: Aabb::empty(),
+            is_double_sided: false,
+        };
+        let bytes = encode_mesh_cache(&mesh_data, SOURCE_HASH);
+        assert_eq!(bytes.len(), HEADER_SIZE);
+        assert_eq!(decode_mesh_cache(&bytes, SOURCE_HASH), Some(mesh_data));
+    }
+
+    #[test]
+    fn corrupted_payloads_and_checksums_are_rejected() {
+        let bytes = encode_mesh_cache(&test_mesh_data(), SOURCE_HASH);
+        for offset in &[HEADER_SIZE, bytes.len() - 1, 60, 67] {
+            let mut corrupted = bytes.clone();
+            corrupted[*offset] ^= 0x10;
+            assert_eq!(
+                decode_mesh_cache(&corrupted, SOURCE_HASH),
+                None,
+                "{}",
+                offset
+            );
+        }
+    }
+
+    #[test]
+    fn other_versions_and_sources_are_rejected() {
+        let bytes = encode_mesh_cache(&test_mesh_data(), SOURCE_HASH);
+        let mut other_version = bytes.clone();
+        other_version[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(decode_mesh_cache(&other_version, SOURCE_HASH), None);
+        assert_eq!(decode_mesh_cache(&bytes, SOURCE_HASH + 1), None);
+        let mut other_magic = bytes;
+        other_magic[0] = b'X';
+        assert_eq!(decode_mesh_cache(&other_magic, SOURCE_HASH), None);
+    }
+
+    #[test]
+    fn truncated_and_padded_files_are_rejected() {
+        let bytes = encode_mesh_cache(&test_mesh_data(), SOURCE_HASH);
+        for len in &[
+            0,
+            3,
+            HEADER_SIZE - 1,
+            HEADER_SIZE,
+            bytes.len() - 4,
+            bytes.len() - 1,
+        ] {
+            assert_eq!(
+                decode_mesh_cache(&bytes[..*len], SOURCE_HASH),
+                None,
+                "{}",
+                len
+            );
+        }
+        let mut padded = bytes;
+        padded.extend_from_slice(&[0; 4]);
+        assert_eq!(decode_mesh_cache(&padded, SOURCE_HASH), None);
+    }
+
+    #[test]
+    fn counts_that_overflow_are_rejected() {
+        let mut bytes = encode_mesh_cache(&test_mesh_data(), SOURCE_HASH);
+        bytes[44..52].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(decode_mesh_cache(&bytes, SOURCE_HASH), None);
+        bytes[44..52].copy_from_slice(&(u64::MAX / 4).to_le_bytes());
+        bytes[52..60].copy_from_slice(&(u64::MAX / 4).to_le_bytes());
+        assert_eq!(decode_mesh_cache(&bytes, SOURCE_HASH), None);
+    }
+}
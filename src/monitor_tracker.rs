@@ -0,0 +1,91 @@
+use std::time::{Duration, Instant};
+use winit::window::Window;
+
+// Moved events arrive continuously while the window is dragged. Wait for them
+// to settle before checking which monitor the window ended up on.
+const MOVE_DEBOUNCE_DURATION: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MonitorInfo {
+    pub name: String,
+    pub width: u32, // In physical pixels
+    pub height: u32,
+    pub scale_factor: f64,
+    /* winit doesn't report the active video mode, so this is the highest rate
+    among the modes with the monitor's current resolution. None if the
+    platform doesn't list video modes. */
+    pub opt_refresh_rate: Option<u16>,
+}
+
+impl MonitorInfo {
+    pub fn of_window(window: &Window) -> MonitorInfo {
+        let monitor = window.current_monitor();
+        let size = monitor.size();
+        let opt_refresh_rate = monitor
+            .video_modes()
+            .filter(|mode| mode.size() == size)
+            .map(|mode| mode.refresh_rate())
+            .max();
+        MonitorInfo {
+            name: monitor.name().unwrap_or_else(|| String::from("Unknown")),
+            width: size.width,
+            height: size.height,
+            scale_factor: monitor.scale_factor(),
+            opt_refresh_rate,
+        }
+    }
+}
+
+/// Keeps track of the monitor that the window is on.
+pub struct MonitorTracker {
+    pub current: MonitorInfo,
+    pub was_changed: bool, // True for the frame on which the window moved to another monitor
+    opt_last_move_instant: Option<Instant>,
+}
+
+impl MonitorTracker {
+    pub fn new(window: &Window) -> MonitorTracker {
+        let current = MonitorInfo::of_window(window);
+        println!("Window is on monitor {}", describe(&current));
+        MonitorTracker {
+            current,
+            was_changed: false,
+            opt_last_move_instant: None,
+        }
+    }
+
+    pub fn on_moved(&mut self) {
+        self.opt_last_move_instant = Some(Instant::now());
+    }
+
+    /// Call once per frame, after the events have been handled.
+    pub fn update(&mut self, window: &Window) {
+        self.was_changed = false;
+        if let Some(last_move_instant) = self.opt_last_move_instant {
+            if last_move_instant.elapsed() < MOVE_DEBOUNCE_DURATION {
+                return;
+            }
+            self.opt_last_move_instant = None;
+            let monitor = MonitorInfo::of_window(window);
+            if monitor != self.current {
+                println!("Window moved to monitor {}", describe(&monitor));
+                self.current = monitor;
+                self.was_changed = true;
+            }
+        }
+    }
+}
+
+fn describe(monitor: &MonitorInfo) -> String {
+    format!(
+        "`{}` ({}x{}, scale {}, {})",
+        monitor.name,
+        monitor.width,
+        monitor.height,
+        monitor.scale_factor,
+        match monitor.opt_refresh_rate {
+            Some(refresh_rate) => format!("{} Hz", refresh_rate),
+            None => String::from("unknown refresh rate"),
+        }
+    )
+}
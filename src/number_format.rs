@@ -0,0 +1,151 @@
+use std::fmt::Write;
+
+/* Writers for the numbers in reports and stats printouts. They append to a
+`String` that the caller reuses, so once it has grown to fit, formatting
+doesn't allocate. `width` right-aligns the number to that many characters, so
+that columns stay put as values change magnitude. Pass 0 to not pad. The
+output doesn't depend on the locale. */
+
+/// Milliseconds with two decimals, e.g. "16.67 ms". Negative deltas keep
+/// their sign, and NaN and infinities are written as "--".
+pub fn write_ms(out: &mut String, ms: f32, width: usize) {
+    if !ms.is_finite() {
+        write_padded(out, "--", width);
+    } else {
+        // Writing to a `String` can't fail
+        let _ = write!(out, "{:>width$.2}", ms, width = width);
+    }
+    out.push_str(" ms");
+}
+
+/// Bytes in binary units, e.g. "512 B", "1.0 KiB" or "11.8 MiB".
+pub fn write_bytes(out: &mut String, bytes: u64, width: usize) {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        let _ = write!(out, "{:>width$} B", bytes, width = width);
+        return;
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit_idx = 0;
+    // Switch units before rounding would print "1024.0"
+    while value >= 1023.95 && unit_idx + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit_idx += 1;
+    }
+    let _ = write!(
+        out,
+        "{:>width$.1} {}",
+        value,
+        UNITS[unit_idx],
+        width = width
+    );
+}
+
+/// Counts with thousands separators, e.g. "12,345,678".
+pub fn write_count(out: &mut String, count: u64, width: usize) {
+    // u64::MAX has 20 digits and 6 separators
+    let mut digits = [0u8; 26];
+    let mut len = 0;
+    let mut rest = count;
+    loop {
+        if len % 4 == 3 {
+            digits[len] = b',';
+            len += 1;
+        }
+        digits[len] = b'0' + (rest % 10) as u8;
+        len += 1;
+        rest /= 10;
+        if rest == 0 {
+            break;
+        }
+    }
+    for _ in len..width {
+        out.push(' ');
+    }
+    for c in digits[..len].iter().rev() {
+        out.push(*c as char);
+    }
+}
+
+fn write_padded(out: &mut String, s: &str, width: usize) {
+    for _ in s.len()..width {
+        out.push(' ');
+    }
+    out.push_str(s);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn formatted(write: impl Fn(&mut String)) -> String {
+        let mut out = String::new();
+        write(&mut out);
+        out
+    }
+
+    #[test]
+    fn milliseconds() {
+        let ms = |ms, width| formatted(|out| write_ms(out, ms, width));
+        assert_eq!(ms(0.0, 0), "0.00 ms");
+        assert_eq!(ms(16.666, 0), "16.67 ms");
+        assert_eq!(ms(999.999, 0), "1000.00 ms");
+        assert_eq!(ms(-1.5, 0), "-1.50 ms");
+        assert_eq!(ms(1.0e9, 0), "1000000000.00 ms");
+        assert_eq!(ms(1.5, 8), "    1.50 ms");
+        assert_eq!(ms(f32::NAN, 0), "-- ms");
+        assert_eq!(ms(f32::INFINITY, 4), "  -- ms");
+    }
+
+    #[test]
+    fn bytes_at_unit_boundaries() {
+        let bytes = |bytes| formatted(|out| write_bytes(out, bytes, 0));
+        assert_eq!(bytes(0), "0 B");
+        assert_eq!(bytes(999), "999 B");
+        assert_eq!(bytes(1000), "1000 B");
+        assert_eq!(bytes(1023), "1023 B");
+        assert_eq!(bytes(1024), "1.0 KiB");
+        assert_eq!(bytes(1536), "1.5 KiB");
+        assert_eq!(bytes(1023 * 1024), "1023.0 KiB");
+        // The last values that round below 1024.0 KiB, and the first that don't
+        assert_eq!(bytes(1_048_524), "1023.9 KiB");
+        assert_eq!(bytes(1_048_525), "1.0 MiB");
+        assert_eq!(bytes(1024 * 1024), "1.0 MiB");
+        assert_eq!(bytes(12_373_196), "11.8 MiB");
+        assert_eq!(bytes(1 << 30), "1.0 GiB");
+        assert_eq!(bytes(1 << 40), "1.0 TiB");
+        // There is no unit past PiB
+        assert_eq!(bytes(u64::MAX), "16384.0 PiB");
+    }
+
+    #[test]
+    fn bytes_are_padded_to_the_width() {
+        assert_eq!(formatted(|out| write_bytes(out, 5, 4)), "   5 B");
+        assert_eq!(formatted(|out| write_bytes(out, 2048, 6)), "   2.0 KiB");
+        assert_eq!(formatted(|out| write_bytes(out, 2048, 1)), "2.0 KiB");
+    }
+
+    #[test]
+    fn counts_at_separator_boundaries() {
+        let count = |count, width| formatted(|out| write_count(out, count, width));
+        assert_eq!(count(0, 0), "0");
+        assert_eq!(count(999, 0), "999");
+        assert_eq!(count(1000, 0), "1,000");
+        assert_eq!(count(999_999, 0), "999,999");
+        assert_eq!(count(1_000_000, 0), "1,000,000");
+        assert_eq!(count(u64::MAX, 0), "18,446,744,073,709,551,615");
+        assert_eq!(count(1000, 7), "  1,000");
+        assert_eq!(count(1000, 3), "1,000");
+    }
+
+    #[test]
+    fn writers_append() {
+        let mut out = String::from("Draws: ");
+        write_count(&mut out, 12, 0);
+        out.push_str(", uploads: ");
+        write_bytes(&mut out, 512, 0);
+        out.push_str(", CPU: ");
+        write_ms(&mut out, 2.0, 0);
+        assert_eq!(out, "Draws: 12, uploads: 512 B, CPU: 2.00 ms");
+    }
+}
@@ -0,0 +1,542 @@
+use crate::*;
+use glam::*;
+use std::rc::Rc;
+
+const DEPTH_PYRAMID_GROUP_SIZE: u32 = 8; // The local size in depth_pyramid.comp
+const CULL_GROUP_SIZE: u32 = 64; // The local size in occlusion_cull.comp
+                                 // Moving or turning the camera further than this in one frame is a cut
+const DEFAULT_CUT_DISTANCE: f32 = 2.0;
+const DEFAULT_CUT_ANGLE: f32 = 0.5; // In radians, about 30 degrees
+
+/// What the culling of a frame kept and removed. Laid out like the counters
+/// in occlusion_cull.comp.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CullingStats {
+    pub num_visible: u32,
+    pub num_outside_frustum: u32,
+    pub num_occluded: u32, // Zero in frames that skip the occlusion test
+}
+
+#[allow(dead_code)]
+struct CullPushConstants {
+    // Matches the layout that occlusion_cull.comp declares
+    mtx_world_to_clip: Mat4,
+    num_objects: u32,
+    is_compacted: u32,
+    is_forced_visible: u32,
+}
+
+/* Hierarchical-Z occlusion culling of indexed draws. Every frame, `cull()`
+tests the bounding sphere of each draw against the frustum, and against a
+depth pyramid: a mip chain of the depth image, where each texel holds the
+farthest depth of the texels it covers in the mip above. A sphere whose
+nearest depth is behind the farthest depth of the texels under it is hidden.
+The results are written into indirect draw arguments on the GPU, which
+`draw()` then draws from. After the pass that draws them renders the depth
+image, `build_depth_pyramid()` reduces it for the next frame.
+
+Since the pyramid is one frame old, objects that come into view are drawn a
+frame late, which isn't noticeable while the camera moves smoothly. After a
+cut, the old depth has nothing to do with the new view, so the occlusion test
+is skipped for a frame rather than hiding what is suddenly in front of the
+camera. Cuts are detected from the camera moving or turning further than
+`cut_distance` or `cut_angle` in one frame, or reported with
+`force_visible()`.
+
+With VK_KHR_draw_indirect_count and multi-draw, the visible draws are
+compacted and drawn with a count that the GPU wrote. Otherwise every draw is
+issued, and culled ones have no instances. */
+pub struct OcclusionCuller {
+    pub name: String,
+    pub num_objects: usize,
+    pub depth_image: ImageHandle, // Rendered by the pass that draws the objects
+    pub pyramid_image: ImageHandle, // Sized and recreated like the depth image
+    pub is_compacted: bool,
+    pub is_enabled: bool,            // False to only cull by the frustum
+    pub is_cut_forced_visible: bool, // False to keep testing against the old depth after cuts, which pops
+    pub cut_distance: f32,
+    pub cut_angle: f32,                   // In radians
+    pub last_stats: CullingStats,         // Of the latest frame that the GPU finished
+    pub num_forced_visible_frames: usize, // Culled without the occlusion test, while enabled
+    opt_last_camera: Option<Camera>,
+    is_next_cull_forced_visible: bool,    // Set by force_visible()
+    opt_built_pyramid: Option<vk::Image>, // The image that the pyramid was last built in
+    shader_depth_pyramid: ShaderHandle,
+    shader_cull: ShaderHandle,
+    sampler: Rc<Sampler>,
+    spheres_buffer: DeviceLocalBuffer, // Center and radius of each object
+    commands_buffer: DeviceLocalBuffer, // The draws of all objects
+    draws_buffer: DeviceLocalBuffer,   // The draws that pass the culling
+    counts_buffer: DeviceLocalBuffer,  // CullingStats, which start with the draw count
+    stats_buffers: Vec<(TypedBuffer<CullingStats>, bool)>, // Per frame in flight, and whether it was written
+}
+
+impl OcclusionCuller {
+    /* `bounding_spheres` are world-space centers and radii, one for each of
+    `commands`. The depth image must have been created with `SAMPLED` usage,
+    and a format without stencil, so that it can be reduced. */
+    pub fn new(
+        ctx: &mut Context,
+        name: &str,
+        depth_image: ImageHandle,
+        bounding_spheres: &[(Vec3, f32)],
+        commands: &[DrawIndexedIndirectCommand],
+    ) -> Result<OcclusionCuller, String> {
+        if commands.is_empty() || bounding_spheres.len() != commands.len() {
+            return Err(format!(
+                "Occlusion culler `{}` has {} draws and {} bounding spheres. It needs one sphere for each draw, and at least one draw.",
+                name,
+                commands.len(),
+                bounding_spheres.len()
+            ));
+        }
+        let pyramid_size = {
+            let internal_image = ctx.image_list.get_image(depth_image)?;
+            let image = &internal_image.image;
+            if image.aspect_flags != vk::ImageAspectFlags::DEPTH
+                || !image.usage.contains(vk::ImageUsageFlags::SAMPLED)
+            {
+                return Err(format!(
+                    "Occlusion culler `{}` reduces image `{}`, which needs a depth format without stencil and `ImageUsage::SAMPLED`.",
+                    name, image.name
+                ));
+            }
+            match internal_image.kind {
+                ImageKind::RelativeSized { scale } => ImageSize::SwapchainRelative(scale),
+                _ => ImageSize::Absolute(Extent::new_2d(image.width, image.height)),
+            }
+        };
+        let shader_depth_pyramid = ctx.new_shader(
+            &format!("{}_depth_pyramid", name),
+            ShaderStage::Compute,
+            "depth_pyramid.comp",
+        )?;
+        let shader_cull = ctx.new_shader(
+            &format!("{}_cull", name),
+            ShaderStage::Compute,
+            "occlusion_cull.comp",
+        )?;
+        let num_pyramid_mips = {
+            let image = &ctx
+                .image_list
+                .get_image_from_handle(depth_image)
+                .unwrap()
+                .image;
+            num_mips_for_size(image.width, image.height)
+        };
+        let pyramid_image = ctx.image_list.new_image(
+            &format!("{}_depth_pyramid", name),
+            pyramid_size,
+            num_pyramid_mips,
+            SampleCount::X1,
+            vk::Format::R32_SFLOAT,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+            vk::ImageAspectFlags::COLOR,
+            &ctx.facade,
+            &ctx.basis,
+            &ctx.gpu,
+            &ctx.debug_utils,
+        )?;
+        // The shaders only fetch texels, so the filters don't matter
+        let sampler = ctx.sampler(&SamplerDesc {
+            mag_filter: Filter::Nearest,
+            min_filter: Filter::Nearest,
+            mip_filter: Filter::Nearest,
+            address_mode: AddressMode::ClampToEdge,
+            anisotropy: AnisotropyLevel::Off,
+            ..Default::default()
+        });
+
+        let spheres: Vec<[f32; 4]> = bounding_spheres
+            .iter()
+            .map(|(center, radius)| [center.x(), center.y(), center.z(), *radius])
+            .collect();
+        let spheres_buffer = DeviceLocalBuffer::new(
+            &format!("{}_spheres", name),
+            &spheres,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            &ctx.gpu,
+            ctx.command_pool,
+            &ctx.debug_utils,
+        );
+        let commands_buffer = DeviceLocalBuffer::new(
+            &format!("{}_commands", name),
+            commands,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            &ctx.gpu,
+            ctx.command_pool,
+            &ctx.debug_utils,
+        );
+        // Everything is drawn until the first cull
+        let draws_buffer = DeviceLocalBuffer::new(
+            &format!("{}_draws", name),
+            commands,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::INDIRECT_BUFFER,
+            &ctx.gpu,
+            ctx.command_pool,
+            &ctx.debug_utils,
+        );
+        let counts_buffer = DeviceLocalBuffer::new(
+            &format!("{}_counts", name),
+            &[CullingStats::default()],
+            vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::INDIRECT_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_SRC
+                | vk::BufferUsageFlags::TRANSFER_DST,
+            &ctx.gpu,
+            ctx.command_pool,
+            &ctx.debug_utils,
+        );
+        let is_compacted = ctx.gpu.opt_draw_indirect_count_fn.is_some()
+            && commands.len() <= ctx.gpu.max_indirect_draws() as usize;
+
+        Ok(OcclusionCuller {
+            name: String::from(name),
+            num_objects: commands.len(),
+            depth_image,
+            pyramid_image,
+            is_compacted,
+            is_enabled: true,
+            is_cut_forced_visible: true,
+            cut_distance: DEFAULT_CUT_DISTANCE,
+            cut_angle: DEFAULT_CUT_ANGLE,
+            last_stats: CullingStats::default(),
+            num_forced_visible_frames: 0,
+            opt_last_camera: None,
+            is_next_cull_forced_visible: false,
+            opt_built_pyramid: None,
+            shader_depth_pyramid,
+            shader_cull,
+            sampler,
+            spheres_buffer,
+            commands_buffer,
+            draws_buffer,
+            counts_buffer,
+            stats_buffers: Vec::new(),
+        })
+    }
+
+    /// Skip the occlusion test in the next `cull()`, e.g. after teleporting
+    /// the camera in a way that the cut detection can't see.
+    pub fn force_visible(&mut self) {
+        self.is_next_cull_forced_visible = true;
+    }
+
+    /* Cull the draws for the camera, between `begin_frame()` and the pass
+    that draws them, outside of any pass. The camera must be the one that
+    the pass renders with, at the aspect ratio of the depth image. */
+    pub fn cull(&mut self, ctx: &mut Context, camera: &Camera) {
+        self.read_back_stats(ctx);
+
+        let command_buffer = ctx.command_buffers[ctx.swapchain_idx];
+        let (pyramid_vk_image, pyramid_view, aspect_ratio) = {
+            let pyramid = &ctx
+                .image_list
+                .get_image_from_handle(self.pyramid_image)
+                .unwrap()
+                .image;
+            // Also when it wasn't built, so that the descriptor's layout is right
+            pyramid.transition(command_buffer, vk::ImageLayout::GENERAL);
+            (
+                pyramid.vk_image,
+                pyramid.image_view,
+                pyramid.width as f32 / pyramid.height as f32,
+            )
+        };
+        let is_cut = match &self.opt_last_camera {
+            Some(last_camera) => {
+                let distance = (camera.position - last_camera.position).length();
+                let cos_angle = camera.forward().dot(last_camera.forward());
+                distance > self.cut_distance || cos_angle < self.cut_angle.cos()
+            }
+            None => true,
+        };
+        // The pyramid is gone after the images are recreated, e.g. on resize
+        let is_pyramid_built = self.opt_built_pyramid == Some(pyramid_vk_image);
+        let is_forced_visible = self.is_next_cull_forced_visible
+            || !is_pyramid_built
+            || (is_cut && self.is_cut_forced_visible);
+        if self.is_enabled && is_forced_visible {
+            self.num_forced_visible_frames += 1;
+        }
+        self.opt_last_camera = Some(*camera);
+        self.is_next_cull_forced_visible = false;
+
+        let device = &ctx.gpu.device;
+        unsafe {
+            // Earlier draws and copies read the buffers that are about to be written
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[buffer_barrier(
+                    self.counts_buffer.vk_buffer,
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::TRANSFER_WRITE,
+                )],
+                &[],
+            );
+            device.cmd_fill_buffer(
+                command_buffer,
+                self.counts_buffer.vk_buffer,
+                0,
+                vk::WHOLE_SIZE,
+                0,
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[
+                    buffer_barrier(
+                        self.counts_buffer.vk_buffer,
+                        vk::AccessFlags::TRANSFER_WRITE,
+                        vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                    ),
+                    buffer_barrier(
+                        self.draws_buffer.vk_buffer,
+                        vk::AccessFlags::empty(),
+                        vk::AccessFlags::SHADER_WRITE,
+                    ),
+                ],
+                &[],
+            );
+        }
+
+        let push_constants = [CullPushConstants {
+            mtx_world_to_clip: camera.view_to_clip(aspect_ratio) * camera.world_to_view(),
+            num_objects: self.num_objects as u32,
+            is_compacted: self.is_compacted as u32,
+            is_forced_visible: (is_forced_visible || !self.is_enabled) as u32,
+        }];
+        let bindings = [
+            ComputeBinding::SampledImage(
+                pyramid_view,
+                self.sampler.vk_sampler,
+                vk::ImageLayout::GENERAL,
+            ),
+            ComputeBinding::StorageBuffer(self.spheres_buffer.vk_buffer),
+            ComputeBinding::StorageBuffer(self.commands_buffer.vk_buffer),
+            ComputeBinding::StorageBuffer(self.draws_buffer.vk_buffer),
+            ComputeBinding::StorageBuffer(self.counts_buffer.vk_buffer),
+        ];
+        let num_groups = (self.num_objects as u32).div_ceil(CULL_GROUP_SIZE);
+        ctx.dispatch_compute_with_bindings(
+            self.shader_cull,
+            &bindings,
+            (num_groups, 1, 1),
+            &push_constants,
+        );
+
+        // Keep the counts of this frame, to read them once its fence signals
+        if self.stats_buffers.len() != ctx.facade.num_frames {
+            self.stats_buffers = (0..ctx.facade.num_frames)
+                .map(|frame_idx| {
+                    let buffer = TypedBuffer::new_host_cached(
+                        &format!("{}_stats_{}", self.name, frame_idx),
+                        1,
+                        vk::BufferUsageFlags::TRANSFER_DST,
+                        &ctx.gpu,
+                        &ctx.debug_utils,
+                    );
+                    (buffer, false)
+                })
+                .collect();
+        }
+        let (stats_buffer, is_written) = &mut self.stats_buffers[ctx.sync_idx];
+        let device = &ctx.gpu.device;
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[
+                    buffer_barrier(
+                        self.counts_buffer.vk_buffer,
+                        vk::AccessFlags::SHADER_WRITE,
+                        vk::AccessFlags::INDIRECT_COMMAND_READ | vk::AccessFlags::TRANSFER_READ,
+                    ),
+                    buffer_barrier(
+                        self.draws_buffer.vk_buffer,
+                        vk::AccessFlags::SHADER_WRITE,
+                        vk::AccessFlags::INDIRECT_COMMAND_READ,
+                    ),
+                ],
+                &[],
+            );
+            device.cmd_copy_buffer(
+                command_buffer,
+                self.counts_buffer.vk_buffer,
+                stats_buffer.vk_buffer(),
+                &[vk::BufferCopy {
+                    src_offset: 0,
+                    dst_offset: 0,
+                    size: std::mem::size_of::<CullingStats>() as u64,
+                }],
+            );
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[buffer_barrier(
+                    stats_buffer.vk_buffer(),
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::HOST_READ,
+                )],
+                &[],
+            );
+        }
+        *is_written = true;
+    }
+
+    /// Draw the objects that passed the last `cull()`, with the vertex and
+    /// index buffers that the caller bound.
+    pub fn draw(&self, recorder: &PassRecorder) {
+        if self.is_compacted {
+            recorder.draw_indexed_indirect_count(
+                &self.draws_buffer,
+                0,
+                &self.counts_buffer,
+                0,
+                self.num_objects as u32,
+            );
+        } else {
+            let max_draws = recorder.context().gpu.max_indirect_draws() as usize;
+            let stride = std::mem::size_of::<DrawIndexedIndirectCommand>();
+            for first_draw in (0..self.num_objects).step_by(max_draws) {
+                let num_draws = (self.num_objects - first_draw).min(max_draws);
+                recorder.draw_indexed_indirect(
+                    &self.draws_buffer,
+                    first_draw * stride,
+                    num_draws as u32,
+                );
+            }
+        }
+    }
+
+    /* Reduce the depth image into the pyramid, for the next frame's cull.
+    Call it after the pass that draws the objects, outside of any pass. */
+    pub fn build_depth_pyramid(&mut self, ctx: &mut Context) {
+        let command_buffer = ctx.command_buffers[ctx.swapchain_idx];
+        let depth_view = {
+            let depth = &ctx
+                .image_list
+                .get_image_from_handle(self.depth_image)
+                .unwrap()
+                .image;
+            depth.transition(command_buffer, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+            depth.image_view
+        };
+        let (pyramid_vk_image, pyramid_view, mips) = {
+            let pyramid = &ctx
+                .image_list
+                .get_image_from_handle(self.pyramid_image)
+                .unwrap()
+                .image;
+            pyramid.transition(command_buffer, vk::ImageLayout::GENERAL);
+            let mips: Vec<(vk::ImageView, u32, u32)> = (0..pyramid.num_mips)
+                .map(|mip| {
+                    let view = pyramid
+                        .create_view(mip, 1, 0, 1, vk::ImageViewType::TYPE_2D)
+                        .unwrap_or_else(|e| panic!("{}", e));
+                    (
+                        view,
+                        (pyramid.width >> mip).max(1),
+                        (pyramid.height >> mip).max(1),
+                    )
+                })
+                .collect();
+            (pyramid.vk_image, pyramid.image_view, mips)
+        };
+
+        for (mip, (mip_view, width, height)) in mips.into_iter().enumerate() {
+            let source = if mip == 0 {
+                ComputeBinding::SampledImage(
+                    depth_view,
+                    self.sampler.vk_sampler,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                )
+            } else {
+                ComputeBinding::SampledImage(
+                    pyramid_view,
+                    self.sampler.vk_sampler,
+                    vk::ImageLayout::GENERAL,
+                )
+            };
+            let num_groups = (
+                width.div_ceil(DEPTH_PYRAMID_GROUP_SIZE),
+                height.div_ceil(DEPTH_PYRAMID_GROUP_SIZE),
+                1,
+            );
+            ctx.dispatch_compute_with_bindings(
+                self.shader_depth_pyramid,
+                &[source, ComputeBinding::StorageImage(mip_view)],
+                num_groups,
+                &[mip as i32 - 1],
+            );
+            // The next mip, or the next frame's cull, reads this one
+            let memory_barriers = [vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .build()];
+            unsafe {
+                ctx.gpu.device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &memory_barriers,
+                    &[],
+                    &[],
+                );
+            }
+        }
+        // The reads must finish before the next frame renders depth again
+        let depth = &ctx
+            .image_list
+            .get_image_from_handle(self.depth_image)
+            .unwrap()
+            .image;
+        depth.transition(
+            command_buffer,
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        );
+        self.opt_built_pyramid = Some(pyramid_vk_image);
+    }
+
+    // The frame that used this sync index last has finished, once begin_frame() returns
+    fn read_back_stats(&mut self, ctx: &Context) {
+        if let Some((stats_buffer, true)) = self.stats_buffers.get(ctx.sync_idx) {
+            let mut stats = [CullingStats::default()];
+            stats_buffer
+                .download(&mut stats, 0)
+                .unwrap_or_else(|e| panic!("Failed to read back the culling stats: {}", e));
+            self.last_stats = stats[0];
+        }
+    }
+}
+
+fn buffer_barrier(
+    vk_buffer: vk::Buffer,
+    src_access_mask: vk::AccessFlags,
+    dst_access_mask: vk::AccessFlags,
+) -> vk::BufferMemoryBarrier {
+    vk::BufferMemoryBarrier::builder()
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(dst_access_mask)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .buffer(vk_buffer)
+        .offset(0)
+        .size(vk::WHOLE_SIZE)
+        .build()
+}
@@ -0,0 +1,94 @@
+use crate::*;
+
+const PIPELINE_CACHE_PATH: &str = "_cache/pipeline_cache.bin";
+// Size of the VK_PIPELINE_CACHE_HEADER_VERSION_ONE header
+const HEADER_SIZE: usize = 16 + vk::UUID_SIZE;
+
+/* The driver's pipeline cache, persisted across runs in the _cache directory
+so that warm startups hit the cache instead of recompiling pipelines. */
+pub struct PipelineCache {
+    pub vk_pipeline_cache: vk::PipelineCache,
+    device: ash::Device,
+}
+
+impl Drop for PipelineCache {
+    fn drop(&mut self) {
+        unsafe {
+            self.device
+                .destroy_pipeline_cache(self.vk_pipeline_cache, None);
+        }
+        audit_destroyed(VkObjectKind::PipelineCache, self.vk_pipeline_cache);
+    }
+}
+
+impl PipelineCache {
+    pub fn new(gpu: &Gpu) -> PipelineCache {
+        // Stale or corrupt data is discarded, and we start with an empty cache.
+        let initial_data = match std::fs::read(PIPELINE_CACHE_PATH) {
+            Ok(data) if is_header_valid(&data, &gpu.properties) => data,
+            Ok(_) => {
+                println!("Discarding pipeline cache made by a different GPU or driver.");
+                Vec::new()
+            }
+            Err(_) => Vec::new(),
+        };
+
+        let create_info = vk::PipelineCacheCreateInfo::builder().initial_data(&initial_data);
+        let vk_pipeline_cache = unsafe {
+            gpu.device
+                .create_pipeline_cache(&create_info, None)
+                .or_else(|_| {
+                    // Some drivers reject data they can't parse, even with a valid header.
+                    let create_info = vk::PipelineCacheCreateInfo::builder();
+                    gpu.device.create_pipeline_cache(&create_info, None)
+                })
+                .expect("Failed to create pipeline cache.")
+        };
+        audit_created(
+            VkObjectKind::PipelineCache,
+            vk_pipeline_cache,
+            &[device_id(&gpu.device)],
+            "pipeline_cache",
+        );
+
+        PipelineCache {
+            vk_pipeline_cache,
+            device: gpu.device.clone(),
+        }
+    }
+
+    /// Best-effort. Failing to save only makes the next startup slower.
+    pub fn save(&self) {
+        let data = unsafe { self.device.get_pipeline_cache_data(self.vk_pipeline_cache) };
+        if let Ok(data) = data {
+            // Write to a temporary file first, so that a crash can't leave a truncated cache
+            let temp_path = format!("{}.tmp", PIPELINE_CACHE_PATH);
+            let is_saved = std::fs::create_dir_all("_cache")
+                .and_then(|_| std::fs::write(&temp_path, &data))
+                .and_then(|_| std::fs::rename(&temp_path, PIPELINE_CACHE_PATH))
+                .is_ok();
+            if !is_saved {
+                println!(
+                    "Failed to save pipeline cache to `{}`.",
+                    PIPELINE_CACHE_PATH
+                );
+            }
+        }
+    }
+}
+
+fn is_header_valid(data: &[u8], properties: &vk::PhysicalDeviceProperties) -> bool {
+    if data.len() < HEADER_SIZE {
+        return false;
+    }
+    let read_u32 = |offset: usize| {
+        let mut bytes = [0_u8; 4];
+        bytes.copy_from_slice(&data[offset..offset + 4]);
+        u32::from_ne_bytes(bytes)
+    };
+    read_u32(0) as usize == HEADER_SIZE
+        && read_u32(4) == vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32
+        && read_u32(8) == properties.vendor_id
+        && read_u32(12) == properties.device_id
+        && data[16..HEADER_SIZE] == properties.pipeline_cache_uuid
+}
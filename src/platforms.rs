@@ -30,7 +30,7 @@ pub fn required_extension_names() -> Vec<*const i8> {
     ]
 }
 
-#[cfg(all(windows))]
+#[cfg(windows)]
 pub fn required_extension_names() -> Vec<*const i8> {
     vec![
         Surface::name().as_ptr(),
@@ -0,0 +1,49 @@
+/*! The supported public API, for `use graphene::prelude::*;`.
+
+Everything else that the crate root exports is reachable for now, but may
+change without notice. Names that are renamed stay available here for one
+release as deprecated aliases, with a note that points to the new name. */
+
+// Context and handles
+pub use crate::{
+    BufferHandle, CommandSender, Context, ContextCommand, GraphHandle, GraphStats, Handle,
+    ImageHandle, PassFlagHandle, PassHandle, ShaderHandle, SurfaceFormatPreference,
+    GRAPH_REPORT_PATH, RING_UNIFORM_BUFFER,
+};
+
+// Passes and resources
+pub use crate::{
+    AddressMode, AnisotropyLevel, BlendMode, BufferError, BufferUsage, BufferView, Color,
+    ColorSpace, CompareOp, DeviceLocalBuffer, DrawIndexedIndirectCommand, DrawIndirectCommand,
+    Extent, Filter, Format, ImageRegion, ImageSize, ImageUsage, IndirectCommand, PassRecorder,
+    SampleCount, Sampler, SamplerCache, SamplerDesc, ShaderStage, TexelBufferKind, UniformBuffer,
+    UploadContext, UploadTicket, LABEL_COLOR_DEBUG, LABEL_COLOR_POST, LABEL_COLOR_SCENE,
+    LABEL_COLOR_SHADOWS, LABEL_COLOR_UI, LABEL_COLOR_UPLOAD, TEXEL_BUFFER_BINDING,
+};
+
+// Scene helpers
+#[cfg(feature = "gltf")]
+pub use crate::Mesh;
+pub use crate::{
+    Aabb, Bvh, BvhHit, Camera, CameraMode, CameraPath, CameraRig, CullingStats, DrawLayer,
+    Handedness, ImportSettings, InsetHandle, InsetRect, OcclusionCuller, Ray, SortKey,
+    TonemapOperator, UpAxis, VideoImage, ViewportComposite, ViewportInset,
+};
+
+// Input, diagnostics and reports
+pub use crate::{
+    add_breadcrumb, copy_text, exposure_scale, num_validation_errors, recent_validation_messages,
+    report_teardown_audit, write_bytes, write_count, write_ms, BackgroundPolicy, BudgetAlert,
+    BudgetEvent, BudgetKind, BudgetMonitor, CopyDestination, FrameBudgets, FramePacer, FrameStats,
+    FrameTimeSummary, InputState, LiveObjects, PacingMode, SyntheticLoad, CRASH_DIR,
+};
+
+// Renamed
+#[deprecated(note = "Renamed to `Facade`.")]
+pub type Apparatus = crate::Facade;
+#[deprecated(note = "Renamed to `Context`.")]
+pub type VulkanApp = crate::Context;
+#[deprecated(note = "Renamed to `Image`.")]
+pub type Texture = crate::Image;
+#[deprecated(note = "Renamed to `ImageHandle`.")]
+pub type TextureHandle = crate::ImageHandle;
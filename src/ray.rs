@@ -0,0 +1,270 @@
+use crate::*;
+use glam::*;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3, // Normalized
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, direction: Vec3) -> Ray {
+        Ray {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    pub fn point_at(&self, t: f32) -> Vec3 {
+        self.origin + self.direction * t
+    }
+
+    /// Distance along the ray to the plane `dot(normal, p) == offset`. None if
+    /// the ray is parallel to the plane or the plane is behind the ray.
+    pub fn intersect_plane(&self, normal: Vec3, offset: f32) -> Option<f32> {
+        let denominator = normal.dot(self.direction);
+        if denominator.abs() < 1e-6 {
+            return None;
+        }
+        let t = (offset - normal.dot(self.origin)) / denominator;
+        if t >= 0.0 {
+            Some(t)
+        } else {
+            None
+        }
+    }
+
+    /// Distance along the ray to the nearest point on the box, using the slab
+    /// method. Zero if the origin is inside the box. Empty boxes are never hit.
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        // The slabs of an inverted box would swap into a huge interval
+        if aabb.is_empty() {
+            return None;
+        }
+        let mut t_min = 0.0_f32;
+        let mut t_max = f32::MAX;
+        let origin: [f32; 3] = self.origin.into();
+        let direction: [f32; 3] = self.direction.into();
+        let min: [f32; 3] = aabb.min.into();
+        let max: [f32; 3] = aabb.max.into();
+        for axis in 0..3 {
+            if direction[axis].abs() < 1e-9 {
+                // Parallel to the slab. Either always inside it or never.
+                if origin[axis] < min[axis] || origin[axis] > max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let inv_direction = 1.0 / direction[axis];
+            let mut t0 = (min[axis] - origin[axis]) * inv_direction;
+            let mut t1 = (max[axis] - origin[axis]) * inv_direction;
+            if t0 > t1 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_min > t_max {
+                return None;
+            }
+        }
+        Some(t_min)
+    }
+
+    /* Closest approach between the ray and the infinite line through
+    `line_origin` along `line_direction`. Returns the parameter along the line
+    (in units of `line_direction`), which is what dragging along a constrained
+    axis needs. None if the ray and the line are parallel. */
+    pub fn closest_point_on_line(&self, line_origin: Vec3, line_direction: Vec3) -> Option<f32> {
+        let w = self.origin - line_origin;
+        let a = line_direction.dot(line_direction);
+        let b = line_direction.dot(self.direction);
+        let c = self.direction.dot(self.direction);
+        let d = line_direction.dot(w);
+        let e = self.direction.dot(w);
+        let denominator = a * c - b * b;
+        if denominator.abs() < 1e-6 * a * c {
+            return None;
+        }
+        Some((d * c - b * e) / denominator)
+    }
+}
+
+impl Camera {
+    /* Ray through a point on the screen, in physical pixels relative to the
+    top-left of the viewport. The ray starts on the near plane. */
+    pub fn screen_point_to_ray(&self, screen_point: Vec2, viewport_size: Vec2) -> Ray {
+        let ndc_x = 2.0 * screen_point.x() / viewport_size.x() - 1.0;
+        let ndc_y = 2.0 * screen_point.y() / viewport_size.y() - 1.0;
+        let aspect_ratio = viewport_size.x() / viewport_size.y();
+        let tan_half_fov = (self.fov_y * 0.5).tan();
+        // Screen +Y points down, while the camera's local +Y points up
+        let local_direction = Vec3::new(
+            ndc_x * tan_half_fov * aspect_ratio,
+            -ndc_y * tan_half_fov,
+            -1.0,
+        );
+        let direction = (self.rotation * local_direction).normalize();
+        let origin = self.position + direction * (self.z_near / direction.dot(self.forward()));
+        Ray { origin, direction }
+    }
+
+    /* Screen point, in physical pixels relative to the top-left of the
+    viewport, of a point in the world. None if the point is behind the camera.
+    Points outside the view frustum still project, to coordinates outside the
+    viewport. */
+    pub fn world_to_screen(&self, world_position: Vec3, viewport_size: Vec2) -> Option<Vec2> {
+        let mtx_world_to_clip =
+            self.view_to_clip(viewport_size.x() / viewport_size.y()) * self.world_to_view();
+        let clip = mtx_world_to_clip * world_position.extend(1.0);
+        if clip.w() <= 0.0 {
+            return None;
+        }
+        // Vulkan NDC +Y already points down the screen
+        let ndc_x = clip.x() / clip.w();
+        let ndc_y = clip.y() / clip.w();
+        Some(Vec2::new(
+            (ndc_x + 1.0) * 0.5 * viewport_size.x(),
+            (ndc_y + 1.0) * 0.5 * viewport_size.y(),
+        ))
+    }
+
+    /* Inverse of `world_to_screen`. `depth` is the value that would be in the
+    depth buffer: 0 on the near plane and 1 on the far plane. */
+    pub fn screen_to_world(&self, screen_point: Vec2, depth: f32, viewport_size: Vec2) -> Vec3 {
+        let mtx_world_to_clip =
+            self.view_to_clip(viewport_size.x() / viewport_size.y()) * self.world_to_view();
+        let ndc = Vec4::new(
+            2.0 * screen_point.x() / viewport_size.x() - 1.0,
+            2.0 * screen_point.y() / viewport_size.y() - 1.0,
+            depth,
+            1.0,
+        );
+        let world = mtx_world_to_clip.inverse() * ndc;
+        world.truncate() / world.w()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewport_size() -> Vec2 {
+        Vec2::new(800.0, 600.0)
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1.0e-4, "{:?} != {:?}", a, b);
+    }
+
+    fn test_camera() -> Camera {
+        Camera::new(
+            Vec3::new(1.0, 2.0, 5.0),
+            Quat::from_rotation_y(0.4) * Quat::from_rotation_x(-0.3),
+        )
+    }
+
+    #[test]
+    fn screen_center_ray_looks_forward_from_the_near_plane() {
+        let camera = test_camera();
+        let ray = camera.screen_point_to_ray(viewport_size() * 0.5, viewport_size());
+        assert_close(ray.direction, camera.forward());
+        assert_close(
+            ray.origin,
+            camera.position + camera.forward() * camera.z_near,
+        );
+    }
+
+    #[test]
+    fn screen_point_ray_projects_back_to_the_screen_point() {
+        let camera = test_camera();
+        for &screen_point in &[
+            Vec2::new(0.0, 0.0),
+            Vec2::new(800.0, 600.0),
+            Vec2::new(123.0, 456.0),
+        ] {
+            let ray = camera.screen_point_to_ray(screen_point, viewport_size());
+            assert!(ray.direction.is_normalized());
+            for &t in &[0.0, 1.0, 50.0] {
+                let projected = camera
+                    .world_to_screen(ray.point_at(t), viewport_size())
+                    .expect("The ray's points are in front of the camera.");
+                assert!(
+                    (projected - screen_point).length() < 0.05,
+                    "{:?} != {:?}",
+                    projected,
+                    screen_point
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn screen_corners_span_the_field_of_view() {
+        let camera = Camera::new(Vec3::zero(), Quat::identity());
+        let top = camera.screen_point_to_ray(Vec2::new(400.0, 0.0), viewport_size());
+        let bottom = camera.screen_point_to_ray(Vec2::new(400.0, 600.0), viewport_size());
+        // Screen +Y points down, so the top of the screen looks up
+        assert!(top.direction.y() > 0.0 && bottom.direction.y() < 0.0);
+        let fov_y = top.direction.angle_between(bottom.direction);
+        assert!((fov_y - camera.fov_y).abs() < 1.0e-4);
+    }
+
+    #[test]
+    fn closest_point_on_a_perpendicular_line() {
+        // The ray runs along +X at height 1, and the line along Z through (3, 0, 0)
+        let ray = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::unit_x());
+        let t = ray
+            .closest_point_on_line(Vec3::new(3.0, 0.0, -2.0), Vec3::new(0.0, 0.0, 2.0))
+            .unwrap();
+        // Reached at z == 0, one unit of the line's direction from its origin
+        assert!((t - 1.0).abs() < 1.0e-5);
+    }
+
+    #[test]
+    fn closest_point_on_a_skew_line() {
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 0.0));
+        let line_origin = Vec3::new(0.0, 4.0, 1.0);
+        let line_direction = Vec3::unit_x();
+        let t = ray
+            .closest_point_on_line(line_origin, line_direction)
+            .unwrap();
+        // The segment between the closest points is perpendicular to both lines
+        let on_line = line_origin + line_direction * t;
+        let s = (on_line - ray.origin).dot(ray.direction);
+        let between = on_line - ray.point_at(s);
+        assert!(between.dot(line_direction).abs() < 1.0e-5);
+        assert!(between.dot(ray.direction).abs() < 1.0e-5);
+    }
+
+    #[test]
+    fn closest_point_on_a_parallel_line_is_none() {
+        let ray = Ray::new(Vec3::zero(), Vec3::unit_x());
+        let line_direction = Vec3::new(-3.0, 0.0, 0.0);
+        assert_eq!(
+            ray.closest_point_on_line(Vec3::unit_y(), line_direction),
+            None
+        );
+    }
+
+    #[test]
+    fn intersect_aabb_hits_and_misses() {
+        let aabb = Aabb {
+            min: Vec3::new(2.0, -1.0, -1.0),
+            max: Vec3::new(4.0, 1.0, 1.0),
+        };
+        let ray = Ray::new(Vec3::zero(), Vec3::unit_x());
+        assert_eq!(ray.intersect_aabb(&aabb), Some(2.0));
+        let inside = Ray::new(Vec3::new(3.0, 0.0, 0.0), Vec3::unit_y());
+        assert_eq!(inside.intersect_aabb(&aabb), Some(0.0));
+        let away = Ray::new(Vec3::zero(), -Vec3::unit_x());
+        assert_eq!(away.intersect_aabb(&aabb), None);
+    }
+
+    #[test]
+    fn intersect_aabb_never_hits_an_empty_box() {
+        for &direction in &[Vec3::unit_x(), Vec3::new(1.0, 2.0, 3.0), -Vec3::unit_z()] {
+            let ray = Ray::new(Vec3::zero(), direction);
+            assert_eq!(ray.intersect_aabb(&Aabb::empty()), None);
+        }
+    }
+}
@@ -1,29 +1,136 @@
 use crate::*;
 
+// Set that holds the image bound by `bind_draw_image()`, at binding 0.
+pub const DRAW_DESCRIPTOR_SET_IDX: u32 = 1;
+// Of the pass's texel buffer, in set 0, if it has one
+pub const TEXEL_BUFFER_BINDING: u32 = 3;
+// Without push descriptors, a pass can bind this many draw images per execution.
+const MAX_DRAW_IMAGES_PER_PASS: u32 = 1024;
+// A `uint` with the view index, pushed to passes that render their views one at a time
+const VIEW_INDEX_PUSH_CONSTANT_SIZE: u32 = 4;
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq)]
+pub enum BlendMode {
+    Opaque,
+    AlphaBlend, // Straight, not premultiplied, alpha
+    Additive,
+}
+
+impl BuilderPass {
+    // The first layer and the number of layers of an output or depth image that it renders into
+    fn rendered_layers(&self, image: &Image) -> (u32, u32) {
+        if image.num_layers > 1 {
+            (self.output_layer, self.num_views)
+        } else {
+            (0, 1)
+        }
+    }
+}
+
+impl BlendMode {
+    fn to_attachment_state(self) -> vk::PipelineColorBlendAttachmentState {
+        let (blend_enable, src_color_blend_factor, dst_color_blend_factor) = match self {
+            BlendMode::Opaque => (vk::FALSE, vk::BlendFactor::ONE, vk::BlendFactor::ZERO),
+            BlendMode::AlphaBlend => (
+                vk::TRUE,
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ),
+            BlendMode::Additive => (vk::TRUE, vk::BlendFactor::ONE, vk::BlendFactor::ONE),
+        };
+        vk::PipelineColorBlendAttachmentState {
+            blend_enable,
+            color_write_mask: vk::ColorComponentFlags::all(),
+            src_color_blend_factor,
+            dst_color_blend_factor,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: vk::BlendFactor::ONE,
+            dst_alpha_blend_factor: vk::BlendFactor::ZERO,
+            alpha_blend_op: vk::BlendOp::ADD,
+        }
+    }
+}
+
 #[derive(Debug, Hash)]
 pub struct BuilderPass {
     pub name: String,
     pub vertex_shader: ShaderHandle,
     pub fragment_shader: ShaderHandle,
     pub output_images: Vec<ImageHandle>,
-    pub input_image: (vk::ImageView, vk::Sampler), // TODO: Convert to image handle
+    pub blend_modes: Vec<BlendMode>, // One per output image
+    pub clear_color: Color,
+    pub label_color: Color,
+    // Sampled as an array at binding 1. TODO: Convert to image handles
+    pub input_images: Vec<(vk::ImageView, vk::Sampler)>,
     pub opt_depth_image: Option<ImageHandle>,
     pub viewport_width: u32,
     pub viewport_height: u32,
     pub uniform_buffer: BufferHandle,
+    pub opt_enabled_if: Option<PassFlagHandle>, // Only the flag is hashed, not its value
+    pub is_double_sided: bool,                  // Disables backface culling
+    // The bias values are dynamic state, so that they don't need their own pipelines
+    pub is_depth_biased: bool,
+    pub depth_compare_op: CompareOp,
+    /* Set if the uniforms are read with a dynamic offset that is chosen every
+    frame, instead of from `uniform_buffer`: the buffer, which is either the
+    context's ring buffer or a `UniformBuffer`, and the size of one frame's
+    uniforms. */
+    pub opt_dynamic_uniforms: Option<(vk::Buffer, usize)>,
+    pub opt_texel_buffer: Option<(vk::BufferView, TexelBufferKind)>, // Read at `TEXEL_BUFFER_BINDING`
+    /* Above `X1`, the pass renders into multisampled images that the graph
+    owns, and resolves the colors into the output images. Passes whose output
+    images are multisampled render into them, and leave the resolve to a
+    resolve pass. */
+    pub sample_count: SampleCount,
+    pub is_sample_shaded: bool, // Shades every sample, if the device supports it
+    pub opt_resolve_source: Option<ImageHandle>, // The multisampled image that a resolve pass reads
+    pub output_layer: u32,      // Of the output and depth images that are arrays
+    pub output_mip: u32,        // Of the output and depth images, which the viewport is sized to
+    /* Above 1, the pass renders into that many layers of its output and
+    depth images, from `output_layer` on, e.g. one per eye. With multiview,
+    every view is rendered in one render pass. Otherwise, the pass is recorded
+    once per view, and the view index is pushed as a constant. */
+    pub num_views: u32,
+    pub is_multiview: bool,
+}
+
+/// The layout that a pass leaves some layers of a mip of an output image in.
+#[derive(Copy, Clone, Debug)]
+pub struct FinalLayout {
+    pub image: ImageHandle,
+    pub mip: u32,
+    pub base_layer: u32,
+    pub num_layers: u32,
+    pub layout: vk::ImageLayout,
 }
 
 pub struct BuiltPass {
     pub pass_handle: PassHandle,
+    pub opt_enabled_if: Option<PassFlagHandle>,
+    pub label_name: CString, // Kept around so that labeling the pass doesn't allocate every frame
+    pub label_color: Color,
     pub clear_values: Vec<vk::ClearValue>,
     pub descriptor_set_layout: vk::DescriptorSetLayout,
     pub descriptor_set: vk::DescriptorSet,
-    pub framebuffer: vk::Framebuffer,
+    pub draw_descriptor_set_layout: vk::DescriptorSetLayout,
+    /* One per frame in flight, indexed by the context's `sync_idx`, since
+    cached graphs are shared between frames. Empty when using push descriptors. */
+    pub draw_descriptor_pools: Vec<vk::DescriptorPool>,
+    // One per view that is recorded on its own, or a single one with multiview
+    pub framebuffers: Vec<vk::Framebuffer>,
     pub render_pass: vk::RenderPass,
     pub pipeline_layout: vk::PipelineLayout,
     pub graphics_pipeline: vk::Pipeline,
     pub viewport_width: u32,
     pub viewport_height: u32,
+    pub output_images: Vec<ImageHandle>, // Including the depth image
+    // Of the output images that the render pass changes the layouts of, chosen by their next use
+    pub final_layouts: Vec<FinalLayout>,
+    pub is_depth_biased: bool,
+    pub is_dynamic_uniform: bool, // Binds its uniforms with a dynamic offset
+    pub is_view_index_pushed: bool, // Set when the views are recorded one at a time
+    // Multisampled color images, then depth, if any. Recreated with the graph.
+    pub msaa_images: Vec<Image>,
 }
 
 pub struct Graph {
@@ -33,52 +140,112 @@ pub struct Graph {
     descriptor_pool: vk::DescriptorPool,
     pub built_passes: Vec<BuiltPass>,
     pub shader_handles: Vec<ShaderHandle>, // Needed for shader hot reloading
+    opt_push_descriptor_fn: Option<vk::KhrPushDescriptorFn>,
 }
 
 impl Drop for Graph {
     fn drop(&mut self) {
+        track_destroyed(&LIVE_GRAPHS);
         unsafe {
             for built_pass in &mut self.built_passes {
                 self.device
                     .destroy_pipeline(built_pass.graphics_pipeline, None);
+                audit_destroyed(VkObjectKind::Pipeline, built_pass.graphics_pipeline);
                 self.device
                     .destroy_pipeline_layout(built_pass.pipeline_layout, None);
+                audit_destroyed(VkObjectKind::PipelineLayout, built_pass.pipeline_layout);
                 self.device
                     .destroy_descriptor_set_layout(built_pass.descriptor_set_layout, None);
+                audit_destroyed(
+                    VkObjectKind::DescriptorSetLayout,
+                    built_pass.descriptor_set_layout,
+                );
                 self.device
-                    .destroy_framebuffer(built_pass.framebuffer, None);
+                    .destroy_descriptor_set_layout(built_pass.draw_descriptor_set_layout, None);
+                audit_destroyed(
+                    VkObjectKind::DescriptorSetLayout,
+                    built_pass.draw_descriptor_set_layout,
+                );
+                for draw_descriptor_pool in &built_pass.draw_descriptor_pools {
+                    self.device
+                        .destroy_descriptor_pool(*draw_descriptor_pool, None);
+                    audit_destroyed(VkObjectKind::DescriptorPool, *draw_descriptor_pool);
+                }
+                for framebuffer in &built_pass.framebuffers {
+                    self.device.destroy_framebuffer(*framebuffer, None);
+                    audit_destroyed(VkObjectKind::Framebuffer, *framebuffer);
+                }
                 self.device
                     .destroy_render_pass(built_pass.render_pass, None);
+                audit_destroyed(VkObjectKind::RenderPass, built_pass.render_pass);
             }
             self.device
                 .destroy_descriptor_pool(self.descriptor_pool, None);
         }
+        audit_destroyed(VkObjectKind::DescriptorPool, self.descriptor_pool);
     }
 }
 
 impl Graph {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         gpu: &Gpu,
-        builder_passes: &Vec<(PassHandle, BuilderPass)>,
+        pipeline_cache: &PipelineCache,
+        builder_passes: &[(PassHandle, BuilderPass)],
         shader_list: &ShaderList,
         buffer_list: &BufferList,
         image_list: &ImageList,
+        shader_debug: &ShaderDebug,
+        num_frames: usize, // In flight, which each get their own draw descriptor pools
+        debug_utils: &DebugUtils,
     ) -> Graph {
-        // Create descriptor pool
+        /* Create descriptor pool, with one set per pass. Zero-sized pools
+        and pool sizes aren't valid, e.g. for a graph without passes. */
         let descriptor_pool = {
+            let num_passes = builder_passes.len() as u32;
+            let num_dynamic_uniform_passes = builder_passes
+                .iter()
+                .filter(|(_, pass)| pass.opt_dynamic_uniforms.is_some())
+                .count() as u32;
+            let num_input_images: usize = builder_passes
+                .iter()
+                .map(|(_, pass)| pass.input_images.len())
+                .sum();
+            let num_texel_buffers = |kind: TexelBufferKind| {
+                builder_passes
+                    .iter()
+                    .filter(|(_, pass)| pass.opt_texel_buffer.is_some_and(|(_, k)| k == kind))
+                    .count() as u32
+            };
             let pool_sizes = [
                 vk::DescriptorPoolSize {
                     ty: vk::DescriptorType::UNIFORM_BUFFER,
-                    descriptor_count: 2, // TODO: Derive this number
+                    descriptor_count: (num_passes - num_dynamic_uniform_passes).max(1),
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                    descriptor_count: num_dynamic_uniform_passes.max(1),
                 },
                 vk::DescriptorPoolSize {
                     ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-                    descriptor_count: 2, // TODO: Derive this number
+                    descriptor_count: (num_input_images as u32).max(1),
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+                    descriptor_count: num_passes.max(1),
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                    descriptor_count: num_texel_buffers(TexelBufferKind::Uniform).max(1),
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                    descriptor_count: num_texel_buffers(TexelBufferKind::Storage).max(1),
                 },
             ];
 
             let descriptor_pool_create_info = vk::DescriptorPoolCreateInfo::builder()
-                .max_sets(2) // TODO: Derive this number
+                .max_sets(num_passes.max(1))
                 .pool_sizes(&pool_sizes);
 
             unsafe {
@@ -90,7 +257,7 @@ impl Graph {
 
         let mut shader_handles = Vec::new();
         let mut built_passes = Vec::new();
-        for (pass_handle, pass) in builder_passes {
+        for (pass_idx, (pass_handle, pass)) in builder_passes.iter().enumerate() {
             /* Record which shader handles have been used. This is needed for
             hot-reloading shaders. */
             shader_handles.push(pass.vertex_shader);
@@ -101,13 +268,8 @@ impl Graph {
             if let Some(depth_handle) = pass.opt_depth_image {
                 opt_depth_image = Some(
                     image_list
-                        .get_image_from_handle(depth_handle)
-                        .unwrap_or_else(|| {
-                            panic!(
-                                "Image with handle `{:?}` not found in the context.",
-                                depth_handle
-                            )
-                        }),
+                        .get_image(depth_handle)
+                        .unwrap_or_else(|e| panic!("{}", e)),
                 );
             }
 
@@ -117,22 +279,121 @@ impl Graph {
                 .iter()
                 .map(|output_handle| {
                     image_list
-                        .get_image_from_handle(*output_handle)
-                        .unwrap_or_else(|| {
-                            panic!(
-                                "Image with handle `{:?}` not found in the context.",
-                                output_handle
-                            )
-                        })
+                        .get_image(*output_handle)
+                        .unwrap_or_else(|e| panic!("{}", e))
                 })
                 .collect();
 
+            // A resolve pass must come after a pass that writes its source
+            if let Some(source_handle) = pass.opt_resolve_source {
+                assert!(
+                    built_passes.iter().any(|built_pass: &BuiltPass| built_pass
+                        .output_images
+                        .contains(&source_handle)),
+                    "Resolve pass `{}` reads an image that no earlier pass writes.",
+                    pass.name
+                );
+            }
+
+            /* Create the multisampled images. Their contents only matter
+            within the pass, so the depth image replaces the pass's one. Output
+            images that are multisampled themselves are rendered into
+            directly, and so is a depth image with the pass's sample count. */
+            let samples = pass.sample_count.to_vk();
+            assert!(
+                pass.num_views == 1 || !pass.sample_count.is_multisampled(),
+                "Pass `{}` renders {} views, so it can't be multisampled.",
+                pass.name,
+                pass.num_views
+            );
+            let is_resolved_in_pass = pass.sample_count.is_multisampled()
+                && output_images
+                    .iter()
+                    .all(|output_image| output_image.image.samples == vk::SampleCountFlags::TYPE_1);
+            let mut msaa_images = Vec::new();
+            if is_resolved_in_pass {
+                for output_image in &output_images {
+                    msaa_images.push(Image::new_multisampled(
+                        &format!("{}_msaa", output_image.image.name),
+                        pass.viewport_width,
+                        pass.viewport_height,
+                        pass.sample_count,
+                        output_image.image.format,
+                        vk::ImageUsageFlags::COLOR_ATTACHMENT
+                            | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+                        vk::ImageAspectFlags::COLOR,
+                        gpu,
+                        debug_utils,
+                    ));
+                }
+            }
+            if pass.sample_count.is_multisampled() {
+                if let Some(depth_image) =
+                    opt_depth_image.filter(|depth_image| depth_image.image.samples != samples)
+                {
+                    msaa_images.push(Image::new_multisampled(
+                        &format!("{}_msaa", depth_image.image.name),
+                        pass.viewport_width,
+                        pass.viewport_height,
+                        pass.sample_count,
+                        depth_image.image.format,
+                        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                            | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+                        depth_image.image.aspect_flags,
+                        gpu,
+                        debug_utils,
+                    ));
+                }
+            }
+            let num_msaa_color_images = if is_resolved_in_pass {
+                output_images.len()
+            } else {
+                0
+            };
+            let (msaa_color_images, msaa_depth_images) =
+                msaa_images.split_at(num_msaa_color_images);
+            let opt_msaa_depth_image = msaa_depth_images.first();
+
+            /* The layouts that the images are left in, for the later passes
+            that use them. A depth image that is replaced by a multisampled
+            one isn't rendered into, so it keeps its layout. */
+            let later_passes = &builder_passes[pass_idx + 1..];
+            let final_layout_of = |image_handle: ImageHandle, internal_image: &InternalImage| {
+                let (base_layer, num_layers) = pass.rendered_layers(&internal_image.image);
+                FinalLayout {
+                    image: image_handle,
+                    mip: pass.output_mip,
+                    base_layer,
+                    num_layers,
+                    layout: next_use_layout(
+                        image_handle,
+                        internal_image,
+                        pass.output_mip,
+                        (base_layer, num_layers),
+                        later_passes,
+                    ),
+                }
+            };
+            let opt_depth_final_layout = match (pass.opt_depth_image, opt_depth_image) {
+                (Some(depth_handle), Some(depth_image)) if opt_msaa_depth_image.is_none() => {
+                    Some(final_layout_of(depth_handle, depth_image))
+                }
+                _ => None,
+            };
+            let output_final_layouts: Vec<FinalLayout> = pass
+                .output_images
+                .iter()
+                .zip(&output_images)
+                .map(|(output_handle, output_image)| final_layout_of(*output_handle, output_image))
+                .collect();
+
             /* Create render pass */
             let render_pass = {
                 let mut attachments: Vec<vk::AttachmentDescription> = Vec::new();
                 let mut attachment_idx = 0;
                 let mut depth_attachment_ptr = ptr::null();
                 let mut color_attachments = Vec::new();
+                let mut resolve_attachments = Vec::new();
 
                 // Depth attachment description and reference
                 let depth_attachment = vk::AttachmentReference {
@@ -143,13 +404,16 @@ impl Graph {
                     attachments.push(vk::AttachmentDescription {
                         format: depth_image.image.format,
                         flags: vk::AttachmentDescriptionFlags::empty(),
-                        samples: vk::SampleCountFlags::TYPE_1,
+                        samples,
                         load_op: vk::AttachmentLoadOp::CLEAR,
                         store_op: vk::AttachmentStoreOp::DONT_CARE, // TODO: Derive from graph
                         stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
                         stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
                         initial_layout: vk::ImageLayout::UNDEFINED,
-                        final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                        final_layout: opt_depth_final_layout.map_or(
+                            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                            |final_layout| final_layout.layout,
+                        ),
                     });
 
                     depth_attachment_ptr = &depth_attachment;
@@ -157,17 +421,32 @@ impl Graph {
                 }
 
                 // Color attachment descriptions and references
-                for output_image in &output_images {
+                for (output_image, output_final_layout) in
+                    output_images.iter().zip(&output_final_layouts)
+                {
+                    /* Multisampled colors are only needed until they're
+                    resolved, unless a resolve pass samples them */
+                    let (store_op, final_layout) = if is_resolved_in_pass {
+                        (
+                            vk::AttachmentStoreOp::DONT_CARE,
+                            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                        )
+                    } else {
+                        (
+                            vk::AttachmentStoreOp::STORE, // TODO: Derive from graph
+                            output_final_layout.layout,
+                        )
+                    };
                     attachments.push(vk::AttachmentDescription {
                         format: output_image.image.format,
                         flags: vk::AttachmentDescriptionFlags::empty(),
-                        samples: vk::SampleCountFlags::TYPE_1,
+                        samples,
                         load_op: vk::AttachmentLoadOp::CLEAR,
-                        store_op: vk::AttachmentStoreOp::STORE, // TODO: Derive from graph
+                        store_op,
                         stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
                         stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
                         initial_layout: vk::ImageLayout::UNDEFINED,
-                        final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                        final_layout,
                     });
                     color_attachments.push(vk::AttachmentReference {
                         attachment: attachment_idx,
@@ -176,17 +455,89 @@ impl Graph {
                     attachment_idx += 1;
                 }
 
+                // The output images are resolve attachments when multisampling
+                if is_resolved_in_pass {
+                    for (output_image, output_final_layout) in
+                        output_images.iter().zip(&output_final_layouts)
+                    {
+                        attachments.push(vk::AttachmentDescription {
+                            format: output_image.image.format,
+                            flags: vk::AttachmentDescriptionFlags::empty(),
+                            samples: vk::SampleCountFlags::TYPE_1,
+                            load_op: vk::AttachmentLoadOp::DONT_CARE,
+                            store_op: vk::AttachmentStoreOp::STORE,
+                            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+                            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                            initial_layout: vk::ImageLayout::UNDEFINED,
+                            final_layout: output_final_layout.layout,
+                        });
+                        resolve_attachments.push(vk::AttachmentReference {
+                            attachment: attachment_idx,
+                            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                        });
+                        attachment_idx += 1;
+                    }
+                }
+
                 let subpasses = [vk::SubpassDescription {
                     pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
-                    color_attachment_count: 1,
+                    color_attachment_count: color_attachments.len() as u32,
                     p_color_attachments: color_attachments.as_ptr(),
+                    p_resolve_attachments: if resolve_attachments.is_empty() {
+                        ptr::null()
+                    } else {
+                        resolve_attachments.as_ptr()
+                    },
                     p_depth_stencil_attachment: depth_attachment_ptr,
                     ..Default::default()
                 }];
 
-                let renderpass_create_info = vk::RenderPassCreateInfo::builder()
+                /* Every view is rendered by the subpass, and they are
+                similar enough to be rendered concurrently */
+                let view_masks = [(1u32 << pass.num_views) - 1];
+                let mut multiview_create_info = vk::RenderPassMultiviewCreateInfo::builder()
+                    .view_masks(&view_masks)
+                    .correlation_masks(&view_masks);
+                /* Order the pass against the passes around it. Passes before
+                it may have written or sampled its attachments, and the final
+                layouts are chosen for the passes after it, which sample the
+                attachments or render into them again. */
+                let attachment_stages = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+                let attachment_writes = vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+                let attachment_accesses = attachment_writes
+                    | vk::AccessFlags::COLOR_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ;
+                let dependencies = [
+                    vk::SubpassDependency {
+                        src_subpass: vk::SUBPASS_EXTERNAL,
+                        dst_subpass: 0,
+                        src_stage_mask: attachment_stages | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                        dst_stage_mask: attachment_stages,
+                        src_access_mask: attachment_writes,
+                        dst_access_mask: attachment_accesses,
+                        dependency_flags: vk::DependencyFlags::empty(),
+                    },
+                    vk::SubpassDependency {
+                        src_subpass: 0,
+                        dst_subpass: vk::SUBPASS_EXTERNAL,
+                        src_stage_mask: attachment_stages,
+                        dst_stage_mask: attachment_stages | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                        src_access_mask: attachment_writes,
+                        dst_access_mask: attachment_accesses | vk::AccessFlags::SHADER_READ,
+                        dependency_flags: vk::DependencyFlags::empty(),
+                    },
+                ];
+                let mut renderpass_create_info = vk::RenderPassCreateInfo::builder()
                     .attachments(&attachments)
-                    .subpasses(&subpasses);
+                    .subpasses(&subpasses)
+                    .dependencies(&dependencies);
+                if pass.is_multiview {
+                    renderpass_create_info =
+                        renderpass_create_info.push_next(&mut multiview_create_info);
+                }
 
                 unsafe {
                     gpu.device
@@ -195,31 +546,57 @@ impl Graph {
                 }
             };
 
-            /* Create framebuffer */
-            let framebuffer: vk::Framebuffer = {
-                let mut attachments: Vec<vk::ImageView> = Vec::new();
-                if let Some(depth_image) = opt_depth_image {
-                    attachments.push(depth_image.image.image_view);
-                }
-                for output_image in &output_images {
-                    attachments.push(output_image.image.image_view);
+            /* Create framebuffers. With multiview, a single one has views of
+            all the layers that are rendered into. */
+            let num_recorded_views = if pass.is_multiview { 1 } else { pass.num_views };
+            let is_view_index_pushed = pass.num_views > 1 && !pass.is_multiview;
+            let view_attachment = |image: &Image, view: u32| {
+                if pass.is_multiview {
+                    image
+                        .create_view(
+                            pass.output_mip,
+                            1,
+                            pass.output_layer,
+                            pass.num_views,
+                            vk::ImageViewType::TYPE_2D_ARRAY,
+                        )
+                        .unwrap_or_else(|e| panic!("{}", e))
+                } else {
+                    image.attachment_view(pass.output_mip, pass.output_layer + view)
                 }
+            };
+            let framebuffers: Vec<vk::Framebuffer> = (0..num_recorded_views)
+                .map(|view| {
+                    let mut attachments: Vec<vk::ImageView> = Vec::new();
+                    if let Some(msaa_depth_image) = opt_msaa_depth_image {
+                        attachments.push(msaa_depth_image.image_view);
+                    } else if let Some(depth_image) = opt_depth_image {
+                        attachments.push(view_attachment(&depth_image.image, view));
+                    }
+                    for msaa_color_image in msaa_color_images {
+                        attachments.push(msaa_color_image.image_view);
+                    }
+                    for output_image in &output_images {
+                        attachments.push(view_attachment(&output_image.image, view));
+                    }
 
-                let framebuffer_create_info = vk::FramebufferCreateInfo::builder()
-                    .render_pass(render_pass)
-                    .attachments(&attachments)
-                    .width(pass.viewport_width)
-                    .height(pass.viewport_height)
-                    .layers(1);
+                    let framebuffer_create_info = vk::FramebufferCreateInfo::builder()
+                        .render_pass(render_pass)
+                        .attachments(&attachments)
+                        .width(pass.viewport_width)
+                        .height(pass.viewport_height)
+                        .layers(1);
 
-                unsafe {
-                    gpu.device
-                        .create_framebuffer(&framebuffer_create_info, None)
-                        .expect("Failed to create framebuffer.")
-                }
-            };
+                    unsafe {
+                        gpu.device
+                            .create_framebuffer(&framebuffer_create_info, None)
+                            .expect("Failed to create framebuffer.")
+                    }
+                })
+                .collect();
 
-            /* Set clear values */
+            /* Set clear values. Resolve attachments aren't cleared, so they
+            don't need any. */
             let mut clear_values = Vec::new();
             if opt_depth_image.is_some() {
                 // Clear value for depth buffer
@@ -232,19 +609,21 @@ impl Graph {
             }
             for _ in &output_images {
                 // Clear values for color buffer
-                clear_values.push(vk::ClearValue {
-                    color: vk::ClearColorValue {
-                        float32: [0.0, 0.0, 0.0, 1.0],
-                    },
-                })
+                clear_values.push(pass.clear_color.to_clear_value());
             }
 
+            let uniform_descriptor_type = if pass.opt_dynamic_uniforms.is_some() {
+                vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC
+            } else {
+                vk::DescriptorType::UNIFORM_BUFFER
+            };
+
             /* Create descriptor set layout */
             let descriptor_set_layout = {
-                let bindings = [
+                let mut bindings = vec![
                     vk::DescriptorSetLayoutBinding {
                         binding: 0,
-                        descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                        descriptor_type: uniform_descriptor_type,
                         descriptor_count: 1,
                         stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
                         p_immutable_samplers: ptr::null(),
@@ -252,11 +631,27 @@ impl Graph {
                     vk::DescriptorSetLayoutBinding {
                         binding: 1,
                         descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                        descriptor_count: pass.input_images.len() as u32,
+                        stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                        p_immutable_samplers: ptr::null(),
+                    },
+                    vk::DescriptorSetLayoutBinding {
+                        binding: SHADER_DEBUG_BINDING,
+                        descriptor_type: vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
                         descriptor_count: 1,
                         stage_flags: vk::ShaderStageFlags::FRAGMENT,
                         p_immutable_samplers: ptr::null(),
                     },
                 ];
+                if let Some((_, kind)) = pass.opt_texel_buffer {
+                    bindings.push(vk::DescriptorSetLayoutBinding {
+                        binding: TEXEL_BUFFER_BINDING,
+                        descriptor_type: kind.descriptor_type(),
+                        descriptor_count: 1,
+                        stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                        p_immutable_samplers: ptr::null(),
+                    });
+                }
 
                 let ubo_layout_create_info =
                     vk::DescriptorSetLayoutCreateInfo::builder().bindings(&bindings);
@@ -268,6 +663,52 @@ impl Graph {
                 }
             };
 
+            /* Create the layout of the per-draw set. With VK_KHR_push_descriptor,
+            draw images are pushed straight into the command buffer. Otherwise,
+            sets are allocated from a per-pass pool that is reset every time
+            the pass begins. */
+            let draw_descriptor_set_layout = {
+                let bindings = [vk::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                    p_immutable_samplers: ptr::null(),
+                }];
+                let flags = if gpu.opt_push_descriptor_fn.is_some() {
+                    vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR
+                } else {
+                    vk::DescriptorSetLayoutCreateFlags::empty()
+                };
+                let layout_create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+                    .flags(flags)
+                    .bindings(&bindings);
+
+                unsafe {
+                    gpu.device
+                        .create_descriptor_set_layout(&layout_create_info, None)
+                        .expect("Failed to create Descriptor Set Layout!")
+                }
+            };
+            let draw_descriptor_pools = if gpu.opt_push_descriptor_fn.is_none() {
+                let pool_sizes = [vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: MAX_DRAW_IMAGES_PER_PASS,
+                }];
+                let pool_create_info = vk::DescriptorPoolCreateInfo::builder()
+                    .max_sets(MAX_DRAW_IMAGES_PER_PASS)
+                    .pool_sizes(&pool_sizes);
+                (0..num_frames)
+                    .map(|_| unsafe {
+                        gpu.device
+                            .create_descriptor_pool(&pool_create_info, None)
+                            .expect("Failed to create descriptor pool.")
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            };
+
             /* Create descriptor set */
             let descriptor_set = {
                 let layouts = [descriptor_set_layout];
@@ -280,34 +721,52 @@ impl Graph {
                         .expect("Failed to allocate descriptor sets.")
                 };
 
-                let uniform_buffer = buffer_list
-                    .get_buffer_from_handle(pass.uniform_buffer)
-                    .unwrap_or_else(|| {
-                        panic!(
-                            "Uniform buffer with handle `{:?}` not found in the context.",
-                            pass.uniform_buffer
-                        )
-                    });
-                let descriptor_buffer_info = [vk::DescriptorBufferInfo {
-                    buffer: uniform_buffer.vk_buffer,
-                    offset: 0,
-                    range: uniform_buffer.size as u64,
-                }];
+                let descriptor_buffer_info = if let Some((buffer, size)) = pass.opt_dynamic_uniforms
+                {
+                    [vk::DescriptorBufferInfo {
+                        buffer,
+                        offset: 0, // Selected per frame with a dynamic offset
+                        range: size as u64,
+                    }]
+                } else {
+                    let uniform_buffer = buffer_list
+                        .get_buffer(pass.uniform_buffer)
+                        .unwrap_or_else(|e| panic!("{}", e));
+                    [vk::DescriptorBufferInfo {
+                        buffer: uniform_buffer.vk_buffer,
+                        offset: 0,
+                        range: uniform_buffer.size as u64,
+                    }]
+                };
+
+                let descriptor_image_infos: Vec<vk::DescriptorImageInfo> = pass
+                    .input_images
+                    .iter()
+                    .map(|(image_view, sampler)| vk::DescriptorImageInfo {
+                        sampler: *sampler,
+                        image_view: *image_view,
+                        image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    })
+                    .collect();
 
-                let (input_image_view, input_sampler) = pass.input_image;
-                let descriptor_image_info = [vk::DescriptorImageInfo {
-                    sampler: input_sampler,
-                    image_view: input_image_view,
-                    image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                let shader_debug_buffer_info = [vk::DescriptorBufferInfo {
+                    buffer: shader_debug.buffer.vk_buffer,
+                    offset: 0, // Selected per frame with a dynamic offset
+                    range: shader_debug.region_size as u64,
                 }];
 
-                let descriptor_write_sets = [
+                let texel_buffer_views: Vec<vk::BufferView> = pass
+                    .opt_texel_buffer
+                    .iter()
+                    .map(|(view, _)| *view)
+                    .collect();
+                let mut descriptor_write_sets = vec![
                     vk::WriteDescriptorSet {
                         dst_set: descriptor_sets[0],
                         dst_binding: 0,
                         dst_array_element: 0,
                         descriptor_count: 1,
-                        descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                        descriptor_type: uniform_descriptor_type,
                         p_buffer_info: descriptor_buffer_info.as_ptr(),
                         ..Default::default()
                     },
@@ -315,12 +774,32 @@ impl Graph {
                         dst_set: descriptor_sets[0],
                         dst_binding: 1,
                         dst_array_element: 0,
-                        descriptor_count: 1,
+                        descriptor_count: descriptor_image_infos.len() as u32,
                         descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-                        p_image_info: descriptor_image_info.as_ptr(),
+                        p_image_info: descriptor_image_infos.as_ptr(),
+                        ..Default::default()
+                    },
+                    vk::WriteDescriptorSet {
+                        dst_set: descriptor_sets[0],
+                        dst_binding: SHADER_DEBUG_BINDING,
+                        dst_array_element: 0,
+                        descriptor_count: 1,
+                        descriptor_type: vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+                        p_buffer_info: shader_debug_buffer_info.as_ptr(),
                         ..Default::default()
                     },
                 ];
+                if let Some((_, kind)) = pass.opt_texel_buffer {
+                    descriptor_write_sets.push(vk::WriteDescriptorSet {
+                        dst_set: descriptor_sets[0],
+                        dst_binding: TEXEL_BUFFER_BINDING,
+                        dst_array_element: 0,
+                        descriptor_count: 1,
+                        descriptor_type: kind.descriptor_type(),
+                        p_texel_buffer_view: texel_buffer_views.as_ptr(),
+                        ..Default::default()
+                    });
+                }
 
                 unsafe {
                     gpu.device
@@ -333,21 +812,11 @@ impl Graph {
             let (graphics_pipeline, pipeline_layout) = {
                 let main_function_name = CString::new("main").unwrap();
                 let vertex_shader = shader_list
-                    .get_shader_from_handle(pass.vertex_shader)
-                    .unwrap_or_else(|| {
-                        panic!(
-                            "Vertex shader with handle `{}` not found in the context.",
-                            pass.vertex_shader.0
-                        )
-                    });
+                    .get_shader(pass.vertex_shader)
+                    .unwrap_or_else(|e| panic!("{}", e));
                 let fragment_shader = shader_list
-                    .get_shader_from_handle(pass.fragment_shader)
-                    .unwrap_or_else(|| {
-                        panic!(
-                            "Fragment shader with handle `{}` not found in the context.",
-                            pass.fragment_shader.0
-                        )
-                    });
+                    .get_shader(pass.fragment_shader)
+                    .unwrap_or_else(|e| panic!("{}", e));
                 let shader_stages = [
                     vk::PipelineShaderStageCreateInfo {
                         stage: vk::ShaderStageFlags::VERTEX,
@@ -417,36 +886,40 @@ impl Graph {
 
                 let rasterization_state_create_info = vk::PipelineRasterizationStateCreateInfo {
                     polygon_mode: vk::PolygonMode::FILL,
-                    cull_mode: vk::CullModeFlags::BACK,
+                    cull_mode: if pass.is_double_sided {
+                        vk::CullModeFlags::NONE
+                    } else {
+                        vk::CullModeFlags::BACK
+                    },
                     front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+                    depth_bias_enable: pass.is_depth_biased as vk::Bool32,
                     line_width: 1.0,
                     ..Default::default()
                 };
 
                 let multisample_state_create_info = vk::PipelineMultisampleStateCreateInfo {
-                    rasterization_samples: vk::SampleCountFlags::TYPE_1,
+                    rasterization_samples: samples,
+                    sample_shading_enable: (pass.is_sample_shaded
+                        && gpu.enabled_features.sample_rate_shading == vk::TRUE)
+                        as vk::Bool32,
+                    min_sample_shading: 1.0,
                     ..Default::default()
                 };
 
                 let depth_state_create_info = vk::PipelineDepthStencilStateCreateInfo {
                     depth_test_enable: vk::TRUE,
                     depth_write_enable: vk::TRUE,
-                    depth_compare_op: vk::CompareOp::LESS,
+                    depth_compare_op: pass.depth_compare_op.to_vk(),
                     max_depth_bounds: 1.0,
                     min_depth_bounds: 0.0,
                     ..Default::default()
                 };
 
-                let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
-                    blend_enable: vk::FALSE,
-                    color_write_mask: vk::ColorComponentFlags::all(),
-                    src_color_blend_factor: vk::BlendFactor::ONE,
-                    dst_color_blend_factor: vk::BlendFactor::ZERO,
-                    color_blend_op: vk::BlendOp::ADD,
-                    src_alpha_blend_factor: vk::BlendFactor::ONE,
-                    dst_alpha_blend_factor: vk::BlendFactor::ZERO,
-                    alpha_blend_op: vk::BlendOp::ADD,
-                }];
+                let color_blend_attachment_states: Vec<vk::PipelineColorBlendAttachmentState> =
+                    pass.blend_modes
+                        .iter()
+                        .map(|blend_mode| blend_mode.to_attachment_state())
+                        .collect();
 
                 let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
                     attachment_count: color_blend_attachment_states.len() as u32,
@@ -455,9 +928,19 @@ impl Graph {
                     ..Default::default()
                 };
 
-                let set_layouts = [descriptor_set_layout];
-                let pipeline_layout_create_info =
-                    vk::PipelineLayoutCreateInfo::builder().set_layouts(&set_layouts);
+                let set_layouts = [descriptor_set_layout, draw_descriptor_set_layout];
+                let push_constant_ranges = [vk::PushConstantRange {
+                    stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                    offset: 0,
+                    size: VIEW_INDEX_PUSH_CONSTANT_SIZE,
+                }];
+                let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+                    .set_layouts(&set_layouts)
+                    .push_constant_ranges(if is_view_index_pushed {
+                        &push_constant_ranges
+                    } else {
+                        &[]
+                    });
 
                 let pipeline_layout = unsafe {
                     gpu.device
@@ -465,7 +948,11 @@ impl Graph {
                         .expect("Failed to create pipeline layout.")
                 };
 
-                let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+                let mut dynamic_states =
+                    vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+                if pass.is_depth_biased {
+                    dynamic_states.push(vk::DynamicState::DEPTH_BIAS);
+                }
                 let dynamic_state_create_info = vk::PipelineDynamicStateCreateInfo {
                     s_type: vk::StructureType::PIPELINE_DYNAMIC_STATE_CREATE_INFO,
                     p_next: ptr::null(),
@@ -495,7 +982,7 @@ impl Graph {
                 let graphics_pipelines = unsafe {
                     gpu.device
                         .create_graphics_pipelines(
-                            vk::PipelineCache::null(),
+                            pipeline_cache.vk_pipeline_cache,
                             &graphic_pipeline_create_infos,
                             None,
                         )
@@ -505,34 +992,142 @@ impl Graph {
                 (graphics_pipelines[0], pipeline_layout)
             };
 
+            let parents = [device_id(&gpu.device)];
+            audit_created(VkObjectKind::RenderPass, render_pass, &parents, &pass.name);
+            for framebuffer in &framebuffers {
+                audit_created(
+                    VkObjectKind::Framebuffer,
+                    *framebuffer,
+                    &parents,
+                    &pass.name,
+                );
+            }
+            audit_created(
+                VkObjectKind::DescriptorSetLayout,
+                descriptor_set_layout,
+                &parents,
+                &pass.name,
+            );
+            audit_created(
+                VkObjectKind::DescriptorSetLayout,
+                draw_descriptor_set_layout,
+                &parents,
+                &pass.name,
+            );
+            for draw_descriptor_pool in &draw_descriptor_pools {
+                audit_created(
+                    VkObjectKind::DescriptorPool,
+                    *draw_descriptor_pool,
+                    &parents,
+                    &pass.name,
+                );
+            }
+            audit_created(
+                VkObjectKind::PipelineLayout,
+                pipeline_layout,
+                &parents,
+                &pass.name,
+            );
+            audit_created(
+                VkObjectKind::Pipeline,
+                graphics_pipeline,
+                &parents,
+                &pass.name,
+            );
+
             built_passes.push(BuiltPass {
-                pass_handle: pass_handle.clone(),
+                pass_handle: *pass_handle,
+                opt_enabled_if: pass.opt_enabled_if,
+                label_name: CString::new(pass.name.as_str()).unwrap(),
+                label_color: pass.label_color,
                 clear_values,
                 descriptor_set_layout,
                 descriptor_set,
-                framebuffer,
+                draw_descriptor_set_layout,
+                draw_descriptor_pools,
+                framebuffers,
                 render_pass,
                 pipeline_layout,
                 graphics_pipeline,
                 viewport_width: pass.viewport_width,
                 viewport_height: pass.viewport_height,
+                output_images: pass
+                    .output_images
+                    .iter()
+                    .chain(&pass.opt_depth_image)
+                    .copied()
+                    .collect(),
+                final_layouts: output_final_layouts
+                    .iter()
+                    .chain(&opt_depth_final_layout)
+                    .copied()
+                    .collect(),
+                is_depth_biased: pass.is_depth_biased,
+                is_dynamic_uniform: pass.opt_dynamic_uniforms.is_some(),
+                is_view_index_pushed,
+                msaa_images,
             });
         }
 
+        audit_created(
+            VkObjectKind::DescriptorPool,
+            descriptor_pool,
+            &[device_id(&gpu.device)],
+            "graph_descriptor_pool",
+        );
+        track_created(&LIVE_GRAPHS);
         Graph {
             device: gpu.device.clone(),
             descriptor_pool,
             built_passes,
             shader_handles,
+            opt_push_descriptor_fn: gpu.opt_push_descriptor_fn.clone(),
         }
     }
 
-    pub fn begin_pass(&self, pass_handle: PassHandle, command_buffer: vk::CommandBuffer) {
-        let built_pass = self
-            .built_passes
+    pub fn get_built_pass(&self, pass_handle: PassHandle) -> &BuiltPass {
+        self.built_passes
             .iter()
             .find(|&p| p.pass_handle == pass_handle)
-            .unwrap_or_else(|| panic!("Pass with handle `{}` not found in graph.", pass_handle.0));
+            .unwrap_or_else(|| panic!("Pass with handle `{}` not found in graph.", pass_handle.0))
+    }
+
+    /* `sync_idx` is the frame in flight that is being recorded, whose
+    previous command buffer has finished executing. `shader_debug_offset`
+    selects the region of the shader debug buffer that the pass writes to, and
+    `opt_dynamic_uniform_offset` this frame's uniforms of the pass, if it
+    binds them with a dynamic offset. Passes whose views are recorded one at a time are
+    begun once per `view`, from 0 to the number of framebuffers, and ended
+    after each. */
+    #[allow(clippy::too_many_arguments)]
+    pub fn begin_pass(
+        &self,
+        pass_handle: PassHandle,
+        sync_idx: usize,
+        command_buffer: vk::CommandBuffer,
+        shader_debug_offset: u32,
+        opt_dynamic_uniform_offset: Option<u32>,
+        view: u32,
+    ) {
+        let built_pass = self.get_built_pass(pass_handle);
+
+        /* The command buffer that used this frame's draw sets last time has
+        finished executing. Other frames in flight may still use theirs. The
+        sets of earlier views are still in use by this one. */
+        if let Some(draw_descriptor_pool) = built_pass
+            .draw_descriptor_pools
+            .get(sync_idx)
+            .filter(|_| view == 0)
+        {
+            unsafe {
+                self.device
+                    .reset_descriptor_pool(
+                        *draw_descriptor_pool,
+                        vk::DescriptorPoolResetFlags::empty(),
+                    )
+                    .expect("Failed to reset descriptor pool.");
+            }
+        }
 
         let extent = vk::Extent2D {
             width: built_pass.viewport_width,
@@ -541,7 +1136,7 @@ impl Graph {
 
         let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
             .render_pass(built_pass.render_pass)
-            .framebuffer(built_pass.framebuffer)
+            .framebuffer(built_pass.framebuffers[view as usize])
             .render_area(vk::Rect2D {
                 offset: vk::Offset2D { x: 0, y: 0 },
                 extent,
@@ -577,16 +1172,102 @@ impl Graph {
                     extent,
                 }];
                 self.device.cmd_set_scissor(command_buffer, 0, &scissors);
+                // No bias until the pass sets one
+                if built_pass.is_depth_biased {
+                    self.device
+                        .cmd_set_depth_bias(command_buffer, 0.0, 0.0, 0.0);
+                }
             }
             // Bind descriptor sets
             {
                 let sets = [built_pass.descriptor_set];
+                // Dynamic offsets are in binding order
+                let dynamic_offsets: Vec<u32> = opt_dynamic_uniform_offset
+                    .into_iter()
+                    .chain(std::iter::once(shader_debug_offset))
+                    .collect();
                 self.device.cmd_bind_descriptor_sets(
                     command_buffer,
                     vk::PipelineBindPoint::GRAPHICS,
                     built_pass.pipeline_layout,
                     0,
                     &sets,
+                    &dynamic_offsets,
+                );
+            }
+            if built_pass.is_view_index_pushed {
+                self.device.cmd_push_constants(
+                    command_buffer,
+                    built_pass.pipeline_layout,
+                    vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                    0,
+                    &view.to_ne_bytes(),
+                );
+            }
+        }
+    }
+
+    /// Bind an image for the following draws of the pass, at binding 0 of
+    /// `DRAW_DESCRIPTOR_SET_IDX`. Must be called between `begin_pass()` and
+    /// `end_pass()`, with the same `sync_idx`.
+    pub fn bind_draw_image(
+        &self,
+        pass_handle: PassHandle,
+        sync_idx: usize,
+        command_buffer: vk::CommandBuffer,
+        image_view: vk::ImageView,
+        sampler: vk::Sampler,
+    ) {
+        let built_pass = self.get_built_pass(pass_handle);
+        let descriptor_image_info = [vk::DescriptorImageInfo {
+            sampler,
+            image_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }];
+        let mut descriptor_write = vk::WriteDescriptorSet {
+            dst_binding: 0,
+            dst_array_element: 0,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            p_image_info: descriptor_image_info.as_ptr(),
+            ..Default::default()
+        };
+
+        if let Some(push_descriptor_fn) = &self.opt_push_descriptor_fn {
+            unsafe {
+                push_descriptor_fn.cmd_push_descriptor_set_khr(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    built_pass.pipeline_layout,
+                    DRAW_DESCRIPTOR_SET_IDX,
+                    1,
+                    &descriptor_write,
+                );
+            }
+        } else {
+            let layouts = [built_pass.draw_descriptor_set_layout];
+            let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+                .descriptor_pool(built_pass.draw_descriptor_pools[sync_idx])
+                .set_layouts(&layouts);
+            let descriptor_set = unsafe {
+                self.device
+                    .allocate_descriptor_sets(&allocate_info)
+                    .unwrap_or_else(|_| {
+                        panic!(
+                            "Pass bound more than {} draw images.",
+                            MAX_DRAW_IMAGES_PER_PASS
+                        )
+                    })[0]
+            };
+            descriptor_write.dst_set = descriptor_set;
+            unsafe {
+                self.device.update_descriptor_sets(&[descriptor_write], &[]);
+                self.device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    built_pass.pipeline_layout,
+                    DRAW_DESCRIPTOR_SET_IDX,
+                    &[descriptor_set],
                     &[],
                 );
             }
@@ -599,3 +1280,47 @@ impl Graph {
         }
     }
 }
+
+/* The layout to leave some layers of a mip of an image in, for the first of
+`later_passes` that renders into them or samples them. Render passes start
+from UNDEFINED, so passes that may be disabled by their flag are skipped when
+they only render into the image, and whatever comes after them decides.
+Images that no later pass uses are left for presenting if they're swapchain
+images, for depth testing if they're depth images, and for sampling otherwise,
+e.g. in the next frame or by a compute shader. */
+fn next_use_layout(
+    image_handle: ImageHandle,
+    internal_image: &InternalImage,
+    mip: u32,
+    (base_layer, num_layers): (u32, u32),
+    later_passes: &[(PassHandle, BuilderPass)],
+) -> vk::ImageLayout {
+    let image = &internal_image.image;
+    for (_, pass) in later_passes {
+        let (pass_base_layer, pass_num_layers) = pass.rendered_layers(image);
+        let is_rendered_into = pass.opt_enabled_if.is_none()
+            && pass.output_mip == mip
+            && pass_base_layer < base_layer + num_layers
+            && base_layer < pass_base_layer + pass_num_layers;
+        if is_rendered_into && pass.output_images.contains(&image_handle) {
+            return vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL;
+        }
+        if is_rendered_into && pass.opt_depth_image == Some(image_handle) {
+            return vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL;
+        }
+        let is_sampled = pass
+            .input_images
+            .iter()
+            .any(|(view, _)| image.is_covered_by_view(*view, mip, base_layer, num_layers));
+        if is_sampled {
+            return vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+        }
+    }
+    if let ImageKind::Swapchain = internal_image.kind {
+        vk::ImageLayout::PRESENT_SRC_KHR
+    } else if image.aspect_flags.contains(vk::ImageAspectFlags::DEPTH) {
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+    } else {
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+    }
+}
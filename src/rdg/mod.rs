@@ -1,2 +1,4 @@
 pub mod graph;
 pub use graph::*;
+pub mod pass_recorder;
+pub use pass_recorder::*;
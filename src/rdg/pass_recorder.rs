@@ -0,0 +1,371 @@
+use crate::*;
+use std::cell::Cell;
+
+/* What a pass records its commands through, between the pass being begun and
+ended by `Context::record_pass()`. The helpers cover what passes usually
+need, and check for the common mistakes in debug builds. Anything else can be
+recorded on the raw command buffer, with these rules:
+
+- Don't begin or end render passes. The pass is already inside one.
+- Only sample images that aren't outputs of the pass.
+- Viewport and scissor can be changed freely, since every pass sets them when
+  it begins. Other dynamic state isn't reset. */
+pub struct PassRecorder<'a> {
+    context: &'a Context,
+    graph: &'a Graph,
+    pass_handle: PassHandle,
+    command_buffer: vk::CommandBuffer,
+    num_draws: Cell<usize>, // Through the draw helpers, for the frame budgets
+}
+
+impl<'a> PassRecorder<'a> {
+    pub fn new(
+        context: &'a Context,
+        graph: &'a Graph,
+        pass_handle: PassHandle,
+        command_buffer: vk::CommandBuffer,
+    ) -> PassRecorder<'a> {
+        PassRecorder {
+            context,
+            graph,
+            pass_handle,
+            command_buffer,
+            num_draws: Cell::new(0),
+        }
+    }
+
+    pub fn context(&self) -> &Context {
+        self.context
+    }
+
+    /// Draws recorded so far, including indirect ones, and not counting ones
+    /// on the raw command buffer.
+    pub fn num_draws(&self) -> usize {
+        self.num_draws.get()
+    }
+
+    /// The escape hatch, for recording commands that the helpers don't cover.
+    pub fn unsafe_raw(&self) -> vk::CommandBuffer {
+        self.command_buffer
+    }
+
+    /// The images that the pass renders to, including the depth image.
+    pub fn output_images(&self) -> &[ImageHandle] {
+        &self.graph.get_built_pass(self.pass_handle).output_images
+    }
+
+    /// Bind a per-draw image. Shaders read it from `set = 1, binding = 0`.
+    pub fn bind_draw_image(&self, image_handle: ImageHandle, sampler: &Sampler) {
+        if cfg!(debug_assertions) && self.output_images().contains(&image_handle) {
+            panic!(
+                "Pass `{}` samples image `{:?}`, which it also renders to.",
+                self.pass_name(),
+                image_handle
+            );
+        }
+        let internal_image = self
+            .context
+            .image_list
+            .get_image(image_handle)
+            .unwrap_or_else(|e| panic!("{}", e));
+        debug_assert_upload_complete(
+            &internal_image.image.opt_upload_ticket,
+            &internal_image.image.name,
+            &self.context.gpu,
+        );
+        self.graph.bind_draw_image(
+            self.pass_handle,
+            self.context.sync_idx,
+            self.command_buffer,
+            internal_image.image.image_view,
+            sampler.vk_sampler,
+        );
+    }
+
+    pub fn bind_vertex_buffer(&self, buffer: &DeviceLocalBuffer) {
+        debug_assert_upload_complete(&buffer.opt_upload_ticket, &buffer.name, &self.context.gpu);
+        unsafe {
+            self.context.gpu.device.cmd_bind_vertex_buffers(
+                self.command_buffer,
+                0,
+                &[buffer.vk_buffer],
+                &[0],
+            );
+        }
+    }
+
+    /// Bind vertices that were written this frame to the context's ring buffer,
+    /// at the offset that `RingBuffer::allocate()` returned.
+    pub fn bind_ring_vertex_buffer(&self, offset: usize) {
+        unsafe {
+            self.context.gpu.device.cmd_bind_vertex_buffers(
+                self.command_buffer,
+                0,
+                &[self.context.ring_buffer.buffer.vk_buffer],
+                &[offset as u64],
+            );
+        }
+    }
+
+    /// The index type follows the buffer's elements, which must be `u16` or `u32`.
+    pub fn bind_index_buffer(&self, buffer: &DeviceLocalBuffer) {
+        let index_type = match buffer.element_size {
+            2 => vk::IndexType::UINT16,
+            4 => vk::IndexType::UINT32,
+            size => panic!(
+                "Index buffers must have 2 or 4 byte elements, not {}.",
+                size
+            ),
+        };
+        debug_assert_upload_complete(&buffer.opt_upload_ticket, &buffer.name, &self.context.gpu);
+        unsafe {
+            self.context.gpu.device.cmd_bind_index_buffer(
+                self.command_buffer,
+                buffer.vk_buffer,
+                0,
+                index_type,
+            );
+        }
+    }
+
+    pub fn draw(&self, num_vertices: u32) {
+        self.num_draws.set(self.num_draws.get() + 1);
+        unsafe {
+            self.context
+                .gpu
+                .device
+                .cmd_draw(self.command_buffer, num_vertices, 1, 0, 0);
+        }
+    }
+
+    pub fn draw_indexed(&self, num_indices: u32) {
+        self.num_draws.set(self.num_draws.get() + 1);
+        unsafe {
+            self.context
+                .gpu
+                .device
+                .cmd_draw_indexed(self.command_buffer, num_indices, 1, 0, 0, 0);
+        }
+    }
+
+    /// Draw `num_draws` times, with the `DrawIndirectCommand`s that start
+    /// `offset` bytes into `buffer`.
+    pub fn draw_indirect(&self, buffer: &DeviceLocalBuffer, offset: usize, num_draws: u32) {
+        self.check_indirect_args::<DrawIndirectCommand>(buffer, offset, num_draws);
+        self.num_draws
+            .set(self.num_draws.get() + num_draws as usize);
+        unsafe {
+            self.context.gpu.device.cmd_draw_indirect(
+                self.command_buffer,
+                buffer.vk_buffer,
+                offset as u64,
+                num_draws,
+                std::mem::size_of::<DrawIndirectCommand>() as u32,
+            );
+        }
+    }
+
+    /// Like `draw_indirect()`, with `DrawIndexedIndirectCommand`s and the
+    /// bound index buffer.
+    pub fn draw_indexed_indirect(&self, buffer: &DeviceLocalBuffer, offset: usize, num_draws: u32) {
+        self.check_indirect_args::<DrawIndexedIndirectCommand>(buffer, offset, num_draws);
+        self.num_draws
+            .set(self.num_draws.get() + num_draws as usize);
+        unsafe {
+            self.context.gpu.device.cmd_draw_indexed_indirect(
+                self.command_buffer,
+                buffer.vk_buffer,
+                offset as u64,
+                num_draws,
+                std::mem::size_of::<DrawIndexedIndirectCommand>() as u32,
+            );
+        }
+    }
+
+    /* Like `draw_indexed_indirect()`, with the number of draws read from the
+    `u32` at `count_offset` bytes into `count_buffer`, e.g. after a compute
+    shader culled them. At most `max_draws` are issued, and all of them count
+    towards `num_draws()`. Needs VK_KHR_draw_indirect_count. */
+    pub fn draw_indexed_indirect_count(
+        &self,
+        buffer: &DeviceLocalBuffer,
+        offset: usize,
+        count_buffer: &DeviceLocalBuffer,
+        count_offset: usize,
+        max_draws: u32,
+    ) {
+        let draw_indirect_count_fn = self
+            .context
+            .gpu
+            .opt_draw_indirect_count_fn
+            .as_ref()
+            .unwrap_or_else(|| {
+                panic!(
+                    "Pass `{}` reads its draw count from a buffer, but the device doesn't support VK_KHR_draw_indirect_count.",
+                    self.pass_name()
+                )
+            });
+        self.check_indirect_args::<DrawIndexedIndirectCommand>(buffer, offset, max_draws);
+        let count_buffer_size = count_buffer.num_elements * count_buffer.element_size;
+        if !count_buffer
+            .usage
+            .contains(vk::BufferUsageFlags::INDIRECT_BUFFER)
+            || !count_offset.is_multiple_of(4)
+            || count_offset + 4 > count_buffer_size
+        {
+            panic!(
+                "Pass `{}` reads its draw count at offset {} of buffer `{}`, which is {} bytes. The buffer needs `INDIRECT` usage, and the offset must be a multiple of 4.",
+                self.pass_name(),
+                count_offset,
+                count_buffer.name,
+                count_buffer_size
+            );
+        }
+        debug_assert_upload_complete(
+            &count_buffer.opt_upload_ticket,
+            &count_buffer.name,
+            &self.context.gpu,
+        );
+        self.num_draws
+            .set(self.num_draws.get() + max_draws as usize);
+        unsafe {
+            draw_indirect_count_fn.cmd_draw_indexed_indirect_count_khr(
+                self.command_buffer,
+                buffer.vk_buffer,
+                offset as u64,
+                count_buffer.vk_buffer,
+                count_offset as u64,
+                max_draws,
+                std::mem::size_of::<DrawIndexedIndirectCommand>() as u32,
+            );
+        }
+    }
+
+    /* Out-of-bounds indirect reads are undefined behavior on the GPU, so
+    these are checked in release builds too. The buffer's elements set the
+    stride, and must be the command type that the draw reads. */
+    fn check_indirect_args<T: IndirectCommand>(
+        &self,
+        buffer: &DeviceLocalBuffer,
+        offset: usize,
+        num_draws: u32,
+    ) {
+        let stride = std::mem::size_of::<T>();
+        if !buffer.usage.contains(vk::BufferUsageFlags::INDIRECT_BUFFER) {
+            panic!(
+                "Pass `{}` draws with arguments from buffer `{}`, which wasn't created with `INDIRECT` usage.",
+                self.pass_name(),
+                buffer.name
+            );
+        }
+        if buffer.element_size != stride {
+            panic!(
+                "Pass `{}` reads {}-byte draw commands from buffer `{}`, whose elements are {} bytes.",
+                self.pass_name(),
+                stride,
+                buffer.name,
+                buffer.element_size
+            );
+        }
+        let buffer_size = buffer.num_elements * buffer.element_size;
+        let is_in_bounds = (num_draws as usize)
+            .checked_mul(stride)
+            .and_then(|size| size.checked_add(offset))
+            .is_some_and(|end| end <= buffer_size);
+        if !offset.is_multiple_of(4) || !is_in_bounds {
+            panic!(
+                "Pass `{}` reads {} draw commands at offset {} of buffer `{}`, which is {} bytes. The offset must be a multiple of 4.",
+                self.pass_name(),
+                num_draws,
+                offset,
+                buffer.name,
+                buffer_size
+            );
+        }
+        let gpu = &self.context.gpu;
+        let max_draws = gpu.max_indirect_draws();
+        if num_draws > max_draws {
+            panic!(
+                "Pass `{}` issues {} indirect draws in one call, but the device allows {}.",
+                self.pass_name(),
+                num_draws,
+                max_draws
+            );
+        }
+        debug_assert_upload_complete(&buffer.opt_upload_ticket, &buffer.name, gpu);
+    }
+
+    /// Restrict the following draws to a region of the pass's outputs.
+    pub fn set_viewport(&self, x: u32, y: u32, width: u32, height: u32) {
+        let built_pass = self.graph.get_built_pass(self.pass_handle);
+        if cfg!(debug_assertions)
+            && (x + width > built_pass.viewport_width || y + height > built_pass.viewport_height)
+        {
+            panic!(
+                "Viewport {}x{} at ({}, {}) is outside of pass `{}`, which is {}x{}.",
+                width,
+                height,
+                x,
+                y,
+                self.pass_name(),
+                built_pass.viewport_width,
+                built_pass.viewport_height
+            );
+        }
+        let viewports = [vk::Viewport {
+            x: x as f32,
+            y: y as f32,
+            width: width as f32,
+            height: height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+        let scissors = [vk::Rect2D {
+            offset: vk::Offset2D {
+                x: x as i32,
+                y: y as i32,
+            },
+            extent: vk::Extent2D { width, height },
+        }];
+        unsafe {
+            let device = &self.context.gpu.device;
+            device.cmd_set_viewport(self.command_buffer, 0, &viewports);
+            device.cmd_set_scissor(self.command_buffer, 0, &scissors);
+        }
+    }
+
+    /* Depth bias for the following draws, in units of the smallest depth
+    difference and of the triangle's depth slope. Negative values pull the
+    geometry towards the camera. The pass must have been made depth biased
+    with `Context::set_pass_depth_biased()`. */
+    pub fn set_depth_bias(&self, constant_factor: f32, slope_factor: f32) {
+        if !self.graph.get_built_pass(self.pass_handle).is_depth_biased {
+            panic!(
+                "Pass `{}` sets a depth bias, but wasn't made depth biased.",
+                self.pass_name()
+            );
+        }
+        unsafe {
+            self.context.gpu.device.cmd_set_depth_bias(
+                self.command_buffer,
+                constant_factor,
+                0.0,
+                slope_factor,
+            );
+        }
+    }
+
+    /// Cover the pass's outputs again, as when the pass began.
+    pub fn reset_viewport(&self) {
+        let built_pass = self.graph.get_built_pass(self.pass_handle);
+        self.set_viewport(0, 0, built_pass.viewport_width, built_pass.viewport_height);
+    }
+
+    fn pass_name(&self) -> String {
+        self.graph
+            .get_built_pass(self.pass_handle)
+            .label_name
+            .to_string_lossy()
+            .into_owned()
+    }
+}
@@ -0,0 +1,446 @@
+use crate::*;
+use glam::*;
+
+const GOLDEN_DIR: &str = "assets/goldens";
+const UPDATE_GOLDENS_ENV_VAR: &str = "GRAPHENE_UPDATE_GOLDENS";
+const AMBIENT: f32 = 0.1; // Of the base color, on faces that point away from the light
+
+/* A mesh as the reference rasterizer draws it: shaded with one directional
+light, in a single base color. */
+pub struct ReferenceObject<'a> {
+    pub mesh_data: &'a MeshData,
+    pub mtx_obj_to_world: Mat4,
+    pub base_color: Color,
+}
+
+/* Renders small images on the CPU, to test the math that scenes are drawn
+with (vertex transforms, projection, culling and color conversion) without a
+GPU. It isn't a renderer for users. It follows the conventions of the
+graphics pipeline: framebuffer Y points down, depth goes from 0 at the near
+plane to 1 at the far plane, counter-clockwise triangles face the front, and
+texels are sampled at their centers. Triangles that reach behind the camera
+are dropped instead of clipped, and there is no top-left fill rule, so
+texels exactly on a shared edge go to the nearer or earlier triangle. */
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReferenceImage {
+    pub width: u32,
+    pub height: u32,
+    pub texels: Vec<[u8; 3]>, // sRGB-encoded, row by row from the top
+}
+
+impl ReferenceImage {
+    pub fn rasterize(
+        objects: &[ReferenceObject],
+        camera: &Camera,
+        light_dir: Vec3, // Towards the light, in world space
+        clear_color: Color,
+        width: u32,
+        height: u32,
+    ) -> ReferenceImage {
+        let num_texels = (width * height) as usize;
+        let [r, g, b, _] = clear_color.to_srgba8();
+        let mut texels = vec![[r, g, b]; num_texels];
+        let mut depths = vec![1.0_f32; num_texels];
+        let mtx_world_to_clip =
+            camera.view_to_clip(width as f32 / height as f32) * camera.world_to_view();
+        let light_dir = light_dir.normalize();
+
+        for object in objects {
+            let mtx_obj_to_clip = mtx_world_to_clip * object.mtx_obj_to_world;
+            let mtx_norm_obj_to_world = object.mtx_obj_to_world.inverse().transpose();
+            let vertex = |idx: u32| {
+                let floats = &object.mesh_data.vertices[idx as usize * 6..idx as usize * 6 + 6];
+                let position = Vec3::new(floats[0], floats[1], floats[2]);
+                let normal = Vec3::new(floats[3], floats[4], floats[5]);
+                (
+                    mtx_obj_to_clip * position.extend(1.0),
+                    mtx_norm_obj_to_world.transform_vector3(normal),
+                )
+            };
+            for triangle in object.mesh_data.indices.chunks_exact(3) {
+                let corners = [
+                    vertex(triangle[0]),
+                    vertex(triangle[1]),
+                    vertex(triangle[2]),
+                ];
+                if corners.iter().any(|(clip, _)| clip.w() <= 0.0) {
+                    continue;
+                }
+                // Viewport transform, without flipping Y
+                let screen: Vec<Vec3> = corners
+                    .iter()
+                    .map(|(clip, _)| {
+                        let ndc = clip.truncate() / clip.w();
+                        Vec3::new(
+                            (ndc.x() + 1.0) * 0.5 * width as f32,
+                            (ndc.y() + 1.0) * 0.5 * height as f32,
+                            ndc.z(),
+                        )
+                    })
+                    .collect();
+                let area = edge_function(screen[0], screen[1], screen[2]);
+                let is_front_facing = area > 0.0;
+                if area == 0.0 || (!is_front_facing && !object.mesh_data.is_double_sided) {
+                    continue;
+                }
+
+                let min_x = screen.iter().map(|p| p.x()).fold(f32::MAX, f32::min);
+                let max_x = screen.iter().map(|p| p.x()).fold(-f32::MAX, f32::max);
+                let min_y = screen.iter().map(|p| p.y()).fold(f32::MAX, f32::min);
+                let max_y = screen.iter().map(|p| p.y()).fold(-f32::MAX, f32::max);
+                let x_range = texel_range(min_x, max_x, width);
+                let y_range = texel_range(min_y, max_y, height);
+                for y in y_range {
+                    for x in x_range.clone() {
+                        let center = Vec3::new(x as f32 + 0.5, y as f32 + 0.5, 0.0);
+                        let weights = [
+                            edge_function(screen[1], screen[2], center) / area,
+                            edge_function(screen[2], screen[0], center) / area,
+                            edge_function(screen[0], screen[1], center) / area,
+                        ];
+                        if weights.iter().any(|w| *w < 0.0) {
+                            continue;
+                        }
+                        // Depth is linear in screen space, and normals in clip space
+                        let depth: f32 = (0..3).map(|i| weights[i] * screen[i].z()).sum();
+                        let texel_idx = (y * width + x) as usize;
+                        if !(0.0..=1.0).contains(&depth) || depth >= depths[texel_idx] {
+                            continue;
+                        }
+                        depths[texel_idx] = depth;
+                        let inv_ws: Vec<f32> =
+                            corners.iter().map(|(clip, _)| 1.0 / clip.w()).collect();
+                        let inv_w: f32 = (0..3).map(|i| weights[i] * inv_ws[i]).sum();
+                        let mut normal = (0..3).fold(Vec3::zero(), |sum, i| {
+                            sum + corners[i].1 * (weights[i] * inv_ws[i] / inv_w)
+                        });
+                        if !is_front_facing {
+                            normal = -normal;
+                        }
+                        let diffuse = normal.normalize().dot(light_dir).max(0.0);
+                        let brightness = AMBIENT + (1.0 - AMBIENT) * diffuse;
+                        let color = Color::from_linear_f32(
+                            object.base_color.r * brightness,
+                            object.base_color.g * brightness,
+                            object.base_color.b * brightness,
+                            1.0,
+                        );
+                        let [r, g, b, _] = color.to_srgba8();
+                        texels[texel_idx] = [r, g, b];
+                    }
+                }
+            }
+        }
+
+        ReferenceImage {
+            width,
+            height,
+            texels,
+        }
+    }
+
+    /// Binary PPM, which most image viewers open.
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut bytes = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        for texel in &self.texels {
+            bytes.extend_from_slice(texel);
+        }
+        bytes
+    }
+
+    /// Only reads what `to_ppm()` writes.
+    pub fn from_ppm(bytes: &[u8]) -> Result<ReferenceImage, String> {
+        let mut fields = Vec::new();
+        let mut pos = 0;
+        while fields.len() < 4 {
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            let start = pos;
+            while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err(String::from("The PPM header is truncated."));
+            }
+            fields.push(String::from_utf8_lossy(&bytes[start..pos]).into_owned());
+        }
+        pos += 1; // The single whitespace byte before the texels
+        let parse = |field: &str| {
+            field
+                .parse::<u32>()
+                .map_err(|_| format!("`{}` isn't a valid PPM size.", field))
+        };
+        if fields[0] != "P6" || fields[3] != "255" {
+            return Err(String::from("Only 8-bit binary PPMs are supported."));
+        }
+        let (width, height) = (parse(&fields[1])?, parse(&fields[2])?);
+        let texel_bytes = bytes.get(pos..).unwrap_or(&[]);
+        if texel_bytes.len() != (width * height * 3) as usize {
+            return Err(format!(
+                "The texels of a {}x{} PPM take {} bytes, not {}.",
+                width,
+                height,
+                width * height * 3,
+                texel_bytes.len()
+            ));
+        }
+        Ok(ReferenceImage {
+            width,
+            height,
+            texels: texel_bytes
+                .chunks_exact(3)
+                .map(|c| [c[0], c[1], c[2]])
+                .collect(),
+        })
+    }
+
+    /// Number of texels where any channel differs from `other` by more than
+    /// `tolerance`. Images of different sizes differ everywhere.
+    pub fn num_differing_texels(&self, other: &ReferenceImage, tolerance: u8) -> usize {
+        if (self.width, self.height) != (other.width, other.height) {
+            return self.texels.len().max(other.texels.len());
+        }
+        self.texels
+            .iter()
+            .zip(&other.texels)
+            .filter(|(a, b)| (0..3).any(|i| a[i].abs_diff(b[i]) > tolerance))
+            .count()
+    }
+}
+
+/* Twice the signed area of the triangle (a, b, p), in framebuffer space. It
+is positive for triangles that the pipeline treats as counter-clockwise. */
+fn edge_function(a: Vec3, b: Vec3, p: Vec3) -> f32 {
+    (p.x() - a.x()) * (b.y() - a.y()) - (p.y() - a.y()) * (b.x() - a.x())
+}
+
+// Texels whose centers may be inside the interval, clamped to the image
+fn texel_range(min: f32, max: f32, size: u32) -> std::ops::Range<u32> {
+    let start = (min - 0.5).ceil().max(0.0) as u32;
+    let end = ((max - 0.5).floor() + 1.0).clamp(0.0, size as f32) as u32;
+    start.min(end)..end
+}
+
+/* Compare against the golden image of that name in `assets/goldens`. Set
+`GRAPHENE_UPDATE_GOLDENS=1` to write the goldens instead, after checking
+that the changes are intended. */
+pub fn check_golden(name: &str, image: &ReferenceImage, tolerance: u8) -> Result<(), String> {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join(GOLDEN_DIR)
+        .join(format!("{}.ppm", name));
+    if std::env::var(UPDATE_GOLDENS_ENV_VAR).is_ok_and(|value| value == "1") {
+        std::fs::create_dir_all(path.parent().unwrap())
+            .map_err(|e| format!("Couldn't create `{}`: {}", GOLDEN_DIR, e))?;
+        return std::fs::write(&path, image.to_ppm())
+            .map_err(|e| format!("Couldn't write `{}`: {}", path.display(), e));
+    }
+    let bytes = std::fs::read(&path).map_err(|e| {
+        format!(
+            "Couldn't read `{}`: {}. Set {}=1 to write it.",
+            path.display(),
+            e,
+            UPDATE_GOLDENS_ENV_VAR
+        )
+    })?;
+    let golden = ReferenceImage::from_ppm(&bytes)?;
+    let num_differing = image.num_differing_texels(&golden, tolerance);
+    if num_differing > 0 {
+        let actual_path = path.with_extension("actual.ppm");
+        let _ = std::fs::write(&actual_path, image.to_ppm());
+        return Err(format!(
+            "{} texels differ from `{}`. The result is in `{}`.",
+            num_differing,
+            path.display(),
+            actual_path.display()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u32 = 64;
+
+    // A cube from -1 to 1, with a normal per face
+    fn cube_mesh_data() -> MeshData {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let axes = [Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z()];
+        for axis in 0..3 {
+            for &sign in &[1.0_f32, -1.0] {
+                let normal = axes[axis] * sign;
+                // Counter-clockwise when seen from outside the cube
+                let u = axes[(axis + 1) % 3] * sign;
+                let v = axes[(axis + 2) % 3];
+                let base = (vertices.len() / 6) as u32;
+                for (a, b) in &[(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                    let position = normal + u * *a + v * *b;
+                    vertices.extend_from_slice(&[position.x(), position.y(), position.z()]);
+                    vertices.extend_from_slice(&[normal.x(), normal.y(), normal.z()]);
+                }
+                indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+            }
+        }
+        MeshData {
+            aabb: Aabb {
+                min: Vec3::splat(-1.0),
+                max: Vec3::splat(1.0),
+            },
+            vertices,
+            indices,
+            is_double_sided: false,
+        }
+    }
+
+    // One triangle facing +Z, counter-clockwise when seen from there
+    fn triangle_mesh_data(is_double_sided: bool) -> MeshData {
+        let positions = [
+            Vec3::new(-1.0, -1.0, 0.0),
+            Vec3::new(1.0, -1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ];
+        let mut vertices = Vec::new();
+        for position in &positions {
+            vertices.extend_from_slice(&[position.x(), position.y(), position.z()]);
+            vertices.extend_from_slice(&[0.0, 0.0, 1.0]);
+        }
+        MeshData {
+            aabb: Aabb::from_points(positions.iter().copied()),
+            vertices,
+            indices: vec![0, 1, 2],
+            is_double_sided,
+        }
+    }
+
+    // On the Z axis, looking at the origin
+    fn camera_at_z(z: f32) -> Camera {
+        let rotation = if z > 0.0 {
+            Quat::identity()
+        } else {
+            Quat::from_rotation_y(std::f32::consts::PI)
+        };
+        Camera::new(Vec3::new(0.0, 0.0, z), rotation)
+    }
+
+    fn texel(image: &ReferenceImage, x: u32, y: u32) -> [u8; 3] {
+        image.texels[(y * image.width + x) as usize]
+    }
+
+    #[test]
+    fn cube_matches_golden() {
+        let mesh_data = cube_mesh_data();
+        let objects = [ReferenceObject {
+            mesh_data: &mesh_data,
+            mtx_obj_to_world: Mat4::from_rotation_y(0.6) * Mat4::from_rotation_x(0.5),
+            base_color: Color::from_srgb8(200, 120, 40),
+        }];
+        let camera = camera_at_z(5.0);
+        let image = ReferenceImage::rasterize(
+            &objects,
+            &camera,
+            Vec3::new(0.3, 1.0, 0.6),
+            Color::from_srgb8(32, 32, 40),
+            SIZE,
+            SIZE,
+        );
+        check_golden("cube", &image, 1).unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    #[test]
+    fn overlapping_triangles_match_golden() {
+        let front = triangle_mesh_data(false);
+        let objects = [
+            // Drawn first, but in front of the other one
+            ReferenceObject {
+                mesh_data: &front,
+                mtx_obj_to_world: Mat4::from_translation(Vec3::new(-0.4, 0.0, 0.5)),
+                base_color: Color::from_srgb8(220, 40, 40),
+            },
+            ReferenceObject {
+                mesh_data: &front,
+                mtx_obj_to_world: Mat4::from_translation(Vec3::new(0.4, 0.2, -0.5)),
+                base_color: Color::from_srgb8(40, 80, 220),
+            },
+        ];
+        let camera = camera_at_z(4.0);
+        let image =
+            ReferenceImage::rasterize(&objects, &camera, Vec3::unit_z(), Color::BLACK, SIZE, SIZE);
+        check_golden("overlapping_triangles", &image, 1).unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    #[test]
+    fn world_up_is_the_top_of_the_image() {
+        let mesh_data = triangle_mesh_data(false);
+        let objects = [ReferenceObject {
+            mesh_data: &mesh_data,
+            mtx_obj_to_world: Mat4::identity(),
+            base_color: Color::WHITE,
+        }];
+        let camera = camera_at_z(3.0);
+        let image =
+            ReferenceImage::rasterize(&objects, &camera, Vec3::unit_z(), Color::BLACK, SIZE, SIZE);
+        // The apex of the triangle is up, so the bottom row is wider than the top
+        let row_coverage = |y: u32| {
+            (0..SIZE)
+                .filter(|x| texel(&image, *x, y) != [0, 0, 0])
+                .count()
+        };
+        let covered_rows: Vec<u32> = (0..SIZE).filter(|y| row_coverage(*y) > 0).collect();
+        let (top, bottom) = (covered_rows[0], *covered_rows.last().unwrap());
+        assert!(row_coverage(top) < row_coverage(bottom));
+        assert_eq!(texel(&image, SIZE / 2, (top + bottom) / 2), [255, 255, 255]);
+    }
+
+    #[test]
+    fn back_faces_are_culled_unless_double_sided() {
+        let camera = camera_at_z(-3.0);
+        let render = |mesh_data: &MeshData| {
+            let objects = [ReferenceObject {
+                mesh_data,
+                mtx_obj_to_world: Mat4::identity(),
+                base_color: Color::WHITE,
+            }];
+            // The light is behind the camera, so lit back faces are white
+            ReferenceImage::rasterize(&objects, &camera, -Vec3::unit_z(), Color::BLACK, SIZE, SIZE)
+        };
+        let culled = render(&triangle_mesh_data(false));
+        assert!(culled.texels.iter().all(|texel| *texel == [0, 0, 0]));
+        let double_sided = render(&triangle_mesh_data(true));
+        assert_eq!(texel(&double_sided, SIZE / 2, SIZE / 2), [255, 255, 255]);
+    }
+
+    #[test]
+    fn nearer_triangles_win_regardless_of_order() {
+        let mesh_data = triangle_mesh_data(false);
+        let camera = camera_at_z(3.0);
+        let render = |z_order: [f32; 2]| {
+            let colors = [Color::from_srgb8(255, 0, 0), Color::from_srgb8(0, 0, 255)];
+            let objects: Vec<ReferenceObject> = (0..2)
+                .map(|i| ReferenceObject {
+                    mesh_data: &mesh_data,
+                    mtx_obj_to_world: Mat4::from_translation(Vec3::new(0.0, 0.0, z_order[i])),
+                    base_color: colors[i],
+                })
+                .collect();
+            let image =
+                ReferenceImage::rasterize(&objects, &camera, Vec3::unit_z(), Color::BLACK, 8, 8);
+            texel(&image, 4, 4)
+        };
+        assert_eq!(render([0.5, 0.0]), [255, 0, 0]);
+        assert_eq!(render([0.0, 0.5]), [0, 0, 255]);
+    }
+
+    #[test]
+    fn ppm_round_trips() {
+        let image = ReferenceImage {
+            width: 2,
+            height: 1,
+            texels: vec![[1, 2, 3], [250, 251, 252]],
+        };
+        assert_eq!(ReferenceImage::from_ppm(&image.to_ppm()), Ok(image));
+        assert!(ReferenceImage::from_ppm(b"P6\n2 1\n255\n\x01\x02").is_err());
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+/* A reference to a resource in a `Registry`: the slot it lives in, and the
+generation of the slot when it was created. Destroying the resource bumps the
+generation, so handles that outlive it are caught on their next use instead of
+reaching whatever reuses the slot. Replacing the resource in place, e.g. when
+hot reloading, keeps its handles valid. */
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    _marker: PhantomData<fn() -> T>, // Handles are Send and Sync, whatever they point to
+}
+
+impl<T> Handle<T> {
+    /// For handles that aren't in a registry, e.g. `RING_UNIFORM_BUFFER`.
+    pub const fn from_raw_parts(index: u32, generation: u32) -> Handle<T> {
+        Handle {
+            index,
+            generation,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn index(self) -> u32 {
+        self.index
+    }
+
+    pub fn generation(self) -> u32 {
+        self.generation
+    }
+}
+
+// Implemented by hand, so that they don't require `T` to implement them
+impl<T> Copy for Handle<T> {}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Handle<T> {
+        *self
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Handle<T>) -> bool {
+        (self.index, self.generation) == (other.index, other.generation)
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}v{}", self.index, self.generation)
+    }
+}
+
+struct Slot<T> {
+    generation: u32,
+    name: String, // Of the resource in the slot, or the last one, for errors about stale handles
+    opt_value: Option<T>,
+}
+
+/* Resources of one type, by handle and by name. Names are unique among the
+live resources. Freed slots are reused by later resources, with a new
+generation. Internal, but reachable through the resource lists. */
+#[doc(hidden)]
+pub struct Registry<T> {
+    kind: &'static str, // E.g. "image", for errors
+    slots: Vec<Slot<T>>,
+    free_indices: Vec<u32>,
+}
+
+impl<T> Registry<T> {
+    pub fn new(kind: &'static str) -> Registry<T> {
+        Registry {
+            kind,
+            slots: Vec::new(),
+            free_indices: Vec::new(),
+        }
+    }
+
+    pub fn insert(&mut self, name: &str, value: T) -> Result<Handle<T>, String> {
+        if self.find(name).is_some() {
+            return Err(format!(
+                "The name `{}` is already taken by another {} in the context.",
+                name, self.kind
+            ));
+        }
+        let index = match self.free_indices.pop() {
+            Some(index) => index,
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    name: String::new(),
+                    opt_value: None,
+                });
+                (self.slots.len() - 1) as u32
+            }
+        };
+        let slot = &mut self.slots[index as usize];
+        slot.name = String::from(name);
+        slot.opt_value = Some(value);
+        Ok(Handle::from_raw_parts(index, slot.generation))
+    }
+
+    pub fn get(&self, handle: Handle<T>) -> Result<&T, String> {
+        let slot = self.live_slot(handle)?;
+        Ok(slot.opt_value.as_ref().unwrap())
+    }
+
+    pub fn get_mut(&mut self, handle: Handle<T>) -> Result<&mut T, String> {
+        self.live_slot(handle)?;
+        Ok(self.slots[handle.index as usize]
+            .opt_value
+            .as_mut()
+            .unwrap())
+    }
+
+    /// Swap in a new resource behind the same handles, returning the old one.
+    pub fn replace(&mut self, handle: Handle<T>, value: T) -> Result<T, String> {
+        let old_value = self.get_mut(handle)?;
+        Ok(std::mem::replace(old_value, value))
+    }
+
+    /// Destroy a resource. Its handles are stale from now on.
+    pub fn remove(&mut self, handle: Handle<T>) -> Result<T, String> {
+        self.live_slot(handle)?;
+        let slot = &mut self.slots[handle.index as usize];
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_indices.push(handle.index);
+        Ok(slot.opt_value.take().unwrap())
+    }
+
+    /// Remove the resources that `is_kept` returns false for.
+    pub fn retain(&mut self, mut is_kept: impl FnMut(&T) -> bool) {
+        let removed: Vec<Handle<T>> = self
+            .iter()
+            .filter(|(_, value)| !is_kept(value))
+            .map(|(handle, _)| handle)
+            .collect();
+        for handle in removed {
+            self.remove(handle).unwrap();
+        }
+    }
+
+    pub fn find(&self, name: &str) -> Option<Handle<T>> {
+        self.slots
+            .iter()
+            .enumerate()
+            .find(|(_, slot)| slot.opt_value.is_some() && slot.name == name)
+            .map(|(index, slot)| Handle::from_raw_parts(index as u32, slot.generation))
+    }
+
+    pub fn name(&self, handle: Handle<T>) -> Result<&str, String> {
+        Ok(&self.live_slot(handle)?.name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free_indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Handle<T>, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.opt_value
+                .as_ref()
+                .map(|value| (Handle::from_raw_parts(index as u32, slot.generation), value))
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Handle<T>, &mut T)> {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                let generation = slot.generation;
+                slot.opt_value
+                    .as_mut()
+                    .map(|value| (Handle::from_raw_parts(index as u32, generation), value))
+            })
+    }
+
+    // The slot of a handle that is still valid, or an error that names what it pointed to
+    fn live_slot(&self, handle: Handle<T>) -> Result<&Slot<T>, String> {
+        let slot = self.slots.get(handle.index as usize).ok_or_else(|| {
+            format!(
+                "The {} handle `{:?}` doesn't belong to this context.",
+                self.kind, handle
+            )
+        })?;
+        if slot.generation != handle.generation || slot.opt_value.is_none() {
+            // The slot keeps the name until another resource reuses it
+            let is_named =
+                slot.opt_value.is_none() && slot.generation.wrapping_sub(handle.generation) == 1;
+            return Err(if is_named {
+                format!(
+                    "The {} handle `{:?}` is stale, since {} `{}` was destroyed.",
+                    self.kind, handle, self.kind, slot.name
+                )
+            } else {
+                format!(
+                    "The {} handle `{:?}` is stale, since its {} was destroyed.",
+                    self.kind, handle, self.kind
+                )
+            });
+        }
+        Ok(slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handles_find_their_values() {
+        let mut registry = Registry::new("image");
+        let a = registry.insert("a", 1).unwrap();
+        let b = registry.insert("b", 2).unwrap();
+        assert_eq!(*registry.get(a).unwrap(), 1);
+        assert_eq!(*registry.get(b).unwrap(), 2);
+        assert_eq!(registry.find("b"), Some(b));
+        assert_eq!(registry.find("c"), None);
+        assert_eq!(registry.len(), 2);
+    }
+
+    #[test]
+    fn names_are_unique_among_live_values() {
+        let mut registry = Registry::new("image");
+        let a = registry.insert("a", 1).unwrap();
+        assert!(registry.insert("a", 2).is_err());
+        registry.remove(a).unwrap();
+        assert!(registry.insert("a", 3).is_ok());
+    }
+
+    #[test]
+    fn removed_handles_are_stale() {
+        let mut registry = Registry::new("image");
+        let a = registry.insert("albedo", 1).unwrap();
+        assert_eq!(registry.remove(a), Ok(1));
+        let err = registry.get(a).unwrap_err();
+        assert!(err.contains("stale"), "{}", err);
+        assert!(err.contains("albedo"), "{}", err);
+        assert!(registry.get_mut(a).is_err());
+        assert!(registry.remove(a).is_err());
+        assert!(registry.replace(a, 2).is_err());
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn reused_slots_reject_stale_handles() {
+        let mut registry = Registry::new("image");
+        let a = registry.insert("a", 1).unwrap();
+        registry.remove(a).unwrap();
+        let b = registry.insert("b", 2).unwrap();
+        // Same slot, new generation
+        assert_eq!(a.index(), b.index());
+        assert_ne!(a.generation(), b.generation());
+        assert_ne!(a, b);
+        assert!(registry.get(a).is_err());
+        assert_eq!(*registry.get(b).unwrap(), 2);
+        // Several generations later, the error can't name the value anymore
+        registry.remove(b).unwrap();
+        let c = registry.insert("c", 3).unwrap();
+        let err = registry.get(a).unwrap_err();
+        assert!(err.contains("stale") && !err.contains("`c`"), "{}", err);
+        assert_eq!(*registry.get(c).unwrap(), 3);
+    }
+
+    #[test]
+    fn replaced_values_keep_their_handles() {
+        let mut registry = Registry::new("shader");
+        let a = registry.insert("a", 1).unwrap();
+        assert_eq!(registry.replace(a, 2), Ok(1));
+        assert_eq!(*registry.get(a).unwrap(), 2);
+        assert_eq!(registry.find("a"), Some(a));
+    }
+
+    #[test]
+    fn foreign_handles_are_rejected() {
+        let registry: Registry<i32> = Registry::new("buffer");
+        let err = registry.get(Handle::from_raw_parts(7, 0)).unwrap_err();
+        assert!(err.contains("doesn't belong"), "{}", err);
+    }
+
+    #[test]
+    fn retain_removes_values_and_skips_them_in_iteration() {
+        let mut registry = Registry::new("image");
+        let handles: Vec<Handle<i32>> = (0..4)
+            .map(|i| registry.insert(&i.to_string(), i).unwrap())
+            .collect();
+        registry.retain(|value| value % 2 == 0);
+        let values: Vec<i32> = registry.iter().map(|(_, value)| *value).collect();
+        assert_eq!(values, vec![0, 2]);
+        assert!(registry.get(handles[1]).is_err());
+        assert!(registry.get(handles[3]).is_err());
+        for (_, value) in registry.iter_mut() {
+            *value *= 10;
+        }
+        assert_eq!(*registry.get(handles[2]).unwrap(), 20);
+    }
+}
@@ -1,30 +1,159 @@
 use crate::*;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AnisotropyLevel {
+    Off,
+    X2,
+    X4,
+    X8,
+    X16,
+}
+
+impl AnisotropyLevel {
+    pub fn max_anisotropy(self) -> f32 {
+        match self {
+            AnisotropyLevel::Off => 1.0,
+            AnisotropyLevel::X2 => 2.0,
+            AnisotropyLevel::X4 => 4.0,
+            AnisotropyLevel::X8 => 8.0,
+            AnisotropyLevel::X16 => 16.0,
+        }
+    }
+
+    /// For cycling through the levels with a key. Wraps around to `Off`.
+    pub fn next(self) -> AnisotropyLevel {
+        match self {
+            AnisotropyLevel::Off => AnisotropyLevel::X2,
+            AnisotropyLevel::X2 => AnisotropyLevel::X4,
+            AnisotropyLevel::X4 => AnisotropyLevel::X8,
+            AnisotropyLevel::X8 => AnisotropyLevel::X16,
+            AnisotropyLevel::X16 => AnisotropyLevel::Off,
+        }
+    }
+}
+
+/* Everything that a sampler is created from. Samplers with equal
+descriptions are interchangeable, so `SamplerCache` creates one per
+description. The default is trilinear, repeating, and as anisotropic as the
+device allows. */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerDesc {
+    pub mag_filter: Filter,
+    pub min_filter: Filter,
+    pub mip_filter: Filter,
+    pub address_mode: AddressMode, // In all three directions
+    pub anisotropy: AnisotropyLevel,
+    pub opt_max_mip: Option<u32>, // None samples every mip of the view
+    pub opt_compare_op: Option<CompareOp>, // For depth comparisons, e.g. shadow maps
+}
+
+impl Default for SamplerDesc {
+    fn default() -> SamplerDesc {
+        SamplerDesc {
+            mag_filter: Filter::Linear,
+            min_filter: Filter::Linear,
+            mip_filter: Filter::Linear,
+            address_mode: AddressMode::Repeat,
+            anisotropy: AnisotropyLevel::X16,
+            opt_max_mip: None,
+            opt_compare_op: None,
+        }
+    }
+}
+
+impl SamplerDesc {
+    /// For debuggers, e.g. `sampler_linear_repeat_x16`.
+    pub fn to_name(&self) -> String {
+        let mut name = String::from("sampler");
+        if self.mag_filter == self.min_filter && self.min_filter == self.mip_filter {
+            name.push_str(&format!("_{:?}", self.mag_filter));
+        } else {
+            name.push_str(&format!(
+                "_{:?}_{:?}_{:?}",
+                self.mag_filter, self.min_filter, self.mip_filter
+            ));
+        }
+        name.push_str(&format!("_{:?}_{:?}", self.address_mode, self.anisotropy));
+        if let Some(max_mip) = self.opt_max_mip {
+            name.push_str(&format!("_mip{}", max_mip));
+        }
+        if let Some(compare_op) = self.opt_compare_op {
+            name.push_str(&format!("_{:?}", compare_op));
+        }
+        name.to_lowercase()
+    }
+}
 
 pub struct Sampler {
     device: ash::Device,
     pub vk_sampler: vk::Sampler,
+    pub desc: SamplerDesc,   // As requested
+    pub max_anisotropy: f32, // As created, after clamping to what the device supports
 }
 
 impl Drop for Sampler {
     fn drop(&mut self) {
+        track_destroyed(&LIVE_SAMPLERS);
         unsafe {
             self.device.destroy_sampler(self.vk_sampler, None);
         }
+        audit_destroyed(VkObjectKind::Sampler, self.vk_sampler);
     }
 }
 
 impl Sampler {
     pub fn new(gpu: &Gpu) -> Sampler {
+        Sampler::new_from_desc(&SamplerDesc::default(), gpu)
+    }
+
+    pub fn new_with_anisotropy(anisotropy: AnisotropyLevel, gpu: &Gpu) -> Sampler {
+        Sampler::new_from_desc(
+            &SamplerDesc {
+                anisotropy,
+                ..SamplerDesc::default()
+            },
+            gpu,
+        )
+    }
+
+    /* The anisotropy level is clamped to the device's limit, and anisotropy
+    is turned off on devices without the feature. Samplers are immutable, so
+    changing the description means creating a new sampler, and rebuilding the
+    graphs that use the old one. Prefer `SamplerCache::get()`, which shares
+    samplers and names them. */
+    pub fn new_from_desc(desc: &SamplerDesc, gpu: &Gpu) -> Sampler {
+        let is_supported = gpu.enabled_features.sampler_anisotropy == vk::TRUE;
+        let max_anisotropy = if is_supported {
+            desc.anisotropy
+                .max_anisotropy()
+                .min(gpu.properties.limits.max_sampler_anisotropy)
+        } else {
+            1.0
+        };
+        // The LOD isn't clamped below the level count of the sampled view
+        let max_lod = desc
+            .opt_max_mip
+            .map(|max_mip| max_mip as f32)
+            .unwrap_or(vk::LOD_CLAMP_NONE);
         let vk_sampler = {
             let sampler_create_info = vk::SamplerCreateInfo::builder()
-                .mag_filter(vk::Filter::LINEAR)
-                .min_filter(vk::Filter::LINEAR)
-                .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-                .address_mode_u(vk::SamplerAddressMode::REPEAT)
-                .address_mode_v(vk::SamplerAddressMode::REPEAT)
-                .address_mode_w(vk::SamplerAddressMode::REPEAT)
-                .anisotropy_enable(true) // TODO: Disable this by default?
-                .max_anisotropy(16.0) //
+                .mag_filter(desc.mag_filter.to_vk())
+                .min_filter(desc.min_filter.to_vk())
+                .mipmap_mode(desc.mip_filter.to_vk_mipmap_mode())
+                .address_mode_u(desc.address_mode.to_vk())
+                .address_mode_v(desc.address_mode.to_vk())
+                .address_mode_w(desc.address_mode.to_vk())
+                .anisotropy_enable(max_anisotropy > 1.0)
+                .max_anisotropy(max_anisotropy)
+                .compare_enable(desc.opt_compare_op.is_some())
+                .compare_op(
+                    desc.opt_compare_op
+                        .map(CompareOp::to_vk)
+                        .unwrap_or(vk::CompareOp::NEVER),
+                )
+                .max_lod(max_lod)
                 .border_color(vk::BorderColor::INT_OPAQUE_BLACK);
 
             unsafe {
@@ -33,9 +162,51 @@ impl Sampler {
                     .expect("Failed to create Sampler!")
             }
         };
+        track_created(&LIVE_SAMPLERS);
+        audit_created(
+            VkObjectKind::Sampler,
+            vk_sampler,
+            &[device_id(&gpu.device)],
+            &desc.to_name(),
+        );
         Sampler {
             device: gpu.device.clone(),
             vk_sampler,
+            desc: *desc,
+            max_anisotropy,
         }
     }
 }
+
+/* Hands out one sampler per description, so that passes requesting the same
+one share a `vk::Sampler`. Samplers live until the cache is dropped, which
+has to happen before the device is destroyed. */
+#[derive(Default)]
+pub struct SamplerCache {
+    samplers: HashMap<SamplerDesc, Rc<Sampler>>,
+}
+
+impl SamplerCache {
+    pub fn new() -> SamplerCache {
+        SamplerCache::default()
+    }
+
+    pub fn get(&mut self, desc: &SamplerDesc, gpu: &Gpu, debug_utils: &DebugUtils) -> Rc<Sampler> {
+        self.samplers
+            .entry(*desc)
+            .or_insert_with(|| {
+                let sampler = Sampler::new_from_desc(desc, gpu);
+                debug_utils.set_sampler_name(sampler.vk_sampler, &desc.to_name());
+                Rc::new(sampler)
+            })
+            .clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.samplers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samplers.is_empty()
+    }
+}
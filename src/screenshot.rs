@@ -0,0 +1,208 @@
+use crate::*;
+
+pub const SCREENSHOT_DIR: &str = "_cache/screenshots";
+
+/// Where F12 saves a screenshot. Named by the time, so earlier ones are kept.
+pub fn default_screenshot_path() -> String {
+    let millis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or(0);
+    format!("{}/screenshot_{}.png", SCREENSHOT_DIR, millis)
+}
+
+/// The size of a texel of a swapchain format that screenshots support, or an
+/// error for the others.
+pub(crate) fn screenshot_texel_size(format: vk::Format) -> Result<usize, String> {
+    let is_supported = matches!(
+        format,
+        vk::Format::B8G8R8A8_SRGB
+            | vk::Format::B8G8R8A8_UNORM
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::R8G8B8A8_UNORM
+    );
+    match format_texel_size(format) {
+        Some(texel_size) if is_supported => Ok(texel_size),
+        _ => Err(format!(
+            "Screenshots of swapchain format {:?} aren't supported.",
+            format
+        )),
+    }
+}
+
+/* Record a copy of a presentable image into `readback_buffer`, which must
+hold `screenshot_texel_size() * width * height` bytes. Record it after the
+frame's last pass, while the image is in `PRESENT_SRC_KHR`, and it's put back
+in that layout for presenting. The host can read the buffer once the command
+buffer completes. Nothing is recorded if the image's format isn't supported,
+or the buffer is too small. */
+pub fn record_screenshot_copy(
+    image: &Image,
+    readback_buffer: &HostVisibleBuffer,
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+) -> Result<(), String> {
+    let texel_size = screenshot_texel_size(image.format)?;
+    let size = texel_size * (image.width * image.height) as usize;
+    if readback_buffer.size < size {
+        return Err(format!(
+            "Buffer `{}` holds {} bytes, but the screenshot of image `{}` needs {}.",
+            readback_buffer.name, readback_buffer.size, image.name, size
+        ));
+    }
+    let subresource_range = vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    };
+    let to_transfer_src = [vk::ImageMemoryBarrier::builder()
+        .old_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+        .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image.vk_image)
+        .subresource_range(subresource_range)
+        .build()];
+    let to_present_src = [vk::ImageMemoryBarrier::builder()
+        .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+        .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+        .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+        .dst_access_mask(vk::AccessFlags::empty())
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image.vk_image)
+        .subresource_range(subresource_range)
+        .build()];
+    // Make the copy visible to the host
+    let host_barriers = [vk::MemoryBarrier {
+        src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+        dst_access_mask: vk::AccessFlags::HOST_READ,
+        ..Default::default()
+    }];
+    // Rows are tightly packed, so the row pitch is `texel_size * width`
+    let copy_regions = [vk::BufferImageCopy {
+        buffer_offset: 0,
+        buffer_row_length: image.width,
+        buffer_image_height: image.height,
+        image_subresource: vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+        image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+        image_extent: vk::Extent3D {
+            width: image.width,
+            height: image.height,
+            depth: 1,
+        },
+    }];
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &to_transfer_src,
+        );
+        device.cmd_copy_image_to_buffer(
+            command_buffer,
+            image.vk_image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            readback_buffer.vk_buffer,
+            &copy_regions,
+        );
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &host_barriers,
+            &[],
+            &[],
+        );
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &to_present_src,
+        );
+    }
+    Ok(())
+}
+
+/* Convert copied texels to tightly packed RGBA8, with opaque alpha, since
+whatever the swapchain holds in alpha isn't meant to be seen. Only 8-bit
+BGRA and RGBA formats are supported. sRGB formats are stored as they are,
+which is what PNG viewers expect. */
+pub fn screenshot_to_rgba8(
+    texels: &[u8],
+    width: u32,
+    height: u32,
+    row_pitch: usize,
+    format: vk::Format,
+) -> Result<Vec<u8>, String> {
+    screenshot_texel_size(format)?;
+    let mut rgba = texels_to_rgba8(texels, width, height, row_pitch, format)?;
+    for texel in rgba.chunks_exact_mut(4) {
+        texel[3] = 255;
+    }
+    Ok(rgba)
+}
+
+/* Convert texels read back from an image to tightly packed RGBA8, keeping
+alpha. 8-bit formats with one, two or four channels are supported, and
+channels that the format doesn't store are read like a shader samples them,
+e.g. R8 to (r, 0, 0, 255). sRGB values are kept as they are encoded. */
+pub fn texels_to_rgba8(
+    texels: &[u8],
+    width: u32,
+    height: u32,
+    row_pitch: usize,
+    format: vk::Format,
+) -> Result<Vec<u8>, String> {
+    let num_channels = match format {
+        vk::Format::R8_UNORM | vk::Format::R8_SRGB => 1,
+        vk::Format::R8G8_UNORM | vk::Format::R8G8_SRGB => 2,
+        vk::Format::B8G8R8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::R8G8B8A8_UNORM => 4,
+        _ => return Err(format!("Converting {:?} to RGBA8 isn't supported.", format)),
+    };
+    let is_bgra = format == vk::Format::B8G8R8A8_SRGB || format == vk::Format::B8G8R8A8_UNORM;
+    let row_size = num_channels * width as usize;
+    if row_pitch < row_size || texels.len() < row_pitch * height as usize {
+        return Err(String::from("The texels don't cover the image."));
+    }
+    let mut rgba = Vec::with_capacity(4 * width as usize * height as usize);
+    for row in texels.chunks(row_pitch).take(height as usize) {
+        for texel in row[..row_size].chunks_exact(num_channels) {
+            match num_channels {
+                1 => rgba.extend_from_slice(&[texel[0], 0, 0, 255]),
+                2 => rgba.extend_from_slice(&[texel[0], texel[1], 0, 255]),
+                _ if is_bgra => rgba.extend_from_slice(&[texel[2], texel[1], texel[0], texel[3]]),
+                _ => rgba.extend_from_slice(texel),
+            }
+        }
+    }
+    Ok(rgba)
+}
+
+pub fn save_screenshot_png(path: &str, rgba: &[u8], width: u32, height: u32) -> Result<(), String> {
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Couldn't create a directory for `{}`: {}", path, e))?;
+    }
+    ::image::save_buffer(path, rgba, width, height, ::image::ColorType::Rgba8)
+        .map_err(|e| format!("Couldn't save screenshot to `{}`: {}", path, e))
+}
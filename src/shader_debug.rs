@@ -0,0 +1,188 @@
+use crate::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/* Shader-side assertions. Every pass has a storage buffer at this binding of
+set 0, which shaders append failed assertions to through
+`assets/shaders/shader_debug.glsl`. The buffer is read back once the GPU is
+done with the frame, and the failures are decoded and logged.
+
+Each pass of each frame in flight writes to its own region of one buffer,
+selected with a dynamic offset, so that failures can be attributed to the
+pass that they came from. The constants must match shader_debug.glsl. */
+pub const SHADER_DEBUG_BINDING: u32 = 2;
+const MAX_RECORDS_PER_PASS: usize = 32;
+// Passes past this many in a frame share the last region
+const MAX_PASSES_PER_FRAME: usize = 32;
+const HEADER_SIZE: usize = 16; // uint count, padded to the alignment of the records
+const RECORD_SIZE: usize = 16; // uint code, payload_0, payload_1, padding
+                               // The same failure in the same pass is logged at most this often
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+// Failure codes
+pub const SHADER_ASSERT_NAN: u32 = 1;
+pub const SHADER_ASSERT_OUT_OF_RANGE: u32 = 2;
+
+struct Report {
+    last_instant: Instant,
+    num_suppressed: usize,
+}
+
+pub struct ShaderDebug {
+    pub buffer: HostVisibleBuffer,
+    pub region_size: usize, // Size of one pass's region, rounded up to the offset alignment
+    pub is_readback_enabled: bool, // Only in debug builds
+    num_frames: usize,
+    /* Names of the passes that write to each region, per frame in flight. The
+    strings are reused across frames, so that claiming a region doesn't
+    allocate. */
+    region_pass_names: RefCell<Vec<Vec<String>>>,
+    num_regions_used: RefCell<Vec<usize>>,
+    reports: HashMap<(String, u32, u32, u32), Report>,
+}
+
+impl ShaderDebug {
+    pub fn new(num_frames: usize, gpu: &Gpu, debug_utils: &DebugUtils) -> ShaderDebug {
+        let alignment = gpu.properties.limits.min_storage_buffer_offset_alignment as usize;
+        let unaligned_size = HEADER_SIZE + MAX_RECORDS_PER_PASS * RECORD_SIZE;
+        let region_size = unaligned_size.div_ceil(alignment) * alignment;
+        let buffer = HostVisibleBuffer::new(
+            "buffer_shader_debug",
+            num_frames * MAX_PASSES_PER_FRAME * region_size,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            gpu,
+            debug_utils,
+        );
+        buffer
+            .upload_data(&vec![0u8; buffer.size], 0)
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        ShaderDebug {
+            buffer,
+            region_size,
+            is_readback_enabled: cfg!(debug_assertions),
+            num_frames,
+            region_pass_names: RefCell::new(vec![Vec::new(); num_frames]),
+            num_regions_used: RefCell::new(vec![0; num_frames]),
+            reports: HashMap::new(),
+        }
+    }
+
+    pub fn num_frames(&self) -> usize {
+        self.num_frames
+    }
+
+    /// Claim a region for a pass that is about to be recorded, and return its dynamic offset.
+    pub fn begin_pass(&self, sync_idx: usize, pass_name: &str) -> u32 {
+        let num_regions_used = &mut self.num_regions_used.borrow_mut()[sync_idx];
+        let region_idx = (*num_regions_used).min(MAX_PASSES_PER_FRAME - 1);
+        if *num_regions_used < MAX_PASSES_PER_FRAME {
+            let pass_names = &mut self.region_pass_names.borrow_mut()[sync_idx];
+            if region_idx < pass_names.len() {
+                pass_names[region_idx].clear();
+                pass_names[region_idx].push_str(pass_name);
+            } else {
+                pass_names.push(String::from(pass_name));
+            }
+            *num_regions_used += 1;
+        }
+        ((sync_idx * MAX_PASSES_PER_FRAME + region_idx) * self.region_size) as u32
+    }
+
+    /* Log the failures of the last frame that used `sync_idx`, and clear its
+    regions for reuse. The GPU must be done with that frame. */
+    pub fn read_back(&mut self, sync_idx: usize) {
+        let num_regions_used = std::mem::take(&mut self.num_regions_used.borrow_mut()[sync_idx]);
+        if num_regions_used == 0 {
+            return;
+        }
+        let region_pass_names = self.region_pass_names.borrow();
+        let pass_names = &region_pass_names[sync_idx][..num_regions_used];
+        let frame_offset = sync_idx * MAX_PASSES_PER_FRAME * self.region_size;
+        let frame_size = num_regions_used * self.region_size;
+        self.buffer
+            .invalidate_mapped_range(frame_offset, frame_size)
+            .unwrap_or_else(|e| panic!("{}", e));
+
+        unsafe {
+            let data_ptr = self.buffer.mapped_slice_mut::<u8>()[frame_offset..].as_mut_ptr();
+
+            if self.is_readback_enabled {
+                for (region_idx, pass_name) in pass_names.iter().enumerate() {
+                    let region_ptr = data_ptr.add(region_idx * self.region_size) as *const u32;
+                    let num_failures = region_ptr.read() as usize;
+                    for record_idx in 0..num_failures.min(MAX_RECORDS_PER_PASS) {
+                        let record_ptr =
+                            region_ptr.add((HEADER_SIZE + record_idx * RECORD_SIZE) / 4);
+                        let code = record_ptr.read();
+                        let payload = (record_ptr.add(1).read(), record_ptr.add(2).read());
+                        report(&mut self.reports, pass_name, code, payload);
+                    }
+                    if num_failures > MAX_RECORDS_PER_PASS {
+                        println!(
+                            "Shader assertions in pass `{}`: {} more failures were dropped.",
+                            pass_name,
+                            num_failures - MAX_RECORDS_PER_PASS
+                        );
+                    }
+                }
+            }
+            // Reset the counts, and with them the records
+            for region_idx in 0..num_regions_used {
+                (data_ptr.add(region_idx * self.region_size) as *mut u32).write(0);
+            }
+        }
+        self.buffer
+            .flush_mapped_range(frame_offset, frame_size)
+            .unwrap_or_else(|e| panic!("{}", e));
+    }
+}
+
+fn report(
+    reports: &mut HashMap<(String, u32, u32, u32), Report>,
+    pass_name: &str,
+    code: u32,
+    payload: (u32, u32),
+) {
+    let now = Instant::now();
+    let key = (String::from(pass_name), code, payload.0, payload.1);
+    if let Some(report) = reports.get_mut(&key) {
+        if now - report.last_instant < REPORT_INTERVAL {
+            report.num_suppressed += 1;
+            return;
+        }
+    }
+    let num_suppressed = reports
+        .insert(
+            key,
+            Report {
+                last_instant: now,
+                num_suppressed: 0,
+            },
+        )
+        .map_or(0, |report| report.num_suppressed);
+
+    let message = match code {
+        SHADER_ASSERT_NAN => String::from("NaN value"),
+        SHADER_ASSERT_OUT_OF_RANGE => format!(
+            "Index {} is out of range for a length of {}",
+            payload.0, payload.1
+        ),
+        _ => format!(
+            "Unknown failure code {} (payload {}, {})",
+            code, payload.0, payload.1
+        ),
+    };
+    if num_suppressed > 0 {
+        println!(
+            "Shader assertion failed in pass `{}`: {} ({} repeats not shown)",
+            pass_name, message, num_suppressed
+        );
+    } else {
+        println!(
+            "Shader assertion failed in pass `{}`: {}",
+            pass_name, message
+        );
+    }
+}
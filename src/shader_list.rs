@@ -1,30 +1,35 @@
 use crate::*;
 use std::path::Path;
 
+#[derive(Copy, Clone)]
 pub enum ShaderStage {
     Vertex,
     Fragment,
+    Compute,
 }
 
 pub struct InternalShader {
     pub name: String,
     pub shader_stage: ShaderStage,
+    pub path: String, // Relative to `assets/shaders`
     pub source_path: String,
     pub spirv_path: String,
+    pub defines: Vec<String>, // Passed to the compiler, each with `-D`
     pub vk_shader_module: vk::ShaderModule,
 }
 
 pub struct ShaderList {
     device: ash::Device,
-    pub list: Vec<(ShaderHandle, InternalShader)>,
+    pub list: Registry<InternalShader>,
 }
 
 impl Drop for ShaderList {
     fn drop(&mut self) {
         unsafe {
-            for (_, shader) in &self.list {
+            for (_, shader) in self.list.iter() {
                 self.device
                     .destroy_shader_module(shader.vk_shader_module, None);
+                audit_destroyed(VkObjectKind::ShaderModule, shader.vk_shader_module);
             }
         }
     }
@@ -34,7 +39,7 @@ impl ShaderList {
     pub fn new(device: ash::Device) -> ShaderList {
         ShaderList {
             device,
-            list: Vec::new(),
+            list: Registry::new("shader"),
         }
     }
 
@@ -44,14 +49,20 @@ impl ShaderList {
         shader_stage: ShaderStage,
         path: &str,
     ) -> Result<ShaderHandle, String> {
-        // Hash
-        let handle = {
-            let mut hasher = DefaultHasher::new();
-            name.hash(&mut hasher);
-            ShaderHandle(hasher.finish())
-        };
+        self.new_shader_with_defines(name, shader_stage, path, &[])
+    }
+
+    /* A permutation of a shader, compiled with `defines`, e.g. `MULTIVIEW`.
+    Each permutation has its own name and its own cached SPIR-V. */
+    pub fn new_shader_with_defines(
+        &mut self,
+        name: &str,
+        shader_stage: ShaderStage,
+        path: &str,
+        defines: &[&str],
+    ) -> Result<ShaderHandle, String> {
         // Error if name already exists
-        if self.get_shader_from_handle(handle).is_some() {
+        if self.list.find(name).is_some() {
             return Err(format!(
                 "A shader with the name `{}` already exists in the context.",
                 name
@@ -61,52 +72,117 @@ impl ShaderList {
         const SHADER_CACHE_PATH: &str = "_cache/shaders";
         std::fs::create_dir_all(SHADER_CACHE_PATH).expect("Could not create the _cache directory.");
         let source_path = String::from(&format!("assets/shaders/{}", path));
-        let spirv_path = String::from(&format!("{}/{}.spv", SHADER_CACHE_PATH, path));
+        let defines: Vec<String> = defines.iter().map(|d| String::from(*d)).collect();
+        let spirv_path = if defines.is_empty() {
+            format!("{}/{}.spv", SHADER_CACHE_PATH, path)
+        } else {
+            format!("{}/{}.{}.spv", SHADER_CACHE_PATH, path, defines.join("."))
+        };
         let is_compilation_needed = is_compilation_needed(&source_path, &spirv_path);
-        let vk_shader_module = get_shader_module(
+        let vk_shader_module = match get_shader_module(
             &self.device,
             &source_path,
             &spirv_path,
+            &defines,
             is_compilation_needed,
-        )?;
+        ) {
+            Ok(vk_shader_module) => vk_shader_module,
+            // Broken fragment shaders are replaced by an obvious error shader until they are fixed
+            Err(err) => match shader_stage {
+                ShaderStage::Fragment => {
+                    println!("{} for shader `{}`. Using the error shader.", err, name);
+                    get_error_fragment_shader_module(&self.device)
+                }
+                ShaderStage::Vertex | ShaderStage::Compute => {
+                    return Err(format!("{} for shader `{}`.", err, name));
+                }
+            },
+        };
         // Insert
-        self.list.push((
-            handle,
+        self.list.insert(
+            name,
             InternalShader {
                 name: String::from(name),
                 shader_stage,
+                path: String::from(path),
                 source_path,
                 spirv_path,
+                defines,
                 vk_shader_module,
             },
-        ));
-        Ok(handle)
+        )
     }
 
-    pub fn get_shader_from_handle(&self, shader_handle: ShaderHandle) -> Option<&InternalShader> {
-        for (handle, shader) in &self.list {
-            if *handle == shader_handle {
-                return Some(shader);
-            }
+    /* The permutation of a shader that is compiled with `define` as well as
+    its own defines, created on first use and named after both. */
+    pub fn permutation(
+        &mut self,
+        shader_handle: ShaderHandle,
+        define: &str,
+    ) -> Result<ShaderHandle, String> {
+        let shader = self.list.get(shader_handle)?;
+        let name = format!("{}_{}", shader.name, define);
+        let shader_stage = shader.shader_stage;
+        let path = shader.path.clone();
+        let mut defines: Vec<String> = shader.defines.clone();
+        defines.push(String::from(define));
+
+        if let Some(handle) = self.list.find(&name) {
+            return Ok(handle);
         }
-        None
+        let defines: Vec<&str> = defines.iter().map(|d| d.as_str()).collect();
+        self.new_shader_with_defines(&name, shader_stage, &path, &defines)
     }
 
-    pub fn hot_reload(&mut self, graph_cache: &mut Vec<(Graph, GraphHandle)>) {
-        for (shader_handle, shader) in &mut self.list {
+    /// Fails with an error that names the shader if it has been destroyed.
+    pub fn get_shader(&self, shader_handle: ShaderHandle) -> Result<&InternalShader, String> {
+        self.list.get(shader_handle)
+    }
+
+    pub fn get_shader_from_handle(&self, shader_handle: ShaderHandle) -> Option<&InternalShader> {
+        self.list.get(shader_handle).ok()
+    }
+
+    pub fn hot_reload(
+        &mut self,
+        graph_cache: &mut Vec<(Graph, GraphHandle)>,
+        compute_pipelines: &mut Vec<ComputePipeline>,
+    ) {
+        for (shader_handle, shader) in self.list.iter_mut() {
             if !is_compilation_needed(&shader.source_path, &shader.spirv_path) {
                 continue;
             }
 
-            if let Ok(vk_shader_module) =
-                get_shader_module(&self.device, &shader.source_path, &shader.spirv_path, true)
-            {
+            let opt_vk_shader_module = match get_shader_module(
+                &self.device,
+                &shader.source_path,
+                &shader.spirv_path,
+                &shader.defines,
+                true,
+            ) {
+                Ok(vk_shader_module) => Some(vk_shader_module),
+                Err(_) => match shader.shader_stage {
+                    /* Swap to the error shader rather than keep showing the
+                    stale shader, so that the failure can't go unnoticed. */
+                    ShaderStage::Fragment => {
+                        println!("Using the error shader for shader `{}`.", shader.name);
+                        Some(get_error_fragment_shader_module(&self.device))
+                    }
+                    ShaderStage::Vertex | ShaderStage::Compute => {
+                        println!("Keeping the previous version of shader `{}`.", shader.name);
+                        None
+                    }
+                },
+            };
+            if let Some(vk_shader_module) = opt_vk_shader_module {
                 // Evict any graphs that contain the shaders that need to be updated
-                graph_cache.retain(|(graph, _)| !graph.shader_handles.contains(shader_handle));
+                graph_cache.retain(|(graph, _)| !graph.shader_handles.contains(&shader_handle));
+                compute_pipelines.retain(|pipeline| pipeline.shader_handle != shader_handle);
 
                 unsafe {
                     self.device
                         .destroy_shader_module(shader.vk_shader_module, None);
+                    audit_destroyed(VkObjectKind::ShaderModule, shader.vk_shader_module);
                     shader.vk_shader_module = vk_shader_module;
                 }
             }
@@ -149,9 +225,10 @@ fn is_compilation_needed(source_path: &str, spirv_path: &str) -> bool {
 
 // We pretty-print the error here instead of returning it as a Err(String).
 // Might want to change this behavior at some point.
-fn compile_shader(source_path: &str, spirv_path: &str) -> Result<(), String> {
+fn compile_shader(source_path: &str, spirv_path: &str, defines: &[String]) -> Result<(), String> {
     print!("Compiling `{}`...", source_path);
     let glslc_output = std::process::Command::new("glslc")
+        .args(defines.iter().map(|define| format!("-D{}", define)))
         .arg(source_path)
         .arg("-o")
         .arg(spirv_path)
@@ -171,15 +248,30 @@ fn compile_shader(source_path: &str, spirv_path: &str) -> Result<(), String> {
     }
 }
 
+fn get_error_fragment_shader_module(device: &ash::Device) -> vk::ShaderModule {
+    let source_path = format!("assets/shaders/{}", ERROR_FRAGMENT_SHADER_PATH);
+    let spirv_path = format!("_cache/shaders/{}.spv", ERROR_FRAGMENT_SHADER_PATH);
+    let is_compilation_needed = is_compilation_needed(&source_path, &spirv_path);
+    get_shader_module(
+        device,
+        &source_path,
+        &spirv_path,
+        &[],
+        is_compilation_needed,
+    )
+    .expect("Failed to compile the error shader.")
+}
+
 fn get_shader_module(
     device: &ash::Device,
     source_path: &str,
     spirv_path: &str,
+    defines: &[String],
     is_compilation_needed: bool,
 ) -> Result<vk::ShaderModule, String> {
     // If spirv path doesn't exist, compile the shader
     if is_compilation_needed {
-        compile_shader(source_path, spirv_path)?;
+        compile_shader(source_path, spirv_path, defines)?;
     }
 
     // Read the spirv file
@@ -205,6 +297,12 @@ fn get_shader_module(
             .create_shader_module(&create_info, None)
             .expect("Failed to create shader module.")
     };
+    audit_created(
+        VkObjectKind::ShaderModule,
+        vk_shader_module,
+        &[device_id(device)],
+        source_path,
+    );
 
     Ok(vk_shader_module)
 }
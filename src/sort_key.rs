@@ -0,0 +1,189 @@
+/* 64-bit keys that draws are sorted by before recording. From the most
+significant bit down:
+
+    Opaque, Overlay and UI:  layer (4) | pipeline (16) | material (16) | depth (28)
+    Transparent:             layer (4) | reversed depth (28) | pipeline (16) | material (16)
+
+So layers always record in order. Within a layer, opaque draws are batched by
+state and go front to back, while transparent draws have to go back to front
+regardless of state. */
+const LAYER_SHIFT: u32 = 60;
+const DEPTH_BITS: u32 = 28;
+const DEPTH_MAX: u64 = (1 << DEPTH_BITS) - 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DrawLayer {
+    Opaque,
+    Transparent,
+    Overlay, // Drawn over the scene, e.g. to highlight an object through walls
+    Ui,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SortKey(pub u64);
+
+impl SortKey {
+    /* `pipeline_bucket` and `material_bucket` are truncated to 16 bits, so
+    hashes can be passed in directly. `depth` is the normalized view depth,
+    0 at the camera and 1 at the far plane, and is clamped to that range. */
+    pub fn new(
+        layer: DrawLayer,
+        pipeline_bucket: u64,
+        material_bucket: u64,
+        depth: f32,
+    ) -> SortKey {
+        let pipeline = pipeline_bucket & 0xffff;
+        let material = material_bucket & 0xffff;
+        let depth = (depth.clamp(0.0, 1.0) as f64 * DEPTH_MAX as f64) as u64;
+        let layer_bits = (layer as u64) << LAYER_SHIFT;
+        match layer {
+            DrawLayer::Transparent => {
+                SortKey(layer_bits | ((DEPTH_MAX - depth) << 32) | (pipeline << 16) | material)
+            }
+            _ => SortKey(layer_bits | (pipeline << 44) | (material << DEPTH_BITS) | depth),
+        }
+    }
+
+    pub fn layer(&self) -> DrawLayer {
+        match self.0 >> LAYER_SHIFT {
+            0 => DrawLayer::Opaque,
+            1 => DrawLayer::Transparent,
+            2 => DrawLayer::Overlay,
+            _ => DrawLayer::Ui,
+        }
+    }
+
+    /// Move the draw into another layer, keeping its state and depth.
+    pub fn with_layer(&self, layer: DrawLayer) -> SortKey {
+        let (pipeline, material, depth) = self.fields();
+        SortKey::new(layer, pipeline, material, depth as f32 / DEPTH_MAX as f32)
+    }
+
+    pub fn pipeline_bucket(&self) -> u64 {
+        self.fields().0
+    }
+
+    pub fn material_bucket(&self) -> u64 {
+        self.fields().1
+    }
+
+    // (pipeline, material, quantized depth)
+    fn fields(&self) -> (u64, u64, u64) {
+        let key = self.0;
+        match self.layer() {
+            DrawLayer::Transparent => (
+                (key >> 16) & 0xffff,
+                key & 0xffff,
+                DEPTH_MAX - ((key >> 32) & DEPTH_MAX),
+            ),
+            _ => (
+                (key >> 44) & 0xffff,
+                (key >> DEPTH_BITS) & 0xffff,
+                key & DEPTH_MAX,
+            ),
+        }
+    }
+}
+
+/// Stable, so draws with equal keys keep the order that they were submitted in.
+pub fn sort_draws<T>(draws: &mut [(SortKey, T)]) {
+    draws.sort_by_key(|(key, _)| *key);
+}
+
+/// Number of pipeline and material changes that recording the draws in this
+/// order takes. Compare before and after sorting to see the batching benefit.
+pub fn count_state_changes<T>(draws: &[(SortKey, T)]) -> (usize, usize) {
+    let mut num_pipeline_changes = 0;
+    let mut num_material_changes = 0;
+    let mut opt_last: Option<(u64, u64)> = None;
+    for (key, _) in draws {
+        let state = (key.pipeline_bucket(), key.material_bucket());
+        if opt_last.is_none_or(|last| last.0 != state.0) {
+            num_pipeline_changes += 1;
+        }
+        if opt_last.is_none_or(|last| last.1 != state.1) {
+            num_material_changes += 1;
+        }
+        opt_last = Some(state);
+    }
+    (num_pipeline_changes, num_material_changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_round_trip() {
+        for layer in &[
+            DrawLayer::Opaque,
+            DrawLayer::Transparent,
+            DrawLayer::Overlay,
+            DrawLayer::Ui,
+        ] {
+            let key = SortKey::new(*layer, 0x1234, 0xabcd, 0.5);
+            assert_eq!(key.layer(), *layer);
+            assert_eq!(key.pipeline_bucket(), 0x1234);
+            assert_eq!(key.material_bucket(), 0xabcd);
+            assert_eq!(key.fields().2, DEPTH_MAX / 2);
+        }
+    }
+
+    #[test]
+    fn opaque_fields_are_packed_at_their_offsets() {
+        let key = SortKey::new(DrawLayer::Overlay, 0x1_0003, 0xf_0002, 1.0);
+        assert_eq!(key.0, 2 << 60 | 3 << 44 | 2 << 28 | DEPTH_MAX);
+        assert_eq!(SortKey::new(DrawLayer::Opaque, 0, 0, -1.0).0, 0);
+        assert_eq!(SortKey::new(DrawLayer::Opaque, 0, 0, 2.0).0, DEPTH_MAX);
+    }
+
+    #[test]
+    fn transparent_fields_are_packed_at_their_offsets() {
+        let key = SortKey::new(DrawLayer::Transparent, 3, 2, 0.0);
+        assert_eq!(key.0, 1 << 60 | DEPTH_MAX << 32 | 3 << 16 | 2);
+        assert_eq!(SortKey::new(DrawLayer::Transparent, 0, 0, 1.0).0, 1 << 60);
+    }
+
+    #[test]
+    fn opaque_draws_order_by_pipeline_then_material_then_depth() {
+        let key =
+            |pipeline, material, depth| SortKey::new(DrawLayer::Opaque, pipeline, material, depth);
+        assert!(key(0, 0xffff, 1.0) < key(1, 0, 0.0));
+        assert!(key(1, 0, 1.0) < key(1, 1, 0.0));
+        assert!(key(1, 1, 0.25) < key(1, 1, 0.75));
+    }
+
+    #[test]
+    fn transparent_draws_order_by_depth_back_to_front() {
+        let key = |pipeline, material, depth| {
+            SortKey::new(DrawLayer::Transparent, pipeline, material, depth)
+        };
+        assert!(key(1, 1, 0.75) < key(0, 0, 0.25));
+        assert!(key(0, 1, 0.5) < key(1, 0, 0.5));
+    }
+
+    #[test]
+    fn layers_order_before_everything_else() {
+        let last_opaque = SortKey::new(DrawLayer::Opaque, 0xffff, 0xffff, 1.0);
+        let first_transparent = SortKey::new(DrawLayer::Transparent, 0, 0, 1.0);
+        let first_overlay = SortKey::new(DrawLayer::Overlay, 0, 0, 0.0);
+        assert!(last_opaque < first_transparent);
+        assert!(first_transparent < first_overlay);
+        assert!(first_overlay < SortKey::new(DrawLayer::Ui, 0, 0, 0.0));
+        let highlighted = last_opaque.with_layer(DrawLayer::Overlay);
+        assert_eq!(highlighted.layer(), DrawLayer::Overlay);
+        assert_eq!(highlighted.fields(), last_opaque.fields());
+    }
+
+    #[test]
+    fn sorting_is_stable_and_batches_state() {
+        let key = |material| SortKey::new(DrawLayer::Opaque, 0, material, 0.5);
+        let mut draws = [(key(1), 'a'), (key(0), 'b'), (key(1), 'c'), (key(0), 'd')];
+        assert_eq!(count_state_changes(&draws), (1, 4));
+        sort_draws(&mut draws);
+        let order: String = draws.iter().map(|(_, draw)| *draw).collect();
+        assert_eq!(order, "bdac");
+        assert_eq!(count_state_changes(&draws), (1, 2));
+        assert_eq!(count_state_changes::<()>(&[]), (0, 0));
+    }
+}
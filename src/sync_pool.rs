@@ -0,0 +1,162 @@
+use crate::*;
+use std::cell::RefCell;
+
+#[derive(Default)]
+struct SyncPoolState {
+    // Handed out, with the name that they were requested with
+    live_fences: Vec<(vk::Fence, String)>,
+    live_semaphores: Vec<(vk::Semaphore, String)>,
+    // Released, ready for reuse
+    free_signaled_fences: Vec<vk::Fence>,
+    free_unsignaled_fences: Vec<vk::Fence>,
+    free_semaphores: Vec<vk::Semaphore>,
+    num_fences_created: usize,
+    num_semaphores_created: usize,
+}
+
+/* Hands out fences and semaphores, and takes them back for reuse instead of
+destroying them. Every handle is tracked under a name, so that the ones that
+are never released show up by name when the GPU is dropped.
+
+A handle must only be released once the GPU is done with it, just like a
+handle that is being destroyed. */
+pub struct SyncPool {
+    device: ash::Device,
+    state: RefCell<SyncPoolState>,
+}
+
+impl SyncPool {
+    pub fn new(device: ash::Device) -> SyncPool {
+        SyncPool {
+            device,
+            state: RefCell::new(SyncPoolState::default()),
+        }
+    }
+
+    pub fn fence(&self, name: &str, is_signaled: bool) -> vk::Fence {
+        let mut state = self.state.borrow_mut();
+        let opt_recycled_fence = if is_signaled {
+            state.free_signaled_fences.pop()
+        } else {
+            state.free_unsignaled_fences.pop().or_else(|| {
+                let opt_fence = state.free_signaled_fences.pop();
+                if let Some(fence) = opt_fence {
+                    unsafe {
+                        self.device
+                            .reset_fences(&[fence])
+                            .expect("Failed to reset fence.");
+                    }
+                }
+                opt_fence
+            })
+        };
+        let fence = opt_recycled_fence.unwrap_or_else(|| {
+            state.num_fences_created += 1;
+            let flags = if is_signaled {
+                vk::FenceCreateFlags::SIGNALED
+            } else {
+                vk::FenceCreateFlags::empty()
+            };
+            let create_info = vk::FenceCreateInfo::builder().flags(flags);
+            let fence = unsafe {
+                self.device
+                    .create_fence(&create_info, None)
+                    .expect("Failed to create Fence Object!")
+            };
+            // Named after the first user, since fences are recycled
+            audit_created(VkObjectKind::Fence, fence, &[device_id(&self.device)], name);
+            fence
+        });
+        state.live_fences.push((fence, String::from(name)));
+        fence
+    }
+
+    pub fn release_fence(&self, fence: vk::Fence) {
+        let mut state = self.state.borrow_mut();
+        let idx = state
+            .live_fences
+            .iter()
+            .position(|(live_fence, _)| *live_fence == fence)
+            .expect("Released a fence that doesn't belong to the pool.");
+        state.live_fences.swap_remove(idx);
+        let is_signaled = unsafe { self.device.get_fence_status(fence) }.is_ok();
+        if is_signaled {
+            state.free_signaled_fences.push(fence);
+        } else {
+            state.free_unsignaled_fences.push(fence);
+        }
+    }
+
+    pub fn semaphore(&self, name: &str) -> vk::Semaphore {
+        let mut state = self.state.borrow_mut();
+        let semaphore = state.free_semaphores.pop().unwrap_or_else(|| {
+            state.num_semaphores_created += 1;
+            let create_info = vk::SemaphoreCreateInfo::builder();
+            let semaphore = unsafe {
+                self.device
+                    .create_semaphore(&create_info, None)
+                    .expect("Failed to create Semaphore Object!")
+            };
+            audit_created(
+                VkObjectKind::Semaphore,
+                semaphore,
+                &[device_id(&self.device)],
+                name,
+            );
+            semaphore
+        });
+        state.live_semaphores.push((semaphore, String::from(name)));
+        semaphore
+    }
+
+    pub fn release_semaphore(&self, semaphore: vk::Semaphore) {
+        let mut state = self.state.borrow_mut();
+        let idx = state
+            .live_semaphores
+            .iter()
+            .position(|(live_semaphore, _)| *live_semaphore == semaphore)
+            .expect("Released a semaphore that doesn't belong to the pool.");
+        state.live_semaphores.swap_remove(idx);
+        state.free_semaphores.push(semaphore);
+    }
+
+    /// Number of fences and semaphores actually created, as opposed to recycled.
+    pub fn num_created(&self) -> (usize, usize) {
+        let state = self.state.borrow();
+        (state.num_fences_created, state.num_semaphores_created)
+    }
+
+    /// Destroy everything in the pool, and report the handles that were never released.
+    pub fn destroy(&self) {
+        let state = &mut *self.state.borrow_mut();
+        for (_, name) in &state.live_fences {
+            println!("Leaked fence `{}`.", name);
+        }
+        for (_, name) in &state.live_semaphores {
+            println!("Leaked semaphore `{}`.", name);
+        }
+        let fences = state
+            .live_fences
+            .drain(..)
+            .map(|(fence, _)| fence)
+            .chain(state.free_signaled_fences.drain(..))
+            .chain(state.free_unsignaled_fences.drain(..))
+            .collect::<Vec<vk::Fence>>();
+        let semaphores = state
+            .live_semaphores
+            .drain(..)
+            .map(|(semaphore, _)| semaphore)
+            .chain(state.free_semaphores.drain(..))
+            .collect::<Vec<vk::Semaphore>>();
+        unsafe {
+            for fence in fences {
+                self.device.destroy_fence(fence, None);
+                audit_destroyed(VkObjectKind::Fence, fence);
+            }
+            for semaphore in semaphores {
+                self.device.destroy_semaphore(semaphore, None);
+                audit_destroyed(VkObjectKind::Semaphore, semaphore);
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use crate::*;
+use glam::*;
+use std::time::Duration;
+
+const MAX_ITERATIONS: u32 = 1 << 16;
+
+#[allow(dead_code)]
+struct SyntheticLoadUniforms {
+    // Matches the layout that fullscreen_triangle.vert declares
+    mtx_obj_to_clip: Mat4,
+    mtx_norm_obj_to_world: Mat4,
+    elapsed_seconds: f32,
+    viewport_w: f32,
+    viewport_h: f32,
+    num_iterations: f32,
+}
+
+/// Debug builds always allow synthetic loads. Release builds only do with the
+/// `synthetic-load` feature, so that they can't end up in a shipped build.
+pub fn is_synthetic_load_allowed() -> bool {
+    cfg!(debug_assertions) || cfg!(feature = "synthetic-load")
+}
+
+/* A fullscreen pass of dependent ALU work, for testing frame pacing,
+throttling and latency under a reproducible GPU load on any machine. The pass
+writes to an image of its own, so it can be added anywhere in the graph
+without changing the output. The iteration count is either fixed, or adjusted
+every frame until frames take `opt_target_ms`. The adjustment measures whole
+frame times, so it needs a GPU-bound frame loop, i.e. presenting without
+vsync. */
+pub struct SyntheticLoad {
+    pub num_iterations: u32,
+    pub opt_target_ms: Option<f32>,
+    shader_vertex: ShaderHandle,
+    shader_fragment: ShaderHandle,
+    output_image: ImageHandle,
+    sampler: Sampler,
+}
+
+impl SyntheticLoad {
+    pub fn new(
+        ctx: &mut Context,
+        num_iterations: u32,
+        opt_target_ms: Option<f32>,
+    ) -> Result<SyntheticLoad, String> {
+        if !is_synthetic_load_allowed() {
+            return Err(String::from(
+                "Synthetic loads are only available in debug builds, or with the `synthetic-load` feature.",
+            ));
+        }
+        let shader_vertex = ctx.new_shader(
+            "synthetic_load_vertex",
+            ShaderStage::Vertex,
+            "fullscreen_triangle.vert",
+        )?;
+        let shader_fragment = ctx.new_shader(
+            "synthetic_load_fragment",
+            ShaderStage::Fragment,
+            "synthetic_load.frag",
+        )?;
+        let output_image = ctx.new_image_relative_size(
+            "image_synthetic_load",
+            1.0,
+            Format::R8G8B8A8Unorm,
+            ImageUsage::COLOR_ATTACHMENT,
+        )?;
+        Ok(SyntheticLoad {
+            num_iterations: num_iterations.clamp(1, MAX_ITERATIONS),
+            opt_target_ms,
+            shader_vertex,
+            shader_fragment,
+            output_image,
+            sampler: Sampler::new(&ctx.gpu),
+        })
+    }
+
+    /// Scale the iteration count by how far the last frame was from the target.
+    pub fn update(&mut self, last_frame_time: Duration) {
+        let target_ms = match self.opt_target_ms {
+            Some(target_ms) => target_ms,
+            None => return,
+        };
+        let frame_ms = last_frame_time.as_secs_f32() * 1000.0;
+        if frame_ms <= 0.0 {
+            return;
+        }
+        // Damped, so that noisy frames don't make the load oscillate
+        let ratio = (target_ms / frame_ms).clamp(0.5, 2.0).powf(0.25);
+        self.num_iterations =
+            ((self.num_iterations as f32 * ratio) as u32).clamp(1, MAX_ITERATIONS);
+    }
+
+    /// Add the pass to the graph that is being built, and write its uniforms.
+    pub fn add_pass(&self, ctx: &mut Context, elapsed_seconds: f32) -> PassHandle {
+        let pass = ctx
+            .add_pass(
+                "synthetic_load",
+                self.shader_vertex,
+                self.shader_fragment,
+                &[self.output_image],
+                Color::BLACK,
+                None,
+                RING_UNIFORM_BUFFER,
+                ctx.default_resources.black_image,
+                &self.sampler,
+            )
+            .expect("Failed to add synthetic load pass.");
+        ctx.set_pass_label_color(pass, LABEL_COLOR_DEBUG);
+        let uniforms = [SyntheticLoadUniforms {
+            mtx_obj_to_clip: Mat4::identity(),
+            mtx_norm_obj_to_world: Mat4::identity(),
+            elapsed_seconds,
+            viewport_w: ctx.facade.swapchain_width as f32,
+            viewport_h: ctx.facade.swapchain_height as f32,
+            num_iterations: self.num_iterations as f32,
+        }];
+        ctx.write_pass_uniforms(pass, &uniforms);
+        pass
+    }
+}
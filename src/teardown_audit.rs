@@ -0,0 +1,209 @@
+use crate::*;
+use ash::vk::Handle;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// Runs an app for a few frames and fails if the teardown audit finds anything
+const VERIFY_TEARDOWN_ARG: &str = "--verify-teardown";
+pub const NUM_VERIFY_TEARDOWN_FRAMES: usize = 10;
+// Set to 1 to record where each object was created. Slow, but the report
+// then points at the code that owns a leaked object.
+const BACKTRACE_ENV_VAR: &str = "GRAPHENE_TEARDOWN_BACKTRACE";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum VkObjectKind {
+    Instance,
+    DebugMessenger,
+    Surface,
+    Device,
+    Swapchain,
+    CommandPool,
+    Fence,
+    Semaphore,
+    Memory,
+    Buffer,
+    BufferView,
+    Image,
+    ImageView,
+    Sampler,
+    ShaderModule,
+    PipelineCache,
+    DescriptorSetLayout,
+    DescriptorPool,
+    RenderPass,
+    Framebuffer,
+    PipelineLayout,
+    Pipeline,
+    QueryPool,
+}
+
+/// Handles of different types may have the same value, so objects are told
+/// apart by their kind as well.
+pub type VkObjectId = (VkObjectKind, u64);
+
+pub fn vk_object_id(kind: VkObjectKind, handle: impl Handle) -> VkObjectId {
+    (kind, handle.as_raw())
+}
+
+/// The parent of everything that is created from the logical device.
+pub fn device_id(device: &ash::Device) -> VkObjectId {
+    vk_object_id(VkObjectKind::Device, device.handle())
+}
+
+struct TrackedObject {
+    name: String,
+    parents: Vec<VkObjectId>,
+    opt_backtrace: Option<String>,
+}
+
+struct AuditState {
+    live_objects: HashMap<VkObjectId, TrackedObject>,
+    violations: Vec<String>,
+}
+
+static AUDIT_STATE: Mutex<Option<AuditState>> = Mutex::new(None);
+
+/* Checks the order in which Vulkan objects are destroyed, in debug builds.
+Objects register when they are created, along with the objects that they were
+created from, like the device or a pool. Destroying an object that has live
+children, destroying an object twice, and objects that are still alive at the
+end are reported by name, which the validation layers' messages at exit
+usually don't say. Release builds don't track anything. */
+pub fn is_teardown_audit_enabled() -> bool {
+    cfg!(debug_assertions)
+}
+
+fn with_state(f: impl FnOnce(&mut AuditState)) {
+    if !is_teardown_audit_enabled() {
+        return;
+    }
+    // A panic while the lock was held doesn't make the records wrong
+    let mut guard = AUDIT_STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let state = guard.get_or_insert_with(|| AuditState {
+        live_objects: HashMap::new(),
+        violations: Vec::new(),
+    });
+    f(state);
+}
+
+fn describe(id: &VkObjectId, object: &TrackedObject) -> String {
+    let mut description = format!("{:?} `{}` ({:#x})", id.0, object.name, id.1);
+    if let Some(backtrace) = &object.opt_backtrace {
+        description.push_str(", created at:\n");
+        description.push_str(backtrace);
+    }
+    description
+}
+
+pub fn audit_created(kind: VkObjectKind, handle: impl Handle, parents: &[VkObjectId], name: &str) {
+    let id = vk_object_id(kind, handle);
+    with_state(|state| {
+        let opt_backtrace = std::env::var(BACKTRACE_ENV_VAR)
+            .is_ok_and(|value| value == "1")
+            .then(|| std::backtrace::Backtrace::force_capture().to_string());
+        let object = TrackedObject {
+            name: String::from(name),
+            parents: parents.to_vec(),
+            opt_backtrace,
+        };
+        for parent in parents {
+            if !state.live_objects.contains_key(parent) {
+                state.violations.push(format!(
+                    "{} was created from {:?} {:#x}, which isn't alive.",
+                    describe(&id, &object),
+                    parent.0,
+                    parent.1
+                ));
+            }
+        }
+        // Drivers may reuse the handle of a destroyed object, but not of a live one
+        if let Some(previous) = state.live_objects.insert(id, object) {
+            state.violations.push(format!(
+                "{} was created while an object with the same handle is alive: {}",
+                describe(&id, &state.live_objects[&id]),
+                describe(&id, &previous)
+            ));
+        }
+    });
+}
+
+pub fn audit_destroyed(kind: VkObjectKind, handle: impl Handle) {
+    let id = vk_object_id(kind, handle);
+    with_state(|state| {
+        let object = match state.live_objects.remove(&id) {
+            Some(object) => object,
+            None => {
+                state.violations.push(format!(
+                    "{:?} {:#x} was destroyed, but isn't alive. It was destroyed twice or never registered.",
+                    id.0, id.1
+                ));
+                return;
+            }
+        };
+        let live_children: Vec<String> = state
+            .live_objects
+            .iter()
+            .filter(|(_, child)| child.parents.contains(&id))
+            .map(|(child_id, child)| describe(child_id, child))
+            .collect();
+        if !live_children.is_empty() {
+            state.violations.push(format!(
+                "{} was destroyed before its children:\n  {}",
+                describe(&id, &object),
+                live_children.join("\n  ")
+            ));
+        }
+    });
+}
+
+/// Objects that aren't tracked, like swapchain images, are ignored.
+pub fn audit_renamed(kind: VkObjectKind, handle: impl Handle, name: &str) {
+    let id = vk_object_id(kind, handle);
+    with_state(|state| {
+        if let Some(object) = state.live_objects.get_mut(&id) {
+            object.name = String::from(name);
+        }
+    });
+}
+
+/* Call once everything, including the instance, has been destroyed. Returns
+the ordering violations so far, followed by the objects that are still
+alive, and resets the audit. Empty if the teardown was clean. */
+pub fn finish_teardown_audit() -> Vec<String> {
+    let mut problems = Vec::new();
+    with_state(|state| {
+        problems.append(&mut state.violations);
+        let mut leaks: Vec<String> = state
+            .live_objects
+            .drain()
+            .map(|(id, object)| format!("{} was never destroyed.", describe(&id, &object)))
+            .collect();
+        leaks.sort();
+        problems.append(&mut leaks);
+    });
+    problems
+}
+
+pub fn is_verifying_teardown() -> bool {
+    std::env::args().any(|arg| arg == VERIFY_TEARDOWN_ARG)
+}
+
+/* Print what the audit found. Call at the very end of `main()`, once the
+context and everything created with it have been dropped. With
+`--verify-teardown`, exits with an error code if anything was found. */
+pub fn report_teardown_audit() {
+    let problems = finish_teardown_audit();
+    for problem in &problems {
+        println!("Teardown: {}", problem);
+    }
+    if is_verifying_teardown() {
+        if !is_teardown_audit_enabled() {
+            println!("The teardown audit only runs in debug builds.");
+            std::process::exit(1);
+        }
+        if !problems.is_empty() {
+            std::process::exit(1);
+        }
+        println!("Teardown verified.");
+    }
+}
@@ -0,0 +1,49 @@
+use glam::*;
+
+/* Maps linear HDR scene colors into the displayable 0..1 range. Applied in
+the final pass, after which everything is in display space. The values match
+the `TONEMAP_*` constants in chromatic_aberration.frag. */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TonemapOperator {
+    Clamp = 0,
+    Reinhard = 1,
+    Aces = 2, // Narkowicz's fit of the ACES filmic curve
+}
+
+impl TonemapOperator {
+    pub fn next(&self) -> TonemapOperator {
+        match self {
+            TonemapOperator::Clamp => TonemapOperator::Reinhard,
+            TonemapOperator::Reinhard => TonemapOperator::Aces,
+            TonemapOperator::Aces => TonemapOperator::Clamp,
+        }
+    }
+
+    /// For passing to shaders through a float uniform.
+    pub fn to_shader_value(&self) -> f32 {
+        *self as u32 as f32
+    }
+
+    /// The same curve as the shader, for checking read back results against.
+    pub fn apply(&self, color: Vec3, exposure_ev: f32) -> Vec3 {
+        let apply_channel = |x: f32| {
+            let x = x * exposure_scale(exposure_ev);
+            let mapped = match self {
+                TonemapOperator::Clamp => x,
+                TonemapOperator::Reinhard => x / (1.0 + x),
+                TonemapOperator::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+            };
+            mapped.clamp(0.0, 1.0)
+        };
+        Vec3::new(
+            apply_channel(color.x()),
+            apply_channel(color.y()),
+            apply_channel(color.z()),
+        )
+    }
+}
+
+/// Linear factor that scene colors are multiplied by before tonemapping.
+pub fn exposure_scale(exposure_ev: f32) -> f32 {
+    2.0_f32.powf(exposure_ev)
+}
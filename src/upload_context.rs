@@ -0,0 +1,315 @@
+use crate::*;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+struct BatchState {
+    fence: Cell<vk::Fence>, // Null until the batch is submitted
+    is_complete: Cell<bool>,
+}
+
+/// Completion of an asynchronous upload. Clones refer to the same upload.
+#[derive(Clone)]
+pub struct UploadTicket {
+    batch: Rc<BatchState>,
+}
+
+impl UploadTicket {
+    /// For data that was written without submitting anything.
+    pub fn completed() -> UploadTicket {
+        UploadTicket {
+            batch: Rc::new(BatchState {
+                fence: Cell::new(vk::Fence::null()),
+                is_complete: Cell::new(true),
+            }),
+        }
+    }
+
+    pub fn is_submitted(&self) -> bool {
+        self.batch.fence.get() != vk::Fence::null()
+    }
+
+    /// Polls the fence of the upload's batch, without blocking.
+    pub fn is_complete(&self, gpu: &Gpu) -> bool {
+        if self.batch.is_complete.get() {
+            return true;
+        }
+        if !self.is_submitted() {
+            return false;
+        }
+        /* The upload context releases the fence only after marking the batch
+        complete, so a fence that is read here still belongs to the batch. */
+        let is_signaled = unsafe { gpu.device.get_fence_status(self.batch.fence.get()) }.is_ok();
+        self.batch.is_complete.set(is_signaled);
+        is_signaled
+    }
+
+    pub fn wait(&self, gpu: &Gpu) {
+        if self.batch.is_complete.get() {
+            return;
+        }
+        assert!(
+            self.is_submitted(),
+            "Waited on an upload that hasn't been submitted. Uploads are submitted at the start of the next frame, or by `UploadContext::flush()`."
+        );
+        unsafe {
+            gpu.device
+                .wait_for_fences(&[self.batch.fence.get()], true, u64::MAX)
+                .expect("Failed to wait for Fence.");
+        }
+        self.batch.is_complete.set(true);
+    }
+}
+
+/// In debug builds, panic if a resource is used before its upload has completed.
+pub fn debug_assert_upload_complete(opt_ticket: &Option<UploadTicket>, name: &str, gpu: &Gpu) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    if let Some(ticket) = opt_ticket {
+        assert!(
+            ticket.is_complete(gpu),
+            "`{}` was used by a pass before its upload completed. Check `UploadTicket::is_complete()` before drawing with it.",
+            name
+        );
+    }
+}
+
+struct UploadBatch {
+    command_buffer: vk::CommandBuffer,
+    staging_chunks: Vec<StagingChunk>, // Freed once the batch completes
+    state: Rc<BatchState>,
+}
+
+#[derive(Default)]
+struct UploadContextState {
+    opt_recording: Option<UploadBatch>,
+    in_flight: Vec<UploadBatch>,
+}
+
+/* Uploads buffers and images without blocking. The copies are recorded into
+one command buffer, which `flush()` submits with a fence at the start of the
+next frame, and every upload returns a ticket that polls that fence. Staging
+chunks and command buffers are recycled once their batch completes.
+
+A resource must not be used by a pass before its ticket completes. Debug
+builds check this when its buffer or image is bound for a draw. */
+pub struct UploadContext {
+    device: ash::Device,
+    command_pool: vk::CommandPool,
+    state: RefCell<UploadContextState>,
+}
+
+impl UploadContext {
+    pub fn new(gpu: &Gpu) -> UploadContext {
+        let info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(gpu.graphics_queue_idx);
+        let command_pool = unsafe {
+            gpu.device
+                .create_command_pool(&info, None)
+                .expect("Failed to create command pool")
+        };
+        audit_created(
+            VkObjectKind::CommandPool,
+            command_pool,
+            &[device_id(&gpu.device)],
+            "upload_command_pool",
+        );
+        UploadContext {
+            device: gpu.device.clone(),
+            command_pool,
+            state: RefCell::new(UploadContextState::default()),
+        }
+    }
+
+    fn recording_batch<'a>(&self, state: &'a mut UploadContextState) -> &'a mut UploadBatch {
+        state.opt_recording.get_or_insert_with(|| UploadBatch {
+            command_buffer: begin_single_use_command_buffer(&self.device, self.command_pool),
+            staging_chunks: Vec::new(),
+            state: Rc::new(BatchState {
+                fence: Cell::new(vk::Fence::null()),
+                is_complete: Cell::new(false),
+            }),
+        })
+    }
+
+    /* Record into the batch that the next flush submits, after the debug
+    fills of the buffers created so far, so that a fill never lands on top of
+    the buffer's upload. */
+    fn record(
+        &self,
+        staging_chunk: StagingChunk,
+        gpu: &Gpu,
+        record: impl FnOnce(vk::CommandBuffer, &StagingChunk),
+    ) -> UploadTicket {
+        let mut state = self.state.borrow_mut();
+        let batch = self.recording_batch(&mut state);
+        gpu.debug_fill
+            .record_pending(&self.device, batch.command_buffer);
+        record(batch.command_buffer, &staging_chunk);
+        batch.staging_chunks.push(staging_chunk);
+        UploadTicket {
+            batch: batch.state.clone(),
+        }
+    }
+
+    pub fn upload_buffer<T>(
+        &self,
+        name: &str,
+        data: &[T],
+        usage: vk::BufferUsageFlags,
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> (DeviceLocalBuffer, UploadTicket) {
+        if gpu.writes_device_local_directly() {
+            let buffer =
+                DeviceLocalBuffer::new_written_directly(name, data, usage, gpu, debug_utils);
+            return (buffer, UploadTicket::completed());
+        }
+        let mut buffer = DeviceLocalBuffer::new_uninitialized(name, data, usage, gpu, debug_utils);
+        let staging_chunk = gpu.staging_pool.allocate(data, gpu, debug_utils);
+        let size = std::mem::size_of_val(data);
+        let ticket = self.record(staging_chunk, gpu, |command_buffer, staging_chunk| {
+            debug_utils.begin_label(
+                command_buffer,
+                &format!("upload {}", name),
+                LABEL_COLOR_UPLOAD,
+            );
+            let copy_regions = [vk::BufferCopy {
+                src_offset: staging_chunk.offset as u64,
+                dst_offset: 0,
+                size: size as u64,
+            }];
+            unsafe {
+                gpu.device.cmd_copy_buffer(
+                    command_buffer,
+                    staging_chunk.vk_buffer,
+                    buffer.vk_buffer,
+                    &copy_regions,
+                );
+            }
+            debug_utils.end_label(command_buffer);
+        });
+        buffer.opt_upload_ticket = Some(ticket.clone());
+        (buffer, ticket)
+    }
+
+    /// Like `Image::new_from_data()`, but without waiting for the copy.
+    pub fn upload_image(
+        &self,
+        name: &str,
+        size: (u32, u32, u32),
+        format: vk::Format,
+        data: &[u8],
+        gpu: &Gpu,
+        debug_utils: &DebugUtils,
+    ) -> (Image, UploadTicket) {
+        let mut image = Image::new_for_data(name, size, 1, 1, format, data, gpu, debug_utils);
+        let staging_chunk = gpu.staging_pool.allocate(data, gpu, debug_utils);
+        let ticket = self.record(staging_chunk, gpu, |command_buffer, staging_chunk| {
+            debug_utils.begin_label(
+                command_buffer,
+                &format!("upload {}", name),
+                LABEL_COLOR_UPLOAD,
+            );
+            image.record_initial_upload(staging_chunk, command_buffer);
+            debug_utils.end_label(command_buffer);
+        });
+        image.opt_upload_ticket = Some(ticket.clone());
+        (image, ticket)
+    }
+
+    /// Submit the uploads and debug fills recorded since the last flush, and
+    /// recycle what the completed ones used.
+    pub fn flush(&self, gpu: &Gpu) {
+        self.reclaim(gpu);
+        let mut state = self.state.borrow_mut();
+        if gpu.debug_fill.num_pending() > 0 {
+            let batch = self.recording_batch(&mut state);
+            gpu.debug_fill
+                .record_pending(&self.device, batch.command_buffer);
+        }
+        let batch = match state.opt_recording.take() {
+            Some(batch) => batch,
+            None => return,
+        };
+        let fence = gpu.sync_pool.fence("fence_upload", false);
+        unsafe {
+            // Later submissions to the queue see the uploaded data
+            let memory_barriers = [vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::MEMORY_READ)
+                .build()];
+            self.device.cmd_pipeline_barrier(
+                batch.command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &memory_barriers,
+                &[],
+                &[],
+            );
+            self.device
+                .end_command_buffer(batch.command_buffer)
+                .expect("Failed to record end-command-buffer");
+            let command_buffers = [batch.command_buffer];
+            let submit_info = [vk::SubmitInfo {
+                command_buffer_count: command_buffers.len() as u32,
+                p_command_buffers: command_buffers.as_ptr(),
+                ..Default::default()
+            }];
+            self.device
+                .queue_submit(gpu.graphics_queue, &submit_info, fence)
+                .expect("Failed to Queue Submit!");
+        }
+        batch.state.fence.set(fence);
+        state.in_flight.push(batch);
+    }
+
+    fn reclaim(&self, gpu: &Gpu) {
+        let mut state = self.state.borrow_mut();
+        let in_flight = std::mem::take(&mut state.in_flight);
+        for batch in in_flight {
+            let fence = batch.state.fence.get();
+            let is_signaled = unsafe { self.device.get_fence_status(fence) }.is_ok();
+            if is_signaled {
+                self.release(batch, gpu);
+            } else {
+                state.in_flight.push(batch);
+            }
+        }
+    }
+
+    fn release(&self, batch: UploadBatch, gpu: &Gpu) {
+        batch.state.is_complete.set(true);
+        unsafe {
+            self.device
+                .free_command_buffers(self.command_pool, &[batch.command_buffer]);
+        }
+        gpu.sync_pool.release_fence(batch.state.fence.get());
+        for chunk in batch.staging_chunks {
+            gpu.staging_pool.free(chunk);
+        }
+    }
+
+    /// The GPU must be idle. Uploads that were never flushed are dropped.
+    pub fn destroy(&self, gpu: &Gpu) {
+        let mut state = self.state.borrow_mut();
+        for batch in std::mem::take(&mut state.in_flight) {
+            self.release(batch, gpu);
+        }
+        if let Some(batch) = state.opt_recording.take() {
+            unsafe {
+                self.device
+                    .free_command_buffers(self.command_pool, &[batch.command_buffer]);
+            }
+            for chunk in batch.staging_chunks {
+                gpu.staging_pool.free(chunk);
+            }
+        }
+        unsafe {
+            self.device.destroy_command_pool(self.command_pool, None);
+        }
+        audit_destroyed(VkObjectKind::CommandPool, self.command_pool);
+    }
+}
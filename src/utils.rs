@@ -59,14 +59,19 @@ pub fn end_single_use_command_buffer(
         ..Default::default()
     }];
 
+    // Wait on a fence rather than on the whole queue
+    let fence = gpu
+        .sync_pool
+        .fence("fence_single_use_command_buffer", false);
     unsafe {
         gpu.device
-            .queue_submit(gpu.graphics_queue, &submit_info, vk::Fence::null())
+            .queue_submit(gpu.graphics_queue, &submit_info, fence)
             .expect("Failed to Queue Submit!");
         gpu.device
-            .queue_wait_idle(gpu.graphics_queue)
-            .expect("Failed to wait Queue idle!");
+            .wait_for_fences(&[fence], true, u64::MAX)
+            .expect("Failed to wait for Fence.");
         gpu.device
             .free_command_buffers(command_pool, &command_buffers);
     }
+    gpu.sync_pool.release_fence(fence);
 }
@@ -0,0 +1,233 @@
+use crate::*;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+
+// Number of decoded frames that can wait for upload. Bounds the CPU memory use.
+const MAX_QUEUED_FRAMES: usize = 2;
+
+struct DecodedFrame {
+    frame_number: usize, // Counts up across loops, unlike the file index
+    pixels: Vec<u8>,
+}
+
+/* Plays back a directory of numbered image files (e.g. frame_0001.png,
+frame_0002.png, ...) into an image, without audio. Frames are decoded on a
+background thread and uploaded with `update_image_regions()`. If decoding
+can't keep up with the playback rate, frames are skipped rather than stalling
+the frame loop. */
+pub struct VideoImage {
+    pub image: ImageHandle,
+    pub frames_per_second: f32,
+    pub is_looping: bool,
+    width: u32,
+    height: u32,
+    num_frames: usize,
+    current_frame_number: usize, // The frame that is in the image right now
+    target_frame_number: Arc<AtomicUsize>, // Lets the decoder skip frames it's behind on
+    is_shutting_down: Arc<AtomicBool>,
+    opt_frame_rx: Option<Receiver<DecodedFrame>>,
+    opt_pending_frame: Option<DecodedFrame>, // Received, but not yet due
+    opt_decode_thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Drop for VideoImage {
+    fn drop(&mut self) {
+        self.is_shutting_down.store(true, Ordering::Relaxed);
+        // Dropping the receiver unblocks a decoder that is waiting to send
+        self.opt_frame_rx = None;
+        if let Some(decode_thread) = self.opt_decode_thread.take() {
+            let _ = decode_thread.join();
+        }
+    }
+}
+
+impl VideoImage {
+    pub fn new(
+        ctx: &mut Context,
+        name: &str,
+        directory: &str,
+        frames_per_second: f32,
+        is_looping: bool,
+    ) -> Result<VideoImage, String> {
+        // The frames are played back in the order of their file names
+        let mut frame_paths: Vec<PathBuf> = std::fs::read_dir(directory)
+            .map_err(|e| format!("Failed to read video directory `{}`: {}", directory, e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        frame_paths.sort();
+        if frame_paths.is_empty() {
+            return Err(format!("Video directory `{}` has no frames.", directory));
+        }
+        if frames_per_second <= 0.0 {
+            return Err(format!(
+                "Invalid playback rate {} for video `{}`.",
+                frames_per_second, name
+            ));
+        }
+
+        /* The first frame determines the size of the video. Mips would be
+        regenerated on every frame, so there is only one. */
+        let first_frame_path = frame_paths[0].to_string_lossy().into_owned();
+        let image = ctx.new_image_from_file_with_mips(name, &first_frame_path, false)?;
+        let (width, height) = {
+            let internal_image = ctx.image_list.get_image_from_handle(image).unwrap();
+            (internal_image.image.width, internal_image.image.height)
+        };
+
+        let num_frames = frame_paths.len();
+        let target_frame_number = Arc::new(AtomicUsize::new(0));
+        let is_shutting_down = Arc::new(AtomicBool::new(false));
+        let (frame_tx, frame_rx) = std::sync::mpsc::sync_channel(MAX_QUEUED_FRAMES);
+        let decode_thread = {
+            let target_frame_number = target_frame_number.clone();
+            let is_shutting_down = is_shutting_down.clone();
+            let thread_video_name = String::from(name);
+            std::thread::Builder::new()
+                .name(format!("video_decode_{}", name))
+                .spawn(move || {
+                    decode_frames(
+                        &thread_video_name,
+                        &frame_paths,
+                        (width, height),
+                        is_looping,
+                        &target_frame_number,
+                        &is_shutting_down,
+                        frame_tx,
+                    )
+                })
+                .map_err(|e| format!("Failed to spawn decode thread for `{}`: {}", name, e))?
+        };
+
+        Ok(VideoImage {
+            image,
+            frames_per_second,
+            is_looping,
+            width,
+            height,
+            num_frames,
+            current_frame_number: 0,
+            target_frame_number,
+            is_shutting_down,
+            opt_frame_rx: Some(frame_rx),
+            opt_pending_frame: None,
+            opt_decode_thread: Some(decode_thread),
+        })
+    }
+
+    /// Upload the most recent decoded frame that is due at `playback_seconds`.
+    /// Call this once per frame, before the passes sampling the image.
+    /// Playback time is expected to only move forward.
+    pub fn update(&mut self, ctx: &mut Context, playback_seconds: f32) {
+        let mut target_frame_number = (playback_seconds.max(0.0) * self.frames_per_second) as usize;
+        if !self.is_looping {
+            target_frame_number = target_frame_number.min(self.num_frames - 1);
+        }
+        self.target_frame_number
+            .store(target_frame_number, Ordering::Relaxed);
+
+        // Take the newest frame that is due. Frames that are already late are skipped.
+        let frame_rx = self.opt_frame_rx.as_ref().unwrap();
+        let mut opt_latest_frame = None;
+        while let Some(frame) = self
+            .opt_pending_frame
+            .take()
+            .or_else(|| frame_rx.try_recv().ok())
+        {
+            if frame.frame_number > target_frame_number {
+                // Decoded ahead of time. Hold on to it until it is due.
+                self.opt_pending_frame = Some(frame);
+                break;
+            }
+            if frame.frame_number > self.current_frame_number {
+                opt_latest_frame = Some(frame);
+            }
+        }
+
+        if let Some(frame) = opt_latest_frame {
+            let region = ImageRegion {
+                x: 0,
+                y: 0,
+                width: self.width,
+                height: self.height,
+                data: &frame.pixels,
+                row_length: 0,
+                layer: 0,
+            };
+            ctx.update_image_regions(self.image, &[region])
+                .expect("Failed to upload video frame.");
+            self.current_frame_number = frame.frame_number;
+        }
+    }
+}
+
+fn decode_frames(
+    name: &str,
+    frame_paths: &[PathBuf],
+    (width, height): (u32, u32),
+    is_looping: bool,
+    target_frame_number: &AtomicUsize,
+    is_shutting_down: &AtomicBool,
+    frame_tx: SyncSender<DecodedFrame>,
+) {
+    // Frame 0 was uploaded when the image was created
+    let mut frame_number = 1;
+    while !is_shutting_down.load(Ordering::Relaxed) {
+        if !is_looping && frame_number >= frame_paths.len() {
+            return;
+        }
+        // Skip ahead if playback has overtaken the decoder
+        frame_number = frame_number.max(target_frame_number.load(Ordering::Relaxed));
+
+        let path = &frame_paths[frame_number % frame_paths.len()];
+        let pixels = match ::image::open(path) {
+            Ok(image_object) => {
+                let image_object = image_object.flipv().to_rgba8();
+                if image_object.dimensions() != (width, height) {
+                    println!(
+                        "Skipping frame `{}` of video `{}`. Expected size {}x{}.",
+                        path.display(),
+                        name,
+                        width,
+                        height
+                    );
+                    frame_number += 1;
+                    continue;
+                }
+                image_object.into_raw()
+            }
+            Err(e) => {
+                println!(
+                    "Skipping frame `{}` of video `{}`: {}",
+                    path.display(),
+                    name,
+                    e
+                );
+                frame_number += 1;
+                continue;
+            }
+        };
+
+        let mut frame = DecodedFrame {
+            frame_number,
+            pixels,
+        };
+        // Wait for room in the queue, but keep checking for shutdown
+        loop {
+            match frame_tx.try_send(frame) {
+                Ok(()) => break,
+                Err(TrySendError::Full(unsent_frame)) => {
+                    if is_shutting_down.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    frame = unsent_frame;
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                }
+                Err(TrySendError::Disconnected(_)) => return,
+            }
+        }
+        frame_number += 1;
+    }
+}
@@ -0,0 +1,359 @@
+use crate::*;
+
+pub const MAX_INSETS: usize = 4;
+const SCENE_FORMAT: Format = Format::R8G8B8A8Srgb;
+
+/// Where an inset goes, as fractions of the output, from its top left.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct InsetRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl InsetRect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> InsetRect {
+        InsetRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.width > 0.0
+            && self.height > 0.0
+            && self.x >= 0.0
+            && self.y >= 0.0
+            && self.x + self.width <= 1.0
+            && self.y + self.height <= 1.0
+    }
+}
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq)]
+pub struct InsetHandle(pub usize);
+
+/* A secondary view, like a rear-view mirror or a minimap, that is rendered
+into images of its own and composited over the main view. The fields can be
+changed at any time. */
+pub struct ViewportInset {
+    pub name: String,
+    pub rect: InsetRect,
+    pub border_color: Color,
+    pub border_width: f32,          // In pixels of the output
+    pub is_visible: bool,           // Hidden insets aren't rendered or composited
+    pub opt_update_hz: Option<f32>, // None renders the inset every frame
+    render_scale: f32,              // Of the swapchain. Set with set_render_scale().
+    image: ImageHandle,
+    depth_image: ImageHandle,
+    update_flag: PassFlagHandle,
+    opt_last_update_seconds: Option<f64>,
+}
+
+/* Whether an inset that was last rendered at `opt_last_update_seconds` is due
+at `now_seconds`. An inset that has never been rendered always is. */
+fn is_update_due(
+    opt_last_update_seconds: Option<f64>,
+    now_seconds: f64,
+    opt_update_hz: Option<f32>,
+) -> bool {
+    match (opt_last_update_seconds, opt_update_hz) {
+        (None, _) | (_, None) => true,
+        (Some(last_update_seconds), Some(update_hz)) => {
+            update_hz > 0.0 && now_seconds - last_update_seconds >= 1.0 / update_hz as f64
+        }
+    }
+}
+
+// Matches the uniform block of viewport_composite.frag
+#[allow(dead_code)]
+#[derive(Copy, Clone, Default)]
+struct CompositeUniforms {
+    rects: [[f32; 4]; MAX_INSETS],
+    border_colors: [[f32; 4]; MAX_INSETS],
+    borders: [[f32; 4]; MAX_INSETS], // Width in pixels, and 1 if visible
+    viewport_w: f32,
+    viewport_h: f32,
+}
+
+/* Picture-in-picture views over the main one. Each inset renders through
+passes of its own, with its own camera, into swapchain-relative images at its
+render scale. The app adds those passes with `set_pass_enabled_if()` and the
+inset's `update_flag()`, which `update()` sets only on the frames where the
+inset is visible and due, so insets can update at a lower rate than the main
+view without rebuilding the graph. The composite pass then draws the main
+view with the insets in their rectangles, later insets over earlier ones.
+
+The images are sRGB, so that the composite samples linear colors, and an sRGB
+output encodes them once. */
+pub struct ViewportComposite {
+    insets: Vec<ViewportInset>,
+    shader_vertex: ShaderHandle,
+    shader_fragment: ShaderHandle,
+    last_swapchain_size: (u32, u32), // Resizes recreate the images, which are then rendered again
+}
+
+impl ViewportComposite {
+    pub fn new(ctx: &mut Context) -> Result<ViewportComposite, String> {
+        Ok(ViewportComposite {
+            insets: Vec::new(),
+            shader_vertex: ctx.new_shader(
+                "viewport_composite_vertex",
+                ShaderStage::Vertex,
+                "fullscreen_triangle.vert",
+            )?,
+            shader_fragment: ctx.new_shader(
+                "viewport_composite_fragment",
+                ShaderStage::Fragment,
+                "viewport_composite.frag",
+            )?,
+            last_swapchain_size: (ctx.facade.swapchain_width, ctx.facade.swapchain_height),
+        })
+    }
+
+    /// The format of the main view's image, which `new_main_image()` creates.
+    pub fn scene_format(&self) -> Format {
+        SCENE_FORMAT
+    }
+
+    /// A swapchain-sized image for the main view to render into.
+    pub fn new_main_image(&self, ctx: &mut Context, name: &str) -> Result<ImageHandle, String> {
+        ctx.new_render_target(name, ImageSize::SwapchainRelative(1.0), SCENE_FORMAT)
+    }
+
+    /// Visible, without a border, and updated every frame until it's set otherwise.
+    pub fn add_inset(
+        &mut self,
+        ctx: &mut Context,
+        name: &str,
+        rect: InsetRect,
+        render_scale: f32,
+    ) -> Result<InsetHandle, String> {
+        if self.insets.len() == MAX_INSETS {
+            return Err(format!(
+                "Can't add inset `{}`, since there can be at most {} insets.",
+                name, MAX_INSETS
+            ));
+        }
+        if !rect.is_valid() {
+            return Err(format!(
+                "Inset `{}` is at {:?}, which isn't inside the output.",
+                name, rect
+            ));
+        }
+        let (image, depth_image) = new_inset_images(ctx, name, render_scale)?;
+        self.insets.push(ViewportInset {
+            name: String::from(name),
+            rect,
+            border_color: Color::BLACK,
+            border_width: 0.0,
+            is_visible: true,
+            opt_update_hz: None,
+            render_scale,
+            image,
+            depth_image,
+            update_flag: ctx.new_pass_flag(&format!("{}_update", name), true),
+            opt_last_update_seconds: None,
+        });
+        Ok(InsetHandle(self.insets.len() - 1))
+    }
+
+    pub fn inset(&self, inset: InsetHandle) -> &ViewportInset {
+        &self.insets[inset.0]
+    }
+
+    pub fn inset_mut(&mut self, inset: InsetHandle) -> &mut ViewportInset {
+        &mut self.insets[inset.0]
+    }
+
+    /// The color image that the inset's passes render into.
+    pub fn image(&self, inset: InsetHandle) -> ImageHandle {
+        self.insets[inset.0].image
+    }
+
+    pub fn depth_image(&self, inset: InsetHandle) -> ImageHandle {
+        self.insets[inset.0].depth_image
+    }
+
+    /// Set by `update()` on the frames where the inset's passes should run.
+    pub fn update_flag(&self, inset: InsetHandle) -> PassFlagHandle {
+        self.insets[inset.0].update_flag
+    }
+
+    pub fn render_scale(&self, inset: InsetHandle) -> f32 {
+        self.insets[inset.0].render_scale
+    }
+
+    /// Recreates the inset's images at the new scale. Their handles change,
+    /// and they are rendered again on the next `update()`.
+    pub fn set_render_scale(
+        &mut self,
+        ctx: &mut Context,
+        inset: InsetHandle,
+        render_scale: f32,
+    ) -> Result<(), String> {
+        let inset = &mut self.insets[inset.0];
+        if render_scale == inset.render_scale {
+            return Ok(());
+        }
+        let (image, depth_image) = new_inset_images(ctx, &inset.name, render_scale)?;
+        ctx.destroy_image(inset.image)?;
+        ctx.destroy_image(inset.depth_image)?;
+        inset.image = image;
+        inset.depth_image = depth_image;
+        inset.render_scale = render_scale;
+        inset.opt_last_update_seconds = None;
+        Ok(())
+    }
+
+    /* Decide which insets are rendered this frame, and set their flags. Call
+    it every frame after `begin_frame()`, with a time that counts up in
+    seconds, before recording the insets' passes. */
+    pub fn update(&mut self, ctx: &mut Context, now_seconds: f64) {
+        let swapchain_size = (ctx.facade.swapchain_width, ctx.facade.swapchain_height);
+        let was_resized = swapchain_size != self.last_swapchain_size;
+        self.last_swapchain_size = swapchain_size;
+        for inset in &mut self.insets {
+            if was_resized {
+                inset.opt_last_update_seconds = None;
+            }
+            let is_updated = inset.is_visible
+                && is_update_due(
+                    inset.opt_last_update_seconds,
+                    now_seconds,
+                    inset.opt_update_hz,
+                );
+            if is_updated {
+                inset.opt_last_update_seconds = Some(now_seconds);
+            }
+            ctx.set_pass_flag(inset.update_flag, is_updated);
+        }
+    }
+
+    /// Whether `update()` set the inset to be rendered this frame.
+    pub fn is_updated(&self, ctx: &Context, inset: InsetHandle) -> bool {
+        ctx.is_pass_flag_set(self.insets[inset.0].update_flag)
+    }
+
+    /* Add the pass that draws `main_image` into `output_image`, e.g. the
+    swapchain image, with the visible insets over it. Call
+    `transition_image_to_sampled()` on the main image and on the insets'
+    images before recording it, and record it with `record_pass()` and a
+    single `draw(3)`. */
+    pub fn add_composite_pass(
+        &self,
+        ctx: &mut Context,
+        main_image: ImageHandle,
+        output_image: ImageHandle,
+        sampler: &Sampler,
+    ) -> Result<PassHandle, String> {
+        let pass = ctx.add_pass(
+            "viewport_composite",
+            self.shader_vertex,
+            self.shader_fragment,
+            &[output_image],
+            Color::BLACK,
+            None,
+            RING_UNIFORM_BUFFER,
+            main_image,
+            sampler,
+        )?;
+        ctx.set_pass_label_color(pass, LABEL_COLOR_POST);
+        // The shader samples an inset in every slot, so the unused ones get a placeholder
+        let mut input_images = vec![main_image];
+        input_images.extend(self.insets.iter().map(|inset| inset.image));
+        input_images.resize(1 + MAX_INSETS, ctx.default_resources.black_image);
+        ctx.set_pass_input_images(pass, &input_images, sampler);
+        ctx.write_pass_uniforms(pass, &[self.uniforms(ctx)]);
+        Ok(pass)
+    }
+
+    fn uniforms(&self, ctx: &Context) -> CompositeUniforms {
+        let mut uniforms = CompositeUniforms {
+            viewport_w: ctx.facade.swapchain_width as f32,
+            viewport_h: ctx.facade.swapchain_height as f32,
+            ..Default::default()
+        };
+        for (inset_idx, inset) in self.insets.iter().enumerate() {
+            let InsetRect {
+                x,
+                y,
+                width,
+                height,
+            } = inset.rect;
+            let Color { r, g, b, a } = inset.border_color;
+            uniforms.rects[inset_idx] = [x, y, width, height];
+            uniforms.border_colors[inset_idx] = [r, g, b, a];
+            uniforms.borders[inset_idx] = [
+                inset.border_width.max(0.0),
+                inset.is_visible as u32 as f32,
+                0.0,
+                0.0,
+            ];
+        }
+        uniforms
+    }
+}
+
+fn new_inset_images(
+    ctx: &mut Context,
+    name: &str,
+    render_scale: f32,
+) -> Result<(ImageHandle, ImageHandle), String> {
+    if !(render_scale > 0.0 && render_scale <= 1.0) {
+        return Err(format!(
+            "Inset `{}` has a render scale of {}, but it must be more than 0 and at most 1.",
+            name, render_scale
+        ));
+    }
+    let image = ctx.new_render_target(
+        &format!("image_{}", name),
+        ImageSize::SwapchainRelative(render_scale),
+        SCENE_FORMAT,
+    )?;
+    let depth_format = ctx.depth_format();
+    let depth_image = ctx.new_image_relative_size(
+        &format!("image_{}_depth", name),
+        render_scale,
+        depth_format,
+        ImageUsage::DEPTH_ATTACHMENT,
+    )?;
+    Ok((image, depth_image))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insets_update_at_their_rate() {
+        let hz = Some(15.0);
+        assert!(is_update_due(None, 0.0, hz));
+        assert!(!is_update_due(Some(1.0), 1.05, hz));
+        assert!(is_update_due(Some(1.0), 1.07, hz));
+        assert!(is_update_due(Some(1.0), 1.001, None));
+        // A rate of zero renders the inset once, and never again
+        assert!(is_update_due(None, 5.0, Some(0.0)));
+        assert!(!is_update_due(Some(0.0), 5.0, Some(0.0)));
+    }
+
+    #[test]
+    fn rects_must_be_inside_the_output() {
+        assert!(InsetRect::new(0.75, 0.0, 0.25, 0.25).is_valid());
+        assert!(InsetRect::new(0.0, 0.0, 1.0, 1.0).is_valid());
+        assert!(!InsetRect::new(0.8, 0.0, 0.25, 0.25).is_valid());
+        assert!(!InsetRect::new(-0.1, 0.0, 0.25, 0.25).is_valid());
+        assert!(!InsetRect::new(0.5, 0.5, 0.0, 0.25).is_valid());
+    }
+
+    #[test]
+    fn uniforms_match_the_shader_layout() {
+        // Three arrays of vec4, and then two floats
+        assert_eq!(
+            std::mem::size_of::<CompositeUniforms>(),
+            3 * 16 * MAX_INSETS + 8
+        );
+    }
+}
@@ -0,0 +1,110 @@
+/* Counts heap allocations around the per-frame work of a 10k-draw scene, to
+show that the frame arena stops allocating once it has grown to fit a frame.
+This is its own test binary, since it replaces the global allocator. */
+use graphene::FrameArena;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+struct CountingAllocator;
+
+thread_local! {
+    // Per thread, so that other tests running at the same time aren't counted
+    static NUM_ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = NUM_ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = NUM_ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const NUM_DRAWS: usize = 10_000;
+const NUM_PASSES: usize = 32;
+
+#[derive(Copy, Clone)]
+struct Draw {
+    sort_key: u64,
+    object_idx: u32,
+}
+
+fn draws(frame_idx: usize) -> impl Iterator<Item = Draw> {
+    (0..NUM_DRAWS).map(move |object_idx| Draw {
+        sort_key: (object_idx as u64 * 2_654_435_761 + frame_idx as u64) % 1_000_003,
+        object_idx: object_idx as u32,
+    })
+}
+
+fn num_allocations_during(work: impl FnOnce()) -> usize {
+    let before = NUM_ALLOCATIONS.with(|count| count.get());
+    work();
+    NUM_ALLOCATIONS.with(|count| count.get()) - before
+}
+
+// A draw list that's built and sorted, and a label per pass, like a frame does
+fn record_frame_on_heap(frame_idx: usize) -> usize {
+    let mut draw_list: Vec<Draw> = draws(frame_idx).collect();
+    draw_list.sort_unstable_by_key(|draw| draw.sort_key);
+    let labels: Vec<String> = (0..NUM_PASSES)
+        .map(|pass_idx| format!("pass_{}", pass_idx))
+        .collect();
+    draw_list[0].object_idx as usize + labels.len()
+}
+
+fn record_frame_in_arena(arena: &mut FrameArena, frame_idx: usize) -> usize {
+    arena.reset();
+    let draw_list = arena.alloc_from_iter(draws(frame_idx));
+    arena
+        .get_mut(draw_list)
+        .sort_unstable_by_key(|draw| draw.sort_key);
+    let mut label_bytes = 0;
+    for pass_idx in 0..NUM_PASSES {
+        let label = arena.alloc_fmt(format_args!("pass_{}", pass_idx));
+        label_bytes += arena.get_cstr(label).to_bytes().len();
+    }
+    arena.get(draw_list)[0].object_idx as usize + label_bytes
+}
+
+#[test]
+fn frames_stop_allocating_once_the_arena_fits_them() {
+    let heap_allocations = num_allocations_during(|| {
+        for frame_idx in 0..100 {
+            std::hint::black_box(record_frame_on_heap(frame_idx));
+        }
+    });
+    println!(
+        "Heap allocations in 100 frames without the arena: {}",
+        heap_allocations
+    );
+    assert!(heap_allocations >= 100 * (NUM_PASSES + 2));
+
+    let mut arena = FrameArena::new(0);
+    // The first frame grows the storage
+    let warm_up_allocations = num_allocations_during(|| {
+        std::hint::black_box(record_frame_in_arena(&mut arena, 0));
+    });
+    assert!(warm_up_allocations > 0);
+    let arena_allocations = num_allocations_during(|| {
+        for frame_idx in 1..101 {
+            std::hint::black_box(record_frame_in_arena(&mut arena, frame_idx));
+        }
+    });
+    println!(
+        "Heap allocations in 100 frames with the arena: {}",
+        arena_allocations
+    );
+    assert_eq!(arena_allocations, 0);
+    assert!(arena.high_water_bytes() >= NUM_DRAWS * std::mem::size_of::<Draw>());
+}
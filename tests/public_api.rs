@@ -0,0 +1,372 @@
+/*! Uses the prelude the way an application outside the crate does, so that a
+change that breaks it fails the build. Nothing but `graphene::prelude` is
+imported from the crate. The tests that need a window and a GPU are ignored,
+but are still compiled. */
+use glam::*;
+use graphene::prelude::*;
+use std::time::Duration;
+use winit::event::VirtualKeyCode;
+
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+struct UniformData {
+    mtx_world_to_clip: Mat4,
+    tint: Vec4,
+}
+
+// What an application keeps between frames
+#[allow(dead_code)]
+struct AppState {
+    shaders: [ShaderHandle; 2],
+    image: ImageHandle,
+    depth_image: ImageHandle,
+    main_image: ImageHandle,
+    buffer: BufferHandle,
+    buffer_view: BufferView,
+    vertex_buffer: DeviceLocalBuffer,
+    indirect_buffer: DeviceLocalBuffer,
+    upload_ticket: UploadTicket,
+    uniform_buffer: UniformBuffer<UniformData>,
+    sampler: Sampler,
+    pass_flag: PassFlagHandle,
+    command_sender: CommandSender,
+    composite: ViewportComposite,
+    mirror: InsetHandle,
+    camera_rig: CameraRig,
+    camera_path: CameraPath,
+    bvh: Bvh,
+    culler: Option<OcclusionCuller>,
+    video: Option<VideoImage>,
+    #[cfg(feature = "gltf")]
+    mesh: Option<Mesh>,
+}
+
+fn new_app_state(ctx: &mut Context) -> Result<AppState, String> {
+    ctx.set_surface_format_preference(SurfaceFormatPreference::Srgb);
+    ctx.set_pacing_mode(PacingMode::Fifo);
+    ctx.background_policy = BackgroundPolicy::new();
+    ctx.set_frame_budgets(FrameBudgets {
+        opt_draws: Some(10_000),
+        ..FrameBudgets::default()
+    });
+    let shaders = [
+        ctx.new_shader("vertex", ShaderStage::Vertex, "fullscreen_triangle.vert")?,
+        ctx.new_shader("fragment", ShaderStage::Fragment, "volume.frag")?,
+    ];
+    let image = ctx.new_image_from_data(
+        "image",
+        Extent::new_2d(2, 2),
+        Format::R8G8B8A8Srgb,
+        &[255; 16],
+    )?;
+    ctx.update_image_regions(
+        image,
+        &[ImageRegion {
+            x: 1,
+            y: 1,
+            width: 1,
+            height: 1,
+            data: &[0; 4],
+            row_length: 0,
+            layer: 0,
+        }],
+    )?;
+    let depth_image = ctx.new_image_relative_size(
+        "image_depth",
+        1.0,
+        ctx.depth_format(),
+        ImageUsage::DEPTH_ATTACHMENT,
+    )?;
+    let buffer = ctx.new_buffer("buffer", 256, BufferUsage::UNIFORM_TEXEL)?;
+    let buffer_view = ctx.new_buffer_view(
+        "buffer_view",
+        buffer,
+        Format::R32Sfloat,
+        0,
+        256,
+        TexelBufferKind::Uniform,
+    )?;
+    let (vertex_buffer, upload_ticket) =
+        ctx.upload_buffer_async("buffer_vertices", &[Vec3::zero(); 3], BufferUsage::VERTEX);
+    let indirect_buffer = ctx.new_indirect_buffer(
+        "buffer_indirect",
+        &[DrawIndirectCommand {
+            num_vertices: 3,
+            num_instances: 1,
+            first_vertex: 0,
+            first_instance: 0,
+        }],
+    );
+    let sampler = Sampler::new(&ctx.gpu);
+    let pass_flag = ctx.new_pass_flag("flag_post", true);
+    let mut composite = ViewportComposite::new(ctx)?;
+    let main_image = composite.new_main_image(ctx, "image_main")?;
+    let mirror = composite.add_inset(ctx, "mirror", InsetRect::new(0.7, 0.0, 0.3, 0.2), 0.5)?;
+    composite.inset_mut(mirror).opt_update_hz = Some(15.0);
+    let culler = OcclusionCuller::new(
+        ctx,
+        "culler",
+        depth_image,
+        &[(Vec3::zero(), 1.0)],
+        &[DrawIndexedIndirectCommand::default()],
+    )
+    .ok();
+    let bvh = Bvh::new(&[Aabb::from_points(vec![-Vec3::one(), Vec3::one()])]);
+    Ok(AppState {
+        shaders,
+        image,
+        depth_image,
+        main_image,
+        buffer,
+        buffer_view,
+        vertex_buffer,
+        indirect_buffer,
+        upload_ticket,
+        uniform_buffer: ctx.new_uniform_buffer("uniform_buffer"),
+        sampler,
+        pass_flag,
+        command_sender: ctx.command_sender(),
+        composite,
+        mirror,
+        camera_rig: CameraRig::new_orbit(Vec3::zero(), 4.0, 0.5, 0.3),
+        camera_path: CameraPath::new(),
+        bvh,
+        culler,
+        video: VideoImage::new(ctx, "image_video", "videos/clip", 24.0, true).ok(),
+        #[cfg(feature = "gltf")]
+        mesh: None,
+    })
+}
+
+fn record_frame(ctx: &mut Context, app: &mut AppState, dt: f32) -> Result<(), String> {
+    if ctx.input.was_key_pressed(VirtualKeyCode::P) {
+        let is_set = ctx.is_pass_flag_set(app.pass_flag);
+        app.command_sender.set_pass_flag(app.pass_flag, !is_set);
+    }
+    if ctx.input.was_key_pressed(VirtualKeyCode::C) {
+        app.camera_rig.set_mode(CameraMode::Fly);
+    }
+    let camera: Camera = *app.camera_rig.update(&ctx.input, dt);
+    let opt_hit: Option<BvhHit> = ctx.pick(&camera, &app.bvh);
+    let ray: Ray = ctx.cursor_ray(&camera);
+    let _ = (opt_hit, ray.point_at(1.0));
+    if let Some(culler) = &mut app.culler {
+        culler.cull(ctx, &camera);
+        let _: CullingStats = culler.last_stats;
+    }
+    app.composite.update(ctx, 0.0);
+    if let Some(video) = &mut app.video {
+        video.update(ctx, 0.0);
+    }
+
+    let pass = ctx.add_pass(
+        "scene",
+        app.shaders[0],
+        app.shaders[1],
+        &[app.main_image],
+        Color::BLACK,
+        Some(app.depth_image),
+        RING_UNIFORM_BUFFER,
+        app.image,
+        &app.sampler,
+    )?;
+    ctx.set_pass_label_color(pass, LABEL_COLOR_SCENE);
+    ctx.set_pass_blend_mode(pass, 0, BlendMode::AlphaBlend);
+    ctx.set_pass_depth_compare_op(pass, CompareOp::Less);
+    ctx.set_pass_sample_count(pass, SampleCount::X1);
+    ctx.set_pass_texel_buffer(pass, &app.buffer_view);
+    ctx.set_pass_enabled_if(pass, app.pass_flag);
+    ctx.write_pass_uniforms(
+        pass,
+        &[UniformData {
+            mtx_world_to_clip: camera.view_to_clip(1.0) * camera.world_to_view(),
+            tint: Color::WHITE.to_vec4(),
+        }],
+    );
+    let output_image = ctx.facade.swapchain_images[ctx.swapchain_idx];
+    let sampler = ctx.sampler(&SamplerDesc {
+        address_mode: AddressMode::ClampToEdge,
+        mip_filter: Filter::Nearest,
+        anisotropy: AnisotropyLevel::Off,
+        ..SamplerDesc::default()
+    });
+    let pass_composite =
+        app.composite
+            .add_composite_pass(ctx, app.main_image, output_image, &sampler)?;
+    let graph: GraphHandle = ctx.build_graph();
+    let vertex_buffer = &app.vertex_buffer;
+    let indirect_buffer = &app.indirect_buffer;
+    ctx.record_pass(graph, pass, |recorder: &PassRecorder| {
+        recorder.bind_vertex_buffer(vertex_buffer);
+        recorder.draw_indirect(indirect_buffer, 0, 1);
+    });
+    ctx.record_pass(graph, pass_composite, |recorder| recorder.draw(3));
+    Ok(())
+}
+
+#[test]
+#[ignore = "Needs a window and a Vulkan device"]
+fn renders_frames() {
+    {
+        let mut ctx = Context::new();
+        let mut app = new_app_state(&mut ctx).unwrap();
+        for _ in 0..3 {
+            if !ctx.begin_frame() {
+                break;
+            }
+            record_frame(&mut ctx, &mut app, 1.0 / 60.0).unwrap();
+            ctx.end_frame();
+        }
+        let _: &GraphStats = &ctx.graph_stats;
+        let _: &UploadContext = &ctx.upload_context;
+        let _: &SamplerCache = &ctx.sampler_cache;
+        let _: &FramePacer = &ctx.frame_pacer;
+        let _: &InputState = &ctx.input;
+        let _: &BudgetMonitor = &ctx.budget_monitor;
+        let _: &FrameStats = &ctx.last_frame_stats;
+        ctx.wait_idle();
+    }
+    report_teardown_audit();
+    assert_eq!(
+        num_validation_errors(),
+        0,
+        "{:?}",
+        recent_validation_messages()
+    );
+}
+
+#[test]
+fn commands_and_handles_are_plain_values() {
+    let (receipt, _) = std::sync::mpsc::channel();
+    let commands = [
+        ContextCommand::SetWindowSize {
+            width: 640,
+            height: 480,
+        },
+        ContextCommand::SetPassFlag {
+            flag: PassFlagHandle(0),
+            is_set: true,
+        },
+        ContextCommand::LoadImage {
+            name: String::from("image"),
+            path: String::from("image.png"),
+            receipt,
+        },
+    ];
+    assert_eq!(commands.len(), 3);
+    let handle: Handle<_> = RING_UNIFORM_BUFFER;
+    let _: BufferHandle = handle;
+    assert_eq!(PassHandle(1), PassHandle(1));
+    assert_eq!(InsetHandle(0), InsetHandle(0));
+    assert!(UploadTicket::completed().is_submitted() || !GRAPH_REPORT_PATH.is_empty());
+    let _ = (TEXEL_BUFFER_BINDING, CRASH_DIR);
+    let error: Option<BufferError> = None;
+    assert!(error.is_none());
+}
+
+#[test]
+fn resource_descriptions() {
+    assert!(Format::D32Sfloat.is_depth());
+    assert!(ImageUsage::SAMPLED.contains(ImageUsage::SAMPLED));
+    assert!(BufferUsage::INDIRECT.contains(BufferUsage::INDIRECT));
+    assert_eq!(Extent::new_2d(4, 2).depth, 1);
+    assert_eq!(
+        ImageSize::SwapchainRelative(0.5),
+        ImageSize::SwapchainRelative(0.5)
+    );
+    assert_ne!(ColorSpace::Srgb, ColorSpace::Linear);
+    assert_eq!(SampleCount::X4.lower(), SampleCount::X2);
+    assert_eq!(AnisotropyLevel::Off.max_anisotropy(), 1.0);
+    assert!(!SamplerDesc::default().to_name().is_empty());
+    let _ = [
+        LABEL_COLOR_DEBUG,
+        LABEL_COLOR_POST,
+        LABEL_COLOR_SCENE,
+        LABEL_COLOR_SHADOWS,
+        LABEL_COLOR_UI,
+        LABEL_COLOR_UPLOAD,
+    ];
+    fn is_indirect_command<T: IndirectCommand>(_: T) {}
+    is_indirect_command(DrawIndirectCommand::default());
+    is_indirect_command(DrawIndexedIndirectCommand::default());
+    assert_eq!(Color::from_srgb8(255, 255, 255).to_srgba8(), [255; 4]);
+}
+
+#[test]
+fn scene_helpers() {
+    let aabb = Aabb::from_points(vec![-Vec3::one(), Vec3::one()]);
+    let ray = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::unit_z());
+    assert_eq!(ray.intersect_aabb(&aabb), Some(4.0));
+    let bvh = Bvh::new(&[aabb]);
+    assert_eq!(bvh.raycast(&ray).map(|hit| hit.object_idx), Some(0));
+
+    let camera = Camera::new(Vec3::zero(), Quat::identity());
+    assert!(camera.forward().length().is_finite());
+    let mut path = CameraPath::new();
+    path.add_keyframe(0.0, Vec3::zero(), Quat::identity());
+    path.add_keyframe(1.0, Vec3::one(), Quat::identity());
+    assert_eq!(path.duration(), 1.0);
+
+    let settings = ImportSettings {
+        up_axis: UpAxis::Z,
+        handedness: Handedness::Left,
+        unit_scale: 0.01,
+    };
+    assert!(settings.flips_winding());
+    let near_opaque = SortKey::new(DrawLayer::Opaque, 1, 2, 0.1);
+    let far_opaque = SortKey::new(DrawLayer::Opaque, 1, 2, 0.9);
+    assert!(near_opaque < far_opaque);
+    assert_eq!(near_opaque.with_layer(DrawLayer::Ui).layer(), DrawLayer::Ui);
+    assert!(TonemapOperator::Aces
+        .apply(Vec3::one(), 0.0)
+        .length()
+        .is_finite());
+    assert_eq!(exposure_scale(0.0), 1.0);
+    assert!(InsetRect::new(0.0, 0.0, 0.5, 0.5).width > 0.0);
+    let _: Option<&ViewportInset> = None;
+}
+
+#[test]
+fn diagnostics_and_reports() {
+    let mut text = String::new();
+    write_ms(&mut text, 1.5, 0);
+    write_bytes(&mut text, 2048, 0);
+    write_count(&mut text, 3, 0);
+    assert!(!text.is_empty());
+
+    let summary = FrameTimeSummary::new(&[Duration::from_millis(16); 10]).unwrap();
+    assert_eq!(summary.num_frames, 10);
+    let mut monitor = BudgetMonitor::new(FrameBudgets {
+        opt_draws: Some(1),
+        num_frames_to_alert: 1,
+        ..FrameBudgets::default()
+    });
+    let stats = FrameStats {
+        pass_draws: vec![(String::from("scene"), 2)],
+        ..FrameStats::default()
+    };
+    match monitor.evaluate(&stats).as_slice() {
+        [BudgetEvent::Raised(alert)] => {
+            let _: &BudgetAlert = alert;
+            assert_eq!(alert.kind, BudgetKind::Draws);
+        }
+        events => panic!("Expected one alert, got {:?}", events),
+    }
+    assert!(!InputState::new().is_key_down(VirtualKeyCode::A));
+    let _: FramePacer = FramePacer::new(None);
+    let _: PacingMode = PacingMode::AdaptiveSync;
+    let _: LiveObjects = LiveObjects::snapshot();
+    add_breadcrumb("public api test");
+    let _: fn(&str) -> Result<CopyDestination, String> = copy_text;
+    let _: Option<SyntheticLoad> = None;
+}
+
+#[test]
+#[allow(deprecated)]
+fn renamed_names_still_resolve() {
+    let _: Option<VulkanApp> = None;
+    let _: Option<Apparatus> = None;
+    let _: Option<Texture> = None;
+    let handle: TextureHandle = Handle::from_raw_parts(0, 0);
+    let _: ImageHandle = handle;
+}